@@ -12,6 +12,7 @@ extern crate enum_set;
 extern crate ordered_float;
 extern crate chrono;
 extern crate indexmap;
+extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate uuid;
 extern crate edn;
@@ -58,6 +59,7 @@ use chrono::{
 use uuid::Uuid;
 
 use edn::{
+    BigInt,
     Cloned,
     ValueRc,
     Utc,
@@ -133,6 +135,7 @@ pub enum AttributeBitFlags {
     IndexVAET     = 1 << 1,
     IndexFulltext = 1 << 2,
     UniqueValue   = 1 << 3,
+    UniqueFolded  = 1 << 4,
 }
 
 pub mod attribute {
@@ -206,6 +209,29 @@ pub struct Attribute {
 
     /// `true` if this attribute doesn't require history to be kept, i.e., it is `:db/noHistory true`.
     pub no_history: bool,
+
+    /// `true` if this attribute's string values are Unicode normalized, i.e., it is `:db/normalize
+    /// true`.
+    ///
+    /// Normalized attributes always have string values.  Values are stored in NFC, and values
+    /// provided in queries are normalized in the same way before comparison.  If the attribute is
+    /// also fulltext indexed, searches match against a compatibility-decomposed form with
+    /// diacritics removed.
+    pub normalize: bool,
+
+    /// `true` if this attribute's uniqueness ignores case, i.e., it is `:db/caseInsensitive true`.
+    ///
+    /// Case-insensitive attributes are always unique and have string values.  Values are stored as
+    /// given, but two values differing only in case conflict, and upserts and lookup refs resolve
+    /// regardless of case.  Case is folded with Unicode's lowercase mapping, not just for ASCII.
+    pub case_insensitive: bool,
+
+    /// `Some(partner)` if this attribute is unique together with the attribute `partner`, i.e., it
+    /// is `:db/uniqueWith partner`.
+    ///
+    /// No two entities may share both a value of this attribute and a value of `partner`.  Either
+    /// value alone may repeat.  Such attributes are always indexed.
+    pub unique_with: Option<Entid>,
 }
 
 impl Attribute {
@@ -225,6 +251,9 @@ impl Attribute {
         if self.unique.is_some() {
             flags |= AttributeBitFlags::UniqueValue as u8;
         }
+        if self.unique.is_some() && self.case_insensitive {
+            flags |= AttributeBitFlags::UniqueFolded as u8;
+        }
         flags
     }
 
@@ -260,6 +289,18 @@ impl Attribute {
             attribute_map.insert(values::DB_NO_HISTORY.clone(), edn::Value::Boolean(true));
         }
 
+        if self.normalize {
+            attribute_map.insert(values::DB_NORMALIZE.clone(), edn::Value::Boolean(true));
+        }
+
+        if self.case_insensitive {
+            attribute_map.insert(values::DB_CASE_INSENSITIVE.clone(), edn::Value::Boolean(true));
+        }
+
+        if let Some(partner) = self.unique_with {
+            attribute_map.insert(values::DB_UNIQUE_WITH.clone(), edn::Value::Integer(partner));
+        }
+
         edn::Value::Map(attribute_map)
     }
 }
@@ -275,6 +316,9 @@ impl Default for Attribute {
             unique: None,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         }
     }
 }
@@ -292,6 +336,8 @@ pub enum ValueType {
     String,
     Keyword,
     Uuid,
+    BigInteger,
+    Bytes,
 }
 
 impl ValueType {
@@ -306,6 +352,8 @@ impl ValueType {
         s.insert(ValueType::String);
         s.insert(ValueType::Keyword);
         s.insert(ValueType::Uuid);
+        s.insert(ValueType::BigInteger);
+        s.insert(ValueType::Bytes);
         s
     }
 }
@@ -332,6 +380,8 @@ impl ValueType {
             ValueType::String => "string",
             ValueType::Keyword => "keyword",
             ValueType::Uuid => "uuid",
+            ValueType::BigInteger => "bigint",
+            ValueType::Bytes => "bytes",
         })
     }
 
@@ -349,6 +399,8 @@ impl ValueType {
             "string" => Some(ValueType::String),
             "keyword" => Some(ValueType::Keyword),
             "uuid" => Some(ValueType::Uuid),
+            "bigint" => Some(ValueType::BigInteger),
+            "bytes" => Some(ValueType::Bytes),
             _ => None,
         }
     }
@@ -363,6 +415,8 @@ impl ValueType {
             ValueType::String => "string",
             ValueType::Keyword => "keyword",
             ValueType::Uuid => "uuid",
+            ValueType::BigInteger => "bigint",
+            ValueType::Bytes => "bytes",
         })
     }
 
@@ -376,6 +430,8 @@ impl ValueType {
             ValueType::String => values::DB_TYPE_STRING.clone(),
             ValueType::Keyword => values::DB_TYPE_KEYWORD.clone(),
             ValueType::Uuid => values::DB_TYPE_UUID.clone(),
+            ValueType::BigInteger => values::DB_TYPE_BIGINT.clone(),
+            ValueType::Bytes => values::DB_TYPE_BYTES.clone(),
         }
     }
 
@@ -398,6 +454,8 @@ impl fmt::Display for ValueType {
             ValueType::String =>  ":db.type/string",
             ValueType::Keyword => ":db.type/keyword",
             ValueType::Uuid =>    ":db.type/uuid",
+            ValueType::BigInteger => ":db.type/bigint",
+            ValueType::Bytes =>   ":db.type/bytes",
         })
     }
 }
@@ -408,7 +466,6 @@ impl TransactableValueMarker for TypedValue {}
 /// Represents a value that can be stored in a Mentat store.
 // TODO: expand to include :db.type/uri. https://github.com/mozilla/mentat/issues/201
 // TODO: JSON data type? https://github.com/mozilla/mentat/issues/31
-// TODO: BigInt? Bytes?
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq, Serialize, Deserialize)]
 pub enum TypedValue {
     Ref(Entid),
@@ -420,6 +477,29 @@ pub enum TypedValue {
     String(ValueRc<String>),
     Keyword(ValueRc<Keyword>),
     Uuid(Uuid),                        // It's only 128 bits, so this should be acceptable to clone.
+    BigInteger(#[serde(with = "bigint_as_string")] BigInt),
+    Bytes(ValueRc<Vec<u8>>),
+}
+
+/// `num`'s `BigInt` doesn't speak this version of serde, so we (de)serialize its decimal string.
+mod bigint_as_string {
+    use serde::{
+        Deserialize,
+        Deserializer,
+        Serializer,
+    };
+    use serde::de::Error;
+
+    use edn::BigInt;
+
+    pub fn serialize<S>(value: &BigInt, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<BigInt, D::Error> where D: Deserializer<'de> {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<BigInt>().map_err(|_| D::Error::custom(format!("invalid big integer: {}", s)))
+    }
 }
 
 impl From<KnownEntid> for TypedValue {
@@ -451,6 +531,8 @@ impl TypedValue {
             &TypedValue::String(_) => ValueType::String,
             &TypedValue::Keyword(_) => ValueType::Keyword,
             &TypedValue::Uuid(_) => ValueType::Uuid,
+            &TypedValue::BigInteger(_) => ValueType::BigInteger,
+            &TypedValue::Bytes(_) => ValueType::Bytes,
         }
     }
 
@@ -602,6 +684,20 @@ impl TypedValue {
             _ => None,
         }
     }
+
+    pub fn into_bigint(self) -> Option<BigInt> {
+        match self {
+            TypedValue::BigInteger(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn into_bytes(self) -> Option<ValueRc<Vec<u8>>> {
+        match self {
+            TypedValue::Bytes(v) => Some(v),
+            _ => None,
+        }
+    }
 }
 
 // We don't do From<i64> or From<Entid> 'cos it's ambiguous.
@@ -626,6 +722,18 @@ impl From<Uuid> for TypedValue {
     }
 }
 
+impl From<BigInt> for TypedValue {
+    fn from(value: BigInt) -> TypedValue {
+        TypedValue::BigInteger(value)
+    }
+}
+
+impl From<Vec<u8>> for TypedValue {
+    fn from(value: Vec<u8>) -> TypedValue {
+        TypedValue::Bytes(ValueRc::new(value))
+    }
+}
+
 impl<'a> From<&'a str> for TypedValue {
     fn from(value: &'a str) -> TypedValue {
         TypedValue::String(ValueRc::new(value.to_string()))
@@ -686,6 +794,12 @@ impl From<i32> for TypedValue {
     }
 }
 
+impl From<i64> for TypedValue {
+    fn from(value: i64) -> TypedValue {
+        TypedValue::Long(value)
+    }
+}
+
 impl From<f64> for TypedValue {
     fn from(value: f64) -> TypedValue {
         TypedValue::Double(OrderedFloat(value))
@@ -716,7 +830,8 @@ impl MicrosecondPrecision for DateTime<Utc> {
 /// * Single structured values, for single-valued component attributes or nested expressions.
 /// * Single typed values, for simple attributes.
 ///
-/// The `Binding` enum defines these three options.
+/// The `Binding` enum defines these three options, and a fourth: `Null`, for a variable that an
+/// `optional` clause left unbound.
 ///
 /// Datomic also supports structured inputs; at present Mentat does not, but this type
 /// would also serve that purpose.
@@ -727,6 +842,9 @@ pub enum Binding {
     Scalar(TypedValue),
     Vec(ValueRc<Vec<Binding>>),
     Map(ValueRc<StructuredMap>),
+
+    /// No value: the clauses that would have bound the variable didn't match.
+    Null,
 }
 
 impl<T> From<T> for Binding where T: Into<TypedValue> {
@@ -747,7 +865,29 @@ impl From<Vec<Binding>> for Binding {
     }
 }
 
+/// `None` is `Binding::Null`.
+impl<T> From<Option<T>> for Binding where T: Into<Binding> {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Binding::Null, |v| v.into())
+    }
+}
+
 impl Binding {
+    pub fn is_null(&self) -> bool {
+        match self {
+            &Binding::Null => true,
+            _ => false,
+        }
+    }
+
+    /// `None` if this binding is `Null`, otherwise the binding itself.
+    pub fn into_option(self) -> Option<Binding> {
+        match self {
+            Binding::Null => None,
+            b => Some(b),
+        }
+    }
+
     pub fn into_scalar(self) -> Option<TypedValue> {
         match self {
             Binding::Scalar(v) => Some(v),
@@ -854,6 +994,7 @@ impl Binding {
 
             &Binding::Map(_) => None,
             &Binding::Vec(_) => None,
+            &Binding::Null => None,
         }
     }
 }
@@ -941,6 +1082,20 @@ impl Binding {
         }
     }
 
+    pub fn into_bigint(self) -> Option<BigInt> {
+        match self {
+            Binding::Scalar(TypedValue::BigInteger(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn into_bytes(self) -> Option<ValueRc<Vec<u8>>> {
+        match self {
+            Binding::Scalar(TypedValue::Bytes(v)) => Some(v),
+            _ => None,
+        }
+    }
+
     pub fn into_c_string(self) -> Option<*mut c_char> {
         match self {
             Binding::Scalar(v) => v.into_c_string(),
@@ -1017,6 +1172,20 @@ impl Binding {
             _ => None,
         }
     }
+
+    pub fn as_bigint(&self) -> Option<&BigInt> {
+        match self {
+            &Binding::Scalar(TypedValue::BigInteger(ref v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            &Binding::Scalar(TypedValue::Bytes(ref v)) => Some(v.as_slice()),
+            _ => None,
+        }
+    }
 }
 
 #[test]
@@ -1028,6 +1197,23 @@ fn test_typed_value() {
     assert!(TypedValue::typed_string("foo").is_congruent_with(None));
 }
 
+#[test]
+fn test_null_binding() {
+    let absent: Option<TypedValue> = None;
+    assert_eq!(Binding::from(absent), Binding::Null);
+    assert_eq!(Binding::from(Some(TypedValue::Long(5))), Binding::Scalar(TypedValue::Long(5)));
+
+    assert!(Binding::Null.is_null());
+    assert!(!Binding::from(5i64).is_null());
+    assert_eq!(Binding::Null.into_option(), None);
+    assert_eq!(Binding::from(5i64).into_option(), Some(Binding::Scalar(TypedValue::Long(5))));
+
+    // An absent value is of no type, and so isn't congruent with any particular type.
+    assert_eq!(Binding::Null.value_type(), None);
+    assert!(!Binding::Null.matches_type(ValueType::Long));
+    assert_eq!(Binding::Null.into_long(), None);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1042,6 +1228,9 @@ mod tests {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         };
 
         assert!(attr1.flags() & AttributeBitFlags::IndexAVET as u8 != 0);
@@ -1057,6 +1246,9 @@ mod tests {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         };
 
         assert!(attr2.flags() & AttributeBitFlags::IndexAVET as u8 == 0);
@@ -1072,6 +1264,9 @@ mod tests {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         };
 
         assert!(attr3.flags() & AttributeBitFlags::IndexAVET as u8 == 0);
@@ -42,14 +42,18 @@ lazy_static_namespaced_keyword_value!(DB_ALTER_ATTRIBUTE, "db.alter", "attribute
 lazy_static_namespaced_keyword_value!(DB_CARDINALITY, "db", "cardinality");
 lazy_static_namespaced_keyword_value!(DB_CARDINALITY_MANY, "db.cardinality", "many");
 lazy_static_namespaced_keyword_value!(DB_CARDINALITY_ONE, "db.cardinality", "one");
+lazy_static_namespaced_keyword_value!(DB_CASE_INSENSITIVE, "db", "caseInsensitive");
 lazy_static_namespaced_keyword_value!(DB_FULLTEXT, "db", "fulltext");
 lazy_static_namespaced_keyword_value!(DB_IDENT, "db", "ident");
 lazy_static_namespaced_keyword_value!(DB_INDEX, "db", "index");
 lazy_static_namespaced_keyword_value!(DB_INSTALL_ATTRIBUTE, "db.install", "attribute");
 lazy_static_namespaced_keyword_value!(DB_IS_COMPONENT, "db", "isComponent");
 lazy_static_namespaced_keyword_value!(DB_NO_HISTORY, "db", "noHistory");
+lazy_static_namespaced_keyword_value!(DB_NORMALIZE, "db", "normalize");
 lazy_static_namespaced_keyword_value!(DB_PART_DB, "db.part", "db");
 lazy_static_namespaced_keyword_value!(DB_RETRACT, "db", "retract");
+lazy_static_namespaced_keyword_value!(DB_TYPE_BIGINT, "db.type", "bigint");
+lazy_static_namespaced_keyword_value!(DB_TYPE_BYTES, "db.type", "bytes");
 lazy_static_namespaced_keyword_value!(DB_TYPE_BOOLEAN, "db.type", "boolean");
 lazy_static_namespaced_keyword_value!(DB_TYPE_DOUBLE, "db.type", "double");
 lazy_static_namespaced_keyword_value!(DB_TYPE_INSTANT, "db.type", "instant");
@@ -62,4 +66,5 @@ lazy_static_namespaced_keyword_value!(DB_TYPE_UUID, "db.type", "uuid");
 lazy_static_namespaced_keyword_value!(DB_UNIQUE, "db", "unique");
 lazy_static_namespaced_keyword_value!(DB_UNIQUE_IDENTITY, "db.unique", "identity");
 lazy_static_namespaced_keyword_value!(DB_UNIQUE_VALUE, "db.unique", "value");
+lazy_static_namespaced_keyword_value!(DB_UNIQUE_WITH, "db", "uniqueWith");
 lazy_static_namespaced_keyword_value!(DB_VALUE_TYPE, "db", "valueType");
@@ -0,0 +1,107 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::{
+    BTreeSet,
+};
+
+use core_traits::{
+    Entid,
+};
+
+/// The order in which an attribute index holds the datoms of its attribute.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum IndexDirection {
+    /// By entity, then value, like AEVT: for finding the values of given entities.
+    Forward,
+
+    /// By value, then entity, like AVET: for finding the entities with given values.
+    Reverse,
+}
+
+impl IndexDirection {
+    fn suffix(&self) -> &'static str {
+        match self {
+            &IndexDirection::Forward => "aev",
+            &IndexDirection::Reverse => "ave",
+        }
+    }
+
+    /// The name of the SQLite index of `attribute`'s datoms in this direction.
+    pub fn index_name(&self, attribute: Entid) -> String {
+        format!("idx_datoms_attribute_{}_{}", attribute, self.suffix())
+    }
+
+    /// The attribute and direction of the index named `name`, if it's an attribute index.
+    pub fn from_index_name(name: &str) -> Option<(Entid, IndexDirection)> {
+        let prefix = "idx_datoms_attribute_";
+        if !name.starts_with(prefix) {
+            return None;
+        }
+        let mut parts = name[prefix.len()..].splitn(2, '_');
+        let attribute = parts.next()?.parse::<Entid>().ok()?;
+        let direction = match parts.next()? {
+            "aev" => IndexDirection::Forward,
+            "ave" => IndexDirection::Reverse,
+            _ => return None,
+        };
+        Some((attribute, direction))
+    }
+}
+
+/// The attributes that have SQLite indexes of their own, beyond those every attribute shares.
+/// Queries that use such an attribute read from its index.
+///
+/// These are read when a store is opened, and kept up to date by the connection that creates and
+/// drops them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AttributeIndexes(BTreeSet<(Entid, IndexDirection)>);
+
+impl AttributeIndexes {
+    pub fn new(indexes: BTreeSet<(Entid, IndexDirection)>) -> AttributeIndexes {
+        AttributeIndexes(indexes)
+    }
+
+    pub fn contains(&self, attribute: Entid, direction: IndexDirection) -> bool {
+        self.0.contains(&(attribute, direction))
+    }
+
+    pub fn insert(&mut self, attribute: Entid, direction: IndexDirection) {
+        self.0.insert((attribute, direction));
+    }
+
+    pub fn remove(&mut self, attribute: Entid, direction: IndexDirection) {
+        self.0.remove(&(attribute, direction));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> ::std::collections::btree_set::Iter<(Entid, IndexDirection)> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_names() {
+        assert_eq!(IndexDirection::Forward.index_name(65), "idx_datoms_attribute_65_aev");
+        assert_eq!(IndexDirection::Reverse.index_name(65), "idx_datoms_attribute_65_ave");
+        assert_eq!(IndexDirection::from_index_name("idx_datoms_attribute_65_aev"), Some((65, IndexDirection::Forward)));
+        assert_eq!(IndexDirection::from_index_name("idx_datoms_attribute_65_ave"), Some((65, IndexDirection::Reverse)));
+        assert_eq!(IndexDirection::from_index_name("idx_datoms_attribute_65_vae"), None);
+        assert_eq!(IndexDirection::from_index_name("idx_datoms_attribute_x_aev"), None);
+        assert_eq!(IndexDirection::from_index_name("idx_datoms_aevt"), None);
+    }
+}
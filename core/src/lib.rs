@@ -13,8 +13,13 @@ extern crate enum_set;
 extern crate failure;
 extern crate indexmap;
 extern crate ordered_float;
+extern crate unicode_normalization;
 extern crate uuid;
 
+#[cfg(feature = "metrics")]
+#[macro_use]
+extern crate lazy_static;
+
 extern crate core_traits;
 
 extern crate edn;
@@ -24,6 +29,7 @@ use core_traits::{
     Entid,
     KnownEntid,
     ValueType,
+    values,
 };
 
 mod cache;
@@ -58,6 +64,18 @@ pub use cache::{
     UpdateableCache,
 };
 
+mod indexes;
+mod statistics;
+
+pub use indexes::{
+    AttributeIndexes,
+    IndexDirection,
+};
+
+pub use statistics::{
+    Statistics,
+};
+
 /// Core types defining a Mentat knowledge base.
 mod types;
 mod tx_report;
@@ -75,6 +93,8 @@ pub use sql_types::{
     SQLTypeAffinity,
     SQLValueType,
     SQLValueTypeSet,
+    bigint_from_sql_text,
+    bigint_to_sql_text,
 };
 
 /// Map `Keyword` idents (`:db/ident`) to positive integer entids (`1`).
@@ -145,8 +165,17 @@ impl Schema {
     /// Returns an symbolic representation of the schema suitable for applying across Mentat stores.
     pub fn to_edn_value(&self) -> edn::Value {
         edn::Value::Vector((&self.attribute_map).iter()
-            .map(|(entid, attribute)|
-                attribute.to_edn_value(self.get_ident(*entid).cloned()))
+            .map(|(entid, attribute)| {
+                let value = attribute.to_edn_value(self.get_ident(*entid).cloned());
+                // Name the partner of a :db/uniqueWith attribute by its ident, if it has one.
+                match (value, attribute.unique_with.and_then(|partner| self.get_ident(partner))) {
+                    (edn::Value::Map(mut map), Some(partner)) => {
+                        map.insert(values::DB_UNIQUE_WITH.clone(), edn::Value::Keyword(partner.clone()));
+                        edn::Value::Map(map)
+                    },
+                    (value, _) => value,
+                }
+            })
             .collect())
     }
 
@@ -154,6 +183,26 @@ impl Schema {
         self.ident_map.get(x).map(|x| *x)
     }
 
+    /// Every attribute that has an ident, sorted by ident.
+    pub fn attributes(&self) -> Vec<(Keyword, Attribute)> {
+        self.ident_map.iter()
+            .filter_map(|(ident, entid)| self.attribute_map.get(entid).map(|a| (ident.clone(), a.clone())))
+            .collect()
+    }
+
+    /// The attributes whose idents are in `namespace` -- `foo` for `:foo/bar` -- sorted by ident.
+    pub fn attributes_in_namespace(&self, namespace: &str) -> Vec<(Keyword, Attribute)> {
+        self.ident_map.iter()
+            .filter(|&(ident, _)| ident.namespace() == Some(namespace))
+            .filter_map(|(ident, entid)| self.attribute_map.get(entid).map(|a| (ident.clone(), a.clone())))
+            .collect()
+    }
+
+    /// Every ident, attribute or not, and the entid it names, sorted by ident.
+    pub fn idents(&self) -> Vec<(Keyword, Entid)> {
+        self.ident_map.iter().map(|(ident, entid)| (ident.clone(), *entid)).collect()
+    }
+
     pub fn update_component_attributes(&mut self) {
         let mut components: Vec<Entid>;
         components = self.attribute_map
@@ -206,6 +255,8 @@ impl HasSchema for Schema {
 }
 
 pub mod counter;
+pub mod metrics;
+pub mod normalization;
 pub mod util;
 
 /// A helper macro to sequentially process an iterable sequence,
@@ -280,6 +331,48 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_attribute_introspection() {
+        let mut schema = Schema::default();
+
+        let string = Attribute {
+            value_type: ValueType::String,
+            ..Default::default()
+        };
+        let many_refs = Attribute {
+            value_type: ValueType::Ref,
+            multival: true,
+            component: true,
+            ..Default::default()
+        };
+        associate_ident(&mut schema, Keyword::namespaced("foo", "name"), 97);
+        add_attribute(&mut schema, 97, string.clone());
+        associate_ident(&mut schema, Keyword::namespaced("foo.bar", "parts"), 98);
+        add_attribute(&mut schema, 98, many_refs.clone());
+        associate_ident(&mut schema, Keyword::namespaced("foo", "kind"), 99);
+        associate_ident(&mut schema, Keyword::namespaced("bar", "children"), 100);
+        add_attribute(&mut schema, 100, many_refs.clone());
+
+        assert_eq!(schema.attributes(), vec![
+            (Keyword::namespaced("bar", "children"), many_refs.clone()),
+            (Keyword::namespaced("foo", "name"), string.clone()),
+            (Keyword::namespaced("foo.bar", "parts"), many_refs.clone()),
+        ]);
+
+        // Namespaces match exactly, and idents that aren't attributes are skipped.
+        assert_eq!(schema.attributes_in_namespace("foo"), vec![
+            (Keyword::namespaced("foo", "name"), string.clone()),
+        ]);
+        assert_eq!(schema.attributes_in_namespace("baz"), vec![]);
+
+        assert_eq!(schema.idents(), vec![
+            (Keyword::namespaced("bar", "children"), 100),
+            (Keyword::namespaced("foo", "kind"), 99),
+            (Keyword::namespaced("foo", "name"), 97),
+            (Keyword::namespaced("foo.bar", "parts"), 98),
+        ]);
+    }
+
     #[test]
     fn test_as_edn_value() {
         let mut schema = Schema::default();
@@ -292,6 +385,9 @@ mod test {
             multival: false,
             component: false,
             no_history: true,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         };
         associate_ident(&mut schema, Keyword::namespaced("foo", "bar"), 97);
         add_attribute(&mut schema, 97, attr1);
@@ -304,6 +400,9 @@ mod test {
             multival: true,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         };
         associate_ident(&mut schema, Keyword::namespaced("foo", "bas"), 98);
         add_attribute(&mut schema, 98, attr2);
@@ -316,6 +415,9 @@ mod test {
             multival: false,
             component: true,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         };
 
         associate_ident(&mut schema, Keyword::namespaced("foo", "bat"), 99);
@@ -0,0 +1,160 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Counters and histograms describing the health of a Mentat store.
+//!
+//! Mentat doesn't ship metrics anywhere itself. With the `metrics` feature enabled, an embedding
+//! application installs a `MetricsRecorder` with `set_recorder`, and forwards what it's given to
+//! whatever observability stack it already uses -- an OpenTelemetry meter, say.  Without the
+//! feature, recording compiles away to nothing.
+//!
+//! Names follow OpenTelemetry conventions: dotted, lowercase, and durations are in seconds.
+
+#[cfg(feature = "metrics")]
+use std::sync::{
+    Arc,
+    RwLock,
+};
+
+#[cfg(feature = "metrics")]
+use std::time::{
+    Instant,
+};
+
+/// The number of queries run, whether prepared or not.
+pub const QUERIES_EXECUTED: &'static str = "mentat.queries.executed";
+
+/// How long each query took to run, in seconds.
+pub const QUERY_DURATION: &'static str = "mentat.query.duration";
+
+/// The number of transacts attempted, whether or not they succeeded.
+pub const TRANSACTS_EXECUTED: &'static str = "mentat.transacts.executed";
+
+/// How long each transact took, in seconds.  This doesn't include committing.
+pub const TRANSACT_DURATION: &'static str = "mentat.transact.duration";
+
+/// The number of attribute lookups answered from the attribute cache.
+pub const CACHE_HITS: &'static str = "mentat.cache.hits";
+
+/// The number of attribute lookups that had to go to SQLite.
+pub const CACHE_MISSES: &'static str = "mentat.cache.misses";
+
+/// The number of syncs attempted, whether or not they succeeded.
+pub const SYNCS_EXECUTED: &'static str = "mentat.syncs.executed";
+
+/// How long each sync took, in seconds.
+pub const SYNC_DURATION: &'static str = "mentat.sync.duration";
+
+/// Receives Mentat's metrics.  Implementations must be cheap: they're called on every query and
+/// transact.
+pub trait MetricsRecorder: Send + Sync {
+    /// Add `value` to the counter `name`.
+    fn increment_counter(&self, name: &'static str, value: u64);
+
+    /// Record `value` in the histogram `name`.
+    fn record_histogram(&self, name: &'static str, value: f64);
+}
+
+#[cfg(feature = "metrics")]
+lazy_static! {
+    static ref RECORDER: RwLock<Option<Arc<MetricsRecorder>>> = RwLock::new(None);
+}
+
+/// Install `recorder` to receive all subsequent metrics, replacing any previous recorder.
+#[cfg(feature = "metrics")]
+pub fn set_recorder(recorder: Arc<MetricsRecorder>) {
+    *RECORDER.write().unwrap() = Some(recorder);
+}
+
+/// Stop recording metrics.
+#[cfg(feature = "metrics")]
+pub fn clear_recorder() {
+    *RECORDER.write().unwrap() = None;
+}
+
+#[cfg(feature = "metrics")]
+fn with_recorder<F>(f: F) where F: FnOnce(&MetricsRecorder) {
+    if let Some(ref recorder) = *RECORDER.read().unwrap() {
+        f(&**recorder);
+    }
+}
+
+/// Add `value` to the counter `name`.
+#[cfg(feature = "metrics")]
+pub fn increment_counter(name: &'static str, value: u64) {
+    with_recorder(|r| r.increment_counter(name, value));
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub fn increment_counter(_name: &'static str, _value: u64) {
+}
+
+/// Run `f`, counting it in `counter` and recording how long it took in `histogram`.
+#[cfg(feature = "metrics")]
+pub fn measure<T, F>(counter: &'static str, histogram: &'static str, f: F) -> T where F: FnOnce() -> T {
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+    with_recorder(|r| {
+        r.increment_counter(counter, 1);
+        r.record_histogram(histogram, seconds);
+    });
+    result
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub fn measure<T, F>(_counter: &'static str, _histogram: &'static str, f: F) -> T where F: FnOnce() -> T {
+    f()
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    use std::sync::{
+        Mutex,
+    };
+
+    #[derive(Default)]
+    struct TestRecorder {
+        counters: Mutex<Vec<(&'static str, u64)>>,
+        histograms: Mutex<Vec<&'static str>>,
+    }
+
+    impl MetricsRecorder for TestRecorder {
+        fn increment_counter(&self, name: &'static str, value: u64) {
+            self.counters.lock().unwrap().push((name, value));
+        }
+
+        fn record_histogram(&self, name: &'static str, value: f64) {
+            assert!(value >= 0.0);
+            self.histograms.lock().unwrap().push(name);
+        }
+    }
+
+    #[test]
+    fn test_recorder() {
+        // Nothing is recorded, or fails, without a recorder.
+        assert_eq!(measure(QUERIES_EXECUTED, QUERY_DURATION, || 5), 5);
+
+        let recorder = Arc::new(TestRecorder::default());
+        set_recorder(recorder.clone());
+        assert_eq!(measure(QUERIES_EXECUTED, QUERY_DURATION, || 6), 6);
+        increment_counter(CACHE_HITS, 2);
+        clear_recorder();
+        increment_counter(CACHE_HITS, 3);
+
+        assert_eq!(*recorder.counters.lock().unwrap(), vec![(QUERIES_EXECUTED, 1), (CACHE_HITS, 2)]);
+        assert_eq!(*recorder.histograms.lock().unwrap(), vec![QUERY_DURATION]);
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Unicode normalization of string values for attributes marked `:db/normalize true`.
+//!
+//! Stored values and query constants are put in Normalization Form C, so that canonically
+//! equivalent strings compare equal.  Fulltext indexing additionally folds text to a
+//! compatibility-decomposed form with combining marks removed, so that searching for "cafe"
+//! matches "café".
+//!
+//! Values of attributes marked `:db/caseInsensitive true` are compared by their case-folded form.
+
+use unicode_normalization::{
+    UnicodeNormalization,
+    is_nfc,
+};
+
+use unicode_normalization::char::{
+    is_combining_mark,
+};
+
+use core_traits::{
+    Attribute,
+    TypedValue,
+};
+
+use edn::{
+    ValueRc,
+};
+
+/// Return `s` in Normalization Form C.
+pub fn nfc(s: &str) -> String {
+    s.nfc().collect()
+}
+
+/// Return `s` decomposed with NFKD and stripped of combining marks.
+pub fn fold_for_fulltext(s: &str) -> String {
+    s.nfkd().filter(|c| !is_combining_mark(*c)).collect()
+}
+
+/// Return `s` folded for comparison regardless of case.  Unlike SQLite's `lower()`, which only
+/// folds ASCII, this folds every cased letter, so "ÄLICE" and "älice" fold alike.
+pub fn fold_case(s: &str) -> String {
+    s.to_lowercase()
+}
+
+/// Normalize `value` if `attribute` asks for it.  Only string values are affected; strings
+/// already in NFC are returned untouched.
+pub fn normalize_value(attribute: &Attribute, value: TypedValue) -> TypedValue {
+    if !attribute.normalize {
+        return value;
+    }
+    match value {
+        TypedValue::String(ref s) if !is_nfc(s) => TypedValue::String(ValueRc::new(nfc(s))),
+        v => v,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nfc() {
+        // "e" followed by COMBINING ACUTE ACCENT composes to "é".
+        assert_eq!(nfc("cafe\u{301}"), "caf\u{e9}");
+        assert_eq!(nfc("caf\u{e9}"), "caf\u{e9}");
+    }
+
+    #[test]
+    fn test_fold_for_fulltext() {
+        assert_eq!(fold_for_fulltext("caf\u{e9}"), "cafe");
+        assert_eq!(fold_for_fulltext("cafe\u{301}"), "cafe");
+        // Compatibility decomposition splits ligatures.
+        assert_eq!(fold_for_fulltext("\u{fb01}ne"), "fine");
+    }
+
+    #[test]
+    fn test_fold_case() {
+        assert_eq!(fold_case("Alice"), "alice");
+        assert_eq!(fold_case("\u{c4}LICE"), "\u{e4}lice");
+        assert_eq!(fold_case("JOS\u{c9}"), fold_case("Jos\u{e9}"));
+    }
+
+    #[test]
+    fn test_normalize_value() {
+        let mut attribute = Attribute::default();
+        let decomposed = TypedValue::typed_string("cafe\u{301}");
+        assert_eq!(normalize_value(&attribute, decomposed.clone()), decomposed);
+
+        attribute.normalize = true;
+        assert_eq!(normalize_value(&attribute, decomposed), TypedValue::typed_string("caf\u{e9}"));
+        assert_eq!(normalize_value(&attribute, TypedValue::Long(1)), TypedValue::Long(1));
+    }
+}
@@ -17,6 +17,10 @@ use core_traits::{
     ValueTypeSet,
 };
 
+use edn::{
+    BigInt,
+};
+
 use types::{
     ValueTypeTag,
 };
@@ -58,6 +62,9 @@ impl SQLValueType for ValueType {
             ValueType::String  => (10, None),
             ValueType::Uuid    => (11, None),
             ValueType::Keyword => (13, None),
+            // Stored as text, since SQLite's integers are only 64 bits wide.  See `bigint_to_sql_text`.
+            ValueType::BigInteger => (14, None),
+            ValueType::Bytes   => (15, None),
         }
     }
 
@@ -78,10 +85,63 @@ impl SQLValueType for ValueType {
             ValueType::String       => false,
             Keyword                 => false,
             Uuid                    => false,
+            BigInteger              => false,          // Always use a big integer.
+            Bytes                   => false,
         }
     }
 }
 
+/// Encode `value` as text that sorts, character by character, in the same order as the integers
+/// do, so that SQLite can order and compare `:db.type/bigint` values.
+///
+/// A non-negative value is `p`, then its number of digits, then its digits.  A negative value is
+/// `n`, then the same for its magnitude with each digit replaced by its nines' complement, so that
+/// larger magnitudes sort first.  The number of digits is itself prefixed by its own number of
+/// digits, so that longer numbers sort after shorter ones: `5` is `p115`, and `-123` is `n86876`.
+pub fn bigint_to_sql_text(value: &BigInt) -> String {
+    let decimal = value.to_string();
+    let (negative, digits) = if decimal.starts_with('-') {
+        (true, &decimal[1..])
+    } else {
+        (false, &decimal[..])
+    };
+    let length = digits.len().to_string();
+    let magnitude = format!("{}{}{}", length.len(), length, digits);
+    if negative {
+        format!("n{}", nines_complement(&magnitude))
+    } else {
+        format!("p{}", magnitude)
+    }
+}
+
+/// Decode text made by `bigint_to_sql_text`, returning `None` if it isn't such text.
+pub fn bigint_from_sql_text(text: &str) -> Option<BigInt> {
+    if text.len() < 2 || !text[1..].bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (negative, magnitude) = match &text[..1] {
+        "p" => (false, text[1..].to_string()),
+        "n" => (true, nines_complement(&text[1..])),
+        _ => return None,
+    };
+    let prefix = (magnitude.as_bytes()[0] - b'0') as usize;
+    if magnitude.len() < 1 + prefix {
+        return None;
+    }
+    let length = magnitude[1..1 + prefix].parse::<usize>().ok()?;
+    let digits = &magnitude[1 + prefix..];
+    if digits.len() != length {
+        return None;
+    }
+    let decimal = if negative { format!("-{}", digits) } else { digits.to_string() };
+    decimal.parse::<BigInt>().ok()
+}
+
+/// Replace each of `digits` by its nines' complement.  `digits` must only contain ASCII digits.
+fn nines_complement(digits: &str) -> String {
+    digits.bytes().map(|b| (b'9' - (b - b'0')) as char).collect()
+}
+
 /// We have an enum of types, `ValueType`. It can be collected into a set, `ValueTypeSet`. Each type
 /// is associated with a type tag, which is how a type is represented in, e.g., SQL storage. Types
 /// can share type tags, because backing SQL storage is able to differentiate between some types
@@ -133,8 +193,13 @@ mod tests {
     use core_traits::{
         ValueType,
     };
+    use edn::{
+        BigInt,
+    };
     use sql_types::{
         SQLValueType,
+        bigint_from_sql_text,
+        bigint_to_sql_text,
     };
 
     #[test]
@@ -146,4 +211,31 @@ mod tests {
         assert!(!ValueType::Boolean.accommodates_integer(10));
         assert!(!ValueType::String.accommodates_integer(10));
     }
+
+    #[test]
+    fn test_bigint_sql_text() {
+        let values: Vec<BigInt> = ["-123456789012345678901234567890", "-1000", "-999", "-10", "-9", "-1",
+                                   "0", "1", "9", "10", "999", "1000", "123456789012345678901234567890"]
+            .iter()
+            .map(|s| s.parse::<BigInt>().expect("big integer"))
+            .collect();
+        let texts: Vec<String> = values.iter().map(bigint_to_sql_text).collect();
+
+        assert_eq!(texts[6], "p110");
+        assert_eq!(bigint_to_sql_text(&BigInt::from(-123)), "n86876");
+
+        // The text sorts in the same order as the values.
+        let mut sorted = texts.clone();
+        sorted.sort();
+        assert_eq!(sorted, texts);
+
+        for (value, text) in values.iter().zip(texts.iter()) {
+            assert_eq!(bigint_from_sql_text(text).as_ref(), Some(value));
+        }
+
+        assert_eq!(bigint_from_sql_text("123"), None);
+        assert_eq!(bigint_from_sql_text("p1"), None);
+        assert_eq!(bigint_from_sql_text("p1123"), None);
+        assert_eq!(bigint_from_sql_text("px"), None);
+    }
 }
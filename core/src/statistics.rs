@@ -0,0 +1,48 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use core_traits::{
+    Entid,
+};
+
+/// How many datoms each attribute has in a store.  The query algebrizer uses these to join the
+/// most selective patterns first.
+///
+/// These are estimates: they're read when a store is opened, or when asked for, and transacting
+/// doesn't keep them up to date.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Statistics {
+    datoms: BTreeMap<Entid, u64>,
+    total: u64,
+}
+
+impl Statistics {
+    pub fn new(datoms: BTreeMap<Entid, u64>) -> Statistics {
+        let total = datoms.values().sum();
+        Statistics {
+            datoms: datoms,
+            total: total,
+        }
+    }
+
+    /// The number of datoms with the given attribute.
+    pub fn datoms_for_attribute(&self, attribute: Entid) -> u64 {
+        self.datoms.get(&attribute).cloned().unwrap_or(0)
+    }
+
+    /// The number of datoms in the store.
+    pub fn total_datoms(&self) -> u64 {
+        self.total
+    }
+}
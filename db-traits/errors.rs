@@ -21,9 +21,11 @@ use std::collections::{
     BTreeSet,
 };
 
+#[cfg(feature = "rusqlite")]
 use rusqlite;
 
 use edn::entities::{
+    EntidOrIdent,
     TempId,
 };
 
@@ -79,6 +81,26 @@ pub enum SchemaConstraintViolation {
     CardinalityConflicts {
         conflicts: Vec<CardinalityConflict>,
     },
+
+    /// A transaction used attributes that aren't installed.
+    UnknownAttributes {
+        /// A map from the index of an entity in the transaction to the unknown attributes it uses.
+        unknown_attributes: BTreeMap<usize, BTreeSet<EntidOrIdent>>,
+    },
+
+    /// A transaction tried to give two entities the same values of an attribute and its
+    /// `:db/uniqueWith` partner.
+    UniqueWithConflicts {
+        /// A map from each `(attribute, partner)` pair to pairs of entities sharing values of both.
+        conflicts: BTreeMap<(Entid, Entid), BTreeSet<(Entid, Entid)>>,
+    },
+
+    /// A transaction tried to give two entities values of a `:db/caseInsensitive` attribute that
+    /// differ only in case.
+    CaseInsensitiveConflicts {
+        /// A map from each attribute to pairs of entities with values differing only in case.
+        conflicts: BTreeMap<Entid, BTreeSet<(Entid, Entid)>>,
+    },
 }
 
 impl ::std::fmt::Display for SchemaConstraintViolation {
@@ -106,6 +128,36 @@ impl ::std::fmt::Display for SchemaConstraintViolation {
                 }
                 Ok(())
             },
+            &UnknownAttributes { ref unknown_attributes } => {
+                writeln!(f, "unknown attributes:")?;
+                for (index, attributes) in unknown_attributes {
+                    for attribute in attributes {
+                        match attribute {
+                            &EntidOrIdent::Entid(e) => writeln!(f, "  entity {} uses {}, which is not an attribute", index, e)?,
+                            &EntidOrIdent::Ident(ref a) => writeln!(f, "  entity {} uses {}, which is not an attribute", index, a)?,
+                        }
+                    }
+                }
+                Ok(())
+            },
+            &UniqueWithConflicts { ref conflicts } => {
+                writeln!(f, "unique with conflicts:")?;
+                for (&(a, partner), entities) in conflicts {
+                    for &(e1, e2) in entities {
+                        writeln!(f, "  entities {} and {} share values of {} and {}", e1, e2, a, partner)?;
+                    }
+                }
+                Ok(())
+            },
+            &CaseInsensitiveConflicts { ref conflicts } => {
+                writeln!(f, "case insensitive conflicts:")?;
+                for (&a, entities) in conflicts {
+                    for &(e1, e2) in entities {
+                        writeln!(f, "  entities {} and {} have values of {} differing only in case", e1, e2, a)?;
+                    }
+                }
+                Ok(())
+            },
         }
     }
 }
@@ -173,6 +225,7 @@ impl From<Context<DbErrorKind>> for DbError {
     }
 }
 
+#[cfg(feature = "rusqlite")]
 impl From<rusqlite::Error> for DbError {
     fn from(error: rusqlite::Error) -> DbError {
         DbError { inner: Context::new(DbErrorKind::RusqliteError(error.to_string())) }
@@ -192,6 +245,7 @@ pub enum DbErrorKind {
 
     /// We've got corrupt data in the SQL store: a value and value_type_tag don't line up.
     /// TODO _1.data_type()
+    #[cfg(feature = "rusqlite")]
     #[fail(display = "bad SQL (value_type_tag, value) pair: ({:?}, {:?})", _0, _1)]
     BadSQLValuePair(rusqlite::types::Value, i32),
 
@@ -294,6 +348,34 @@ pub enum DbErrorKind {
     #[fail(display = "Supplied an invalid transaction range")]
     TimelinesInvalidRange,
 
+    #[fail(display = "can't excise {}: {}", _0, _1)]
+    CannotExcise(Entid, String),
+
+    #[fail(display = "can't create partition {}: {}", _0, _1)]
+    CannotCreatePartition(String, String),
+
+    #[fail(display = "can't upgrade a store from version {}: {}", _0, _1)]
+    CannotUpgrade(i32, String),
+
+    /// A tempid named a partition that its entid can't be allocated in.
+    #[fail(display = "can't allocate tempid {}: {}", _0, _1)]
+    CannotAllocateTempId(String, String),
+
+    #[fail(display = "couldn't resolve lookup ref [a v]: {}", _0)]
+    UnresolvedLookupRef(String),
+
+    #[fail(display = "can't resolve lookup ref {}: attribute {} is not :db/unique", _1, _0)]
+    NonUniqueLookupRef(Entid, String),
+
+    #[fail(display = "unknown transaction function {}", _0)]
+    UnknownTransactionFunction(String),
+
+    #[fail(display = "transaction function {} nested too deeply", _0)]
+    TransactionFunctionTooDeep(String),
+
+    #[fail(display = "[:db/retract ...] entity referenced tempid that did not upsert: {}", _0)]
+    UnresolvedRetractTempId(String),
+
     // It would be better to capture the underlying `rusqlite::Error`, but that type doesn't
     // implement many useful traits, including `Clone`, `Eq`, and `PartialEq`.
     #[fail(display = "SQL error: {}", _0)]
@@ -12,6 +12,7 @@
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+#[cfg(feature = "rusqlite")]
 extern crate rusqlite;
 
 extern crate edn;
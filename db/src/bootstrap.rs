@@ -45,7 +45,7 @@ pub const USER0: i64 = 0x10000;
 pub const CORE_SCHEMA_VERSION: u32 = 1;
 
 lazy_static! {
-    static ref V1_IDENTS: [(symbols::Keyword, i64); 40] = {
+    static ref V1_IDENTS: [(symbols::Keyword, i64); 44] = {
             [(ns_keyword!("db", "ident"),             entids::DB_IDENT),
              (ns_keyword!("db.part", "db"),           entids::DB_PART_DB),
              (ns_keyword!("db", "txInstant"),         entids::DB_TX_INSTANT),
@@ -86,6 +86,10 @@ lazy_static! {
              (ns_keyword!("db.schema", "version"),    entids::DB_SCHEMA_VERSION),
              (ns_keyword!("db.schema", "attribute"),  entids::DB_SCHEMA_ATTRIBUTE),
              (ns_keyword!("db.schema", "core"),       entids::DB_SCHEMA_CORE),
+             (ns_keyword!("db", "normalize"),         entids::DB_NORMALIZE),
+             (ns_keyword!("db", "caseInsensitive"),   entids::DB_CASE_INSENSITIVE),
+             (ns_keyword!("db.type", "bigint"),       entids::DB_TYPE_BIGINT),
+             (ns_keyword!("db", "uniqueWith"),        entids::DB_UNIQUE_WITH),
         ]
     };
 
@@ -96,7 +100,7 @@ lazy_static! {
         ]
     };
 
-    static ref V1_CORE_SCHEMA: [(symbols::Keyword); 16] = {
+    static ref V1_CORE_SCHEMA: [(symbols::Keyword); 19] = {
             [(ns_keyword!("db", "ident")),
              (ns_keyword!("db.install", "partition")),
              (ns_keyword!("db.install", "valueType")),
@@ -110,6 +114,9 @@ lazy_static! {
              (ns_keyword!("db", "index")),
              (ns_keyword!("db", "fulltext")),
              (ns_keyword!("db", "noHistory")),
+             (ns_keyword!("db", "normalize")),
+             (ns_keyword!("db", "caseInsensitive")),
+             (ns_keyword!("db", "uniqueWith")),
              (ns_keyword!("db.alter", "attribute")),
              (ns_keyword!("db.schema", "version")),
              (ns_keyword!("db.schema", "attribute")),
@@ -150,6 +157,12 @@ lazy_static! {
                         :db/cardinality :db.cardinality/one}
  :db/noHistory         {:db/valueType   :db.type/boolean
                         :db/cardinality :db.cardinality/one}
+ :db/normalize         {:db/valueType   :db.type/boolean
+                        :db/cardinality :db.cardinality/one}
+ :db/caseInsensitive   {:db/valueType   :db.type/boolean
+                        :db/cardinality :db.cardinality/one}
+ :db/uniqueWith        {:db/valueType   :db.type/ref
+                        :db/cardinality :db.cardinality/one}
  :db.alter/attribute   {:db/valueType   :db.type/ref
                         :db/cardinality :db.cardinality/many}
  :db.schema/version    {:db/valueType   :db.type/long
@@ -309,3 +322,41 @@ pub(crate) fn bootstrap_entities() -> Vec<Entity<edn::ValueAndSpan>> {
     let bootstrap_entities: Vec<Entity<edn::ValueAndSpan>> = edn::parse::entities(&bootstrap_assertions.to_string()).expect("bootstrap assertions");
     return bootstrap_entities;
 }
+
+/// The bootstrap assertions installing the idents, and core schema attributes, that `ident_map`
+/// doesn't have -- those Mentat has added since a store was created -- and the entids they have.
+pub(crate) fn new_bootstrap_entities(ident_map: &IdentMap) -> (Vec<i64>, Vec<Entity<edn::ValueAndSpan>>) {
+    let new_idents: Vec<(symbols::Keyword, i64)> = V1_IDENTS.iter()
+        .filter(|&&(ref ident, _)| !ident_map.contains_key(ident))
+        .cloned()
+        .collect();
+    if new_idents.is_empty() {
+        return (vec![], vec![]);
+    }
+
+    let is_new = |ident: &symbols::Keyword| new_idents.iter().any(|&(ref new, _)| new == ident);
+    let new_core_schema: Vec<symbols::Keyword> = V1_CORE_SCHEMA.iter()
+        .filter(|ident| is_new(ident))
+        .cloned()
+        .collect();
+    let new_symbolic_schema = match *V1_SYMBOLIC_SCHEMA {
+        Value::Map(ref m) => Value::Map(m.iter()
+            .filter(|&(ident, _)| match ident {
+                &Value::Keyword(ref ident) => is_new(ident),
+                _ => false,
+            })
+            .map(|(ident, mp)| (ident.clone(), mp.clone()))
+            .collect()),
+        _ => unreachable!(),
+    };
+
+    let assertions: Value = Value::Vector([
+        symbolic_schema_to_assertions(&new_symbolic_schema).expect("symbolic schema"),
+        idents_to_assertions(&new_idents[..]),
+        schema_attrs_to_assertions(CORE_SCHEMA_VERSION, &new_core_schema[..]),
+    ].concat());
+
+    // As in `bootstrap_entities`, failure here is a coding error.
+    let entities: Vec<Entity<edn::ValueAndSpan>> = edn::parse::entities(&assertions.to_string()).expect("bootstrap assertions");
+    (new_idents.into_iter().map(|(_, entid)| entid).collect(), entities)
+}
@@ -108,6 +108,7 @@ use edn::entities::{
 
 use db::{
     TypedSQLValue,
+    resolved_value_sql,
 };
 
 use db_traits::errors::{
@@ -896,7 +897,8 @@ impl AttributeCaches {
                   attribute: Entid) -> Result<()> {
         let is_fulltext = schema.attribute_for_entid(attribute).map_or(false, |s| s.fulltext);
         let table = if is_fulltext { "fulltext_datoms" } else { "datoms" };
-        let sql = format!("SELECT a, e, v, value_type_tag FROM {} WHERE a = ? ORDER BY a ASC, e ASC", table);
+        let sql = format!("SELECT a, e, {}, value_type_tag FROM {} WHERE a = ? ORDER BY a ASC, e ASC",
+                          resolved_value_sql(table), table);
         let args: Vec<&rusqlite::types::ToSql> = vec![&attribute];
         let mut stmt = sqlite.prepare(&sql).context(DbErrorKind::CacheUpdateFailed)?;
         let replacing = true;
@@ -967,9 +969,9 @@ impl AttributeCaches {
         // Mark the attributes as cached as we go. We do this because we're going in through the
         // back door here, and the usual caching API won't have taken care of this for us.
         let mut qb = SQLiteQueryBuilder::new();
-        qb.push_sql("SELECT a, e, v, value_type_tag FROM ");
         match attrs {
             AttributeSpec::All => {
+                qb.push_sql(&format!("SELECT a, e, {}, value_type_tag FROM ", resolved_value_sql("all_datoms")));
                 qb.push_sql("all_datoms WHERE e IN (");
                 interpose!(item, entities,
                            { qb.push_sql(&item.to_string()) },
@@ -988,6 +990,7 @@ impl AttributeCaches {
                 }
 
                 if has_non_fts {
+                    qb.push_sql(&format!("SELECT a, e, {}, value_type_tag FROM ", resolved_value_sql("datoms")));
                     qb.push_sql("datoms WHERE e IN (");
                     interpose!(item, entities,
                                { qb.push_sql(&item.to_string()) },
@@ -1003,10 +1006,11 @@ impl AttributeCaches {
 
                 if has_fts && has_non_fts {
                     // Both.
-                    qb.push_sql(" UNION ALL SELECT a, e, v, value_type_tag FROM ");
+                    qb.push_sql(" UNION ALL ");
                 }
 
                 if has_fts {
+                    qb.push_sql("SELECT a, e, v, value_type_tag FROM ");
                     qb.push_sql("fulltext_datoms WHERE e IN (");
                     interpose!(item, entities,
                                { qb.push_sql(&item.to_string()) },
@@ -1318,6 +1322,16 @@ impl CachedAttributes for SQLiteAttributeCache {
 }
 
 impl SQLiteAttributeCache {
+    /// The attributes cached in the forward direction.
+    pub fn forward_cached_attributes(&self) -> &BTreeSet<Entid> {
+        &self.inner.forward_cached_attributes
+    }
+
+    /// The attributes cached in the reverse direction.
+    pub fn reverse_cached_attributes(&self) -> &BTreeSet<Entid> {
+        &self.inner.reverse_cached_attributes
+    }
+
     /// Intended for use from tests.
     pub fn values_pairs<U>(&self, schema: &Schema, attribute: U) -> Option<&BTreeMap<Entid, Vec<TypedValue>>>
     where U: Into<Entid> {
@@ -1339,6 +1353,10 @@ pub struct InProgressSQLiteAttributeCache {
     pub overlay: AttributeCaches,
     unregistered_forward: BTreeSet<Entid>,
     unregistered_reverse: BTreeSet<Entid>,
+
+    /// Attributes repopulated after their cardinality or uniqueness was altered: their values in
+    /// `inner` are cached in the wrong shape, and are dropped on commit.
+    recached: BTreeSet<Entid>,
 }
 
 impl InProgressSQLiteAttributeCache {
@@ -1349,6 +1367,7 @@ impl InProgressSQLiteAttributeCache {
             overlay: overlay,
             unregistered_forward: Default::default(),
             unregistered_reverse: Default::default(),
+            recached: Default::default(),
         }
     }
 
@@ -1412,6 +1431,29 @@ impl InProgressSQLiteAttributeCache {
     }
 
 
+    /// Repopulate the caches of `attribute` after its cardinality or uniqueness has been altered.
+    /// Single- and multi-valued attributes are cached differently, as are unique and non-unique
+    /// attributes in reverse, so the existing caches can't simply be updated.
+    pub fn recache<U>(&mut self, schema: &Schema, sqlite: &rusqlite::Connection, attribute: U) -> Result<()>
+    where U: Into<Entid> {
+        let a = attribute.into();
+        let forward = self.is_attribute_cached_forward(a);
+        let reverse = self.is_attribute_cached_reverse(a);
+        if !forward && !reverse {
+            return Ok(());
+        }
+
+        self.unregister(a);
+        self.recached.insert(a);
+        if forward {
+            self.register_forward(schema, sqlite, a)?;
+        }
+        if reverse {
+            self.register_reverse(schema, sqlite, a)?;
+        }
+        Ok(())
+    }
+
     pub fn unregister<U>(&mut self, attribute: U)
     where U: Into<Entid> {
         let a = attribute.into();
@@ -1566,6 +1608,9 @@ impl InProgressSQLiteAttributeCache {
             for unregistered in self.unregistered_forward.union(&self.unregistered_reverse) {
                 dest.unregister_attribute(*unregistered);
             }
+            for recached in self.recached.iter() {
+                dest.unregister_attribute(*recached);
+            }
 
             // Now replace each attribute's entry with `overlay`.
             dest.absorb(self.overlay);
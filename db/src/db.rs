@@ -14,16 +14,25 @@ use failure::{
     ResultExt,
 };
 
-use std::collections::HashMap;
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+    HashMap,
+    HashSet,
+};
 use std::collections::hash_map::{
     Entry,
 };
 use std::iter::{once, repeat};
 use std::ops::Range;
-use std::path::Path;
+use std::path::{
+    Path,
+    PathBuf,
+};
 
 use itertools;
 use itertools::Itertools;
+use regex::Regex;
 use rusqlite;
 use rusqlite::TransactionBehavior;
 use rusqlite::limits::Limit;
@@ -33,6 +42,7 @@ use ::{repeat_values, to_namespaced_keyword};
 use bootstrap;
 
 use edn::{
+    BigInt,
     DateTime,
     Utc,
     Uuid,
@@ -51,17 +61,28 @@ use core_traits::{
 };
 
 use mentat_core::{
+    AttributeIndexes,
     AttributeMap,
+    bigint_from_sql_text,
+    bigint_to_sql_text,
     FromMicros,
     IdentMap,
+    IndexDirection,
     Schema,
+    Statistics,
     ToMicros,
     ValueRc,
 };
 
+use mentat_core::normalization::{
+    fold_case,
+    fold_for_fulltext,
+};
+
 use db_traits::errors::{
     DbErrorKind,
     Result,
+    SchemaConstraintViolation,
 };
 
 use metadata;
@@ -89,10 +110,10 @@ fn escape_string_for_pragma(s: &str) -> String {
     s.replace("'", "''")
 }
 
-fn make_connection(uri: &Path, maybe_encryption_key: Option<&str>) -> rusqlite::Result<rusqlite::Connection> {
+fn make_connection(uri: &Path, maybe_encryption_key: Option<&str>, flags: rusqlite::OpenFlags) -> rusqlite::Result<rusqlite::Connection> {
     let conn = match uri.to_string_lossy().len() {
-        0 => rusqlite::Connection::open_in_memory()?,
-        _ => rusqlite::Connection::open(uri)?,
+        0 => rusqlite::Connection::open_in_memory_with_flags(flags)?,
+        _ => rusqlite::Connection::open_with_flags(uri, flags)?,
     };
 
     let page_size = 32768;
@@ -118,26 +139,140 @@ fn make_connection(uri: &Path, maybe_encryption_key: Option<&str>) -> rusqlite::
     // Some of the platforms we support do not have a tmp partition (e.g. Android)
     // necessary to store temp files on disk. Ideally, consumers should be able to
     // override this behaviour (see issue 505).
-    conn.execute_batch(&format!("
-        {}
+    //
+    // A read-only connection can't change the journal mode, so it reads the store in whatever
+    // mode it was written.
+    //
+    // Incremental auto-vacuum only takes effect for a new database, or at the next `VACUUM` of an
+    // existing one; see `maintenance::incremental_vacuum`.
+    let journal_pragmas = if flags.contains(rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY) {
+        ""
+    } else {
+        "
         PRAGMA journal_mode=wal;
         PRAGMA wal_autocheckpoint=32;
         PRAGMA journal_size_limit=3145728;
+        PRAGMA auto_vacuum=INCREMENTAL;
+        "
+    };
+    conn.execute_batch(&format!("
+        {}
+        {}
         PRAGMA foreign_keys=ON;
         PRAGMA temp_store=2;
-    ", initial_pragmas))?;
+    ", initial_pragmas, journal_pragmas))?;
+
+    conn.create_scalar_function(FULLTEXT_SCORE_FUNCTION, 1, true, |ctx| {
+        let matchinfo: Vec<u8> = ctx.get(0)?;
+        Ok(fulltext_score(&matchinfo))
+    })?;
+
+    // Queries use the same few patterns over and over, so we keep them compiled.
+    let mut regexes: HashMap<String, Regex> = HashMap::new();
+    conn.create_scalar_function(REGEXP_FUNCTION, 2, true, move |ctx| {
+        if regexes.len() >= MAX_CACHED_REGEXES {
+            regexes.clear();
+        }
+        let pattern: String = ctx.get(0)?;
+        let regex = match regexes.entry(pattern) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let regex = Regex::new(entry.key()).map_err(|e| rusqlite::Error::UserFunctionError(Box::new(e)))?;
+                entry.insert(regex)
+            },
+        };
+        // SQLite might try values before checking their type tags, so anything that isn't text
+        // simply doesn't match.
+        match ctx.get::<rusqlite::types::Value>(1)? {
+            rusqlite::types::Value::Text(text) => Ok(regex.is_match(&text)),
+            _ => Ok(false),
+        }
+    })?;
 
     Ok(conn)
 }
 
+/// The SQL function with which queries score fulltext matches.  Its argument is
+/// `matchinfo(fulltext_values, 'pcnalx')`.
+pub const FULLTEXT_SCORE_FUNCTION: &'static str = "mentat_fulltext_score";
+
+/// The SQL function that SQLite calls to evaluate `v REGEXP pattern`, as `regexp(pattern, v)`.
+/// Patterns use the syntax of the `regex` crate.
+pub const REGEXP_FUNCTION: &'static str = "regexp";
+
+/// How many compiled patterns each connection keeps for `REGEXP_FUNCTION`.
+const MAX_CACHED_REGEXES: usize = 64;
+
+/// Score a fulltext match using Okapi BM25, given the match's `pcnalx` matchinfo.  Higher scores
+/// are more relevant.  Scores are only comparable between rows matching the same search.
+///
+/// FTS4 doesn't rank matches itself; see https://www.sqlite.org/fts3.html#matchinfo for the layout
+/// of the matchinfo blob, which is an array of native-endian unsigned 32-bit integers.
+pub fn fulltext_score(matchinfo: &[u8]) -> f64 {
+    const K1: f64 = 1.2;
+    const B: f64 = 0.75;
+
+    let values: Vec<u32> = matchinfo.chunks(4)
+                                    .filter(|chunk| chunk.len() == 4)
+                                    .map(|chunk| {
+                                        let mut bytes = [0u8; 4];
+                                        bytes.copy_from_slice(chunk);
+                                        u32::from_ne_bytes(bytes)
+                                    })
+                                    .collect();
+    if values.len() < 3 {
+        return 0.0;
+    }
+
+    let phrases = values[0] as usize;
+    let columns = values[1] as usize;
+    let rows = values[2] as f64;
+    let averages = &values[3..];
+    let lengths = &values[(3 + columns).min(values.len())..];
+    let hits = &values[(3 + 2 * columns).min(values.len())..];
+    if hits.len() < 3 * phrases * columns {
+        return 0.0;
+    }
+
+    let mut score = 0.0;
+    for phrase in 0..phrases {
+        for column in 0..columns {
+            let x = 3 * (phrase * columns + column);
+            let hits_in_row = hits[x] as f64;
+            let rows_with_hits = hits[x + 2] as f64;
+            if hits_in_row == 0.0 {
+                continue;
+            }
+            // This form of IDF is never negative, even for very common terms.
+            let idf = (1.0 + (rows - rows_with_hits + 0.5) / (rows_with_hits + 0.5)).ln();
+            let average = (averages[column] as f64).max(1.0);
+            let length = lengths[column] as f64;
+            score += idf * (hits_in_row * (K1 + 1.0)) / (hits_in_row + K1 * (1.0 - B + B * length / average));
+        }
+    }
+    score
+}
+
 pub fn new_connection<T>(uri: T) -> rusqlite::Result<rusqlite::Connection> where T: AsRef<Path> {
-    make_connection(uri.as_ref(), None)
+    make_connection(uri.as_ref(), None, rusqlite::OpenFlags::default())
+}
+
+/// Like `new_connection`, but opened with `flags`: for example, `SQLITE_OPEN_READ_ONLY`, or
+/// without `SQLITE_OPEN_CREATE` to fail if there's no database at `uri`.
+pub fn new_connection_with_flags<T>(uri: T, flags: rusqlite::OpenFlags) -> rusqlite::Result<rusqlite::Connection> where T: AsRef<Path> {
+    make_connection(uri.as_ref(), None, flags)
 }
 
 #[cfg(feature = "sqlcipher")]
 pub fn new_connection_with_key<P, S>(uri: P, encryption_key: S) -> rusqlite::Result<rusqlite::Connection>
 where P: AsRef<Path>, S: AsRef<str> {
-    make_connection(uri.as_ref(), Some(encryption_key.as_ref()))
+    make_connection(uri.as_ref(), Some(encryption_key.as_ref()), rusqlite::OpenFlags::default())
+}
+
+#[cfg(feature = "sqlcipher")]
+pub fn new_connection_with_key_and_flags<P, S>(uri: P, encryption_key: S, flags: rusqlite::OpenFlags) -> rusqlite::Result<rusqlite::Connection>
+where P: AsRef<Path>, S: AsRef<str> {
+    make_connection(uri.as_ref(), Some(encryption_key.as_ref()), flags)
 }
 
 #[cfg(feature = "sqlcipher")]
@@ -149,10 +284,163 @@ where S: AsRef<str> {
     conn.execute_batch(&format!("PRAGMA rekey = '{}';", escaped))
 }
 
+/// SQLite's default page cache size, in KiB, expressed as `PRAGMA cache_size` expresses it.
+const DEFAULT_CACHE_SIZE: i64 = -2000;
+
+/// Let SQLite spill the temporary tables and indices it builds for this connection -- to sort,
+/// group, or de-duplicate query results -- to temporary files once they outgrow `threshold`
+/// bytes, rather than holding them in memory.  Pass `None` to keep them in memory, which is the
+/// default.
+///
+/// The threshold is the connection's page cache size, which is also what bounds SQLite's sorter
+/// before it spills, so it doesn't affect other connections.  This only bounds SQLite's own work.
+/// A query that orders by a computed value still sorts in SQLite, so reading its results with
+/// `PreparedQuery::rows`, which steps SQLite's cursor, keeps them on disk until they're read;
+/// running it with `q_once` or `PreparedQuery::run` collects them all in memory.
+///
+/// This trades speed for bounded memory on constrained devices.  Spilling requires a writable
+/// temporary directory, which is why it isn't the default; see `make_connection`.
+pub fn set_spill_threshold(conn: &rusqlite::Connection, threshold: Option<u64>) -> rusqlite::Result<()> {
+    let (temp_store, cache_size) = match threshold {
+        // A negative cache size is in KiB rather than pages.
+        Some(bytes) => (1, -((bytes / 1024).max(1) as i64)),
+        None => (2, DEFAULT_CACHE_SIZE),
+    };
+    conn.execute_batch(&format!("PRAGMA cache_size = {}; PRAGMA temp_store = {};", cache_size, temp_store))
+}
+
+/// How many times a store has been written, and how many of those writes changed its schema.
+///
+/// Every commit that writes bumps the generation, so a connection that remembers the generation
+/// its metadata describes can tell when another connection -- perhaps in another process -- has
+/// written to the store since, and reload.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub struct StoreGeneration {
+    pub data: i64,
+    pub schema: i64,
+}
+
+/// Stores created before generations were recorded start counting from zero.
+fn ensure_generation(conn: &rusqlite::Connection) -> Result<()> {
+    let exists: i64 = conn.query_row("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'generation'",
+                                     &[], |row| row.get(0))?;
+    if exists == 0 {
+        conn.execute_batch("CREATE TABLE generation (data INTEGER NOT NULL, schema INTEGER NOT NULL);
+                            INSERT INTO generation (data, schema) VALUES (0, 0);")?;
+    }
+    Ok(())
+}
+
+/// Stores created before large values were offloaded have nowhere to put them.
+fn ensure_large_values(conn: &rusqlite::Connection) -> Result<()> {
+    conn.execute("CREATE TABLE IF NOT EXISTS large_values (id INTEGER PRIMARY KEY, text TEXT NOT NULL UNIQUE)", &[])?;
+    Ok(())
+}
+
+/// Return the store's current generation.
+pub fn read_generation(conn: &rusqlite::Connection) -> Result<StoreGeneration> {
+    let mut stmt = conn.prepare_cached("SELECT data, schema FROM generation")?;
+    let generation = stmt.query_row(&[], |row| StoreGeneration { data: row.get(0), schema: row.get(1) })?;
+    Ok(generation)
+}
+
+/// Record a write, and whether it changed the schema, returning the new generation.  This must be
+/// done in the writing transaction.
+pub fn bump_generation(conn: &rusqlite::Connection, schema_changed: bool) -> Result<StoreGeneration> {
+    conn.execute("UPDATE generation SET data = data + 1, schema = schema + ?", &[&(schema_changed as i64)])?;
+    read_generation(conn)
+}
+
+/// Count the datoms for each attribute.  This reads all of the AEVT index.
+pub fn read_statistics(conn: &rusqlite::Connection) -> Result<Statistics> {
+    let mut stmt = conn.prepare("SELECT a, count(*) FROM datoms INDEXED BY idx_datoms_aevt GROUP BY a")?;
+    let counts: Result<BTreeMap<Entid, u64>> = stmt.query_and_then(&[], |row| -> Result<(Entid, u64)> {
+        let count: i64 = row.get_checked(1)?;
+        Ok((row.get_checked(0)?, count as u64))
+    })?.collect();
+    Ok(Statistics::new(counts?))
+}
+
+/// Create an index of the datoms of `attribute`, ordered by `direction`, unless there is one
+/// already.  The index is partial, so it's only as large as the attribute, and covers every
+/// column that queries read.
+pub fn ensure_attribute_index(conn: &rusqlite::Connection, attribute: Entid, direction: IndexDirection) -> Result<()> {
+    let columns = match direction {
+        IndexDirection::Forward => "a, e, value_type_tag, v, tx",
+        IndexDirection::Reverse => "a, value_type_tag, v, e, tx",
+    };
+    // `attribute` is an integer, so it's safe to format into SQL, and it has to be: SQLite only
+    // uses a partial index for a query whose constraints match its own literally.
+    conn.execute(&format!("CREATE INDEX IF NOT EXISTS {} ON datoms ({}) WHERE a = {}",
+                          direction.index_name(attribute), columns, attribute),
+                 &[])?;
+    Ok(())
+}
+
+/// Drop the index of the datoms of `attribute` ordered by `direction`, if there is one.
+pub fn drop_attribute_index(conn: &rusqlite::Connection, attribute: Entid, direction: IndexDirection) -> Result<()> {
+    conn.execute(&format!("DROP INDEX IF EXISTS {}", direction.index_name(attribute)), &[])?;
+    Ok(())
+}
+
+/// Find the indexes that `ensure_attribute_index` has created.
+pub fn read_attribute_indexes(conn: &rusqlite::Connection) -> Result<AttributeIndexes> {
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'datoms'")?;
+    let names: Result<Vec<String>> = stmt.query_and_then(&[], |row| -> Result<String> {
+        Ok(row.get_checked(0)?)
+    })?.collect();
+    Ok(AttributeIndexes::new(names?.iter().filter_map(|name| IndexDirection::from_index_name(name)).collect()))
+}
+
+/// Return the path of the file backing the main database of `conn`, or `None` for in-memory and
+/// temporary databases.
+pub fn database_path(conn: &rusqlite::Connection) -> rusqlite::Result<Option<PathBuf>> {
+    let mut stmt = conn.prepare("PRAGMA database_list")?;
+    let mut rows = stmt.query(&[])?;
+    while let Some(row) = rows.next() {
+        let row = row?;
+        let name: String = row.get_checked(1)?;
+        if name == "main" {
+            let file: Option<String> = row.get_checked(2)?;
+            return Ok(file.filter(|f| !f.is_empty()).map(PathBuf::from));
+        }
+    }
+    Ok(None)
+}
+
+/// Read the parts of the `datoms` table that queries about `attribute` use -- its run of the AEVT
+/// index, the rows that run points to, and its run of the AVET index if it has one -- so that they
+/// are in the operating system's page cache before anyone asks for them.  Returns the number of
+/// datoms read.
+///
+/// This is only useful for a file-backed database, and from a connection other than the one that
+/// will run those queries: its purpose is the I/O, not the answer.
+pub fn warm_attribute(conn: &rusqlite::Connection, entid: Entid, attribute: &Attribute) -> rusqlite::Result<i64> {
+    // `total(tx)` reads each row, since `tx` isn't in the index, without risking overflow.
+    let count: i64 = conn.query_row("SELECT count(tx), total(tx) FROM datoms INDEXED BY idx_datoms_aevt WHERE a = ?",
+                                    &[&entid],
+                                    |row| row.get(0))?;
+    if attribute.flags() & AttributeBitFlags::IndexAVET as u8 != 0 {
+        conn.query_row("SELECT count(*) FROM datoms INDEXED BY idx_datoms_avet WHERE a = ? AND index_avet IS NOT 0",
+                       &[&entid],
+                       |_| ())?;
+    }
+    Ok(count)
+}
+
 /// Version history:
 ///
 /// 1: initial Rust Mentat schema.
-pub const CURRENT_VERSION: i32 = 1;
+/// 2: `datoms` has a `unique_folded` column, for `:db/caseInsensitive`.
+/// 3: `fulltext_values` has a `folded` column, for `:db/normalize`.
+/// 4: big integers are stored as text that sorts in numeric order.
+/// 5: the core schema has `:db/uniqueWith`.
+/// 6: `datoms` has a `folded` column, holding the case-folded values of `:db/caseInsensitive`
+///    attributes.
+///
+/// Upgrading a store also installs the idents and core schema attributes that Mentat has added
+/// since it was created.  See `upgrade_current_version`.
+pub const CURRENT_VERSION: i32 = 6;
 
 /// MIN_SQLITE_VERSION should be changed when there's a new minimum version of sqlite required
 /// for the project to work.
@@ -169,15 +457,40 @@ fn to_bool_ref(x: bool) -> &'static bool {
     if x { TRUE } else { FALSE }
 }
 
+const CREATE_IDX_DATOMS_UNIQUE_FOLDED: &'static str =
+    r#"CREATE UNIQUE INDEX idx_datoms_unique_folded ON datoms (a, value_type_tag, folded) WHERE unique_folded IS NOT 0"#;
+
+// Optional settings:
+// tokenize="porter"#,
+// prefix='2,3'
+// By default we use Unicode-aware tokenizing (particularly for case folding), but preserve
+// diacritics.  Values of :db/normalize attributes also populate `folded`, a
+// compatibility-decomposed form without diacritics that fulltext searches match against.
+const CREATE_FULLTEXT_VALUES: &'static str =
+    r#"CREATE VIRTUAL TABLE fulltext_values
+         USING FTS4 (text NOT NULL, searchid INT, folded, tokenize=unicode61 "remove_diacritics=0")"#;
+
+/// SQL statements to be executed, in order, to drop what `DERIVED_STATEMENTS` creates.
+const DROP_DERIVED_STATEMENTS: [&'static str; 5] = [
+    "DROP VIEW IF EXISTS all_datoms",
+    "DROP VIEW IF EXISTS fulltext_datoms",
+    "DROP TRIGGER IF EXISTS insert_fulltext_searchid",
+    "DROP TRIGGER IF EXISTS replace_fulltext_searchid",
+    "DROP VIEW IF EXISTS fulltext_values_view",
+];
+
 lazy_static! {
-    /// SQL statements to be executed, in order, to create the Mentat SQL schema (version 1).
+    /// SQL statements to be executed, in order, to create the Mentat SQL schema's tables.  The
+    /// views and triggers over them follow, in `DERIVED_STATEMENTS`.
     #[cfg_attr(rustfmt, rustfmt_skip)]
     static ref V1_STATEMENTS: Vec<&'static str> = { vec![
         r#"CREATE TABLE datoms (e INTEGER NOT NULL, a SMALLINT NOT NULL, v BLOB NOT NULL, tx INTEGER NOT NULL,
                                 value_type_tag SMALLINT NOT NULL,
                                 index_avet TINYINT NOT NULL DEFAULT 0, index_vaet TINYINT NOT NULL DEFAULT 0,
                                 index_fulltext TINYINT NOT NULL DEFAULT 0,
-                                unique_value TINYINT NOT NULL DEFAULT 0)"#,
+                                unique_value TINYINT NOT NULL DEFAULT 0,
+                                unique_folded TINYINT NOT NULL DEFAULT 0,
+                                folded TEXT)"#,
         r#"CREATE UNIQUE INDEX idx_datoms_eavt ON datoms (e, a, value_type_tag, v)"#,
         r#"CREATE UNIQUE INDEX idx_datoms_aevt ON datoms (a, e, value_type_tag, v)"#,
 
@@ -199,60 +512,76 @@ lazy_static! {
         // differentiate, e.g., keywords and strings.
         r#"CREATE UNIQUE INDEX idx_datoms_unique_value ON datoms (a, value_type_tag, v) WHERE unique_value IS NOT 0"#,
 
+        // Opt-in index: only if a has :db/caseInsensitive true.  Unlike the index above, this one
+        // is what enforces uniqueness, since the transactor only compares values exactly.  `folded`
+        // is the value case-folded by the transactor; SQLite's lower() only folds ASCII.
+        CREATE_IDX_DATOMS_UNIQUE_FOLDED,
+
         r#"CREATE TABLE timelined_transactions (e INTEGER NOT NULL, a SMALLINT NOT NULL, v BLOB NOT NULL, tx INTEGER NOT NULL, added TINYINT NOT NULL DEFAULT 1, value_type_tag SMALLINT NOT NULL, timeline TINYINT NOT NULL DEFAULT 0)"#,
         r#"CREATE INDEX idx_timelined_transactions_timeline ON timelined_transactions (timeline)"#,
         r#"CREATE VIEW transactions AS SELECT e, a, v, value_type_tag, tx, added FROM timelined_transactions WHERE timeline IS 0"#,
 
         // Fulltext indexing.
         // A fulltext indexed value v is an integer rowid referencing fulltext_values.
+        CREATE_FULLTEXT_VALUES,
+
+        // Large value offloading.
+        // A :db.type/string value longer than the transactor's configured threshold is stored once
+        // here, and `datoms` and `transactions` store its integer id instead, keeping the hot
+        // indices compact.  Outside of fulltext indexed attributes, offloaded values are the only
+        // :db.type/string values stored as integers.
+        r#"CREATE TABLE large_values (id INTEGER PRIMARY KEY, text TEXT NOT NULL UNIQUE)"#,
+
+        // Materialized views of the metadata.
+        r#"CREATE TABLE idents (e INTEGER NOT NULL, a SMALLINT NOT NULL, v BLOB NOT NULL, value_type_tag SMALLINT NOT NULL)"#,
+        r#"CREATE INDEX idx_idents_unique ON idents (e, a, v, value_type_tag)"#,
+        r#"CREATE TABLE schema (e INTEGER NOT NULL, a SMALLINT NOT NULL, v BLOB NOT NULL, value_type_tag SMALLINT NOT NULL)"#,
+        r#"CREATE INDEX idx_schema_unique ON schema (e, a, v, value_type_tag)"#,
 
-        // Optional settings:
-        // tokenize="porter"#,
-        // prefix='2,3'
-        // By default we use Unicode-aware tokenizing (particularly for case folding), but preserve
-        // diacritics.
-        r#"CREATE VIRTUAL TABLE fulltext_values
-             USING FTS4 (text NOT NULL, searchid INT, tokenize=unicode61 "remove_diacritics=0")"#,
+        // TODO: store entid instead of ident for partition name.
+        r#"CREATE TABLE known_parts (part TEXT NOT NULL PRIMARY KEY, start INTEGER NOT NULL, end INTEGER NOT NULL, allow_excision SMALLINT NOT NULL)"#,
+
+        // See `StoreGeneration`.
+        r#"CREATE TABLE generation (data INTEGER NOT NULL, schema INTEGER NOT NULL)"#,
+        r#"INSERT INTO generation (data, schema) VALUES (0, 0)"#,
+        ]
+    };
 
+    /// SQL statements to be executed, in order, to create the views and triggers over the tables
+    /// of the Mentat SQL schema.  Upgrading a store drops and recreates these, since they depend
+    /// on the columns upgrades add.
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    static ref DERIVED_STATEMENTS: Vec<&'static str> = { vec![
         // This combination of view and triggers allows you to transparently
-        // update-or-insert into FTS. Just INSERT INTO fulltext_values_view (text, searchid).
+        // update-or-insert into FTS. Just INSERT INTO fulltext_values_view (text, searchid, folded).
         r#"CREATE VIEW fulltext_values_view AS SELECT * FROM fulltext_values"#,
         r#"CREATE TRIGGER replace_fulltext_searchid
              INSTEAD OF INSERT ON fulltext_values_view
              WHEN EXISTS (SELECT 1 FROM fulltext_values WHERE text = new.text)
              BEGIN
-               UPDATE fulltext_values SET searchid = new.searchid WHERE text = new.text;
+               UPDATE fulltext_values SET searchid = new.searchid, folded = coalesce(new.folded, folded) WHERE text = new.text;
              END"#,
         r#"CREATE TRIGGER insert_fulltext_searchid
              INSTEAD OF INSERT ON fulltext_values_view
              WHEN NOT EXISTS (SELECT 1 FROM fulltext_values WHERE text = new.text)
              BEGIN
-               INSERT INTO fulltext_values (text, searchid) VALUES (new.text, new.searchid);
+               INSERT INTO fulltext_values (text, searchid, folded) VALUES (new.text, new.searchid, new.folded);
              END"#,
 
         // A view transparently interpolating fulltext indexed values into the datom structure.
         r#"CREATE VIEW fulltext_datoms AS
-             SELECT e, a, fulltext_values.text AS v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value
+             SELECT e, a, fulltext_values.text AS v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value, unique_folded, datoms.folded
                FROM datoms, fulltext_values
                WHERE datoms.index_fulltext IS NOT 0 AND datoms.v = fulltext_values.rowid"#,
 
         // A view transparently interpolating all entities (fulltext and non-fulltext) into the datom structure.
         r#"CREATE VIEW all_datoms AS
-             SELECT e, a, v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value
+             SELECT e, a, v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value, unique_folded, folded
                FROM datoms
                WHERE index_fulltext IS 0
              UNION ALL
-             SELECT e, a, v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value
+             SELECT e, a, v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value, unique_folded, folded
                FROM fulltext_datoms"#,
-
-        // Materialized views of the metadata.
-        r#"CREATE TABLE idents (e INTEGER NOT NULL, a SMALLINT NOT NULL, v BLOB NOT NULL, value_type_tag SMALLINT NOT NULL)"#,
-        r#"CREATE INDEX idx_idents_unique ON idents (e, a, v, value_type_tag)"#,
-        r#"CREATE TABLE schema (e INTEGER NOT NULL, a SMALLINT NOT NULL, v BLOB NOT NULL, value_type_tag SMALLINT NOT NULL)"#,
-        r#"CREATE INDEX idx_schema_unique ON schema (e, a, v, value_type_tag)"#,
-
-        // TODO: store entid instead of ident for partition name.
-        r#"CREATE TABLE known_parts (part TEXT NOT NULL PRIMARY KEY, start INTEGER NOT NULL, end INTEGER NOT NULL, allow_excision SMALLINT NOT NULL)"#,
         ]
     };
 }
@@ -282,7 +611,7 @@ fn get_user_version(conn: &rusqlite::Connection) -> Result<i32> {
 pub fn create_empty_current_version(conn: &mut rusqlite::Connection) -> Result<(rusqlite::Transaction, DB)> {
     let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
 
-    for statement in (&V1_STATEMENTS).iter() {
+    for statement in (&V1_STATEMENTS).iter().chain((&DERIVED_STATEMENTS).iter()) {
         tx.execute(statement, &[])?;
     }
 
@@ -323,6 +652,42 @@ fn create_current_partition_view(conn: &rusqlite::Connection) -> Result<()> {
     Ok(())
 }
 
+/// Create the partition `name`, starting at `start`, by splitting it off the end of the partition
+/// that contains `start`, which keeps the entids before it.  That partition can't be
+/// `:db.part/tx`, and mustn't have allocated `start` or any entid after it.  The new partition
+/// allows excision if the partition it's split from does.
+pub fn create_partition(conn: &rusqlite::Connection, partition_map: &mut PartitionMap, name: &str, start: Entid) -> Result<()> {
+    if partition_map.contains_key(name) {
+        bail!(DbErrorKind::CannotCreatePartition(name.to_string(), "it already exists".to_string()));
+    }
+
+    let (from, partition) = match partition_map.iter().find(|&(_, partition)| partition.allows_entid(start)) {
+        Some((from, partition)) => (from.clone(), partition.clone()),
+        None => bail!(DbErrorKind::CannotCreatePartition(name.to_string(), format!("no partition contains {}", start))),
+    };
+    if from == ":db.part/tx" {
+        bail!(DbErrorKind::CannotCreatePartition(name.to_string(), format!("{} is reserved for transactions", from)));
+    }
+    if start <= partition.next_entid() {
+        bail!(DbErrorKind::CannotCreatePartition(name.to_string(), format!("{} has allocated entids up to {}", from, partition.next_entid())));
+    }
+    if start >= partition.end {
+        bail!(DbErrorKind::CannotCreatePartition(name.to_string(), format!("{} ends at {}", from, partition.end)));
+    }
+
+    conn.execute("UPDATE known_parts SET end = ? WHERE part = ?", &[&(start - 1), &from])?;
+    conn.execute("INSERT INTO known_parts (part, start, end, allow_excision) VALUES (?, ?, ?, ?)",
+                 &[&name, &start, &partition.end, &partition.allow_excision])?;
+
+    // The `parts` view assigns entids to partitions by their ends.
+    conn.execute("DROP VIEW parts", &[])?;
+    create_current_partition_view(conn)?;
+
+    partition_map.get_mut(&from).expect("partition").end = start - 1;
+    partition_map.insert(name.to_string(), Partition::new(start, partition.end, start, partition.allow_excision));
+    Ok(())
+}
+
 // TODO: rename "SQL" functions to align with "datoms" functions.
 pub fn create_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     let (tx, mut db) = create_empty_current_version(conn)?;
@@ -341,7 +706,7 @@ pub fn create_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     // TODO: return to transact_internal to self-manage the encompassing SQLite transaction.
     let bootstrap_schema_for_mutation = Schema::default(); // The bootstrap transaction will populate this schema.
 
-    let (_report, next_partition_map, next_schema, _watcher) = transact(&tx, db.partition_map, &bootstrap_schema_for_mutation, &db.schema, NullWatcher(), bootstrap::bootstrap_entities())?;
+    let (_report, next_partition_map, next_schema, _watcher) = transact(&tx, db.partition_map, &bootstrap_schema_for_mutation, &db.schema, NullWatcher(), RedundantAssertions::Skip, None, bootstrap::bootstrap_entities())?;
 
     // TODO: validate metadata mutations that aren't schema related, like additional partitions.
     if let Some(next_schema) = next_schema {
@@ -357,6 +722,157 @@ pub fn create_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     Ok(db)
 }
 
+/// Return true if `table` has a column named `column`.
+fn has_column(conn: &rusqlite::Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let names: Result<Vec<String>> = stmt.query_and_then(&[], |row| -> Result<String> {
+        Ok(row.get_checked(1)?)
+    })?.collect();
+    Ok(names?.iter().any(|name| name == column))
+}
+
+/// Version 2: `datoms` gains `unique_folded`.  No attribute of an older store can be
+/// `:db/caseInsensitive`, so every datom keeps the default.  The index on it is created by
+/// `upgrade_to_v6`.
+fn upgrade_to_v2(conn: &rusqlite::Connection) -> Result<()> {
+    if !has_column(conn, "datoms", "unique_folded")? {
+        conn.execute("ALTER TABLE datoms ADD COLUMN unique_folded TINYINT NOT NULL DEFAULT 0", &[])?;
+    }
+    Ok(())
+}
+
+/// Version 3: `fulltext_values` gains `folded`.  FTS4 tables can't gain columns, so we copy the
+/// values into a new table, keeping the rowids that `datoms` refers to.  No attribute of an older
+/// store can be `:db/normalize`, so no value has a folded form.
+fn upgrade_to_v3(conn: &rusqlite::Connection) -> Result<()> {
+    if has_column(conn, "fulltext_values", "folded")? {
+        return Ok(());
+    }
+    conn.execute("ALTER TABLE fulltext_values RENAME TO fulltext_values_v2", &[])?;
+    conn.execute(CREATE_FULLTEXT_VALUES, &[])?;
+    conn.execute_batch("INSERT INTO fulltext_values (rowid, text, searchid) SELECT rowid, text, searchid FROM fulltext_values_v2;
+                        DROP TABLE fulltext_values_v2;")?;
+    Ok(())
+}
+
+/// Version 4: big integers, which were stored as their decimal text, are stored as text that sorts
+/// in numeric order.  See `bigint_to_sql_text`.
+fn upgrade_to_v4(conn: &rusqlite::Connection) -> Result<()> {
+    for table in &["datoms", "timelined_transactions"] {
+        let values: Vec<String> = {
+            let mut stmt = conn.prepare(&format!("SELECT DISTINCT v FROM {} WHERE value_type_tag = 14", table))?;
+            let values: Result<Vec<String>> = stmt.query_and_then(&[], |row| -> Result<String> {
+                Ok(row.get_checked(0)?)
+            })?.collect();
+            values?
+        };
+
+        let mut stmt = conn.prepare(&format!("UPDATE {} SET v = ? WHERE value_type_tag = 14 AND v = ?", table))?;
+        for value in values {
+            let parsed = value.parse::<BigInt>()
+                .map_err(|_| DbErrorKind::CannotUpgrade(3, format!("{} is not a big integer", value)))?;
+            stmt.execute(&[&bigint_to_sql_text(&parsed), &value])?;
+        }
+    }
+    Ok(())
+}
+
+/// Version 6: `datoms` gains `folded`, and `idx_datoms_unique_folded` indexes it rather than
+/// `lower(v)`.  Values that only differed in the case of non-ASCII letters were allowed before,
+/// and now collide; we refuse to upgrade a store that has them rather than choose between them.
+fn upgrade_to_v6(conn: &rusqlite::Connection) -> Result<()> {
+    if !has_column(conn, "datoms", "folded")? {
+        conn.execute("ALTER TABLE datoms ADD COLUMN folded TEXT", &[])?;
+    }
+
+    let values: Vec<(i64, String)> = {
+        let mut stmt = conn.prepare("SELECT rowid, v FROM datoms WHERE unique_folded IS NOT 0")?;
+        let values: Result<Vec<(i64, String)>> = stmt.query_and_then(&[], |row| -> Result<(i64, String)> {
+            Ok((row.get_checked(0)?, row.get_checked(1)?))
+        })?.collect();
+        values?
+    };
+    let mut stmt = conn.prepare("UPDATE datoms SET folded = ? WHERE rowid = ?")?;
+    for (rowid, value) in values {
+        stmt.execute(&[&fold_case(&value), &rowid])?;
+    }
+
+    let mut stmt = conn.prepare(r#"SELECT a, min(v), max(v) FROM datoms WHERE unique_folded IS NOT 0
+                                   GROUP BY a, value_type_tag, folded HAVING count(*) > 1 LIMIT 1"#)?;
+    let mut rows = stmt.query(&[])?;
+    if let Some(row) = rows.next() {
+        let row = row?;
+        let (a, v1, v2): (Entid, String, String) = (row.get_checked(0)?, row.get_checked(1)?, row.get_checked(2)?);
+        bail!(DbErrorKind::CannotUpgrade(5, format!("values {:?} and {:?} of attribute {} differ only in case", v1, v2, a)));
+    }
+
+    conn.execute("DROP INDEX IF EXISTS idx_datoms_unique_folded", &[])?;
+    conn.execute(CREATE_IDX_DATOMS_UNIQUE_FOLDED, &[])?;
+    Ok(())
+}
+
+/// Install the idents, and core schema attributes, that the bootstrap has and the store doesn't.
+/// They take the entids the bootstrap gives them, which the store mustn't have used.
+fn install_new_bootstrap_idents(conn: &rusqlite::Connection, version: i32) -> Result<()> {
+    let db = read_db(conn)?;
+    let (entids, entities) = bootstrap::new_bootstrap_entities(&db.schema.ident_map);
+    if entities.is_empty() {
+        return Ok(());
+    }
+
+    let mut stmt = conn.prepare("SELECT count(*) FROM timelined_transactions WHERE e = ?")?;
+    for entid in entids {
+        let used: i64 = stmt.query_row(&[&entid], |row| row.get(0))?;
+        if used > 0 {
+            bail!(DbErrorKind::CannotUpgrade(version, format!("entid {} is already in use", entid)));
+        }
+    }
+
+    // The new idents are only known to the bootstrap schema, which is all they refer to.
+    let bootstrap_schema = bootstrap::bootstrap_schema();
+    transact(conn, db.partition_map, &db.schema, &bootstrap_schema, NullWatcher(), RedundantAssertions::Skip, None, entities)?;
+    Ok(())
+}
+
+/// Bring a store written by an earlier version of Mentat up to `CURRENT_VERSION`, in one SQLite
+/// transaction.
+fn upgrade_current_version(conn: &mut rusqlite::Connection, version: i32) -> Result<DB> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+
+    ensure_generation(&tx)?;
+    ensure_large_values(&tx)?;
+
+    // The views depend on the columns that the steps add.
+    for statement in DROP_DERIVED_STATEMENTS.iter() {
+        tx.execute(statement, &[])?;
+    }
+    if version < 2 {
+        upgrade_to_v2(&tx)?;
+    }
+    if version < 3 {
+        upgrade_to_v3(&tx)?;
+    }
+    if version < 4 {
+        upgrade_to_v4(&tx)?;
+    }
+    if version < 6 {
+        upgrade_to_v6(&tx)?;
+    }
+    for statement in (&DERIVED_STATEMENTS).iter() {
+        tx.execute(statement, &[])?;
+    }
+
+    install_new_bootstrap_idents(&tx, version)?;
+
+    // Connections that have the store open must reload its schema.
+    bump_generation(&tx, true)?;
+    set_user_version(&tx, CURRENT_VERSION)?;
+
+    let db = read_db(&tx)?;
+    tx.commit()?;
+    Ok(db)
+}
+
 pub fn ensure_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     if rusqlite::version_number() < MIN_SQLITE_VERSION {
         panic!("Mentat requires at least sqlite {}", MIN_SQLITE_VERSION);
@@ -365,7 +881,12 @@ pub fn ensure_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     let user_version = get_user_version(&conn)?;
     match user_version {
         0               => create_current_version(conn),
-        CURRENT_VERSION => read_db(conn),
+        CURRENT_VERSION => {
+            ensure_generation(conn)?;
+            ensure_large_values(conn)?;
+            read_db(conn)
+        },
+        v if v > 0 && v < CURRENT_VERSION => upgrade_current_version(conn, v),
 
         // TODO: support updating an existing store.
         v => bail!(DbErrorKind::NotYetImplemented(format!("Opening databases with Mentat version: {}", v))),
@@ -406,6 +927,13 @@ impl TypedSQLValue for TypedValue {
             (13, rusqlite::types::Value::Text(x)) => {
                 to_namespaced_keyword(&x).map(|k| k.into())
             },
+            (14, rusqlite::types::Value::Text(x)) => {
+                match bigint_from_sql_text(&x) {
+                    Some(i) => Ok(TypedValue::BigInteger(i)),
+                    None => bail!(DbErrorKind::BadSQLValuePair(rusqlite::types::Value::Text(x), value_type_tag)),
+                }
+            },
+            (15, rusqlite::types::Value::Blob(x)) => Ok(x.into()),
             (_, value) => bail!(DbErrorKind::BadSQLValuePair(value, value_type_tag)),
         }
     }
@@ -422,6 +950,8 @@ impl TypedSQLValue for TypedValue {
             &Value::Boolean(x) => Some(TypedValue::Boolean(x)),
             &Value::Instant(x) => Some(TypedValue::Instant(x)),
             &Value::Integer(x) => Some(TypedValue::Long(x)),
+            &Value::BigInteger(ref x) => Some(TypedValue::BigInteger(x.clone())),
+            &Value::Bytes(ref x) => Some(x.clone().into()),
             &Value::Uuid(x) => Some(TypedValue::Uuid(x)),
             &Value::Float(ref x) => Some(TypedValue::Double(x.clone())),
             &Value::Text(ref x) => Some(x.clone().into()),
@@ -442,6 +972,9 @@ impl TypedSQLValue for TypedValue {
             &TypedValue::String(ref x) => (rusqlite::types::ValueRef::Text(x.as_str()).into(), 10),
             &TypedValue::Uuid(ref u) => (rusqlite::types::Value::Blob(u.as_bytes().to_vec()).into(), 11),
             &TypedValue::Keyword(ref x) => (rusqlite::types::ValueRef::Text(&x.to_string()).into(), 13),
+            // Stored as text: SQLite's integers are only 64 bits wide.
+            &TypedValue::BigInteger(ref x) => (rusqlite::types::Value::Text(bigint_to_sql_text(x)).into(), 14),
+            &TypedValue::Bytes(ref x) => (rusqlite::types::ValueRef::Blob(x.as_slice()).into(), 15),
         }
     }
 
@@ -456,6 +989,8 @@ impl TypedSQLValue for TypedValue {
             &TypedValue::String(ref x) => (Value::Text(x.as_ref().clone()), ValueType::String),
             &TypedValue::Uuid(ref u) => (Value::Uuid(u.clone()), ValueType::Uuid),
             &TypedValue::Keyword(ref x) => (Value::Keyword(x.as_ref().clone()), ValueType::Keyword),
+            &TypedValue::BigInteger(ref x) => (Value::BigInteger(x.clone()), ValueType::BigInteger),
+            &TypedValue::Bytes(ref x) => (Value::Bytes(x.as_ref().clone()), ValueType::Bytes),
         }
     }
 }
@@ -537,7 +1072,7 @@ pub(crate) fn read_attribute_map(conn: &rusqlite::Connection) -> Result<Attribut
 
 /// Read the materialized views from the given SQL store and return a Mentat `DB` for querying and
 /// applying transactions.
-pub(crate) fn read_db(conn: &rusqlite::Connection) -> Result<DB> {
+pub fn read_db(conn: &rusqlite::Connection) -> Result<DB> {
     let partition_map = read_partition_map(conn)?;
     let ident_map = read_ident_map(conn)?;
     let attribute_map = read_attribute_map(conn)?;
@@ -554,6 +1089,169 @@ pub enum SearchType {
     Inexact,
 }
 
+/// What to do with assertions of datoms that are already present in the store.
+///
+/// Mentat treats the datoms of `:db.cardinality/many` attributes as a set, not a bag: re-asserting
+/// an existing datom changes nothing, so by default it isn't recorded in the transaction log,
+/// doesn't appear in history, and isn't synced.
+#[derive(Clone,Copy,Debug,Eq,Hash,Ord,PartialOrd,PartialEq)]
+pub enum RedundantAssertions {
+    /// Leave assertions of existing datoms out of the transaction log.
+    Skip,
+
+    /// Record assertions of existing datoms in the transaction log, just like new datoms.  The
+    /// store itself is unchanged.
+    Record,
+}
+
+impl Default for RedundantAssertions {
+    fn default() -> RedundantAssertions {
+        RedundantAssertions::Skip
+    }
+}
+
+/// Return true if values of the given attribute may be stored in `large_values`.
+///
+/// Fulltext values are already stored out of line; case-insensitive values are indexed beside
+/// their folded forms; and values of attributes that might update metadata are read back directly.
+fn may_offload(a: Entid, attribute: &Attribute) -> bool {
+    attribute.value_type == ValueType::String &&
+    !attribute.fulltext &&
+    !attribute.case_insensitive &&
+    !entids::might_update_metadata(a)
+}
+
+/// Return the id of the offloaded value `text`, if it has been offloaded.
+pub fn large_value_id(conn: &rusqlite::Connection, text: &str) -> Result<Option<i64>> {
+    let mut stmt = conn.prepare_cached("SELECT id FROM large_values WHERE text = ?")?;
+    let mut rows = stmt.query(&[&text])?;
+    match rows.next() {
+        Some(row) => Ok(Some(row?.get_checked(0)?)),
+        None => Ok(None),
+    }
+}
+
+/// Store `text` in `large_values`, if it isn't already there, and return its id.
+fn offload_large_value(conn: &rusqlite::Connection, text: &str) -> Result<i64> {
+    let mut stmt = conn.prepare_cached("INSERT OR IGNORE INTO large_values (text) VALUES (?)")?;
+    stmt.execute(&[&text])?;
+    large_value_id(conn, text)?.ok_or_else(|| DbErrorKind::NotYetImplemented(format!("Failed to offload value")).into())
+}
+
+/// Return true if any string has been offloaded to `large_values`.
+pub fn has_large_values(conn: &rusqlite::Connection) -> Result<bool> {
+    let mut stmt = conn.prepare_cached("SELECT EXISTS (SELECT 1 FROM large_values)")?;
+    stmt.query_row(&[], |row| row.get(0)).map_err(|e| e.into())
+}
+
+/// Represent the given value of the given attribute as it is stored.
+///
+/// Once a string has been offloaded it is always stored by id, regardless of the threshold, so
+/// that equal values are stored equally.  Lowering the threshold doesn't move values that are
+/// already stored inline.  If `offloaded` is false, no string has been offloaded, and we needn't
+/// look for one.
+fn to_stored_sql_value_pair<'a>(conn: &rusqlite::Connection,
+                                a: Entid,
+                                attribute: &Attribute,
+                                typed_value: &'a TypedValue,
+                                large_value_threshold: Option<usize>,
+                                offloaded: bool) -> Result<(ToSqlOutput<'a>, i32)> {
+    if let &TypedValue::String(ref text) = typed_value {
+        if may_offload(a, attribute) {
+            let id = if large_value_threshold.map_or(false, |threshold| text.len() > threshold) {
+                Some(offload_large_value(conn, text)?)
+            } else if offloaded {
+                large_value_id(conn, text)?
+            } else {
+                None
+            };
+            if let Some(id) = id {
+                return Ok((rusqlite::types::Value::Integer(id).into(), 10));
+            }
+        }
+    }
+    Ok(typed_value.to_sql_value_pair())
+}
+
+/// Like `TypedValue::from_sql_value_pair`, but reads offloaded values from `large_values`.
+///
+/// Only use this for values read from `datoms` or `transactions` that can't be fulltext rowids:
+/// those of non-fulltext attributes, or those read through `fulltext_datoms` or `all_datoms`.
+pub fn read_typed_value(conn: &rusqlite::Connection, value: rusqlite::types::Value, value_type_tag: i32) -> Result<TypedValue> {
+    match (value_type_tag, value) {
+        (10, rusqlite::types::Value::Integer(id)) => {
+            let text: String = conn.query_row("SELECT text FROM large_values WHERE id = ?", &[&id], |row| row.get(0))?;
+            Ok(text.into())
+        },
+        (value_type_tag, value) => TypedValue::from_sql_value_pair(value, value_type_tag),
+    }
+}
+
+/// An SQL expression for the `v` column of the given table, view, or alias, with offloaded values
+/// replaced by the strings they stand for.  The same caveats as `read_typed_value` apply.
+pub fn resolved_value_sql(table: &str) -> String {
+    format!("CASE WHEN {t}.value_type_tag = 10 AND typeof({t}.v) = 'integer' \
+             THEN (SELECT text FROM large_values WHERE id = {t}.v) ELSE {t}.v END", t = table)
+}
+
+/// An SQL expression for the `v` column of the given alias of `transactions` or
+/// `timelined_transactions`, with fulltext and offloaded values replaced by the strings they stand
+/// for.  The log stores both by id, and, unlike `all_datoms`, has no view that resolves them.
+pub fn logged_value_sql(table: &str) -> String {
+    format!("CASE WHEN {t}.a IN (SELECT e FROM datoms WHERE a = {fulltext} AND v = 1) \
+             THEN (SELECT text FROM fulltext_values WHERE rowid = {t}.v) ELSE {resolved} END",
+            t = table, fulltext = entids::DB_FULLTEXT, resolved = resolved_value_sql(table))
+}
+
+/// The datoms that `[:db.fn/retractEntity entity]` retracts: those of `entity`, those that refer
+/// to it, and, recursively, those of the entities it refers to through component attributes.
+pub(crate) fn retract_entity_datoms(conn: &rusqlite::Connection, schema: &Schema, entity: Entid) -> Result<BTreeSet<(Entid, Entid, TypedValue)>> {
+    let mut own_stmt = conn.prepare_cached(&format!("SELECT a, {}, value_type_tag FROM all_datoms WHERE e = ?",
+                                                    resolved_value_sql("all_datoms")))?;
+    let mut referring_stmt = conn.prepare_cached("SELECT e, a FROM datoms WHERE index_vaet IS NOT 0 AND v = ?")?;
+
+    let mut datoms = BTreeSet::new();
+    let mut seen: BTreeSet<Entid> = BTreeSet::new();
+    let mut pending = vec![entity];
+    while let Some(e) = pending.pop() {
+        if !seen.insert(e) {
+            continue;
+        }
+
+        {
+            let mut rows = own_stmt.query(&[&e])?;
+            while let Some(row) = rows.next() {
+                let row = row?;
+                let a: Entid = row.get_checked(0)?;
+                let v = TypedValue::from_sql_value_pair(row.get_checked(1)?, row.get_checked(2)?)?;
+                if let TypedValue::Ref(component) = v {
+                    if schema.component_attributes.binary_search(&a).is_ok() {
+                        pending.push(component);
+                    }
+                }
+                datoms.insert((e, a, v));
+            }
+        }
+
+        let mut rows = referring_stmt.query(&[&e])?;
+        while let Some(row) = rows.next() {
+            let row = row?;
+            datoms.insert((row.get_checked(0)?, row.get_checked(1)?, TypedValue::Ref(e)));
+        }
+    }
+    Ok(datoms)
+}
+
+/// The values of `attribute` that `[:db.fn/retractAttribute entity attribute]` retracts.
+pub(crate) fn retract_attribute_values(conn: &rusqlite::Connection, entity: Entid, attribute: Entid) -> Result<Vec<TypedValue>> {
+    let mut stmt = conn.prepare_cached(&format!("SELECT {}, value_type_tag FROM all_datoms WHERE e = ? AND a = ?",
+                                                resolved_value_sql("all_datoms")))?;
+    let values = stmt.query_and_then(&[&entity, &attribute], |row| -> Result<TypedValue> {
+        TypedValue::from_sql_value_pair(row.get_checked(0)?, row.get_checked(1)?)
+    })?;
+    values.collect()
+}
+
 /// `MentatStoring` will be the trait that encapsulates the storage layer.  It is consumed by the
 /// transaction processing layer.
 ///
@@ -578,7 +1276,7 @@ pub trait MentatStoring {
     fn begin_tx_application(&self) -> Result<()>;
 
     // TODO: this is not a reasonable abstraction, but I don't want to really consider non-SQL storage just yet.
-    fn insert_non_fts_searches<'a>(&self, entities: &'a [ReducedEntity], search_type: SearchType) -> Result<()>;
+    fn insert_non_fts_searches<'a>(&self, entities: &'a [ReducedEntity], search_type: SearchType, large_value_threshold: Option<usize>) -> Result<()>;
     fn insert_fts_searches<'a>(&self, entities: &'a [ReducedEntity], search_type: SearchType) -> Result<()>;
 
     /// Prepare the underlying storage layer for finalization after a Mentat transaction.
@@ -590,7 +1288,7 @@ pub trait MentatStoring {
     /// Finalize the underlying storage layer after a Mentat transaction.
     ///
     /// This is a final step in performing a transaction.
-    fn commit_mentat_transaction(&self, tx_id: Entid) -> Result<()>;
+    fn commit_mentat_transaction(&self, tx_id: Entid, redundant_assertions: RedundantAssertions) -> Result<()>;
 
     /// Extract metadata-related [e a typed_value added] datoms resolved in the last
     /// materialized transaction.
@@ -605,7 +1303,7 @@ fn search(conn: &rusqlite::Connection) -> Result<()> {
     // Second is slower, but still only one table walk: lookup old value by ea.
     let s = r#"
       INSERT INTO temp.search_results
-      SELECT t.e0, t.a0, t.v0, t.value_type_tag0, t.added0, t.flags0, ':db.cardinality/many', d.rowid, d.v
+      SELECT t.e0, t.a0, t.v0, t.value_type_tag0, t.added0, t.flags0, t.folded0, ':db.cardinality/many', d.rowid, d.v
       FROM temp.exact_searches AS t
       LEFT JOIN datoms AS d
       ON t.e0 = d.e AND
@@ -615,7 +1313,7 @@ fn search(conn: &rusqlite::Connection) -> Result<()> {
 
       UNION ALL
 
-      SELECT t.e0, t.a0, t.v0, t.value_type_tag0, t.added0, t.flags0, ':db.cardinality/one', d.rowid, d.v
+      SELECT t.e0, t.a0, t.v0, t.value_type_tag0, t.added0, t.flags0, t.folded0, ':db.cardinality/one', d.rowid, d.v
       FROM temp.inexact_searches AS t
       LEFT JOIN datoms AS d
       ON t.e0 = d.e AND
@@ -631,18 +1329,26 @@ fn search(conn: &rusqlite::Connection) -> Result<()> {
 /// This turns the contents of `search_results` into a new transaction.
 ///
 /// See https://github.com/mozilla/mentat/wiki/Transacting:-entity-to-SQL-translation.
-fn insert_transaction(conn: &rusqlite::Connection, tx: Entid) -> Result<()> {
+fn insert_transaction(conn: &rusqlite::Connection, tx: Entid, redundant_assertions: RedundantAssertions) -> Result<()> {
     // Mentat follows Datomic and treats its input as a set.  That means it is okay to transact the
     // same [e a v] twice in one transaction.  However, we don't want to represent the transacted
     // datom twice.  Therefore, the transactor unifies repeated datoms, and in addition we add
     // indices to the search inputs and search results to ensure that we don't see repeated datoms
     // at this point.
-
-    let s = r#"
-      INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag)
-      SELECT e0, a0, v0, ?, 1, value_type_tag0
-      FROM temp.search_results
-      WHERE added0 IS 1 AND ((rid IS NULL) OR ((rid IS NOT NULL) AND (v0 IS NOT v)))"#;
+    //
+    // Asserting a datom that's already present is a no-op, which we don't record unless asked to.
+    let s = match redundant_assertions {
+        RedundantAssertions::Skip => r#"
+          INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag)
+          SELECT e0, a0, v0, ?, 1, value_type_tag0
+          FROM temp.search_results
+          WHERE added0 IS 1 AND ((rid IS NULL) OR ((rid IS NOT NULL) AND (v0 IS NOT v)))"#,
+        RedundantAssertions::Record => r#"
+          INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag)
+          SELECT e0, a0, v0, ?, 1, value_type_tag0
+          FROM temp.search_results
+          WHERE added0 IS 1"#,
+    };
 
     let mut stmt = conn.prepare_cached(s)?;
     stmt.execute(&[&tx]).context(DbErrorKind::TxInsertFailedToAddMissingDatoms)?;
@@ -680,6 +1386,8 @@ fn update_datoms(conn: &rusqlite::Connection, tx: Entid) -> Result<()> {
     let mut stmt = conn.prepare_cached(s)?;
     stmt.execute(&[]).context(DbErrorKind::DatomsUpdateFailedToRetract)?;
 
+    ensure_case_insensitive_unique(conn)?;
+
     // Insert datoms that were added and not already present. We also must expand our bitfield into
     // flags.  Since Mentat follows Datomic and treats its input as a set, it is okay to transact
     // the same [e a v] twice in one transaction, but we don't want to represent the transacted
@@ -687,36 +1395,124 @@ fn update_datoms(conn: &rusqlite::Connection, tx: Entid) -> Result<()> {
     // indices to the search inputs and search results to ensure that we don't see repeated datoms
     // at this point.
     let s = format!(r#"
-      INSERT INTO datoms (e, a, v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value)
+      INSERT INTO datoms (e, a, v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value, unique_folded, folded)
       SELECT e0, a0, v0, ?, value_type_tag0,
              flags0 & {} IS NOT 0,
              flags0 & {} IS NOT 0,
              flags0 & {} IS NOT 0,
-             flags0 & {} IS NOT 0
+             flags0 & {} IS NOT 0,
+             flags0 & {} IS NOT 0,
+             folded0
       FROM temp.search_results
       WHERE added0 IS 1 AND ((rid IS NULL) OR ((rid IS NOT NULL) AND (v0 IS NOT v)))"#,
       AttributeBitFlags::IndexAVET as u8,
       AttributeBitFlags::IndexVAET as u8,
       AttributeBitFlags::IndexFulltext as u8,
-      AttributeBitFlags::UniqueValue as u8);
+      AttributeBitFlags::UniqueValue as u8,
+      AttributeBitFlags::UniqueFolded as u8);
 
     let mut stmt = conn.prepare_cached(&s)?;
     stmt.execute(&[&tx]).context(DbErrorKind::DatomsUpdateFailedToAdd)?;
     Ok(())
 }
 
+/// Fail if the transaction in `search_results` would give two entities values of a
+/// `:db/caseInsensitive` attribute that differ only in case, which `idx_datoms_unique_folded`
+/// would otherwise refuse without saying which.
+///
+/// This runs after retracted datoms are deleted and before added datoms are inserted, so we
+/// compare the datoms to be inserted with those that remain, and with each other.
+fn ensure_case_insensitive_unique(conn: &rusqlite::Connection) -> Result<()> {
+    let s = format!(r#"
+      WITH added AS (SELECT rowid AS id, e0, a0, value_type_tag0, folded0
+                     FROM temp.search_results
+                     WHERE added0 IS 1 AND flags0 & {} IS NOT 0 AND
+                           ((rid IS NULL) OR ((rid IS NOT NULL) AND (v0 IS NOT v))))
+      SELECT t.a0, t.e0, d.e
+      FROM added AS t, datoms AS d
+      WHERE d.a = t.a0 AND d.value_type_tag = t.value_type_tag0 AND d.folded = t.folded0 AND d.unique_folded IS NOT 0
+      UNION
+      SELECT t1.a0, t1.e0, t2.e0
+      FROM added AS t1, added AS t2
+      WHERE t1.id < t2.id AND t1.a0 = t2.a0 AND t1.value_type_tag0 = t2.value_type_tag0 AND t1.folded0 = t2.folded0"#,
+      AttributeBitFlags::UniqueFolded as u8);
+
+    let mut stmt = conn.prepare_cached(&s)?;
+    let triples: Result<Vec<(Entid, Entid, Entid)>> = stmt.query_and_then(&[], |row| -> Result<(Entid, Entid, Entid)> {
+        Ok((row.get_checked(0)?, row.get_checked(1)?, row.get_checked(2)?))
+    })?.collect();
+
+    let mut conflicts: BTreeMap<Entid, BTreeSet<(Entid, Entid)>> = BTreeMap::new();
+    for (a, e1, e2) in triples? {
+        let pair = if e1 < e2 { (e1, e2) } else { (e2, e1) };
+        conflicts.entry(a).or_insert_with(BTreeSet::new).insert(pair);
+    }
+
+    if !conflicts.is_empty() {
+        bail!(DbErrorKind::SchemaConstraintViolation(SchemaConstraintViolation::CaseInsensitiveConflicts { conflicts }));
+    }
+    Ok(())
+}
+
+/// Fail if the transaction in `search_results` left two entities sharing values of an attribute
+/// and of its `:db/uniqueWith` partner.
+///
+/// Only entities that were just given a value of either attribute can have come into conflict.
+/// Attributes that are unique with another are indexed, so we find other entities with the same
+/// value through `idx_datoms_avet`.
+pub fn ensure_unique_with(conn: &rusqlite::Connection, schema: &Schema) -> Result<()> {
+    let s = r#"
+      SELECT DISTINCT d1.e, d2.e
+      FROM temp.search_results AS t, datoms AS d1, datoms AS p1, datoms AS d2, datoms AS p2
+      WHERE t.added0 IS 1 AND t.a0 IN (?1, ?2) AND
+            d1.e = t.e0 AND d1.a = ?1 AND
+            p1.e = t.e0 AND p1.a = ?2 AND
+            d2.a = ?1 AND d2.index_avet IS NOT 0 AND d2.value_type_tag = d1.value_type_tag AND d2.v = d1.v AND d2.e IS NOT d1.e AND
+            p2.e = d2.e AND p2.a = ?2 AND p2.value_type_tag = p1.value_type_tag AND p2.v = p1.v"#;
+
+    let mut conflicts: BTreeMap<(Entid, Entid), BTreeSet<(Entid, Entid)>> = BTreeMap::new();
+    for (&a, attribute) in schema.attribute_map.iter() {
+        let partner = match attribute.unique_with {
+            Some(partner) => partner,
+            None => continue,
+        };
+        let mut stmt = conn.prepare_cached(s)?;
+        let pairs: Result<Vec<(Entid, Entid)>> = stmt.query_and_then(&[&a, &partner], |row| -> Result<(Entid, Entid)> {
+            Ok((row.get_checked(0)?, row.get_checked(1)?))
+        })?.collect();
+        for (e1, e2) in pairs? {
+            // Each conflict is found from both sides when both entities were just transacted.
+            let pair = if e1 < e2 { (e1, e2) } else { (e2, e1) };
+            conflicts.entry((a, partner)).or_insert_with(BTreeSet::new).insert(pair);
+        }
+    }
+
+    if !conflicts.is_empty() {
+        bail!(DbErrorKind::SchemaConstraintViolation(SchemaConstraintViolation::UniqueWithConflicts { conflicts }));
+    }
+    Ok(())
+}
+
 impl MentatStoring for rusqlite::Connection {
     fn resolve_avs<'a>(&self, avs: &'a [&'a AVPair]) -> Result<AVMap<'a>> {
         // Start search_id's at some identifiable number.
         let initial_search_id = 2000;
-        let bindings_per_statement = 4;
+        let bindings_per_statement = 5;
 
         // We map [a v] -> numeric search_id -> e, and then we use the search_id lookups to finally
         // produce the map [a v] -> e.
         //
         // TODO: `collect` into a HashSet so that any (a, v) is resolved at most once.
         let max_vars = self.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
-        let chunks: itertools::IntoChunks<_> = avs.into_iter().enumerate().chunks(max_vars / 4);
+        let chunks: itertools::IntoChunks<_> = avs.into_iter().enumerate().chunks(max_vars / bindings_per_statement);
+
+        // Offloaded values match the strings they stand for, but only look for them if there are
+        // any.
+        let offloaded = if has_large_values(self)? {
+            " OR (d.index_fulltext IS 0 AND typeof(d.v) = 'integer' AND d.v IN (SELECT id FROM large_values WHERE text = t.v))"
+        } else {
+            ""
+        };
 
         // We'd like to `flat_map` here, but it's not obvious how to `flat_map` across `Result`.
         // Alternatively, this is a `fold`, and it might be wise to express it as such.
@@ -725,20 +1521,26 @@ impl MentatStoring for rusqlite::Connection {
 
             // We must keep these computed values somewhere to reference them later, so we can't
             // combine this `map` and the subsequent `flat_map`.
-            let block: Vec<(i64, i64, ToSqlOutput<'a>, i32)> = chunk.map(|(index, &&(a, ref v))| {
+            // We don't know which attributes are :db/caseInsensitive, so we fold every string.
+            let block: Vec<(i64, i64, ToSqlOutput<'a>, i32, Option<String>)> = chunk.map(|(index, &&(a, ref v))| {
                 count += 1;
                 let search_id: i64 = initial_search_id + index as i64;
                 let (value, value_type_tag) = v.to_sql_value_pair();
-                (search_id, a, value, value_type_tag)
+                let folded = match v {
+                    &TypedValue::String(ref s) => Some(fold_case(s)),
+                    _ => None,
+                };
+                (search_id, a, value, value_type_tag, folded)
             }).collect();
 
             // `params` reference computed values in `block`.
-            let params: Vec<&ToSql> = block.iter().flat_map(|&(ref searchid, ref a, ref value, ref value_type_tag)| {
+            let params: Vec<&ToSql> = block.iter().flat_map(|&(ref searchid, ref a, ref value, ref value_type_tag, ref folded)| {
                 // Avoid inner heap allocation.
                 once(searchid as &ToSql)
                     .chain(once(a as &ToSql)
                            .chain(once(value as &ToSql)
-                                  .chain(once(value_type_tag as &ToSql))))
+                                  .chain(once(value_type_tag as &ToSql)
+                                         .chain(once(folded as &ToSql)))))
             }).collect();
 
             // TODO: cache these statements for selected values of `count`.
@@ -748,11 +1550,13 @@ impl MentatStoring for rusqlite::Connection {
             // `datoms`, which will be much faster.ˇ
             assert!(bindings_per_statement * count < max_vars, "Too many values: {} * {} >= {}", bindings_per_statement, count, max_vars);
 
+            // Values of :db/caseInsensitive attributes match regardless of case.
             let values: String = repeat_values(bindings_per_statement, count);
-            let s: String = format!("WITH t(search_id, a, v, value_type_tag) AS (VALUES {}) SELECT t.search_id, d.e \
+            let s: String = format!("WITH t(search_id, a, v, value_type_tag, folded) AS (VALUES {}) SELECT t.search_id, d.e \
                                      FROM t, all_datoms AS d \
-                                     WHERE d.index_avet IS NOT 0 AND d.a = t.a AND d.value_type_tag = t.value_type_tag AND d.v = t.v",
-                                    values);
+                                     WHERE d.index_avet IS NOT 0 AND d.a = t.a AND d.value_type_tag = t.value_type_tag AND \
+                                     (d.v = t.v OR (d.unique_folded IS NOT 0 AND d.folded = t.folded){})",
+                                    values, offloaded);
             let mut stmt: rusqlite::Statement = self.prepare(s.as_str())?;
 
             let m: Result<Vec<(i64, Entid)>> = stmt.query_and_then(&params, |row| -> Result<(i64, Entid)> {
@@ -786,7 +1590,8 @@ impl MentatStoring for rusqlite::Connection {
                v0 BLOB NOT NULL,
                value_type_tag0 SMALLINT NOT NULL,
                added0 TINYINT NOT NULL,
-               flags0 TINYINT NOT NULL)"#,
+               flags0 TINYINT NOT NULL,
+               folded0 TEXT)"#,
             // There's no real need to split exact and inexact searches, so long as we keep things
             // in the correct place and performant.  Splitting has the advantage of being explicit
             // and slightly easier to read, so we'll do that to start.
@@ -797,7 +1602,8 @@ impl MentatStoring for rusqlite::Connection {
                v0 BLOB NOT NULL,
                value_type_tag0 SMALLINT NOT NULL,
                added0 TINYINT NOT NULL,
-               flags0 TINYINT NOT NULL)"#,
+               flags0 TINYINT NOT NULL,
+               folded0 TEXT)"#,
 
             // It is fine to transact the same [e a v] twice in one transaction, but the transaction
             // processor should unify such repeated datoms.  This index will cause insertion to fail
@@ -814,6 +1620,7 @@ impl MentatStoring for rusqlite::Connection {
                value_type_tag0 SMALLINT NOT NULL,
                added0 TINYINT NOT NULL,
                flags0 TINYINT NOT NULL,
+               folded0 TEXT,
                search_type STRING NOT NULL,
                rid INTEGER,
                v BLOB)"#,
@@ -838,36 +1645,47 @@ impl MentatStoring for rusqlite::Connection {
     ///
     /// Eventually, the details of this approach will be captured in
     /// https://github.com/mozilla/mentat/wiki/Transacting:-entity-to-SQL-translation.
-    fn insert_non_fts_searches<'a>(&self, entities: &'a [ReducedEntity<'a>], search_type: SearchType) -> Result<()> {
-        let bindings_per_statement = 6;
+    fn insert_non_fts_searches<'a>(&self, entities: &'a [ReducedEntity<'a>], search_type: SearchType, large_value_threshold: Option<usize>) -> Result<()> {
+        let bindings_per_statement = 7;
 
         let max_vars = self.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
         let chunks: itertools::IntoChunks<_> = entities.into_iter().chunks(max_vars / bindings_per_statement);
 
+        // A string this call offloads is longer than the threshold, so every copy of it in these
+        // entities is offloaded too: we need only look for strings offloaded before we started.
+        let offloaded = has_large_values(self)?;
+
         // We'd like to flat_map here, but it's not obvious how to flat_map across Result.
         let results: Result<Vec<()>> = chunks.into_iter().map(|chunk| -> Result<()> {
             let mut count = 0;
 
             // We must keep these computed values somewhere to reference them later, so we can't
             // combine this map and the subsequent flat_map.
-            // (e0, a0, v0, value_type_tag0, added0, flags0)
+            // (e0, a0, v0, value_type_tag0, added0, flags0, folded0)
             let block: Result<Vec<(i64 /* e */,
                                    i64 /* a */,
                                    ToSqlOutput<'a> /* value */,
                                    i32 /* value_type_tag */,
                                    bool, /* added0 */
-                                   u8 /* flags0 */)>> = chunk.map(|&(e, a, ref attribute, ref typed_value, added)| {
+                                   u8 /* flags0 */,
+                                   Option<String> /* folded0 */)>> = chunk.map(|&(e, a, ref attribute, ref typed_value, added)| {
                 count += 1;
 
                 // Now we can represent the typed value as an SQL value.
-                let (value, value_type_tag): (ToSqlOutput, i32) = typed_value.to_sql_value_pair();
+                let (value, value_type_tag): (ToSqlOutput, i32) = to_stored_sql_value_pair(self, a, attribute, typed_value, large_value_threshold, offloaded)?;
+
+                // Values of :db/caseInsensitive attributes are unique by their folded form.
+                let folded = match typed_value {
+                    &TypedValue::String(ref s) if attribute.case_insensitive => Some(fold_case(s)),
+                    _ => None,
+                };
 
-                Ok((e, a, value, value_type_tag, added, attribute.flags()))
+                Ok((e, a, value, value_type_tag, added, attribute.flags(), folded))
             }).collect();
             let block = block?;
 
             // `params` reference computed values in `block`.
-            let params: Vec<&ToSql> = block.iter().flat_map(|&(ref e, ref a, ref value, ref value_type_tag, added, ref flags)| {
+            let params: Vec<&ToSql> = block.iter().flat_map(|&(ref e, ref a, ref value, ref value_type_tag, added, ref flags, ref folded)| {
                 // Avoid inner heap allocation.
                 // TODO: extract some finite length iterator to make this less indented!
                 once(e as &ToSql)
@@ -875,17 +1693,18 @@ impl MentatStoring for rusqlite::Connection {
                            .chain(once(value as &ToSql)
                                   .chain(once(value_type_tag as &ToSql)
                                          .chain(once(to_bool_ref(added) as &ToSql)
-                                                .chain(once(flags as &ToSql))))))
+                                                .chain(once(flags as &ToSql)
+                                                       .chain(once(folded as &ToSql)))))))
             }).collect();
 
             // TODO: cache this for selected values of count.
             assert!(bindings_per_statement * count < max_vars, "Too many values: {} * {} >= {}", bindings_per_statement, count, max_vars);
             let values: String = repeat_values(bindings_per_statement, count);
             let s: String = if search_type == SearchType::Exact {
-                format!("INSERT INTO temp.exact_searches (e0, a0, v0, value_type_tag0, added0, flags0) VALUES {}", values)
+                format!("INSERT INTO temp.exact_searches (e0, a0, v0, value_type_tag0, added0, flags0, folded0) VALUES {}", values)
             } else {
                 // This will err for duplicates within the tx.
-                format!("INSERT INTO temp.inexact_searches (e0, a0, v0, value_type_tag0, added0, flags0) VALUES {}", values)
+                format!("INSERT INTO temp.inexact_searches (e0, a0, v0, value_type_tag0, added0, flags0, folded0) VALUES {}", values)
             };
 
             // TODO: consider ensuring we inserted the expected number of rows.
@@ -914,6 +1733,15 @@ impl MentatStoring for rusqlite::Connection {
         // From string to (searchid, value_type_tag).
         let mut seen: HashMap<ValueRc<String>, (i64, i32)> = HashMap::with_capacity(entities.len());
 
+        // A string is only inserted the first time it's seen, so we need to know up front whether
+        // any :db/normalize attribute has it as a value.
+        let normalized: HashSet<&String> = entities.iter().filter_map(|&(_, _, ref attribute, ref typed_value, _)| {
+            match typed_value {
+                &TypedValue::String(ref rc) if attribute.normalize => Some(&**rc),
+                _ => None,
+            }
+        }).collect();
+
         // We'd like to flat_map here, but it's not obvious how to flat_map across Result.
         let results: Result<Vec<()>> = chunks.into_iter().map(|chunk| -> Result<()> {
             let mut datom_count = 0;
@@ -928,7 +1756,8 @@ impl MentatStoring for rusqlite::Connection {
                                    i32 /* value_type_tag */,
                                    bool /* added0 */,
                                    u8 /* flags0 */,
-                                   i64 /* searchid */)>> = chunk.map(|&(e, a, ref attribute, ref typed_value, added)| {
+                                   i64 /* searchid */,
+                                   Option<String> /* folded */)>> = chunk.map(|&(e, a, ref attribute, ref typed_value, added)| {
                 match typed_value {
                     &TypedValue::String(ref rc) => {
                         datom_count += 1;
@@ -936,7 +1765,7 @@ impl MentatStoring for rusqlite::Connection {
                         match entry {
                             Entry::Occupied(entry) => {
                                 let &(searchid, value_type_tag) = entry.get();
-                                Ok((e, a, None, value_type_tag, added, attribute.flags(), searchid))
+                                Ok((e, a, None, value_type_tag, added, attribute.flags(), searchid, None))
                             },
                             Entry::Vacant(entry) => {
                                 outer_searchid += 1;
@@ -946,7 +1775,9 @@ impl MentatStoring for rusqlite::Connection {
                                 let (value, value_type_tag): (ToSqlOutput, i32) = typed_value.to_sql_value_pair();
                                 entry.insert((outer_searchid, value_type_tag));
 
-                                Ok((e, a, Some(value), value_type_tag, added, attribute.flags(), outer_searchid))
+                                let folded = if normalized.contains(&**rc) { Some(fold_for_fulltext(rc)) } else { None };
+
+                                Ok((e, a, Some(value), value_type_tag, added, attribute.flags(), outer_searchid, folded))
                             }
                         }
                     },
@@ -963,18 +1794,19 @@ impl MentatStoring for rusqlite::Connection {
             // `fts_params` reference computed values in `block`.
             let fts_params: Vec<&ToSql> =
                 block.iter()
-                     .filter(|&&(ref _e, ref _a, ref value, ref _value_type_tag, _added, ref _flags, ref _searchid)| {
+                     .filter(|&&(ref _e, ref _a, ref value, ref _value_type_tag, _added, ref _flags, ref _searchid, ref _folded)| {
                          value.is_some()
                      })
-                     .flat_map(|&(ref _e, ref _a, ref value, ref _value_type_tag, _added, ref _flags, ref searchid)| {
+                     .flat_map(|&(ref _e, ref _a, ref value, ref _value_type_tag, _added, ref _flags, ref searchid, ref folded)| {
                          // Avoid inner heap allocation.
                          once(value as &ToSql)
-                             .chain(once(searchid as &ToSql))
+                             .chain(once(searchid as &ToSql)
+                                    .chain(once(folded as &ToSql)))
                      }).collect();
 
             // TODO: make this maximally efficient. It's not terribly inefficient right now.
-            let fts_values: String = repeat_values(2, string_count);
-            let fts_s: String = format!("INSERT INTO fulltext_values_view (text, searchid) VALUES {}", fts_values);
+            let fts_values: String = repeat_values(3, string_count);
+            let fts_s: String = format!("INSERT INTO fulltext_values_view (text, searchid, folded) VALUES {}", fts_values);
 
             // TODO: consider ensuring we inserted the expected number of rows.
             let mut stmt = self.prepare_cached(fts_s.as_str())?;
@@ -982,7 +1814,7 @@ impl MentatStoring for rusqlite::Connection {
 
             // Second, insert searches.
             // `params` reference computed values in `block`.
-            let params: Vec<&ToSql> = block.iter().flat_map(|&(ref e, ref a, ref _value, ref value_type_tag, added, ref flags, ref searchid)| {
+            let params: Vec<&ToSql> = block.iter().flat_map(|&(ref e, ref a, ref _value, ref value_type_tag, added, ref flags, ref searchid, ref _folded)| {
                 // Avoid inner heap allocation.
                 // TODO: extract some finite length iterator to make this less indented!
                 once(e as &ToSql)
@@ -1017,8 +1849,8 @@ impl MentatStoring for rusqlite::Connection {
         results.map(|_| ())
     }
 
-    fn commit_mentat_transaction(&self, tx_id: Entid) -> Result<()> {
-        insert_transaction(&self, tx_id)?;
+    fn commit_mentat_transaction(&self, tx_id: Entid, redundant_assertions: RedundantAssertions) -> Result<()> {
+        insert_transaction(&self, tx_id, redundant_assertions)?;
         Ok(())
     }
 
@@ -1140,15 +1972,34 @@ pub fn update_metadata(conn: &rusqlite::Connection, _old_schema: &Schema, new_sc
     }
 
     let mut index_stmt = conn.prepare("UPDATE datoms SET index_avet = ? WHERE a = ?")?;
-    let mut unique_value_stmt = conn.prepare("UPDATE datoms SET unique_value = ? WHERE a = ?")?;
-    let mut cardinality_stmt = conn.prepare(r#"
-SELECT EXISTS
-    (SELECT 1
-        FROM datoms AS left, datoms AS right
-        WHERE left.a = ? AND
-        left.a = right.a AND
-        left.e = right.e AND
-        left.v <> right.v)"#)?;
+    let mut unique_value_stmt = conn.prepare("UPDATE datoms SET unique_value = ?, unique_folded = ? WHERE a = ?")?;
+    // A value held by more than one entity, which can't be made unique.
+    let mut repeated_value_stmt = conn.prepare(&format!(r#"
+SELECT {}, value_type_tag
+    FROM all_datoms AS d
+    WHERE d.a = ?
+    GROUP BY d.value_type_tag, d.v
+    HAVING count(*) > 1
+    LIMIT 1"#, resolved_value_sql("d")))?;
+    // An entity with more than one value, which can't be made :db.cardinality/one.
+    let mut repeated_entity_stmt = conn.prepare(r#"
+SELECT left.e
+    FROM datoms AS left, datoms AS right
+    WHERE left.a = ? AND
+    left.a = right.a AND
+    left.e = right.e AND
+    (left.value_type_tag <> right.value_type_tag OR left.v <> right.v)
+    LIMIT 1"#)?;
+    // Two entities sharing values of an attribute and of its partner, which can't be made
+    // :db/uniqueWith that partner.
+    let mut repeated_pair_stmt = conn.prepare(r#"
+SELECT d1.e, d2.e
+    FROM datoms AS d1, datoms AS p1, datoms AS d2, datoms AS p2
+    WHERE d1.a = ?1 AND
+    p1.e = d1.e AND p1.a = ?2 AND
+    d2.a = d1.a AND d2.value_type_tag = d1.value_type_tag AND d2.v = d1.v AND d2.e > d1.e AND
+    p2.e = d2.e AND p2.a = p1.a AND p2.value_type_tag = p1.value_type_tag AND p2.v = p1.v
+    LIMIT 1"#)?;
 
     for (&entid, alterations) in &metadata_report.attributes_altered {
         let attribute = new_schema.require_attribute_for_entid(entid)?;
@@ -1160,26 +2011,47 @@ SELECT EXISTS
                     index_stmt.execute(&[&attribute.index, &entid as &ToSql])?;
                 },
                 &Unique => {
-                    // TODO: This can fail if there are conflicting values; give a more helpful
-                    // error message in this case.
-                    if unique_value_stmt.execute(&[to_bool_ref(attribute.unique.is_some()), &entid as &ToSql]).is_err() {
-                        match attribute.unique {
-                            Some(attribute::Unique::Value) => bail!(DbErrorKind::SchemaAlterationFailed(format!("Cannot alter schema attribute {} to be :db.unique/value", entid))),
-                            Some(attribute::Unique::Identity) => bail!(DbErrorKind::SchemaAlterationFailed(format!("Cannot alter schema attribute {} to be :db.unique/identity", entid))),
-                            None => unreachable!(), // This shouldn't happen, even after we support removing :db/unique.
+                    // Check for repeated values ourselves, so that we can say which value is to
+                    // blame.  Case-insensitive values can still collide, which the unique index
+                    // on their folded forms catches below.
+                    let describe = |unique: &Option<attribute::Unique>| match *unique {
+                        Some(attribute::Unique::Value) => ":db.unique/value",
+                        Some(attribute::Unique::Identity) => ":db.unique/identity",
+                        None => unreachable!(),
+                    };
+                    if attribute.unique.is_some() {
+                        let mut rows = repeated_value_stmt.query(&[&entid as &ToSql])?;
+                        if let Some(row) = rows.next() {
+                            let row = row?;
+                            let value = TypedValue::from_sql_value_pair(row.get_checked(0)?, row.get_checked(1)?)?;
+                            bail!(DbErrorKind::SchemaAlterationFailed(format!("Cannot alter schema attribute {} to be {}: value {:?} is held by more than one entity", entid, describe(&attribute.unique), value)));
                         }
                     }
+                    let unique = attribute.unique.is_some();
+                    let folded = unique && attribute.case_insensitive;
+                    if unique_value_stmt.execute(&[to_bool_ref(unique), to_bool_ref(folded), &entid as &ToSql]).is_err() {
+                        bail!(DbErrorKind::SchemaAlterationFailed(format!("Cannot alter schema attribute {} to be {}: values differing only in case are held by more than one entity", entid, describe(&attribute.unique))));
+                    }
                 },
                 &Cardinality => {
                     // We can always go from :db.cardinality/one to :db.cardinality many.  It's
                     // :db.cardinality/many to :db.cardinality/one that can fail.
-                    //
-                    // TODO: improve the failure message.  Perhaps try to mimic what Datomic says in
-                    // this case?
                     if !attribute.multival {
-                        let mut rows = cardinality_stmt.query(&[&entid as &ToSql])?;
-                        if rows.next().is_some() {
-                            bail!(DbErrorKind::SchemaAlterationFailed(format!("Cannot alter schema attribute {} to be :db.cardinality/one", entid)));
+                        let mut rows = repeated_entity_stmt.query(&[&entid as &ToSql])?;
+                        if let Some(row) = rows.next() {
+                            let e: Entid = row?.get_checked(0)?;
+                            bail!(DbErrorKind::SchemaAlterationFailed(format!("Cannot alter schema attribute {} to be :db.cardinality/one: entity {} has more than one value", entid, e)));
+                        }
+                    }
+                },
+                &UniqueWith => {
+                    // Transactions only check the entities they touch, so check every entity once.
+                    if let Some(partner) = attribute.unique_with {
+                        let mut rows = repeated_pair_stmt.query(&[&entid as &ToSql, &partner])?;
+                        if let Some(row) = rows.next() {
+                            let row = row?;
+                            let (e1, e2): (Entid, Entid) = (row.get_checked(0)?, row.get_checked(1)?);
+                            bail!(DbErrorKind::SchemaAlterationFailed(format!("Cannot alter schema attribute {} to be :db/uniqueWith {}: entities {} and {} share values of both", entid, partner, e1, e2)));
                         }
                     }
                 },
@@ -1215,6 +2087,7 @@ impl PartitionMap {
 #[cfg(test)]
 mod tests {
     extern crate env_logger;
+    extern crate tempfile;
 
     use std::borrow::{
         Borrow,
@@ -1541,7 +2414,7 @@ mod tests {
 
         // tempids in :db/retract that don't upsert fail.
         assert_transact!(conn, "[[:db/retract \"t1\" :db/ident :name/Anonymous]]",
-                         Err("not yet implemented: [:db/retract ...] entity referenced tempid that did not upsert: t1"));
+                         Err("[:db/retract ...] entity referenced tempid that did not upsert: t1"));
 
         // tempids in :db/retract that do upsert are retracted.  The ref given doesn't exist, so the
         // assertion will be ignored.
@@ -1622,6 +2495,88 @@ mod tests {
         ]"#);
     }
 
+    #[test]
+    fn test_chained_upserts() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, "[
+            {:db/ident :test/id
+             :db/valueType :db.type/string
+             :db/unique :db.unique/identity
+             :db/index true
+             :db/cardinality :db.cardinality/one}
+            {:db/ident :test/email
+             :db/valueType :db.type/string
+             :db/unique :db.unique/identity
+             :db/index true
+             :db/cardinality :db.cardinality/one}
+            {:db/ident :test/ref
+             :db/valueType :db.type/ref
+             :db/unique :db.unique/identity
+             :db/index true
+             :db/cardinality :db.cardinality/one}
+        ]");
+
+        assert_transact!(conn, r#"[
+            [:db/add 100 :test/id "0"]
+            [:db/add 101 :test/id "1"]
+            [:db/add 102 :test/ref 100]
+        ]"#);
+
+        // A tempid that upserts via one unique attribute still asserts its other unique attributes.
+        let report = assert_transact!(conn, r#"[
+            {:db/id "a" :test/id "0" :test/email "zero@example.com"}
+        ]"#);
+        assert_matches!(tempids(&report), r#"{"a" 100}"#);
+        assert_matches!(conn.last_transaction(), r#"[
+            [100 :test/email "zero@example.com" ?tx true]
+            [?tx :db/txInstant ?ms ?tx true]
+        ]"#);
+
+        // String tempids are shared across entity maps.  "a" upserts in the first generation; its
+        // reference to "b" only becomes a simple upsert in the second generation, where it doesn't
+        // match anything in the store.  "a" must not be allocated a fresh entid.
+        let report = assert_transact!(conn, r#"[
+            {:db/id "a" :test/id "0"}
+            {:db/id "a" :test/ref "b"}
+            {:db/id "b" :test/id "1"}
+        ]"#);
+        assert_matches!(tempids(&report), r#"{"a" 100 "b" 101}"#);
+        assert_matches!(conn.last_transaction(), r#"[
+            [100 :test/ref 101 ?tx true]
+            [?tx :db/txInstant ?ms ?tx true]
+        ]"#);
+
+        // Upserts chain through references: "c" resolves because "b" resolves because "a" resolves.
+        // "d" refers to "c", but nothing refers to 102 yet, so "d" is allocated.
+        let report = assert_transact!(conn, r#"[
+            {:db/id "a" :test/id "1"}
+            {:db/id "b" :test/ref "a"}
+            {:db/id "c" :test/ref "b" :test/email "c@example.com"}
+            {:db/id "d" :test/ref "c" :test/id "d"}
+        ]"#);
+        assert_matches!(tempids(&report), r#"{"a" 101 "b" 100 "c" 102 "d" ?d}"#);
+        assert_matches!(conn.last_transaction(), r#"[
+            [102 :test/email "c@example.com" ?tx true]
+            [?d :test/id "d" ?tx true]
+            [?d :test/ref 102 ?tx true]
+            [?tx :db/txInstant ?ms ?tx true]
+        ]"#);
+
+        // When a chain leads a tempid to two different entities, the transaction fails.
+        assert_transact!(conn, r#"[
+            [:db/add "a" :test/id "1"]
+            [:db/add "a" :test/ref "b"]
+            [:db/add "b" :test/id "0"]
+        ]"#,
+        Err("schema constraint violation: conflicting upserts:\n  tempid External(\"a\") upserts to {KnownEntid(101), KnownEntid(102)}\n"));
+
+        // Retractions can only name tempids that upsert.
+        assert_transact!(conn, r#"[
+            [:db/retract "a" :test/ref "b"]
+        ]"#,
+        Err("[:db/retract ...] entity referenced tempid that did not upsert: a"));
+    }
+
     #[test]
     fn test_sqlite_limit() {
         let conn = new_connection("").expect("Couldn't open in-memory db");
@@ -1812,6 +2767,42 @@ mod tests {
         assert!(conn.schema.ident_map.get(&to_namespaced_keyword(":name/Petr").unwrap()).is_none());
     }
 
+    #[test]
+    fn test_db_ident_rename_attribute() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 100 :db/ident :test/old]
+                                 [:db/add 100 :db/valueType :db.type/long]
+                                 [:db/add 100 :db/cardinality :db.cardinality/one]]");
+        assert_transact!(conn, "[[:db/add 200 :test/old 1]]");
+
+        // Renaming an attribute keeps its entid, its definition, and its data.
+        assert_transact!(conn, "[[:db/add :test/old :db/ident :test/new]]");
+        assert_eq!(conn.schema.ident_map.get(&to_namespaced_keyword(":test/new").unwrap()).cloned(), Some(100));
+        assert!(conn.schema.ident_map.get(&to_namespaced_keyword(":test/old").unwrap()).is_none());
+        assert_eq!(conn.schema.attribute_for_entid(100).unwrap().value_type, ValueType::Long);
+        assert_matches!(conn.datoms(),
+                        "[[100 :db/ident :test/new]
+                          [100 :db/valueType :db.type/long]
+                          [100 :db/cardinality :db.cardinality/one]
+                          [200 :test/new 1]]");
+
+        // Only the new name works.
+        assert_transact!(conn, "[[:db/add 200 :test/new 2]]");
+        assert_transact!(conn, "[[:db/add 200 :test/old 3]]",
+                         Err("schema constraint violation: unknown attributes:\n  entity 0 uses :test/old, which is not an attribute\n"));
+
+        // Two entids can swap idents in a single transaction.
+        assert_transact!(conn, "[[:db/add 101 :db/ident :test/other]]");
+        assert_transact!(conn, "[[:db/add 100 :db/ident :test/other]
+                                 [:db/add 101 :db/ident :test/new]]");
+        assert_eq!(conn.schema.ident_map.get(&to_namespaced_keyword(":test/other").unwrap()).cloned(), Some(100));
+        assert_eq!(conn.schema.ident_map.get(&to_namespaced_keyword(":test/new").unwrap()).cloned(), Some(101));
+        assert_eq!(conn.schema.entid_map.get(&100).cloned(), to_namespaced_keyword(":test/other").ok());
+        assert_eq!(conn.schema.entid_map.get(&101).cloned(), to_namespaced_keyword(":test/new").ok());
+        assert_eq!(conn.schema.entid_map.len(), conn.schema.ident_map.len());
+    }
+
     #[test]
     fn test_db_alter_cardinality() {
         let mut conn = TestConn::default();
@@ -1837,8 +2828,20 @@ mod tests {
 
         // We can't always go from :db.cardinality/many to :db.cardinality/one.
         assert_transact!(conn, "[[:db/add 100 :db/cardinality :db.cardinality/one]]",
-                         // TODO: give more helpful error details.
-                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.cardinality/one"));
+                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.cardinality/one: entity 200 has more than one value"));
+
+        // But we can once each entity has only one value.
+        assert_transact!(conn, "[[:db/retract 200 :test/ident 2]]");
+        assert_transact!(conn, "[[:db/add 100 :db/cardinality :db.cardinality/one]]");
+        assert_eq!(conn.schema.attribute_for_entid(100).unwrap().multival, false);
+
+        // And the attribute behaves as :db.cardinality/one again.
+        assert_transact!(conn, "[[:db/add 200 :test/ident 3]]");
+        assert_matches!(conn.datoms(),
+                        "[[100 :db/ident :test/ident]
+                          [100 :db/valueType :db.type/long]
+                          [100 :db/cardinality :db.cardinality/one]
+                          [200 :test/ident 3]]");
     }
 
     #[test]
@@ -1853,15 +2856,19 @@ mod tests {
         assert_transact!(conn, "[[:db/add 200 :test/ident 1]
                                  [:db/add 201 :test/ident 1]]");
 
-        // We can't always migrate to be :db.unique/value.
+        // Unique attributes must be indexed.
         assert_transact!(conn, "[[:db/add :test/ident :db/unique :db.unique/value]]",
-                         // TODO: give more helpful error details.
-                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.unique/value"));
+                         Err("bad schema assertion: :db/unique :db/unique_value without :db/index true for entid: 100"));
+
+        // We can't always migrate to be :db.unique/value.
+        assert_transact!(conn, "[[:db/add :test/ident :db/index true]
+                                 [:db/add :test/ident :db/unique :db.unique/value]]",
+                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.unique/value: value Long(1) is held by more than one entity"));
 
         // Not even indirectly!
-        assert_transact!(conn, "[[:db/add :test/ident :db/unique :db.unique/identity]]",
-                         // TODO: give more helpful error details.
-                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.unique/identity"));
+        assert_transact!(conn, "[[:db/add :test/ident :db/index true]
+                                 [:db/add :test/ident :db/unique :db.unique/identity]]",
+                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.unique/identity: value Long(1) is held by more than one entity"));
 
         // But we can if we make sure there's no repeated [a v] pair.
         assert_transact!(conn, "[[:db/add 201 :test/ident 2]]");
@@ -1884,6 +2891,117 @@ mod tests {
                                  [:db/add 221 :test/ident 2]]");
     }
 
+    #[test]
+    fn test_retract_entity() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 100 :db/ident :test/name]
+                                 [:db/add 100 :db/valueType :db.type/string]
+                                 [:db/add 100 :db/cardinality :db.cardinality/one]
+                                 [:db/add 100 :db/unique :db.unique/identity]
+                                 [:db/add 100 :db/index true]
+                                 [:db/add 101 :db/ident :test/part]
+                                 [:db/add 101 :db/valueType :db.type/ref]
+                                 [:db/add 101 :db/cardinality :db.cardinality/many]
+                                 [:db/add 101 :db/isComponent true]
+                                 [:db/add 102 :db/ident :test/friend]
+                                 [:db/add 102 :db/valueType :db.type/ref]
+                                 [:db/add 102 :db/cardinality :db.cardinality/many]]");
+
+        // 200 has parts 201 and 202; 202 has part 203.  300 is 200's friend, and 200 is 300's.
+        assert_transact!(conn, r#"[[:db/add 200 :test/name "whole"]
+                                   [:db/add 200 :test/part 201]
+                                   [:db/add 200 :test/part 202]
+                                   [:db/add 200 :test/friend 300]
+                                   [:db/add 201 :test/name "part 1"]
+                                   [:db/add 202 :test/name "part 2"]
+                                   [:db/add 202 :test/part 203]
+                                   [:db/add 203 :test/name "part 2.1"]
+                                   [:db/add 300 :test/name "friend"]
+                                   [:db/add 300 :test/friend 200]]"#);
+
+        // Retracting an entity retracts its components, recursively, and references to it, but
+        // not the entities it merely refers to.
+        assert_transact!(conn, r#"[[:db.fn/retractEntity (lookup-ref :test/name "whole")]]"#);
+        assert_matches!(conn.last_transaction(),
+                        r#"[[200 :test/name "whole" ?tx false]
+                            [200 :test/part 201 ?tx false]
+                            [200 :test/part 202 ?tx false]
+                            [200 :test/friend 300 ?tx false]
+                            [201 :test/name "part 1" ?tx false]
+                            [202 :test/name "part 2" ?tx false]
+                            [202 :test/part 203 ?tx false]
+                            [203 :test/name "part 2.1" ?tx false]
+                            [300 :test/friend 200 ?tx false]
+                            [?tx :db/txInstant ?ms ?tx true]]"#);
+        assert_matches!(conn.datoms(),
+                        r#"[[100 :db/ident :test/name]
+                            [100 :db/valueType :db.type/string]
+                            [100 :db/cardinality :db.cardinality/one]
+                            [100 :db/unique :db.unique/identity]
+                            [100 :db/index true]
+                            [101 :db/ident :test/part]
+                            [101 :db/valueType :db.type/ref]
+                            [101 :db/cardinality :db.cardinality/many]
+                            [101 :db/isComponent true]
+                            [102 :db/ident :test/friend]
+                            [102 :db/valueType :db.type/ref]
+                            [102 :db/cardinality :db.cardinality/many]
+                            [300 :test/name "friend"]]"#);
+
+        // Retracting an entity with no datoms does nothing.
+        assert_transact!(conn, "[[:db.fn/retractEntity 200]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?tx :db/txInstant ?ms ?tx true]]");
+
+        // Tempids can't be retracted.
+        assert_transact!(conn, r#"[[:db.fn/retractEntity "t"]]"#,
+                         Err("not yet implemented: Cannot retract an entity named by a tempid"));
+    }
+
+    #[test]
+    fn test_retract_attribute() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 100 :db/ident :test/name]
+                                 [:db/add 100 :db/valueType :db.type/string]
+                                 [:db/add 100 :db/cardinality :db.cardinality/one]
+                                 [:db/add 101 :db/ident :test/tag]
+                                 [:db/add 101 :db/valueType :db.type/keyword]
+                                 [:db/add 101 :db/cardinality :db.cardinality/many]]");
+        assert_transact!(conn, r#"[[:db/add 200 :test/name "x"]
+                                   [:db/add 200 :test/tag :tag/a]
+                                   [:db/add 200 :test/tag :tag/b]
+                                   [:db/add 201 :test/tag :tag/a]]"#);
+
+        // Only the given entity's values of the given attribute are retracted.
+        assert_transact!(conn, "[[:db.fn/retractAttribute 200 :test/tag]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[200 :test/tag :tag/a ?tx false]
+                          [200 :test/tag :tag/b ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+        assert_matches!(conn.datoms(),
+                        r#"[[100 :db/ident :test/name]
+                            [100 :db/valueType :db.type/string]
+                            [100 :db/cardinality :db.cardinality/one]
+                            [101 :db/ident :test/tag]
+                            [101 :db/valueType :db.type/keyword]
+                            [101 :db/cardinality :db.cardinality/many]
+                            [200 :test/name "x"]
+                            [201 :test/tag :tag/a]]"#);
+
+        // Values can be asserted in the same transaction that retracts the old ones.
+        assert_transact!(conn, "[[:db.fn/retractAttribute 201 :test/tag]
+                                 [:db/add 201 :test/tag :tag/c]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[201 :test/tag :tag/a ?tx false]
+                          [201 :test/tag :tag/c ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        assert_transact!(conn, "[[:db.fn/retractAttribute 200 :test/unknown]]",
+                         Err("schema constraint violation: unknown attributes:\n  entity 0 uses :test/unknown, which is not an attribute\n"));
+    }
+
     #[test]
     fn test_db_double_retraction_issue_818() {
         let mut conn = TestConn::default();
@@ -2088,6 +3206,199 @@ mod tests {
                           [301 :test/other 3]]");
     }
 
+    #[test]
+    fn test_db_normalize() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/name]
+                                 [:db/add 111 :db/valueType :db.type/string]
+                                 [:db/add 111 :db/unique :db.unique/identity]
+                                 [:db/add 111 :db/index true]
+                                 [:db/add 111 :db/normalize true]
+                                 [:db/add 222 :db/ident :test/text]
+                                 [:db/add 222 :db/valueType :db.type/string]
+                                 [:db/add 222 :db/index true]
+                                 [:db/add 222 :db/fulltext true]
+                                 [:db/add 222 :db/normalize true]]");
+
+        let name = conn.schema.attribute_for_entid(111).cloned().expect(":test/name");
+        assert_eq!(name.normalize, true);
+
+        // "e" followed by COMBINING ACUTE ACCENT is stored as the precomposed "é".
+        assert_transact!(conn, "[[:db/add \"t\" :test/name \"cafe\u{301}\"]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?e :test/name \"caf\u{e9}\" ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // The precomposed form upserts to the same entity, so nothing changes.
+        assert_transact!(conn, "[[:db/add \"t\" :test/name \"caf\u{e9}\"]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?tx :db/txInstant ?ms ?tx true]]");
+
+        // Lookup refs are normalized too.
+        assert_transact!(conn, "[[:db/add (lookup-ref :test/name \"cafe\u{301}\") :test/text \"cr\u{e8}me br\u{fb}l\u{e9}e\"]]");
+
+        // Fulltext values keep their NFC text and gain a folded form.
+        let folded: String = conn.sqlite.query_row("SELECT folded FROM fulltext_values WHERE text = ?",
+                                                   &[&"cr\u{e8}me br\u{fb}l\u{e9}e"], |row| row.get(0))
+                                        .expect("folded");
+        assert_eq!(folded, "creme brulee");
+
+        // A string is folded even when an attribute that doesn't normalize has it first.
+        assert_transact!(conn, "[[:db/add 101 :db/ident :test/notes]
+                                 [:db/add 101 :db/valueType :db.type/string]
+                                 [:db/add 101 :db/index true]
+                                 [:db/add 101 :db/fulltext true]]");
+        assert_transact!(conn, "[[:db/add \"n\" :test/notes \"na\u{ef}ve\"]
+                                 [:db/add \"n\" :test/text \"na\u{ef}ve\"]]");
+        let folded: String = conn.sqlite.query_row("SELECT folded FROM fulltext_values WHERE text = ?",
+                                                   &[&"na\u{ef}ve"], |row| row.get(0))
+                                        .expect("folded");
+        assert_eq!(folded, "naive");
+
+        // Normalization can't be added to or removed from an existing attribute.
+        assert_transact!(conn, "[[:db/add 333 :db/ident :test/plain]
+                                 [:db/add 333 :db/valueType :db.type/string]
+                                 [:db/add 333 :db/cardinality :db.cardinality/one]]");
+        assert_transact!(conn, "[[:db/add :test/plain :db/normalize true]]",
+                         Err("bad schema assertion: Schema alteration for existing attribute with entid 333 is not valid"));
+
+        // Only strings can be normalized.
+        assert_transact!(conn, "[[:db/add 444 :db/ident :test/long]
+                                 [:db/add 444 :db/valueType :db.type/long]
+                                 [:db/add 444 :db/cardinality :db.cardinality/one]
+                                 [:db/add 444 :db/normalize true]]",
+                         Err("bad schema assertion: :db/normalize true without :db/valueType :db.type/string for entid: 444"));
+    }
+
+    #[test]
+    fn test_db_case_insensitive() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/email]
+                                 [:db/add 111 :db/valueType :db.type/string]
+                                 [:db/add 111 :db/cardinality :db.cardinality/one]
+                                 [:db/add 111 :db/unique :db.unique/identity]
+                                 [:db/add 111 :db/index true]
+                                 [:db/add 111 :db/caseInsensitive true]
+                                 [:db/add 222 :db/ident :test/name]
+                                 [:db/add 222 :db/valueType :db.type/string]
+                                 [:db/add 222 :db/cardinality :db.cardinality/one]]");
+
+        let email = conn.schema.attribute_for_entid(111).cloned().expect(":test/email");
+        assert_eq!(email.case_insensitive, true);
+
+        // Values are stored as given.
+        assert_transact!(conn, "[[:db/add 301 :test/email \"Alice@Example.com\"]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[301 :test/email \"Alice@Example.com\" ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // A value differing only in case upserts to the same entity.
+        let report = assert_transact!(conn, "[[:db/add \"t\" :test/email \"alice@example.com\"]
+                                              [:db/add \"t\" :test/name \"Alice\"]]");
+        assert_eq!(report.tempids.get("t"), Some(&301));
+
+        // Lookup refs resolve regardless of case, too.
+        assert_transact!(conn, "[[:db/add (lookup-ref :test/email \"ALICE@EXAMPLE.COM\") :test/name \"Alicia\"]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[301 :test/name \"Alice\" ?tx false]
+                          [301 :test/name \"Alicia\" ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Another entity can't claim the same value in a different case.
+        assert_transact!(conn, "[[:db/add 302 :test/email \"ALICE@example.com\"]]",
+                         Err("schema constraint violation: case insensitive conflicts:\n  entities 301 and 302 have values of 111 differing only in case\n"));
+
+        // Nor can two entities in the same transaction.
+        assert_transact!(conn, "[[:db/add 303 :test/email \"bob@example.com\"]
+                                 [:db/add 304 :test/email \"BOB@example.com\"]]",
+                         Err("schema constraint violation: case insensitive conflicts:\n  entities 303 and 304 have values of 111 differing only in case\n"));
+
+        // Case is folded beyond ASCII.
+        assert_transact!(conn, "[[:db/add 305 :test/email \"JOSÉ@example.com\"]]");
+        let report = assert_transact!(conn, "[[:db/add \"t\" :test/email \"josé@example.com\"]]");
+        assert_eq!(report.tempids.get("t"), Some(&305));
+        assert_transact!(conn, "[[:db/add 306 :test/email \"josé@EXAMPLE.com\"]]",
+                         Err("schema constraint violation: case insensitive conflicts:\n  entities 305 and 306 have values of 111 differing only in case\n"));
+
+        // Only unique string attributes can be case-insensitive.
+        assert_transact!(conn, "[[:db/add 333 :db/ident :test/nickname]
+                                 [:db/add 333 :db/valueType :db.type/string]
+                                 [:db/add 333 :db/cardinality :db.cardinality/one]
+                                 [:db/add 333 :db/caseInsensitive true]]",
+                         Err("bad schema assertion: :db/caseInsensitive true without :db/unique for entid: 333"));
+    }
+
+    #[test]
+    fn test_db_unique_with() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/page]
+                                 [:db/add 111 :db/valueType :db.type/string]
+                                 [:db/add 111 :db/cardinality :db.cardinality/one]
+                                 [:db/add 222 :db/ident :test/timestamp]
+                                 [:db/add 222 :db/valueType :db.type/long]
+                                 [:db/add 222 :db/cardinality :db.cardinality/one]
+                                 [:db/add 222 :db/index true]
+                                 [:db/add 222 :db/uniqueWith 111]]");
+
+        let timestamp = conn.schema.attribute_for_entid(222).cloned().expect(":test/timestamp");
+        assert_eq!(timestamp.unique_with, Some(111));
+
+        // Either value alone may repeat.
+        assert_transact!(conn, "[[:db/add 301 :test/page \"a\"]
+                                 [:db/add 301 :test/timestamp 1]
+                                 [:db/add 302 :test/page \"a\"]
+                                 [:db/add 302 :test/timestamp 2]
+                                 [:db/add 303 :test/page \"b\"]
+                                 [:db/add 303 :test/timestamp 1]]");
+
+        // Both together may not, whichever of the two attributes brings them together.
+        assert_transact!(conn, "[[:db/add 304 :test/page \"a\"]
+                                 [:db/add 304 :test/timestamp 1]]",
+                         Err("schema constraint violation: unique with conflicts:\n  entities 301 and 304 share values of 222 and 111\n"));
+        assert_transact!(conn, "[[:db/add 303 :test/page \"a\"]]",
+                         Err("schema constraint violation: unique with conflicts:\n  entities 301 and 303 share values of 222 and 111\n"));
+
+        // Moving one entity out of the way first is fine.
+        assert_transact!(conn, "[[:db/add 301 :test/timestamp 3]
+                                 [:db/add 303 :test/page \"a\"]]");
+
+        // Attributes that are unique with another must be indexed, and their partner must be
+        // another attribute.
+        assert_transact!(conn, "[[:db/add 333 :db/ident :test/visitor]
+                                 [:db/add 333 :db/valueType :db.type/string]
+                                 [:db/add 333 :db/cardinality :db.cardinality/one]
+                                 [:db/add 333 :db/uniqueWith 111]]",
+                         Err("bad schema assertion: :db/uniqueWith without :db/index true for entid: 333"));
+        assert_transact!(conn, "[[:db/add 333 :db/ident :test/visitor]
+                                 [:db/add 333 :db/valueType :db.type/string]
+                                 [:db/add 333 :db/cardinality :db.cardinality/one]
+                                 [:db/add 333 :db/index true]
+                                 [:db/add 333 :db/uniqueWith 333]]",
+                         Err("bad schema assertion: :db/uniqueWith 333 is not another attribute for entid: 333"));
+
+        // An existing attribute can be made unique with another, if no two entities already share
+        // values of both.
+        assert_transact!(conn, "[[:db/add 333 :db/ident :test/visitor]
+                                 [:db/add 333 :db/valueType :db.type/string]
+                                 [:db/add 333 :db/cardinality :db.cardinality/one]
+                                 [:db/add 333 :db/index true]]");
+        assert_transact!(conn, "[[:db/add 301 :test/visitor \"alice\"]
+                                 [:db/add 302 :test/visitor \"bob\"]
+                                 [:db/add 303 :test/visitor \"alice\"]]");
+        assert_transact!(conn, "[[:db/add 333 :db/uniqueWith 111]]",
+                         Err("schema alteration failed: Cannot alter schema attribute 333 to be :db/uniqueWith 111: entities 301 and 303 share values of both"));
+        assert_eq!(conn.schema.attribute_for_entid(333).and_then(|a| a.unique_with), None);
+
+        assert_transact!(conn, "[[:db/add 303 :test/visitor \"carol\"]]");
+        assert_transact!(conn, "[[:db/add 333 :db/uniqueWith 111]]");
+        assert_eq!(conn.schema.attribute_for_entid(333).and_then(|a| a.unique_with), Some(111));
+        assert_transact!(conn, "[[:db/add 302 :test/visitor \"alice\"]]",
+                         Err("schema constraint violation: unique with conflicts:\n  entities 301 and 302 share values of 333 and 111\n"));
+    }
+
     #[test]
     fn test_lookup_refs_entity_column() {
         let mut conn = TestConn::default();
@@ -2129,7 +3440,7 @@ mod tests {
         // We cannot resolve lookup refs that aren't :db/unique.
         assert_transact!(conn,
                          "[[:db/add (lookup-ref :test/not_unique :test/keyword) :test/not_unique :test/keyword]]",
-                         Err("not yet implemented: Cannot resolve (lookup-ref 333 Keyword(Keyword(NamespaceableName { namespace: Some(\"test\"), name: \"keyword\" }))) with attribute that is not :db/unique"));
+                         Err("can't resolve lookup ref Keyword(Keyword(NamespaceableName { namespace: Some(\"test\"), name: \"keyword\" })): attribute 333 is not :db/unique"));
 
         // We type check the lookup ref's value against the lookup ref's attribute.
         assert_transact!(conn,
@@ -2139,7 +3450,7 @@ mod tests {
         // Each lookup ref in the entity column must resolve
         assert_transact!(conn,
                          "[[:db/add (lookup-ref :test/unique_value \"unmatched string value\") :test/not_unique :test/keyword]]",
-                         Err("no entid found for ident: couldn\'t lookup [a v]: (111, String(\"unmatched string value\"))"));
+                         Err("couldn\'t resolve lookup ref [a v]: (111, String(\"unmatched string value\"))"));
     }
 
     #[test]
@@ -2200,7 +3511,46 @@ mod tests {
         // Each lookup ref in the value column must resolve
         assert_transact!(conn,
                          "[[:db/add \"t\" :test/ref (lookup-ref :test/unique_value \"unmatched string value\")]]",
-                         Err("no entid found for ident: couldn\'t lookup [a v]: (111, String(\"unmatched string value\"))"));
+                         Err("couldn\'t resolve lookup ref [a v]: (111, String(\"unmatched string value\"))"));
+    }
+
+    #[test]
+    fn test_vector_lookup_refs() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[{:db/id 111 :db/ident :test/email :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+                                 {:db/id 222 :db/ident :test/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+                                 {:db/id 333 :db/ident :test/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/many}
+                                 {:db/id 444 :db/ident :test/tag :db/valueType :db.type/ref :db/cardinality :db.cardinality/many}]");
+        assert_transact!(conn, "[[:db/add 501 :test/email \"x@y.com\"]
+                                 [:db/add 502 :test/email \"z@y.com\"]]");
+
+        // In the entity place, in :db/id, and in the value place of a ref attribute.
+        assert_transact!(conn, "[[:db/add [:test/email \"x@y.com\"] :test/name \"X\"]
+                                 {:db/id [:test/email \"z@y.com\"] :test/name \"Z\"}
+                                 [:db/add 501 :test/friend [:test/email \"z@y.com\"]]
+                                 [:db/add [:test/email \"x@y.com\"] :test/_friend 502]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[501 :test/name \"X\" ?tx true]
+                          [501 :test/friend 502 ?tx true]
+                          [502 :test/name \"Z\" ?tx true]
+                          [502 :test/friend 501 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // A vector whose attribute isn't :db/unique is a vector of values.
+        assert_transact!(conn, "[[:db/add 501 :test/tag [:test/name :test/email]]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[501 :test/tag :test/email ?tx true]
+                          [501 :test/tag :test/name ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Each lookup ref must resolve, and must use a :db/unique attribute.
+        assert_transact!(conn,
+                         "[[:db/add [:test/email \"w@y.com\"] :test/name \"W\"]]",
+                         Err("couldn\'t resolve lookup ref [a v]: (111, String(\"w@y.com\"))"));
+        assert_transact!(conn,
+                         "[[:db/add [:test/name \"X\"] :test/name \"W\"]]",
+                         Err("can\'t resolve lookup ref String(\"X\"): attribute 222 is not :db/unique"));
     }
 
     #[test]
@@ -2479,7 +3829,7 @@ mod tests {
         // Verify that we can't use reverse notation with unrecognized attributes.
         assert_transact!(conn,
                          "[{:test/_unknown 500}]",
-                         Err("no entid found for ident: :test/unknown")); // TODO: make this error reference the original :test/_unknown.
+                         Err("schema constraint violation: unknown attributes:\n  entity 0 uses :test/_unknown, which is not an attribute\n"));
 
         // Verify that we can't use reverse notation with bad value types: here, an unknown keyword
         // that can't be coerced to a ref.
@@ -2492,6 +3842,40 @@ mod tests {
                          Err("value \'1.23\' is not the expected Mentat value type Ref"));
     }
 
+    #[test]
+    fn test_unknown_attributes() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/unique]
+                                 [:db/add 111 :db/unique :db.unique/identity]
+                                 [:db/add 111 :db/index true]
+                                 [:db/add 111 :db/valueType :db.type/long]
+                                 [:db/add 222 :db/ident :test/ref]
+                                 [:db/add 222 :db/valueType :db.type/ref]]");
+
+        // Every unknown attribute is reported against the entity that uses it, whether it's in the
+        // attribute place, in nested map notation, or in a lookup ref.
+        assert_transact!(conn, "[[:db/add \"a\" :test/unique 1]
+                                 [:db/add \"b\" :test/missing 2]
+                                 {:test/ref {:test/nested \"x\"} :test/other true}
+                                 [:db/add (lookup-ref :test/lookup 1) :test/unique 3]]",
+                         Err("schema constraint violation: unknown attributes:\n  entity 1 uses :test/missing, which is not an attribute\n  entity 2 uses :test/nested, which is not an attribute\n  entity 2 uses :test/other, which is not an attribute\n  entity 3 uses :test/lookup, which is not an attribute\n"));
+
+        // Entids that aren't attributes are unknown attributes, too.
+        assert_transact!(conn, "[[:db/add 111 :db/doc \"ok\"] [:db/add 111 333 \"not ok\"]]",
+                         Err("schema constraint violation: unknown attributes:\n  entity 1 uses 333, which is not an attribute\n"));
+
+        // Nothing was transacted.
+        assert_matches!(conn.last_transaction(),
+                        "[[111 :db/ident :test/unique ?tx true]
+                          [111 :db/valueType :db.type/long ?tx true]
+                          [111 :db/unique :db.unique/identity ?tx true]
+                          [111 :db/index true ?tx true]
+                          [222 :db/ident :test/ref ?tx true]
+                          [222 :db/valueType :db.type/ref ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+    }
+
     #[test]
     fn test_cardinality_one_violation_existing_entity() {
         let mut conn = TestConn::default();
@@ -2740,6 +4124,219 @@ mod tests {
         Err("schema constraint violation: cardinality conflicts:\n  AddRetractConflict { e: 100, a: 200, vs: {Long(7)} }\n  AddRetractConflict { e: 100, a: 201, vs: {Long(8)} }\n"));
     }
 
+    #[test]
+    fn test_spill_threshold() {
+        let sqlite = new_connection("").expect("Couldn't open in-memory db");
+        let other = new_connection("").expect("Couldn't open in-memory db");
+        let pragma = |conn: &rusqlite::Connection, name: &str| -> i64 {
+            conn.query_row(&format!("PRAGMA {}", name), &[], |row| row.get(0)).expect("pragma")
+        };
+
+        // By default, temporary structures live in memory.
+        assert_eq!(pragma(&sqlite, "temp_store"), 2);
+        assert_eq!(pragma(&sqlite, "cache_size"), DEFAULT_CACHE_SIZE);
+
+        set_spill_threshold(&sqlite, Some(8 * 1024 * 1024)).expect("set threshold");
+        assert_eq!(pragma(&sqlite, "temp_store"), 1);
+        assert_eq!(pragma(&sqlite, "cache_size"), -8 * 1024);
+
+        // The threshold is per-connection.
+        assert_eq!(pragma(&other, "temp_store"), 2);
+        assert_eq!(pragma(&other, "cache_size"), DEFAULT_CACHE_SIZE);
+
+        set_spill_threshold(&sqlite, None).expect("cleared threshold");
+        assert_eq!(pragma(&sqlite, "temp_store"), 2);
+        assert_eq!(pragma(&sqlite, "cache_size"), DEFAULT_CACHE_SIZE);
+    }
+
+    #[test]
+    fn test_create_partition() {
+        let mut conn = TestConn::default();
+        let start = ::USER0 + 1000;
+        create_partition(&conn.sqlite, &mut conn.partition_map, ":test/part", start).expect("created");
+        assert_eq!(conn.partition_map[":db.part/user"].end, start - 1);
+        assert_eq!(conn.partition_map[":test/part"], Partition::new(start, ::TX0 - 1, start, true));
+
+        // Tempids are allocated in :db.part/user unless they name a partition.
+        let report = assert_transact!(conn, r#"[[:db/add (tempid :test/part "a") :db.schema/version 1]
+                                                [:db/add "b" :db.schema/attribute (tempid :test/part "a")]]"#);
+        assert_eq!(report.tempids["a"], start);
+        assert_eq!(report.tempids["b"], ::USER0);
+
+        // The partition is stored.
+        let partition_map = read_partition_map(&conn.sqlite).expect("read");
+        assert_eq!(partition_map[":test/part"], Partition::new(start, ::TX0 - 1, start + 1, true));
+        assert_eq!(partition_map[":db.part/user"], Partition::new(::USER0, start - 1, ::USER0 + 1, true));
+
+        assert_eq!(create_partition(&conn.sqlite, &mut conn.partition_map, ":test/part", start + 10).expect_err("exists").to_string(),
+                   "can't create partition :test/part: it already exists");
+        assert_eq!(create_partition(&conn.sqlite, &mut conn.partition_map, ":test/early", ::USER0).expect_err("allocated").to_string(),
+                   format!("can't create partition :test/early: :db.part/user has allocated entids up to {}", ::USER0 + 1));
+        assert_eq!(create_partition(&conn.sqlite, &mut conn.partition_map, ":test/late", ::TX0 + 1000).expect_err("tx").to_string(),
+                   "can't create partition :test/late: :db.part/tx is reserved for transactions");
+
+        assert_transact!(conn, r#"[[:db/add (tempid :test/nope "c") :db.schema/version 1]]"#,
+                         Err("can't allocate tempid (tempid :test/nope \"c\"): no such partition :test/nope"));
+        assert_transact!(conn, r#"[[:db/add (tempid :db.part/tx "c") :db.schema/version 1]]"#,
+                         Err("can't allocate tempid (tempid :db.part/tx \"c\"): :db.part/tx is reserved for transactions"));
+        assert_transact!(conn, r#"[[:db/add (tempid :test/part "c") :db.schema/version 1]
+                                   [:db/add "c" :db.schema/version 2]]"#,
+                         Err("can't allocate tempid c: it names tempids in more than one partition"));
+    }
+
+    /// Copy the store `fixture` to a temporary file, and open it.
+    fn open_fixture_copy(fixture: &str) -> (tempfile::NamedTempFile, rusqlite::Connection) {
+        let file = tempfile::NamedTempFile::new().expect("temporary file");
+        ::std::fs::copy(fixture, file.path()).expect("fixture");
+        let sqlite = new_connection(file.path()).expect("connection");
+        (file, sqlite)
+    }
+
+    #[test]
+    fn test_open_without_large_values() {
+        // A store written by Mentat's first schema version, before values were offloaded.
+        let (_file, mut sqlite) = open_fixture_copy("../fixtures/v1fulltext.db");
+        let tables = "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'large_values'";
+        assert_eq!(sqlite.query_row(tables, &[], |row| row.get::<_, i64>(0)).expect("tables"), 0);
+
+        ensure_current_version(&mut sqlite).expect("opened");
+        assert_eq!(sqlite.query_row(tables, &[], |row| row.get::<_, i64>(0)).expect("tables"), 1);
+    }
+
+    #[test]
+    fn test_upgrade_from_v1() {
+        // A store written by Mentat's first schema version, with some fulltext values.
+        let (_file, mut sqlite) = open_fixture_copy("../fixtures/v1fulltext.db");
+        assert_eq!(get_user_version(&sqlite).expect("version"), 1);
+
+        let db = ensure_current_version(&mut sqlite).expect("upgraded");
+        assert_eq!(get_user_version(&sqlite).expect("version"), CURRENT_VERSION);
+        assert!(has_column(&sqlite, "datoms", "unique_folded").expect("columns"));
+        assert_eq!(db.schema.get_entid(&Keyword::namespaced("db", "caseInsensitive")), Some(KnownEntid(entids::DB_CASE_INSENSITIVE)));
+        assert_eq!(db.schema.attribute_for_entid(entids::DB_CASE_INSENSITIVE).map(|a| a.value_type), Some(ValueType::Boolean));
+
+        // The new idents have been allocated.
+        assert!(db.partition_map[":db.part/db"].next_entid() > entids::DB_CASE_INSENSITIVE);
+
+        assert!(has_column(&sqlite, "fulltext_values", "folded").expect("columns"));
+        assert_eq!(db.schema.get_entid(&Keyword::namespaced("db", "normalize")), Some(KnownEntid(entids::DB_NORMALIZE)));
+        assert_eq!(db.schema.get_entid(&Keyword::namespaced("db.type", "bigint")), Some(KnownEntid(entids::DB_TYPE_BIGINT)));
+        assert_eq!(db.schema.attribute_for_entid(entids::DB_UNIQUE_WITH).map(|a| a.value_type), Some(ValueType::Ref));
+
+        // Fulltext values are still interpolated into datoms.
+        let fulltext: i64 = sqlite.query_row("SELECT count(*) FROM all_datoms WHERE index_fulltext IS NOT 0 AND v IN (?, ?)",
+                                             &[&"Cr\u{e8}me br\u{fb}l\u{e9}e recipe", &"A shopping list"], |row| row.get(0))
+                                  .expect("fulltext datoms");
+        assert_eq!(fulltext, 2);
+
+        // Upgrading is only done once.
+        let generation = read_generation(&sqlite).expect("generation");
+        let db = ensure_current_version(&mut sqlite).expect("current");
+        assert_eq!(read_generation(&sqlite).expect("generation"), generation);
+
+        // The new schema works.
+        let mut conn = TestConn { sqlite: sqlite, partition_map: db.partition_map, schema: db.schema };
+        assert_transact!(conn, "[{:db/ident :test/email
+                                  :db/valueType :db.type/string
+                                  :db/cardinality :db.cardinality/one
+                                  :db/unique :db.unique/identity
+                                  :db/index true
+                                  :db/caseInsensitive true}]");
+        let report = assert_transact!(conn, "[[:db/add \"a\" :test/email \"alice@example.com\"]]");
+        let alice = report.tempids["a"];
+        let report = assert_transact!(conn, "[[:db/add \"a\" :test/email \"ALICE@example.com\"]]");
+        assert_eq!(report.tempids["a"], alice);
+
+        assert_transact!(conn, "[{:db/ident :test/title
+                                  :db/valueType :db.type/string
+                                  :db/cardinality :db.cardinality/one
+                                  :db/index true
+                                  :db/fulltext true
+                                  :db/normalize true}]");
+        assert_transact!(conn, "[[:db/add \"a\" :test/title \"Cr\u{e8}me br\u{fb}l\u{e9}e recipe\"]]");
+        let folded: String = conn.sqlite.query_row("SELECT folded FROM fulltext_values WHERE text = ?",
+                                                   &[&"Cr\u{e8}me br\u{fb}l\u{e9}e recipe"], |row| row.get(0))
+                                        .expect("folded");
+        assert_eq!(folded, "Creme brulee recipe");
+    }
+
+    #[test]
+    fn test_upgrade_big_integers() {
+        let file = tempfile::NamedTempFile::new().expect("temporary file");
+        let mut sqlite = new_connection(file.path()).expect("connection");
+        let db = ensure_current_version(&mut sqlite).expect("created");
+        let mut conn = TestConn { sqlite: sqlite, partition_map: db.partition_map, schema: db.schema };
+        assert_transact!(conn, "[{:db/ident :test/big
+                                  :db/valueType :db.type/bigint
+                                  :db/cardinality :db.cardinality/many}]");
+        assert_transact!(conn, "[[:db/add \"e\" :test/big -12N]
+                                 [:db/add \"e\" :test/big 123456789012345678901234567890N]]");
+
+        // Rewrite the values as a version 3 store would have stored them.
+        let mut sqlite = conn.sqlite;
+        for table in &["datoms", "timelined_transactions"] {
+            sqlite.execute(&format!("UPDATE {} SET v = ? WHERE value_type_tag = 14 AND v = ?", table),
+                           &[&"-12", &bigint_to_sql_text(&BigInt::from(-12))]).expect("rewritten");
+            sqlite.execute(&format!("UPDATE {} SET v = ? WHERE value_type_tag = 14 AND v LIKE 'p%'", table),
+                           &[&"123456789012345678901234567890"]).expect("rewritten");
+        }
+        set_user_version(&sqlite, 3).expect("version");
+
+        ensure_current_version(&mut sqlite).expect("upgraded");
+        let values: Vec<String> = {
+            let mut stmt = sqlite.prepare("SELECT v FROM datoms WHERE value_type_tag = 14 ORDER BY v").expect("statement");
+            let values: rusqlite::Result<Vec<String>> = stmt.query_map(&[], |row| row.get(0)).expect("values").collect();
+            values.expect("values")
+        };
+        assert_eq!(values, vec![bigint_to_sql_text(&BigInt::from(-12)),
+                                bigint_to_sql_text(&"123456789012345678901234567890".parse::<BigInt>().expect("big integer"))]);
+        let legacy: i64 = sqlite.query_row("SELECT count(*) FROM timelined_transactions WHERE value_type_tag = 14 AND v NOT LIKE 'p%' AND v NOT LIKE 'n%'",
+                                           &[], |row| row.get(0))
+                                .expect("count");
+        assert_eq!(legacy, 0);
+    }
+
+    #[test]
+    fn test_upgrade_case_folding() {
+        let file = tempfile::NamedTempFile::new().expect("temporary file");
+        let mut sqlite = new_connection(file.path()).expect("connection");
+        let db = ensure_current_version(&mut sqlite).expect("created");
+        let mut conn = TestConn { sqlite: sqlite, partition_map: db.partition_map, schema: db.schema };
+        assert_transact!(conn, "[{:db/ident :test/email
+                                  :db/valueType :db.type/string
+                                  :db/cardinality :db.cardinality/one
+                                  :db/unique :db.unique/identity
+                                  :db/index true
+                                  :db/caseInsensitive true}]");
+        let report = assert_transact!(conn, "[[:db/add \"a\" :test/email \"ÄLICE@example.com\"]
+                                              [:db/add \"b\" :test/email \"bob@example.com\"]]");
+        let alice = report.tempids["a"];
+
+        // Make the store look like a version 5 store, which compared `lower(v)`, and so let values
+        // differing only in the case of non-ASCII letters collide.
+        let mut sqlite = conn.sqlite;
+        sqlite.execute_batch("DROP INDEX idx_datoms_unique_folded;
+                              CREATE UNIQUE INDEX idx_datoms_unique_folded ON datoms (a, value_type_tag, lower(v)) WHERE unique_folded IS NOT 0;
+                              UPDATE datoms SET folded = NULL;
+                              UPDATE datoms SET v = 'älice@example.com' WHERE v = 'bob@example.com';").expect("rewritten");
+        set_user_version(&sqlite, 5).expect("version");
+
+        // We can't choose between them.
+        assert_eq!(ensure_current_version(&mut sqlite).err().map(|e| e.to_string()),
+                   Some("can't upgrade a store from version 5: values \"ÄLICE@example.com\" and \"älice@example.com\" of attribute 65536 differ only in case".to_string()));
+        assert_eq!(get_user_version(&sqlite).expect("version"), 5);
+
+        sqlite.execute("UPDATE datoms SET v = 'bob@example.com' WHERE v = 'älice@example.com'", &[]).expect("rewritten");
+        let db = ensure_current_version(&mut sqlite).expect("upgraded");
+        let folded: String = sqlite.query_row("SELECT folded FROM datoms WHERE e = ? AND unique_folded IS NOT 0", &[&alice], |row| row.get(0))
+                                   .expect("folded");
+        assert_eq!(folded, "älice@example.com");
+
+        let mut conn = TestConn { sqlite: sqlite, partition_map: db.partition_map, schema: db.schema };
+        let report = assert_transact!(conn, "[[:db/add \"a\" :test/email \"älice@EXAMPLE.com\"]]");
+        assert_eq!(report.tempids["a"], alice);
+    }
+
     #[test]
     #[cfg(feature = "sqlcipher")]
     fn test_sqlcipher_openable() {
@@ -343,7 +343,7 @@ impl TestConn {
             // We're about to write, so go straight ahead and get an IMMEDIATE transaction.
             let tx = self.sqlite.transaction_with_behavior(TransactionBehavior::Immediate)?;
             // Applying the transaction can fail, so we don't unwrap.
-            let details = transact(&tx, self.partition_map.clone(), &self.schema, &self.schema, NullWatcher(), entities)?;
+            let details = transact(&tx, self.partition_map.clone(), &self.schema, &self.schema, NullWatcher(), RedundantAssertions::Skip, None, entities)?;
             tx.commit()?;
             details
         };
@@ -366,7 +366,7 @@ impl TestConn {
             // We're about to write, so go straight ahead and get an IMMEDIATE transaction.
             let tx = self.sqlite.transaction_with_behavior(TransactionBehavior::Immediate)?;
             // Applying the transaction can fail, so we don't unwrap.
-            let details = transact_terms(&tx, self.partition_map.clone(), &self.schema, &self.schema, NullWatcher(), terms, tempid_set)?;
+            let details = transact_terms(&tx, self.partition_map.clone(), &self.schema, &self.schema, NullWatcher(), RedundantAssertions::Skip, None, terms, tempid_set)?;
             tx.commit()?;
             details
         };
@@ -408,12 +408,12 @@ impl TestConn {
 
         // Does not include :db/txInstant.
         let datoms = datoms_after(&conn, &db.schema, 0).unwrap();
-        assert_eq!(datoms.0.len(), 94);
+        assert_eq!(datoms.0.len(), 107);
 
         // Includes :db/txInstant.
         let transactions = transactions_after(&conn, &db.schema, 0).unwrap();
         assert_eq!(transactions.0.len(), 1);
-        assert_eq!(transactions.0[0].0.len(), 95);
+        assert_eq!(transactions.0[0].0.len(), 108);
 
         let mut parts = db.partition_map;
 
@@ -59,70 +59,69 @@ pub const DB_DOC: Entid = 37;
 pub const DB_SCHEMA_VERSION: Entid = 38;
 pub const DB_SCHEMA_ATTRIBUTE: Entid = 39;
 pub const DB_SCHEMA_CORE: Entid = 40;
+pub const DB_NORMALIZE: Entid = 41;
+pub const DB_CASE_INSENSITIVE: Entid = 42;
+pub const DB_TYPE_BIGINT: Entid = 43;
+pub const DB_UNIQUE_WITH: Entid = 44;
+
+/// Attributes that describe a schema attribute.  These might change the "schema" materialized view.
+pub const SCHEMA_ATTRIBUTES: [Entid; 9] = [
+    DB_CARDINALITY,
+    DB_CASE_INSENSITIVE,
+    DB_FULLTEXT,
+    DB_INDEX,
+    DB_IS_COMPONENT,
+    DB_NORMALIZE,
+    DB_UNIQUE,
+    DB_UNIQUE_WITH,
+    DB_VALUE_TYPE,
+];
+
+/// Attributes that change the metadata: recognized idents and schema.  These might change one of
+/// the materialized views.
+pub const METADATA_ATTRIBUTES: [Entid; 10] = [
+    DB_CARDINALITY,
+    DB_CASE_INSENSITIVE,
+    DB_FULLTEXT,
+    DB_IDENT,
+    DB_INDEX,
+    DB_IS_COMPONENT,
+    DB_NORMALIZE,
+    DB_UNIQUE,
+    DB_UNIQUE_WITH,
+    DB_VALUE_TYPE,
+];
 
 /// Return `false` if the given attribute will not change the metadata: recognized idents, schema,
 /// partitions in the partition map.
 pub fn might_update_metadata(attribute: Entid) -> bool {
-    if attribute >= DB_DOC {
-        return false
-    }
-    match attribute {
-        // Idents.
-        DB_IDENT |
-        // Schema.
-        DB_CARDINALITY |
-        DB_FULLTEXT |
-        DB_INDEX |
-        DB_IS_COMPONENT |
-        DB_UNIQUE |
-        DB_VALUE_TYPE =>
-            true,
-        _ => false,
-    }
+    METADATA_ATTRIBUTES.contains(&attribute)
 }
 
 /// Return 'false' if the given attribute might be used to describe a schema attribute.
 pub fn is_a_schema_attribute(attribute: Entid) -> bool {
-    match attribute {
-        DB_IDENT |
-        DB_CARDINALITY |
-        DB_FULLTEXT |
-        DB_INDEX |
-        DB_IS_COMPONENT |
-        DB_UNIQUE |
-        DB_VALUE_TYPE =>
-            true,
-        _ => false,
-    }
+    attribute == DB_IDENT || SCHEMA_ATTRIBUTES.contains(&attribute)
+}
+
+/// Format `attributes` as a parenthesized SQL list, like `(1, 2, 3)`.
+fn to_sql_list(attributes: &[Entid]) -> String {
+    let attributes: Vec<String> = attributes.iter().map(|a| a.to_string()).collect();
+    format!("({})", attributes.join(", "))
 }
 
 lazy_static! {
     /// Attributes that are "ident related".  These might change the "idents" materialized view.
     pub static ref IDENTS_SQL_LIST: String = {
-        format!("({})",
-                DB_IDENT)
+        to_sql_list(&[DB_IDENT])
     };
 
     /// Attributes that are "schema related".  These might change the "schema" materialized view.
     pub static ref SCHEMA_SQL_LIST: String = {
-        format!("({}, {}, {}, {}, {}, {})",
-                DB_CARDINALITY,
-                DB_FULLTEXT,
-                DB_INDEX,
-                DB_IS_COMPONENT,
-                DB_UNIQUE,
-                DB_VALUE_TYPE)
+        to_sql_list(&SCHEMA_ATTRIBUTES)
     };
 
     /// Attributes that are "metadata" related.  These might change one of the materialized views.
     pub static ref METADATA_SQL_LIST: String = {
-        format!("({}, {}, {}, {}, {}, {}, {})",
-                DB_CARDINALITY,
-                DB_FULLTEXT,
-                DB_IDENT,
-                DB_INDEX,
-                DB_IS_COMPONENT,
-                DB_UNIQUE,
-                DB_VALUE_TYPE)
+        to_sql_list(&METADATA_ATTRIBUTES)
     };
 }
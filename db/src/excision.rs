@@ -0,0 +1,228 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Excision: permanently removing what the store knows about an entity, present and past, as if
+//! it had never been transacted.  Unlike a retraction, an excision leaves nothing in the
+//! transaction log.
+//!
+//! Excising deletes rows but doesn't shrink the database file; `vacuum` reclaims the space.
+
+use std::collections::BTreeSet;
+
+use rusqlite;
+
+use core_traits::{
+    Entid,
+    TypedValue,
+};
+
+use mentat_core::{
+    HasSchema,
+    Schema,
+};
+
+use db::{
+    TypedSQLValue,
+    resolved_value_sql,
+};
+
+use db_traits::errors::{
+    DbErrorKind,
+    Result,
+};
+
+use types::{
+    PartitionMap,
+};
+
+/// The SQL conditions that select the rows to excise from a table aliased as `t`.
+fn excised_rows(target: Entid, attributes: &BTreeSet<Entid>) -> String {
+    if attributes.is_empty() {
+        format!("t.e = {}", target)
+    } else {
+        let attributes: Vec<String> = attributes.iter().map(|a| a.to_string()).collect();
+        format!("t.e = {} AND t.a IN ({})", target, attributes.join(", "))
+    }
+}
+
+/// Excise `target`: remove its datoms, and the transaction log's record of them, from the store.
+/// If `attributes` isn't empty, only datoms of those attributes are excised.
+///
+/// If `before_tx` is given, only history is excised: log entries from transactions before
+/// `before_tx`, except those that asserted datoms that still hold.  The current datoms are kept.
+///
+/// Fulltext and offloaded values that nothing refers to any longer are removed, too.
+///
+/// Only entities in partitions that allow excision can be excised, and attributes and other
+/// entities with idents never can, since the schema depends on them.  Excising doesn't update
+/// the attribute cache, and doesn't notify transaction observers or sync.  Returns the current
+/// datoms that were excised, which the caller can use to update the cache.
+pub fn excise(conn: &rusqlite::Connection,
+              schema: &Schema,
+              partition_map: &PartitionMap,
+              target: Entid,
+              attributes: &BTreeSet<Entid>,
+              before_tx: Option<Entid>) -> Result<Vec<(Entid, Entid, TypedValue)>> {
+    match partition_map.values().find(|partition| partition.contains_entid(target)) {
+        Some(partition) if partition.allow_excision => {},
+        Some(_) => bail!(DbErrorKind::CannotExcise(target, "its partition doesn't allow excision".to_string())),
+        None => bail!(DbErrorKind::UnallocatedEntid(target)),
+    }
+    if let Some(ident) = schema.get_ident(target) {
+        bail!(DbErrorKind::CannotExcise(target, format!("it has the ident {}", ident)));
+    }
+    for &a in attributes {
+        if !schema.is_attribute(a) {
+            bail!(DbErrorKind::UnknownAttribute(a));
+        }
+    }
+
+    let rows = excised_rows(target, attributes);
+
+    // The log rows to excise.  With `before_tx`, the assertions of current datoms are kept.
+    let log_rows = match before_tx {
+        None => rows.clone(),
+        Some(before_tx) => format!(
+            "{} AND t.tx < {} AND NOT (t.added IS NOT 0 AND EXISTS \
+             (SELECT 1 FROM datoms AS d \
+              WHERE d.e = t.e AND d.a = t.a AND d.value_type_tag = t.value_type_tag AND d.v = t.v AND d.tx = t.tx))",
+            rows, before_tx),
+    };
+
+    let mut excised = vec![];
+    if before_tx.is_none() {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT t.e, t.a, {}, t.value_type_tag FROM all_datoms AS t WHERE {}",
+            resolved_value_sql("t"), rows))?;
+        let mut datoms = stmt.query_and_then(&[], |row| -> Result<(Entid, Entid, TypedValue)> {
+            let value_type_tag: i32 = row.get_checked(3)?;
+            let v = TypedValue::from_sql_value_pair(row.get_checked(2)?, value_type_tag)?;
+            Ok((row.get_checked(0)?, row.get_checked(1)?, v))
+        })?;
+        while let Some(datom) = datoms.next() {
+            excised.push(datom?);
+        }
+    }
+
+    let current_rows = if before_tx.is_none() { Some(rows.as_str()) } else { None };
+    excise_rows(conn, schema, current_rows, &log_rows)?;
+    Ok(excised)
+}
+
+/// Delete the rows of `datoms` selected by `current_rows`, if given, and of the transaction log
+/// selected by `log_rows`, both SQL conditions on a table aliased as `t`, along with the fulltext
+/// and offloaded values that nothing refers to any longer.
+pub(crate) fn excise_rows(conn: &rusqlite::Connection, schema: &Schema, current_rows: Option<&str>, log_rows: &str) -> Result<()> {
+    // Remember the fulltext and offloaded values that we're about to lose references to.
+    let fulltext: Vec<String> = schema.attribute_map
+                                      .iter()
+                                      .filter(|&(_, attribute)| attribute.fulltext)
+                                      .map(|(a, _)| a.to_string())
+                                      .collect();
+    let fulltext = fulltext.join(", ");
+    conn.execute("DROP TABLE IF EXISTS temp.excised_values", &[])?;
+    conn.execute("CREATE TABLE temp.excised_values (v INTEGER NOT NULL, fulltext TINYINT NOT NULL)", &[])?;
+    conn.execute(&format!(
+        "INSERT INTO temp.excised_values (v, fulltext) \
+         SELECT DISTINCT t.v, t.a IN ({fulltext}) FROM timelined_transactions AS t \
+         WHERE {log_rows} AND typeof(t.v) = 'integer' AND (t.a IN ({fulltext}) OR t.value_type_tag = 10)",
+        fulltext = fulltext, log_rows = log_rows), &[])?;
+
+    if let Some(rows) = current_rows {
+        conn.execute(&format!("DELETE FROM datoms WHERE rowid IN (SELECT t.rowid FROM datoms AS t WHERE {})", rows), &[])?;
+    }
+    conn.execute(&format!("DELETE FROM timelined_transactions WHERE rowid IN \
+                           (SELECT t.rowid FROM timelined_transactions AS t WHERE {})", log_rows), &[])?;
+
+    conn.execute(&format!(
+        "DELETE FROM fulltext_values WHERE rowid IN (SELECT v FROM temp.excised_values WHERE fulltext IS NOT 0) \
+         AND rowid NOT IN (SELECT v FROM datoms WHERE index_fulltext IS NOT 0) \
+         AND rowid NOT IN (SELECT v FROM timelined_transactions WHERE a IN ({}))", fulltext), &[])?;
+    conn.execute(&format!(
+        "DELETE FROM large_values WHERE id IN (SELECT v FROM temp.excised_values WHERE fulltext IS 0) \
+         AND id NOT IN (SELECT v FROM datoms WHERE value_type_tag = 10 AND index_fulltext IS 0 AND typeof(v) = 'integer') \
+         AND id NOT IN (SELECT v FROM timelined_transactions WHERE value_type_tag = 10 AND a NOT IN ({}) AND typeof(v) = 'integer')",
+        fulltext), &[])?;
+    conn.execute("DROP TABLE temp.excised_values", &[])?;
+    Ok(())
+}
+
+/// Reclaim the space that excisions and retractions have freed.  This rewrites the whole
+/// database, and can't run inside a transaction.
+pub fn vacuum(conn: &rusqlite::Connection) -> Result<()> {
+    conn.execute("INSERT INTO fulltext_values (fulltext_values) VALUES ('optimize')", &[])?;
+    conn.execute("VACUUM", &[])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edn::{
+        Keyword,
+    };
+
+    use debug::{
+        TestConn,
+    };
+
+    fn count(conn: &rusqlite::Connection, sql: &str) -> i64 {
+        conn.query_row(sql, &[], |row| row.get(0)).expect("counted")
+    }
+
+    #[test]
+    fn test_excise() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, r#"[
+            {:db/ident :test/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :test/bio :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true}
+        ]"#);
+        let report = assert_transact!(conn, r#"[{:db/id "a" :test/name "Alice" :test/bio "Likes Rust"}
+                                                 {:db/id "b" :test/name "Bob" :test/bio "Likes Rust"}]"#);
+        let alice = report.tempids["a"];
+        let bob = report.tempids["b"];
+        assert_transact!(conn, format!(r#"[[:db/add {} :test/name "Alicia"]]"#, alice));
+
+        let name = conn.schema.get_entid(&Keyword::namespaced("test", "name")).expect("name").0;
+        let bio = conn.schema.get_entid(&Keyword::namespaced("test", "bio")).expect("bio").0;
+
+        // Only history.
+        let before = conn.last_tx_id() + 1;
+        excise(&conn.sqlite, &conn.schema, &conn.partition_map, alice, &BTreeSet::new(), Some(before)).expect("excised");
+        assert_eq!(count(&conn.sqlite, &format!("SELECT count(*) FROM datoms WHERE e = {}", alice)), 2);
+        assert_eq!(count(&conn.sqlite, &format!("SELECT count(*) FROM transactions WHERE e = {}", alice)), 2);
+
+        // Only names.
+        let attributes = vec![name].into_iter().collect();
+        let excised = excise(&conn.sqlite, &conn.schema, &conn.partition_map, alice, &attributes, None).expect("excised");
+        assert_eq!(excised, vec![(alice, name, TypedValue::typed_string("Alicia"))]);
+        assert_eq!(count(&conn.sqlite, &format!("SELECT count(*) FROM datoms WHERE e = {}", alice)), 1);
+
+        // Everything.  Bob still refers to the fulltext value.
+        excise(&conn.sqlite, &conn.schema, &conn.partition_map, alice, &BTreeSet::new(), None).expect("excised");
+        assert_eq!(count(&conn.sqlite, &format!("SELECT count(*) FROM datoms WHERE e = {}", alice)), 0);
+        assert_eq!(count(&conn.sqlite, &format!("SELECT count(*) FROM transactions WHERE e = {}", alice)), 0);
+        assert_eq!(count(&conn.sqlite, "SELECT count(*) FROM fulltext_values"), 1);
+
+        excise(&conn.sqlite, &conn.schema, &conn.partition_map, bob, &vec![bio].into_iter().collect(), None).expect("excised");
+        assert_eq!(count(&conn.sqlite, "SELECT count(*) FROM fulltext_values"), 0);
+
+        // The schema can't be excised.
+        match excise(&conn.sqlite, &conn.schema, &conn.partition_map, name, &BTreeSet::new(), None).map_err(|e| e.kind()) {
+            Err(DbErrorKind::CannotExcise(e, _)) => assert_eq!(e, name),
+            x => panic!("expected CannotExcise, got {:?}", x),
+        }
+        match excise(&conn.sqlite, &conn.schema, &conn.partition_map, 1, &BTreeSet::new(), None).map_err(|e| e.kind()) {
+            Err(DbErrorKind::CannotExcise(e, _)) => assert_eq!(e, 1),
+            x => panic!("expected CannotExcise, got {:?}", x),
+        }
+    }
+}
@@ -47,6 +47,7 @@ use db_traits::errors::{
     DbErrorKind,
     Result,
 };
+use db::TypedSQLValue;
 use schema::{
     SchemaTypeChecking,
 };
@@ -100,17 +101,62 @@ impl TransactableValue for ValueAndSpan {
             BigInteger(_) |
             Float(_) |
             Uuid(_) |
+            Bytes(_) |
             PlainSymbol(_) |
             NamespacedSymbol(_) |
             Vector(_) |
             Set(_) |
-            Map(_) => bail!(DbErrorKind::InputError(errors::InputError::BadEntityPlace)),
+            Map(_) |
+            Tagged(_, _) => bail!(DbErrorKind::InputError(errors::InputError::BadEntityPlace)),
         }
     }
 
     fn as_tempid(&self) -> Option<TempId> {
         self.inner.as_text().cloned().map(TempId::External).map(|v| v.into())
     }
+
+    fn value_type_hint(&self) -> Option<ValueType> {
+        use self::SpannedValue::*;
+        match self.inner {
+            Boolean(_) => Some(ValueType::Boolean),
+            Integer(_) => Some(ValueType::Long),
+            Instant(_) => Some(ValueType::Instant),
+            Float(_) => Some(ValueType::Double),
+            Text(_) => Some(ValueType::String),
+            Uuid(_) => Some(ValueType::Uuid),
+            Keyword(_) => Some(ValueType::Keyword),
+            BigInteger(_) => Some(ValueType::BigInteger),
+            Bytes(_) => Some(ValueType::Bytes),
+            Nil |
+            PlainSymbol(_) |
+            NamespacedSymbol(_) |
+            Vector(_) |
+            List(_) |
+            Set(_) |
+            Map(_) |
+            Tagged(_, _) => None,
+        }
+    }
+
+    fn from_typed_value(value: TypedValue) -> Self {
+        let inner = match value {
+            TypedValue::Ref(x) => SpannedValue::Integer(x),
+            TypedValue::Boolean(x) => SpannedValue::Boolean(x),
+            TypedValue::Long(x) => SpannedValue::Integer(x),
+            TypedValue::Double(x) => SpannedValue::Float(x),
+            TypedValue::Instant(x) => SpannedValue::Instant(x),
+            TypedValue::String(x) => SpannedValue::Text((*x).clone()),
+            TypedValue::Uuid(x) => SpannedValue::Uuid(x),
+            TypedValue::Keyword(x) => SpannedValue::Keyword((*x).clone()),
+            TypedValue::BigInteger(x) => SpannedValue::BigInteger(x),
+            TypedValue::Bytes(x) => SpannedValue::Bytes((*x).clone()),
+        };
+        ValueAndSpan::new(inner, None)
+    }
+
+    fn into_edn_value(self) -> edn::Value {
+        self.without_spans()
+    }
 }
 
 impl TransactableValue for TypedValue {
@@ -130,7 +176,9 @@ impl TransactableValue for TypedValue {
             TypedValue::Long(_) |
             TypedValue::Double(_) |
             TypedValue::Instant(_) |
-            TypedValue::Uuid(_) => bail!(DbErrorKind::InputError(errors::InputError::BadEntityPlace)),
+            TypedValue::Uuid(_) |
+            TypedValue::BigInteger(_) |
+            TypedValue::Bytes(_) => bail!(DbErrorKind::InputError(errors::InputError::BadEntityPlace)),
         }
     }
 
@@ -140,6 +188,18 @@ impl TransactableValue for TypedValue {
             _ => None,
         }
     }
+
+    fn value_type_hint(&self) -> Option<ValueType> {
+        Some(self.value_type())
+    }
+
+    fn from_typed_value(value: TypedValue) -> Self {
+        value
+    }
+
+    fn into_edn_value(self) -> edn::Value {
+        self.to_edn_value_pair().0
+    }
 }
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
@@ -208,8 +268,7 @@ pub fn replace_lookup_ref<T, U>(lookup_map: &AVMap, desired_or: Either<T, Lookup
                 LookupRefOrTempId::TempId(t) => Ok(Right(t)),
                 LookupRefOrTempId::LookupRef(av) => lookup_map.get(&*av)
                     .map(|x| lift(*x)).map(Left)
-                    // XXX TODO: fix this error kind!
-                    .ok_or_else(|| DbErrorKind::UnrecognizedIdent(format!("couldn't lookup [a v]: {:?}", (*av).clone())).into()),
+                    .ok_or_else(|| DbErrorKind::UnresolvedLookupRef(format!("{:?}", (*av).clone())).into()),
             }
         }
     }
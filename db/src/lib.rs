@@ -18,6 +18,7 @@ extern crate itertools;
 #[macro_use] extern crate serde_derive;
 
 extern crate petgraph;
+extern crate regex;
 extern crate rusqlite;
 extern crate tabwriter;
 extern crate time;
@@ -44,8 +45,11 @@ pub mod cache;
 pub mod db;
 mod bootstrap;
 pub mod entids;
+pub mod excision;
 pub mod internal_types;    // pub because we need them for building entities programmatically.
+pub mod maintenance;
 mod metadata;
+pub mod retention;
 mod schema;
 pub mod tx_observer;
 mod watcher;
@@ -80,13 +84,28 @@ pub use entids::{
 };
 
 pub use db::{
+    RedundantAssertions,
+    StoreGeneration,
     TypedSQLValue,
+    bump_generation,
+    create_partition,
+    has_large_values,
+    large_value_id,
+    logged_value_sql,
     new_connection,
+    new_connection_with_flags,
+    read_attribute_indexes,
+    read_db,
+    read_generation,
+    read_statistics,
+    read_typed_value,
+    resolved_value_sql,
 };
 
 #[cfg(feature = "sqlcipher")]
 pub use db::{
     new_connection_with_key,
+    new_connection_with_key_and_flags,
     change_encryption_key,
 };
 
@@ -99,10 +118,16 @@ pub use tx::{
     transact_terms,
 };
 
+pub use tx_checking::{
+    PROVISIONAL_ATTRIBUTE_DOC,
+    provisional_attributes,
+};
+
 pub use tx_observer::{
     InProgressObserverTransactWatcher,
     TxObservationService,
     TxObserver,
+    TxSnapshot,
 };
 
 pub use types::{
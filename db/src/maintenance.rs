@@ -0,0 +1,183 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Keeping a long-lived store in shape: reporting how large it is and where its space goes, and
+//! the SQLite housekeeping that reclaims free space and keeps the query planner well informed.
+//!
+//! Rewriting the whole database file is `excision::vacuum`.
+
+use std::collections::BTreeMap;
+
+use rusqlite;
+
+use core_traits::{
+    Entid,
+};
+
+use db_traits::errors::{
+    Result,
+};
+
+/// What a store holds, and how much of its database file is in use.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MaintenanceReport {
+    /// The number of datoms each attribute has.
+    pub datoms: BTreeMap<Entid, u64>,
+
+    /// The number of distinct fulltext values.
+    pub fulltext_values: u64,
+
+    /// The size of the text of those values, in bytes.
+    pub fulltext_text_bytes: u64,
+
+    /// The size of the fulltext index over them, in bytes.
+    pub fulltext_index_bytes: u64,
+
+    pub page_size: u64,
+
+    /// The number of pages in the database file, including free ones.
+    pub page_count: u64,
+
+    /// The number of pages that are free: space that deletions have left behind, that SQLite
+    /// will reuse, but that the file doesn't shrink by until it's vacuumed.
+    pub free_page_count: u64,
+}
+
+impl MaintenanceReport {
+    pub fn total_datoms(&self) -> u64 {
+        self.datoms.values().sum()
+    }
+
+    pub fn database_bytes(&self) -> u64 {
+        self.page_count * self.page_size
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.free_page_count * self.page_size
+    }
+
+    /// The fraction of the database file that is free pages, from 0 just after a vacuum to
+    /// nearly 1 after deleting almost everything.
+    pub fn fragmentation(&self) -> f64 {
+        if self.page_count == 0 {
+            0.0
+        } else {
+            self.free_page_count as f64 / self.page_count as f64
+        }
+    }
+}
+
+fn pragma(conn: &rusqlite::Connection, name: &str) -> Result<u64> {
+    let value: i64 = conn.query_row(&format!("PRAGMA {}", name), &[], |row| row.get(0))?;
+    Ok(value as u64)
+}
+
+/// Collect a `MaintenanceReport`.  Counting datoms reads all of the AEVT index, and sizing the
+/// fulltext values reads all of them, so this takes time in proportion to the store.
+pub fn read_maintenance_report(conn: &rusqlite::Connection) -> Result<MaintenanceReport> {
+    let mut stmt = conn.prepare("SELECT a, count(*) FROM datoms INDEXED BY idx_datoms_aevt GROUP BY a")?;
+    let datoms: Result<BTreeMap<Entid, u64>> = stmt.query_and_then(&[], |row| -> Result<(Entid, u64)> {
+        let count: i64 = row.get_checked(1)?;
+        Ok((row.get_checked(0)?, count as u64))
+    })?.collect();
+
+    let (fulltext_values, fulltext_text_bytes): (i64, i64) =
+        conn.query_row("SELECT count(*), coalesce(sum(length(CAST(text AS BLOB))), 0) FROM fulltext_values",
+                       &[], |row| (row.get(0), row.get(1)))?;
+    // FTS4 keeps the roots of its b-trees in `_segdir`, and the rest in `_segments`.
+    let fulltext_index_bytes: i64 =
+        conn.query_row("SELECT (SELECT coalesce(sum(length(block)), 0) FROM fulltext_values_segments) + \
+                               (SELECT coalesce(sum(length(root)), 0) FROM fulltext_values_segdir)",
+                       &[], |row| row.get(0))?;
+
+    Ok(MaintenanceReport {
+        datoms: datoms?,
+        fulltext_values: fulltext_values as u64,
+        fulltext_text_bytes: fulltext_text_bytes as u64,
+        fulltext_index_bytes: fulltext_index_bytes as u64,
+        page_size: pragma(conn, "page_size")?,
+        page_count: pragma(conn, "page_count")?,
+        free_page_count: pragma(conn, "freelist_count")?,
+    })
+}
+
+/// Return up to `pages` free pages -- all of them, if `None` -- to the file system, shrinking the
+/// database file.  Unlike `vacuum`, this is quick, and doesn't defragment what remains.
+///
+/// New stores allow this.  Older ones don't until they've been vacuumed once; until then, this
+/// does nothing.
+pub fn incremental_vacuum(conn: &rusqlite::Connection, pages: Option<u32>) -> Result<()> {
+    match pages {
+        Some(pages) => conn.execute_batch(&format!("PRAGMA incremental_vacuum({});", pages))?,
+        None => conn.execute_batch("PRAGMA incremental_vacuum;")?,
+    }
+    Ok(())
+}
+
+/// Have SQLite gather statistics about the tables and their indexes, with which its query
+/// planner chooses between indexes.  `EXPLAIN QUERY PLAN` row estimates use these, too.
+pub fn analyze(conn: &rusqlite::Connection) -> Result<()> {
+    conn.execute_batch("ANALYZE;")?;
+    Ok(())
+}
+
+/// Merge the fulltext index's segments into one, which makes fulltext searches faster.  Each
+/// transaction that adds fulltext values adds segments, which SQLite merges only now and then.
+pub fn optimize_fulltext(conn: &rusqlite::Connection) -> Result<()> {
+    conn.execute("INSERT INTO fulltext_values (fulltext_values) VALUES ('optimize')", &[])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edn::{
+        Keyword,
+    };
+
+    use mentat_core::{
+        HasSchema,
+    };
+
+    use debug::{
+        TestConn,
+    };
+
+    #[test]
+    fn test_maintenance_report() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, r#"[
+            {:db/ident :test/text :db/valueType :db.type/string :db/cardinality :db.cardinality/many :db/index true :db/fulltext true}
+        ]"#);
+
+        let before = read_maintenance_report(&conn.sqlite).expect("report");
+        assert_eq!(before.fulltext_values, 0);
+        assert!(before.total_datoms() > 0);
+        assert!(before.page_count > 0);
+
+        let text = conn.schema.get_entid(&Keyword::namespaced("test", "text")).expect("text").0;
+        assert_transact!(conn, r#"[[:db/add "e" :test/text "héllo"] [:db/add "e" :test/text "world"]]"#);
+        let report = read_maintenance_report(&conn.sqlite).expect("report");
+        assert_eq!(report.datoms.get(&text), Some(&2));
+        // And the transaction's :db/txInstant.
+        assert_eq!(report.total_datoms(), before.total_datoms() + 3);
+        assert_eq!(report.fulltext_values, 2);
+        assert_eq!(report.fulltext_text_bytes, 11);
+        assert!(report.fulltext_index_bytes > 0);
+        assert!(report.fragmentation() < 1.0);
+
+        optimize_fulltext(&conn.sqlite).expect("optimized");
+        analyze(&conn.sqlite).expect("analyzed");
+        incremental_vacuum(&conn.sqlite, Some(1)).expect("vacuumed");
+        incremental_vacuum(&conn.sqlite, None).expect("vacuumed");
+        assert_eq!(read_maintenance_report(&conn.sqlite).expect("report").free_page_count, 0);
+    }
+}
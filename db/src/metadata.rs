@@ -54,6 +54,7 @@ use mentat_core::{
 use schema::{
     AttributeBuilder,
     AttributeValidation,
+    validate_unique_with,
 };
 
 use types::{
@@ -76,6 +77,8 @@ pub enum AttributeAlteration {
     NoHistory,
     /// - change whether an attribute is treated as a component
     IsComponent,
+    /// - make an attribute unique together with another
+    UniqueWith,
 }
 
 /// An alteration to an ident.
@@ -225,7 +228,10 @@ pub fn update_attribute_map_from_entid_triples(attribute_map: &mut AttributeMap,
             entids::DB_CARDINALITY |
             entids::DB_INDEX |
             entids::DB_FULLTEXT |
-            entids::DB_NO_HISTORY => {
+            entids::DB_NO_HISTORY |
+            entids::DB_NORMALIZE |
+            entids::DB_CASE_INSENSITIVE |
+            entids::DB_UNIQUE_WITH => {
                 bail!(DbErrorKind::BadSchemaAssertion(format!("Retracting attribute {} for entity {} not permitted.", attr, entid)));
             },
 
@@ -243,7 +249,9 @@ pub fn update_attribute_map_from_entid_triples(attribute_map: &mut AttributeMap,
         match attr {
             entids::DB_VALUE_TYPE => {
                 match *value {
+                    TypedValue::Ref(entids::DB_TYPE_BIGINT)  => { builder.value_type(ValueType::BigInteger); },
                     TypedValue::Ref(entids::DB_TYPE_BOOLEAN) => { builder.value_type(ValueType::Boolean); },
+                    TypedValue::Ref(entids::DB_TYPE_BYTES)   => { builder.value_type(ValueType::Bytes); },
                     TypedValue::Ref(entids::DB_TYPE_DOUBLE)  => { builder.value_type(ValueType::Double); },
                     TypedValue::Ref(entids::DB_TYPE_INSTANT) => { builder.value_type(ValueType::Instant); },
                     TypedValue::Ref(entids::DB_TYPE_KEYWORD) => { builder.value_type(ValueType::Keyword); },
@@ -299,6 +307,27 @@ pub fn update_attribute_map_from_entid_triples(attribute_map: &mut AttributeMap,
                 }
             },
 
+            entids::DB_NORMALIZE => {
+                match *value {
+                    TypedValue::Boolean(x) => { builder.normalize(x); },
+                    _ => bail!(DbErrorKind::BadSchemaAssertion(format!("Expected [... :db/normalize true|false] but got [... :db/normalize {:?}]", value)))
+                }
+            },
+
+            entids::DB_CASE_INSENSITIVE => {
+                match *value {
+                    TypedValue::Boolean(x) => { builder.case_insensitive(x); },
+                    _ => bail!(DbErrorKind::BadSchemaAssertion(format!("Expected [... :db/caseInsensitive true|false] but got [... :db/caseInsensitive {:?}]", value)))
+                }
+            },
+
+            entids::DB_UNIQUE_WITH => {
+                match *value {
+                    TypedValue::Ref(partner) => { builder.unique_with(partner); },
+                    _ => bail!(DbErrorKind::BadSchemaAssertion(format!("Expected [... :db/uniqueWith :attribute] but got [... :db/uniqueWith {:?}]", value)))
+                }
+            },
+
             _ => {
                 bail!(DbErrorKind::BadSchemaAssertion(format!("Do not recognize attribute {} for entid {}", attr, entid)))
             }
@@ -324,11 +353,18 @@ pub fn update_attribute_map_from_entid_triples(attribute_map: &mut AttributeMap,
             Entry::Occupied(mut entry) => {
                 builder.validate_alter_attribute().context(DbErrorKind::BadSchemaAssertion(format!("Schema alteration for existing attribute with entid {} is not valid", entid)))?;
                 let mutations = builder.mutate(entry.get_mut());
+                entry.get().validate(|| entid.to_string())?;
                 attributes_altered.insert(entid, mutations);
             },
         }
     }
 
+    // An attribute can be installed together with its :db/uniqueWith partner, so we only look for
+    // the partner once every attribute is in place.
+    for entid in attributes_installed.iter().chain(attributes_altered.keys()) {
+        validate_unique_with(*entid, attribute_map, || entid.to_string())?;
+    }
+
     Ok(MetadataReport {
         attributes_installed: attributes_installed,
         attributes_altered: attributes_altered,
@@ -387,26 +423,32 @@ pub fn update_schema_from_entid_quadruples<U>(schema: &mut Schema, assertions: U
 
     let mut idents_altered: BTreeMap<Entid, IdentAlteration> = BTreeMap::new();
 
-    // Asserted, altered, or retracted :db/idents update the relevant entids.
+    // Asserted, altered, or retracted :db/idents update the relevant entids.  Old idents are
+    // removed before new idents are added, so that an ident can move between entids -- or two
+    // entids can swap idents -- in a single transaction.  An entid keeps its attribute, whatever
+    // it's called.
+    for (entid, ident) in &ident_set.retracted {
+        schema.entid_map.remove(entid);
+        schema.ident_map.remove(ident);
+        idents_altered.insert(*entid, IdentAlteration::Ident(ident.clone()));
+    }
+
+    for &(ref old_ident, _) in ident_set.altered.values() {
+        schema.ident_map.remove(old_ident);
+    }
+
     for (entid, ident) in ident_set.asserted {
         schema.entid_map.insert(entid, ident.clone());
         schema.ident_map.insert(ident.clone(), entid);
         idents_altered.insert(entid, IdentAlteration::Ident(ident.clone()));
     }
 
-    for (entid, (old_ident, new_ident)) in ident_set.altered {
+    for (entid, (_old_ident, new_ident)) in ident_set.altered {
         schema.entid_map.insert(entid, new_ident.clone()); // Overwrite existing.
-        schema.ident_map.remove(&old_ident); // Remove old.
-        schema.ident_map.insert(new_ident.clone(), entid); // Insert new.
+        schema.ident_map.insert(new_ident.clone(), entid);
         idents_altered.insert(entid, IdentAlteration::Ident(new_ident.clone()));
     }
 
-    for (entid, ident) in &ident_set.retracted {
-        schema.entid_map.remove(entid);
-        schema.ident_map.remove(ident);
-        idents_altered.insert(*entid, IdentAlteration::Ident(ident.clone()));
-    }
-
     // Component attributes need to change if either:
     // - a component attribute changed
     // - a schema attribute that was a component was retracted
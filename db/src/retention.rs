@@ -0,0 +1,224 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Retention: trimming the values of history-heavy attributes, like a page's visits, to the most
+//! recent ones.
+//!
+//! A `RetentionPolicy` says which of an attribute's current values to keep.  The others are
+//! trimmed, either by retracting them, which leaves them in the transaction log, or by excising
+//! them, which removes them and their history altogether.
+
+use rusqlite;
+
+use edn::{
+    DateTime,
+    Utc,
+};
+
+use core_traits::{
+    Entid,
+    TypedValue,
+};
+
+use mentat_core::{
+    Schema,
+    ToMicros,
+};
+
+use db::{
+    TypedSQLValue,
+    resolved_value_sql,
+};
+
+use db_traits::errors::{
+    Result,
+};
+
+use entids;
+
+use excision::{
+    excise_rows,
+};
+
+use types::{
+    PartitionMap,
+};
+
+/// Which of an attribute's values to keep.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Keep {
+    /// The `n` most recently asserted values of each entity.
+    Latest(u32),
+
+    /// The values asserted in the last `n` days, by the `:db/txInstant` of the asserting
+    /// transaction.
+    Days(u32),
+}
+
+/// How to trim an attribute's values.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RetentionPolicy {
+    pub keep: Keep,
+
+    /// Excise trimmed values rather than retract them.
+    pub excise: bool,
+}
+
+impl RetentionPolicy {
+    pub fn keep_latest(n: u32) -> RetentionPolicy {
+        RetentionPolicy {
+            keep: Keep::Latest(n),
+            excise: false,
+        }
+    }
+
+    pub fn keep_days(n: u32) -> RetentionPolicy {
+        RetentionPolicy {
+            keep: Keep::Days(n),
+            excise: false,
+        }
+    }
+
+    pub fn excising(self) -> RetentionPolicy {
+        RetentionPolicy {
+            excise: true,
+            ..self
+        }
+    }
+}
+
+/// The SQL conditions that select the datoms of `attribute` that `keep` trims, as of `now`, from
+/// the `datoms` table aliased as `t`.
+fn trimmed_rows(attribute: Entid, keep: Keep, now: DateTime<Utc>) -> String {
+    match keep {
+        Keep::Latest(n) => format!(
+            "t.a = {} AND (SELECT count(*) FROM datoms AS n \
+                           WHERE n.e = t.e AND n.a = t.a AND (n.tx > t.tx OR (n.tx = t.tx AND n.rowid > t.rowid))) >= {}",
+            attribute, n),
+        Keep::Days(n) => {
+            let cutoff = now.to_micros() - (n as i64) * 24 * 60 * 60 * 1_000_000;
+            format!("t.a = {} AND EXISTS (SELECT 1 FROM datoms AS i WHERE i.e = t.tx AND i.a = {} AND i.v < {})",
+                    attribute, entids::DB_TX_INSTANT, cutoff)
+        },
+    }
+}
+
+/// Collect the datoms selected by `rows` into `temp.trimmed_datoms`, and return them.
+fn collect_trimmed(conn: &rusqlite::Connection, rows: &str) -> Result<Vec<(Entid, Entid, TypedValue)>> {
+    conn.execute("DROP TABLE IF EXISTS temp.trimmed_datoms", &[])?;
+    conn.execute(&format!("CREATE TABLE temp.trimmed_datoms AS \
+                           SELECT t.e, t.a, t.v, t.value_type_tag, t.index_fulltext FROM datoms AS t WHERE {}", rows), &[])?;
+
+    // Fulltext values are stored as ids, like offloaded values, but in their own table.
+    let mut stmt = conn.prepare(&format!(
+        "SELECT r.e, r.a, \
+         CASE WHEN r.index_fulltext IS NOT 0 THEN (SELECT text FROM fulltext_values WHERE rowid = r.v) ELSE {} END, \
+         r.value_type_tag FROM temp.trimmed_datoms AS r",
+        resolved_value_sql("r")))?;
+    let mut datoms = stmt.query_and_then(&[], |row| -> Result<(Entid, Entid, TypedValue)> {
+        let value_type_tag: i32 = row.get_checked(3)?;
+        let v = TypedValue::from_sql_value_pair(row.get_checked(2)?, value_type_tag)?;
+        Ok((row.get_checked(0)?, row.get_checked(1)?, v))
+    })?;
+    let mut trimmed = vec![];
+    while let Some(datom) = datoms.next() {
+        trimmed.push(datom?);
+    }
+    Ok(trimmed)
+}
+
+/// The current datoms of `attribute` that `keep` trims as of `now`, for the caller to retract.
+pub fn trimmed_datoms(conn: &rusqlite::Connection, attribute: Entid, keep: Keep, now: DateTime<Utc>) -> Result<Vec<(Entid, Entid, TypedValue)>> {
+    let trimmed = collect_trimmed(conn, &trimmed_rows(attribute, keep, now))?;
+    conn.execute("DROP TABLE temp.trimmed_datoms", &[])?;
+    Ok(trimmed)
+}
+
+/// Excise the current datoms of `attribute` that `keep` trims as of `now`, along with every
+/// record of them in the transaction log.  As with `excision::excise`, only the datoms of
+/// entities in partitions that allow excision, and without idents, are trimmed.  Returns the
+/// excised datoms, which the caller can use to update the cache.
+pub fn excise_trimmed(conn: &rusqlite::Connection,
+                      schema: &Schema,
+                      partition_map: &PartitionMap,
+                      attribute: Entid,
+                      keep: Keep,
+                      now: DateTime<Utc>) -> Result<Vec<(Entid, Entid, TypedValue)>> {
+    let excisable: Vec<String> = partition_map.values()
+                                              .filter(|partition| partition.allow_excision)
+                                              .map(|partition| format!("(t.e >= {} AND t.e < {})", partition.start, partition.end))
+                                              .collect();
+    if excisable.is_empty() {
+        return Ok(vec![]);
+    }
+    let rows = format!("{} AND ({}) AND NOT EXISTS (SELECT 1 FROM datoms AS d WHERE d.e = t.e AND d.a = {})",
+                       trimmed_rows(attribute, keep, now), excisable.join(" OR "), entids::DB_IDENT);
+    let excised = collect_trimmed(conn, &rows)?;
+
+    let trimmed = "EXISTS (SELECT 1 FROM temp.trimmed_datoms AS r \
+                   WHERE r.e = t.e AND r.a = t.a AND r.value_type_tag = t.value_type_tag AND r.v = t.v)";
+    excise_rows(conn, schema, Some(trimmed), trimmed)?;
+    conn.execute("DROP TABLE temp.trimmed_datoms", &[])?;
+    Ok(excised)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edn::{
+        Keyword,
+    };
+
+    use mentat_core::{
+        HasSchema,
+    };
+
+    use debug::{
+        TestConn,
+    };
+
+    fn count(conn: &rusqlite::Connection, sql: &str) -> i64 {
+        conn.query_row(sql, &[], |row| row.get(0)).expect("counted")
+    }
+
+    #[test]
+    fn test_trim() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, r#"[
+            {:db/ident :test/visit :db/valueType :db.type/long :db/cardinality :db.cardinality/many}
+        ]"#);
+        let report = assert_transact!(conn, r#"[[:db/add (transaction-tx) :db/txInstant #inst "2018-01-01T00:00:00.000Z"]
+                                                 [:db/add "p" :test/visit 1]]"#);
+        let page = report.tempids["p"];
+        assert_transact!(conn, format!(r#"[[:db/add (transaction-tx) :db/txInstant #inst "2018-01-10T00:00:00.000Z"]
+                                           [:db/add {} :test/visit 2]]"#, page));
+        assert_transact!(conn, format!(r#"[[:db/add (transaction-tx) :db/txInstant #inst "2018-01-20T00:00:00.000Z"]
+                                           [:db/add {} :test/visit 3]]"#, page));
+
+        let visit = conn.schema.get_entid(&Keyword::namespaced("test", "visit")).expect("visit").0;
+        let now = "2018-01-21T00:00:00.000Z".parse::<DateTime<Utc>>().expect("instant");
+
+        // The most recent values.
+        assert_eq!(trimmed_datoms(&conn.sqlite, visit, Keep::Latest(2), now).expect("trimmed"),
+                   vec![(page, visit, TypedValue::Long(1))]);
+        assert_eq!(trimmed_datoms(&conn.sqlite, visit, Keep::Latest(3), now).expect("trimmed"), vec![]);
+
+        // The values of recent transactions.
+        assert_eq!(trimmed_datoms(&conn.sqlite, visit, Keep::Days(5), now).expect("trimmed"),
+                   vec![(page, visit, TypedValue::Long(1)), (page, visit, TypedValue::Long(2))]);
+
+        // Excising leaves no trace of trimmed values.
+        let excised = excise_trimmed(&conn.sqlite, &conn.schema, &conn.partition_map, visit, Keep::Latest(1), now).expect("excised");
+        assert_eq!(excised, vec![(page, visit, TypedValue::Long(1)), (page, visit, TypedValue::Long(2))]);
+        assert_eq!(count(&conn.sqlite, &format!("SELECT count(*) FROM datoms WHERE e = {}", page)), 1);
+        assert_eq!(count(&conn.sqlite, &format!("SELECT count(*) FROM transactions WHERE e = {}", page)), 1);
+    }
+}
@@ -57,6 +57,24 @@ impl AttributeValidation for Attribute {
         if self.fulltext && !self.index {
             bail!(DbErrorKind::BadSchemaAssertion(format!(":db/fulltext true without :db/index true for entid: {}", ident())))
         }
+        if self.normalize && self.value_type != ValueType::String {
+            bail!(DbErrorKind::BadSchemaAssertion(format!(":db/normalize true without :db/valueType :db.type/string for entid: {}", ident())))
+        }
+        if self.case_insensitive && self.value_type != ValueType::String {
+            bail!(DbErrorKind::BadSchemaAssertion(format!(":db/caseInsensitive true without :db/valueType :db.type/string for entid: {}", ident())))
+        }
+        if self.case_insensitive && self.unique.is_none() {
+            bail!(DbErrorKind::BadSchemaAssertion(format!(":db/caseInsensitive true without :db/unique for entid: {}", ident())))
+        }
+        if self.case_insensitive && self.fulltext {
+            bail!(DbErrorKind::BadSchemaAssertion(format!(":db/caseInsensitive true with :db/fulltext true for entid: {}", ident())))
+        }
+        if self.unique_with.is_some() && !self.index {
+            bail!(DbErrorKind::BadSchemaAssertion(format!(":db/uniqueWith without :db/index true for entid: {}", ident())))
+        }
+        if self.unique_with.is_some() && self.fulltext {
+            bail!(DbErrorKind::BadSchemaAssertion(format!(":db/uniqueWith with :db/fulltext true for entid: {}", ident())))
+        }
         if self.component && self.value_type != ValueType::Ref {
             bail!(DbErrorKind::BadSchemaAssertion(format!(":db/isComponent true without :db/valueType :db.type/ref for entid: {}", ident())))
         }
@@ -68,11 +86,23 @@ impl AttributeValidation for Attribute {
     }
 }
 
+/// Return `Ok(())` if the attribute `entid` in `attribute_map` is unique with nothing, or with
+/// another attribute in `attribute_map`.
+pub fn validate_unique_with<F>(entid: Entid, attribute_map: &AttributeMap, ident: F) -> Result<()> where F: Fn() -> String {
+    if let Some(partner) = attribute_map.get(&entid).and_then(|attribute| attribute.unique_with) {
+        if partner == entid || !attribute_map.contains_key(&partner) {
+            bail!(DbErrorKind::BadSchemaAssertion(format!(":db/uniqueWith {} is not another attribute for entid: {}", partner, ident())))
+        }
+    }
+    Ok(())
+}
+
 /// Return `Ok(())` if `attribute_map` defines a valid Mentat schema.
 fn validate_attribute_map(entid_map: &EntidMap, attribute_map: &AttributeMap) -> Result<()> {
     for (entid, attribute) in attribute_map {
         let ident = || entid_map.get(entid).map(|ident| ident.to_string()).unwrap_or(entid.to_string());
-        attribute.validate(ident)?;
+        attribute.validate(&ident)?;
+        validate_unique_with(*entid, attribute_map, &ident)?;
     }
     Ok(())
 }
@@ -87,6 +117,9 @@ pub struct AttributeBuilder {
     pub fulltext: Option<bool>,
     pub component: Option<bool>,
     pub no_history: Option<bool>,
+    pub normalize: Option<bool>,
+    pub case_insensitive: Option<bool>,
+    pub unique_with: Option<Entid>,
 }
 
 impl AttributeBuilder {
@@ -155,6 +188,21 @@ impl AttributeBuilder {
         self
     }
 
+    pub fn normalize<'a>(&'a mut self, normalize: bool) -> &'a mut Self {
+        self.normalize = Some(normalize);
+        self
+    }
+
+    pub fn case_insensitive<'a>(&'a mut self, case_insensitive: bool) -> &'a mut Self {
+        self.case_insensitive = Some(case_insensitive);
+        self
+    }
+
+    pub fn unique_with<'a>(&'a mut self, partner: Entid) -> &'a mut Self {
+        self.unique_with = Some(partner);
+        self
+    }
+
     pub fn validate_install_attribute(&self) -> Result<()> {
         if self.value_type.is_none() {
             bail!(DbErrorKind::BadSchemaAssertion("Schema attribute for new attribute does not set :db/valueType".into()));
@@ -169,6 +217,12 @@ impl AttributeBuilder {
         if self.fulltext.is_some() {
             bail!(DbErrorKind::BadSchemaAssertion("Schema alteration must not set :db/fulltext".into()));
         }
+        if self.normalize.is_some() {
+            bail!(DbErrorKind::BadSchemaAssertion("Schema alteration must not set :db/normalize".into()));
+        }
+        if self.case_insensitive.is_some() {
+            bail!(DbErrorKind::BadSchemaAssertion("Schema alteration must not set :db/caseInsensitive".into()));
+        }
         Ok(())
     }
 
@@ -195,6 +249,15 @@ impl AttributeBuilder {
         if let Some(no_history) = self.no_history {
             attribute.no_history = no_history;
         }
+        if let Some(normalize) = self.normalize {
+            attribute.normalize = normalize;
+        }
+        if let Some(case_insensitive) = self.case_insensitive {
+            attribute.case_insensitive = case_insensitive;
+        }
+        if let Some(unique_with) = self.unique_with {
+            attribute.unique_with = Some(unique_with);
+        }
 
         attribute
     }
@@ -238,6 +301,12 @@ impl AttributeBuilder {
                 mutations.push(AttributeAlteration::NoHistory);
             }
         }
+        if let Some(unique_with) = self.unique_with {
+            if Some(unique_with) != attribute.unique_with {
+                attribute.unique_with = Some(unique_with);
+                mutations.push(AttributeAlteration::UniqueWith);
+            }
+        }
 
         mutations
     }
@@ -322,6 +391,10 @@ impl SchemaTypeChecking for Schema {
                 (ValueType::Uuid, tv @ TypedValue::Uuid(_)) => Ok(tv),
                 (ValueType::Instant, tv @ TypedValue::Instant(_)) => Ok(tv),
                 (ValueType::Keyword, tv @ TypedValue::Keyword(_)) => Ok(tv),
+                (ValueType::BigInteger, tv @ TypedValue::BigInteger(_)) => Ok(tv),
+                (ValueType::Bytes, tv @ TypedValue::Bytes(_)) => Ok(tv),
+                // Any long is also a big integer.
+                (ValueType::BigInteger, TypedValue::Long(x)) => Ok(TypedValue::BigInteger(x.into())),
                 // Ref coerces a little: we interpret some things depending on the schema as a Ref.
                 (ValueType::Ref, TypedValue::Long(x)) => Ok(TypedValue::Ref(x)),
                 (ValueType::Ref, TypedValue::Keyword(ref x)) => self.require_entid(&x).map(|entid| entid.into()),
@@ -337,6 +410,8 @@ impl SchemaTypeChecking for Schema {
                 (vt @ ValueType::Uuid, _) |
                 (vt @ ValueType::Instant, _) |
                 (vt @ ValueType::Keyword, _) |
+                (vt @ ValueType::BigInteger, _) |
+                (vt @ ValueType::Bytes, _) |
                 (vt @ ValueType::Ref, _)
                 => bail!(DbErrorKind::BadValuePair(format!("{}", value), vt)),
             }
@@ -378,6 +453,9 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         });
         // attribute is unique by value and an index
         add_attribute(&mut schema, Keyword::namespaced("foo", "baz"), 98, Attribute {
@@ -388,6 +466,9 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         });
         // attribue is unique by identity and an index
         add_attribute(&mut schema, Keyword::namespaced("foo", "bat"), 99, Attribute {
@@ -398,6 +479,9 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         });
         // attribute is a components and a `Ref`
         add_attribute(&mut schema, Keyword::namespaced("foo", "bak"), 100, Attribute {
@@ -408,6 +492,9 @@ mod test {
             multival: false,
             component: true,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         });
         // fulltext attribute is a string and an index
         add_attribute(&mut schema, Keyword::namespaced("foo", "bap"), 101, Attribute {
@@ -418,6 +505,9 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         });
 
         assert!(validate_attribute_map(&schema.entid_map, &schema.attribute_map).is_ok());
@@ -436,6 +526,9 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         });
 
         let err = validate_attribute_map(&schema.entid_map, &schema.attribute_map).err().map(|e| e.kind());
@@ -454,6 +547,9 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         });
 
         let err = validate_attribute_map(&schema.entid_map, &schema.attribute_map).err().map(|e| e.kind());
@@ -472,6 +568,9 @@ mod test {
             multival: false,
             component: true,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         });
 
         let err = validate_attribute_map(&schema.entid_map, &schema.attribute_map).err().map(|e| e.kind());
@@ -490,6 +589,9 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         });
 
         let err = validate_attribute_map(&schema.entid_map, &schema.attribute_map).err().map(|e| e.kind());
@@ -507,6 +609,9 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            normalize: false,
+            case_insensitive: false,
+            unique_with: None,
         });
 
         let err = validate_attribute_map(&schema.entid_map, &schema.attribute_map).err().map(|e| e.kind());
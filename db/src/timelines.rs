@@ -35,6 +35,7 @@ use edn::entities::OpType;
 
 use db;
 use db::{
+    RedundantAssertions,
     TypedSQLValue,
 };
 
@@ -113,7 +114,8 @@ fn is_timeline_empty(conn: &rusqlite::Connection, timeline: Entid) -> Result<boo
 
 /// Get terms for tx_id, reversing them in meaning (swap add & retract).
 fn reversed_terms_for(conn: &rusqlite::Connection, tx_id: Entid) -> Result<Vec<TermWithoutTempIds>> {
-    let mut stmt = conn.prepare("SELECT e, a, v, value_type_tag, tx, added FROM timelined_transactions WHERE tx = ? AND timeline = ? ORDER BY tx DESC")?;
+    let mut stmt = conn.prepare(&format!("SELECT e, a, {}, value_type_tag, tx, added FROM timelined_transactions AS t WHERE tx = ? AND timeline = ? ORDER BY tx DESC",
+                                         db::logged_value_sql("t")))?;
     let mut rows = stmt.query_and_then(&[&tx_id, &::TIMELINE_MAIN], |row| -> Result<TermWithoutTempIds> {
         let op = match row.get_checked(5)? {
             true => OpType::Retract,
@@ -158,7 +160,7 @@ pub fn move_from_main_timeline(conn: &rusqlite::Connection, schema: &Schema,
 
         // Rewind schema and datoms.
         let (report, _, new_schema, _) = transact_terms_with_action(
-            conn, partition_map.clone(), schema, schema, NullWatcher(),
+            conn, partition_map.clone(), schema, schema, NullWatcher(), RedundantAssertions::Skip, None,
             reversed_terms.into_iter().map(|t| t.rewrap()),
             InternSet::new(), TransactorAction::Materialize
         )?;
@@ -87,6 +87,9 @@ use internal_types::{
     replace_lookup_ref,
 };
 
+use mentat_core::normalization::{
+    normalize_value,
+};
 use mentat_core::util::Either;
 
 use core_traits::{
@@ -101,6 +104,7 @@ use core_traits::{
 
 use mentat_core::{
     DateTime,
+    HasSchema,
     Schema,
     TxReport,
     Utc,
@@ -175,6 +179,12 @@ pub struct Tx<'conn, 'a, W> where W: TransactWatcher {
 
     /// The transaction ID of the transaction.
     tx_id: Entid,
+
+    /// Whether to record assertions of datoms that are already present.
+    redundant_assertions: db::RedundantAssertions,
+
+    /// The length above which string values are offloaded to `large_values`, if any.
+    large_value_threshold: Option<usize>,
 }
 
 /// Remove any :db/id value from the given map notation, converting the returned value into
@@ -190,7 +200,12 @@ pub fn remove_db_id<V: TransactableValue>(map: &mut entmod::MapNotation<V>) -> R
             entmod::ValuePlace::TempId(e) => Some(entmod::EntityPlace::TempId(e)),
             entmod::ValuePlace::TxFunction(e) => Some(entmod::EntityPlace::TxFunction(e)),
             entmod::ValuePlace::Atom(v) => Some(v.into_entity_place()?),
-            entmod::ValuePlace::Vector(_) |
+            entmod::ValuePlace::Vector(vs) => {
+                match vector_into_lookup_ref(&vs[..]) {
+                    Some(lookup_ref) => Some(entmod::EntityPlace::LookupRef(lookup_ref)),
+                    None => bail!(DbErrorKind::InputError(errors::InputError::BadDbId)),
+                }
+            },
             entmod::ValuePlace::MapNotation(_) => {
                 bail!(DbErrorKind::InputError(errors::InputError::BadDbId))
             },
@@ -202,6 +217,42 @@ pub fn remove_db_id<V: TransactableValue>(map: &mut entmod::MapNotation<V>) -> R
     Ok(db_id)
 }
 
+/// Interpret a vector like `[:foo/email "x@y.com"]` -- an attribute ident followed by a single
+/// value -- as a lookup ref.  Whether it is one or a vector of values depends on where it appears.
+fn vector_into_lookup_ref<V: TransactableValue>(vs: &[entmod::ValuePlace<V>]) -> Option<entmod::LookupRef<V>> {
+    if vs.len() != 2 {
+        return None;
+    }
+    let a = match vs[0] {
+        entmod::ValuePlace::Entid(entmod::EntidOrIdent::Ident(ref a)) => a.clone(),
+        entmod::ValuePlace::Atom(ref a) => match a.clone().into_entity_place() {
+            Ok(entmod::EntityPlace::Entid(entmod::EntidOrIdent::Ident(a))) => a,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    if !a.is_forward() {
+        return None;
+    }
+    match vs[1] {
+        entmod::ValuePlace::Atom(ref v) => Some(entmod::LookupRef { a: AttributePlace::Entid(entmod::EntidOrIdent::Ident(a)), v: v.clone() }),
+        _ => None,
+    }
+}
+
+/// In the value place of a ref attribute, a vector is a lookup ref only if its attribute is
+/// `:db/unique`; otherwise it's a vector of values, like `[:foo/a :foo/b]`.
+fn is_unique_lookup_ref<V>(schema: &Schema, lookup_ref: &entmod::LookupRef<V>) -> bool {
+    match lookup_ref.a {
+        AttributePlace::Entid(entmod::EntidOrIdent::Ident(ref a)) => {
+            schema.attribute_for_ident(a).map_or(false, |(attribute, _)| attribute.unique.is_some())
+        },
+        AttributePlace::Entid(entmod::EntidOrIdent::Entid(a)) => {
+            schema.attribute_for_entid(a).map_or(false, |attribute| attribute.unique.is_some())
+        },
+    }
+}
+
 impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
     pub fn new(
         store: &'conn rusqlite::Connection,
@@ -217,15 +268,29 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             schema: schema,
             watcher: watcher,
             tx_id: tx_id,
+            redundant_assertions: db::RedundantAssertions::default(),
+            large_value_threshold: None,
         }
     }
 
+    /// Choose whether to record assertions of datoms that are already present.
+    pub fn redundant_assertions(&mut self, redundant_assertions: db::RedundantAssertions) {
+        self.redundant_assertions = redundant_assertions;
+    }
+
+    /// Choose the length above which string values are stored out of line, in `large_values`, rather
+    /// than in `datoms`.  By default no values are offloaded.
+    pub fn large_value_threshold(&mut self, large_value_threshold: Option<usize>) {
+        self.large_value_threshold = large_value_threshold;
+    }
+
     /// Given a collection of tempids and the [a v] pairs that they might upsert to, resolve exactly
     /// which [a v] pairs do upsert to entids, and map each tempid that upserts to the upserted
-    /// entid.  The keys of the resulting map are exactly those tempids that upserted.
-    pub(crate) fn resolve_temp_id_avs<'b>(&self, temp_id_avs: &'b [(TempIdHandle, AVPair)]) -> Result<TempIdMap> {
+    /// entid.  The keys of the resulting map are exactly those tempids that upserted.  Also returns
+    /// the set of `[a v]` pairs that were found in the store.
+    pub(crate) fn resolve_temp_id_avs<'b>(&self, temp_id_avs: &'b [(TempIdHandle, AVPair)]) -> Result<(TempIdMap, BTreeSet<AVPair>)> {
         if temp_id_avs.is_empty() {
-            return Ok(TempIdMap::default());
+            return Ok((TempIdMap::default(), BTreeSet::default()));
         }
 
         // Map [a v]->entid.
@@ -242,12 +307,16 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         // Map id->entid.
         let mut tempids: TempIdMap = TempIdMap::default();
 
+        // The [a v] pairs that upserted.
+        let mut upserted_avs: BTreeSet<AVPair> = BTreeSet::default();
+
         // Errors.  BTree* since we want deterministic results.
         let mut conflicting_upserts: BTreeMap<TempId, BTreeSet<KnownEntid>> = BTreeMap::default();
 
         for &(ref tempid, ref av_pair) in temp_id_avs {
             trace!("tempid {:?} av_pair {:?} -> {:?}", tempid, av_pair, av_map.get(&av_pair));
             if let Some(entid) = av_map.get(&av_pair).cloned().map(KnownEntid) {
+                upserted_avs.insert(av_pair.clone());
                 tempids.insert(tempid.clone(), entid).map(|previous| {
                     if entid != previous {
                         conflicting_upserts.entry((**tempid).clone()).or_insert_with(|| once(previous).collect::<BTreeSet<_>>()).insert(entid);
@@ -260,7 +329,7 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             bail!(DbErrorKind::SchemaConstraintViolation(errors::SchemaConstraintViolation::ConflictingUpserts { conflicting_upserts }));
         }
 
-        Ok(tempids)
+        Ok((tempids, upserted_avs))
     }
 
     /// Pipeline stage 1: convert `Entity` instances into `Term` instances, ready for term
@@ -310,8 +379,9 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                 let lr_attribute: &Attribute = self.schema.require_attribute_for_entid(lr_a)?;
 
                 let lr_typed_value: TypedValue = lookup_ref.v.clone().into_typed_value(&self.schema, lr_attribute.value_type)?;
+                let lr_typed_value = normalize_value(lr_attribute, lr_typed_value);
                 if lr_attribute.unique.is_none() {
-                    bail!(DbErrorKind::NotYetImplemented(format!("Cannot resolve (lookup-ref {} {:?}) with attribute that is not :db/unique", lr_a, lr_typed_value)))
+                    bail!(DbErrorKind::NonUniqueLookupRef(lr_a, format!("{:?}", lr_typed_value)))
                 }
 
                 Ok(self.lookup_refs.intern((lr_a, lr_typed_value)))
@@ -351,6 +421,25 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                 }
             }
 
+            /// Like `entity_e_into_term_e`, but the entity must already exist: transaction
+            /// functions like `:db.fn/retractEntity` need to know now which datoms they expand to.
+            fn entity_e_into_existing_entid<W: TransactableValue>(&mut self, store: &rusqlite::Connection, x: entmod::EntityPlace<W>) -> Result<KnownEntid> {
+                match self.entity_e_into_term_e(x)? {
+                    Either::Left(e) => Ok(e),
+                    Either::Right(LookupRefOrTempId::LookupRef(av)) => {
+                        let avs = vec![&*av];
+                        let av_map = store.resolve_avs(&avs[..])?;
+                        match replace_lookup_ref(&av_map, Either::Right(LookupRefOrTempId::LookupRef(av.clone())), KnownEntid)? {
+                            Either::Left(e) => Ok(e),
+                            Either::Right(_) => unreachable!(),
+                        }
+                    },
+                    Either::Right(LookupRefOrTempId::TempId(_)) => {
+                        bail!(DbErrorKind::NotYetImplemented(format!("Cannot retract an entity named by a tempid")));
+                    },
+                }
+            }
+
             fn entity_a_into_term_a(&mut self, x: entmod::EntidOrIdent) -> Result<Entid> {
                 let a = match x {
                     entmod::EntidOrIdent::Entid(ref a) => *a,
@@ -409,8 +498,14 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                                 }
                             },
 
-                            entmod::ValuePlace::Vector(_) =>
-                                bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode vector value in :attr/_reversed notation for attribute {}", forward_a))),
+                            entmod::ValuePlace::Vector(vs) => {
+                                match vector_into_lookup_ref(&vs[..]) {
+                                    Some(ref lookup_ref) =>
+                                        Ok(Either::Right(LookupRefOrTempId::LookupRef(self.intern_lookup_ref(lookup_ref)?))),
+                                    None =>
+                                        bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode vector value in :attr/_reversed notation for attribute {}", forward_a))),
+                                }
+                            },
 
                             entmod::ValuePlace::MapNotation(_) =>
                                 bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode map notation value in :attr/_reversed notation for attribute {}", forward_a))),
@@ -449,6 +544,29 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                     }
                 },
 
+                Entity::RetractEntity { e } => {
+                    let e = in_process.entity_e_into_existing_entid(self.store, e)?;
+                    for (e, a, v) in db::retract_entity_datoms(self.store, self.schema, e.0)? {
+                        terms.push(Term::AddOrRetract(OpType::Retract, Either::Left(KnownEntid(e)), a, Either::Left(v)));
+                    }
+                },
+
+                Entity::RetractAttribute { e, a } => {
+                    let AttributePlace::Entid(a) = a;
+                    let e = in_process.entity_e_into_existing_entid(self.store, e)?;
+                    let a = in_process.entity_a_into_term_a(a)?;
+                    self.schema.require_attribute_for_entid(a)?;
+                    for v in db::retract_attribute_values(self.store, e.0, a)? {
+                        terms.push(Term::AddOrRetract(OpType::Retract, Either::Left(e), a, Either::Left(v)));
+                    }
+                },
+
+                Entity::Call { f, .. } => {
+                    // Calls are expanded by whoever knows the registered transaction functions;
+                    // the transactor never does.
+                    bail!(DbErrorKind::UnknownTransactionFunction(f.to_string()));
+                },
+
                 Entity::AddOrRetract { op, e, a, v } => {
                     let AttributePlace::Entid(a) = a;
 
@@ -511,19 +629,26 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                             },
 
                             entmod::ValuePlace::Vector(vs) => {
-                                if !attribute.multival {
-                                    bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode vector value for attribute {} that is not :db.cardinality :db.cardinality/many", a)));
-                                }
+                                match vector_into_lookup_ref(&vs[..]) {
+                                    Some(ref lookup_ref) if attribute.value_type == ValueType::Ref && is_unique_lookup_ref(self.schema, lookup_ref) => {
+                                        Either::Right(LookupRefOrTempId::LookupRef(in_process.intern_lookup_ref(lookup_ref)?))
+                                    },
+                                    _ => {
+                                        if !attribute.multival {
+                                            bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode vector value for attribute {} that is not :db.cardinality :db.cardinality/many", a)));
+                                        }
 
-                                for vv in vs {
-                                    deque.push_front(Entity::AddOrRetract {
-                                        op: op.clone(),
-                                        e: e.clone(),
-                                        a: AttributePlace::Entid(entmod::EntidOrIdent::Entid(a)),
-                                        v: vv,
-                                    });
+                                        for vv in vs {
+                                            deque.push_front(Entity::AddOrRetract {
+                                                op: op.clone(),
+                                                e: e.clone(),
+                                                a: AttributePlace::Entid(entmod::EntidOrIdent::Entid(a)),
+                                                v: vv,
+                                            });
+                                        }
+                                        continue
+                                    },
                                 }
-                                continue
                             },
 
                             entmod::ValuePlace::MapNotation(mut map_notation) => {
@@ -627,6 +752,14 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
     // TODO: move this to the transactor layer.
     pub fn transact_entities<I, V: TransactableValue>(&mut self, entities: I) -> Result<TxReport>
     where I: IntoIterator<Item=Entity<V>> {
+        // Reject unknown attributes up front, so that the error names every offending entity
+        // rather than whichever unknown ident the pipeline happens to reach first.
+        let entities: Vec<Entity<V>> = entities.into_iter().collect();
+        let unknown_attributes = tx_checking::unknown_attributes(self.schema, &entities[..]);
+        if !unknown_attributes.is_empty() {
+            bail!(DbErrorKind::SchemaConstraintViolation(errors::SchemaConstraintViolation::UnknownAttributes { unknown_attributes }));
+        }
+
         // Pipeline stage 1: entities -> terms with tempids and lookup refs.
         let (terms_with_temp_ids_and_lookup_refs, tempid_set, lookup_ref_set) = self.entities_into_terms_with_temp_ids_and_lookup_refs(entities)?;
 
@@ -646,13 +779,41 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
 
     fn transact_simple_terms_with_action<I>(&mut self, terms: I, tempid_set: InternSet<TempId>, action: TransactorAction) -> Result<TxReport>
     where I: IntoIterator<Item=TermWithTempIds> {
+        // Tempids are reported by name, so one name can't name tempids in different partitions.
+        let mut names: BTreeSet<&str> = BTreeSet::default();
+        for tempid in tempid_set.iter() {
+            let name = match **tempid {
+                TempId::External(ref name) | TempId::Partitioned(_, ref name) => name,
+                TempId::Internal(_) => continue,
+            };
+            if !names.insert(name) {
+                bail!(DbErrorKind::CannotAllocateTempId(name.to_string(), "it names tempids in more than one partition".to_string()));
+            }
+        }
+
         // TODO: push these into an internal transaction report?
         let mut tempids: BTreeMap<TempId, KnownEntid> = BTreeMap::default();
 
+        // String values of `:db/normalize` attributes are stored in NFC.  Do this before upserting
+        // so that canonically equivalent strings resolve to the same entity.
+        let schema = self.schema;
+        let terms = terms.into_iter().map(|Term::AddOrRetract(op, e, a, v)| {
+            let v = match (v, schema.attribute_map.get(&a)) {
+                (Either::Left(v), Some(attribute)) => Either::Left(normalize_value(attribute, v)),
+                (v, _) => v,
+            };
+            Term::AddOrRetract(op, e, a, v)
+        });
+
         // Pipeline stage 3: upsert tempids -> terms without tempids or lookup refs.
         // Now we can collect upsert populations.
         let (mut generation, inert_terms) = Generation::from(terms, &self.schema)?;
 
+        // Every tempid resolved so far, across all generations.  Upserts can chain: resolving one
+        // tempid can turn a complex upsert into a simple upsert, which can then resolve another
+        // tempid in the next generation, and so on.
+        let mut resolved_temp_ids: TempIdMap = TempIdMap::default();
+
         // And evolve them forward.
         while generation.can_evolve() {
             debug!("generation {:?}", generation);
@@ -661,12 +822,10 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             debug!("trying to resolve avs {:?}", tempid_avs);
 
             // Evolve further.
-            let temp_id_map: TempIdMap = self.resolve_temp_id_avs(&tempid_avs[..])?;
+            let (temp_id_map, upserted_avs) = self.resolve_temp_id_avs(&tempid_avs[..])?;
 
             debug!("resolved avs for tempids {:?}", temp_id_map);
 
-            generation = generation.evolve_one_step(&temp_id_map);
-
             // Errors.  BTree* since we want deterministic results.
             let mut conflicting_upserts: BTreeMap<TempId, BTreeSet<KnownEntid>> = BTreeMap::default();
 
@@ -679,12 +838,15 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                         conflicting_upserts.entry((*tempid).clone()).or_insert_with(|| once(previous).collect::<BTreeSet<_>>()).insert(entid);
                     }
                 });
+                resolved_temp_ids.insert(tempid, entid);
             }
 
             if !conflicting_upserts.is_empty() {
                 bail!(DbErrorKind::SchemaConstraintViolation(errors::SchemaConstraintViolation::ConflictingUpserts { conflicting_upserts }));
             }
 
+            generation = generation.evolve_one_step(&resolved_temp_ids, &upserted_avs);
+
             debug!("tempids {:?}", tempids);
         }
 
@@ -697,12 +859,32 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
 
         debug!("unresolved tempids {:?}", unresolved_temp_ids);
 
-        // TODO: track partitions for temporary IDs.
-        let entids = self.partition_map.allocate_entids(":db.part/user", unresolved_temp_ids.len());
+        // Tempids that upsert together share an index, and so an entid, which is allocated in the
+        // partition they name.  Indices are allocated in order, so that "a" allocates before "b".
+        let mut partitions: BTreeMap<usize, (String, &TempIdHandle)> = BTreeMap::default();
+        for (tempid, &index) in unresolved_temp_ids.iter() {
+            let partition = tempid_partition(&self.partition_map, tempid)?;
+            if let Some(&(ref other, _)) = partitions.get(&index) {
+                if *other != partition {
+                    bail!(DbErrorKind::CannotAllocateTempId(tempid.to_string(), format!("it names the same entity as a tempid in {}", other)));
+                }
+                continue;
+            }
+            partitions.insert(index, (partition, tempid));
+        }
+
+        let mut entids: BTreeMap<usize, KnownEntid> = BTreeMap::default();
+        for (index, (partition, tempid)) in partitions {
+            let next = &self.partition_map[&partition];
+            if next.next_entid() >= next.end {
+                bail!(DbErrorKind::CannotAllocateTempId(tempid.to_string(), format!("{} is full", partition)));
+            }
+            entids.insert(index, KnownEntid(self.partition_map.allocate_entid(&partition)));
+        }
 
         let temp_id_allocations = unresolved_temp_ids
             .into_iter()
-            .map(|(tempid, index)| (tempid, KnownEntid(entids.start + (index as i64))))
+            .map(|(tempid, index)| (tempid, entids[&index]))
             .collect();
 
         debug!("tempid allocations {:?}", temp_id_allocations);
@@ -794,11 +976,11 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         }
 
         if !non_fts_one.is_empty() {
-            self.store.insert_non_fts_searches(&non_fts_one[..], db::SearchType::Inexact)?;
+            self.store.insert_non_fts_searches(&non_fts_one[..], db::SearchType::Inexact, self.large_value_threshold)?;
         }
 
         if !non_fts_many.is_empty() {
-            self.store.insert_non_fts_searches(&non_fts_many[..], db::SearchType::Exact)?;
+            self.store.insert_non_fts_searches(&non_fts_many[..], db::SearchType::Exact, self.large_value_threshold)?;
         }
 
         if !fts_one.is_empty() {
@@ -812,10 +994,12 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         match action {
             TransactorAction::Materialize => {
                 self.store.materialize_mentat_transaction(self.tx_id)?;
+                db::ensure_unique_with(self.store, self.schema)?;
             },
             TransactorAction::MaterializeAndCommit => {
                 self.store.materialize_mentat_transaction(self.tx_id)?;
-                self.store.commit_mentat_transaction(self.tx_id)?;
+                db::ensure_unique_with(self.store, self.schema)?;
+                self.store.commit_mentat_transaction(self.tx_id, self.redundant_assertions)?;
             }
         }
 
@@ -851,17 +1035,38 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
     }
 }
 
+/// The partition to allocate `tempid` in: the one it names, which must exist and not be reserved
+/// for transactions, or `:db.part/user`.
+fn tempid_partition(partition_map: &PartitionMap, tempid: &TempId) -> Result<String> {
+    let partition = match tempid.partition() {
+        Some(partition) => partition.to_string(),
+        None => return Ok(":db.part/user".to_string()),
+    };
+    if !partition_map.contains_key(&partition) {
+        bail!(DbErrorKind::CannotAllocateTempId(tempid.to_string(), format!("no such partition {}", partition)));
+    }
+    if partition == ":db.part/tx" {
+        bail!(DbErrorKind::CannotAllocateTempId(tempid.to_string(), format!("{} is reserved for transactions", partition)));
+    }
+    Ok(partition)
+}
+
 /// Initialize a new Tx object with a new tx id and a tx instant. Kick off the SQLite conn, too.
 fn start_tx<'conn, 'a, W>(conn: &'conn rusqlite::Connection,
                        mut partition_map: PartitionMap,
                        schema_for_mutation: &'a Schema,
                        schema: &'a Schema,
-                       watcher: W) -> Result<Tx<'conn, 'a, W>>
+                       watcher: W,
+                       redundant_assertions: db::RedundantAssertions,
+                       large_value_threshold: Option<usize>) -> Result<Tx<'conn, 'a, W>>
     where W: TransactWatcher {
     let tx_id = partition_map.allocate_entid(":db.part/tx");
     conn.begin_tx_application()?;
 
-    Ok(Tx::new(conn, partition_map, schema_for_mutation, schema, watcher, tx_id))
+    let mut tx = Tx::new(conn, partition_map, schema_for_mutation, schema, watcher, tx_id);
+    tx.redundant_assertions(redundant_assertions);
+    tx.large_value_threshold(large_value_threshold);
+    Ok(tx)
 }
 
 fn conclude_tx<W>(tx: Tx<W>, report: TxReport) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
@@ -885,12 +1090,14 @@ pub fn transact<'conn, 'a, I, V, W>(conn: &'conn rusqlite::Connection,
                                  schema_for_mutation: &'a Schema,
                                  schema: &'a Schema,
                                  watcher: W,
+                                 redundant_assertions: db::RedundantAssertions,
+                                 large_value_threshold: Option<usize>,
                                  entities: I) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
     where I: IntoIterator<Item=Entity<V>>,
           V: TransactableValue,
           W: TransactWatcher {
 
-    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher)?;
+    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher, redundant_assertions, large_value_threshold)?;
     let report = tx.transact_entities(entities)?;
     conclude_tx(tx, report)
 }
@@ -901,13 +1108,15 @@ pub fn transact_terms<'conn, 'a, I, W>(conn: &'conn rusqlite::Connection,
                                        schema_for_mutation: &'a Schema,
                                        schema: &'a Schema,
                                        watcher: W,
+                                       redundant_assertions: db::RedundantAssertions,
+                                       large_value_threshold: Option<usize>,
                                        terms: I,
                                        tempid_set: InternSet<TempId>) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
     where I: IntoIterator<Item=TermWithTempIds>,
           W: TransactWatcher {
 
     transact_terms_with_action(
-        conn, partition_map, schema_for_mutation, schema, watcher, terms, tempid_set,
+        conn, partition_map, schema_for_mutation, schema, watcher, redundant_assertions, large_value_threshold, terms, tempid_set,
         TransactorAction::MaterializeAndCommit
     )
 }
@@ -917,13 +1126,15 @@ pub(crate) fn transact_terms_with_action<'conn, 'a, I, W>(conn: &'conn rusqlite:
                                        schema_for_mutation: &'a Schema,
                                        schema: &'a Schema,
                                        watcher: W,
+                                       redundant_assertions: db::RedundantAssertions,
+                                       large_value_threshold: Option<usize>,
                                        terms: I,
                                        tempid_set: InternSet<TempId>,
                                        action: TransactorAction) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
     where I: IntoIterator<Item=TermWithTempIds>,
           W: TransactWatcher {
 
-    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher)?;
+    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher, redundant_assertions, large_value_threshold)?;
     let report = tx.transact_simple_terms_with_action(terms, tempid_set, action)?;
     conclude_tx(tx, report)
 }
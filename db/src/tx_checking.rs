@@ -19,14 +19,36 @@ use core_traits::{
     ValueType,
 };
 
+use mentat_core::{
+    HasSchema,
+    Keyword,
+    Schema,
+};
+
+use edn::entities::{
+    AttributePlace,
+    Entity,
+    EntidOrIdent,
+    EntityPlace,
+    MapNotation,
+    ValuePlace,
+};
+
 use db_traits::errors::{
     CardinalityConflict,
+    DbErrorKind,
+    Result,
+    SchemaConstraintViolation,
 };
 
 use internal_types::{
     AEVTrie,
 };
 
+use types::{
+    TransactableValue,
+};
+
 /// Map from found [e a v] to expected type.
 pub(crate) type TypeDisagreements = BTreeMap<(Entid, Entid, TypedValue), ValueType>;
 
@@ -82,3 +104,194 @@ pub(crate) fn cardinality_conflicts<'schema>(aev_trie: &AEVTrie<'schema>) -> Vec
 
     errors
 }
+
+/// Map from the index of an entity in a transaction to the unknown attributes it uses.
+pub(crate) type UnknownAttributes = BTreeMap<usize, BTreeSet<EntidOrIdent>>;
+
+/// Call `f` with every attribute used by the given entity, including attributes used in nested map
+/// notation and in lookup refs.  Attributes in the attribute place are given along with their
+/// value; attributes in lookup refs are given without a value.
+fn visit_attributes<'e, V, F>(entity: &'e Entity<V>, f: &mut F)
+where F: FnMut(&'e EntidOrIdent, Option<&'e ValuePlace<V>>) {
+    match entity {
+        &Entity::AddOrRetract { ref e, a: AttributePlace::Entid(ref a), ref v, .. } => {
+            if let &EntityPlace::LookupRef(ref lookup_ref) = e {
+                let AttributePlace::Entid(ref a) = lookup_ref.a;
+                f(a, None);
+            }
+            f(a, Some(v));
+            visit_value_attributes(v, f);
+        },
+        &Entity::MapNotation(ref map_notation) => {
+            visit_map_notation_attributes(map_notation, f);
+        },
+        &Entity::RetractEntity { ref e } => {
+            if let &EntityPlace::LookupRef(ref lookup_ref) = e {
+                let AttributePlace::Entid(ref a) = lookup_ref.a;
+                f(a, None);
+            }
+        },
+        &Entity::RetractAttribute { ref e, a: AttributePlace::Entid(ref a) } => {
+            if let &EntityPlace::LookupRef(ref lookup_ref) = e {
+                let AttributePlace::Entid(ref a) = lookup_ref.a;
+                f(a, None);
+            }
+            f(a, None);
+        },
+        &Entity::Call { .. } => {},
+    }
+}
+
+fn visit_map_notation_attributes<'e, V, F>(map_notation: &'e MapNotation<V>, f: &mut F)
+where F: FnMut(&'e EntidOrIdent, Option<&'e ValuePlace<V>>) {
+    for (a, v) in map_notation {
+        match a {
+            &EntidOrIdent::Ident(ref ident) if *ident == Keyword::namespaced("db", "id") => {},
+            _ => f(a, Some(v)),
+        }
+        visit_value_attributes(v, f);
+    }
+}
+
+fn visit_value_attributes<'e, V, F>(v: &'e ValuePlace<V>, f: &mut F)
+where F: FnMut(&'e EntidOrIdent, Option<&'e ValuePlace<V>>) {
+    match v {
+        &ValuePlace::LookupRef(ref lookup_ref) => {
+            let AttributePlace::Entid(ref a) = lookup_ref.a;
+            f(a, None);
+        },
+        &ValuePlace::MapNotation(ref map_notation) => visit_map_notation_attributes(map_notation, f),
+        &ValuePlace::Vector(ref vs) => {
+            for v in vs {
+                visit_value_attributes(v, f);
+            }
+        },
+        &ValuePlace::Entid(_) |
+        &ValuePlace::TempId(_) |
+        &ValuePlace::TxFunction(_) |
+        &ValuePlace::Atom(_) => {},
+    }
+}
+
+fn is_known_attribute(schema: &Schema, a: &EntidOrIdent) -> bool {
+    match a.unreversed().as_ref().unwrap_or(a) {
+        &EntidOrIdent::Entid(e) => schema.attribute_for_entid(e).is_some(),
+        &EntidOrIdent::Ident(ref ident) => schema.attribute_for_ident(ident).is_some(),
+    }
+}
+
+/// Ensure that the given entities only use attributes installed in the given schema.
+///
+/// Like the other checks, we yield every unknown attribute, grouped by the entity that uses it,
+/// rather than only the first.
+pub(crate) fn unknown_attributes<V>(schema: &Schema, entities: &[Entity<V>]) -> UnknownAttributes {
+    let mut errors = UnknownAttributes::default();
+
+    for (index, entity) in entities.iter().enumerate() {
+        visit_attributes(entity, &mut |a, _| {
+            if !is_known_attribute(schema, a) {
+                errors.entry(index).or_insert_with(BTreeSet::new).insert(a.clone());
+            }
+        });
+    }
+
+    errors
+}
+
+/// The value type and cardinality implied by a single use of an attribute, if there's an obvious
+/// one.
+fn inferred_shape<V: TransactableValue>(v: &ValuePlace<V>) -> Option<(ValueType, bool)> {
+    match v {
+        &ValuePlace::Atom(ref v) => v.value_type_hint().map(|t| (t, false)),
+        &ValuePlace::Entid(_) |
+        &ValuePlace::TempId(_) |
+        &ValuePlace::LookupRef(_) |
+        &ValuePlace::TxFunction(_) |
+        &ValuePlace::MapNotation(_) => Some((ValueType::Ref, false)),
+        &ValuePlace::Vector(ref vs) => {
+            let mut value_types = vs.iter().map(|v| inferred_shape(v).map(|(t, _)| t));
+            match value_types.next() {
+                Some(Some(t)) if value_types.all(|u| u == Some(t)) => Some((t, true)),
+                _ => None,
+            }
+        },
+    }
+}
+
+/// Infer definitions for the attributes used by the given entities that aren't installed in the
+/// given schema.
+///
+/// Each attribute's value type is taken from the values it's used with, and it's `:db.cardinality/many`
+/// only if it's used with a vector of values.  Attributes used with `:attr/_reversed` notation
+/// are refs.  The definitions are marked with `:db/doc` so that they're easy to find and replace
+/// later.
+///
+/// Attributes named by entid, attributes only used in lookup refs, and attributes used with values
+/// of disagreeing types can't be inferred; these yield the same error as a strict transaction.
+pub fn provisional_attributes<V: TransactableValue>(schema: &Schema, entities: &[Entity<V>]) -> Result<Vec<Entity<TypedValue>>> {
+    let mut shapes: BTreeMap<Keyword, Option<(ValueType, bool)>> = BTreeMap::default();
+    let mut uninferable: BTreeSet<EntidOrIdent> = BTreeSet::default();
+
+    for entity in entities {
+        visit_attributes(entity, &mut |a, v| {
+            if is_known_attribute(schema, a) {
+                return;
+            }
+            let (ident, shape) = match (a, a.unreversed(), v) {
+                (_, Some(EntidOrIdent::Ident(forward)), Some(_)) => (forward, Some((ValueType::Ref, false))),
+                (&EntidOrIdent::Ident(ref ident), None, Some(v)) => (ident.clone(), inferred_shape(v)),
+                _ => {
+                    uninferable.insert(a.clone());
+                    return;
+                },
+            };
+            let agreed = match (shapes.get(&ident), shape) {
+                (None, shape) => shape,
+                (Some(&Some((t, many))), Some((u, also_many))) if t == u => Some((t, many || also_many)),
+                _ => None,
+            };
+            shapes.insert(ident, agreed);
+        });
+    }
+
+    for (ident, shape) in shapes.iter() {
+        if shape.is_none() {
+            uninferable.insert(EntidOrIdent::Ident(ident.clone()));
+        }
+    }
+
+    if !uninferable.is_empty() {
+        // Report the uninferable attributes against the entities that use them.
+        let unknown_attributes = unknown_attributes(schema, entities).into_iter()
+            .map(|(index, attributes)| {
+                let attributes: BTreeSet<EntidOrIdent> = attributes.into_iter()
+                    .filter(|a| uninferable.contains(a) || a.unreversed().map_or(false, |a| uninferable.contains(&a)))
+                    .collect();
+                (index, attributes)
+            })
+            .filter(|&(_, ref attributes)| !attributes.is_empty())
+            .collect();
+        bail!(DbErrorKind::SchemaConstraintViolation(SchemaConstraintViolation::UnknownAttributes { unknown_attributes }));
+    }
+
+    let definitions = shapes.into_iter().filter_map(|(ident, shape)| {
+        shape.map(|(value_type, many)| {
+            let cardinality = if many { "many" } else { "one" };
+            let mut definition: MapNotation<TypedValue> = MapNotation::default();
+            definition.insert(EntidOrIdent::Ident(Keyword::namespaced("db", "ident")),
+                              ValuePlace::Atom(TypedValue::Keyword(ident.into())));
+            definition.insert(EntidOrIdent::Ident(Keyword::namespaced("db", "valueType")),
+                              ValuePlace::Entid(EntidOrIdent::Ident(value_type.into_keyword())));
+            definition.insert(EntidOrIdent::Ident(Keyword::namespaced("db", "cardinality")),
+                              ValuePlace::Entid(EntidOrIdent::Ident(Keyword::namespaced("db.cardinality", cardinality))));
+            definition.insert(EntidOrIdent::Ident(Keyword::namespaced("db", "doc")),
+                              ValuePlace::Atom(TypedValue::typed_string(PROVISIONAL_ATTRIBUTE_DOC)));
+            Entity::MapNotation(definition)
+        })
+    }).collect();
+
+    Ok(definitions)
+}
+
+/// The `:db/doc` of attributes installed by `provisional_attributes`.
+pub const PROVISIONAL_ATTRIBUTE_DOC: &'static str = "Provisional attribute installed by a lenient transaction.";
@@ -8,6 +8,10 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::path::{
+    Path,
+};
+
 use std::sync::{
     Arc,
     Weak,
@@ -26,6 +30,8 @@ use indexmap::{
     IndexMap,
 };
 
+use rusqlite;
+
 use core_traits::{
     Entid,
     TypedValue,
@@ -33,6 +39,7 @@ use core_traits::{
 
 use mentat_core::{
     Schema,
+    TxReport,
 };
 
 use edn::entities::{
@@ -43,33 +50,137 @@ use db_traits::errors::{
     Result,
 };
 
+use cache::{
+    SQLiteAttributeCache,
+};
+
+use db::{
+    new_connection,
+};
+
 use types::{
     AttributeSet,
 };
 
 use watcher::TransactWatcher;
 
+/// A read-only view of the store exactly as it was when a batch of transactions committed.
+///
+/// The snapshot has its own SQLite connection, holding open a read transaction that was begun
+/// before any later write through the same `Conn` could commit.  Observers can query it from the
+/// notification thread without contending for the write lock or the `Conn`'s metadata.  The read
+/// transaction ends when every observer has been notified.
+///
+/// Snapshots can only be taken of unencrypted stores backed by a file.
+pub struct TxSnapshot {
+    sqlite: rusqlite::Connection,
+    schema: Arc<Schema>,
+    cache: SQLiteAttributeCache,
+}
+
+impl TxSnapshot {
+    /// Open a connection to the store at `path` and begin reading.  The caller must ensure that
+    /// nothing has committed since the transactions that `schema` and `cache` describe.
+    pub fn open(path: &Path, schema: Arc<Schema>, cache: SQLiteAttributeCache) -> rusqlite::Result<TxSnapshot> {
+        TxSnapshot::begin(new_connection(path)?, schema, cache)
+    }
+
+    /// Begin reading on an existing connection, which must not be in a transaction.  The same
+    /// caveat as for `open` applies.
+    pub fn begin(sqlite: rusqlite::Connection, schema: Arc<Schema>, cache: SQLiteAttributeCache) -> rusqlite::Result<TxSnapshot> {
+        // A deferred transaction doesn't take its snapshot until it first reads.
+        sqlite.execute_batch("BEGIN DEFERRED; SELECT COUNT(*) FROM sqlite_master;")?;
+
+        Ok(TxSnapshot {
+            sqlite,
+            schema,
+            cache,
+        })
+    }
+
+    /// End the read transaction, returning the connection for reuse.
+    pub fn end(self) -> rusqlite::Result<rusqlite::Connection> {
+        self.sqlite.execute_batch("ROLLBACK")?;
+        Ok(self.sqlite)
+    }
+
+    pub fn sqlite(&self) -> &rusqlite::Connection {
+        &self.sqlite
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &*self.schema
+    }
+
+    pub fn cache(&self) -> &SQLiteAttributeCache {
+        &self.cache
+    }
+}
+
+enum NotifyFn {
+    Reports(Box<Fn(&str, IndexMap<&Entid, &AttributeSet>) + Send + Sync>),
+    Snapshot(Box<Fn(&str, IndexMap<&Entid, &AttributeSet>, Option<&TxSnapshot>) + Send + Sync>),
+    EachTransaction(Box<Fn(&str, &TxReport, &AttributeSet) + Send + Sync>),
+}
+
 pub struct TxObserver {
-    notify_fn: Arc<Box<Fn(&str, IndexMap<&Entid, &AttributeSet>) + Send + Sync>>,
+    notify_fn: Arc<NotifyFn>,
     attributes: AttributeSet,
 }
 
 impl TxObserver {
     pub fn new<F>(attributes: AttributeSet, notify_fn: F) -> TxObserver where F: Fn(&str, IndexMap<&Entid, &AttributeSet>) + 'static + Send + Sync {
         TxObserver {
-            notify_fn: Arc::new(Box::new(notify_fn)),
+            notify_fn: Arc::new(NotifyFn::Reports(Box::new(notify_fn))),
             attributes,
         }
     }
 
+    /// Like `new`, but `notify_fn` is also given a `TxSnapshot` of the store as of the reported
+    /// transactions, which it can query.  The snapshot is `None` if the store is in memory or
+    /// encrypted, or if it couldn't be opened.
+    pub fn with_snapshot<F>(attributes: AttributeSet, notify_fn: F) -> TxObserver where F: Fn(&str, IndexMap<&Entid, &AttributeSet>, Option<&TxSnapshot>) + 'static + Send + Sync {
+        TxObserver {
+            notify_fn: Arc::new(NotifyFn::Snapshot(Box::new(notify_fn))),
+            attributes,
+        }
+    }
+
+    /// Like `new`, but `notify_fn` is called once for each applicable transaction, in the order
+    /// they committed, with that transaction's `TxReport` and the attributes it changed.
+    pub fn for_each_transaction<F>(attributes: AttributeSet, notify_fn: F) -> TxObserver where F: Fn(&str, &TxReport, &AttributeSet) + 'static + Send + Sync {
+        TxObserver {
+            notify_fn: Arc::new(NotifyFn::EachTransaction(Box::new(notify_fn))),
+            attributes,
+        }
+    }
+
+    pub fn wants_snapshot(&self) -> bool {
+        match *self.notify_fn {
+            NotifyFn::Reports(_) => false,
+            NotifyFn::Snapshot(_) => true,
+            NotifyFn::EachTransaction(_) => false,
+        }
+    }
+
     pub fn applicable_reports<'r>(&self, reports: &'r IndexMap<Entid, AttributeSet>) -> IndexMap<&'r Entid, &'r AttributeSet> {
         reports.into_iter()
                .filter(|&(_txid, attrs)| !self.attributes.is_disjoint(attrs))
                .collect()
     }
 
-    fn notify(&self, key: &str, reports: IndexMap<&Entid, &AttributeSet>) {
-        (*self.notify_fn)(key, reports);
+    fn notify(&self, key: &str, reports: IndexMap<&Entid, &AttributeSet>, tx_reports: &IndexMap<Entid, TxReport>, snapshot: Option<&TxSnapshot>) {
+        match *self.notify_fn {
+            NotifyFn::Reports(ref f) => f(key, reports),
+            NotifyFn::Snapshot(ref f) => f(key, reports, snapshot),
+            NotifyFn::EachTransaction(ref f) => {
+                for (tx_id, attributes) in reports {
+                    if let Some(report) = tx_reports.get(tx_id) {
+                        f(key, report, attributes);
+                    }
+                }
+            },
+        }
     }
 }
 
@@ -79,14 +190,18 @@ pub trait Command {
 
 pub struct TxCommand {
     reports: IndexMap<Entid, AttributeSet>,
+    tx_reports: IndexMap<Entid, TxReport>,
     observers: Weak<IndexMap<String, Arc<TxObserver>>>,
+    snapshot: Option<TxSnapshot>,
 }
 
 impl TxCommand {
-    fn new(observers: &Arc<IndexMap<String, Arc<TxObserver>>>, reports: IndexMap<Entid, AttributeSet>) -> Self {
+    fn new(observers: &Arc<IndexMap<String, Arc<TxObserver>>>, reports: IndexMap<Entid, AttributeSet>, tx_reports: IndexMap<Entid, TxReport>, snapshot: Option<TxSnapshot>) -> Self {
         TxCommand {
             reports,
+            tx_reports,
             observers: Arc::downgrade(observers),
+            snapshot,
         }
     }
 }
@@ -97,7 +212,7 @@ impl Command for TxCommand {
             for (key, observer) in observers.iter() {
                 let applicable_reports = observer.applicable_reports(&self.reports);
                 if !applicable_reports.is_empty() {
-                    observer.notify(&key, applicable_reports);
+                    observer.notify(&key, applicable_reports, &self.tx_reports, self.snapshot.as_ref());
                 }
             }
         });
@@ -134,7 +249,15 @@ impl TxObservationService {
         !self.observers.is_empty()
     }
 
-    pub fn in_progress_did_commit(&mut self, txes: IndexMap<Entid, AttributeSet>) {
+    /// Whether any observer should be given a `TxSnapshot` when transactions commit.
+    pub fn wants_snapshot(&self) -> bool {
+        self.observers.values().any(|o| o.wants_snapshot())
+    }
+
+    /// Notify observers of `txes`, described by `tx_reports`, on the notification thread.
+    /// `snapshot`, if given, is handed to observers that asked for one; it's dropped, ending its
+    /// read, once they've all been told.
+    pub fn in_progress_did_commit(&mut self, txes: IndexMap<Entid, AttributeSet>, tx_reports: IndexMap<Entid, TxReport>, snapshot: Option<TxSnapshot>) {
         // Don't spawn a thread only to say nothing.
         if !self.has_observers() {
             return;
@@ -151,7 +274,7 @@ impl TxObservationService {
             tx
         });
 
-        let cmd = Box::new(TxCommand::new(&self.observers, txes));
+        let cmd = Box::new(TxCommand::new(&self.observers, txes, tx_reports, snapshot));
         executor.send(cmd).unwrap();
     }
 }
@@ -165,6 +288,7 @@ impl Drop for TxObservationService {
 pub struct InProgressObserverTransactWatcher {
     collected_attributes: AttributeSet,
     pub txes: IndexMap<Entid, AttributeSet>,
+    pub tx_reports: IndexMap<Entid, TxReport>,
 }
 
 impl InProgressObserverTransactWatcher {
@@ -172,8 +296,14 @@ impl InProgressObserverTransactWatcher {
         InProgressObserverTransactWatcher {
             collected_attributes: Default::default(),
             txes: Default::default(),
+            tx_reports: Default::default(),
         }
     }
+
+    /// Remember the report of a successful transaction, to hand to observers on commit.
+    pub fn did_transact(&mut self, report: &TxReport) {
+        self.tx_reports.insert(report.tx_id, report.clone());
+    }
 }
 
 impl TransactWatcher for InProgressObserverTransactWatcher {
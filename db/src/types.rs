@@ -38,6 +38,7 @@ pub use self::mentat_core::{
     Utc,
 };
 
+use edn;
 use edn::entities::{
     EntityPlace,
     TempId,
@@ -170,6 +171,18 @@ pub trait TransactableValue: Clone {
     fn into_entity_place(self) -> errors::Result<EntityPlace<Self>>;
 
     fn as_tempid(&self) -> Option<TempId>;
+
+    /// The value type this value would have if it were not coerced, if there is an obvious one.
+    /// This is used to infer definitions for attributes that aren't installed yet.
+    fn value_type_hint(&self) -> Option<ValueType>;
+
+    /// Make a value place out of a typed value.  This is how entities returned by transaction
+    /// functions are transacted alongside the entities that called them.
+    fn from_typed_value(value: TypedValue) -> Self;
+
+    /// The EDN value this value place stands for.  This is how transaction functions see their
+    /// arguments.
+    fn into_edn_value(self) -> edn::Value;
 }
 
 #[cfg(test)]
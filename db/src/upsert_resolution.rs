@@ -153,17 +153,30 @@ impl Generation {
     /// Evolve this generation one step further by rewriting the existing :db/add entities using the
     /// given temporary IDs.
     ///
+    /// `temp_id_map` must contain every tempid resolved so far, not just those resolved in this
+    /// step: a tempid that upserted in an earlier generation can reappear in a simple upsert that
+    /// was produced by a complex upsert.  `upserted_avs` are exactly the `[a v]` pairs that were
+    /// found in the store in this step.  A simple upsert whose tempid resolved via some other
+    /// `[a v]` pair is not known to be in the store, and so is resolved rather than upserted.
+    ///
     /// TODO: Considering doing this in place; the function already consumes `self`.
-    pub(crate) fn evolve_one_step(self, temp_id_map: &TempIdMap) -> Generation {
+    pub(crate) fn evolve_one_step(self, temp_id_map: &TempIdMap, upserted_avs: &BTreeSet<AVPair>) -> Generation {
         let mut next = Generation::default();
 
         // We'll iterate our own allocations to resolve more things, but terms that have already
         // resolved stay resolved.
         next.resolved = self.resolved;
+        next.upserted = self.upserted;
 
         for UpsertE(t, a, v) in self.upserts_e {
             match temp_id_map.get(&*t) {
-                Some(&n) => next.upserted.push(Term::AddOrRetract(OpType::Add, n, a, v)),
+                Some(&n) => {
+                    if upserted_avs.contains(&(a, v.clone())) {
+                        next.upserted.push(Term::AddOrRetract(OpType::Add, n, a, v));
+                    } else {
+                        next.resolved.push(Term::AddOrRetract(OpType::Add, n, a, v));
+                    }
+                },
                 None => next.allocations.push(Term::AddOrRetract(OpType::Add, Right(t), a, Left(v))),
             }
         }
@@ -334,21 +347,22 @@ impl Generation {
                     match (op, temp_id_map.get(&*t1), temp_id_map.get(&*t2)) {
                         (op, Some(&n1), Some(&n2)) => Term::AddOrRetract(op, n1, a, TypedValue::Ref(n2.0)),
                         (OpType::Add, _, _) => unreachable!(), // This is a coding error -- every tempid in a :db/add entity should resolve or be allocated.
-                        (OpType::Retract, _, _) => bail!(DbErrorKind::NotYetImplemented(format!("[:db/retract ...] entity referenced tempid that did not upsert: one of {}, {}", t1, t2))),
+                        (OpType::Retract, None, _) => bail!(DbErrorKind::UnresolvedRetractTempId(t1.to_string())),
+                        (OpType::Retract, _, None) => bail!(DbErrorKind::UnresolvedRetractTempId(t2.to_string())),
                     }
                 },
                 Term::AddOrRetract(op, Right(t), a, Left(v)) => {
                     match (op, temp_id_map.get(&*t)) {
                         (op, Some(&n)) => Term::AddOrRetract(op, n, a, v),
                         (OpType::Add, _) => unreachable!(), // This is a coding error.
-                        (OpType::Retract, _) => bail!(DbErrorKind::NotYetImplemented(format!("[:db/retract ...] entity referenced tempid that did not upsert: {}", t))),
+                        (OpType::Retract, _) => bail!(DbErrorKind::UnresolvedRetractTempId(t.to_string())),
                     }
                 },
                 Term::AddOrRetract(op, Left(e), a, Right(t)) => {
                     match (op, temp_id_map.get(&*t)) {
                         (op, Some(&n)) => Term::AddOrRetract(op, e, a, TypedValue::Ref(n.0)),
                         (OpType::Add, _) => unreachable!(), // This is a coding error.
-                        (OpType::Retract, _) => bail!(DbErrorKind::NotYetImplemented(format!("[:db/retract ...] entity referenced tempid that did not upsert: {}", t))),
+                        (OpType::Retract, _) => bail!(DbErrorKind::UnresolvedRetractTempId(t.to_string())),
                     }
                 },
                 Term::AddOrRetract(_, Left(_), _, Left(_)) => unreachable!(), // This is a coding error -- these should not be in allocations.
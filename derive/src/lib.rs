@@ -0,0 +1,381 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! `#[derive(ToEntity)]` for structs with named fields.  Each field becomes an attribute:
+//!
+//! ```ignore
+//! #[derive(ToEntity)]
+//! #[mentat(namespace = "person")]
+//! struct Person {
+//!     #[mentat(tempid)]
+//!     handle: String,                 // :person/handle, and names the tempid.
+//!     first_name: String,             // :person/first-name
+//!     #[mentat(attribute = ":person/nick")]
+//!     nickname: Option<String>,       // Omitted when `None`.
+//!     #[mentat(ref)]
+//!     friends: Vec<String>,           // One value each; these are other entities' tempids.
+//!     #[mentat(skip)]
+//!     scratch: u32,
+//! }
+//! ```
+//!
+//! The namespace defaults to the struct's name in kebab case, and attribute names to the field's
+//! name in kebab case.  Values are cloned and converted with `Into<TypedValue>`.
+//!
+//! `#[derive(MentatEntity)]` takes the same options and derives both `ToEntity` and
+//! `mentat::entity::MentatEntity`, which reads values back out of pull results and describes the
+//! attributes so that they can be installed as a vocabulary.  Fields can additionally be marked
+//! `#[mentat(unique = "identity")]` or `#[mentat(unique = "value")]`, `#[mentat(index)]`, and
+//! `#[mentat(fulltext)]`.  Field types other than `ref` fields must implement
+//! `mentat::entity::EntityValue`; `ref` and `skip` fields read back as their `Default`.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+
+use proc_macro2::TokenStream as TokenStream2;
+
+use syn::{
+    Attribute,
+    Data,
+    DeriveInput,
+    Fields,
+    GenericArgument,
+    Ident,
+    Lit,
+    Meta,
+    NestedMeta,
+    PathArguments,
+    Type,
+};
+
+#[proc_macro_derive(ToEntity, attributes(mentat))]
+pub fn derive_to_entity(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match to_entity(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[proc_macro_derive(MentatEntity, attributes(mentat))]
+pub fn derive_mentat_entity(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match to_entity(&input).and_then(|to| mentat_entity(&input).map(|from| quote! { #to #from })) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// How many values a field holds.
+enum Shape {
+    One,
+    Optional,
+    Many,
+}
+
+#[derive(Default)]
+struct FieldOptions {
+    attribute: Option<(String, String)>,
+    tempid: bool,
+    is_ref: bool,
+    skip: bool,
+    unique: Option<TokenStream2>,
+    index: bool,
+    fulltext: bool,
+}
+
+/// Turn `FirstName` or `first_name` into `first-name`.
+fn kebab_case(name: &str) -> String {
+    let name = name.trim_start_matches("r#");
+    let mut out = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c == '_' {
+            out.push('-');
+        } else if c.is_uppercase() {
+            if i > 0 && !out.ends_with('-') {
+                out.push('-');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn mentat_meta(attrs: &[Attribute]) -> syn::Result<Vec<NestedMeta>> {
+    let mut items = vec![];
+    for attr in attrs.iter().filter(|a| a.path.is_ident("mentat")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => items.extend(list.nested.into_iter()),
+            other => return Err(syn::Error::new_spanned(other, "expected #[mentat(...)]")),
+        }
+    }
+    Ok(items)
+}
+
+fn string_value(lit: &Lit) -> syn::Result<String> {
+    match lit {
+        &Lit::Str(ref s) => Ok(s.value()),
+        _ => Err(syn::Error::new_spanned(lit, "expected a string")),
+    }
+}
+
+/// Split `:ns/name` into its namespace and name.
+fn parse_attribute(lit: &Lit) -> syn::Result<(String, String)> {
+    let value = string_value(lit)?;
+    let value = value.trim_start_matches(':');
+    match value.find('/') {
+        Some(i) if i > 0 && i + 1 < value.len() => Ok((value[..i].to_string(), value[i + 1..].to_string())),
+        _ => Err(syn::Error::new_spanned(lit, "expected a namespaced keyword like \":person/name\"")),
+    }
+}
+
+fn field_options(attrs: &[Attribute]) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for item in mentat_meta(attrs)? {
+        match item {
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("tempid") => options.tempid = true,
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("ref") => options.is_ref = true,
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("skip") => options.skip = true,
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("index") => options.index = true,
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("fulltext") => options.fulltext = true,
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("attribute") => {
+                options.attribute = Some(parse_attribute(&nv.lit)?);
+            },
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("unique") => {
+                options.unique = Some(match string_value(&nv.lit)?.as_str() {
+                    "identity" => quote! { ::mentat::entity::Unique::Identity },
+                    "value" => quote! { ::mentat::entity::Unique::Value },
+                    _ => return Err(syn::Error::new_spanned(&nv.lit, "expected \"identity\" or \"value\"")),
+                });
+            },
+            other => return Err(syn::Error::new_spanned(other, "unknown mentat field option")),
+        }
+    }
+    Ok(options)
+}
+
+fn namespace(input: &DeriveInput) -> syn::Result<String> {
+    let mut namespace = kebab_case(&input.ident.to_string());
+    for item in mentat_meta(&input.attrs)? {
+        match item {
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("namespace") => {
+                namespace = string_value(&nv.lit)?;
+            },
+            other => return Err(syn::Error::new_spanned(other, "unknown mentat option")),
+        }
+    }
+    Ok(namespace)
+}
+
+/// The shape of a field, and the type of each of its values.
+fn shape(ty: &Type) -> (Shape, &Type) {
+    if let &Type::Path(ref path) = ty {
+        if let Some(segment) = path.path.segments.last() {
+            if let PathArguments::AngleBracketed(ref args) = segment.arguments {
+                if let (1, Some(&GenericArgument::Type(ref inner))) = (args.args.len(), args.args.first()) {
+                    if segment.ident == "Option" {
+                        return (Shape::Optional, inner);
+                    }
+                    if segment.ident == "Vec" {
+                        return (Shape::Many, inner);
+                    }
+                }
+            }
+        }
+    }
+    (Shape::One, ty)
+}
+
+fn named_fields(input: &DeriveInput, derive: &str) -> syn::Result<Vec<syn::Field>> {
+    match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => Ok(fields.named.iter().cloned().collect()),
+            _ => Err(syn::Error::new_spanned(&input.ident, format!("{} needs a struct with named fields", derive))),
+        },
+        _ => Err(syn::Error::new_spanned(&input.ident, format!("{} can only be derived for structs", derive))),
+    }
+}
+
+fn to_entity(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = named_fields(input, "ToEntity")?;
+
+    let namespace = namespace(input)?;
+
+    let mut tempid: Option<TokenStream2> = None;
+    let mut adds: Vec<TokenStream2> = vec![];
+
+    for field in fields.iter() {
+        let ident: &Ident = field.ident.as_ref().expect("named field");
+        let options = field_options(&field.attrs)?;
+        let (shape, _) = shape(&field.ty);
+
+        if options.tempid {
+            if tempid.is_some() {
+                return Err(syn::Error::new_spanned(ident, "only one field can name the tempid"));
+            }
+            tempid = Some(match shape {
+                Shape::One => quote! {
+                    ::std::option::Option::Some(::std::string::ToString::to_string(&self.#ident))
+                },
+                Shape::Optional => quote! {
+                    self.#ident.as_ref().map(::std::string::ToString::to_string)
+                },
+                Shape::Many => return Err(syn::Error::new_spanned(ident, "a tempid can't come from a Vec")),
+            });
+        }
+
+        if options.skip {
+            continue;
+        }
+
+        let (ns, name) = options.attribute.unwrap_or_else(|| (namespace.clone(), kebab_case(&ident.to_string())));
+        let add = if options.is_ref {
+            quote! {
+                attributes.add_ref(::mentat::Keyword::namespaced(#ns, #name), ::std::string::ToString::to_string(value));
+            }
+        } else {
+            quote! {
+                attributes.add(::mentat::Keyword::namespaced(#ns, #name), ::std::clone::Clone::clone(value));
+            }
+        };
+
+        adds.push(match shape {
+            Shape::One => quote! {
+                {
+                    let value = &self.#ident;
+                    #add
+                }
+            },
+            Shape::Optional => quote! {
+                if let ::std::option::Option::Some(ref value) = self.#ident {
+                    #add
+                }
+            },
+            Shape::Many => quote! {
+                for value in self.#ident.iter() {
+                    #add
+                }
+            },
+        });
+    }
+
+    let tempid = tempid.unwrap_or_else(|| quote! { ::std::option::Option::None });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::mentat::ToEntity for #name #ty_generics #where_clause {
+            fn tempid(&self) -> ::std::option::Option<::std::string::String> {
+                #tempid
+            }
+
+            fn attributes(&self) -> ::mentat::EntityAttributes {
+                let mut attributes = ::mentat::EntityAttributes::new();
+                #(#adds)*
+                attributes
+            }
+        }
+    })
+}
+
+fn mentat_entity(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = named_fields(input, "MentatEntity")?;
+    let namespace = namespace(input)?;
+
+    let mut definitions: Vec<TokenStream2> = vec![];
+    let mut reads: Vec<TokenStream2> = vec![];
+
+    for field in fields.iter() {
+        let ident: &Ident = field.ident.as_ref().expect("named field");
+        let options = field_options(&field.attrs)?;
+        let (shape, value_ty) = shape(&field.ty);
+
+        if options.skip {
+            reads.push(quote! { #ident: ::std::default::Default::default() });
+            continue;
+        }
+
+        let (ns, name) = options.attribute.unwrap_or_else(|| (namespace.clone(), kebab_case(&ident.to_string())));
+
+        let value_type = if options.is_ref {
+            quote! { ::mentat::ValueType::Ref }
+        } else {
+            quote! { <#value_ty as ::mentat::entity::EntityValue>::value_type() }
+        };
+        let multival = match shape {
+            Shape::Many => true,
+            _ => false,
+        };
+        let unique = options.unique.map(|unique| quote! { .unique(#unique) });
+        let index = if options.index { Some(quote! { .index(true) }) } else { None };
+        let fulltext = if options.fulltext { Some(quote! { .fulltext(true) }) } else { None };
+        definitions.push(quote! {
+            (::mentat::Keyword::namespaced(#ns, #name),
+             ::mentat::vocabulary::AttributeBuilder::helpful()
+                 .value_type(#value_type)
+                 .multival(#multival)
+                 #unique
+                 #index
+                 #fulltext
+                 .build())
+        });
+
+        // Refs name tempids, which don't outlive the transaction that uses them.
+        if options.is_ref {
+            reads.push(quote! { #ident: ::std::default::Default::default() });
+            continue;
+        }
+
+        let read = match shape {
+            Shape::One => quote! { ::mentat::entity::read_one },
+            Shape::Optional => quote! { ::mentat::entity::read_optional },
+            Shape::Many => quote! { ::mentat::entity::read_many },
+        };
+        reads.push(quote! {
+            #ident: #read(map, &::mentat::Keyword::namespaced(#ns, #name))?
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::mentat::entity::MentatEntity for #name #ty_generics #where_clause {
+            fn attribute_definitions() -> ::std::vec::Vec<(::mentat::Keyword, ::mentat::Attribute)> {
+                vec![#(#definitions),*]
+            }
+
+            fn from_map(map: &::mentat::StructuredMap) -> ::mentat::Result<Self> {
+                ::std::result::Result::Ok(#name {
+                    #(#reads),*
+                })
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kebab_case() {
+        assert_eq!(kebab_case("Person"), "person");
+        assert_eq!(kebab_case("PageVisit"), "page-visit");
+        assert_eq!(kebab_case("first_name"), "first-name");
+        assert_eq!(kebab_case("r#type"), "type");
+    }
+}
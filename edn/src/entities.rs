@@ -38,9 +38,13 @@ impl TransactableValueMarker for ValueAndSpan {}
 
 /// A tempid, either an external tempid given in a transaction (usually as an `Value::Text`),
 /// or an internal tempid allocated by Mentat itself.
+///
+/// External tempids are allocated in `:db.part/user` unless they name a partition, like
+/// `(tempid :my/part "name")`.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
 pub enum TempId {
     External(String),
+    Partitioned(Keyword, String),
     Internal(i64),
 }
 
@@ -48,15 +52,25 @@ impl TempId {
     pub fn into_external(self) -> Option<String> {
         match self {
             TempId::External(s) => Some(s),
+            TempId::Partitioned(_, s) => Some(s),
             TempId::Internal(_) => None,
         }
     }
+
+    /// The partition this tempid names, if any.
+    pub fn partition(&self) -> Option<&Keyword> {
+        match self {
+            &TempId::Partitioned(ref partition, _) => Some(partition),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for TempId {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
             &TempId::External(ref s) => write!(f, "{}", s),
+            &TempId::Partitioned(ref partition, ref s) => write!(f, "(tempid {} {:?})", partition, s),
             &TempId::Internal(x) => write!(f, "<tempid {}>", x),
         }
     }
@@ -245,4 +259,66 @@ pub enum Entity<V> {
     },
     // Like {:db/id "tempid" a1 v1 a2 v2}.
     MapNotation(MapNotation<V>),
+    // Like [:db.fn/retractEntity e].
+    RetractEntity {
+        e: EntityPlace<V>,
+    },
+    // Like [:db.fn/retractAttribute e a].
+    RetractAttribute {
+        e: EntityPlace<V>,
+        a: AttributePlace,
+    },
+    // Like [:my/fn arg1 arg2], a call to a transaction function registered by the embedding
+    // application.  Calls are expanded into the entities they return before transacting.
+    Call {
+        f: Keyword,
+        args: Vec<V>,
+    },
+}
+
+impl<V> LookupRef<V> {
+    pub fn map_values<W, F>(self, f: &mut F) -> LookupRef<W> where F: FnMut(V) -> W {
+        LookupRef { a: self.a, v: f(self.v) }
+    }
+}
+
+impl<V> ValuePlace<V> {
+    /// Replace every value embedded in this value place, including values in lookup refs and in
+    /// nested vectors and map notation.
+    pub fn map_values<W, F>(self, f: &mut F) -> ValuePlace<W> where F: FnMut(V) -> W {
+        match self {
+            ValuePlace::Entid(v) => ValuePlace::Entid(v),
+            ValuePlace::TempId(v) => ValuePlace::TempId(v),
+            ValuePlace::LookupRef(v) => ValuePlace::LookupRef(v.map_values(f)),
+            ValuePlace::TxFunction(v) => ValuePlace::TxFunction(v),
+            ValuePlace::Vector(vs) => ValuePlace::Vector(vs.into_iter().map(|v| v.map_values(f)).collect()),
+            ValuePlace::Atom(v) => ValuePlace::Atom(f(v)),
+            ValuePlace::MapNotation(m) => ValuePlace::MapNotation(m.into_iter().map(|(a, v)| (a, v.map_values(f))).collect()),
+        }
+    }
+}
+
+impl<V> EntityPlace<V> {
+    pub fn map_values<W, F>(self, f: &mut F) -> EntityPlace<W> where F: FnMut(V) -> W {
+        match self {
+            EntityPlace::Entid(v) => EntityPlace::Entid(v),
+            EntityPlace::TempId(v) => EntityPlace::TempId(v),
+            EntityPlace::LookupRef(v) => EntityPlace::LookupRef(v.map_values(f)),
+            EntityPlace::TxFunction(v) => EntityPlace::TxFunction(v),
+        }
+    }
+}
+
+impl<V> Entity<V> {
+    /// Replace every value embedded in this entity.  This is how entities built around one value
+    /// type, like those returned by a transaction function, are transacted alongside another.
+    pub fn map_values<W, F>(self, f: &mut F) -> Entity<W> where F: FnMut(V) -> W {
+        match self {
+            Entity::AddOrRetract { op, e, a, v } => Entity::AddOrRetract { op, e: e.map_values(f), a, v: v.map_values(f) },
+            Entity::MapNotation(m) => Entity::MapNotation(m.into_iter().map(|(a, v)| (a, v.map_values(f))).collect()),
+            Entity::RetractEntity { e } => Entity::RetractEntity { e: e.map_values(f) },
+            Entity::RetractAttribute { e, a } => Entity::RetractAttribute { e: e.map_values(f), a },
+            Entity::Call { f: name, args } => Entity::Call { f: name, args: args.into_iter().map(|v| f(v)).collect() },
+        }
+    }
 }
@@ -8,6 +8,7 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+extern crate base64;
 extern crate chrono;
 extern crate itertools;
 extern crate num;
@@ -31,11 +32,14 @@ pub use intern_set::{
 mod namespaceable_name;
 pub mod query;
 pub mod symbols;
+pub mod tagged;
 pub mod types;
 pub mod pretty_print;
 pub mod utils;
 pub mod matcher;
 pub mod value_rc;
+#[cfg(feature = "serde_support")]
+mod value_serde;
 pub use value_rc::{
     Cloned,
     FromRc,
@@ -66,6 +70,11 @@ pub use types::{
     ValueAndSpan,
 };
 
+pub use tagged::{
+    TagError,
+    TagReaders,
+};
+
 pub use symbols::{
     Keyword,
     NamespacedSymbol,
@@ -8,6 +8,8 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use base64;
+
 use chrono::{
     SecondsFormat,
 };
@@ -18,7 +20,10 @@ use pretty;
 use std::io;
 use std::borrow::Cow;
 
-use types::Value;
+use types::{
+    Value,
+    escape_text,
+};
 
 impl Value {
     /// Return a pretty string representation of this `Value`.
@@ -71,9 +76,11 @@ impl Value {
             Value::NamespacedSymbol(ref v) => pp.text(v.namespace()).append("/").append(v.name()),
             Value::PlainSymbol(ref v) => pp.text(v.to_string()),
             Value::Keyword(ref v) => pp.text(v.to_string()),
-            Value::Text(ref v) => pp.text("\"").append(v.as_str()).append("\""),
+            Value::Text(ref v) => pp.text("\"").append(escape_text(v)).append("\""),
             Value::Uuid(ref u) => pp.text("#uuid \"").append(u.hyphenated().to_string()).append("\""),
+            Value::Bytes(ref b) => pp.text("#bytes \"").append(base64::encode(b)).append("\""),
             Value::Instant(ref v) => pp.text("#inst \"").append(v.to_rfc3339_opts(SecondsFormat::AutoSi, true)).append("\""),
+            Value::Tagged(ref t, ref v) => pp.text("#").append(t.to_string()).append(pp.space()).append(v.as_doc(pp)).group(),
             _ => pp.text(self.to_string())
         }
     }
@@ -165,9 +165,42 @@ pub enum Direction {
     Descending,
 }
 
-/// An abstract declaration of ordering: direction and variable.
+/// What to order by: a variable, or a function call.  A call is either an aggregate that
+/// appears in the find spec, like `(count ?x)`, or arithmetic on variables and numbers, like
+/// `(* ?price ?quantity)`; which one is decided during algebrizing.
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Order(pub Direction, pub Variable);   // Future: Element instead of Variable?
+pub enum OrderExpression {
+    Variable(Variable),
+    Call {
+        func: QueryFunction,
+        args: Vec<FnArg>,
+    },
+}
+
+impl From<Variable> for OrderExpression {
+    fn from(x: Variable) -> OrderExpression {
+        OrderExpression::Variable(x)
+    }
+}
+
+impl std::fmt::Display for OrderExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            &OrderExpression::Variable(ref var) => write!(f, "{}", var),
+            &OrderExpression::Call { ref func, ref args } => {
+                write!(f, "({}", func)?;
+                for arg in args.iter() {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+/// An abstract declaration of ordering: direction and what to order by.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Order(pub Direction, pub OrderExpression);
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SrcVar {
@@ -208,6 +241,7 @@ pub enum NonIntegerConstant {
     Text(ValueRc<String>),
     Instant(DateTime<Utc>),
     Uuid(Uuid),
+    Bytes(ValueRc<Vec<u8>>),
 }
 
 impl<'a> From<&'a str> for NonIntegerConstant {
@@ -251,6 +285,8 @@ impl FromValue<FnArg> for FnArg {
                 Some(FnArg::Constant(NonIntegerConstant::Instant(x))),
             Uuid(x) =>
                 Some(FnArg::Constant(NonIntegerConstant::Uuid(x))),
+            Bytes(ref x) =>
+                Some(FnArg::Constant(NonIntegerConstant::Bytes(ValueRc::new(x.clone())))),
             Boolean(x) =>
                 Some(FnArg::Constant(NonIntegerConstant::Boolean(x))),
             Float(x) =>
@@ -265,7 +301,8 @@ impl FromValue<FnArg> for FnArg {
             Vector(_) |
             List(_) |
             Set(_) |
-            Map(_) => None,
+            Map(_) |
+            Tagged(_, _) => None,
         }
     }
 }
@@ -300,8 +337,8 @@ impl FnArg {
 }
 
 /// e, a, tx can't be values -- no strings, no floats -- and so
-/// they can only be variables, entity IDs, ident keywords, or
-/// placeholders.
+/// they can only be variables, entity IDs, ident keywords, lookup
+/// refs, or placeholders.
 /// This encoding allows us to represent integers that aren't
 /// entity IDs. That'll get filtered out in the context of the
 /// database.
@@ -311,6 +348,37 @@ pub enum PatternNonValuePlace {
     Variable(Variable),
     Entid(i64),                       // Will always be +ve. See #190.
     Ident(ValueRc<Keyword>),
+    LookupRef(LookupRef),
+}
+
+/// A lookup ref, like `[:foo/email "x@y.com"]`: the entity that has the given value for the given
+/// `:db/unique` attribute.  The value is always a constant.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LookupRef {
+    pub attribute: ValueRc<Keyword>,
+    pub value: PatternValuePlace,
+}
+
+impl FromValue<LookupRef> for LookupRef {
+    fn from_value(v: &::ValueAndSpan) -> Option<LookupRef> {
+        if let ::SpannedValue::Vector(ref vs) = v.inner {
+            if let (Some(a), Some(v), None) = (vs.get(0), vs.get(1), vs.get(2)) {
+                let attribute = match a.inner {
+                    ::SpannedValue::Keyword(ref a) if a.is_namespaced() && a.is_forward() => a.clone(),
+                    _ => return None,
+                };
+                return match PatternValuePlace::from_value(v) {
+                    Some(value @ PatternValuePlace::EntidOrInteger(_)) |
+                    Some(value @ PatternValuePlace::IdentOrKeyword(_)) |
+                    Some(value @ PatternValuePlace::Constant(_)) => Some(LookupRef { attribute: ValueRc::new(attribute), value }),
+                    Some(PatternValuePlace::Placeholder) |
+                    Some(PatternValuePlace::Variable(_)) |
+                    None => None,
+                };
+            }
+        }
+        None
+    }
 }
 
 impl From<Rc<Keyword>> for PatternNonValuePlace {
@@ -328,21 +396,23 @@ impl From<Keyword> for PatternNonValuePlace {
 impl PatternNonValuePlace {
     // I think we'll want move variants, so let's leave these here for now.
     #[allow(dead_code)]
-    fn into_pattern_value_place(self) -> PatternValuePlace {
+    fn into_pattern_value_place(self) -> Option<PatternValuePlace> {
         match self {
-            PatternNonValuePlace::Placeholder => PatternValuePlace::Placeholder,
-            PatternNonValuePlace::Variable(x) => PatternValuePlace::Variable(x),
-            PatternNonValuePlace::Entid(x)    => PatternValuePlace::EntidOrInteger(x),
-            PatternNonValuePlace::Ident(x)    => PatternValuePlace::IdentOrKeyword(x),
+            PatternNonValuePlace::Placeholder => Some(PatternValuePlace::Placeholder),
+            PatternNonValuePlace::Variable(x) => Some(PatternValuePlace::Variable(x)),
+            PatternNonValuePlace::Entid(x)    => Some(PatternValuePlace::EntidOrInteger(x)),
+            PatternNonValuePlace::Ident(x)    => Some(PatternValuePlace::IdentOrKeyword(x)),
+            PatternNonValuePlace::LookupRef(_) => None,
         }
     }
 
-    fn to_pattern_value_place(&self) -> PatternValuePlace {
+    fn to_pattern_value_place(&self) -> Option<PatternValuePlace> {
         match *self {
-            PatternNonValuePlace::Placeholder     => PatternValuePlace::Placeholder,
-            PatternNonValuePlace::Variable(ref x) => PatternValuePlace::Variable(x.clone()),
-            PatternNonValuePlace::Entid(x)        => PatternValuePlace::EntidOrInteger(x),
-            PatternNonValuePlace::Ident(ref x)    => PatternValuePlace::IdentOrKeyword(x.clone()),
+            PatternNonValuePlace::Placeholder     => Some(PatternValuePlace::Placeholder),
+            PatternNonValuePlace::Variable(ref x) => Some(PatternValuePlace::Variable(x.clone())),
+            PatternNonValuePlace::Entid(x)        => Some(PatternValuePlace::EntidOrInteger(x)),
+            PatternNonValuePlace::Ident(ref x)    => Some(PatternValuePlace::IdentOrKeyword(x.clone())),
+            PatternNonValuePlace::LookupRef(_)    => None,
         }
     }
 }
@@ -366,6 +436,8 @@ impl FromValue<PatternNonValuePlace> for PatternNonValuePlace {
             },
             ::SpannedValue::Keyword(ref x) =>
                 Some(x.clone().into()),
+            ::SpannedValue::Vector(_) =>
+                LookupRef::from_value(v).map(PatternNonValuePlace::LookupRef),
             _ => None,
         }
     }
@@ -425,6 +497,8 @@ impl FromValue<PatternValuePlace> for PatternValuePlace {
                 Some(PatternValuePlace::Constant(x.clone().into())),
             ::SpannedValue::Uuid(ref u) =>
                 Some(PatternValuePlace::Constant(NonIntegerConstant::Uuid(u.clone()))),
+            ::SpannedValue::Bytes(ref b) =>
+                Some(PatternValuePlace::Constant(NonIntegerConstant::Bytes(ValueRc::new(b.clone())))),
 
             // These don't appear in queries.
             ::SpannedValue::Nil => None,
@@ -434,6 +508,7 @@ impl FromValue<PatternValuePlace> for PatternValuePlace {
             ::SpannedValue::List(_) => None,
             ::SpannedValue::Set(_) => None,
             ::SpannedValue::Vector(_) => None,
+            ::SpannedValue::Tagged(_, _) => None,
         }
     }
 }
@@ -623,6 +698,26 @@ pub enum Limit {
     Variable(Variable),
 }
 
+/// The number of results to skip before returning any, as in `:offset 20`.  Unlike a limit, an
+/// offset can be zero, which is the same as no offset at all.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Offset {
+    None,
+    Fixed(u64),
+    Variable(Variable),
+}
+
+impl Offset {
+    /// Return true if this offset might skip results.
+    pub fn is_some(&self) -> bool {
+        match self {
+            &Offset::None => false,
+            &Offset::Fixed(n) => n > 0,
+            &Offset::Variable(_) => true,
+        }
+    }
+}
+
 /// A definition of the first part of a find query: the
 /// `[:find ?foo ?bar…]` bit.
 ///
@@ -713,6 +808,15 @@ impl FindSpec {
         !self.is_unit_limited()
     }
 
+    /// Returns true if any element of the find spec is an aggregate, in which case results are
+    /// grouped by the spec's other elements.
+    pub fn has_aggregates(&self) -> bool {
+        self.columns().any(|e| match e {
+            &Element::Aggregate(_) => true,
+            _ => false,
+        })
+    }
+
     pub fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         use self::FindSpec::*;
         match self {
@@ -823,9 +927,20 @@ pub struct Pattern {
     pub attribute: PatternNonValuePlace,
     pub value: PatternValuePlace,
     pub tx: PatternNonValuePlace,
+
+    /// Whether the datom was asserted or retracted.  Only the transaction log -- the `$history`
+    /// source -- has retracted datoms, so only patterns against it can use this place.
+    pub added: PatternValuePlace,
 }
 
 impl Pattern {
+    pub fn has_lookup_refs(&self) -> bool {
+        [&self.entity, &self.attribute, &self.tx].iter().any(|place| match **place {
+            PatternNonValuePlace::LookupRef(_) => true,
+            _ => false,
+        })
+    }
+
     pub fn simple(e: PatternNonValuePlace,
                   a: PatternNonValuePlace,
                   v: PatternValuePlace) -> Option<Pattern> {
@@ -843,14 +958,14 @@ impl Pattern {
                 // e and v have different types; we must convert them.
                 // Not every parseable value is suitable for the entity field!
                 // As such, this is a failable constructor.
-                let e_v = e.to_pattern_value_place();
-                if let Some(v_e) = v.to_pattern_non_value_place() {
+                if let (Some(e_v), Some(v_e)) = (e.to_pattern_value_place(), v.to_pattern_non_value_place()) {
                     return Some(Pattern {
                         source: src,
                         entity: v_e,
                         attribute: k.to_reversed().into(),
                         value: e_v,
                         tx: tx,
+                        added: PatternValuePlace::Placeholder,
                     });
                 } else {
                     return None;
@@ -863,6 +978,7 @@ impl Pattern {
             attribute: a,
             value: v,
             tx: tx,
+            added: PatternValuePlace::Placeholder,
         })
     }
 }
@@ -936,6 +1052,8 @@ impl OrWhereClause {
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct OrJoin {
+    /// The source that the clauses query, as in `($src or …)`.
+    pub source: Option<SrcVar>,
     pub unify_vars: UnifyVars,
     pub clauses: Vec<OrWhereClause>,
 
@@ -945,6 +1063,8 @@ pub struct OrJoin {
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct NotJoin {
+    /// The source that the clauses query, as in `($src not …)`.
+    pub source: Option<SrcVar>,
     pub unify_vars: UnifyVars,
     pub clauses: Vec<WhereClause>,
 }
@@ -952,10 +1072,71 @@ pub struct NotJoin {
 impl NotJoin {
     pub fn new(unify_vars: UnifyVars, clauses: Vec<WhereClause>) -> NotJoin {
         NotJoin {
+            source: None,
             unify_vars: unify_vars,
             clauses: clauses,
         }
     }
+
+    pub fn with_source(mut self, source: Option<SrcVar>) -> NotJoin {
+        self.source = source;
+        self
+    }
+}
+
+/// Clauses that needn't match, as in `(optional [?x :person/nickname ?nick])`.  The variables that
+/// only they bind are absent, rather than the row dropped, when they don't.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Optional {
+    /// The source that the clauses query, as in `($src optional …)`.
+    pub source: Option<SrcVar>,
+    pub clauses: Vec<WhereClause>,
+}
+
+impl Optional {
+    pub fn new(clauses: Vec<WhereClause>) -> Optional {
+        Optional {
+            source: None,
+            clauses: clauses,
+        }
+    }
+
+    pub fn with_source(mut self, source: Option<SrcVar>) -> Optional {
+        self.source = source;
+        self
+    }
+}
+
+/// An invocation of a rule in a `:where` clause, like `(ancestor ?x ?y)`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RuleExpr {
+    pub name: PlainSymbol,
+    pub args: Vec<FnArg>,
+}
+
+impl RuleExpr {
+    /// Names that introduce other kinds of clause, and so can't name a rule.
+    pub fn is_reserved_name(name: &PlainSymbol) -> bool {
+        match name.name() {
+            "and" | "or" | "or-join" | "not" | "not-join" | "optional" => true,
+            _ => false,
+        }
+    }
+}
+
+/// A rule definition, as given in a query's `:rules`: a head naming the rule and its variables,
+/// and a body of clauses, like
+///
+/// ```edn
+/// [(ancestor ?x ?y) [?x :person/parent ?y]]
+/// ```
+///
+/// Several definitions can share a name, in which case the rule matches if any of them do.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    pub name: PlainSymbol,
+    pub vars: Vec<Variable>,
+    pub clauses: Vec<WhereClause>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -968,10 +1149,11 @@ pub struct TypeAnnotation {
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum WhereClause {
     NotJoin(NotJoin),
+    Optional(Optional),
     OrJoin(OrJoin),
     Pred(Predicate),
     WhereFn(WhereFn),
-    RuleExpr,
+    RuleExpr(RuleExpr),
     Pattern(Pattern),
     TypeAnnotation(TypeAnnotation),
 }
@@ -982,20 +1164,34 @@ pub struct ParsedQuery {
     pub find_spec: FindSpec,
     pub default_source: SrcVar,
     pub with: Vec<Variable>,
-    pub in_vars: Vec<Variable>,
+    pub in_bindings: Vec<Binding>,
     pub in_sources: BTreeSet<SrcVar>,
     pub limit: Limit,
+    pub offset: Offset,
+
+    /// False if the query asked, with `:distinct false`, for every result row, duplicates and all.
+    pub distinct: bool,
     pub where_clauses: Vec<WhereClause>,
     pub order: Option<Vec<Order>>,
+    pub rules: Vec<Rule>,
 }
 
 pub(crate) enum QueryPart {
     FindSpec(FindSpec),
     WithVars(Vec<Variable>),
-    InVars(Vec<Variable>),
+    InBindings(Vec<InPart>),
     Limit(Limit),
+    Offset(Offset),
+    Distinct(bool),
     WhereClauses(Vec<WhereClause>),
     Order(Vec<Order>),
+    Rules(Vec<Rule>),
+}
+
+/// An `:in` clause names sources as well as bindings: `:in $ $history ?name`.
+pub(crate) enum InPart {
+    Source(SrcVar),
+    Binding(Binding),
 }
 
 /// A `ParsedQuery` represents a parsed but potentially invalid query to the query algebrizer.
@@ -1008,10 +1204,14 @@ impl ParsedQuery {
     pub(crate) fn from_parts(parts: Vec<QueryPart>) -> std::result::Result<ParsedQuery, &'static str> {
         let mut find_spec: Option<FindSpec> = None;
         let mut with: Option<Vec<Variable>> = None;
-        let mut in_vars: Option<Vec<Variable>> = None;
+        let mut in_bindings: Option<Vec<Binding>> = None;
+        let mut in_sources: BTreeSet<SrcVar> = BTreeSet::default();
         let mut limit: Option<Limit> = None;
+        let mut offset: Option<Offset> = None;
+        let mut distinct: Option<bool> = None;
         let mut where_clauses: Option<Vec<WhereClause>> = None;
         let mut order: Option<Vec<Order>> = None;
+        let mut rules: Option<Vec<Rule>> = None;
 
         for part in parts.into_iter() {
             match part {
@@ -1027,11 +1227,22 @@ impl ParsedQuery {
                     }
                     with = Some(x)
                 },
-                QueryPart::InVars(x) => {
-                    if in_vars.is_some() {
+                QueryPart::InBindings(x) => {
+                    if in_bindings.is_some() {
                         return Err("find query has repeated :in");
                     }
-                    in_vars = Some(x)
+                    let mut bindings = vec![];
+                    for part in x {
+                        match part {
+                            InPart::Source(source) => {
+                                if !in_sources.insert(source) {
+                                    return Err("find query has repeated source in :in");
+                                }
+                            },
+                            InPart::Binding(binding) => bindings.push(binding),
+                        }
+                    }
+                    in_bindings = Some(bindings)
                 },
                 QueryPart::Limit(x) => {
                     if limit.is_some() {
@@ -1039,6 +1250,18 @@ impl ParsedQuery {
                     }
                     limit = Some(x)
                 },
+                QueryPart::Offset(x) => {
+                    if offset.is_some() {
+                        return Err("find query has repeated :offset");
+                    }
+                    offset = Some(x)
+                },
+                QueryPart::Distinct(x) => {
+                    if distinct.is_some() {
+                        return Err("find query has repeated :distinct");
+                    }
+                    distinct = Some(x)
+                },
                 QueryPart::WhereClauses(x) => {
                     if where_clauses.is_some() {
                         return Err("find query has repeated :where");
@@ -1051,6 +1274,12 @@ impl ParsedQuery {
                     }
                     order = Some(x)
                 },
+                QueryPart::Rules(x) => {
+                    if rules.is_some() {
+                        return Err("find query has repeated :rules");
+                    }
+                    rules = Some(x)
+                },
             }
         }
 
@@ -1058,11 +1287,14 @@ impl ParsedQuery {
             find_spec: find_spec.ok_or("expected :find")?,
             default_source: SrcVar::DefaultSrc,
             with: with.unwrap_or(vec![]),
-            in_vars: in_vars.unwrap_or(vec![]),
-            in_sources: BTreeSet::default(),
+            in_bindings: in_bindings.unwrap_or(vec![]),
+            in_sources,
             limit: limit.unwrap_or(Limit::None),
+            offset: offset.unwrap_or(Offset::None),
+            distinct: distinct.unwrap_or(true),
             where_clauses: where_clauses.ok_or("expected :where")?,
             order,
+            rules: rules.unwrap_or(vec![]),
         })
     }
 }
@@ -1070,12 +1302,18 @@ impl ParsedQuery {
 impl OrJoin {
     pub fn new(unify_vars: UnifyVars, clauses: Vec<OrWhereClause>) -> OrJoin {
         OrJoin {
+            source: None,
             unify_vars: unify_vars,
             clauses: clauses,
             mentioned_vars: None,
         }
     }
 
+    pub fn with_source(mut self, source: Option<SrcVar>) -> OrJoin {
+        self.source = source;
+        self
+    }
+
     /// Return true if either the `OrJoin` is `UnifyVars::Implicit`, or if
     /// every variable mentioned inside the join is also mentioned in the `UnifyVars` list.
     pub fn is_fully_unified(&self) -> bool {
@@ -1114,9 +1352,10 @@ impl ContainsVariables for WhereClause {
             &Pred(ref p)           => p.accumulate_mentioned_variables(acc),
             &Pattern(ref p)        => p.accumulate_mentioned_variables(acc),
             &NotJoin(ref n)        => n.accumulate_mentioned_variables(acc),
+            &Optional(ref o)       => o.accumulate_mentioned_variables(acc),
             &WhereFn(ref f)        => f.accumulate_mentioned_variables(acc),
             &TypeAnnotation(ref a) => a.accumulate_mentioned_variables(acc),
-            &RuleExpr              => (),
+            &RuleExpr(ref r)       => r.accumulate_mentioned_variables(acc),
         }
     }
 }
@@ -1170,6 +1409,14 @@ impl ContainsVariables for NotJoin {
     }
 }
 
+impl ContainsVariables for Optional {
+    fn accumulate_mentioned_variables(&self, acc: &mut BTreeSet<Variable>) {
+        for clause in &self.clauses {
+            clause.accumulate_mentioned_variables(acc);
+        }
+    }
+}
+
 impl ContainsVariables for Predicate {
     fn accumulate_mentioned_variables(&self, acc: &mut BTreeSet<Variable>) {
         for arg in &self.args {
@@ -1180,6 +1427,16 @@ impl ContainsVariables for Predicate {
     }
 }
 
+impl ContainsVariables for RuleExpr {
+    fn accumulate_mentioned_variables(&self, acc: &mut BTreeSet<Variable>) {
+        for arg in &self.args {
+            if let &FnArg::Variable(ref v) = arg {
+                acc_ref(acc, v)
+            }
+        }
+    }
+}
+
 impl ContainsVariables for TypeAnnotation {
     fn accumulate_mentioned_variables(&self, acc: &mut BTreeSet<Variable>) {
         acc_ref(acc, &self.variable);
@@ -1235,5 +1492,8 @@ impl ContainsVariables for Pattern {
         if let PatternNonValuePlace::Variable(ref v) = self.tx {
             acc_ref(acc, v)
         }
+        if let PatternValuePlace::Variable(ref v) = self.added {
+            acc_ref(acc, v)
+        }
     }
 }
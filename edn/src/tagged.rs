@@ -0,0 +1,99 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! The parser keeps user-defined tagged elements, like `#myapp/Point [1 2]`, as `Value::Tagged`.
+//! A `TagReaders` registry turns them into something more useful, much like Clojure's
+//! `*data-readers*`: each reader receives the tagged value and returns its replacement.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use std::error;
+use std::fmt;
+
+use symbols::{
+    NamespacedSymbol,
+};
+
+use types::{
+    Value,
+};
+
+/// A reader failed to make sense of the value it was given.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TagError {
+    pub tag: NamespacedSymbol,
+    pub message: String,
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't read #{}: {}", self.tag, self.message)
+    }
+}
+
+impl error::Error for TagError {
+    fn description(&self) -> &str {
+        "couldn't read tagged element"
+    }
+}
+
+type TagReader = Box<Fn(Value) -> Result<Value, String>>;
+
+#[derive(Default)]
+pub struct TagReaders {
+    readers: BTreeMap<NamespacedSymbol, TagReader>,
+}
+
+impl TagReaders {
+    pub fn new() -> TagReaders {
+        TagReaders::default()
+    }
+
+    /// Read elements tagged with `tag` using `reader`, replacing any previous reader for `tag`.
+    pub fn register<F>(&mut self, tag: NamespacedSymbol, reader: F)
+    where F: Fn(Value) -> Result<Value, String> + 'static {
+        self.readers.insert(tag, Box::new(reader));
+    }
+
+    pub fn unregister(&mut self, tag: &NamespacedSymbol) -> bool {
+        self.readers.remove(tag).is_some()
+    }
+
+    pub fn is_registered(&self, tag: &NamespacedSymbol) -> bool {
+        self.readers.contains_key(tag)
+    }
+
+    /// Replace every tagged element in `value` that has a registered reader, innermost first.
+    /// Elements with unknown tags are kept as they are.
+    pub fn resolve(&self, value: Value) -> Result<Value, TagError> {
+        match value {
+            Value::Tagged(tag, inner) => {
+                let inner = self.resolve(*inner)?;
+                match self.readers.get(&tag) {
+                    Some(reader) => reader(inner).map_err(|message| TagError {
+                        tag: tag,
+                        message: message,
+                    }),
+                    None => Ok(Value::Tagged(tag, Box::new(inner))),
+                }
+            },
+            Value::Vector(vs) => vs.into_iter().map(|v| self.resolve(v)).collect::<Result<_, _>>().map(Value::Vector),
+            Value::List(vs) => vs.into_iter().map(|v| self.resolve(v)).collect::<Result<_, _>>().map(Value::List),
+            Value::Set(vs) => vs.into_iter().map(|v| self.resolve(v)).collect::<Result<_, _>>().map(Value::Set),
+            Value::Map(vs) => vs.into_iter()
+                                .map(|(k, v)| Ok((self.resolve(k)?, self.resolve(v)?)))
+                                .collect::<Result<_, _>>()
+                                .map(Value::Map),
+            v => Ok(v),
+        }
+    }
+}
@@ -10,6 +10,7 @@
 
 #![cfg_attr(feature = "cargo-clippy", allow(linkedlist))]
 
+use std::borrow::Cow;
 use std::collections::{BTreeSet, BTreeMap, LinkedList};
 use std::cmp::{Ordering, Ord, PartialOrd};
 use std::fmt::{Display, Formatter};
@@ -21,6 +22,7 @@ use chrono::{
     TimeZone,           // For Utc::timestamp. The compiler incorrectly complains that this is unused.
     Utc,
 };
+use base64;
 use num::BigInt;
 use ordered_float::OrderedFloat;
 use uuid::Uuid;
@@ -38,6 +40,7 @@ pub enum Value {
     Float(OrderedFloat<f64>),
     Text(String),
     Uuid(Uuid),
+    Bytes(Vec<u8>),
     PlainSymbol(symbols::PlainSymbol),
     NamespacedSymbol(symbols::NamespacedSymbol),
     Keyword(symbols::Keyword),
@@ -52,6 +55,10 @@ pub enum Value {
     // See https://internals.rust-lang.org/t/implementing-hash-for-hashset-hashmap/3817/1
     Set(BTreeSet<Value>),
     Map(BTreeMap<Value, Value>),
+    // A value with a user-defined tag, like `#myapp/Person {:name "Alice"}`.  Tags without a
+    // namespace are reserved for EDN's built-ins, which we parse into their own variants.  Use
+    // `tagged::TagReaders` to turn these into something more meaningful.
+    Tagged(symbols::NamespacedSymbol, Box<Value>),
 }
 
 /// `SpannedValue` is the parallel to `Value` but used in `ValueAndSpan`.
@@ -66,6 +73,7 @@ pub enum SpannedValue {
     Float(OrderedFloat<f64>),
     Text(String),
     Uuid(Uuid),
+    Bytes(Vec<u8>),
     PlainSymbol(symbols::PlainSymbol),
     NamespacedSymbol(symbols::NamespacedSymbol),
     Keyword(symbols::Keyword),
@@ -73,6 +81,7 @@ pub enum SpannedValue {
     List(LinkedList<ValueAndSpan>),
     Set(BTreeSet<ValueAndSpan>),
     Map(BTreeMap<ValueAndSpan, ValueAndSpan>),
+    Tagged(symbols::NamespacedSymbol, Box<ValueAndSpan>),
 }
 
 /// Span represents the current offset (start, end) into the input string.
@@ -154,6 +163,7 @@ impl From<SpannedValue> for Value {
             SpannedValue::Float(v) => Value::Float(v),
             SpannedValue::Text(v) => Value::Text(v),
             SpannedValue::Uuid(v) => Value::Uuid(v),
+            SpannedValue::Bytes(v) => Value::Bytes(v),
             SpannedValue::PlainSymbol(v) => Value::PlainSymbol(v),
             SpannedValue::NamespacedSymbol(v) => Value::NamespacedSymbol(v),
             SpannedValue::Keyword(v) => Value::Keyword(v),
@@ -161,6 +171,7 @@ impl From<SpannedValue> for Value {
             SpannedValue::List(v) => Value::List(v.into_iter().map(|x| x.without_spans()).collect()),
             SpannedValue::Set(v) => Value::Set(v.into_iter().map(|x| x.without_spans()).collect()),
             SpannedValue::Map(v) => Value::Map(v.into_iter().map(|(x, y)| (x.without_spans(), y.without_spans())).collect()),
+            SpannedValue::Tagged(t, v) => Value::Tagged(t, Box::new(v.without_spans())),
         }
     }
 }
@@ -171,6 +182,47 @@ impl From<ValueAndSpan> for Value {
     }
 }
 
+/// Implements `From<$t> for Value`, so that values can be built up with `.into()`.
+macro_rules! def_value_from {
+    ($t: ty, $kind: path) => {
+        impl From<$t> for Value {
+            fn from(src: $t) -> Value {
+                $kind(src.into())
+            }
+        }
+    }
+}
+
+def_value_from!(bool, Value::Boolean);
+def_value_from!(i64, Value::Integer);
+def_value_from!(i32, Value::Integer);
+def_value_from!(BigInt, Value::BigInteger);
+def_value_from!(f64, Value::Float);
+def_value_from!(OrderedFloat<f64>, Value::Float);
+def_value_from!(DateTime<Utc>, Value::Instant);
+def_value_from!(String, Value::Text);
+def_value_from!(Uuid, Value::Uuid);
+def_value_from!(symbols::PlainSymbol, Value::PlainSymbol);
+def_value_from!(symbols::NamespacedSymbol, Value::NamespacedSymbol);
+def_value_from!(symbols::Keyword, Value::Keyword);
+def_value_from!(Vec<Value>, Value::Vector);
+def_value_from!(LinkedList<Value>, Value::List);
+def_value_from!(BTreeSet<Value>, Value::Set);
+def_value_from!(BTreeMap<Value, Value>, Value::Map);
+
+impl<'a> From<&'a str> for Value {
+    fn from(src: &'a str) -> Value {
+        Value::Text(src.to_string())
+    }
+}
+
+impl<T> From<Option<T>> for Value where T: Into<Value> {
+    /// `None` is `nil`.
+    fn from(src: Option<T>) -> Value {
+        src.map_or(Value::Nil, |v| v.into())
+    }
+}
+
 /// Creates `from_$TYPE` helper functions for Value and SpannedValue,
 /// like `from_float()` or `from_ordered_float()`.
 macro_rules! def_from {
@@ -306,12 +358,14 @@ macro_rules! def_common_value_methods {
         def_is!(is_float, $t::Float(_));
         def_is!(is_text, $t::Text(_));
         def_is!(is_uuid, $t::Uuid(_));
+        def_is!(is_bytes, $t::Bytes(_));
         def_is!(is_symbol, $t::PlainSymbol(_));
         def_is!(is_namespaced_symbol, $t::NamespacedSymbol(_));
         def_is!(is_vector, $t::Vector(_));
         def_is!(is_list, $t::List(_));
         def_is!(is_set, $t::Set(_));
         def_is!(is_map, $t::Map(_));
+        def_is!(is_tagged, $t::Tagged(_, _));
 
         pub fn is_keyword(&self) -> bool {
             match self {
@@ -342,6 +396,7 @@ macro_rules! def_common_value_methods {
         def_as_ref!(as_ordered_float, $t::Float, OrderedFloat<f64>);
         def_as_ref!(as_text, $t::Text, String);
         def_as_ref!(as_uuid, $t::Uuid, Uuid);
+        def_as_ref!(as_bytes, $t::Bytes, Vec<u8>);
         def_as_ref!(as_symbol, $t::PlainSymbol, symbols::PlainSymbol);
         def_as_ref!(as_namespaced_symbol, $t::NamespacedSymbol, symbols::NamespacedSymbol);
 
@@ -379,6 +434,7 @@ macro_rules! def_common_value_methods {
         def_into!(into_float, $t::Float, f64, |v: OrderedFloat<f64>| v.into_inner());
         def_into!(into_text, $t::Text, String,);
         def_into!(into_uuid, $t::Uuid, Uuid,);
+        def_into!(into_bytes, $t::Bytes, Vec<u8>,);
         def_into!(into_symbol, $t::PlainSymbol, symbols::PlainSymbol,);
         def_into!(into_namespaced_symbol, $t::NamespacedSymbol, symbols::NamespacedSymbol,);
 
@@ -433,6 +489,20 @@ macro_rules! def_common_value_methods {
             to_keyword!(namespace, name, $t)
         }
 
+        pub fn as_tagged(&self) -> Option<(&symbols::NamespacedSymbol, &$tchild)> {
+            match *self {
+                $t::Tagged(ref t, ref v) => Some((t, &**v)),
+                _ => None,
+            }
+        }
+
+        pub fn into_tagged(self) -> Option<(symbols::NamespacedSymbol, $tchild)> {
+            match self {
+                $t::Tagged(t, v) => Some((t, *v)),
+                _ => None,
+            }
+        }
+
         fn precedence(&self) -> i32 {
             match *self {
                 $t::Nil => 0,
@@ -443,14 +513,16 @@ macro_rules! def_common_value_methods {
                 $t::Instant(_) => 5,
                 $t::Text(_) => 6,
                 $t::Uuid(_) => 7,
-                $t::PlainSymbol(_) => 8,
-                $t::NamespacedSymbol(_) => 9,
-                $t::Keyword(ref k) if !k.is_namespaced() => 10,
-                $t::Keyword(_) => 11,
-                $t::Vector(_) => 12,
-                $t::List(_) => 13,
-                $t::Set(_) => 14,
-                $t::Map(_) => 15,
+                $t::Bytes(_) => 8,
+                $t::PlainSymbol(_) => 9,
+                $t::NamespacedSymbol(_) => 10,
+                $t::Keyword(ref k) if !k.is_namespaced() => 11,
+                $t::Keyword(_) => 12,
+                $t::Vector(_) => 13,
+                $t::List(_) => 14,
+                $t::Set(_) => 15,
+                $t::Map(_) => 16,
+                $t::Tagged(_, _) => 17,
             }
         }
 
@@ -464,6 +536,7 @@ macro_rules! def_common_value_methods {
                 $t::Float(_) => false,
                 $t::Text(_) => false,
                 $t::Uuid(_) => false,
+                $t::Bytes(_) => false,
                 $t::PlainSymbol(_) => false,
                 $t::NamespacedSymbol(_) => false,
                 $t::Keyword(_) => false,
@@ -471,6 +544,7 @@ macro_rules! def_common_value_methods {
                 $t::List(_) => true,
                 $t::Set(_) => true,
                 $t::Map(_) => true,
+                $t::Tagged(_, _) => false,
             }
         }
 
@@ -501,6 +575,7 @@ macro_rules! def_common_value_ord {
             (&$t::Float(ref a), &$t::Float(ref b)) => b.cmp(a),
             (&$t::Text(ref a), &$t::Text(ref b)) => b.cmp(a),
             (&$t::Uuid(ref a), &$t::Uuid(ref b)) => b.cmp(a),
+            (&$t::Bytes(ref a), &$t::Bytes(ref b)) => b.cmp(a),
             (&$t::PlainSymbol(ref a), &$t::PlainSymbol(ref b)) => b.cmp(a),
             (&$t::NamespacedSymbol(ref a), &$t::NamespacedSymbol(ref b)) => b.cmp(a),
             (&$t::Keyword(ref a), &$t::Keyword(ref b)) => b.cmp(a),
@@ -508,13 +583,33 @@ macro_rules! def_common_value_ord {
             (&$t::List(ref a), &$t::List(ref b)) => b.cmp(a),
             (&$t::Set(ref a), &$t::Set(ref b)) => b.cmp(a),
             (&$t::Map(ref a), &$t::Map(ref b)) => b.cmp(a),
+            (&$t::Tagged(ref at, ref a), &$t::Tagged(ref bt, ref b)) => bt.cmp(at).then_with(|| b.cmp(a)),
             _ => $value.precedence().cmp(&$other.precedence())
         }
     }
 }
 
+/// Escape `s` so that it can be written between double quotes and parsed back as the same text.
+pub(crate) fn escape_text(s: &str) -> Cow<str> {
+    if !s.contains(|c| c == '"' || c == '\\' || c == '\n' || c == '\t' || c == '\r') {
+        return Cow::Borrowed(s);
+    }
+    let mut escaped = String::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
 /// Converts a Value or SpannedValue to string, given a formatter.
-// TODO: Make sure float syntax is correct, handle NaN and escaping.
+// TODO: Make sure float syntax is correct.
 // See https://github.com/mozilla/mentat/issues/232
 macro_rules! def_common_value_display {
     ( $t:tt, $value:expr, $f:expr ) => {
@@ -536,9 +631,9 @@ macro_rules! def_common_value_display {
                     write!($f, "{}", v)
                 }
             }
-            // TODO: EDN escaping.
-            $t::Text(ref v) => write!($f, "\"{}\"", v),
+            $t::Text(ref v) => write!($f, "\"{}\"", escape_text(v)),
             $t::Uuid(ref u) => write!($f, "#uuid \"{}\"", u.hyphenated().to_string()),
+            $t::Bytes(ref b) => write!($f, "#bytes \"{}\"", base64::encode(b)),
             $t::PlainSymbol(ref v) => v.fmt($f),
             $t::NamespacedSymbol(ref v) => v.fmt($f),
             $t::Keyword(ref v) => v.fmt($f),
@@ -570,6 +665,7 @@ macro_rules! def_common_value_display {
                 }
                 write!($f, " }}")
             }
+            $t::Tagged(ref t, ref v) => write!($f, "#{} {}", t, v),
         }
     }
 }
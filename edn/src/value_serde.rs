@@ -0,0 +1,158 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Serde support for `Value`, so that EDN can be converted to and from JSON and friends.
+//!
+//! The serde data model is smaller than EDN's, so serializing loses information: instants, UUIDs,
+//! big integers, symbols and keywords are written as their EDN text, lists and sets as sequences,
+//! and tagged elements as the value they tag.  Deserializing produces only nil, booleans, numbers,
+//! text, vectors and maps.  Use the EDN parser and `Display` if you need a faithful round trip.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use std::fmt;
+
+use chrono::{
+    SecondsFormat,
+};
+
+use num::BigInt;
+
+use serde::de::{
+    self,
+    Deserialize,
+    Deserializer,
+    MapAccess,
+    SeqAccess,
+    Visitor,
+};
+
+use serde::ser::{
+    Serialize,
+    SerializeMap,
+    Serializer,
+};
+
+use types::{
+    Value,
+};
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match *self {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Boolean(v) => serializer.serialize_bool(v),
+            Value::Integer(v) => serializer.serialize_i64(v),
+            Value::Instant(v) => serializer.serialize_str(&v.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            Value::BigInteger(ref v) => serializer.serialize_str(&v.to_string()),
+            Value::Float(v) => serializer.serialize_f64(v.into_inner()),
+            Value::Text(ref v) => serializer.serialize_str(v),
+            Value::Uuid(ref v) => serializer.serialize_str(&v.hyphenated().to_string()),
+            Value::Bytes(ref v) => serializer.serialize_bytes(v),
+            Value::PlainSymbol(ref v) => serializer.serialize_str(&v.to_string()),
+            Value::NamespacedSymbol(ref v) => serializer.serialize_str(&v.to_string()),
+            Value::Keyword(ref v) => serializer.serialize_str(&v.to_string()),
+            Value::Vector(ref vs) => serializer.collect_seq(vs),
+            Value::List(ref vs) => serializer.collect_seq(vs),
+            Value::Set(ref vs) => serializer.collect_seq(vs),
+            Value::Map(ref vs) => {
+                let mut map = serializer.serialize_map(Some(vs.len()))?;
+                // `Value::Map` orders its keys in reverse; write them in the order they read.
+                for (k, v) in vs.iter().rev() {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            },
+            Value::Tagged(_, ref v) => v.serialize(serializer),
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Value, D::Error> where D: Deserializer<'de> {
+        Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Integer(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        if v <= i64::max_value() as u64 {
+            Ok(Value::Integer(v as i64))
+        } else {
+            Ok(Value::BigInteger(BigInt::from(v)))
+        }
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::from_float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> where E: de::Error {
+        Ok(Value::Text(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::Text(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Value, E> where E: de::Error {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Value, E> {
+        Ok(Value::Bytes(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Value, A::Error> where A: SeqAccess<'de> {
+        let mut vs = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(v) = seq.next_element()? {
+            vs.push(v);
+        }
+        Ok(Value::Vector(vs))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Value, A::Error> where A: MapAccess<'de> {
+        let mut vs = BTreeMap::new();
+        while let Some((k, v)) = map.next_entry()? {
+            vs.insert(k, v);
+        }
+        Ok(Value::Map(vs))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> Result<Value, D::Error> where D: Deserializer<'de> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
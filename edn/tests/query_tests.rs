@@ -16,21 +16,31 @@ use edn::{
 };
 
 use edn::query::{
+    Binding,
     Direction,
     Element,
     FindSpec,
     FnArg,
     Limit,
     NonIntegerConstant,
+    NotJoin,
+    Offset,
+    Optional,
     Order,
+    OrderExpression,
     OrJoin,
     OrWhereClause,
     Pattern,
     PatternNonValuePlace,
     PatternValuePlace,
     Predicate,
+    QueryFunction,
+    Rule,
+    RuleExpr,
+    SrcVar,
     UnifyVars,
     Variable,
+    VariableOrPlaceholder,
     WhereClause,
 };
 
@@ -58,6 +68,7 @@ fn can_parse_predicates() {
                        attribute: PatternNonValuePlace::Placeholder,
                        value: PatternValuePlace::Variable(Variable::from_valid_name("?y")),
                        tx: PatternNonValuePlace::Placeholder,
+                       added: PatternValuePlace::Placeholder,
                    }),
                    WhereClause::Pred(Predicate { operator: PlainSymbol::plain("<"), args: vec![
                        FnArg::Variable(Variable::from_valid_name("?y")), FnArg::EntidOrInteger(10),
@@ -84,6 +95,7 @@ fn can_parse_simple_or() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(10),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                            OrWhereClause::Clause(
                                WhereClause::Pattern(Pattern {
@@ -92,12 +104,77 @@ fn can_parse_simple_or() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(15),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                        ],
                    )),
                ]);
 }
 
+#[test]
+fn can_parse_or_and_not_with_source() {
+    let s = "[:find ?x :where ($ or [?x _ 10] [?x _ 15]) ($db not-join [?x] [?x _ 20])]";
+    let p = parse_query(s).unwrap();
+
+    let pattern = |v| OrWhereClause::Clause(WhereClause::Pattern(Pattern {
+        source: None,
+        entity: PatternNonValuePlace::Variable(Variable::from_valid_name("?x")),
+        attribute: PatternNonValuePlace::Placeholder,
+        value: PatternValuePlace::EntidOrInteger(v),
+        tx: PatternNonValuePlace::Placeholder,
+        added: PatternValuePlace::Placeholder,
+    }));
+    assert_eq!(p.where_clauses,
+               vec![
+                   WhereClause::OrJoin(OrJoin::new(UnifyVars::Implicit, vec![pattern(10), pattern(15)])
+                                          .with_source(Some(SrcVar::DefaultSrc))),
+                   WhereClause::NotJoin(NotJoin::new(
+                       UnifyVars::Explicit(::std::iter::once(Variable::from_valid_name("?x")).collect()),
+                       vec![WhereClause::Pattern(Pattern {
+                           source: None,
+                           entity: PatternNonValuePlace::Variable(Variable::from_valid_name("?x")),
+                           attribute: PatternNonValuePlace::Placeholder,
+                           value: PatternValuePlace::EntidOrInteger(20),
+                           tx: PatternNonValuePlace::Placeholder,
+                           added: PatternValuePlace::Placeholder,
+                       })]).with_source(Some(SrcVar::NamedSrc("db".to_string())))),
+               ]);
+
+    // Without a source.
+    let s = "[:find ?x :where [?x _ 5] (not [?x _ 20])]";
+    match parse_query(s).unwrap().where_clauses.pop() {
+        Some(WhereClause::NotJoin(not_join)) => assert_eq!(not_join.source, None),
+        c => panic!("expected not, got {:?}", c),
+    }
+}
+
+#[test]
+fn can_parse_optional() {
+    let s = "[:find ?x ?nick :where [?x _ 5] (optional [?x :person/nickname ?nick])]";
+    let p = parse_query(s).unwrap();
+    assert_eq!(p.where_clauses.last(),
+               Some(&WhereClause::Optional(Optional::new(vec![
+                   WhereClause::Pattern(Pattern {
+                       source: None,
+                       entity: PatternNonValuePlace::Variable(Variable::from_valid_name("?x")),
+                       attribute: PatternNonValuePlace::Ident(Keyword::namespaced("person", "nickname").into()),
+                       value: PatternValuePlace::Variable(Variable::from_valid_name("?nick")),
+                       tx: PatternNonValuePlace::Placeholder,
+                       added: PatternValuePlace::Placeholder,
+                   }),
+               ]))));
+
+    // With a source.
+    let s = "[:find ?x :where [?x _ 5] ($ optional [?x _ 20])]";
+    match parse_query(s).unwrap().where_clauses.pop() {
+        Some(WhereClause::Optional(optional)) => assert_eq!(optional.source, Some(SrcVar::DefaultSrc)),
+        c => panic!("expected optional, got {:?}", c),
+    }
+
+    // `optional` can't name a rule.
+    assert!(parse_query("[:find ?x :where (optional)]").is_err());
+}
+
 #[test]
 fn can_parse_unit_or_join() {
     let s = "[:find ?x . :where (or-join [?x] [?x _ 15])]";
@@ -117,6 +194,7 @@ fn can_parse_unit_or_join() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(15),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                        ],
                    )),
@@ -142,6 +220,7 @@ fn can_parse_simple_or_join() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(10),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                            OrWhereClause::Clause(
                                WhereClause::Pattern(Pattern {
@@ -150,6 +229,7 @@ fn can_parse_simple_or_join() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(-15),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                        ],
                    )),
@@ -180,6 +260,7 @@ fn can_parse_simple_or_and_join() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(10),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                            OrWhereClause::And(
                                vec![
@@ -192,6 +273,7 @@ fn can_parse_simple_or_and_join() {
                                                attribute: ident("foo", "bar"),
                                                value: PatternValuePlace::Variable(Variable::from_valid_name("?y")),
                                                tx: PatternNonValuePlace::Placeholder,
+                                               added: PatternValuePlace::Placeholder,
                                            })),
                                            OrWhereClause::Clause(WhereClause::Pattern(Pattern {
                                                source: None,
@@ -199,6 +281,7 @@ fn can_parse_simple_or_and_join() {
                                                attribute: ident("foo", "baz"),
                                                value: PatternValuePlace::Variable(Variable::from_valid_name("?y")),
                                                tx: PatternNonValuePlace::Placeholder,
+                                               added: PatternValuePlace::Placeholder,
                                            })),
                                        ],
                                    )),
@@ -221,20 +304,42 @@ fn can_parse_order_by() {
     // Defaults to ascending.
     let default = "[:find ?x :where [?x :foo/baz ?y] :order ?y]";
     assert_eq!(parse_query(default).unwrap().order,
-               Some(vec![Order(Direction::Ascending, Variable::from_valid_name("?y"))]));
+               Some(vec![Order(Direction::Ascending, Variable::from_valid_name("?y").into())]));
 
     let ascending = "[:find ?x :where [?x :foo/baz ?y] :order (asc ?y)]";
     assert_eq!(parse_query(ascending).unwrap().order,
-               Some(vec![Order(Direction::Ascending, Variable::from_valid_name("?y"))]));
+               Some(vec![Order(Direction::Ascending, Variable::from_valid_name("?y").into())]));
 
     let descending = "[:find ?x :where [?x :foo/baz ?y] :order (desc ?y)]";
     assert_eq!(parse_query(descending).unwrap().order,
-               Some(vec![Order(Direction::Descending, Variable::from_valid_name("?y"))]));
+               Some(vec![Order(Direction::Descending, Variable::from_valid_name("?y").into())]));
 
     let mixed = "[:find ?x :where [?x :foo/baz ?y] :order (desc ?y) (asc ?x)]";
     assert_eq!(parse_query(mixed).unwrap().order,
-               Some(vec![Order(Direction::Descending, Variable::from_valid_name("?y")),
-                         Order(Direction::Ascending, Variable::from_valid_name("?x"))]));
+               Some(vec![Order(Direction::Descending, Variable::from_valid_name("?y").into()),
+                         Order(Direction::Ascending, Variable::from_valid_name("?x").into())]));
+
+    let aggregate = "[:find ?x (count ?y) :where [?x :foo/baz ?y] :order (desc (count ?y)) ?x]";
+    assert_eq!(parse_query(aggregate).unwrap().order,
+               Some(vec![Order(Direction::Descending, OrderExpression::Call {
+                             func: QueryFunction::from_symbol(&PlainSymbol::plain("count")).unwrap(),
+                             args: vec![FnArg::Variable(Variable::from_valid_name("?y"))],
+                         }),
+                         Order(Direction::Ascending, Variable::from_valid_name("?x").into())]));
+
+    let arithmetic = "[:find ?x :where [?x :foo/baz ?y] :order (* ?y 2)]";
+    assert_eq!(parse_query(arithmetic).unwrap().order,
+               Some(vec![Order(Direction::Ascending, OrderExpression::Call {
+                             func: QueryFunction::from_symbol(&PlainSymbol::plain("*")).unwrap(),
+                             args: vec![FnArg::Variable(Variable::from_valid_name("?y")), FnArg::EntidOrInteger(2)],
+                         })]));
+
+    let subtraction = "[:find ?x :where [?x :foo/baz ?y] [?x :foo/bar ?z] :order (desc (- ?y ?z))]";
+    assert_eq!(parse_query(subtraction).unwrap().order,
+               Some(vec![Order(Direction::Descending, OrderExpression::Call {
+                             func: QueryFunction::from_symbol(&PlainSymbol::plain("-")).unwrap(),
+                             args: vec![FnArg::Variable(Variable::from_valid_name("?y")), FnArg::Variable(Variable::from_valid_name("?z"))],
+                         })]));
 }
 
 #[test]
@@ -266,6 +371,139 @@ fn can_parse_limit() {
                Limit::Variable(Variable::from_valid_name("?limit")));
 }
 
+#[test]
+fn can_parse_in_bindings() {
+    let s = "[:find ?x :in ?a [?b ...] [[?c _]] [?d ?e] :where [?x :foo/baz ?a]]";
+    let p = parse_query(s).unwrap();
+
+    let var = |name| Variable::from_valid_name(name);
+    let place = |name| VariableOrPlaceholder::Variable(Variable::from_valid_name(name));
+    assert_eq!(p.in_bindings,
+               vec![Binding::BindScalar(var("?a")),
+                    Binding::BindColl(var("?b")),
+                    Binding::BindRel(vec![place("?c"), VariableOrPlaceholder::Placeholder]),
+                    Binding::BindTuple(vec![place("?d"), place("?e")])]);
+
+    let repeated_invalid = "[:find ?x :in ?a :in ?b :where [?x :foo/baz ?a]]";
+    assert!(parse_query(repeated_invalid).is_err());
+}
+
+#[test]
+fn can_parse_in_sources() {
+    let s = "[:find ?x ?added :in $ ?a $history :where [$history ?x :foo/baz ?a _ ?added]]";
+    let p = parse_query(s).unwrap();
+
+    assert_eq!(p.in_bindings, vec![Binding::BindScalar(Variable::from_valid_name("?a"))]);
+    assert_eq!(p.in_sources,
+               vec![SrcVar::DefaultSrc, SrcVar::NamedSrc("history".to_string())].into_iter().collect());
+    assert_eq!(p.where_clauses,
+               vec![WhereClause::Pattern(Pattern {
+                   source: Some(SrcVar::NamedSrc("history".to_string())),
+                   entity: PatternNonValuePlace::Variable(Variable::from_valid_name("?x")),
+                   attribute: PatternNonValuePlace::Ident(Keyword::namespaced("foo", "baz").into()),
+                   value: PatternValuePlace::Variable(Variable::from_valid_name("?a")),
+                   tx: PatternNonValuePlace::Placeholder,
+                   added: PatternValuePlace::Variable(Variable::from_valid_name("?added")),
+               })]);
+
+    let repeated_invalid = "[:find ?x :in $ $ :where [?x :foo/baz _]]";
+    assert!(parse_query(repeated_invalid).is_err());
+}
+
+#[test]
+fn can_parse_offset() {
+    let invalid = "[:find ?x :where [?x :foo/baz ?y] :offset]";
+    assert!(parse_query(invalid).is_err());
+
+    let negative_invalid = "[:find ?x :where [?x :foo/baz ?y] :offset -1]";
+    assert!(parse_query(negative_invalid).is_err());
+
+    let repeated_invalid = "[:find ?x :where [?x :foo/baz ?y] :offset 1 :offset 2]";
+    assert!(parse_query(repeated_invalid).is_err());
+
+    let none = "[:find ?x :where [?x :foo/baz ?y]]";
+    assert_eq!(parse_query(none).unwrap().offset,
+               Offset::None);
+
+    let zero = "[:find ?x :where [?x :foo/baz ?y] :offset 0]";
+    assert_eq!(parse_query(zero).unwrap().offset,
+               Offset::Fixed(0));
+
+    let with_limit = "[:find ?x :where [?x :foo/baz ?y] :limit 10 :offset 20]";
+    let parsed = parse_query(with_limit).unwrap();
+    assert_eq!(parsed.limit, Limit::Fixed(10));
+    assert_eq!(parsed.offset, Offset::Fixed(20));
+
+    let variable_with_in = "[:find ?x :in ?offset :where [?x :foo/baz ?y] :offset ?offset]";
+    assert_eq!(parse_query(variable_with_in).unwrap().offset,
+               Offset::Variable(Variable::from_valid_name("?offset")));
+}
+
+#[test]
+fn can_parse_distinct() {
+    let none = "[:find ?x :where [?x :foo/baz ?y]]";
+    assert!(parse_query(none).unwrap().distinct);
+
+    let off = "[:find ?x :where [?x :foo/baz ?y] :distinct false]";
+    assert!(!parse_query(off).unwrap().distinct);
+
+    let on = "[:find ?x :distinct true :where [?x :foo/baz ?y]]";
+    assert!(parse_query(on).unwrap().distinct);
+
+    let invalid = "[:find ?x :where [?x :foo/baz ?y] :distinct 0]";
+    assert!(parse_query(invalid).is_err());
+
+    let repeated_invalid = "[:find ?x :where [?x :foo/baz ?y] :distinct false :distinct false]";
+    assert!(parse_query(repeated_invalid).is_err());
+}
+
+#[test]
+fn can_detect_aggregates() {
+    for s in &["[:find (count ?x) . :where [?x :foo/baz ?y]]",
+               "[:find ?y (sum ?x) :where [?x :foo/baz ?y]]",
+               "[:find [(min ?y) (max ?y) (avg ?y)] :where [?x :foo/baz ?y]]"] {
+        assert!(parse_query(s).unwrap().find_spec.has_aggregates());
+    }
+
+    for s in &["[:find ?x . :where [?x :foo/baz ?y]]",
+               "[:find ?x ?y :where [?x :foo/baz ?y]]"] {
+        assert!(!parse_query(s).unwrap().find_spec.has_aggregates());
+    }
+}
+
+#[test]
+fn can_parse_rules() {
+    let s = "[:find ?y :where (reachable ?x ?y) :rules [[(reachable ?a ?b) [?a :foo/link ?b]]]]";
+    let p = parse_query(s).expect("parsed");
+
+    let x = Variable::from_valid_name("?x");
+    let y = Variable::from_valid_name("?y");
+    let a = Variable::from_valid_name("?a");
+    let b = Variable::from_valid_name("?b");
+    assert_eq!(p.where_clauses,
+               vec![WhereClause::RuleExpr(RuleExpr {
+                   name: PlainSymbol::plain("reachable"),
+                   args: vec![FnArg::Variable(x), FnArg::Variable(y)],
+               })]);
+    assert_eq!(p.rules,
+               vec![Rule {
+                   name: PlainSymbol::plain("reachable"),
+                   vars: vec![a.clone(), b.clone()],
+                   clauses: vec![WhereClause::Pattern(Pattern {
+                       source: None,
+                       entity: PatternNonValuePlace::Variable(a),
+                       attribute: Keyword::namespaced("foo", "link").into(),
+                       value: PatternValuePlace::Variable(b),
+                       tx: PatternNonValuePlace::Placeholder,
+                       added: PatternValuePlace::Placeholder,
+                   })],
+               }]);
+
+    // A rule's head can't repeat a variable, and a rule needs a body.
+    assert!(parse_query("[:find ?x :where [?x _ _] :rules [[(r ?a ?a) [?a _ _]]]]").is_err());
+    assert!(parse_query("[:find ?x :where [?x _ _] :rules [[(r ?a)]]]").is_err());
+}
+
 #[test]
 fn can_parse_uuid() {
     let expected = edn::Uuid::parse_str("4cb3f828-752d-497a-90c9-b1fd516d5644").expect("valid uuid");
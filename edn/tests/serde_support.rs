@@ -54,3 +54,25 @@ fn test_deserialize_keyword() {
 
 
 
+#[cfg(feature = "serde_support")]
+#[test]
+fn test_value_to_json() {
+    let value = edn::parse::value(r#"{:name "Alice" :tags #{:a} :n 5N :when #inst "2017-04-28T20:23:05.187Z" :loc #myapp/Point [1.5 nil]}"#)
+        .unwrap()
+        .without_spans();
+    let json = serde_json::to_value(&value).unwrap();
+    assert_eq!(json, serde_json::from_str::<serde_json::Value>(r#"{
+        ":name": "Alice",
+        ":tags": [":a"],
+        ":n": "5",
+        ":when": "2017-04-28T20:23:05.187Z",
+        ":loc": [1.5, null]
+    }"#).unwrap());
+}
+
+#[cfg(feature = "serde_support")]
+#[test]
+fn test_value_from_json() {
+    let value: edn::Value = serde_json::from_str(r#"{"a": [1, -2, 2.5, true, null, 18446744073709551615]}"#).unwrap();
+    assert_eq!(value, edn::parse::value(r#"{"a" [1 -2 2.5 true nil 18446744073709551615N]}"#).unwrap().without_spans());
+}
@@ -274,6 +274,25 @@ fn test_uuid() {
     assert_eq!(value.to_pretty(100).unwrap(), s);
 }
 
+#[test]
+fn test_bytes() {
+    assert!(parse::bytes("#bytes\"3q2+7w==\"").is_err());        // No whitespace.
+    assert!(parse::bytes("#bytes \"3q2+7w=\"").is_err());        // Bad padding.
+    assert!(parse::bytes("#bytes \"3q2-7w==\"").is_err());       // Not base64.
+    assert!(parse::bytes("\"3q2+7w==\"").is_err());              // No tag.
+
+    let s = "#bytes \"3q2+7w==\"";
+    let actual = parse::bytes(s)
+                       .expect("parse success")
+                       .into();
+    let value = self::Value::Bytes(vec![0xde, 0xad, 0xbe, 0xef]);
+    assert_eq!(value, actual);
+    assert_eq!(format!("{}", value), s);
+    assert_eq!(value.to_pretty(100).unwrap(), s);
+
+    assert_eq!(parse::value("#bytes \"\"").unwrap().without_spans(), Value::Bytes(vec![]));
+}
+
 #[test]
 fn test_inst() {
     assert!(parse::value("#inst\"2016-01-01T11:00:00.000Z\"").is_err());   // No whitespace.
@@ -358,6 +377,17 @@ fn test_text() {
                });
 }
 
+#[test]
+fn test_text_escapes() {
+    assert_eq!(text(r#""tab\tnewline\nreturn\r""#).unwrap(), Value::Text("tab\tnewline\nreturn\r".to_string()));
+
+    // Writing text escapes whatever the parser unescapes.
+    let raw = Value::Text("\"quoted\"\tand\\slashed\n".to_string());
+    assert_eq!(raw.to_string(), r#""\"quoted\"\tand\\slashed\n""#);
+    assert_eq!(raw.to_pretty(80).unwrap(), raw.to_string());
+    assert_eq!(value(raw.to_string().as_str()).unwrap(), raw);
+}
+
 #[test]
 fn test_span_text() {
     assert_eq!(parse::value("\"hello world\"").unwrap(), ValueAndSpan {
@@ -1508,6 +1538,71 @@ fn test_is_and_as_type_helper_functions() {
     }
 }
 
+#[test]
+fn test_tagged() {
+    let point = Value::Tagged(symbols::NamespacedSymbol::namespaced("myapp", "Point"),
+                              Box::new(Value::Vector(vec![Value::Integer(1), Value::Integer(2)])));
+    assert_eq!(value("#myapp/Point [1 2]").unwrap(), point);
+    assert_eq!(value("#myapp/Point[1 2]").unwrap(), point);
+    assert_eq!(value("[#myapp/Point [1 2]]").unwrap(), Value::Vector(vec![point.clone()]));
+    assert!(point.is_tagged());
+
+    // Round trips.
+    assert_eq!(point.to_string(), "#myapp/Point [ 1 2 ]");
+    assert_eq!(point.to_pretty(80).unwrap(), "#myapp/Point [1 2]");
+    assert_eq!(value(point.to_string().as_str()).unwrap(), point);
+
+    // Tags can be nested.
+    let nested = value("#a/b #c/d nil").unwrap();
+    assert_eq!(nested.as_tagged().map(|(t, v)| (t.to_string(), v.is_tagged())),
+               Some(("a/b".to_string(), true)));
+
+    // Tags without a namespace are reserved.
+    assert!(value("#point [1 2]").is_err());
+    assert!(value("#myapp/Point").is_err());
+}
+
+#[test]
+fn test_tag_readers() {
+    let mut readers = edn::TagReaders::new();
+    let point = symbols::NamespacedSymbol::namespaced("myapp", "Point");
+    readers.register(point.clone(), |v| {
+        match v {
+            Value::Vector(ref xs) if xs.len() == 2 => Ok(Value::Map(BTreeMap::from_iter(vec![
+                (k_plain("x"), xs[0].clone()),
+                (k_plain("y"), xs[1].clone()),
+            ]))),
+            _ => Err("expected [x y]".to_string()),
+        }
+    });
+    assert!(readers.is_registered(&point));
+
+    let resolved = readers.resolve(value("[#myapp/Point [1 2] #myapp/Other 3]").unwrap()).unwrap();
+    assert_eq!(resolved, value("[{:x 1 :y 2} #myapp/Other 3]").unwrap());
+
+    let error = readers.resolve(value("{:p #myapp/Point [1]}").unwrap()).unwrap_err();
+    assert_eq!(error.tag, point);
+    assert_eq!(error.to_string(), "couldn't read #myapp/Point: expected [x y]");
+
+    assert!(readers.unregister(&point));
+    assert!(readers.resolve(value("#myapp/Point [1 2]").unwrap()).unwrap().is_tagged());
+}
+
+#[test]
+fn test_value_from() {
+    let built: Value = vec![
+        Value::from(true),
+        5i64.into(),
+        2.5.into(),
+        "text".into(),
+        symbols::Keyword::namespaced("foo", "bar").into(),
+        None::<i64>.into(),
+        BTreeSet::from_iter(vec![Value::from(1)]).into(),
+        BTreeMap::from_iter(vec![(Value::from("k"), Value::from(Some("v")))]).into(),
+    ].into();
+    assert_eq!(built, value(r#"[true 5 2.5 "text" :foo/bar nil #{1} {"k" "v"}]"#).unwrap());
+}
+
 /*
 // Handy templates for creating test cases follow:
 
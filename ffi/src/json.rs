@@ -0,0 +1,135 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Query results encoded as JSON, so that a consumer can read a whole result set with one call
+//! over the FFI rather than one for each value.
+//!
+//! The encoding is stable: consumers may rely on it.  Results are an object with a single key
+//! naming the shape of the find spec, whose value holds the results in that shape:
+//!
+//! - `{"scalar": value}` or `{"scalar": null}`
+//! - `{"tuple": [value, ...]}` or `{"tuple": null}`
+//! - `{"coll": [value, ...]}`
+//! - `{"rel": [[value, ...], ...]}`, a list of rows
+//!
+//! Each value is an object with its `"type"` and its `"value"`:
+//!
+//! | type        | value                                          |
+//! |-------------|------------------------------------------------|
+//! | `"ref"`     | the entid, a number                            |
+//! | `"boolean"` | `true` or `false`                              |
+//! | `"long"`    | a number                                       |
+//! | `"double"`  | a number, or `null` for NaN and the infinities |
+//! | `"instant"` | microseconds since the Unix epoch, a number    |
+//! | `"string"`  | a string                                       |
+//! | `"keyword"` | a string, like `":foo/bar"`                    |
+//! | `"uuid"`    | a hyphenated string                            |
+//! | `"bigint"`  | a string of decimal digits                     |
+//! | `"bytes"`   | a string of lowercase hexadecimal digits       |
+//!
+//! Pulled attributes are objects whose keys are attribute keywords, like `":foo/bar"`, and
+//! whose values are values, lists of values, or nested pulled objects.
+
+use serde_json::{
+    Map,
+    Value,
+};
+
+use mentat::{
+    Binding,
+    QueryResults,
+    TypedValue,
+};
+
+fn typed(t: &str, value: Value) -> Value {
+    let mut map = Map::new();
+    map.insert("type".to_string(), Value::String(t.to_string()));
+    map.insert("value".to_string(), value);
+    Value::Object(map)
+}
+
+pub fn typed_value_to_json(value: &TypedValue) -> Value {
+    match value {
+        &TypedValue::Ref(e) => typed("ref", Value::from(e)),
+        &TypedValue::Boolean(b) => typed("boolean", Value::Bool(b)),
+        &TypedValue::Long(l) => typed("long", Value::from(l)),
+        &TypedValue::Double(d) => typed("double", Value::from(d.into_inner())),
+        &TypedValue::Instant(ref i) => {
+            let micros = i.timestamp() * 1_000_000 + i64::from(i.timestamp_subsec_micros());
+            typed("instant", Value::from(micros))
+        },
+        &TypedValue::String(ref s) => typed("string", Value::String(s.to_string())),
+        &TypedValue::Keyword(ref k) => typed("keyword", Value::String(k.to_string())),
+        &TypedValue::Uuid(ref u) => typed("uuid", Value::String(u.hyphenated().to_string())),
+        &TypedValue::BigInteger(ref b) => typed("bigint", Value::String(b.to_string())),
+        &TypedValue::Bytes(ref b) => {
+            let hex: String = b.iter().map(|byte| format!("{:02x}", byte)).collect();
+            typed("bytes", Value::String(hex))
+        },
+    }
+}
+
+pub fn binding_to_json(binding: &Binding) -> Value {
+    match binding {
+        &Binding::Scalar(ref value) => typed_value_to_json(value),
+        &Binding::Vec(ref values) => bindings_to_json(values.iter()),
+        &Binding::Map(ref map) => {
+            Value::Object(map.iter().map(|(k, v)| (k.to_string(), binding_to_json(v))).collect())
+        },
+        &Binding::Null => Value::Null,
+    }
+}
+
+fn bindings_to_json<'a, I>(bindings: I) -> Value where I: Iterator<Item=&'a Binding> {
+    Value::Array(bindings.map(binding_to_json).collect())
+}
+
+pub fn results_to_json(results: &QueryResults) -> Value {
+    let (shape, value) = match results {
+        &QueryResults::Scalar(ref binding) => {
+            ("scalar", binding.as_ref().map(binding_to_json).unwrap_or(Value::Null))
+        },
+        &QueryResults::Tuple(ref bindings) => {
+            ("tuple", bindings.as_ref().map(|bs| bindings_to_json(bs.iter())).unwrap_or(Value::Null))
+        },
+        &QueryResults::Coll(ref bindings) => ("coll", bindings_to_json(bindings.iter())),
+        &QueryResults::Rel(ref rel) => ("rel", Value::Array(rel.rows().map(|row| bindings_to_json(row.iter())).collect())),
+    };
+    let mut map = Map::new();
+    map.insert(shape.to_string(), value);
+    Value::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mentat::{
+        Keyword,
+        RelResult,
+    };
+
+    #[test]
+    fn test_results_to_json() {
+        let rel = RelResult {
+            width: 2,
+            values: vec![TypedValue::Ref(65536).into(), TypedValue::typed_string("a").into(),
+                         TypedValue::Ref(65537).into(), TypedValue::Keyword(Keyword::namespaced("foo", "bar").into()).into()],
+        };
+        assert_eq!(results_to_json(&QueryResults::Rel(rel)).to_string(),
+                   r#"{"rel":[[{"type":"ref","value":65536},{"type":"string","value":"a"}],[{"type":"ref","value":65537},{"type":"keyword","value":":foo/bar"}]]}"#);
+
+        assert_eq!(results_to_json(&QueryResults::Scalar(None)).to_string(), r#"{"scalar":null}"#);
+        assert_eq!(results_to_json(&QueryResults::Coll(vec![TypedValue::Long(i64::max_value()).into(), TypedValue::Boolean(true).into()])).to_string(),
+                   r#"{"coll":[{"type":"long","value":9223372036854775807},{"type":"boolean","value":true}]}"#);
+        assert_eq!(results_to_json(&QueryResults::Tuple(Some(vec![TypedValue::Bytes(vec![0, 255].into()).into()]))).to_string(),
+                   r#"{"tuple":[{"type":"bytes","value":"00ff"}]}"#);
+    }
+}
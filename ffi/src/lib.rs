@@ -72,6 +72,7 @@
 extern crate core;
 extern crate libc;
 extern crate mentat;
+extern crate serde_json;
 
 use core::fmt::Display;
 
@@ -122,6 +123,7 @@ pub use mentat::entity_builder::{
 };
 
 pub mod android;
+pub mod json;
 pub mod utils;
 
 pub use utils::strings::{
@@ -134,6 +136,7 @@ use utils::error::{
     ExternError,
     translate_result,
     translate_opt_result,
+    translate_string_result,
     translate_void_result,
 };
 
@@ -219,7 +222,7 @@ pub unsafe extern "C" fn store_open(uri: *const c_char, error: *mut ExternError)
 pub unsafe extern "C" fn store_open_encrypted(uri: *const c_char, key: *const c_char, error: *mut ExternError) -> *mut Store {
     let uri = c_char_to_string(uri);
     let key = c_char_to_string(key);
-    translate_result(Store::open_with_key(&uri, &key), error)
+    translate_result(Store::open_encrypted(&uri, &key), error)
 }
 
 // TODO: open empty
@@ -1521,6 +1524,21 @@ pub unsafe extern "C" fn query_builder_execute(query_builder: *mut QueryBuilder,
     translate_result(results, error)
 }
 
+/// Executes a query and returns its results, whatever the shape of its find spec, encoded as
+/// JSON.  See the [json](json) module for the encoding.
+///
+/// # Safety
+///
+/// Callers are responsible for managing the memory for the return value.
+/// `rust_c_string_destroy` is provided for releasing the memory for this pointer type.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_json(query_builder: *mut QueryBuilder, error: *mut ExternError) -> *mut c_char {
+    assert_not_null!(query_builder);
+    let query_builder = &mut *query_builder;
+    let results = query_builder.execute().map(|output| json::results_to_json(&output.results).to_string());
+    translate_string_result(results, error)
+}
+
 fn unwrap_conversion<T>(value: Option<T>, expected_type: ValueType) -> T {
     match value {
         Some(v) => v,
@@ -1652,12 +1670,26 @@ pub unsafe extern "C" fn typed_value_into_uuid(typed_value: *mut Binding) -> *mu
 }
 
 /// Returns the [ValueType](mentat::ValueType) of this [Binding](mentat::Binding).
+///
+/// # Panics
+///
+/// If the [Binding](mentat::Binding) is not scalar: use `typed_value_is_null` to check for
+/// a value left absent by an `optional` clause.
 #[no_mangle]
 pub unsafe extern "C" fn typed_value_value_type(typed_value: *mut Binding) -> ValueType {
     let typed_value = &*typed_value;
     typed_value.value_type().unwrap_or_else(|| panic!("Binding is not Scalar and has no ValueType"))
 }
 
+/// Returns 1 if this [Binding](mentat::Binding) is absent -- the variable was bound by an
+/// `optional` clause that didn't match -- and 0 otherwise.
+#[no_mangle]
+pub unsafe extern "C" fn typed_value_is_null(typed_value: *mut Binding) -> i32 {
+    assert_not_null!(typed_value);
+    let typed_value = &*typed_value;
+    if typed_value.is_null() { 1 } else { 0 }
+}
+
 /// Returns the value at the provided `index` as a `Vec<ValueType>`.
 /// If there is no value present at the `index`, a null pointer is returned.
 ///
@@ -1754,6 +1786,19 @@ pub unsafe extern "C" fn value_at_index(values: *mut Vec<Binding>, index: c_int)
     }
 }
 
+/// Returns 1 if the [Binding](mentat::Binding) at `index` is absent, and 0 otherwise.
+///
+/// # Panics
+///
+/// If there is no value at `index`.
+#[no_mangle]
+pub unsafe extern "C" fn value_at_index_is_null(values: *mut Vec<Binding>, index: c_int) -> i32 {
+    assert_not_null!(values);
+    let result = &*values;
+    let value = result.get(index as usize).expect("No value at index");
+    if value.is_null() { 1 } else { 0 }
+}
+
 /// Returns the value of the [Binding](mentat::Binding) at `index` as a `long`.
 ///
 /// # Panics
@@ -146,6 +146,22 @@ pub mod error {
         }
     }
 
+    /// Translate Result<String, E> into a C string, which the caller frees with
+    /// `rust_c_string_destroy`.  Errors are handled as in `translate_result`.
+    pub unsafe fn translate_string_result<E>(result: Result<String, E>, error: *mut ExternError) -> *mut c_char
+    where E: Display {
+        assert!(!error.is_null(), "Error output parameter is not optional");
+        let error = &mut *error;
+        error.message = ptr::null_mut();
+        match result {
+            Ok(s) => string_to_c_char(s),
+            Err(e) => {
+                error.message = string_to_c_char(e.to_string());
+                ptr::null_mut()
+            }
+        }
+    }
+
     /// Identical to `translate_result`, but with additional type checking for the case that we have
     /// a `Result<(), E>` (which we're about to drop on the floor).
     pub unsafe fn translate_void_result<E>(result: Result<(), E>, error: *mut ExternError) where E: Display {
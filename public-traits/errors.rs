@@ -13,8 +13,10 @@
 use std; // To refer to std::result::Result.
 
 use std::collections::BTreeSet;
+#[cfg(feature = "rusqlite")]
 use std::error::Error;
 
+#[cfg(feature = "rusqlite")]
 use rusqlite;
 use uuid;
 
@@ -23,6 +25,7 @@ use edn;
 use core_traits::{
     Attribute,
     ValueType,
+    ValueTypeSet,
 };
 
 use db_traits::errors::{
@@ -62,9 +65,21 @@ pub enum MentatError {
     #[fail(display = "path {} already exists", _0)]
     PathAlreadyExists(String),
 
+    #[fail(display = "path {} does not exist", _0)]
+    PathDoesNotExist(String),
+
+    #[fail(display = "{} is encrypted, or is not a database; open it with its key", _0)]
+    EncryptionKeyRequired(String),
+
+    #[fail(display = "wrong key for {}, or it is not a database", _0)]
+    IncorrectEncryptionKey(String),
+
     #[fail(display = "variables {:?} unbound at query execution time", _0)]
     UnboundVariables(BTreeSet<String>),
 
+    #[fail(display = "value of type {} provided for var {}, expected one of {:?}", _2, _0, _1)]
+    ParameterTypeMismatch(String, ValueTypeSet, ValueType),
+
     #[fail(display = "invalid argument name: '{}'", _0)]
     InvalidArgumentName(String),
 
@@ -74,6 +89,9 @@ pub enum MentatError {
     #[fail(display = "invalid vocabulary version")]
     InvalidVocabularyVersion,
 
+    #[fail(display = "vocabulary {} has a migration to version {}, but is only at version {}", _0, _1, _2)]
+    InvalidMigrationVersion(String, u32, u32),
+
     #[fail(display = "vocabulary {}/version {} already has attribute {}, and the requested definition differs", _0, _1, _2)]
     ConflictingAttributeDefinitions(String, u32, String, Attribute, Attribute),
 
@@ -86,15 +104,44 @@ pub enum MentatError {
     #[fail(display = "Lost the transact() race!")]
     UnexpectedLostTransactRace,
 
+    /// Another write transaction is open against this store.  Includes the holding thread, how
+    /// long the transaction has been open, and, in debug builds, where it was begun.
+    #[fail(display = "write transaction already in progress on thread {}, open for {:?}", _0, _1)]
+    WriteTransactionInProgress(String, std::time::Duration, Option<String>),
+
     #[fail(display = "missing core attribute {}", _0)]
     MissingCoreVocabulary(edn::query::Keyword),
 
     #[fail(display = "schema changed since query was prepared")]
     PreparedQuerySchemaMismatch,
 
+    #[fail(display = "query can't be answered from the attribute cache alone")]
+    UncachedQuery,
+
+    #[fail(display = "only stores backed by a file can be snapshotted")]
+    SnapshotUnavailable,
+
+    #[fail(display = "only stores backed by a file can be shared")]
+    SharedStoreUnavailable,
+
+    /// Another connection to this store changed the definitions of these attributes in a way that
+    /// might invalidate what this connection was doing with them: it removed them, or changed
+    /// their value types.  The new schema has been loaded, so trying again will use it.
+    #[fail(display = "schema changed incompatibly by another connection: {:?}", _0)]
+    IncompatibleSchemaChange(Vec<String>),
+
     #[fail(display = "provided value of type {} doesn't match attribute value type {}", _0, _1)]
     ValueTypeMismatch(ValueType, ValueType),
 
+    #[fail(display = "expected a map of attribute values")]
+    ExpectedEntityMap,
+
+    #[fail(display = "no value for attribute {}", _0)]
+    MissingEntityAttribute(String),
+
+    #[fail(display = "value of attribute {} is not of type {}", _0, _1)]
+    UnexpectedEntityValue(String, ValueType),
+
     #[fail(display = "{}", _0)]
     IoError(#[cause] std::io::Error),
 
@@ -152,6 +199,7 @@ impl From<std::io::Error> for MentatError {
     }
 }
 
+#[cfg(feature = "rusqlite")]
 impl From<rusqlite::Error> for MentatError {
     fn from(error: rusqlite::Error) -> MentatError {
         let cause = match error.cause() {
@@ -40,6 +40,9 @@ pub enum BindingError {
     /// potentially erroneous) bindings.
     ExpectedBindRelOrBindColl,
 
+    /// Expected `?x` but got some other type of binding.
+    ExpectedBindScalar,
+
     /// Expected `[?x1 … ?xN]` or `[[?x1 … ?xN]]` but got some other number of bindings.  Mentat is
     /// deliberately more strict than Datomic: we prefer placeholders to omission.
     InvalidNumberOfBindings { number: usize, expected: usize },
@@ -72,6 +75,9 @@ pub enum AlgebrizerError {
     #[fail(display = "invalid limit {} of type {}: expected natural number.", _0, _1)]
     InvalidLimit(String, ValueType),
 
+    #[fail(display = "invalid offset {} of type {}: expected non-negative integer.", _0, _1)]
+    InvalidOffset(String, ValueType),
+
     #[fail(display = "mismatched bindings in ground")]
     GroundBindingsMismatch,
 
@@ -81,12 +87,27 @@ pub enum AlgebrizerError {
     #[fail(display = "no function named {}", _0)]
     UnknownFunction(PlainSymbol),
 
+    #[fail(display = "no rule named {}", _0)]
+    UnknownRule(PlainSymbol),
+
+    #[fail(display = "invalid rule {}: {}", _0, _1)]
+    InvalidRule(PlainSymbol, &'static str),
+
+    #[fail(display = "unsupported rule {}: {}", _0, _1)]
+    UnsupportedRule(PlainSymbol, &'static str),
+
     #[fail(display = ":limit var {} not present in :in", _0)]
     UnknownLimitVar(PlainSymbol),
 
+    #[fail(display = ":offset var {} not present in :in", _0)]
+    UnknownOffsetVar(PlainSymbol),
+
     #[fail(display = "unbound variable {} in order clause or function call", _0)]
     UnboundVariable(PlainSymbol),
 
+    #[fail(display = "can't order by {}: expected an aggregate from :find, or arithmetic", _0)]
+    InvalidOrderExpression(String),
+
     // TODO: flesh out.
     #[fail(display = "non-matching variables in 'or' clause")]
     NonMatchingVariablesInOrClause,
@@ -97,6 +118,24 @@ pub enum AlgebrizerError {
     #[fail(display = "binding error in {}: {:?}", _0, _1)]
     InvalidBinding(PlainSymbol, BindingError),
 
+    #[fail(display = "unsupported source {}: only $, and $history in patterns, can be queried", _0)]
+    UnsupportedSource(String),
+
+    #[fail(display = "only patterns against $history can match whether a datom was added")]
+    AddedWithoutHistory,
+
+    #[fail(display = "no values provided for :in binding of {}", _0)]
+    UnboundInputBinding(String),
+
+    #[fail(display = "input {} can't be bound to the {} column", _0, _1)]
+    UnbindableInput(PlainSymbol, String),
+
+    #[fail(display = "expected {} values in each row for :in binding of {}, got {}", _1, _0, _2)]
+    InputBindingMismatch(String, usize, usize),
+
+    #[fail(display = "lookup ref attribute {} is not :db/unique", _0)]
+    NonUniqueLookupRef(String),
+
     #[fail(display = "{}", _0)]
     EdnParseError(#[cause] ParseError),
 }
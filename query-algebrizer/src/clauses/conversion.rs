@@ -0,0 +1,216 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use core_traits::{
+    TypedValue,
+    ValueType,
+    ValueTypeSet,
+};
+
+use edn::{
+    ToMicros,
+};
+
+use edn::query::{
+    Binding,
+    FnArg,
+    NonIntegerConstant,
+    WhereFn,
+};
+
+use clauses::{
+    ConjoiningClauses,
+};
+
+use query_algebrizer_traits::errors::{
+    AlgebrizerError,
+    BindingError,
+    Result,
+};
+
+use types::{
+    Column,
+};
+
+use Known;
+
+/// The types that `long` and `double` accept.  Instants are stored as microseconds since the
+/// epoch, so they convert like longs.
+fn convertible_types() -> ValueTypeSet {
+    ValueTypeSet::of_numeric_types().union(&ValueTypeSet::of_one(ValueType::Instant))
+}
+
+/// Convert a constant just as SQLite's `CAST` would.
+fn convert(value: TypedValue, target: ValueType) -> Option<TypedValue> {
+    let long = match value {
+        TypedValue::Long(v) => v,
+        TypedValue::Instant(v) => v.to_micros(),
+        TypedValue::Double(v) => {
+            return Some(match target {
+                ValueType::Double => TypedValue::Double(v),
+                // Truncate towards zero.
+                _ => TypedValue::Long(v.into_inner() as i64),
+            });
+        },
+        _ => return None,
+    };
+    Some(match target {
+        ValueType::Double => TypedValue::Double((long as f64).into()),
+        _ => TypedValue::Long(long),
+    })
+}
+
+/// Application of conversion functions.
+impl ConjoiningClauses {
+    /// `[(long ?x) ?y]` binds `?y` to the value of `?x` as a long; `[(double ?x) ?y]` binds it to
+    /// the value as a double.  `?x` may be a long, a double, or an instant, which converts to
+    /// microseconds since the epoch.  Doubles convert to longs by truncating towards zero.
+    ///
+    /// When `?x` is bound to a column the conversion is a SQL `CAST`; when it's a constant or a
+    /// bound input we convert it here.
+    pub(crate) fn apply_conversion(&mut self, known: Known, target: ValueType, where_fn: WhereFn) -> Result<()> {
+        if where_fn.args.len() != 1 {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(where_fn.operator.clone(), where_fn.args.len(), 1));
+        }
+
+        let var = match where_fn.binding {
+            Binding::BindScalar(var) => var,
+            _ => bail!(AlgebrizerError::InvalidBinding(where_fn.operator.clone(), BindingError::ExpectedBindScalar)),
+        };
+
+        let supported = convertible_types();
+        let arg = where_fn.args.into_iter().next().unwrap();
+        let constant = match arg {
+            FnArg::Variable(ref v) => self.bound_value(v),
+            FnArg::EntidOrInteger(i) => Some(TypedValue::Long(i)),
+            FnArg::Constant(NonIntegerConstant::Float(f)) => Some(TypedValue::Double(f)),
+            FnArg::Constant(NonIntegerConstant::Instant(v)) => Some(TypedValue::Instant(v)),
+            _ => bail!(AlgebrizerError::InvalidArgumentType(where_fn.operator.clone(), supported, 0)),
+        };
+
+        // Whatever we convert, the result has the target type.
+        self.constrain_var_to_type(var.clone(), target);
+
+        if let Some(value) = constant {
+            let value_type = value.value_type();
+            match convert(value, target) {
+                Some(converted) => {
+                    if self.is_known_empty() {
+                        return Ok(());
+                    }
+                    return self.apply_ground_value(var, converted);
+                },
+                None => {
+                    let name = arg.as_variable().map(|v| v.name()).unwrap_or(where_fn.operator.clone());
+                    bail!(AlgebrizerError::InputTypeDisagreement(name, ValueType::Long, value_type));
+                },
+            }
+        }
+
+        // Otherwise, the argument is a variable that must already be bound to a column.
+        let source = arg.as_variable().cloned().expect("only variables remain");
+        if self.known_type_set(&source).intersection(&supported).is_empty() {
+            bail!(AlgebrizerError::InvalidArgumentType(where_fn.operator.clone(), supported, 0));
+        }
+        self.add_type_requirement(source.clone(), supported);
+
+        let column = self.column_bindings
+                         .get(&source)
+                         .and_then(|columns| columns.first().cloned())
+                         .ok_or_else(|| AlgebrizerError::UnboundVariable(source.name()))?;
+
+        if self.is_known_empty() {
+            return Ok(());
+        }
+
+        let table = column.0;
+        self.bind_column_to_var(known.schema, table, Column::Cast(Box::new(column.1), target), var)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    use core_traits::{
+        Attribute,
+    };
+
+    use mentat_core::{
+        Schema,
+    };
+
+    use edn::query::{
+        Keyword,
+        PlainSymbol,
+        Variable,
+    };
+
+    use clauses::{
+        add_attribute,
+        associate_ident,
+    };
+
+    use types::{
+        DatomsColumn,
+        QualifiedAlias,
+    };
+
+    use parse_find_string;
+    use algebrize;
+
+    fn prepopulated_schema() -> Schema {
+        let mut schema = Schema::default();
+        associate_ident(&mut schema, Keyword::namespaced("foo", "age"), 99);
+        add_attribute(&mut schema, 99, Attribute {
+            value_type: ValueType::Double,
+            ..Default::default()
+        });
+        schema
+    }
+
+    #[test]
+    fn test_apply_conversion() {
+        let schema = prepopulated_schema();
+        let known = Known::for_schema(&schema);
+
+        let query = r#"[:find ?years :where [?x :foo/age ?age] [(long ?age) ?years]]"#;
+        let cc = algebrize(known, parse_find_string(query).expect("parsed")).expect("algebrized").cc;
+        let years = Variable::from_valid_name("?years");
+        assert_eq!(cc.column_bindings.get(&years).expect("bound").clone(),
+                   vec![QualifiedAlias("datoms00".to_string(),
+                                       Column::Cast(Box::new(Column::Fixed(DatomsColumn::Value)), ValueType::Long))]);
+        assert_eq!(cc.known_type(&years), Some(ValueType::Long));
+
+        // Constants are converted right away.
+        let query = r#"[:find ?y :where [(long 2.9) ?y]]"#;
+        let cc = algebrize(known, parse_find_string(query).expect("parsed")).expect("algebrized").cc;
+        assert_eq!(cc.bound_value(&Variable::from_valid_name("?y")), Some(TypedValue::Long(2)));
+
+        let query = r#"[:find ?y :where [(double 3) ?y]]"#;
+        let cc = algebrize(known, parse_find_string(query).expect("parsed")).expect("algebrized").cc;
+        assert_eq!(cc.bound_value(&Variable::from_valid_name("?y")), Some(TypedValue::Double(3.0.into())));
+
+        // Strings don't convert.
+        let query = r#"[:find ?y :where [(long "3") ?y]]"#;
+        match algebrize(known, parse_find_string(query).expect("parsed")) {
+            Err(AlgebrizerError::InvalidArgumentType(op, _, 0)) => assert_eq!(op, PlainSymbol::plain("long")),
+            _ => panic!("expected an error"),
+        }
+
+        // The result must be a single variable.
+        let query = r#"[:find ?y :where [?x :foo/age ?age] [(long ?age) [?y ...]]]"#;
+        match algebrize(known, parse_find_string(query).expect("parsed")) {
+            Err(AlgebrizerError::InvalidBinding(_, BindingError::ExpectedBindScalar)) => {},
+            _ => panic!("expected an error"),
+        }
+    }
+}
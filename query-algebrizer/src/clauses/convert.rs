@@ -81,19 +81,16 @@ impl ValueTypes for FnArg {
                     ValueTypeSet::any()
                 },
 
-                &FnArg::Constant(NonIntegerConstant::BigInteger(_)) => {
-                    // Not yet implemented.
-                    bail!(AlgebrizerError::UnsupportedArgument)
-                },
-
                 // These don't make sense here. TODO: split FnArg into scalar and non-scalar…
                 &FnArg::Vector(_) |
                 &FnArg::SrcVar(_) => bail!(AlgebrizerError::UnsupportedArgument),
 
                 // These are all straightforward.
+                &FnArg::Constant(NonIntegerConstant::BigInteger(_)) => ValueTypeSet::of_one(ValueType::BigInteger),
                 &FnArg::Constant(NonIntegerConstant::Boolean(_)) => ValueTypeSet::of_one(ValueType::Boolean),
                 &FnArg::Constant(NonIntegerConstant::Instant(_)) => ValueTypeSet::of_one(ValueType::Instant),
                 &FnArg::Constant(NonIntegerConstant::Uuid(_)) => ValueTypeSet::of_one(ValueType::Uuid),
+                &FnArg::Constant(NonIntegerConstant::Bytes(_)) => ValueTypeSet::of_one(ValueType::Bytes),
                 &FnArg::Constant(NonIntegerConstant::Float(_)) => ValueTypeSet::of_one(ValueType::Double),
                 &FnArg::Constant(NonIntegerConstant::Text(_)) => ValueTypeSet::of_one(ValueType::String),
             })
@@ -213,14 +210,14 @@ impl ConjoiningClauses {
                 }
             },
 
-            // This isn't implemented yet.
-            FnArg::Constant(NonIntegerConstant::BigInteger(_)) => unimplemented!(),
-
             // These don't make sense here.
             FnArg::Vector(_) |
             FnArg::SrcVar(_) => bail!(AlgebrizerError::InvalidGroundConstant),
 
             // These are all straightforward.
+            FnArg::Constant(NonIntegerConstant::BigInteger(x)) => {
+                coerce_to_typed_value!(var, x, known_types, ValueType::BigInteger, TypedValue::BigInteger)
+            },
             FnArg::Constant(NonIntegerConstant::Boolean(x)) => {
                 coerce_to_typed_value!(var, x, known_types, ValueType::Boolean, TypedValue::Boolean)
             },
@@ -230,6 +227,9 @@ impl ConjoiningClauses {
             FnArg::Constant(NonIntegerConstant::Uuid(x)) => {
                 coerce_to_typed_value!(var, x, known_types, ValueType::Uuid, TypedValue::Uuid)
             },
+            FnArg::Constant(NonIntegerConstant::Bytes(x)) => {
+                coerce_to_typed_value!(var, x, known_types, ValueType::Bytes, TypedValue::Bytes)
+            },
             FnArg::Constant(NonIntegerConstant::Float(x)) => {
                 coerce_to_typed_value!(var, x, known_types, ValueType::Double, TypedValue::Double)
             },
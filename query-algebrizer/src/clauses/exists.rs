@@ -0,0 +1,267 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use core_traits::{
+    ValueType,
+};
+
+use edn::query::{
+    ContainsVariables,
+    Pattern,
+    PatternNonValuePlace,
+    PatternValuePlace,
+    SrcVar,
+    Variable,
+    WhereClause,
+};
+
+use clauses::ConjoiningClauses;
+
+use query_algebrizer_traits::errors::{
+    Result,
+};
+
+use types::{
+    ColumnConstraint,
+    ComputedTable,
+};
+
+use Known;
+
+/// Patterns that together only check that something exists, and the variables through which they
+/// refer to the rest of the query.
+struct Existential {
+    patterns: Vec<usize>,
+    unified: BTreeSet<Variable>,
+    types: Vec<(Variable, ValueType)>,
+}
+
+fn is_against_datoms(pattern: &Pattern) -> bool {
+    match pattern.source {
+        None | Some(SrcVar::DefaultSrc) => true,
+        _ => false,
+    }
+}
+
+/// Patterns against cached attributes can often be answered from the cache without a join at all,
+/// so we leave them where they are.
+fn uses_cached_attribute(known: Known, pattern: &Pattern) -> bool {
+    let attribute = match pattern.attribute {
+        PatternNonValuePlace::Ident(ref ident) => known.entid_for_ident(ident).map(|entid| entid.0),
+        PatternNonValuePlace::Entid(entid) => Some(entid),
+        _ => None,
+    };
+    attribute.map_or(false, |a| known.is_attribute_cached_forward(a) || known.is_attribute_cached_reverse(a))
+}
+
+/// The type each of `vars` must have where it appears in `pattern`, or `None` if we can't tell.
+fn types_in_pattern(known: Known, pattern: &Pattern, vars: &BTreeSet<Variable>) -> Option<Vec<(Variable, ValueType)>> {
+    let mut types = vec![];
+    for place in [&pattern.entity, &pattern.attribute, &pattern.tx].iter() {
+        if let &&PatternNonValuePlace::Variable(ref v) = place {
+            if vars.contains(v) {
+                types.push((v.clone(), ValueType::Ref));
+            }
+        }
+    }
+    if let PatternValuePlace::Variable(ref v) = pattern.value {
+        if vars.contains(v) {
+            let attribute = match pattern.attribute {
+                PatternNonValuePlace::Ident(ref ident) => known.entid_for_ident(ident).and_then(|entid| known.attribute_facts(entid.0)),
+                PatternNonValuePlace::Entid(entid) => known.attribute_facts(entid),
+                _ => None,
+            };
+            types.push((v.clone(), attribute?.value_type));
+        }
+    }
+    Some(types)
+}
+
+impl ConjoiningClauses {
+    /// Apply the `:where` clauses of a query whose results depend only on `needed`.
+    ///
+    /// Patterns that join to the rest of the query only through other patterns' variables, and
+    /// otherwise bind variables nothing else uses, only check that something exists.  Joining
+    /// them in yields a row for each of the things that exist, all but one of which `DISTINCT` then
+    /// throws away; instead we test for them with a correlated `EXISTS` subquery.
+    pub(crate) fn apply_where_clauses(&mut self, known: Known, needed: &BTreeSet<Variable>, where_clauses: Vec<WhereClause>) -> Result<()> {
+        let existentials = self.find_existentials(known, needed, &where_clauses);
+        if existentials.is_empty() {
+            return self.apply_clauses(known, where_clauses);
+        }
+
+        // Types that joining the patterns would have imposed on the rest of the query.
+        for existential in existentials.iter() {
+            for &(ref var, value_type) in existential.types.iter() {
+                self.constrain_var_to_type(var.clone(), value_type);
+            }
+        }
+
+        let moving: BTreeSet<usize> = existentials.iter().flat_map(|e| e.patterns.iter().cloned()).collect();
+        let mut moved: BTreeMap<usize, Pattern> = BTreeMap::new();
+        let mut outer = Vec::with_capacity(where_clauses.len());
+        for (i, clause) in where_clauses.into_iter().enumerate() {
+            if !moving.contains(&i) {
+                outer.push(clause);
+            } else if let WhereClause::Pattern(p) = clause {
+                moved.insert(i, p);
+            }
+        }
+
+        self.apply_clauses(known, outer)?;
+        for existential in existentials {
+            if self.is_known_empty() {
+                break;
+            }
+            let patterns = existential.patterns.iter().filter_map(|i| moved.remove(i)).collect();
+            self.apply_exists(known, existential.unified, patterns)?;
+        }
+        Ok(())
+    }
+
+    /// Find the groups of patterns in `where_clauses` that can be tested with `EXISTS`.  See
+    /// `apply_where_clauses`.
+    fn find_existentials(&self, known: Known, needed: &BTreeSet<Variable>, where_clauses: &[WhereClause]) -> Vec<Existential> {
+        let movable = |p: &Pattern| is_against_datoms(p) && !uses_cached_attribute(known, p);
+        let patterns: Vec<(usize, &Pattern)> = where_clauses.iter().enumerate().filter_map(|(i, clause)| match clause {
+            &WhereClause::Pattern(ref p) if movable(p) => Some((i, p)),
+            _ => None,
+        }).collect();
+        if patterns.len() < 3 {
+            // We need two patterns to move and one to stay.
+            return vec![];
+        }
+
+        // Variables that the query needs, that are already bound, or that anything other than these
+        // patterns uses, stay.
+        let mut fixed = needed.clone();
+        fixed.extend(self.input_variables.iter().cloned());
+        fixed.extend(self.value_bindings.keys().cloned());
+        fixed.extend(self.column_bindings.keys().cloned());
+        for clause in where_clauses.iter() {
+            match clause {
+                &WhereClause::Pattern(ref p) if movable(p) => {},
+                clause => clause.accumulate_mentioned_variables(&mut fixed),
+            }
+        }
+        let vars: Vec<BTreeSet<Variable>> = patterns.iter().map(|&(_, p)| p.collect_mentioned_variables()).collect();
+
+        // The first pattern that binds each variable the query needs stays, and so its other
+        // variables will be bound, too.  Later patterns that mention the variable can still move.
+        let mut anchored: BTreeSet<Variable> = BTreeSet::new();
+        for pattern_vars in vars.iter() {
+            if pattern_vars.iter().any(|v| needed.contains(v) && !anchored.contains(v)) {
+                anchored.extend(pattern_vars.iter().cloned());
+            }
+        }
+        fixed.extend(anchored.into_iter());
+
+        // Group the patterns that are joined by variables that don't stay.
+        let mut grouped = vec![false; patterns.len()];
+        let mut groups: Vec<Vec<usize>> = vec![];
+        for start in 0..patterns.len() {
+            if grouped[start] {
+                continue;
+            }
+            let mut members = vec![start];
+            grouped[start] = true;
+            let mut next = 0;
+            while next < members.len() {
+                let member = members[next];
+                next += 1;
+                for other in 0..patterns.len() {
+                    if !grouped[other] &&
+                       vars[member].intersection(&vars[other]).any(|v| !fixed.contains(v)) {
+                        grouped[other] = true;
+                        members.push(other);
+                    }
+                }
+            }
+            members.sort();
+            groups.push(members);
+        }
+
+        // A group can be moved if each variable it shares with the rest of the query is bound by
+        // a pattern that stays, or by an input.  Joining a single pattern multiplies rows no more
+        // than testing for it would, so we leave those be.
+        let mut stays = vec![true; patterns.len()];
+        let mut existentials: Vec<Existential> = vec![];
+        for members in groups.into_iter().filter(|members| members.len() > 1) {
+            let unified: BTreeSet<Variable> = members.iter()
+                                                     .flat_map(|&m| vars[m].iter())
+                                                     .filter(|v| fixed.contains(*v))
+                                                     .cloned()
+                                                     .collect();
+            let types = match members.iter()
+                                      .map(|&m| types_in_pattern(known, patterns[m].1, &unified))
+                                      .collect::<Option<Vec<_>>>() {
+                Some(types) => types.concat(),
+                None => continue,
+            };
+
+            for &m in members.iter() {
+                stays[m] = false;
+            }
+            existentials.push(Existential {
+                patterns: members.iter().map(|&m| patterns[m].0).collect(),
+                unified: unified,
+                types: types,
+            });
+
+            // Moving this group mustn't leave it, or one we moved before, with nothing to refer to.
+            let all_bound = {
+                let bound = |var: &Variable| {
+                    self.bound_value(var).is_some() ||
+                    self.column_bindings.contains_key(var) ||
+                    (0..patterns.len()).any(|i| stays[i] && vars[i].contains(var))
+                };
+                stays.iter().any(|&s| s) &&
+                existentials.iter().all(|e| e.unified.iter().all(|v| bound(v)))
+            };
+            if !all_bound {
+                for &m in members.iter() {
+                    stays[m] = true;
+                }
+                existentials.pop();
+            }
+        }
+        existentials
+    }
+
+    /// Require that `patterns` match, with `unified` -- already bound -- as they are here.
+    fn apply_exists(&mut self, known: Known, unified: BTreeSet<Variable>, patterns: Vec<Pattern>) -> Result<()> {
+        let mut template = self.use_as_correlated_template(&unified)?;
+        template.apply_clauses(known, patterns.into_iter().map(WhereClause::Pattern).collect())?;
+
+        if !template.is_known_empty() {
+            template.expand_column_bindings();
+        }
+        if !template.is_known_empty() {
+            template.prune_extracted_types();
+        }
+        if !template.is_known_empty() {
+            template.process_required_types()?;
+        }
+
+        // If nothing can match, then neither can we.
+        if let Some(because) = template.empty_because.clone() {
+            self.mark_known_empty(because);
+            return Ok(());
+        }
+
+        self.wheres.add_intersection(ColumnConstraint::Exists(ComputedTable::Subquery(template)));
+        Ok(())
+    }
+}
@@ -17,6 +17,9 @@ use mentat_core::{
     HasSchema,
 };
 
+use mentat_core::normalization::{
+    fold_for_fulltext,
+};
 use mentat_core::util::Either;
 
 use edn::query::{
@@ -202,13 +205,25 @@ impl ConjoiningClauses {
             _ => bail!(AlgebrizerError::InvalidArgument(where_fn.operator.clone(), "string", 2)),
         };
 
-        let qv = match search {
-            Either::Left(tv) => QueryValue::TypedValue(tv),
-            Either::Right(qa) => QueryValue::Column(qa),
+        // Values of :db/normalize attributes are searched in their folded form, so the search
+        // text is folded the same way.
+        let (search_column, qv) = if attribute.normalize {
+            let qv = match search {
+                Either::Left(TypedValue::String(s)) => QueryValue::TypedValue(TypedValue::typed_string(fold_for_fulltext(&s))),
+                Either::Left(tv) => QueryValue::TypedValue(tv),
+                Either::Right(qa) => QueryValue::Column(qa),
+            };
+            (FulltextColumn::Folded, qv)
+        } else {
+            let qv = match search {
+                Either::Left(tv) => QueryValue::TypedValue(tv),
+                Either::Right(qa) => QueryValue::Column(qa),
+            };
+            (FulltextColumn::Text, qv)
         };
 
         let constraint = ColumnConstraint::Matches(QualifiedAlias(fulltext_values_alias.clone(),
-                                                                  Column::Fulltext(FulltextColumn::Text)),
+                                                                  Column::Fulltext(search_column)),
                                                    qv);
         self.wheres.add_intersection(constraint);
 
@@ -219,7 +234,7 @@ impl ConjoiningClauses {
                 return Ok(());
             }
 
-            self.bind_column_to_var(schema, datoms_table_alias.clone(), DatomsColumn::Entity, var.clone());
+            self.bind_column_to_var(schema, datoms_table_alias.clone(), DatomsColumn::Entity, var.clone())?;
         }
 
         if let VariableOrPlaceholder::Variable(ref var) = b_value {
@@ -229,7 +244,7 @@ impl ConjoiningClauses {
                 return Ok(());
             }
 
-            self.bind_column_to_var(schema, fulltext_values_alias.clone(), Column::Fulltext(FulltextColumn::Text), var.clone());
+            self.bind_column_to_var(schema, fulltext_values_alias.clone(), Column::Fulltext(FulltextColumn::Text), var.clone())?;
         }
 
         if let VariableOrPlaceholder::Variable(ref var) = b_tx {
@@ -239,7 +254,7 @@ impl ConjoiningClauses {
                 return Ok(());
             }
 
-            self.bind_column_to_var(schema, datoms_table_alias.clone(), DatomsColumn::Tx, var.clone());
+            self.bind_column_to_var(schema, datoms_table_alias.clone(), DatomsColumn::Tx, var.clone())?;
         }
 
         if let VariableOrPlaceholder::Variable(ref var) = b_score {
@@ -250,10 +265,11 @@ impl ConjoiningClauses {
             if self.value_bindings.contains_key(var) || self.input_variables.contains(var) {
                 bail!(AlgebrizerError::InvalidBinding(var.name(), BindingError::UnexpectedBinding));
             }
+            if self.is_known_empty() {
+                return Ok(());
+            }
 
-            // We bind the value ourselves. This handily takes care of substituting into existing uses.
-            // TODO: produce real scores using SQLite's matchinfo.
-            self.bind_value(var, TypedValue::Double(0.0.into()));
+            self.bind_column_to_var(schema, fulltext_values_alias.clone(), Column::Fulltext(FulltextColumn::Score), var.clone())?;
         }
 
         Ok(())
@@ -339,7 +355,7 @@ mod testing {
                                                            QueryValue::TypedValue("needle".into())).into());
 
         let bindings = cc.column_bindings;
-        assert_eq!(bindings.len(), 3);
+        assert_eq!(bindings.len(), 4);
 
         assert_eq!(bindings.get(&Variable::from_valid_name("?entity")).expect("column binding for ?entity").clone(),
                    vec![QualifiedAlias("datoms01".to_string(), Column::Fixed(DatomsColumn::Entity))]);
@@ -347,11 +363,8 @@ mod testing {
                    vec![QualifiedAlias("fulltext_values00".to_string(), Column::Fulltext(FulltextColumn::Text))]);
         assert_eq!(bindings.get(&Variable::from_valid_name("?tx")).expect("column binding for ?tx").clone(),
                    vec![QualifiedAlias("datoms01".to_string(), Column::Fixed(DatomsColumn::Tx))]);
-
-        // Score is a value binding.
-        let values = cc.value_bindings;
-        assert_eq!(values.get(&Variable::from_valid_name("?score")).expect("column binding for ?score").clone(),
-                   TypedValue::Double(0.0.into()));
+        assert_eq!(bindings.get(&Variable::from_valid_name("?score")).expect("column binding for ?score").clone(),
+                   vec![QualifiedAlias("fulltext_values00".to_string(), Column::Fulltext(FulltextColumn::Score))]);
 
         let known_types = cc.known_types;
         assert_eq!(known_types.len(), 4);
@@ -0,0 +1,426 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::{
+    BTreeSet,
+};
+
+use core_traits::{
+    Attribute,
+    Entid,
+    TypedValue,
+    ValueType,
+    ValueTypeSet,
+};
+
+use mentat_core::{
+    HasSchema,
+    Schema,
+    SQLValueType,
+};
+
+use edn::query::{
+    Binding,
+    FnArg,
+    NotJoin,
+    Pattern,
+    PatternNonValuePlace,
+    PatternValuePlace,
+    PlainSymbol,
+    Predicate,
+    SrcVar,
+    UnifyVars,
+    VariableOrPlaceholder,
+    WhereClause,
+    WhereFn,
+};
+
+use clauses::{
+    ConjoiningClauses,
+};
+
+use clauses::convert::{
+    ValueConversion,
+};
+
+use query_algebrizer_traits::errors::{
+    AlgebrizerError,
+    BindingError,
+    Result,
+};
+
+use types::{
+    Column,
+    ColumnConstraint,
+    ColumnIntersection,
+    DatomsColumn,
+    DatomsTable,
+    LeftJoin,
+    QualifiedAlias,
+    QueryValue,
+    SourceAlias,
+    TableAlias,
+};
+
+use Known;
+
+/// Application of `get-else`, `get-some`, and `missing?`, which handle attributes that an entity
+/// might not have.
+impl ConjoiningClauses {
+    fn expect_default_source(&self, function: &PlainSymbol, arg: FnArg) -> Result<()> {
+        // TODO: process source variables.
+        match arg {
+            FnArg::SrcVar(SrcVar::DefaultSrc) => Ok(()),
+            _ => bail!(AlgebrizerError::InvalidArgument(function.clone(), "source variable", 0)),
+        }
+    }
+
+    /// Look up an attribute that we can `LEFT JOIN` against: at most one datom can match, so it
+    /// must be cardinality-one, and it mustn't be fulltext, because we want its value.
+    fn optional_attribute<'s>(&self, schema: &'s Schema, function: &PlainSymbol, position: usize, arg: FnArg) -> Result<(&'s Attribute, Entid)> {
+        let ident = match arg {
+            FnArg::IdentOrKeyword(ident) => ident,
+            _ => bail!(AlgebrizerError::InvalidArgument(function.clone(), "attribute", position)),
+        };
+        let (attribute, entid) = schema.attribute_for_ident(&ident)
+                                       .ok_or_else(|| AlgebrizerError::UnrecognizedIdent(ident.to_string()))?;
+        if attribute.multival || attribute.fulltext {
+            bail!(AlgebrizerError::InvalidArgument(function.clone(), "cardinality-one, non-fulltext attribute", position));
+        }
+        Ok((attribute, entid.into()))
+    }
+
+    /// `LEFT JOIN` the datom, if any, that gives `entity` a value for `attribute`.
+    fn left_join_attribute(&mut self, entity: QueryValue, attribute: Entid) -> TableAlias {
+        let datoms = self.next_alias_for_table(DatomsTable::Datoms);
+        let mut on = ColumnIntersection::default();
+        on.add_intersection(ColumnConstraint::Equals(QualifiedAlias::new(datoms.clone(), DatomsColumn::Entity), entity));
+        on.add_intersection(ColumnConstraint::Equals(QualifiedAlias::new(datoms.clone(), DatomsColumn::Attribute), QueryValue::Entid(attribute)));
+        self.left_joins.push(LeftJoin {
+            table: SourceAlias(DatomsTable::Datoms, datoms.clone()),
+            on,
+        });
+        datoms
+    }
+
+    /// `[(get-else $ ?e :foo/bar default) ?v]` binds `?v` to the value of `:foo/bar` for `?e`, or
+    /// to `default` if `?e` has none.  We `LEFT JOIN` the attribute's datom and `COALESCE` its
+    /// value with the default.
+    ///
+    /// The default needn't have the attribute's type, in which case `?v` might have either.
+    pub(crate) fn apply_get_else(&mut self, known: Known, where_fn: WhereFn) -> Result<()> {
+        if where_fn.args.len() != 4 {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(where_fn.operator.clone(), where_fn.args.len(), 4));
+        }
+
+        let var = match where_fn.binding {
+            Binding::BindScalar(var) => var,
+            _ => bail!(AlgebrizerError::InvalidBinding(where_fn.operator.clone(), BindingError::ExpectedBindScalar)),
+        };
+
+        let schema = known.schema;
+        let mut args = where_fn.args.into_iter();
+        self.expect_default_source(&where_fn.operator, args.next().unwrap())?;
+        let entity = self.resolve_ref_argument(schema, &where_fn.operator, 1, args.next().unwrap())?;
+        let (attribute, entid) = self.optional_attribute(schema, &where_fn.operator, 2, args.next().unwrap())?;
+        let default_arg = args.next().unwrap();
+
+        // Read an ambiguous default, like an integer that might be a ref, as the attribute's type
+        // if we can.
+        let attribute_types = ValueTypeSet::of_one(attribute.value_type);
+        let preferred = self.known_type_set(&var).intersection(&attribute_types);
+        let default = match self.typed_value_from_arg(schema, &var, default_arg.clone(), preferred)? {
+            ValueConversion::Val(value) => value,
+            ValueConversion::Impossible(_) => {
+                let known_types = self.known_type_set(&var);
+                match self.typed_value_from_arg(schema, &var, default_arg, known_types)? {
+                    ValueConversion::Val(value) => value,
+                    ValueConversion::Impossible(because) => {
+                        self.mark_known_empty(because);
+                        return Ok(());
+                    },
+                }
+            },
+        };
+
+        let default_type = default.value_type();
+        self.narrow_types_for_var(var.clone(), attribute_types.union(&ValueTypeSet::of_one(default_type)));
+        if self.is_known_empty() {
+            return Ok(());
+        }
+
+        let datoms = self.left_join_attribute(entity, entid);
+        let value = Column::Coalesce(vec![QualifiedAlias::new(datoms.clone(), DatomsColumn::Value)], Some(default));
+        self.bind_column_to_var(schema, datoms.clone(), value, var.clone())?;
+
+        // The default's type tag stands in for a missing datom's.
+        if self.known_type(&var).is_none() && !self.extracted_types.contains_key(&var) {
+            let tag = TypedValue::Long(default_type.value_type_tag().into());
+            let type_tag = Column::Coalesce(vec![QualifiedAlias::new(datoms.clone(), DatomsColumn::ValueTypeTag)], Some(tag));
+            self.extracted_types.insert(var, QualifiedAlias(datoms, type_tag));
+        }
+        Ok(())
+    }
+
+    /// `[(get-some $ ?e :foo/bar :foo/baz) [?a ?v]]` binds `?a` to the first of the given
+    /// attributes that `?e` has, and `?v` to its value.  Entities with none of the attributes
+    /// don't match.
+    ///
+    /// Each attribute is `LEFT JOIN`ed in turn, and we `COALESCE` their columns in order.
+    pub(crate) fn apply_get_some(&mut self, known: Known, where_fn: WhereFn) -> Result<()> {
+        if where_fn.args.len() < 3 {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(where_fn.operator.clone(), where_fn.args.len(), 3));
+        }
+
+        if where_fn.binding.is_empty() {
+            // The binding must introduce at least one bound variable.
+            bail!(AlgebrizerError::InvalidBinding(where_fn.operator.clone(), BindingError::NoBoundVariable));
+        }
+
+        if !where_fn.binding.is_valid() {
+            // The binding must not duplicate bound variables.
+            bail!(AlgebrizerError::InvalidBinding(where_fn.operator.clone(), BindingError::RepeatedBoundVariable));
+        }
+
+        let (attribute_place, value_place) = match where_fn.binding {
+            Binding::BindTuple(bindings) => {
+                let bindings_count = bindings.len();
+                if bindings_count != 2 {
+                    bail!(AlgebrizerError::InvalidBinding(where_fn.operator.clone(),
+                                                    BindingError::InvalidNumberOfBindings {
+                                                        number: bindings_count,
+                                                        expected: 2,
+                                                    }));
+                }
+                let mut bindings = bindings.into_iter();
+                (bindings.next().unwrap(), bindings.next().unwrap())
+            },
+            Binding::BindScalar(_) |
+            Binding::BindColl(_) |
+            Binding::BindRel(_) => {
+                bail!(AlgebrizerError::InvalidBinding(where_fn.operator.clone(), BindingError::InvalidNumberOfBindings {
+                    number: 1,
+                    expected: 2,
+                }))
+            },
+        };
+
+        let schema = known.schema;
+        let mut args = where_fn.args.into_iter();
+        self.expect_default_source(&where_fn.operator, args.next().unwrap())?;
+        let entity = self.resolve_ref_argument(schema, &where_fn.operator, 1, args.next().unwrap())?;
+
+        let mut attributes = Vec::with_capacity(args.len());
+        let mut value_types = ValueTypeSet::none();
+        for (i, arg) in args.enumerate() {
+            let (attribute, entid) = self.optional_attribute(schema, &where_fn.operator, i + 2, arg)?;
+            value_types = value_types.union(&ValueTypeSet::of_one(attribute.value_type));
+            attributes.push(entid);
+        }
+
+        let tables: Vec<TableAlias> = attributes.into_iter()
+                                                .map(|entid| self.left_join_attribute(entity.clone(), entid))
+                                                .collect();
+
+        // With one attribute there's nothing to choose between.
+        let first = |column: DatomsColumn| -> QualifiedAlias {
+            if tables.len() == 1 {
+                QualifiedAlias::new(tables[0].clone(), column)
+            } else {
+                let columns = tables.iter().map(|t| QualifiedAlias::new(t.clone(), column.clone())).collect();
+                QualifiedAlias(tables[0].clone(), Column::Coalesce(columns, None))
+            }
+        };
+
+        self.wheres.add_intersection(ColumnConstraint::NotNull(first(DatomsColumn::Attribute)));
+
+        if let VariableOrPlaceholder::Variable(var) = attribute_place {
+            self.constrain_var_to_type(var.clone(), ValueType::Ref);
+            if self.is_known_empty() {
+                return Ok(());
+            }
+            let QualifiedAlias(table, column) = first(DatomsColumn::Attribute);
+            self.bind_column_to_var(schema, table, column, var)?;
+        }
+
+        if let VariableOrPlaceholder::Variable(var) = value_place {
+            self.narrow_types_for_var(var.clone(), value_types);
+            if self.is_known_empty() {
+                return Ok(());
+            }
+            let QualifiedAlias(table, column) = first(DatomsColumn::Value);
+            self.bind_column_to_var(schema, table, column, var.clone())?;
+
+            // A single attribute's type tag is extracted as usual.
+            if self.known_type(&var).is_none() && !self.extracted_types.contains_key(&var) {
+                self.extracted_types.insert(var, first(DatomsColumn::ValueTypeTag));
+            }
+        }
+        Ok(())
+    }
+
+    /// `[(missing? $ ?e :foo/bar)]` matches when `?e` has no value for `:foo/bar`.  It's the same
+    /// as `(not [?e :foo/bar])`, so that's how we algebrize it: as a `NOT EXISTS`.
+    pub(crate) fn apply_missing(&mut self, known: Known, predicate: Predicate) -> Result<()> {
+        if predicate.args.len() != 3 {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(predicate.operator.clone(), predicate.args.len(), 3));
+        }
+
+        let operator = predicate.operator;
+        let mut args = predicate.args.into_iter();
+        self.expect_default_source(&operator, args.next().unwrap())?;
+
+        let mut unify_vars = BTreeSet::new();
+        let entity = match args.next().unwrap() {
+            FnArg::Variable(var) => {
+                unify_vars.insert(var.clone());
+                PatternNonValuePlace::Variable(var)
+            },
+            FnArg::EntidOrInteger(entid) => PatternNonValuePlace::Entid(entid),
+            FnArg::IdentOrKeyword(ident) => PatternNonValuePlace::Ident(ident.into()),
+            _ => bail!(AlgebrizerError::InvalidArgument(operator.clone(), "entity", 1)),
+        };
+
+        let attribute = match args.next().unwrap() {
+            FnArg::IdentOrKeyword(ident) => {
+                if known.schema.attribute_for_ident(&ident).is_none() {
+                    bail!(AlgebrizerError::UnrecognizedIdent(ident.to_string()));
+                }
+                PatternNonValuePlace::Ident(ident.into())
+            },
+            _ => bail!(AlgebrizerError::InvalidArgument(operator.clone(), "attribute", 2)),
+        };
+
+        let pattern = Pattern::simple(entity, attribute, PatternValuePlace::Placeholder)
+            .ok_or_else(|| AlgebrizerError::InvalidArgument(operator.clone(), "entity", 1))?;
+        let not_join = NotJoin::new(UnifyVars::Explicit(unify_vars), vec![WhereClause::Pattern(pattern)]);
+        self.apply_not_join(known, not_join)
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    use edn::query::{
+        Keyword,
+        Variable,
+    };
+
+    use clauses::{
+        add_attribute,
+        associate_ident,
+    };
+
+    use parse_find_string;
+    use algebrize;
+
+    fn prepopulated_schema() -> Schema {
+        let mut schema = Schema::default();
+        associate_ident(&mut schema, Keyword::namespaced("foo", "name"), 65);
+        associate_ident(&mut schema, Keyword::namespaced("foo", "nick"), 66);
+        associate_ident(&mut schema, Keyword::namespaced("foo", "age"), 67);
+        associate_ident(&mut schema, Keyword::namespaced("foo", "tag"), 68);
+        add_attribute(&mut schema, 65, Attribute {
+            value_type: ValueType::String,
+            ..Default::default()
+        });
+        add_attribute(&mut schema, 66, Attribute {
+            value_type: ValueType::String,
+            ..Default::default()
+        });
+        add_attribute(&mut schema, 67, Attribute {
+            value_type: ValueType::Long,
+            ..Default::default()
+        });
+        add_attribute(&mut schema, 68, Attribute {
+            value_type: ValueType::String,
+            multival: true,
+            ..Default::default()
+        });
+        schema
+    }
+
+    #[test]
+    fn test_apply_get_else() {
+        let schema = prepopulated_schema();
+        let known = Known::for_schema(&schema);
+
+        let query = r#"[:find ?e ?age :where [?e :foo/name _] [(get-else $ ?e :foo/age 0) ?age]]"#;
+        let cc = algebrize(known, parse_find_string(query).expect("parsed")).expect("algebrized").cc;
+        let age = Variable::from_valid_name("?age");
+        assert_eq!(cc.left_joins.len(), 1);
+        assert_eq!(cc.left_joins[0].table, SourceAlias(DatomsTable::Datoms, "datoms01".to_string()));
+        assert_eq!(cc.column_bindings.get(&age).expect("bound").clone(),
+                   vec![QualifiedAlias("datoms01".to_string(),
+                                       Column::Coalesce(vec![QualifiedAlias::new("datoms01".to_string(), DatomsColumn::Value)],
+                                                        Some(TypedValue::Long(0))))]);
+        assert_eq!(cc.known_type(&age), Some(ValueType::Long));
+        assert!(!cc.extracted_types.contains_key(&age));
+
+        // A default of another type means we must extract the type.
+        let query = r#"[:find ?e ?age :where [?e :foo/name _] [(get-else $ ?e :foo/age "unknown") ?age]]"#;
+        let cc = algebrize(known, parse_find_string(query).expect("parsed")).expect("algebrized").cc;
+        assert_eq!(cc.known_type_set(&age), ValueTypeSet::of_one(ValueType::Long).union(&ValueTypeSet::of_one(ValueType::String)));
+        assert_eq!(cc.extracted_types.get(&age).expect("extracted").clone(),
+                   QualifiedAlias("datoms01".to_string(),
+                                  Column::Coalesce(vec![QualifiedAlias::new("datoms01".to_string(), DatomsColumn::ValueTypeTag)],
+                                                   Some(TypedValue::Long(10)))));
+
+        // Cardinality-many attributes might have several values.
+        let query = r#"[:find ?e ?tag :where [?e :foo/name _] [(get-else $ ?e :foo/tag "none") ?tag]]"#;
+        match algebrize(known, parse_find_string(query).expect("parsed")) {
+            Err(AlgebrizerError::InvalidArgument(op, _, 2)) => assert_eq!(op, PlainSymbol::plain("get-else")),
+            _ => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn test_apply_get_some() {
+        let schema = prepopulated_schema();
+        let known = Known::for_schema(&schema);
+
+        let query = r#"[:find ?e ?a ?v :where [?e :foo/age _] [(get-some $ ?e :foo/nick :foo/name) [?a ?v]]]"#;
+        let cc = algebrize(known, parse_find_string(query).expect("parsed")).expect("algebrized").cc;
+        assert_eq!(cc.left_joins.len(), 2);
+        let v = Variable::from_valid_name("?v");
+        assert_eq!(cc.known_type(&v), Some(ValueType::String));
+        assert_eq!(cc.known_type(&Variable::from_valid_name("?a")), Some(ValueType::Ref));
+        assert_eq!(cc.column_bindings.get(&v).expect("bound").clone(),
+                   vec![QualifiedAlias("datoms01".to_string(),
+                                       Column::Coalesce(vec![QualifiedAlias::new("datoms01".to_string(), DatomsColumn::Value),
+                                                             QualifiedAlias::new("datoms02".to_string(), DatomsColumn::Value)],
+                                                        None))]);
+
+        let query = r#"[:find ?e ?v :where [?e :foo/age _] [(get-some $ ?e :foo/nick) ?v]]"#;
+        match algebrize(known, parse_find_string(query).expect("parsed")) {
+            Err(AlgebrizerError::InvalidBinding(_, BindingError::InvalidNumberOfBindings { number: 1, expected: 2 })) => {},
+            _ => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn test_apply_missing() {
+        let schema = prepopulated_schema();
+        let known = Known::for_schema(&schema);
+
+        let query = r#"[:find ?e :where [?e :foo/name _] [(missing? $ ?e :foo/age)]]"#;
+        let cc = algebrize(known, parse_find_string(query).expect("parsed")).expect("algebrized").cc;
+        assert!(!cc.is_known_empty());
+        assert!(cc.left_joins.is_empty());
+        assert_eq!(cc.wheres.0.len(), 2);
+
+        let query = r#"[:find ?e :where [?e :foo/name _] [(missing? $ ?e :foo/unknown)]]"#;
+        match algebrize(known, parse_find_string(query).expect("parsed")) {
+            Err(AlgebrizerError::UnrecognizedIdent(ident)) => assert_eq!(ident, ":foo/unknown"),
+            _ => panic!("expected an error"),
+        }
+    }
+}
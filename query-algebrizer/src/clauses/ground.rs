@@ -53,9 +53,9 @@ impl ConjoiningClauses {
     /// the provided types.
     /// Construct a computed table to yield this relation.
     /// This function will panic if some invariants are not met.
-    fn collect_named_bindings<'s>(&mut self, schema: &'s Schema, names: Vec<Variable>, types: Vec<ValueType>, values: Vec<TypedValue>) {
+    pub(crate) fn collect_named_bindings<'s>(&mut self, schema: &'s Schema, names: Vec<Variable>, types: Vec<ValueType>, values: Vec<TypedValue>) -> Result<()> {
         if values.is_empty() {
-            return;
+            return Ok(());
         }
 
         assert!(!names.is_empty());
@@ -74,10 +74,11 @@ impl ConjoiningClauses {
         // Stitch the computed table into column_bindings, so we get cross-linking.
         for (name, ty) in names.iter().zip(types.into_iter()) {
             self.constrain_var_to_type(name.clone(), ty);
-            self.bind_column_to_var(schema, alias.clone(), VariableColumn::Variable(name.clone()), name.clone());
+            self.bind_column_to_var(schema, alias.clone(), VariableColumn::Variable(name.clone()), name.clone())?;
         }
 
         self.from.push(SourceAlias(table, alias));
+        Ok(())
     }
 
     fn apply_ground_place<'s>(&mut self, schema: &'s Schema, var: VariableOrPlaceholder, arg: FnArg) -> Result<()> {
@@ -101,7 +102,7 @@ impl ConjoiningClauses {
     }
 
     /// Marks known-empty on failure.
-    fn apply_ground_value(&mut self, var: Variable, value: TypedValue) -> Result<()> {
+    pub(crate) fn apply_ground_value(&mut self, var: Variable, value: TypedValue) -> Result<()> {
         if let Some(existing) = self.bound_value(&var) {
             if existing != value {
                 self.mark_known_empty(EmptyBecause::ConflictingBindings {
@@ -205,7 +206,7 @@ impl ConjoiningClauses {
                 let types = vec![accumulated_types.exemplar().unwrap()];
                 let names = vec![var.clone()];
 
-                self.collect_named_bindings(schema, names, types, values);
+                self.collect_named_bindings(schema, names, types, values)?;
                 Ok(())
             },
 
@@ -312,7 +313,7 @@ impl ConjoiningClauses {
                 let types = accumulated_types_for_columns.into_iter()
                                                          .map(|x| x.exemplar().unwrap())
                                                          .collect();
-                self.collect_named_bindings(schema, names, types, matrix);
+                self.collect_named_bindings(schema, names, types, matrix)?;
                 Ok(())
             },
             (_, _) => bail!(AlgebrizerError::InvalidGroundConstant),
@@ -0,0 +1,203 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use core_traits::{
+    TypedValue,
+    ValueType,
+};
+
+use query_algebrizer_traits::errors::{
+    Result,
+};
+
+use clauses::{
+    ConjoiningClauses,
+};
+
+use types::{
+    DatomsTable,
+    EmptyBecause,
+    EvolvedPattern,
+    EvolvedValuePlace,
+    SourceAlias,
+    TransactionsColumn,
+};
+
+use Known;
+
+impl ConjoiningClauses {
+    /// Apply a pattern against `$history`: every datom ever asserted or retracted, as recorded in
+    /// the `transactions` table.  The pattern's fifth place matches whether the datom was added:
+    ///
+    /// ```edn
+    /// [$history ?e :person/name ?name ?tx false]
+    /// ```
+    ///
+    /// As with `tx-data`, the values of fulltext and offloaded strings are the ids that stand
+    /// for them.
+    pub(crate) fn apply_history_pattern(&mut self, known: Known, pattern: EvolvedPattern) -> Result<()> {
+        // `transactions` has the same `e`, `a`, `v`, `tx`, and `value_type_tag` columns as
+        // `datoms`, so the rest of the pattern constrains it just as it would `datoms`.
+        let alias = SourceAlias(DatomsTable::Transactions, self.next_alias_for_table(DatomsTable::Transactions));
+        self.apply_pattern_clause_for_alias(known, &pattern, &alias)?;
+        self.note_large_values(known, &pattern, &alias);
+        if self.is_known_empty() {
+            return Ok(());
+        }
+
+        let table = alias.1.clone();
+        match pattern.added {
+            EvolvedValuePlace::Placeholder => {},
+            EvolvedValuePlace::Variable(ref var) => {
+                self.constrain_var_to_type(var.clone(), ValueType::Boolean);
+                if self.is_known_empty() {
+                    return Ok(());
+                }
+                self.bind_column_to_var(known.schema, table, TransactionsColumn::Added, var.clone())?;
+            },
+            EvolvedValuePlace::Value(TypedValue::Boolean(added)) => {
+                self.constrain_column_to_constant(table, TransactionsColumn::Added, TypedValue::Boolean(added));
+            },
+            EvolvedValuePlace::Value(v) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(ValueType::Boolean, v));
+                return Ok(());
+            },
+            EvolvedValuePlace::Entid(e) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(ValueType::Boolean, TypedValue::Ref(e)));
+                return Ok(());
+            },
+            EvolvedValuePlace::EntidOrInteger(i) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(ValueType::Boolean, TypedValue::Long(i)));
+                return Ok(());
+            },
+            EvolvedValuePlace::IdentOrKeyword(kw) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(ValueType::Boolean, TypedValue::Keyword(kw)));
+                return Ok(());
+            },
+        }
+
+        self.from.push(alias);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    use core_traits::{
+        Attribute,
+        ValueTypeSet,
+    };
+
+    use mentat_core::{
+        Schema,
+    };
+
+    use edn::query::{
+        Keyword,
+        Variable,
+    };
+
+    use query_algebrizer_traits::errors::{
+        AlgebrizerError,
+    };
+
+    use clauses::{
+        add_attribute,
+        associate_ident,
+    };
+
+    use types::{
+        Column,
+        ColumnConstraint,
+        DatomsColumn,
+        QualifiedAlias,
+        QueryValue,
+    };
+
+    use {
+        algebrize,
+        parse_find_string,
+    };
+
+    fn prepopulated_schema() -> Schema {
+        let mut schema = Schema::default();
+        associate_ident(&mut schema, Keyword::namespaced("foo", "name"), 65);
+        add_attribute(&mut schema, 65, Attribute {
+            value_type: ValueType::String,
+            ..Default::default()
+        });
+        schema
+    }
+
+    #[test]
+    fn test_history_pattern() {
+        let schema = prepopulated_schema();
+        let known = Known::for_schema(&schema);
+        let query = r#"[:find ?e ?name ?tx ?added
+                        :in $history
+                        :where [$history ?e :foo/name ?name ?tx ?added]]"#;
+        let cc = algebrize(known, parse_find_string(query).expect("parsed")).expect("algebrized").cc;
+
+        assert_eq!(cc.from, vec![SourceAlias(DatomsTable::Transactions, "transactions00".to_string())]);
+        let transactions = |c: Column| QualifiedAlias("transactions00".to_string(), c);
+        assert_eq!(cc.column_bindings.get(&Variable::from_valid_name("?name")),
+                   Some(&vec![transactions(Column::Fixed(DatomsColumn::Value))]));
+        assert_eq!(cc.column_bindings.get(&Variable::from_valid_name("?added")),
+                   Some(&vec![transactions(Column::Transactions(TransactionsColumn::Added))]));
+        assert_eq!(cc.known_types.get(&Variable::from_valid_name("?added")),
+                   Some(&ValueTypeSet::of_one(ValueType::Boolean)));
+        assert_eq!(cc.wheres.0[0],
+                   ColumnConstraint::Equals(transactions(Column::Fixed(DatomsColumn::Attribute)), QueryValue::Entid(65)).into());
+    }
+
+    #[test]
+    fn test_history_added_constant() {
+        let schema = prepopulated_schema();
+        let known = Known::for_schema(&schema);
+
+        let query = r#"[:find ?e :where [$history ?e :foo/name _ _ false]]"#;
+        let cc = algebrize(known, parse_find_string(query).expect("parsed")).expect("algebrized").cc;
+        assert!(!cc.is_known_empty());
+        assert!(cc.wheres.0.contains(
+            &ColumnConstraint::Equals(QualifiedAlias("transactions00".to_string(), Column::Transactions(TransactionsColumn::Added)),
+                                      QueryValue::TypedValue(TypedValue::Boolean(false))).into()));
+
+        // `added` is a boolean.
+        let query = r#"[:find ?e :where [$history ?e :foo/name _ _ "yes"]]"#;
+        let cc = algebrize(known, parse_find_string(query).expect("parsed")).expect("algebrized").cc;
+        assert!(cc.is_known_empty());
+    }
+
+    #[test]
+    fn test_history_sources() {
+        let schema = prepopulated_schema();
+        let known = Known::for_schema(&schema);
+
+        // Only `$history` knows whether a datom was added.
+        let query = r#"[:find ?e :where [?e :foo/name _ _ true]]"#;
+        match algebrize(known, parse_find_string(query).expect("parsed")) {
+            Err(AlgebrizerError::AddedWithoutHistory) => {},
+            x => panic!("expected AddedWithoutHistory, got {:?}", x.map(|_| ())),
+        }
+
+        let query = r#"[:find ?e :where [$other ?e :foo/name _]]"#;
+        match algebrize(known, parse_find_string(query).expect("parsed")) {
+            Err(AlgebrizerError::UnsupportedSource(ref s)) if s == "$other" => {},
+            x => panic!("expected UnsupportedSource, got {:?}", x.map(|_| ())),
+        }
+
+        match parse_find_string(r#"[:find ?e :in $other :where [?e :foo/name _]]"#) {
+            Err(AlgebrizerError::UnsupportedSource(ref s)) if s == "$other" => {},
+            x => panic!("expected UnsupportedSource, got {:?}", x.map(|_| ())),
+        }
+    }
+}
@@ -9,30 +9,50 @@
 // specific language governing permissions and limitations under the License.
 
 use std::collections::BTreeMap;
+use std::mem;
 
 use core_traits::{
     ValueType,
+    ValueTypeSet,
     TypedValue,
 };
 
+use mentat_core::{
+    Schema,
+};
+
 use edn::query::{
+    Binding,
     Variable,
 };
 
+use clauses::{
+    ConjoiningClauses,
+};
+
 use query_algebrizer_traits::errors::{
     AlgebrizerError,
     Result,
 };
 
+use types::{
+    EmptyBecause,
+};
+
 /// Define the inputs to a query. This is in two parts: a set of values known now, and a set of
 /// types known now.
 /// The separate map of types is to allow queries to be algebrized without full knowledge of
 /// the bindings that will be used at execution time.
 /// When built correctly, `types` is guaranteed to contain the types of `values` -- use
 /// `QueryInputs::new` or `QueryInputs::with_values` to construct an instance.
+///
+/// Collection and relation bindings in `:in`, like `[?x ...]` and `[[?x ?y]]`, take their values
+/// from `relations`, keyed by the binding's variables in order. A collection is a relation with one
+/// variable. These values must be known when the query is algebrized.
 pub struct QueryInputs {
     pub(crate) types: BTreeMap<Variable, ValueType>,
     pub(crate) values: BTreeMap<Variable, TypedValue>,
+    pub(crate) relations: BTreeMap<Vec<Variable>, Vec<Vec<TypedValue>>>,
 }
 
 impl Default for QueryInputs {
@@ -40,6 +60,7 @@ impl Default for QueryInputs {
         QueryInputs {
             types: BTreeMap::default(),
             values: BTreeMap::default(),
+            relations: BTreeMap::default(),
         }
     }
 }
@@ -53,7 +74,7 @@ impl QueryInputs {
     pub fn with_type_sequence(types: Vec<(Variable, ValueType)>) -> QueryInputs {
         QueryInputs {
             types: types.into_iter().collect(),
-            values: BTreeMap::default(),
+            ..Default::default()
         }
     }
 
@@ -61,9 +82,31 @@ impl QueryInputs {
         QueryInputs {
             types: values.iter().map(|(var, val)| (var.clone(), val.value_type())).collect(),
             values: values,
+            relations: BTreeMap::default(),
         }
     }
 
+    /// Supply the values for a collection binding, `[?x ...]`.
+    pub fn with_collection(self, var: Variable, values: Vec<TypedValue>) -> QueryInputs {
+        self.with_relation(vec![var], values.into_iter().map(|v| vec![v]).collect())
+    }
+
+    /// Supply the rows for a relation binding, `[[?x ?y]]`. Each row has a value for each of
+    /// `vars`, in order; placeholders in the binding don't take a value.
+    pub fn with_relation(mut self, vars: Vec<Variable>, rows: Vec<Vec<TypedValue>>) -> QueryInputs {
+        self.relations.insert(vars, rows);
+        self
+    }
+
+    pub(crate) fn take_relations(&mut self) -> BTreeMap<Vec<Variable>, Vec<Vec<TypedValue>>> {
+        mem::replace(&mut self.relations, BTreeMap::default())
+    }
+
+    /// The values supplied for variables.
+    pub fn values(&self) -> &BTreeMap<Variable, TypedValue> {
+        &self.values
+    }
+
     pub fn new(mut types: BTreeMap<Variable, ValueType>,
                values: BTreeMap<Variable, TypedValue>) -> Result<QueryInputs> {
         // Make sure that the types of the values agree with those in types, and collect.
@@ -76,6 +119,49 @@ impl QueryInputs {
                 }
             }
         }
-        Ok(QueryInputs { types: types, values: values })
+        Ok(QueryInputs { types: types, values: values, relations: BTreeMap::default() })
+    }
+}
+
+impl ConjoiningClauses {
+    /// Bind each collection or relation binding from `:in` to the rows supplied for it, as a
+    /// computed table. Every column must have values of a single type.
+    pub(crate) fn apply_input_bindings(&mut self,
+                                       schema: &Schema,
+                                       bindings: Vec<Binding>,
+                                       mut relations: BTreeMap<Vec<Variable>, Vec<Vec<TypedValue>>>) -> Result<()> {
+        for binding in bindings.into_iter() {
+            let names: Vec<Variable> = binding.variables().into_iter().filter_map(|v| v).collect();
+            let description = names.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(" ");
+            let rows = relations.remove(&names)
+                                .ok_or_else(|| AlgebrizerError::UnboundInputBinding(description.clone()))?;
+
+            if rows.is_empty() {
+                self.mark_known_empty(EmptyBecause::NoInputValues(names[0].clone()));
+                continue;
+            }
+
+            let mut types = vec![ValueTypeSet::none(); names.len()];
+            let mut values = Vec::with_capacity(names.len() * rows.len());
+            for row in rows.into_iter() {
+                if row.len() != names.len() {
+                    bail!(AlgebrizerError::InputBindingMismatch(description, names.len(), row.len()));
+                }
+                for ((value, acc), name) in row.into_iter().zip(types.iter_mut()).zip(names.iter()) {
+                    let value_type = value.value_type();
+                    if let Some(existing) = acc.exemplar() {
+                        if existing != value_type {
+                            bail!(AlgebrizerError::InputTypeDisagreement(name.name(), existing, value_type));
+                        }
+                    }
+                    acc.insert(value_type);
+                    values.push(value);
+                }
+            }
+
+            let types = types.into_iter().map(|t| t.exemplar().expect("a type for each column")).collect();
+            self.collect_named_bindings(schema, names, types, values)?;
+        }
+        Ok(())
     }
 }
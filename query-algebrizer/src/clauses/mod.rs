@@ -20,13 +20,14 @@ use std::collections::btree_map::{
     Entry,
 };
 
+use std::rc::Rc;
+
 use std::fmt::{
     Debug,
     Formatter,
 };
 
 use core_traits::{
-    Attribute,
     Entid,
     KnownEntid,
     ValueType,
@@ -38,6 +39,7 @@ use mentat_core::{
     Cloned,
     HasSchema,
     Schema,
+    Statistics,
 };
 
 use mentat_core::counter::RcCounter;
@@ -46,10 +48,12 @@ use edn::query::{
     Element,
     FindSpec,
     Keyword,
+    Pattern,
     Pull,
     Variable,
     WhereClause,
     PatternNonValuePlace,
+    PatternValuePlace,
 };
 
 use query_algebrizer_traits::errors::{
@@ -69,6 +73,7 @@ use types::{
     EvolvedPattern,
     EvolvedValuePlace,
     FulltextColumn,
+    LeftJoin,
     PlaceOrEmpty,
     QualifiedAlias,
     QueryValue,
@@ -77,25 +82,42 @@ use types::{
 };
 
 mod convert;              // Converting args to values.
+mod conversion;
 mod inputs;
 mod or;
 mod not;
+mod optional;
+mod exists;
 mod pattern;
 mod predicate;
 mod resolve;
+mod rules;
 
 mod ground;
 mod fulltext;
+mod get_else;
+mod history;
 mod tx_log_api;
 mod where_fn;
 
 use validate::{
     validate_not_join,
+    validate_optional,
     validate_or_join,
+    validate_pattern_source,
 };
 
 pub use self::inputs::QueryInputs;
 
+use self::rules::{
+    RecursiveReference,
+    Rules,
+};
+
+use memo::{
+    AttributeFacts,
+};
+
 use Known;
 
 trait Contains<K, T> {
@@ -184,6 +206,13 @@ pub struct ConjoiningClauses {
     /// A vector of source/alias pairs used to construct a SQL `FROM` list.
     pub from: Vec<SourceAlias>,
 
+    /// Tables joined after those in `from` with `LEFT JOIN`, as for `get-else`.
+    pub left_joins: Vec<LeftJoin>,
+
+    /// The attribute index to read each of the tables in `from` with, if not the usual.  See
+    /// `Known::indexes`.
+    pub index_hints: BTreeMap<TableAlias, String>,
+
     /// A vector of computed tables (typically subqueries). The index into this vector is used as
     /// an identifier in a `DatomsTable::Computed(c)` table reference.
     pub computed_tables: Vec<ComputedTable>,
@@ -223,12 +252,24 @@ pub struct ConjoiningClauses {
 
     /// Map of variables to the set of type requirements we have for them.
     required_types: BTreeMap<Variable, ValueTypeSet>,
+
+    /// The rules defined by the enclosing query, shared by every CC it spawns.
+    rules: Rc<Rules>,
+
+    /// `Some` if this CC is the recursive arm of a recursive rule.
+    recursive_reference: Option<Rc<RecursiveReference>>,
+
+    /// The patterns among the clauses being applied, if those include an `or`, which might push
+    /// some of them down into its arms.  See `apply_complex_or_join`.
+    sibling_patterns: Vec<Pattern>,
 }
 
 impl PartialEq for ConjoiningClauses {
     fn eq(&self, other: &ConjoiningClauses) -> bool {
         self.empty_because.eq(&other.empty_because) &&
         self.from.eq(&other.from) &&
+        self.left_joins.eq(&other.left_joins) &&
+        self.index_hints.eq(&other.index_hints) &&
         self.computed_tables.eq(&other.computed_tables) &&
         self.wheres.eq(&other.wheres) &&
         self.column_bindings.eq(&other.column_bindings) &&
@@ -247,6 +288,8 @@ impl Debug for ConjoiningClauses {
         fmt.debug_struct("ConjoiningClauses")
             .field("empty_because", &self.empty_because)
             .field("from", &self.from)
+            .field("left_joins", &self.left_joins)
+            .field("index_hints", &self.index_hints)
             .field("computed_tables", &self.computed_tables)
             .field("wheres", &self.wheres)
             .field("column_bindings", &self.column_bindings)
@@ -266,6 +309,8 @@ impl Default for ConjoiningClauses {
             empty_because: None,
             alias_counter: RcCounter::new(),
             from: vec![],
+            left_joins: vec![],
+            index_hints: BTreeMap::new(),
             computed_tables: vec![],
             wheres: ColumnIntersection::default(),
             required_types: BTreeMap::new(),
@@ -274,6 +319,9 @@ impl Default for ConjoiningClauses {
             value_bindings: BTreeMap::new(),
             known_types: BTreeMap::new(),
             extracted_types: BTreeMap::new(),
+            rules: Rc::new(Rules::default()),
+            recursive_reference: None,
+            sibling_patterns: vec![],
         }
     }
 }
@@ -312,8 +360,12 @@ impl ConjoiningClauses {
                                                    alias_counter: RcCounter) -> ConjoiningClauses
     where T: Into<Option<QueryInputs>> {
         match inputs.into() {
-            None => ConjoiningClauses::with_alias_counter(alias_counter),
-            Some(QueryInputs { mut types, mut values }) => {
+            None => ConjoiningClauses {
+                alias_counter: alias_counter,
+                input_variables: in_variables,
+                ..Default::default()
+            },
+            Some(QueryInputs { mut types, mut values, .. }) => {
                 // Discard any bindings not mentioned in our :in clause.
                 types.keep_intersected_keys(&in_variables);
                 values.keep_intersected_keys(&in_variables);
@@ -361,6 +413,7 @@ impl ConjoiningClauses {
             known_types: self.known_types.clone(),
             extracted_types: self.extracted_types.clone(),
             required_types: self.required_types.clone(),
+            rules: self.rules.clone(),
             ..Default::default()
         }
     }
@@ -376,9 +429,26 @@ impl ConjoiningClauses {
             known_types: self.known_types.with_intersected_keys(&vars),
             extracted_types: self.extracted_types.with_intersected_keys(&vars),
             required_types: self.required_types.with_intersected_keys(&vars),
+            rules: self.rules.clone(),
             ..Default::default()
         }
     }
+
+    /// Make a new CC for a subquery that refers to `vars` in this one.  Each is bound in the new CC
+    /// to its value here, or to the first column it's bound to here, so it must already be bound.
+    fn use_as_correlated_template(&self, vars: &BTreeSet<Variable>) -> Result<ConjoiningClauses> {
+        let mut template = self.use_as_template(vars);
+        for v in vars.iter() {
+            if let Some(val) = self.value_bindings.get(v) {
+                template.value_bindings.insert(v.clone(), val.clone());
+            } else if let Some(cols) = self.column_bindings.get(v) {
+                template.column_bindings.insert(v.clone(), vec![cols[0].clone()]);
+            } else {
+                bail!(AlgebrizerError::UnboundVariable(v.name()));
+            }
+        }
+        Ok(template)
+    }
 }
 
 impl ConjoiningClauses {
@@ -442,7 +512,65 @@ impl ConjoiningClauses {
         self.known_types.get(var).cloned().unwrap_or(ValueTypeSet::any())
     }
 
-    pub(crate) fn bind_column_to_var<C: Into<Column>>(&mut self, schema: &Schema, table: TableAlias, column: C, var: Variable) {
+    /// If `var` is an `:in` variable that has no value yet and isn't bound to a column, refer to
+    /// the parameter it'll be bound to when the query is run.
+    pub(crate) fn parameter_for_input(&self, var: &Variable) -> Option<QueryValue> {
+        if self.input_variables.contains(var) &&
+           !self.value_bindings.contains_key(var) &&
+           !self.column_bindings.contains_key(var) {
+            Some(QueryValue::Parameter(var.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Turn each `:in` variable that wasn't given a value into a parameter, to be bound when the
+    /// query is run, and return the types each can take.  A variable's type must be known
+    /// precisely -- or be known to be numeric, because all numbers compare alike -- for its value
+    /// to be bound without also binding a type tag; other variables are left unbound.
+    pub(crate) fn bind_parameters(&mut self) -> BTreeMap<Variable, ValueTypeSet> {
+        let mut parameters = BTreeMap::new();
+        let unbound: Vec<Variable> = self.input_variables
+                                         .iter()
+                                         .filter(|var| !self.value_bindings.contains_key(var))
+                                         .cloned()
+                                         .collect();
+        for var in unbound {
+            let types = self.known_type_set(&var);
+            if types.is_empty() || !(types.is_unit() || types.is_only_numeric()) {
+                continue;
+            }
+            // Every other column bound to the variable is already constrained to equal this one.
+            let column = self.column_bindings.get(&var).and_then(|columns| columns.first()).cloned();
+            if let Some(column) = column {
+                self.wheres.add_intersection(ColumnConstraint::Equals(column, QueryValue::Parameter(var.clone())));
+            }
+            parameters.insert(var, types);
+        }
+        parameters
+    }
+
+    /// Return the attribute whose values `var` takes, if `var` appears in the value place of at
+    /// least one pattern, and every such pattern names the same attribute.
+    pub fn attribute_for_var(&self, var: &Variable) -> Option<Entid> {
+        let attributes: Vec<Option<Entid>> =
+            self.column_bindings
+                .get(var)?
+                .iter()
+                .filter_map(|&QualifiedAlias(ref table, ref column)| match column {
+                    &Column::Fixed(DatomsColumn::Value) => Some(self.attribute_for_table(table)),
+                    _ => None,
+                })
+                .collect();
+        let first = (*attributes.first()?)?;
+        if attributes.iter().all(|a| *a == Some(first)) {
+            Some(first)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn bind_column_to_var<C: Into<Column>>(&mut self, schema: &Schema, table: TableAlias, column: C, var: Variable) -> Result<()> {
         let column = column.into();
         // Do we have an external binding for this?
         if let Some(bound_val) = self.bound_value(&var) {
@@ -456,15 +584,24 @@ impl ConjoiningClauses {
                     self.constrain_column_to_constant(table, column, bound_val);
                 },
 
-                Column::Transactions(_) => {
+                Column::Transactions(_) |
+                Column::Cast(_, _) |
+                Column::Coalesce(_, _) => {
                     self.constrain_column_to_constant(table, column, bound_val);
                 },
 
                 Column::Fulltext(FulltextColumn::Rowid) |
-                Column::Fulltext(FulltextColumn::Text) => {
+                Column::Fulltext(FulltextColumn::Text) |
+                Column::Fulltext(FulltextColumn::Folded) |
+                Column::Fulltext(FulltextColumn::Score) => {
                     // We never expose `rowid` via queries.  We do expose `text`, but only
                     // indirectly, by joining against `datoms`.  Therefore, these are meaningless.
-                    unimplemented!()
+                    bail!(AlgebrizerError::UnbindableInput(var.name(), format!("{:?}", column)));
+                },
+
+                Column::Fixed(DatomsColumn::IndexAVET) => {
+                    // We only ever constrain this column; it's never bound to a variable.
+                    bail!(AlgebrizerError::UnbindableInput(var.name(), format!("{:?}", column)));
                 },
 
                 Column::Fixed(DatomsColumn::ValueTypeTag) => {
@@ -476,7 +613,7 @@ impl ConjoiningClauses {
                     //  :where [?x _ ?y]
                     //         [(= (typeof ?y) :db.valueType/double)]]
                     // ```
-                    unimplemented!();
+                    bail!(AlgebrizerError::UnbindableInput(var.name(), format!("{:?}", column)));
                 },
 
                 // TODO: recognize when the valueType might be a ref and also translate entids there.
@@ -512,7 +649,7 @@ impl ConjoiningClauses {
                 }
             }
 
-            return;
+            return Ok(());
         }
 
         // Will we have an external binding for this?
@@ -537,6 +674,7 @@ impl ConjoiningClauses {
         }
 
         self.column_bindings.entry(var).or_insert(vec![]).push(alias);
+        Ok(())
     }
 
     pub(crate) fn constrain_column_to_constant<C: Into<Column>>(&mut self, table: TableAlias, column: C, constant: TypedValue) {
@@ -762,7 +900,7 @@ impl ConjoiningClauses {
         schema.get_entid(&ident)
     }
 
-    fn table_for_attribute_and_value<'s, 'a>(&self, attribute: &'s Attribute, value: &'a EvolvedValuePlace) -> ::std::result::Result<DatomsTable, EmptyBecause> {
+    fn table_for_attribute_and_value<'a>(&self, attribute: AttributeFacts, value: &'a EvolvedValuePlace) -> ::std::result::Result<DatomsTable, EmptyBecause> {
         if attribute.fulltext {
             match value {
                 &EvolvedValuePlace::Placeholder =>
@@ -820,12 +958,12 @@ impl ConjoiningClauses {
     /// If the attribute input or value binding doesn't name an attribute, or doesn't name an
     /// attribute that is congruent with the supplied value, we return an `EmptyBecause`.
     /// The caller is responsible for marking the CC as known-empty if this is a fatal failure.
-    fn table_for_places<'s, 'a>(&self, schema: &'s Schema, attribute: &'a EvolvedNonValuePlace, value: &'a EvolvedValuePlace) -> ::std::result::Result<DatomsTable, EmptyBecause> {
+    fn table_for_places<'a>(&self, known: Known, attribute: &'a EvolvedNonValuePlace, value: &'a EvolvedValuePlace) -> ::std::result::Result<DatomsTable, EmptyBecause> {
         match attribute {
             &EvolvedNonValuePlace::Entid(id) =>
-                schema.attribute_for_entid(id)
-                      .ok_or_else(|| EmptyBecause::InvalidAttributeEntid(id))
-                      .and_then(|attribute| self.table_for_attribute_and_value(attribute, value)),
+                known.attribute_facts(id)
+                     .ok_or_else(|| EmptyBecause::InvalidAttributeEntid(id))
+                     .and_then(|attribute| self.table_for_attribute_and_value(attribute, value)),
             // TODO: In a prepared context, defer this decision until a second algebrizing phase.
             // #278.
             &EvolvedNonValuePlace::Placeholder =>
@@ -839,12 +977,13 @@ impl ConjoiningClauses {
                         self.table_for_unknown_attribute(value),
                     Some(TypedValue::Ref(id)) =>
                         // Recurse: it's easy.
-                        self.table_for_places(schema, &EvolvedNonValuePlace::Entid(id), value),
+                        self.table_for_places(known, &EvolvedNonValuePlace::Entid(id), value),
                     Some(TypedValue::Keyword(ref kw)) =>
                         // Don't recurse: avoid needing to clone the keyword.
-                        schema.attribute_for_ident(kw)
-                              .ok_or_else(|| EmptyBecause::InvalidAttributeIdent(kw.cloned()))
-                              .and_then(|(attribute, _entid)| self.table_for_attribute_and_value(attribute, value)),
+                        known.entid_for_ident(kw)
+                             .and_then(|entid| known.attribute_facts(entid.0))
+                             .ok_or_else(|| EmptyBecause::InvalidAttributeIdent(kw.cloned()))
+                             .and_then(|attribute| self.table_for_attribute_and_value(attribute, value)),
                     Some(v) => {
                         // This pattern cannot match: the caller has bound a non-entity value to an
                         // attribute place.
@@ -868,8 +1007,8 @@ impl ConjoiningClauses {
     /// This is a mutating method because it mutates the aliaser function!
     /// Note that if this function decides that a pattern cannot match, it will flip
     /// `empty_because`.
-    fn alias_table<'s, 'a>(&mut self, schema: &'s Schema, pattern: &'a EvolvedPattern) -> Option<SourceAlias> {
-        self.table_for_places(schema, &pattern.attribute, &pattern.value)
+    fn alias_table<'a>(&mut self, known: Known, pattern: &'a EvolvedPattern) -> Option<SourceAlias> {
+        self.table_for_places(known, &pattern.attribute, &pattern.value)
             .map_err(|reason| {
                 self.mark_known_empty(reason);
             })
@@ -877,32 +1016,32 @@ impl ConjoiningClauses {
             .ok()
     }
 
-    fn get_attribute_for_value<'s>(&self, schema: &'s Schema, value: &TypedValue) -> Option<&'s Attribute> {
+    fn get_attribute_for_value(&self, known: Known, value: &TypedValue) -> Option<AttributeFacts> {
         match value {
             // We know this one is known if the attribute lookup succeeds…
-            &TypedValue::Ref(id) => schema.attribute_for_entid(id),
-            &TypedValue::Keyword(ref kw) => schema.attribute_for_ident(kw).map(|(a, _id)| a),
+            &TypedValue::Ref(id) => known.attribute_facts(id),
+            &TypedValue::Keyword(ref kw) => known.entid_for_ident(kw).and_then(|entid| known.attribute_facts(entid.0)),
             _ => None,
         }
     }
 
-    fn get_attribute<'s, 'a>(&self, schema: &'s Schema, pattern: &'a EvolvedPattern) -> Option<&'s Attribute> {
+    fn get_attribute<'a>(&self, known: Known, pattern: &'a EvolvedPattern) -> Option<AttributeFacts> {
         match pattern.attribute {
             EvolvedNonValuePlace::Entid(id) =>
                 // We know this one is known if the attribute lookup succeeds…
-                schema.attribute_for_entid(id),
+                known.attribute_facts(id),
             EvolvedNonValuePlace::Variable(ref var) =>
                 // If the pattern has a variable, we've already determined that the binding -- if
                 // any -- is acceptable and yields a table. Here, simply look to see if it names
                 // an attribute so we can find out the type.
                 self.value_bindings.get(var)
-                                   .and_then(|val| self.get_attribute_for_value(schema, val)),
+                                   .and_then(|val| self.get_attribute_for_value(known, val)),
             EvolvedNonValuePlace::Placeholder => None,
         }
     }
 
-    fn get_value_type<'s, 'a>(&self, schema: &'s Schema, pattern: &'a EvolvedPattern) -> Option<ValueType> {
-        self.get_attribute(schema, pattern).map(|a| a.value_type)
+    fn get_value_type<'a>(&self, known: Known, pattern: &'a EvolvedPattern) -> Option<ValueType> {
+        self.get_attribute(known, pattern).map(|a| a.value_type)
     }
 }
 
@@ -1054,9 +1193,18 @@ impl ConjoiningClauses {
 
 impl ConjoiningClauses {
     fn apply_evolved_patterns(&mut self, known: Known, mut patterns: VecDeque<EvolvedPattern>) -> Result<()> {
+        // Join the patterns that match the fewest datoms first.  SQLite joins the tables in the
+        // order we list them unless it knows better, and it doesn't know how many datoms each
+        // attribute has.  The sort is stable, so patterns we can't tell apart keep their order.
+        if let Some(statistics) = known.statistics {
+            let mut sorted: Vec<EvolvedPattern> = patterns.into_iter().collect();
+            sorted.sort_by_key(|pattern| estimated_rows(known.schema, statistics, pattern));
+            patterns = sorted.into_iter().collect();
+        }
+
         while let Some(pattern) = patterns.pop_front() {
             match self.evolve_pattern(known, pattern) {
-                PlaceOrEmpty::Place(re_evolved) => self.apply_pattern(known, re_evolved),
+                PlaceOrEmpty::Place(re_evolved) => self.apply_pattern(known, re_evolved)?,
                 PlaceOrEmpty::Empty(because) => {
                     self.mark_known_empty(because);
                     patterns.clear();
@@ -1072,7 +1220,67 @@ impl ConjoiningClauses {
         }
     }
 
+    /// Replace each lookup ref in a pattern with a fresh variable, bound by a pattern of its own:
+    /// `[[:foo/email "x@y.com"] :foo/name ?name]` is algebrized as if it were
+    /// `[?lookup_ref__1 :foo/email "x@y.com"] [?lookup_ref__1 :foo/name ?name]`.  Lookup ref
+    /// attributes must be `:db/unique`, so the fresh variable binds at most one entity.
+    fn expand_lookup_refs(&self, known: Known, where_clauses: Vec<WhereClause>) -> Result<Vec<WhereClause>> {
+        let mut expanded = Vec::with_capacity(where_clauses.len());
+        for clause in where_clauses {
+            match clause {
+                WhereClause::Pattern(mut pattern) => {
+                    for place in [&mut pattern.entity, &mut pattern.attribute, &mut pattern.tx].iter_mut() {
+                        let lookup_ref = match **place {
+                            PatternNonValuePlace::LookupRef(ref lookup_ref) => lookup_ref.clone(),
+                            _ => continue,
+                        };
+                        let unique = known.schema.attribute_for_ident(&lookup_ref.attribute)
+                                                 .map(|(attribute, _)| attribute.unique.is_some());
+                        if unique == Some(false) {
+                            bail!(AlgebrizerError::NonUniqueLookupRef(lookup_ref.attribute.to_string()));
+                        }
+                        let var = Variable::from_valid_name(&format!("?lookup_ref__{}", self.alias_counter.next()));
+                        expanded.push(WhereClause::Pattern(Pattern {
+                            source: None,
+                            entity: PatternNonValuePlace::Variable(var.clone()),
+                            attribute: PatternNonValuePlace::Ident(lookup_ref.attribute),
+                            value: lookup_ref.value,
+                            tx: PatternNonValuePlace::Placeholder,
+                            added: PatternValuePlace::Placeholder,
+                        }));
+                        **place = PatternNonValuePlace::Variable(var);
+                    }
+                    expanded.push(WhereClause::Pattern(pattern));
+                },
+                clause => expanded.push(clause),
+            }
+        }
+        Ok(expanded)
+    }
+
     pub(crate) fn apply_clauses(&mut self, known: Known, where_clauses: Vec<WhereClause>) -> Result<()> {
+        let where_clauses = self.expand_lookup_refs(known, where_clauses)?;
+
+        // Rules with several definitions are applied as an `or`.
+        let has_or = where_clauses.iter().any(|clause| match clause {
+            &WhereClause::OrJoin(_) | &WhereClause::RuleExpr(_) => true,
+            _ => false,
+        });
+        if !has_or {
+            return self.apply_expanded_clauses(known, where_clauses);
+        }
+
+        let siblings = where_clauses.iter().filter_map(|clause| match clause {
+            &WhereClause::Pattern(ref p) => Some(p.clone()),
+            _ => None,
+        }).collect();
+        let enclosing = ::std::mem::replace(&mut self.sibling_patterns, siblings);
+        let applied = self.apply_expanded_clauses(known, where_clauses);
+        self.sibling_patterns = enclosing;
+        applied
+    }
+
+    fn apply_expanded_clauses(&mut self, known: Known, where_clauses: Vec<WhereClause>) -> Result<()> {
         // We apply (top level) type predicates first as an optimization.
         for clause in where_clauses.iter() {
             match clause {
@@ -1106,6 +1314,7 @@ impl ConjoiningClauses {
             }
             match clause {
                 WhereClause::Pattern(p) => {
+                    validate_pattern_source(&p)?;
                     match self.make_evolved_pattern(known, p) {
                         PlaceOrEmpty::Place(evolved) => patterns.push_back(evolved),
                         PlaceOrEmpty::Empty(because) => {
@@ -1131,8 +1340,12 @@ impl ConjoiningClauses {
     pub(crate) fn apply_clause(&mut self, known: Known, where_clause: WhereClause) -> Result<()> {
         match where_clause {
             WhereClause::Pattern(p) => {
+                if p.has_lookup_refs() {
+                    return self.apply_clauses(known, vec![WhereClause::Pattern(p)]);
+                }
+                validate_pattern_source(&p)?;
                 match self.make_evolved_pattern(known, p) {
-                    PlaceOrEmpty::Place(evolved) => self.apply_pattern(known, evolved),
+                    PlaceOrEmpty::Place(evolved) => self.apply_pattern(known, evolved)?,
                     PlaceOrEmpty::Empty(because) => self.mark_known_empty(because),
                 }
                 Ok(())
@@ -1151,10 +1364,16 @@ impl ConjoiningClauses {
                 validate_not_join(&n)?;
                 self.apply_not_join(known, n)
             },
+            WhereClause::Optional(o) => {
+                validate_optional(&o)?;
+                self.apply_optional(known, o)
+            },
             WhereClause::TypeAnnotation(anno) => {
                 self.apply_type_anno(&anno)
             },
-            _ => unimplemented!(),
+            WhereClause::RuleExpr(r) => {
+                self.apply_rule_expr(known, r)
+            },
         }
     }
 }
@@ -1171,7 +1390,34 @@ impl PushComputed for Vec<ComputedTable> {
     }
 }
 
+/// Roughly how many datoms `pattern` matches, from how many its attribute has.  A pattern that
+/// names an entity, or a value of a unique attribute, matches at most a handful.
+fn estimated_rows(schema: &Schema, statistics: &Statistics, pattern: &EvolvedPattern) -> u64 {
+    let attribute = match pattern.attribute {
+        EvolvedNonValuePlace::Entid(a) => a,
+        _ => return statistics.total_datoms(),
+    };
+    let datoms = statistics.datoms_for_attribute(attribute);
+    let known_entity = match pattern.entity {
+        EvolvedNonValuePlace::Entid(_) => true,
+        _ => false,
+    };
+    let known_value = match pattern.value {
+        EvolvedValuePlace::Placeholder | EvolvedValuePlace::Variable(_) => false,
+        _ => true,
+    };
+    let unique = schema.attribute_for_entid(attribute).map_or(false, |a| a.unique.is_some());
+    if known_entity || (known_value && unique) {
+        cmp::min(datoms, 1)
+    } else {
+        datoms
+    }
+}
+
 // These are helpers that tests use to build Schema instances.
+#[cfg(test)]
+use core_traits::Attribute;
+
 #[cfg(test)]
 fn associate_ident(schema: &mut Schema, i: Keyword, e: Entid) {
     schema.entid_map.insert(e, i.clone());
@@ -17,7 +17,6 @@ use edn::query::{
 use clauses::ConjoiningClauses;
 
 use query_algebrizer_traits::errors::{
-    AlgebrizerError,
     Result,
 };
 
@@ -35,19 +34,7 @@ impl ConjoiningClauses {
             UnifyVars::Explicit(vs) => vs,
         };
 
-        let mut template = self.use_as_template(&unified);
-
-        for v in unified.iter() {
-            if self.value_bindings.contains_key(&v) {
-                let val = self.value_bindings.get(&v).unwrap().clone();
-                template.value_bindings.insert(v.clone(), val);
-            } else if self.column_bindings.contains_key(&v) {
-                let col = self.column_bindings.get(&v).unwrap()[0].clone();
-                template.column_bindings.insert(v.clone(), vec![col]);
-            } else {
-                bail!(AlgebrizerError::UnboundVariable(v.name()));
-            }
-        }
+        let mut template = self.use_as_correlated_template(&unified)?;
 
         template.apply_clauses(known, not_join.clauses)?;
 
@@ -0,0 +1,271 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use core_traits::{
+    ValueTypeSet,
+};
+
+use mentat_core::{
+    SQLValueTypeSet,
+};
+
+use edn::query::{
+    ContainsVariables,
+    Optional,
+    Variable,
+};
+
+use clauses::{
+    ConjoiningClauses,
+    PushComputed,
+};
+
+use query_algebrizer_traits::errors::{
+    AlgebrizerError,
+    Result,
+};
+
+use types::{
+    Column,
+    ColumnConstraint,
+    ColumnIntersection,
+    ComputedTable,
+    DatomsTable,
+    LeftJoin,
+    QualifiedAlias,
+    QueryValue,
+    SourceAlias,
+    VariableColumn,
+};
+
+use Known;
+
+impl ConjoiningClauses {
+    /// `(optional [?x :person/nickname ?nick])` keeps every row of the enclosing query, binding
+    /// `?nick` where `?x` has a nickname and leaving it absent -- `NULL` -- where it doesn't.
+    ///
+    /// Variables already bound when the `optional` is applied join it to the rest of the query; the
+    /// others are bound only by its clauses.  The clauses are computed on their own, like an arm of
+    /// an `or-join`, and `LEFT JOIN`ed on the joined variables:
+    ///
+    /// ```sql
+    /// SELECT datoms00.e AS `?x`, c00.`?nick` AS `?nick`
+    /// FROM datoms AS datoms00
+    /// LEFT JOIN (SELECT datoms01.e AS `?x`, datoms01.v AS `?nick`
+    ///            FROM datoms AS datoms01
+    ///            WHERE datoms01.a = :person/nickname) AS c00
+    /// ON c00.`?x` = datoms00.e
+    /// WHERE datoms00.a = :person/name
+    /// ```
+    ///
+    /// As with `not`, the variables it joins on must be bound by the clauses before it.
+    pub(crate) fn apply_optional(&mut self, known: Known, optional: Optional) -> Result<()> {
+        if self.is_known_empty() {
+            return Ok(());
+        }
+
+        let mentioned = optional.collect_mentioned_variables();
+        let (joined, introduced): (BTreeSet<Variable>, BTreeSet<Variable>) =
+            mentioned.iter().cloned().partition(|var| {
+                self.value_bindings.contains_key(var) ||
+                self.column_bindings.contains_key(var) ||
+                self.input_variables.contains(var)
+            });
+
+        // If the clauses bind nothing new, whether they match can't change the results.
+        if introduced.is_empty() {
+            return Ok(());
+        }
+
+        // Each joined variable bound to a column out here must be projected from the clauses, so
+        // that we can join on it.
+        let on_columns: BTreeMap<Variable, QualifiedAlias> =
+            joined.iter()
+                  .filter(|var| !self.value_bindings.contains_key(var))
+                  .filter_map(|var| self.column_bindings.get(var).map(|cols| (var.clone(), cols[0].clone())))
+                  .collect();
+
+        // Type tags are extracted from the clauses' own tables, not from ours.
+        let mut template = self.use_as_template(&mentioned);
+        template.extracted_types.clear();
+        template.apply_clauses(known, optional.clauses)?;
+        if !template.is_known_empty() {
+            template.expand_column_bindings();
+            template.prune_extracted_types();
+            template.process_required_types()?;
+        }
+
+        let schema = known.schema;
+        if template.is_known_empty() {
+            // The clauses can never match, so the variables they introduce are always absent.
+            let alias = self.next_alias_for_table(DatomsTable::Datoms);
+            let absent = Column::Coalesce(vec![], None);
+            for var in introduced.into_iter() {
+                if !self.known_type_set(&var).has_unique_type_tag() {
+                    self.extracted_types.insert(var.clone(), QualifiedAlias(alias.clone(), absent.clone()));
+                }
+                self.bind_column_to_var(schema, alias.clone(), absent.clone(), var)?;
+            }
+            return Ok(());
+        }
+
+        for var in on_columns.keys() {
+            if !template.column_bindings.contains_key(var) && template.bound_value(var).is_none() {
+                bail!(AlgebrizerError::UnboundVariable(var.name()));
+            }
+        }
+
+        // The clauses only tell us what types the variables they introduce have when they match.
+        let types: BTreeMap<Variable, ValueTypeSet> = introduced.iter()
+                                                                .map(|var| (var.clone(), template.known_type_set(var)))
+                                                                .collect();
+        let type_needed: BTreeSet<Variable> = types.iter()
+                                                   .filter(|&(_, types)| !types.has_unique_type_tag())
+                                                   .map(|(var, _)| var.clone())
+                                                   .collect();
+        self.narrow_types(types);
+        if self.is_known_empty() {
+            return Ok(());
+        }
+
+        let projection: BTreeSet<Variable> = on_columns.keys().cloned().chain(introduced.iter().cloned()).collect();
+        let union = ComputedTable::Union {
+            projection: projection,
+            type_extraction: type_needed.clone(),
+            arms: vec![template],
+            disjoint: true,
+        };
+        let table = self.computed_tables.push_computed(union);
+        let alias = self.next_alias_for_table(table);
+
+        let mut on = ColumnIntersection::default();
+        for (var, column) in on_columns.into_iter() {
+            let projected = QualifiedAlias::new(alias.clone(), VariableColumn::Variable(var));
+            on.add_intersection(ColumnConstraint::Equals(projected, QueryValue::Column(column)));
+        }
+        for var in introduced.into_iter() {
+            self.bind_column_to_var(schema, alias.clone(), VariableColumn::Variable(var.clone()), var)?;
+        }
+        for var in type_needed.into_iter() {
+            self.extracted_types.insert(var.clone(), QualifiedAlias::new(alias.clone(), VariableColumn::VariableTypeTag(var)));
+        }
+        self.left_joins.push(LeftJoin {
+            table: SourceAlias(table, alias),
+            on,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    use core_traits::{
+        Attribute,
+        ValueType,
+    };
+
+    use mentat_core::{
+        Schema,
+    };
+
+    use edn::query::{
+        Keyword,
+    };
+
+    use clauses::{
+        add_attribute,
+        associate_ident,
+    };
+
+    use {
+        algebrize,
+        parse_find_string,
+    };
+
+    fn alg(schema: &Schema, input: &str) -> ConjoiningClauses {
+        let known = Known::for_schema(schema);
+        let parsed = parse_find_string(input).expect("parse failed");
+        algebrize(known, parsed).expect("algebrize failed").cc
+    }
+
+    fn prepopulated_schema() -> Schema {
+        let mut schema = Schema::default();
+        associate_ident(&mut schema, Keyword::namespaced("person", "name"), 65);
+        associate_ident(&mut schema, Keyword::namespaced("person", "nickname"), 66);
+        for entid in 65..67 {
+            add_attribute(&mut schema, entid, Attribute {
+                value_type: ValueType::String,
+                ..Default::default()
+            });
+        }
+        schema
+    }
+
+    #[test]
+    fn test_optional_left_joins_clauses() {
+        let schema = prepopulated_schema();
+        let cc = alg(&schema, r#"[:find ?x ?nick
+                                  :where [?x :person/name _]
+                                         (optional [?x :person/nickname ?nick])]"#);
+        let x = Variable::from_valid_name("?x");
+        let nick = Variable::from_valid_name("?nick");
+
+        assert_eq!(cc.from, vec![SourceAlias(DatomsTable::Datoms, "datoms00".to_string())]);
+        assert_eq!(cc.left_joins.len(), 1);
+        assert_eq!(cc.left_joins[0].table, SourceAlias(DatomsTable::Computed(0), "c00".to_string()));
+        match cc.computed_tables[0] {
+            ComputedTable::Union { ref projection, ref type_extraction, ref arms, .. } => {
+                assert_eq!(projection, &vec![x.clone(), nick.clone()].into_iter().collect());
+                assert!(type_extraction.is_empty());
+                assert_eq!(arms.len(), 1);
+            },
+            _ => panic!("expected a union"),
+        }
+
+        // The nickname is a string when there is one.
+        assert_eq!(cc.known_type(&nick), Some(ValueType::String));
+        assert_eq!(cc.column_bindings.get(&nick),
+                   Some(&vec![QualifiedAlias::new("c00".to_string(), VariableColumn::Variable(nick.clone()))]));
+    }
+
+    #[test]
+    fn test_optional_binding_nothing_new_is_dropped() {
+        let schema = prepopulated_schema();
+        let cc = alg(&schema, r#"[:find ?x
+                                  :where [?x :person/name ?name]
+                                         (optional [?x :person/nickname ?name])]"#);
+        assert!(cc.left_joins.is_empty());
+        assert!(cc.computed_tables.is_empty());
+    }
+
+    #[test]
+    fn test_optional_that_cannot_match() {
+        let schema = prepopulated_schema();
+        let cc = alg(&schema, r#"[:find ?x ?nick
+                                  :where [?x :person/name _]
+                                         (optional [?x :person/age ?nick])]"#);
+
+        // The query as a whole still matches, but `?nick` is never bound.
+        assert!(!cc.is_known_empty());
+        assert!(cc.left_joins.is_empty());
+        let nick = Variable::from_valid_name("?nick");
+        match cc.column_bindings.get(&nick).map(|cols| &cols[0]) {
+            Some(&QualifiedAlias(_, Column::Coalesce(ref columns, None))) => assert!(columns.is_empty()),
+            c => panic!("expected an absent column, got {:?}", c),
+        }
+    }
+}
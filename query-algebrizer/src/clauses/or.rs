@@ -15,15 +15,26 @@ use std::collections::{
 };
 
 use core_traits::{
+    TypedValue,
     ValueTypeSet,
 };
 
+use mentat_core::{
+    SQLValueTypeSet,
+};
+
+use edn::{
+    ToMicros,
+};
+
 use edn::query::{
+    ContainsVariables,
     OrJoin,
     OrWhereClause,
     Pattern,
     PatternValuePlace,
     PatternNonValuePlace,
+    SrcVar,
     UnifyVars,
     Variable,
     WhereClause,
@@ -39,6 +50,7 @@ use query_algebrizer_traits::errors::{
 };
 
 use types::{
+    ColumnConstraint,
     ColumnConstraintOrAlternation,
     ColumnAlternation,
     ColumnIntersection,
@@ -48,12 +60,20 @@ use types::{
     EvolvedPattern,
     PlaceOrEmpty,
     QualifiedAlias,
+    QueryValue,
     SourceAlias,
     VariableColumn,
 };
 
 use Known;
 
+/// Return true if the pattern queries the current datoms rather than `$history`.  Only those
+/// patterns can share a single table alias.
+fn queries_datoms(pattern: &Pattern) -> bool {
+    (pattern.source.is_none() || pattern.source == Some(SrcVar::DefaultSrc)) &&
+    pattern.added == PatternValuePlace::Placeholder
+}
+
 /// Return true if both left and right are the same variable or both are non-variable.
 fn _simply_matches_place(left: &PatternNonValuePlace, right: &PatternNonValuePlace) -> bool {
     match (left, right) {
@@ -112,6 +132,17 @@ impl ConjoiningClauses {
         // Pre-cache mentioned variables. We use these in a few places.
         or_join.mentioned_variables();
 
+        // Identical arms match the same rows, so all but the first can go.
+        if or_join.clauses.len() > 1 {
+            let mut distinct: Vec<OrWhereClause> = Vec::with_capacity(or_join.clauses.len());
+            for clause in or_join.clauses.drain(..) {
+                if !distinct.contains(&clause) {
+                    distinct.push(clause);
+                }
+            }
+            or_join.clauses = distinct;
+        }
+
         match or_join.clauses.len() {
             0 => Ok(()),
             1 if or_join.is_fully_unified() => {
@@ -214,6 +245,14 @@ impl ConjoiningClauses {
             // Keep a handle to the clause itself here to smooth over the moved `if let` below.
             let last: OrWhereClause;
 
+            // Lookup refs expand into patterns of their own, so a pattern that uses them can't be
+            // part of a simple `or`.  As the only clause of an `and`, it isn't.
+            let clause = match clause {
+                OrWhereClause::Clause(WhereClause::Pattern(ref p)) if p.has_lookup_refs() =>
+                    OrWhereClause::And(vec![WhereClause::Pattern(p.clone())]),
+                clause => clause,
+            };
+
             if let OrWhereClause::Clause(WhereClause::Pattern(p)) = clause {
                 // Compute the table for the pattern. If we can't figure one out, it means
                 // the pattern cannot succeed; we drop it.
@@ -223,7 +262,7 @@ impl ConjoiningClauses {
                     Place((aaa, value_type)) => {
                         match self.make_evolved_value(&known, value_type, p.value.clone()) {
                             Place(v) => {
-                                self.table_for_places(known.schema, &aaa, &v)
+                                self.table_for_places(known, &aaa, &v)
                             },
                             Empty(e) => Err(e),
                         }
@@ -240,7 +279,7 @@ impl ConjoiningClauses {
                     },
                     Ok(table) => {
                         // Check the shape of the pattern against a previous pattern.
-                        let same_shape =
+                        let same_shape = queries_datoms(&p) &&
                             if let Some(template) = patterns.get(0) {
                                 template.source == p.source &&     // or-arms all use the same source anyway.
                                 _simply_matches_place(&template.entity, &p.entity) &&
@@ -327,7 +366,7 @@ impl ConjoiningClauses {
                         self.mark_known_empty(e);
                     },
                     PlaceOrEmpty::Place(pattern) => {
-                        self.apply_pattern(known, pattern);
+                        self.apply_pattern(known, pattern)?;
                     },
                 };
                 Ok(())
@@ -405,7 +444,7 @@ impl ConjoiningClauses {
 
         // We expect this to always work: if it doesn't, it means we should never have got to this
         // point.
-        let source_alias = self.alias_table(known.schema, &patterns[0]).expect("couldn't get table");
+        let source_alias = self.alias_table(known, &patterns[0]).expect("couldn't get table");
 
         // This is where we'll collect everything we eventually add to the destination CC.
         let mut folded = ConjoiningClauses::default();
@@ -432,14 +471,15 @@ impl ConjoiningClauses {
             //  :where [?a :some/int ?x]
             //         [_ :some/otherint ?x]]
             // ```
-            let mut receptacles =
+            let receptacles =
                 patterns.into_iter()
                         .map(|pattern| {
                             let mut receptacle = template.make_receptacle();
-                            receptacle.apply_pattern_clause_for_alias(known, &pattern, &source_alias);
-                            receptacle
+                            receptacle.apply_pattern_clause_for_alias(known, &pattern, &source_alias)?;
+                            Ok(receptacle)
                         })
-                        .peekable();
+                        .collect::<Result<Vec<ConjoiningClauses>>>()?;
+            let mut receptacles = receptacles.into_iter().peekable();
 
             // Let's see if we can grab a reason if every pattern failed.
             // If every pattern failed, we can just take the first!
@@ -583,20 +623,38 @@ impl ConjoiningClauses {
             UnifyVars::Explicit(vs) => vs,
         };
 
-        let template = self.use_as_template(&projected);
+        // Each arm is computed on its own, and only then joined with the rest of the query, so
+        // without help an arm would consider every datom its clauses match, even when the enclosing
+        // patterns have narrowed the unified variables to a few entities.  Patterns that constrain
+        // only unified and already-bound variables can be applied in each arm as well.
+        let pushed = self.patterns_to_push_down(&projected);
+        let mut template_vars = projected.clone();
+        for pattern in pushed.iter() {
+            pattern.accumulate_mentioned_variables(&mut template_vars);
+        }
+        let template = self.use_as_template(&template_vars);
 
         let mut acc = Vec::with_capacity(join_clauses.len());
         let mut empty_because: Option<EmptyBecause> = None;
 
         for clause in join_clauses.into_iter() {
             let mut receptacle = template.make_receptacle();
-            match clause {
-                OrWhereClause::And(clauses) => {
-                    receptacle.apply_clauses(known, clauses)?;
-                },
-                OrWhereClause::Clause(clause) => {
-                    receptacle.apply_clause(known, clause)?;
-                },
+            if pushed.is_empty() {
+                match clause {
+                    OrWhereClause::And(clauses) => {
+                        receptacle.apply_clauses(known, clauses)?;
+                    },
+                    OrWhereClause::Clause(clause) => {
+                        receptacle.apply_clause(known, clause)?;
+                    },
+                }
+            } else {
+                let clauses = match clause {
+                    OrWhereClause::And(clauses) => clauses,
+                    OrWhereClause::Clause(clause) => vec![clause],
+                };
+                let pushed = pushed.iter().cloned().map(WhereClause::Pattern);
+                receptacle.apply_clauses(known, pushed.chain(clauses).collect())?;
             }
             if receptacle.is_known_empty() {
                 empty_because = receptacle.empty_because;
@@ -633,12 +691,13 @@ impl ConjoiningClauses {
         //            (or [(< ?y 10)]
         //                [_ :foo/verified ?y])]
         //    ```
-        // 2. Not know the type, but every CC bound it to the same single type. Don't project a type
-        //    tag; we simply contribute the single type to the enclosing CC. Example:
+        // 2. Not know the type, but every CC bound it to types that share a single type tag. Don't
+        //    project a type tag; we simply contribute the union of the types to the enclosing CC.
+        //    Example, where `:foo/length` is a long and `:foo/ratio` is a double:
         //    ```
         //    [:find ?x ?y
         //     :where (or [?x :foo/length ?y]
-        //                [?x :foo/width ?y])]
+        //                [?x :foo/ratio ?y])]
         //    ```
         // 3. (a) Have every CC come up with a non-unit type set for the var. Every CC will project
         //        a type tag column from one of its internal bindings, and the union will project it
@@ -660,14 +719,26 @@ impl ConjoiningClauses {
         let projection: BTreeSet<Variable> = projected.into_iter().collect();
         let mut type_needed: BTreeSet<Variable> = BTreeSet::default();
 
-        // For any variable which has an imprecise type anywhere in the UNION, add it to the
+        // Each row of the UNION comes from one of its arms, so a projected variable can only have
+        // the types that some arm allows it.
+        let arm_types: BTreeMap<Variable, ValueTypeSet> =
+            projection.iter()
+                      .map(|var| {
+                          let types = acc.iter().fold(ValueTypeSet::none(), |types, cc| types.union(&cc.known_type_set(var)));
+                          (var.clone(), types)
+                      })
+                      .collect();
+
+        // For any variable whose types across the UNION don't share a type tag, add it to the
         // set that needs type extraction. All UNION arms must project the same columns.
-        for var in projection.iter() {
-            if acc.iter().any(|cc| !cc.known_type(var).is_some()) {
+        for (var, types) in arm_types.iter() {
+            if !types.has_unique_type_tag() {
                 type_needed.insert(var.clone());
             }
         }
 
+        let disjoint = arms_are_disjoint(&projection, &type_needed, &acc);
+
         // Hang on to these so we can stuff them in our column bindings.
         let var_associations: Vec<Variable>;
         let type_associations: Vec<Variable>;
@@ -676,25 +747,15 @@ impl ConjoiningClauses {
             type_associations = type_needed.iter().cloned().collect();
         }
 
-        // Collect the new type information from the arms. There's some redundant work here --
-        // they already have all of the information from the parent.
-        // Note that we start with the first clause's type information.
-        {
-            let mut clauses = acc.iter();
-            let mut additional_types = clauses.next()
-                                              .expect("there to be at least one clause")
-                                              .known_types
-                                              .clone();
-            for cc in clauses {
-                union_types(&mut additional_types, &cc.known_types);
-            }
-            self.broaden_types(additional_types);
-        }
+        // Contribute the new type information from the arms. Variables that aren't projected are
+        // local to each arm, and say nothing about variables of the same name out here.
+        self.narrow_types(arm_types);
 
         let union = ComputedTable::Union {
             projection: projection,
             type_extraction: type_needed,
             arms: acc,
+            disjoint: disjoint,
         };
         let table = self.computed_tables.push_computed(union);
         let alias = self.next_alias_for_table(table);
@@ -702,7 +763,7 @@ impl ConjoiningClauses {
         // Stitch the computed table into column_bindings, so we get cross-linking.
         let schema = known.schema;
         for var in var_associations.into_iter() {
-            self.bind_column_to_var(schema, alias.clone(), VariableColumn::Variable(var.clone()), var);
+            self.bind_column_to_var(schema, alias.clone(), VariableColumn::Variable(var.clone()), var)?;
         }
         for var in type_associations.into_iter() {
             self.extracted_types.insert(var.clone(), QualifiedAlias::new(alias.clone(), VariableColumn::VariableTypeTag(var)));
@@ -712,41 +773,94 @@ impl ConjoiningClauses {
     }
 }
 
-/// Helper to fold together a set of type maps.
-fn union_types(into: &mut BTreeMap<Variable, ValueTypeSet>,
-               additional_types: &BTreeMap<Variable, ValueTypeSet>) {
-    // We want the exclusive disjunction -- any variable not mentioned in both sets -- to default
-    // to ValueTypeSet::Any.
-    // This is necessary because we lazily populate known_types, so sometimes the type set will
-    // be missing a `ValueTypeSet::Any` for a variable, and we want to broaden rather than
-    // accidentally taking the other side's word for it!
-    // The alternative would be to exhaustively pre-fill `known_types` with all mentioned variables
-    // in the whole query, which is daunting.
-    let mut any: BTreeMap<Variable, ValueTypeSet>;
-    // Scoped borrow of `into`.
-    {
-        let i: BTreeSet<&Variable> = into.keys().collect();
-        let a: BTreeSet<&Variable> = additional_types.keys().collect();
-        any = i.symmetric_difference(&a)
-               .map(|v| ((*v).clone(), ValueTypeSet::any()))
-               .collect();
+impl ConjoiningClauses {
+    /// The patterns alongside an `or` that can be applied in each of its arms without changing its
+    /// results: those against the current datoms, with a known attribute, whose variables are
+    /// all either unified with the `or` -- in `projected` -- or already bound to values.
+    fn patterns_to_push_down(&self, projected: &BTreeSet<Variable>) -> Vec<Pattern> {
+        self.sibling_patterns.iter().filter(|pattern| {
+            let known_attribute = match pattern.attribute {
+                PatternNonValuePlace::Ident(_) | PatternNonValuePlace::Entid(_) => true,
+                _ => false,
+            };
+            let vars = pattern.collect_mentioned_variables();
+            queries_datoms(pattern) &&
+            known_attribute &&
+            !vars.is_empty() &&
+            vars.iter().all(|var| projected.contains(var) || self.bound_value(var).is_some())
+        }).cloned().collect()
     }
+}
 
-    // Collect the additional types.
-    for (var, new_types) in additional_types {
-        match into.entry(var.clone()) {
-            Entry::Vacant(e) => {
-                e.insert(new_types.clone());
-            },
-            Entry::Occupied(mut e) => {
-                let new = e.get().union(&new_types);
-                e.insert(new);
+/// The single value that `var` can take in `cc`, if there is one: either because `var` is bound to
+/// a constant, or because a column it's bound to is constrained to equal a constant.
+fn pinned_value(cc: &ConjoiningClauses, var: &Variable) -> Option<TypedValue> {
+    if let Some(value) = cc.bound_value(var) {
+        return Some(value);
+    }
+    let columns = cc.column_bindings.get(var)?;
+    cc.wheres.0.iter().filter_map(|constraint| {
+        match constraint {
+            &ColumnConstraintOrAlternation::Constraint(ColumnConstraint::Equals(ref qa, ref value)) if columns.contains(qa) => {
+                match value {
+                    &QueryValue::Entid(entid) => Some(TypedValue::Ref(entid)),
+                    &QueryValue::TypedValue(ref value) => Some(value.clone()),
+                    _ => None,
+                }
             },
+            _ => None,
         }
+    }).next()
+}
+
+/// Return true if `left` and `right` are certainly stored as different SQL values. Values of
+/// different types might not be: `5` and `5.0` are equal in SQLite.
+fn values_differ(left: &TypedValue, right: &TypedValue) -> bool {
+    match (left, right) {
+        // Instants are stored with microsecond precision.
+        (&TypedValue::Instant(ref l), &TypedValue::Instant(ref r)) => l.to_micros() != r.to_micros(),
+        (l, r) => l.value_type() == r.value_type() && l != r,
     }
+}
+
+/// Return true if no row projected by `left` can equal a row projected by `right`: some projected
+/// variable either has disjoint type tags in the two arms (and we project its type tag), or is
+/// pinned to different constants.
+///
+/// This catches arms that are distinguished by a constant, including an attribute:
+///
+/// ```edn
+/// [:find ?x ?kind
+///  :where (or-join [?x ?kind]
+///           (and [?x :foo/knows _] [(ground :knows) ?kind])
+///           (and [?x :foo/parent _] [(ground :parent) ?kind]))]
+/// ```
+fn arms_disjoint(projection: &BTreeSet<Variable>,
+                 type_extraction: &BTreeSet<Variable>,
+                 left: &ConjoiningClauses,
+                 right: &ConjoiningClauses) -> bool {
+    projection.iter().any(|var| {
+        if type_extraction.contains(var) {
+            let left_tags = left.known_type_set(var).value_type_tags();
+            let right_tags = right.known_type_set(var).value_type_tags();
+            if left_tags.is_disjoint(&right_tags) {
+                return true;
+            }
+        }
+        match (pinned_value(left, var), pinned_value(right, var)) {
+            (Some(ref l), Some(ref r)) => values_differ(l, r),
+            _ => false,
+        }
+    })
+}
 
-    // Blat in those that are disjoint.
-    into.append(&mut any);
+/// Return true if every pair of `arms` is disjoint. See `arms_disjoint`.
+fn arms_are_disjoint(projection: &BTreeSet<Variable>,
+                     type_extraction: &BTreeSet<Variable>,
+                     arms: &[ConjoiningClauses]) -> bool {
+    arms.iter().enumerate().all(|(i, left)| {
+        arms[i + 1..].iter().all(|right| arms_disjoint(projection, type_extraction, left, right))
+    })
 }
 
 #[cfg(test)]
@@ -813,6 +927,7 @@ mod testing {
         associate_ident(&mut schema, Keyword::namespaced("foo", "parent"), 67);
         associate_ident(&mut schema, Keyword::namespaced("foo", "age"), 68);
         associate_ident(&mut schema, Keyword::namespaced("foo", "height"), 69);
+        associate_ident(&mut schema, Keyword::namespaced("foo", "ratio"), 70);
         add_attribute(&mut schema, 65, Attribute {
             value_type: ValueType::String,
             multival: false,
@@ -838,6 +953,11 @@ mod testing {
             multival: false,
             ..Default::default()
         });
+        add_attribute(&mut schema, 70, Attribute {
+            value_type: ValueType::Double,
+            multival: false,
+            ..Default::default()
+        });
         schema
     }
 
@@ -1101,7 +1221,9 @@ mod testing {
         let cc = alg(known, query);
         let mut tables = cc.computed_tables.into_iter();
         match (tables.next(), tables.next()) {
-            (Some(ComputedTable::Union { projection, type_extraction, arms }), None) => {
+            (Some(ComputedTable::Union { projection, type_extraction, arms, disjoint }), None) => {
+                // Both arms can match the same ?x.
+                assert!(!disjoint);
                 assert_eq!(projection, vec![Variable::from_valid_name("?x")].into_iter().collect());
                 assert!(type_extraction.is_empty());
 
@@ -1129,6 +1251,58 @@ mod testing {
         }
     }
 
+    #[test]
+    fn test_duplicate_arms_are_dropped() {
+        let schema = prepopulated_schema();
+        let known = Known::for_schema(&schema);
+        let query = r#"
+            [:find ?x
+             :where (or [?x :foo/knows "John"]
+                        [?x :foo/knows "John"])]"#;
+        let simple = r#"
+            [:find ?x
+             :where [?x :foo/knows "John"]]"#;
+        compare_ccs(alg(known, query), alg(known, simple));
+    }
+
+    #[test]
+    fn test_enclosing_pattern_is_pushed_into_arms() {
+        let schema = prepopulated_schema();
+        let known = Known::for_schema(&schema);
+        let query = r#"
+            [:find ?x
+             :where [?x :foo/name "Ada"]
+                    (or (and [?x :foo/knows "John"]
+                             [?x :foo/parent "Ámbar"])
+                        [?x :foo/knows "Daphne"])]"#;
+        let cc = alg(known, query);
+        let mut tables = cc.computed_tables.into_iter();
+        match (tables.next(), tables.next()) {
+            (Some(ComputedTable::Union { arms, .. }), None) => {
+                let mut arms = arms.into_iter();
+                match (arms.next(), arms.next(), arms.next()) {
+                    (Some(and), Some(pattern), None) => {
+                        let expected_and = alg_c(known,
+                                                 1,  // The enclosing pattern takes the first alias.
+                                                 r#"[:find ?x :where [?x :foo/name "Ada"] [?x :foo/knows "John"] [?x :foo/parent "Ámbar"]]"#);
+                        compare_ccs(and, expected_and);
+
+                        let expected_pattern = alg_c(known,
+                                                     4,      // Three more taken by the other arm.
+                                                     r#"[:find ?x :where [?x :foo/name "Ada"] [?x :foo/knows "Daphne"]]"#);
+                        compare_ccs(pattern, expected_pattern);
+                    },
+                    _ => {
+                        panic!("Expected two arms");
+                    }
+                }
+            },
+            _ => {
+                panic!("Didn't get one inner table.");
+            },
+        }
+    }
+
     #[test]
     fn test_type_based_or_pruning() {
         let schema = prepopulated_schema();
@@ -1148,4 +1322,48 @@ mod testing {
                     [_ :foo/height ?x]]"#;
         compare_ccs(alg(known, query), alg(known, simple));
     }
+
+    #[test]
+    fn test_complex_or_join_types() {
+        let schema = prepopulated_schema();
+        let known = Known::for_schema(&schema);
+        let y = Variable::from_valid_name("?y");
+
+        // Longs and doubles share a type tag, so the union needn't project one.
+        let query = r#"
+            [:find ?x ?y
+             :where (or-join [?x ?y]
+                      [?x :foo/age ?y]
+                      (and [?x :foo/knows _]
+                           [?x :foo/ratio ?y]))]"#;
+        let cc = alg(known, query);
+        assert_eq!(cc.known_type_set(&y), ValueTypeSet::of_numeric_types());
+        assert!(!cc.extracted_types.contains_key(&y));
+        match cc.computed_tables[0] {
+            ComputedTable::Union { ref type_extraction, .. } => assert!(type_extraction.is_empty()),
+            _ => panic!("expected a union"),
+        }
+
+        // Strings don't.
+        let query = r#"
+            [:find ?x ?y
+             :where (or-join [?x ?y]
+                      [?x :foo/age ?y]
+                      (and [?x :foo/knows _]
+                           [?x :foo/name ?y]))]"#;
+        let cc = alg(known, query);
+        assert_eq!(cc.known_type_set(&y), ValueTypeSet::of_one(ValueType::Long).union(&ValueTypeSet::of_one(ValueType::String)));
+        assert!(cc.extracted_types.contains_key(&y));
+
+        // What the arms know tells us about the enclosing query.
+        let query = r#"
+            [:find ?x ?y
+             :where [?x _ ?y]
+                    (or-join [?x ?y]
+                      [?x :foo/age ?y]
+                      (and [?x :foo/knows _]
+                           [?x :foo/height ?y]))]"#;
+        let cc = alg(known, query);
+        assert_eq!(cc.known_type(&y), Some(ValueType::Long));
+    }
 }
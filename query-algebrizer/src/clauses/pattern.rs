@@ -18,6 +18,11 @@ use core_traits::{
 use mentat_core::{
     Cloned,
     HasSchema,
+    IndexDirection,
+};
+
+use mentat_core::normalization::{
+    normalize_value,
 };
 
 use edn::query::{
@@ -29,6 +34,10 @@ use edn::query::{
     Variable,
 };
 
+use query_algebrizer_traits::errors::{
+    Result,
+};
+
 use clauses::{
     ConjoiningClauses,
 };
@@ -36,6 +45,7 @@ use clauses::{
 use types::{
     ColumnConstraint,
     DatomsColumn,
+    DatomsTable,
     EmptyBecause,
     EvolvedNonValuePlace,
     EvolvedPattern,
@@ -48,12 +58,13 @@ use Known;
 
 pub fn into_typed_value(nic: NonIntegerConstant) -> TypedValue {
     match nic {
-        NonIntegerConstant::BigInteger(_) => unimplemented!(),     // TODO: #280.
+        NonIntegerConstant::BigInteger(v) => TypedValue::BigInteger(v),
         NonIntegerConstant::Boolean(v) => TypedValue::Boolean(v),
         NonIntegerConstant::Float(v) => TypedValue::Double(v),
         NonIntegerConstant::Text(v) => v.into(),
         NonIntegerConstant::Instant(v) => TypedValue::Instant(v),
         NonIntegerConstant::Uuid(v) => TypedValue::Uuid(v),
+        NonIntegerConstant::Bytes(v) => TypedValue::Bytes(v),
     }
 }
 
@@ -95,9 +106,9 @@ impl ConjoiningClauses {
     ///   existence subquery instead of a join.
     ///
     /// This method is only public for use from `or.rs`.
-    pub(crate) fn apply_pattern_clause_for_alias(&mut self, known: Known, pattern: &EvolvedPattern, alias: &SourceAlias) {
+    pub(crate) fn apply_pattern_clause_for_alias(&mut self, known: Known, pattern: &EvolvedPattern, alias: &SourceAlias) -> Result<()> {
         if self.is_known_empty() {
-            return;
+            return Ok(());
         }
 
         // Process each place in turn, applying constraints.
@@ -120,7 +131,7 @@ impl ConjoiningClauses {
                 // IS NOT NULL, because we don't store nulls in our schema.
                 (),
             EvolvedNonValuePlace::Variable(ref v) =>
-                self.bind_column_to_var(schema, col.clone(), DatomsColumn::Entity, v.clone()),
+                self.bind_column_to_var(schema, col.clone(), DatomsColumn::Entity, v.clone())?,
             EvolvedNonValuePlace::Entid(entid) =>
                 self.constrain_column_to_entity(col.clone(), DatomsColumn::Entity, entid),
         }
@@ -129,13 +140,13 @@ impl ConjoiningClauses {
             EvolvedNonValuePlace::Placeholder =>
                 (),
             EvolvedNonValuePlace::Variable(ref v) =>
-                self.bind_column_to_var(schema, col.clone(), DatomsColumn::Attribute, v.clone()),
+                self.bind_column_to_var(schema, col.clone(), DatomsColumn::Attribute, v.clone())?,
             EvolvedNonValuePlace::Entid(entid) => {
                 if !schema.is_attribute(entid) {
                     // Furthermore, that entid must resolve to an attribute. If it doesn't, this
                     // query is meaningless.
                     self.mark_known_empty(EmptyBecause::InvalidAttributeEntid(entid));
-                    return;
+                    return Ok(());
                 }
                 self.constrain_attribute(col.clone(), entid)
             },
@@ -146,7 +157,7 @@ impl ConjoiningClauses {
         // At this point it's possible that the type of the value is
         // inconsistent with the attribute; in that case this pattern
         // cannot return results, and we short-circuit.
-        let value_type = self.get_value_type(schema, pattern);
+        let value_type = self.get_value_type(known, pattern);
 
         match pattern.value {
             EvolvedValuePlace::Placeholder =>
@@ -158,11 +169,11 @@ impl ConjoiningClauses {
                     // It doesn't matter too much: collisons won't be too frequent.
                     self.constrain_var_to_type(v.clone(), this_type);
                     if self.is_known_empty() {
-                        return;
+                        return Ok(());
                     }
                 }
 
-                self.bind_column_to_var(schema, col.clone(), DatomsColumn::Value, v.clone());
+                self.bind_column_to_var(schema, col.clone(), DatomsColumn::Value, v.clone())?;
             },
             EvolvedValuePlace::Entid(i) => {
                 match value_type {
@@ -205,13 +216,13 @@ impl ConjoiningClauses {
                 // know it can only return results if treated as a keyword, and we can treat it as
                 // such.
                 if let Some(ValueType::Ref) = value_type {
-                    if let Some(entid) = self.entid_for_ident(schema, kw) {
+                    if let Some(entid) = known.entid_for_ident(kw) {
                         self.constrain_column_to_entity(col.clone(), DatomsColumn::Value, entid.into())
                     } else {
                         // A resolution failure means we're done here: this attribute must have an
                         // entity value.
                         self.mark_known_empty(EmptyBecause::UnresolvedIdent(kw.cloned()));
-                        return;
+                        return Ok(());
                     }
                 } else {
                     // It must be a keyword.
@@ -229,7 +240,7 @@ impl ConjoiningClauses {
                     let value_type = value_type.expect("Congruence failure but couldn't unwrap");
                     let why = EmptyBecause::ValueTypeMismatch(value_type, typed_value);
                     self.mark_known_empty(why);
-                    return;
+                    return Ok(());
                 }
 
                 // TODO: if we don't know the type of the attribute because we don't know the
@@ -261,15 +272,16 @@ impl ConjoiningClauses {
         match pattern.tx {
             EvolvedNonValuePlace::Placeholder => (),
             EvolvedNonValuePlace::Variable(ref v) => {
-                self.bind_column_to_var(schema, col.clone(), DatomsColumn::Tx, v.clone());
+                self.bind_column_to_var(schema, col.clone(), DatomsColumn::Tx, v.clone())?;
             },
             EvolvedNonValuePlace::Entid(entid) => {
                 self.constrain_column_to_entity(col.clone(), DatomsColumn::Tx, entid);
             },
         }
+        Ok(())
     }
 
-    fn reverse_lookup(&mut self, known: Known, var: &Variable, attr: Entid, val: &TypedValue) -> bool {
+    fn reverse_lookup(&mut self, known: Known, var: &Variable, attr: Entid, val: &TypedValue) -> Result<bool> {
         if let Some(attribute) = known.schema.attribute_for_entid(attr) {
             let unique = attribute.unique.is_some();
             if unique {
@@ -279,11 +291,11 @@ impl ConjoiningClauses {
                             value: val.clone(),
                             attr: attr,
                         });
-                        true
+                        Ok(true)
                     },
                     Some(item) => {
                         self.bind_value(var, TypedValue::Ref(item));
-                        true
+                        Ok(true)
                     },
                 }
             } else {
@@ -293,38 +305,102 @@ impl ConjoiningClauses {
                             value: val.clone(),
                             attr: attr,
                         });
-                        true
+                        Ok(true)
                     },
                     Some(items) => {
-                        if items.len() == 1 {
-                            let item = items.iter().next().cloned().unwrap();
-                            self.bind_value(var, TypedValue::Ref(item));
-                            true
-                        } else {
-                            // Oh well.
-                            // TODO: handle multiple values.
-                            false
+                        match items.len() {
+                            0 => {
+                                self.mark_known_empty(EmptyBecause::CachedAttributeHasNoEntity {
+                                    value: val.clone(),
+                                    attr: attr,
+                                });
+                            },
+                            1 => {
+                                let item = items.iter().next().cloned().unwrap();
+                                self.bind_value(var, TypedValue::Ref(item));
+                            },
+                            _ => {
+                                // Several entities: bind them as a computed table, just as
+                                // `ground` would.
+                                let entities = items.iter().cloned().map(TypedValue::Ref).collect();
+                                self.collect_named_bindings(known.schema, vec![var.clone()], vec![ValueType::Ref], entities)?;
+                            },
                         }
+                        Ok(true)
                     },
                 }
             }
         } else {
             self.mark_known_empty(EmptyBecause::InvalidAttributeEntid(attr));
-            true
+            Ok(true)
+        }
+    }
+
+    /// Answer `[entity attr ?v]` or `[entity attr value]` from the forward cache for `attr`.
+    ///
+    /// The cache is kept up to date as transactions are written, so whatever it holds is the
+    /// current state of the store.  Returns false if the value place is one the cache can't
+    /// answer, in which case the caller should query the datoms table as usual.
+    fn forward_lookup(&mut self, known: Known, entity: Entid, attr: Entid, value: &EvolvedValuePlace) -> Result<bool> {
+        let attribute = match known.schema.attribute_for_entid(attr) {
+            Some(attribute) => attribute,
+            None => {
+                self.mark_known_empty(EmptyBecause::InvalidAttributeEntid(attr));
+                return Ok(true);
+            },
+        };
+
+        let values: Vec<TypedValue> = if attribute.multival {
+            known.get_values_for_entid(known.schema, attr, entity).cloned().unwrap_or_default()
+        } else {
+            known.get_value_for_entid(known.schema, attr, entity).cloned().into_iter().collect()
+        };
+
+        let expected = match *value {
+            EvolvedValuePlace::Variable(ref var) => {
+                match values.len() {
+                    0 => {
+                        self.mark_known_empty(EmptyBecause::CachedAttributeHasNoValues {
+                            entity: entity,
+                            attr: attr,
+                        });
+                    },
+                    1 => {
+                        self.bind_value(var, values.into_iter().next().unwrap());
+                    },
+                    _ => {
+                        self.collect_named_bindings(known.schema, vec![var.clone()], vec![attribute.value_type], values)?;
+                    },
+                }
+                return Ok(true);
+            },
+            EvolvedValuePlace::Value(ref val) => val.clone(),
+            EvolvedValuePlace::Entid(e) if attribute.value_type == ValueType::Ref => TypedValue::Ref(e),
+            _ => return Ok(false),
+        };
+
+        // A fully bound pattern binds nothing: it either holds or the query is empty.
+        if !values.contains(&expected) {
+            self.mark_known_empty(EmptyBecause::CachedDatomNotPresent {
+                entity: entity,
+                attr: attr,
+                value: expected,
+            });
         }
+        Ok(true)
     }
 
     // TODO: generalize.
     // TODO: use constant values -- extract transformation code from apply_pattern_clause_for_alias.
     // TODO: loop over all patterns until no more cache values apply?
-    fn attempt_cache_lookup(&mut self, known: Known, pattern: &EvolvedPattern) -> bool {
+    fn attempt_cache_lookup(&mut self, known: Known, pattern: &EvolvedPattern) -> Result<bool> {
         // Precondition: default source. If it's not default, don't call this.
         assert!(pattern.source == SrcVar::DefaultSrc);
 
         let schema = known.schema;
 
         if pattern.tx != EvolvedNonValuePlace::Placeholder {
-            return false;
+            return Ok(false);
         }
 
         // See if we can use the cache.
@@ -334,7 +410,7 @@ impl ConjoiningClauses {
                     // Furthermore, that entid must resolve to an attribute. If it doesn't, this
                     // query is meaningless.
                     self.mark_known_empty(EmptyBecause::InvalidAttributeEntid(attr));
-                    return true;
+                    return Ok(true);
                 }
 
                 let cached_forward = known.is_attribute_cached_forward(attr);
@@ -358,7 +434,7 @@ impl ConjoiningClauses {
                                         ValueType::Ref => {
                                             // It's an ident.
                                             // TODO
-                                            return false;
+                                            return Ok(false);
                                         },
                                         ValueType::Keyword => {
                                             let tv: TypedValue = TypedValue::Keyword(kw.clone());
@@ -368,7 +444,7 @@ impl ConjoiningClauses {
                                             let tv: TypedValue = TypedValue::Keyword(kw.clone());
                                             // Anything else can't match an IdentOrKeyword.
                                             self.mark_known_empty(EmptyBecause::ValueTypeMismatch(t, tv));
-                                            return true;
+                                            return Ok(true);
                                         },
                                     }
                                 },
@@ -383,25 +459,8 @@ impl ConjoiningClauses {
 
                         // Forward lookup.
                         EvolvedNonValuePlace::Entid(entity) => {
-                            match pattern.value {
-                                EvolvedValuePlace::Variable(ref var) => {
-                                    if cached_forward {
-                                        match known.get_value_for_entid(known.schema, attr, entity) {
-                                            None => {
-                                                self.mark_known_empty(EmptyBecause::CachedAttributeHasNoValues {
-                                                    entity: entity,
-                                                    attr: attr,
-                                                });
-                                                return true;
-                                            },
-                                            Some(item) => {
-                                                self.bind_value(var, item.clone());
-                                                return true;
-                                            }
-                                        }
-                                    }
-                                }
-                                _ => {},      // TODO: check constant values against cache.
+                            if cached_forward {
+                                return self.forward_lookup(known, entity, attr, &pattern.value);
                             }
                         },
                         _ => {},
@@ -410,7 +469,7 @@ impl ConjoiningClauses {
             },
             _ => {},
         }
-        false
+        Ok(false)
     }
 
     /// Transform a pattern place into a narrower type.
@@ -422,7 +481,7 @@ impl ConjoiningClauses {
             PatternNonValuePlace::Entid(e) => Place(EvolvedNonValuePlace::Entid(e)),
             PatternNonValuePlace::Ident(kw) => {
                 // Resolve the ident.
-                if let Some(entid) = known.schema.get_entid(&kw) {
+                if let Some(entid) = known.entid_for_ident(&kw) {
                     Place(EvolvedNonValuePlace::Entid(entid.into()))
                 } else {
                     Empty(EmptyBecause::UnresolvedIdent((&*kw).clone()))
@@ -435,7 +494,7 @@ impl ConjoiningClauses {
                     Some(TypedValue::Ref(entid)) => Place(EvolvedNonValuePlace::Entid(entid)),
                     Some(TypedValue::Keyword(kw)) => {
                         // We'll allow this only if it's an ident.
-                        if let Some(entid) = known.schema.get_entid(&kw) {
+                        if let Some(entid) = known.entid_for_ident(&kw) {
                             Place(EvolvedNonValuePlace::Entid(entid.into()))
                         } else {
                             Empty(EmptyBecause::UnresolvedIdent((&*kw).clone()))
@@ -446,6 +505,9 @@ impl ConjoiningClauses {
                     },
                 }
             },
+            PatternNonValuePlace::LookupRef(_) => {
+                unreachable!("lookup refs are expanded into patterns before patterns are evolved");
+            },
         }
     }
 
@@ -464,7 +526,7 @@ impl ConjoiningClauses {
             .and_then(|a| {
                 // Make sure that, if it's an entid, it names an attribute.
                 if let EvolvedNonValuePlace::Entid(e) = a {
-                    if let Some(attr) = known.schema.attribute_for_entid(e) {
+                    if let Some(attr) = known.attribute_facts(e) {
                         Place((a, Some(attr.value_type)))
                     } else {
                         Empty(EmptyBecause::InvalidAttributeEntid(e))
@@ -487,6 +549,7 @@ impl ConjoiningClauses {
                     Some(ValueType::Ref) => Place(EvolvedValuePlace::Entid(e)),
                     Some(ValueType::Long) => Place(EvolvedValuePlace::Value(TypedValue::Long(e))),
                     Some(ValueType::Double) => Place(EvolvedValuePlace::Value((e as f64).into())),
+                    Some(ValueType::BigInteger) => Place(EvolvedValuePlace::Value(TypedValue::BigInteger(e.into()))),
                     Some(t) => Empty(EmptyBecause::ValueTypeMismatch(t, TypedValue::Long(e))),
                     None => Place(EvolvedValuePlace::EntidOrInteger(e)),
                 }
@@ -495,7 +558,7 @@ impl ConjoiningClauses {
                 match value_type {
                     Some(ValueType::Ref) => {
                         // Resolve the ident.
-                        if let Some(entid) = known.schema.get_entid(&kw) {
+                        if let Some(entid) = known.entid_for_ident(&kw) {
                             Place(EvolvedValuePlace::Entid(entid.into()))
                         } else {
                             Empty(EmptyBecause::UnresolvedIdent((&*kw).clone()))
@@ -539,7 +602,7 @@ impl ConjoiningClauses {
     }
 
     pub(crate) fn make_evolved_pattern(&self, known: Known, pattern: Pattern) -> PlaceOrEmpty<EvolvedPattern> {
-        let (e, a, v, tx, source) = (pattern.entity, pattern.attribute, pattern.value, pattern.tx, pattern.source);
+        let (e, a, v, tx, added, source) = (pattern.entity, pattern.attribute, pattern.value, pattern.tx, pattern.added, pattern.source);
         use self::PlaceOrEmpty::*;
         match self.make_evolved_entity(&known, e) {
             Empty(because) => Empty(because),
@@ -553,13 +616,19 @@ impl ConjoiningClauses {
                                 match self.make_evolved_tx(&known, tx) {
                                     Empty(because) => Empty(because),
                                     Place(tx) => {
-                                        PlaceOrEmpty::Place(EvolvedPattern {
-                                            source: source.unwrap_or(SrcVar::DefaultSrc),
-                                            entity: e,
-                                            attribute: a,
-                                            value: v,
-                                            tx: tx,
-                                        })
+                                        match self.make_evolved_value(&known, Some(ValueType::Boolean), added) {
+                                            Empty(because) => Empty(because),
+                                            Place(added) => {
+                                                PlaceOrEmpty::Place(EvolvedPattern {
+                                                    source: source.unwrap_or(SrcVar::DefaultSrc),
+                                                    entity: e,
+                                                    attribute: a,
+                                                    value: v,
+                                                    tx: tx,
+                                                    added: added,
+                                                })
+                                            },
+                                        }
                                     },
                                 }
                             },
@@ -620,36 +689,91 @@ impl ConjoiningClauses {
         Place(pattern)
     }
 
+    /// Note `alias` if its `v` column might hold strings offloaded to `large_values`: those of
+    /// string attributes that aren't fulltext indexed, and those of attributes we don't know.
+    pub(crate) fn note_large_values(&self, known: Known, pattern: &EvolvedPattern, alias: &SourceAlias) {
+        if !known.large_values {
+            return;
+        }
+        match alias.0 {
+            DatomsTable::Datoms | DatomsTable::AllDatoms | DatomsTable::Transactions => {},
+            _ => return,
+        }
+        if self.get_attribute(known, pattern).map_or(true, |a| a.value_type == ValueType::String && !a.fulltext) {
+            known.note_large_values(&alias.1);
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn apply_parsed_pattern(&mut self, known: Known, pattern: Pattern) {
         use self::PlaceOrEmpty::*;
         match self.make_evolved_pattern(known, pattern) {
             Empty(e) => self.mark_known_empty(e),
-            Place(p) => self.apply_pattern(known, p),
+            Place(p) => self.apply_pattern(known, p).expect("applied pattern"),
         };
     }
 
-    pub(crate) fn apply_pattern(&mut self, known: Known, pattern: EvolvedPattern) {
-        // For now we only support the default source.
+    pub(crate) fn apply_pattern(&mut self, known: Known, mut pattern: EvolvedPattern) -> Result<()> {
+        // Sources are validated before patterns are applied: anything but the default is
+        // `$history`.
         if pattern.source != SrcVar::DefaultSrc {
-            unimplemented!();
+            return self.apply_history_pattern(known, pattern);
         }
 
-        if self.attempt_cache_lookup(known, &pattern) {
-            return;
+        // Values of :db/normalize attributes are stored in NFC; compare against the same form.
+        if let EvolvedNonValuePlace::Entid(a) = pattern.attribute {
+            if let Some(attribute) = known.schema.attribute_for_entid(a) {
+                if let EvolvedValuePlace::Value(ref mut v) = pattern.value {
+                    *v = normalize_value(attribute, v.clone());
+                }
+            }
         }
 
-        if let Some(alias) = self.alias_table(known.schema, &pattern) {
-            self.apply_pattern_clause_for_alias(known, &pattern, &alias);
+        if self.attempt_cache_lookup(known, &pattern)? {
+            return Ok(());
+        }
+
+        if let Some(alias) = self.alias_table(known, &pattern) {
+            self.apply_pattern_clause_for_alias(known, &pattern, &alias)?;
+            self.note_large_values(known, &pattern, &alias);
+            if let Some(index) = index_for_pattern(known, &pattern, &alias) {
+                self.index_hints.insert(alias.1.clone(), index);
+            }
             self.from.push(alias);
         } else {
             // We didn't determine a table, likely because there was a mismatch
             // between an attribute and a value.
             // We know we cannot return a result, so we short-circuit here.
             self.mark_known_empty(EmptyBecause::AttributeLookupFailed);
-            return;
         }
+        Ok(())
+    }
+}
+
+/// The attribute index, if any, that `pattern` should read `alias` with.  We prefer the index
+/// that starts with the value if the pattern gives one, and the one that starts with the entity
+/// otherwise: either can be used, because the pattern always constrains the attribute.
+fn index_for_pattern(known: Known, pattern: &EvolvedPattern, alias: &SourceAlias) -> Option<String> {
+    let indexes = known.indexes?;
+    if alias.0 != DatomsTable::Datoms {
+        return None;
     }
+    let attribute = match pattern.attribute {
+        EvolvedNonValuePlace::Entid(entid) => entid,
+        _ => return None,
+    };
+    let value_first = match pattern.value {
+        EvolvedValuePlace::Placeholder | EvolvedValuePlace::Variable(_) => false,
+        _ => true,
+    };
+    let preferred = if value_first {
+        [IndexDirection::Reverse, IndexDirection::Forward]
+    } else {
+        [IndexDirection::Forward, IndexDirection::Reverse]
+    };
+    preferred.iter()
+             .find(|direction| indexes.contains(attribute, **direction))
+             .map(|direction| direction.index_name(attribute))
 }
 
 #[cfg(test)]
@@ -714,6 +838,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         assert!(cc.is_known_empty());
@@ -733,6 +858,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         assert!(cc.is_known_empty());
@@ -757,6 +883,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // println!("{:#?}", cc);
@@ -798,6 +925,7 @@ mod testing {
             attribute: PatternNonValuePlace::Placeholder,
             value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // println!("{:#?}", cc);
@@ -848,6 +976,7 @@ mod testing {
             attribute: PatternNonValuePlace::Variable(a.clone()),
             value: PatternValuePlace::Variable(v.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // println!("{:#?}", cc);
@@ -892,6 +1021,7 @@ mod testing {
             attribute: PatternNonValuePlace::Variable(a.clone()),
             value: PatternValuePlace::Variable(v.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         assert!(cc.is_known_empty());
@@ -915,6 +1045,7 @@ mod testing {
             attribute: PatternNonValuePlace::Variable(a.clone()),
             value: PatternValuePlace::Variable(v.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // println!("{:#?}", cc);
@@ -946,6 +1077,7 @@ mod testing {
             attribute: PatternNonValuePlace::Placeholder,
             value: PatternValuePlace::Constant("hello".into()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // println!("{:#?}", cc);
@@ -997,6 +1129,7 @@ mod testing {
             attribute: ident("foo", "roz"),
             value: PatternValuePlace::Constant("idgoeshere".into()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
         cc.apply_parsed_pattern(known, Pattern {
             source: None,
@@ -1004,6 +1137,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // Finally, expand column bindings to get the overlaps for ?x.
@@ -1072,6 +1206,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         let d0_e = QualifiedAlias::new("datoms00".to_string(), DatomsColumn::Entity);
@@ -1120,6 +1255,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // The type of the provided binding doesn't match the type of the attribute.
@@ -1156,6 +1292,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // The type of the provided binding doesn't match the type of the attribute.
@@ -1191,6 +1328,7 @@ mod testing {
             attribute: ident("foo", "roz"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
         cc.apply_parsed_pattern(known, Pattern {
             source: None,
@@ -1198,6 +1336,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // Finally, expand column bindings to get the overlaps for ?x.
@@ -1234,6 +1373,7 @@ mod testing {
             attribute: PatternNonValuePlace::Variable(y.clone()),
             value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
         cc.apply_parsed_pattern(known, Pattern {
             source: None,
@@ -1241,6 +1381,7 @@ mod testing {
             attribute: PatternNonValuePlace::Variable(y.clone()),
             value: PatternValuePlace::Variable(x.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // Finally, expand column bindings to get the overlaps for ?x.
@@ -9,19 +9,24 @@
 // specific language governing permissions and limitations under the License.
 
 use core_traits::{
+    Entid,
+    TypedValue,
     ValueType,
     ValueTypeSet,
 };
 
 use mentat_core::{
+    HasSchema,
     Schema,
 };
 
 use edn::query::{
     FnArg,
+    NonIntegerConstant,
     PlainSymbol,
     Predicate,
     TypeAnnotation,
+    Variable,
 };
 
 use clauses::ConjoiningClauses;
@@ -34,10 +39,18 @@ use query_algebrizer_traits::errors::{
 };
 
 use types::{
+    Column,
     ColumnConstraint,
+    ColumnConstraintOrAlternation,
+    DatomsColumn,
+    DatomsTable,
     EmptyBecause,
     Inequality,
+    QualifiedAlias,
     QueryValue,
+    SourceAlias,
+    StringMatch,
+    TableAlias,
 };
 
 use Known;
@@ -47,14 +60,25 @@ impl ConjoiningClauses {
     /// There are several kinds of predicates in our Datalog:
     /// - A limited set of binary comparison operators: < > <= >= !=.
     ///   These are converted into SQLite binary comparisons and some type constraints.
+    /// - `starts-with` (or `str-starts-with?`), which is converted into a range over a string
+    ///   column.
+    /// - `str-includes?` and `regex-match`, which are converted into `GLOB` and `REGEXP`.
+    /// - `missing?`, which is converted into a `NOT EXISTS`.
     /// - In the future, some predicates that are implemented via function calls in SQLite.
-    ///
-    /// At present we have implemented only the five built-in comparison binary operators.
     pub(crate) fn apply_predicate(&mut self, known: Known, predicate: Predicate) -> Result<()> {
         // Because we'll be growing the set of built-in predicates, handling each differently,
         // and ultimately allowing user-specified predicates, we match on the predicate name first.
         if let Some(op) = Inequality::from_datalog_operator(predicate.operator.0.as_str()) {
             self.apply_inequality(known, op, predicate)
+        } else if predicate.operator.0.as_str() == "starts-with" ||
+                  predicate.operator.0.as_str() == "str-starts-with?" {
+            self.apply_starts_with(known, predicate)
+        } else if predicate.operator.0.as_str() == "str-includes?" {
+            self.apply_str_includes(known, predicate)
+        } else if predicate.operator.0.as_str() == "regex-match" {
+            self.apply_regex_match(known, predicate)
+        } else if predicate.operator.0.as_str() == "missing?" {
+            self.apply_missing(known, predicate)
         } else {
             bail!(AlgebrizerError::UnknownFunction(predicate.operator.clone()))
         }
@@ -162,6 +186,287 @@ impl ConjoiningClauses {
         self.wheres.add_intersection(constraint);
         Ok(())
     }
+
+    /// `(starts-with ?v "prefix")` holds when the string `?v` begins with `prefix`.
+    ///
+    /// We don't use `LIKE`, which ignores ASCII case and can't use our indices.  Instead we
+    /// constrain the value to a range: `v >= 'prefix' AND v < 'prefiy'`.  If `?v` is the value of an
+    /// indexed attribute we also constrain the value type tag and `index_avet`, which lets SQLite
+    /// answer the range from the partial AVET index rather than scanning every value of the
+    /// attribute.
+    ///
+    /// SQLite compares text using the `BINARY` collation, which for UTF-8 is code point order.
+    /// That makes the match case-sensitive, and means that no Unicode normalization is done: a
+    /// decomposed "é" doesn't start with a precomposed one.  Fold or normalize values and prefixes
+    /// yourself if that matters.
+    ///
+    /// The range is over values as they're stored, and a string offloaded to `large_values` is
+    /// stored as an integer, which sorts before any text, so `starts-with` never matches one.
+    /// `str-includes?` and `regex-match` read offloaded strings.
+    ///
+    /// The prefix must be a string constant or a bound input.  `str-starts-with?` is another name
+    /// for `starts-with`.
+    pub(crate) fn apply_starts_with(&mut self, known: Known, predicate: Predicate) -> Result<()> {
+        if predicate.args.len() != 2 {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(predicate.operator.clone(), predicate.args.len(), 2));
+        }
+
+        let mut args = predicate.args.into_iter();
+        let value = args.next().expect("two args");
+        let prefix = args.next().expect("two args");
+
+        let prefix = self.resolve_string_constant(&predicate.operator, 1, prefix)?;
+        let var = match value {
+            FnArg::Variable(var) => var,
+            _ => bail!(AlgebrizerError::InvalidArgument(predicate.operator.clone(), "variable", 0)),
+        };
+
+        // If we already know the value, we can evaluate the predicate right now.
+        match self.bound_value(&var) {
+            Some(TypedValue::String(s)) => {
+                if !s.starts_with(prefix.as_str()) {
+                    self.mark_known_empty(EmptyBecause::PrefixMismatch {
+                        var: var,
+                        value: s.as_ref().clone(),
+                        prefix: prefix,
+                    });
+                }
+                return Ok(());
+            },
+            Some(v) => bail!(AlgebrizerError::InputTypeDisagreement(var.name().clone(), ValueType::String, v.value_type())),
+            None => {},
+        }
+
+        let column = match self.string_column(&var)? {
+            Some(column) => column,
+            None => return Ok(()),
+        };
+
+        // Every string starts with the empty string.
+        if prefix.is_empty() {
+            return Ok(());
+        }
+
+        self.constrain_to_string_values(known, &column, true);
+
+        let upper = prefix_successor(&prefix);
+        self.wheres.add_intersection(ColumnConstraint::Inequality {
+            operator: Inequality::GreaterThanOrEquals,
+            left: QueryValue::Column(column.clone()),
+            right: QueryValue::TypedValue(TypedValue::typed_string(prefix)),
+        });
+        if let Some(upper) = upper {
+            self.wheres.add_intersection(ColumnConstraint::Inequality {
+                operator: Inequality::LessThan,
+                left: QueryValue::Column(column),
+                right: QueryValue::TypedValue(TypedValue::typed_string(upper)),
+            });
+        }
+        Ok(())
+    }
+
+    /// `(str-includes? ?v "substring")` holds when the string `?v` contains `substring`.
+    ///
+    /// This is converted into `v GLOB '*substring*'`, with any of `GLOB`'s special characters in
+    /// `substring` escaped.  Unlike `LIKE`, `GLOB` is case-sensitive, and so agrees with
+    /// `starts-with`.  No index can help, so every value of the attribute is examined.
+    ///
+    /// The substring must be a string constant or a bound input.
+    pub(crate) fn apply_str_includes(&mut self, known: Known, predicate: Predicate) -> Result<()> {
+        if predicate.args.len() != 2 {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(predicate.operator.clone(), predicate.args.len(), 2));
+        }
+
+        let mut args = predicate.args.into_iter();
+        let value = args.next().expect("two args");
+        let substring = args.next().expect("two args");
+
+        let substring = self.resolve_string_constant(&predicate.operator, 1, substring)?;
+        let var = match value {
+            FnArg::Variable(var) => var,
+            _ => bail!(AlgebrizerError::InvalidArgument(predicate.operator.clone(), "variable", 0)),
+        };
+
+        match self.bound_value(&var) {
+            Some(TypedValue::String(s)) => {
+                if !s.contains(substring.as_str()) {
+                    self.mark_known_empty(EmptyBecause::SubstringMismatch {
+                        var: var,
+                        value: s.as_ref().clone(),
+                        substring: substring,
+                    });
+                }
+                return Ok(());
+            },
+            Some(v) => bail!(AlgebrizerError::InputTypeDisagreement(var.name().clone(), ValueType::String, v.value_type())),
+            None => {},
+        }
+
+        let column = match self.string_column(&var)? {
+            Some(column) => column,
+            None => return Ok(()),
+        };
+
+        // Every string includes the empty string.
+        if substring.is_empty() {
+            return Ok(());
+        }
+
+        self.constrain_to_string_values(known, &column, false);
+        self.wheres.add_intersection(ColumnConstraint::StringMatches {
+            operator: StringMatch::Glob,
+            value: QueryValue::Column(column),
+            pattern: QueryValue::TypedValue(TypedValue::typed_string(glob_including(&substring))),
+        });
+        Ok(())
+    }
+
+    /// `(regex-match ?v "pattern")` holds when the regular expression `pattern` matches some part
+    /// of the string `?v`.  Anchor the pattern with `^` and `$` to match all of `?v`.
+    ///
+    /// This is converted into `v REGEXP 'pattern'`, which SQLite evaluates by calling the `regexp`
+    /// function that Mentat registers on each connection.  Patterns use the syntax of the Rust
+    /// `regex` crate, and are only compiled when the query is run: an invalid pattern is reported
+    /// as a SQLite error then.
+    ///
+    /// The pattern must be a string constant or a bound input.
+    pub(crate) fn apply_regex_match(&mut self, known: Known, predicate: Predicate) -> Result<()> {
+        if predicate.args.len() != 2 {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(predicate.operator.clone(), predicate.args.len(), 2));
+        }
+
+        let mut args = predicate.args.into_iter();
+        let value = args.next().expect("two args");
+        let pattern = args.next().expect("two args");
+
+        let pattern = self.resolve_string_constant(&predicate.operator, 1, pattern)?;
+        let var = match value {
+            FnArg::Variable(var) => var,
+            _ => bail!(AlgebrizerError::InvalidArgument(predicate.operator.clone(), "variable", 0)),
+        };
+
+        // We can't evaluate a bound value here, so we leave that to SQLite, too.
+        let value = match self.bound_value(&var) {
+            Some(TypedValue::String(s)) => QueryValue::TypedValue(TypedValue::String(s)),
+            Some(v) => bail!(AlgebrizerError::InputTypeDisagreement(var.name().clone(), ValueType::String, v.value_type())),
+            None => {
+                let column = match self.string_column(&var)? {
+                    Some(column) => column,
+                    None => return Ok(()),
+                };
+                self.constrain_to_string_values(known, &column, false);
+                QueryValue::Column(column)
+            },
+        };
+
+        self.wheres.add_intersection(ColumnConstraint::StringMatches {
+            operator: StringMatch::Regexp,
+            value: value,
+            pattern: QueryValue::TypedValue(TypedValue::typed_string(pattern)),
+        });
+        Ok(())
+    }
+
+    /// Return the string that the argument in position `position` of the string predicate
+    /// `operator` stands for.  That argument must be a string constant or a bound input.
+    fn resolve_string_constant(&self, operator: &PlainSymbol, position: usize, arg: FnArg) -> Result<String> {
+        match arg {
+            FnArg::Constant(NonIntegerConstant::Text(s)) => Ok(s.as_ref().clone()),
+            FnArg::Variable(var) => {
+                match self.bound_value(&var) {
+                    Some(TypedValue::String(s)) => Ok(s.as_ref().clone()),
+                    Some(v) => bail!(AlgebrizerError::InputTypeDisagreement(var.name().clone(), ValueType::String, v.value_type())),
+                    None => bail!(AlgebrizerError::InvalidArgument(operator.clone(), "bound string", position)),
+                }
+            },
+            _ => bail!(AlgebrizerError::InvalidArgumentType(operator.clone(), ValueTypeSet::of_one(ValueType::String), position)),
+        }
+    }
+
+    /// Require `var`, the value of a string predicate, to be a string, and return the column
+    /// that binds it.  Returns `None` if that means the query can't match.
+    fn string_column(&mut self, var: &Variable) -> Result<Option<QualifiedAlias>> {
+        self.constrain_var_to_type(var.clone(), ValueType::String);
+        if self.is_known_empty() {
+            return Ok(None);
+        }
+
+        let column = self.column_bindings
+                         .get(var)
+                         .and_then(|cols| cols.first().cloned())
+                         .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()))?;
+        Ok(Some(column))
+    }
+
+    /// Keywords are stored as text, too, so unless the attribute already tells us that `column`
+    /// holds strings we must check the type tag.  If `use_avet` and the attribute is indexed, we
+    /// constrain the type tag and `index_avet` so that SQLite can use the partial AVET index,
+    /// which leads with the type tag.
+    fn constrain_to_string_values(&mut self, known: Known, column: &QualifiedAlias, use_avet: bool) {
+        if column.1 != Column::Fixed(DatomsColumn::Value) {
+            return;
+        }
+
+        let table = column.0.clone();
+        let attribute = self.attribute_for_table(&table)
+                            .and_then(|a| known.schema.attribute_for_entid(a));
+        let indexed = use_avet &&
+                      attribute.map_or(false, |a| a.index) &&
+                      self.from.contains(&SourceAlias(DatomsTable::Datoms, table.clone()));
+        if attribute.is_none() || indexed {
+            self.wheres.add_intersection(ColumnConstraint::has_unit_type(table.clone(), ValueType::String));
+        }
+        if indexed {
+            self.wheres.add_intersection(ColumnConstraint::InAVETIndex(table));
+        }
+    }
+
+    /// Return the attribute that the datoms table `table` is constrained to, if there is one.
+    pub(crate) fn attribute_for_table(&self, table: &TableAlias) -> Option<Entid> {
+        self.wheres.0.iter().filter_map(|constraint| {
+            match constraint {
+                &ColumnConstraintOrAlternation::Constraint(
+                    ColumnConstraint::Equals(QualifiedAlias(ref t, Column::Fixed(DatomsColumn::Attribute)),
+                                             QueryValue::Entid(entid))) if t == table => Some(entid),
+                _ => None,
+            }
+        }).next()
+    }
+}
+
+/// Return a `GLOB` pattern that matches the strings that include `substring`.  Each of `GLOB`'s
+/// special characters in `substring` is escaped by putting it in a character class of its own.
+fn glob_including(substring: &str) -> String {
+    let mut pattern = String::with_capacity(substring.len() + 2);
+    pattern.push('*');
+    for c in substring.chars() {
+        match c {
+            '*' | '?' | '[' => {
+                pattern.push('[');
+                pattern.push(c);
+                pattern.push(']');
+            },
+            c => pattern.push(c),
+        }
+    }
+    pattern.push('*');
+    pattern
+}
+
+/// Return the smallest string that's greater than every string starting with `prefix`, or `None`
+/// if there isn't one.  That's `prefix` with its last character incremented, dropping any
+/// characters that can't be.
+fn prefix_successor(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        // Skip over the surrogates, which aren't chars.
+        let next = if last == '\u{D7FF}' { 0xE000 } else { last as u32 + 1 };
+        if let Some(next) = ::std::char::from_u32(next) {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
 }
 
 impl Inequality {
@@ -244,6 +549,7 @@ mod testing {
             attribute: PatternNonValuePlace::Placeholder,
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
         assert!(!cc.is_known_empty());
 
@@ -305,6 +611,7 @@ mod testing {
             attribute: PatternNonValuePlace::Placeholder,
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
         assert!(!cc.is_known_empty());
 
@@ -323,6 +630,7 @@ mod testing {
             attribute: ident("foo", "roz"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // Finally, expand column bindings to get the overlaps for ?x.
@@ -59,6 +59,7 @@ impl ConjoiningClauses {
                     self.column_bindings
                         .get(&var)
                         .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
+                        .or_else(|| self.parameter_for_input(&var))
                         .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()).into())
                 }
             },
@@ -71,6 +72,7 @@ impl ConjoiningClauses {
             Constant(NonIntegerConstant::Uuid(_)) |
             Constant(NonIntegerConstant::Instant(_)) |        // Instants are covered below.
             Constant(NonIntegerConstant::BigInteger(_)) |
+            Constant(NonIntegerConstant::Bytes(_)) |
             Vector(_) => {
                 self.mark_known_empty(EmptyBecause::NonNumericArgument);
                 bail!(AlgebrizerError::InvalidArgument(function.clone(), "numeric", position))
@@ -92,6 +94,7 @@ impl ConjoiningClauses {
                         self.column_bindings
                             .get(&var)
                             .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
+                            .or_else(|| self.parameter_for_input(&var))
                             .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()).into())
                     },
                 }
@@ -109,6 +112,7 @@ impl ConjoiningClauses {
             Constant(NonIntegerConstant::Text(_)) |
             Constant(NonIntegerConstant::Uuid(_)) |
             Constant(NonIntegerConstant::BigInteger(_)) |
+            Constant(NonIntegerConstant::Bytes(_)) |
             Vector(_) => {
                 self.mark_known_empty(EmptyBecause::NonInstantArgument);
                 bail!(AlgebrizerError::InvalidArgumentType(function.clone(), ValueType::Instant.into(), position))
@@ -130,6 +134,7 @@ impl ConjoiningClauses {
                     self.column_bindings
                         .get(&var)
                         .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
+                        .or_else(|| self.parameter_for_input(&var))
                         .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()).into())
                 }
             },
@@ -145,6 +150,7 @@ impl ConjoiningClauses {
             Constant(NonIntegerConstant::Uuid(_)) |
             Constant(NonIntegerConstant::Instant(_)) |
             Constant(NonIntegerConstant::BigInteger(_)) |
+            Constant(NonIntegerConstant::Bytes(_)) |
             SrcVar(_) |
             Vector(_) => {
                 self.mark_known_empty(EmptyBecause::NonEntityArgument);
@@ -175,6 +181,7 @@ impl ConjoiningClauses {
                         self.column_bindings
                             .get(&var)
                             .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
+                            .or_else(|| self.parameter_for_input(&var))
                             .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()).into())
                     },
                 }
@@ -185,8 +192,9 @@ impl ConjoiningClauses {
             Constant(NonIntegerConstant::Float(f)) => Ok(QueryValue::TypedValue(TypedValue::Double(f))),
             Constant(NonIntegerConstant::Text(s)) => Ok(QueryValue::TypedValue(TypedValue::typed_string(s.as_str()))),
             Constant(NonIntegerConstant::Uuid(u)) => Ok(QueryValue::TypedValue(TypedValue::Uuid(u))),
+            Constant(NonIntegerConstant::Bytes(b)) => Ok(QueryValue::TypedValue(TypedValue::Bytes(b))),
             Constant(NonIntegerConstant::Instant(u)) => Ok(QueryValue::TypedValue(TypedValue::Instant(u))),
-            Constant(NonIntegerConstant::BigInteger(_)) => unimplemented!(),
+            Constant(NonIntegerConstant::BigInteger(i)) => Ok(QueryValue::TypedValue(TypedValue::BigInteger(i))),
             SrcVar(_) => unimplemented!(),
             Vector(_) => unimplemented!(),    // TODO
         }
@@ -0,0 +1,542 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use std::rc::Rc;
+
+use core_traits::{
+    ValueTypeSet,
+};
+
+use edn::query::{
+    Binding,
+    ContainsVariables,
+    FnArg,
+    NotJoin,
+    Optional,
+    OrJoin,
+    OrWhereClause,
+    Pattern,
+    PatternNonValuePlace,
+    PatternValuePlace,
+    PlainSymbol,
+    Predicate,
+    Rule,
+    RuleExpr,
+    TypeAnnotation,
+    UnifyVars,
+    Variable,
+    VariableOrPlaceholder,
+    WhereClause,
+    WhereFn,
+};
+
+use clauses::{
+    ConjoiningClauses,
+    PushComputed,
+};
+
+use query_algebrizer_traits::errors::{
+    AlgebrizerError,
+    Result,
+};
+
+use types::{
+    ComputedTable,
+    EmptyBecause,
+    QualifiedAlias,
+    VariableColumn,
+};
+
+use validate::{
+    validate_or_join,
+};
+
+use Known;
+
+/// The rules defined by a query's `:rules`, grouped by name.
+///
+/// Rules are checked when they're defined, so that invoking one can't fail for reasons other than
+/// the invocation itself. A rule is recursive if any of its definitions can reach it again. We
+/// support recursion only of the linear kind that SQLite's `WITH RECURSIVE` can express: a
+/// recursive rule has one definition that invokes the rule exactly once, at the top level of its
+/// body, and at least one definition that doesn't invoke it at all.
+#[derive(Debug, Default)]
+pub(crate) struct Rules {
+    definitions: BTreeMap<PlainSymbol, Vec<Rule>>,
+    recursive: BTreeSet<PlainSymbol>,
+}
+
+/// Within the recursive arm of a recursive rule, how to refer back to the rule's own table.
+#[derive(Debug)]
+pub(crate) struct RecursiveReference {
+    name: PlainSymbol,
+    table: String,
+    columns: Vec<Variable>,
+    types: Vec<ValueTypeSet>,
+}
+
+/// Accumulate the names of the rules invoked anywhere within `clauses`.
+fn accumulate_invoked_rules<'a, I>(clauses: I, acc: &mut Vec<PlainSymbol>) where I: IntoIterator<Item=&'a WhereClause> {
+    for clause in clauses {
+        match clause {
+            &WhereClause::RuleExpr(ref expr) => acc.push(expr.name.clone()),
+            &WhereClause::OrJoin(ref o) => {
+                for arm in o.clauses.iter() {
+                    match arm {
+                        &OrWhereClause::Clause(ref c) => accumulate_invoked_rules(::std::iter::once(c), acc),
+                        &OrWhereClause::And(ref cs) => accumulate_invoked_rules(cs.iter(), acc),
+                    }
+                }
+            },
+            &WhereClause::NotJoin(ref n) => accumulate_invoked_rules(n.clauses.iter(), acc),
+            &WhereClause::Optional(ref o) => accumulate_invoked_rules(o.clauses.iter(), acc),
+            _ => {},
+        }
+    }
+}
+
+fn invoked_rules(clauses: &[WhereClause]) -> Vec<PlainSymbol> {
+    let mut acc = vec![];
+    accumulate_invoked_rules(clauses.iter(), &mut acc);
+    acc
+}
+
+impl Rules {
+    pub(crate) fn new(rules: Vec<Rule>) -> Result<Rules> {
+        let mut definitions: BTreeMap<PlainSymbol, Vec<Rule>> = BTreeMap::new();
+        for rule in rules.into_iter() {
+            if let Some(existing) = definitions.get(&rule.name) {
+                if existing[0].vars.len() != rule.vars.len() {
+                    bail!(AlgebrizerError::InvalidRule(rule.name.clone(), "every definition must have the same number of variables"));
+                }
+            }
+
+            let mentioned: BTreeSet<Variable> = rule.clauses.iter().flat_map(|c| c.collect_mentioned_variables()).collect();
+            if rule.vars.iter().any(|v| !mentioned.contains(v)) {
+                bail!(AlgebrizerError::InvalidRule(rule.name.clone(), "every variable in a rule's head must appear in its body"));
+            }
+
+            definitions.entry(rule.name.clone()).or_insert(vec![]).push(rule);
+        }
+
+        // Which rules can each rule reach?
+        let calls: BTreeMap<&PlainSymbol, BTreeSet<PlainSymbol>> =
+            definitions.iter()
+                       .map(|(name, rules)| (name, rules.iter().flat_map(|r| invoked_rules(&r.clauses)).collect()))
+                       .collect();
+        let reachable = |from: &PlainSymbol| -> BTreeSet<PlainSymbol> {
+            let mut seen: BTreeSet<PlainSymbol> = BTreeSet::new();
+            let mut pending: Vec<&PlainSymbol> = vec![from];
+            while let Some(name) = pending.pop() {
+                if let Some(callees) = calls.get(name) {
+                    for callee in callees.iter() {
+                        if seen.insert(callee.clone()) {
+                            pending.push(callee);
+                        }
+                    }
+                }
+            }
+            seen
+        };
+
+        let mut recursive = BTreeSet::new();
+        for (name, rules) in definitions.iter() {
+            let reached = reachable(name);
+            if !reached.contains(name) {
+                continue;
+            }
+
+            if reached.iter().any(|other| other != name && reachable(other).contains(name)) {
+                bail!(AlgebrizerError::UnsupportedRule(name.clone(), "mutually recursive rules are not supported"));
+            }
+
+            let mut recursive_definitions = 0;
+            for rule in rules.iter() {
+                let direct = rule.clauses.iter().filter(|c| match c {
+                    &&WhereClause::RuleExpr(ref expr) => &expr.name == name,
+                    _ => false,
+                }).count();
+                let all = invoked_rules(&rule.clauses).into_iter().filter(|n| n == name).count();
+                if all > direct {
+                    bail!(AlgebrizerError::UnsupportedRule(name.clone(), "a recursive rule can't invoke itself within or or not"));
+                }
+                if direct > 1 {
+                    bail!(AlgebrizerError::UnsupportedRule(name.clone(), "a recursive rule can only invoke itself once in each definition"));
+                }
+                recursive_definitions += direct;
+            }
+            if recursive_definitions > 1 {
+                bail!(AlgebrizerError::UnsupportedRule(name.clone(), "a recursive rule can only have one recursive definition"));
+            }
+            if recursive_definitions == rules.len() {
+                bail!(AlgebrizerError::InvalidRule(name.clone(), "a recursive rule needs a definition that doesn't invoke itself"));
+            }
+            recursive.insert(name.clone());
+        }
+
+        Ok(Rules {
+            definitions,
+            recursive,
+        })
+    }
+}
+
+/// Rewrites the clauses of a rule definition for a particular invocation: the head's variables
+/// become the invocation's arguments, and every other variable gets a fresh name, so that
+/// separate invocations don't interfere with each other or with the enclosing query.
+struct Substitution<'a> {
+    rule: &'a PlainSymbol,
+    replacements: BTreeMap<Variable, FnArg>,
+}
+
+impl<'a> Substitution<'a> {
+    fn new(cc: &ConjoiningClauses, rule: &'a Rule, args: &[FnArg]) -> Substitution<'a> {
+        let mut replacements: BTreeMap<Variable, FnArg> =
+            rule.vars.iter().cloned().zip(args.iter().cloned()).collect();
+        for clause in rule.clauses.iter() {
+            for var in clause.collect_mentioned_variables() {
+                if !replacements.contains_key(&var) {
+                    let fresh = Variable::from_valid_name(&format!("{}__{}", var.as_str(), cc.alias_counter.next()));
+                    replacements.insert(var, FnArg::Variable(fresh));
+                }
+            }
+        }
+        Substitution {
+            rule: &rule.name,
+            replacements,
+        }
+    }
+
+    fn arg(&self, arg: &FnArg) -> FnArg {
+        match arg {
+            &FnArg::Variable(ref v) => self.replacements.get(v).cloned().unwrap_or_else(|| arg.clone()),
+            &FnArg::Vector(ref args) => FnArg::Vector(args.iter().map(|a| self.arg(a)).collect()),
+            _ => arg.clone(),
+        }
+    }
+
+    fn var(&self, var: &Variable) -> Result<Variable> {
+        match self.arg(&FnArg::Variable(var.clone())) {
+            FnArg::Variable(v) => Ok(v),
+            _ => bail!(AlgebrizerError::InvalidRule(self.rule.clone(), "only a variable can be passed where the rule binds or unifies it")),
+        }
+    }
+
+    fn non_value_place(&self, place: &PatternNonValuePlace) -> Result<PatternNonValuePlace> {
+        match place {
+            &PatternNonValuePlace::Variable(ref v) => {
+                match self.arg(&FnArg::Variable(v.clone())) {
+                    FnArg::Variable(v) => Ok(PatternNonValuePlace::Variable(v)),
+                    FnArg::EntidOrInteger(e) => Ok(PatternNonValuePlace::Entid(e)),
+                    FnArg::IdentOrKeyword(k) => Ok(PatternNonValuePlace::Ident(k.into())),
+                    _ => bail!(AlgebrizerError::InvalidRule(self.rule.clone(), "only an entity can be passed where the rule expects an entity")),
+                }
+            },
+            _ => Ok(place.clone()),
+        }
+    }
+
+    fn value_place(&self, place: &PatternValuePlace) -> Result<PatternValuePlace> {
+        match place {
+            &PatternValuePlace::Variable(ref v) => {
+                match self.arg(&FnArg::Variable(v.clone())) {
+                    FnArg::Variable(v) => Ok(PatternValuePlace::Variable(v)),
+                    FnArg::EntidOrInteger(e) => Ok(PatternValuePlace::EntidOrInteger(e)),
+                    FnArg::IdentOrKeyword(k) => Ok(PatternValuePlace::IdentOrKeyword(k.into())),
+                    FnArg::Constant(c) => Ok(PatternValuePlace::Constant(c)),
+                    _ => bail!(AlgebrizerError::InvalidRule(self.rule.clone(), "only a value can be passed where the rule expects a value")),
+                }
+            },
+            _ => Ok(place.clone()),
+        }
+    }
+
+    fn binding(&self, binding: &Binding) -> Result<Binding> {
+        let var_or_placeholder = |v: &VariableOrPlaceholder| -> Result<VariableOrPlaceholder> {
+            match v {
+                &VariableOrPlaceholder::Variable(ref v) => self.var(v).map(VariableOrPlaceholder::Variable),
+                &VariableOrPlaceholder::Placeholder => Ok(VariableOrPlaceholder::Placeholder),
+            }
+        };
+        Ok(match binding {
+            &Binding::BindScalar(ref v) => Binding::BindScalar(self.var(v)?),
+            &Binding::BindColl(ref v) => Binding::BindColl(self.var(v)?),
+            &Binding::BindRel(ref vs) => Binding::BindRel(vs.iter().map(&var_or_placeholder).collect::<Result<_>>()?),
+            &Binding::BindTuple(ref vs) => Binding::BindTuple(vs.iter().map(&var_or_placeholder).collect::<Result<_>>()?),
+        })
+    }
+
+    fn unify_vars(&self, unify_vars: &UnifyVars) -> Result<UnifyVars> {
+        Ok(match unify_vars {
+            &UnifyVars::Implicit => UnifyVars::Implicit,
+            &UnifyVars::Explicit(ref vs) => UnifyVars::Explicit(vs.iter().map(|v| self.var(v)).collect::<Result<_>>()?),
+        })
+    }
+
+    fn clauses(&self, clauses: &[WhereClause]) -> Result<Vec<WhereClause>> {
+        clauses.iter().map(|c| self.clause(c)).collect()
+    }
+
+    fn clause(&self, clause: &WhereClause) -> Result<WhereClause> {
+        Ok(match clause {
+            &WhereClause::Pattern(ref p) => {
+                WhereClause::Pattern(Pattern {
+                    source: p.source.clone(),
+                    entity: self.non_value_place(&p.entity)?,
+                    attribute: self.non_value_place(&p.attribute)?,
+                    value: self.value_place(&p.value)?,
+                    tx: self.non_value_place(&p.tx)?,
+                    added: self.value_place(&p.added)?,
+                })
+            },
+            &WhereClause::Pred(ref p) => {
+                WhereClause::Pred(Predicate {
+                    operator: p.operator.clone(),
+                    args: p.args.iter().map(|a| self.arg(a)).collect(),
+                })
+            },
+            &WhereClause::WhereFn(ref f) => {
+                WhereClause::WhereFn(WhereFn {
+                    operator: f.operator.clone(),
+                    args: f.args.iter().map(|a| self.arg(a)).collect(),
+                    binding: self.binding(&f.binding)?,
+                })
+            },
+            &WhereClause::RuleExpr(ref r) => {
+                WhereClause::RuleExpr(RuleExpr {
+                    name: r.name.clone(),
+                    args: r.args.iter().map(|a| self.arg(a)).collect(),
+                })
+            },
+            &WhereClause::OrJoin(ref o) => {
+                let arms = o.clauses.iter().map(|arm| {
+                    Ok(match arm {
+                        &OrWhereClause::Clause(ref c) => OrWhereClause::Clause(self.clause(c)?),
+                        &OrWhereClause::And(ref cs) => OrWhereClause::And(self.clauses(cs)?),
+                    })
+                }).collect::<Result<_>>()?;
+                WhereClause::OrJoin(OrJoin::new(self.unify_vars(&o.unify_vars)?, arms).with_source(o.source.clone()))
+            },
+            &WhereClause::NotJoin(ref n) => {
+                WhereClause::NotJoin(NotJoin::new(self.unify_vars(&n.unify_vars)?, self.clauses(&n.clauses)?)
+                                         .with_source(n.source.clone()))
+            },
+            &WhereClause::Optional(ref o) => {
+                WhereClause::Optional(Optional::new(self.clauses(&o.clauses)?).with_source(o.source.clone()))
+            },
+            &WhereClause::TypeAnnotation(ref a) => {
+                WhereClause::TypeAnnotation(TypeAnnotation {
+                    value_type: a.value_type.clone(),
+                    variable: self.var(&a.variable)?,
+                })
+            },
+        })
+    }
+}
+
+fn expect_variables(name: &PlainSymbol, args: &[FnArg]) -> Result<Vec<Variable>> {
+    args.iter().map(|arg| match arg {
+        &FnArg::Variable(ref v) => Ok(v.clone()),
+        _ => bail!(AlgebrizerError::UnsupportedRule(name.clone(), "a recursive rule can only be invoked with variables")),
+    }).collect()
+}
+
+fn is_invocation_of(clause: &WhereClause, name: &PlainSymbol) -> bool {
+    match clause {
+        &WhereClause::RuleExpr(ref expr) => &expr.name == name,
+        _ => false,
+    }
+}
+
+impl ConjoiningClauses {
+    pub(crate) fn use_rules(&mut self, rules: Vec<Rule>) -> Result<()> {
+        self.rules = Rc::new(Rules::new(rules)?);
+        Ok(())
+    }
+
+    /// Apply a rule invocation.
+    ///
+    /// A rule with a single definition is simply applied in place. A rule with several
+    /// definitions becomes an `or-join` on the invocation's variables, each arm the body of one
+    /// definition. A recursive rule becomes a `WITH RECURSIVE` table that's joined against the
+    /// invocation's variables. For example,
+    ///
+    /// ```edn
+    /// [:find ?a
+    ///  :where (ancestor "Alice" ?a)
+    ///  :rules [[(ancestor ?x ?y) [?x :person/parent ?y]]
+    ///          [(ancestor ?x ?y) [?x :person/parent ?z] (ancestor ?z ?y)]]]
+    /// ```
+    ///
+    /// computes the `ancestor` relation once, with each definition's body as an arm of the
+    /// recursive table.
+    pub(crate) fn apply_rule_expr(&mut self, known: Known, expr: RuleExpr) -> Result<()> {
+        let rules = self.rules.clone();
+        let definitions = rules.definitions
+                               .get(&expr.name)
+                               .ok_or_else(|| AlgebrizerError::UnknownRule(expr.name.clone()))?;
+
+        let arity = definitions[0].vars.len();
+        if expr.args.len() != arity {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(expr.name.clone(), expr.args.len(), arity));
+        }
+
+        let is_reference = match self.recursive_reference {
+            Some(ref reference) => reference.name == expr.name,
+            None => false,
+        };
+        if is_reference {
+            return self.apply_rule_reference(known, expr);
+        }
+
+        if rules.recursive.contains(&expr.name) {
+            return self.apply_recursive_rule(known, expr, definitions);
+        }
+
+        if definitions.len() == 1 {
+            let clauses = Substitution::new(self, &definitions[0], &expr.args[..]).clauses(&definitions[0].clauses)?;
+            return self.apply_clauses(known, clauses);
+        }
+
+        let unify: BTreeSet<Variable> = expr.collect_mentioned_variables();
+        if unify.is_empty() {
+            bail!(AlgebrizerError::UnsupportedRule(expr.name.clone(), "a rule with several definitions must be invoked with at least one variable"));
+        }
+
+        let arms = definitions.iter().map(|rule| {
+            Substitution::new(self, rule, &expr.args[..]).clauses(&rule.clauses).map(OrWhereClause::And)
+        }).collect::<Result<Vec<_>>>()?;
+
+        let or_join = OrJoin::new(UnifyVars::Explicit(unify), arms);
+        validate_or_join(&or_join)?;
+        self.apply_or_join(known, or_join)
+    }
+
+    fn apply_recursive_rule(&mut self, known: Known, expr: RuleExpr, definitions: &[Rule]) -> Result<()> {
+        let args = expect_variables(&expr.name, &expr.args[..])?;
+
+        // Each definition projects the same columns, named for the table.
+        let table = format!("rule{:02}", self.alias_counter.next());
+        let columns: Vec<Variable> = (0..args.len()).map(|i| Variable::from_valid_name(&format!("?{}_{}", table, i)))
+                                                   .collect();
+        let column_args: Vec<FnArg> = columns.iter().cloned().map(FnArg::Variable).collect();
+
+        let mut base = vec![];
+        let mut recursive = None;
+        for rule in definitions.iter() {
+            let clauses = Substitution::new(self, rule, &column_args[..]).clauses(&rule.clauses)?;
+            if clauses.iter().any(|c| is_invocation_of(c, &expr.name)) {
+                recursive = Some(clauses);
+            } else {
+                base.push(clauses);
+            }
+        }
+
+        let mut arms: Vec<ConjoiningClauses> = Vec::with_capacity(definitions.len());
+        let mut empty_because: Option<EmptyBecause> = None;
+        for clauses in base.into_iter() {
+            let arm = self.rule_arm(known, &expr.name, &columns, clauses, None)?;
+            match arm {
+                Ok(arm) => arms.push(arm),
+                Err(why) => empty_because = Some(why),
+            }
+        }
+
+        if arms.is_empty() {
+            self.mark_known_empty(empty_because.expect("empty for a reason"));
+            return Ok(());
+        }
+
+        let base_types = column_types(&arms[..], &columns);
+        if let Some(clauses) = recursive {
+            let reference = RecursiveReference {
+                name: expr.name.clone(),
+                table: table.clone(),
+                columns: columns.clone(),
+                types: base_types,
+            };
+            if let Ok(arm) = self.rule_arm(known, &expr.name, &columns, clauses, Some(reference))? {
+                arms.push(arm);
+            }
+        }
+
+        let types = column_types(&arms[..], &columns);
+        let computed = ComputedTable::RecursiveRule {
+            name: table,
+            columns: columns.clone(),
+            arms,
+        };
+        let table = self.computed_tables.push_computed(computed);
+        let alias = self.next_alias_for_table(table);
+        self.bind_rule_columns(known, alias.clone(), &columns, args, types)?;
+        self.from.push(::types::SourceAlias(table, alias));
+        Ok(())
+    }
+
+    /// Algebrize one definition of a recursive rule, returning why it can't match if it can't.
+    fn rule_arm(&self,
+                known: Known,
+                name: &PlainSymbol,
+                columns: &[Variable],
+                clauses: Vec<WhereClause>,
+                reference: Option<RecursiveReference>) -> Result<::std::result::Result<ConjoiningClauses, EmptyBecause>> {
+        let mut arm = ConjoiningClauses::with_alias_counter(self.alias_counter.clone());
+        arm.rules = self.rules.clone();
+        arm.recursive_reference = reference.map(Rc::new);
+        arm.apply_clauses(known, clauses)?;
+        if let Some(why) = arm.empty_because.take() {
+            return Ok(Err(why));
+        }
+        arm.expand_column_bindings();
+        arm.prune_extracted_types();
+        arm.process_required_types()?;
+
+        for column in columns.iter() {
+            if !arm.column_bindings.contains_key(column) && !arm.value_bindings.contains_key(column) {
+                bail!(AlgebrizerError::InvalidRule(name.clone(), "every variable in a recursive rule's head must be bound by each definition"));
+            }
+        }
+        Ok(Ok(arm))
+    }
+
+    /// Within the recursive arm of a recursive rule, join against the rows computed so far.
+    fn apply_rule_reference(&mut self, known: Known, expr: RuleExpr) -> Result<()> {
+        let args = expect_variables(&expr.name, &expr.args[..])?;
+        let reference = self.recursive_reference.clone().expect("a recursive reference");
+        let table = self.computed_tables.push_computed(ComputedTable::RuleReference(reference.table.clone()));
+        let alias = self.next_alias_for_table(table);
+        self.bind_rule_columns(known, alias.clone(), &reference.columns, args, reference.types.clone())?;
+        self.from.push(::types::SourceAlias(table, alias));
+        Ok(())
+    }
+
+    fn bind_rule_columns(&mut self, known: Known, alias: String, columns: &[Variable], args: Vec<Variable>, types: Vec<ValueTypeSet>) -> Result<()> {
+        for ((column, arg), types) in columns.iter().zip(args.into_iter()).zip(types.into_iter()) {
+            self.bind_column_to_var(known.schema, alias.clone(), VariableColumn::Variable(column.clone()), arg.clone())?;
+            self.narrow_types_for_var(arg.clone(), types);
+            if !types.is_unit() && self.known_type(&arg).is_none() && !self.extracted_types.contains_key(&arg) {
+                self.extracted_types.insert(arg, QualifiedAlias::new(alias.clone(), VariableColumn::VariableTypeTag(column.clone())));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The types each arm might give each of `columns`.
+fn column_types(arms: &[ConjoiningClauses], columns: &[Variable]) -> Vec<ValueTypeSet> {
+    columns.iter()
+           .map(|column| arms.iter().fold(ValueTypeSet::none(), |acc, arm| acc.union(&arm.known_type_set(column))))
+           .collect()
+}
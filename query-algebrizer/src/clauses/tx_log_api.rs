@@ -9,14 +9,27 @@
 // specific language governing permissions and limitations under the License.
 
 use core_traits::{
+    TypedValue,
     ValueType,
 };
 
+use mentat_core::{
+    HasSchema,
+    ValueRc,
+};
+
 use edn::query::{
     Binding,
     FnArg,
+    Keyword,
+    NonIntegerConstant,
+    Pattern,
+    PatternNonValuePlace,
+    PatternValuePlace,
+    PlainSymbol,
     SrcVar,
     VariableOrPlaceholder,
+    WhereClause,
     WhereFn,
 };
 
@@ -33,11 +46,13 @@ use query_algebrizer_traits::errors::{
 use types::{
     Column,
     ColumnConstraint,
+    DatomsColumn,
     DatomsTable,
     Inequality,
     QualifiedAlias,
     QueryValue,
     SourceAlias,
+    TableAlias,
     TransactionsColumn,
 };
 
@@ -53,13 +68,15 @@ impl ConjoiningClauses {
     //
     // [(tx-ids ?log ?tx1 ?tx2) [?tx ...]]
     //
+    // The range includes `?tx1` but not `?tx2`.  Either bound can be an instant rather than a
+    // transaction, in which case it's compared to each transaction's `:db/txInstant`, and `?tx2`
+    // can be omitted altogether to take every transaction from `?tx1` on.
+    //
     // TODO: handle tx1 == 0 (more generally, tx1 < bootstrap::TX0) specially (no after constraint).
-    // TODO: allow tx2 to be missing (no before constraint).
-    // TODO: allow txK arguments to be instants.
     // TODO: allow arbitrary additional attribute arguments that restrict the tx-ids to those
     // transactions that impact one of the given attributes.
     pub(crate) fn apply_tx_ids(&mut self, known: Known, where_fn: WhereFn) -> Result<()> {
-        if where_fn.args.len() != 3 {
+        if where_fn.args.len() != 2 && where_fn.args.len() != 3 {
             bail!(AlgebrizerError::InvalidNumberOfArguments(where_fn.operator.clone(), where_fn.args.len(), 3));
         }
 
@@ -104,9 +121,6 @@ impl ConjoiningClauses {
             _ => bail!(AlgebrizerError::InvalidArgument(where_fn.operator.clone(), "source variable", 0)),
         }
 
-        let tx1 = self.resolve_tx_argument(&known.schema, &where_fn.operator, 1, args.next().unwrap())?;
-        let tx2 = self.resolve_tx_argument(&known.schema, &where_fn.operator, 2, args.next().unwrap())?;
-
         let transactions = self.next_alias_for_table(DatomsTable::Transactions);
 
         self.from.push(SourceAlias(DatomsTable::Transactions, transactions.clone()));
@@ -117,25 +131,92 @@ impl ConjoiningClauses {
             return Ok(());
         }
 
-        self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Tx, tx_var.clone());
+        self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Tx, tx_var.clone())?;
 
-        let after_constraint = ColumnConstraint::Inequality {
-            operator: Inequality::LessThanOrEquals,
-            left: tx1,
-            right: QueryValue::Column(QualifiedAlias(transactions.clone(), Column::Transactions(TransactionsColumn::Tx))),
-        };
-        self.wheres.add_intersection(after_constraint);
+        let tx1 = args.next().unwrap();
+        self.constrain_tx_range(known, &where_fn.operator, &transactions, 1, tx1)?;
+
+        if let Some(tx2) = args.next() {
+            self.constrain_tx_range(known, &where_fn.operator, &transactions, 2, tx2)?;
+        }
 
-        let before_constraint = ColumnConstraint::Inequality {
-            operator: Inequality::LessThan,
-            left: QueryValue::Column(QualifiedAlias(transactions.clone(), Column::Transactions(TransactionsColumn::Tx))),
-            right: tx2,
+        Ok(())
+    }
+
+    /// Constrain the transactions in `transactions` to those on or after the bound in position 1,
+    /// or before the bound in position 2.  A bound is a transaction, or an instant to compare with
+    /// the transaction's `:db/txInstant`.
+    fn constrain_tx_range(&mut self, known: Known, function: &PlainSymbol, transactions: &TableAlias, position: usize, bound: FnArg) -> Result<()> {
+        let tx = QualifiedAlias(transactions.clone(), Column::Transactions(TransactionsColumn::Tx));
+        let (column, bound) = match bound {
+            FnArg::Constant(NonIntegerConstant::Instant(instant)) => {
+                // Transactions are entities like any other, so we can find when each was
+                // transacted by joining its `:db/txInstant` datom.
+                let tx_instant = known.schema.get_entid(&Keyword::namespaced("db", "txInstant"))
+                                             .ok_or_else(|| AlgebrizerError::UnrecognizedIdent(":db/txInstant".to_string()))?;
+                let datoms = self.next_alias_for_table(DatomsTable::Datoms);
+                self.from.push(SourceAlias(DatomsTable::Datoms, datoms.clone()));
+                self.wheres.add_intersection(ColumnConstraint::Equals(
+                    QualifiedAlias(datoms.clone(), Column::Fixed(DatomsColumn::Entity)),
+                    QueryValue::Column(tx)));
+                self.wheres.add_intersection(ColumnConstraint::Equals(
+                    QualifiedAlias(datoms.clone(), Column::Fixed(DatomsColumn::Attribute)),
+                    QueryValue::Entid(tx_instant.into())));
+                (QueryValue::Column(QualifiedAlias(datoms, Column::Fixed(DatomsColumn::Value))),
+                 QueryValue::TypedValue(TypedValue::Instant(instant)))
+            },
+            bound => {
+                (QueryValue::Column(tx),
+                 self.resolve_tx_argument(&known.schema, function, position, bound)?)
+            },
         };
-        self.wheres.add_intersection(before_constraint);
 
+        let constraint = if position == 1 {
+            ColumnConstraint::Inequality {
+                operator: Inequality::LessThanOrEquals,
+                left: bound,
+                right: column,
+            }
+        } else {
+            ColumnConstraint::Inequality {
+                operator: Inequality::LessThan,
+                left: column,
+                right: bound,
+            }
+        };
+        self.wheres.add_intersection(constraint);
         Ok(())
     }
 
+    // `[(tx-instant ?tx) ?when]` binds `?when` to the instant at which `?tx` was transacted.  That's
+    // just what `[?tx :db/txInstant ?when]` does, and so that's how we apply it: by joining the
+    // transaction's own datom.
+    pub(crate) fn apply_tx_instant(&mut self, known: Known, where_fn: WhereFn) -> Result<()> {
+        if where_fn.args.len() != 1 {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(where_fn.operator.clone(), where_fn.args.len(), 1));
+        }
+
+        let when = match where_fn.binding {
+            Binding::BindScalar(var) => var,
+            _ => bail!(AlgebrizerError::InvalidBinding(where_fn.operator.clone(), BindingError::ExpectedBindScalar)),
+        };
+
+        let tx = match where_fn.args.into_iter().next().unwrap() {
+            FnArg::Variable(var) => PatternNonValuePlace::Variable(var),
+            FnArg::EntidOrInteger(entid) => PatternNonValuePlace::Entid(entid),
+            _ => bail!(AlgebrizerError::InvalidArgument(where_fn.operator.clone(), "transaction", 0)),
+        };
+
+        self.apply_clause(known, WhereClause::Pattern(Pattern {
+            source: None,
+            entity: tx,
+            attribute: PatternNonValuePlace::Ident(ValueRc::new(Keyword::namespaced("db", "txInstant"))),
+            value: PatternValuePlace::Variable(when),
+            tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
+        }))
+    }
+
     pub(crate) fn apply_tx_data(&mut self, known: Known, where_fn: WhereFn) -> Result<()> {
         if where_fn.args.len() != 2 {
             bail!(AlgebrizerError::InvalidNumberOfArguments(where_fn.operator.clone(), where_fn.args.len(), 2));
@@ -201,7 +282,7 @@ impl ConjoiningClauses {
                 return Ok(());
             }
 
-            self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Entity, var.clone());
+            self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Entity, var.clone())?;
         }
 
         if let VariableOrPlaceholder::Variable(ref var) = b_a {
@@ -211,11 +292,11 @@ impl ConjoiningClauses {
                 return Ok(());
             }
 
-            self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Attribute, var.clone());
+            self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Attribute, var.clone())?;
         }
 
         if let VariableOrPlaceholder::Variable(ref var) = b_v {
-            self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Value, var.clone());
+            self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Value, var.clone())?;
         }
 
         if let VariableOrPlaceholder::Variable(ref var) = b_tx {
@@ -227,7 +308,7 @@ impl ConjoiningClauses {
 
             // TODO: this might be a programming error if var is our tx argument.  Perhaps we can be
             // helpful in that case.
-            self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Tx, var.clone());
+            self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Tx, var.clone())?;
         }
 
         if let VariableOrPlaceholder::Variable(ref var) = b_op {
@@ -237,7 +318,7 @@ impl ConjoiningClauses {
                 return Ok(());
             }
 
-            self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Added, var.clone());
+            self.bind_column_to_var(known.schema, transactions.clone(), TransactionsColumn::Added, var.clone())?;
         }
 
         Ok(())
@@ -319,6 +400,37 @@ mod testing {
                    vec![ValueType::Ref].into_iter().collect());
     }
 
+    #[test]
+    fn test_apply_tx_ids_without_upper_bound() {
+        let mut cc = ConjoiningClauses::default();
+        let schema = Schema::default();
+
+        let known = Known::for_schema(&schema);
+
+        let op = PlainSymbol::plain("tx-ids");
+        cc.apply_tx_ids(known, WhereFn {
+            operator: op,
+            args: vec![
+                FnArg::SrcVar(SrcVar::DefaultSrc),
+                FnArg::EntidOrInteger(1000),
+            ],
+            binding: Binding::BindColl(Variable::from_valid_name("?tx")),
+        }).expect("to be able to apply_tx_ids");
+
+        assert!(!cc.is_known_empty());
+
+        // There's only the lower bound.
+        let clauses = cc.wheres;
+        assert_eq!(clauses.len(), 1);
+
+        assert_eq!(clauses.0[0],
+                   ColumnConstraint::Inequality {
+                       operator: Inequality::LessThanOrEquals,
+                       left: QueryValue::TypedValue(TypedValue::Ref(1000)),
+                       right: QueryValue::Column(QualifiedAlias("transactions00".to_string(), Column::Transactions(TransactionsColumn::Tx))),
+                   }.into());
+    }
+
     #[test]
     fn test_apply_tx_data() {
         let mut cc = ConjoiningClauses::default();
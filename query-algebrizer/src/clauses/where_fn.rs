@@ -8,6 +8,10 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use core_traits::{
+    ValueType,
+};
+
 use edn::query::{
     WhereFn,
 };
@@ -27,7 +31,9 @@ use Known;
 impl ConjoiningClauses {
     /// There are several kinds of functions binding variables in our Datalog:
     /// - A set of functions like `ground`, fulltext` and `get-else` that are translated into SQL
-    ///   `VALUES`, `MATCH`, or `JOIN`, yielding bindings.
+    ///   `VALUES`, `MATCH`, or `JOIN`, yielding bindings.  `get-else` and `get-some` are
+    ///   `LEFT JOIN`s.
+    /// - Conversions like `long` and `double`, which are translated into SQL `CAST`s.
     /// - In the future, some functions that are implemented via function calls in SQLite.
     ///
     /// At present we have implemented only a limited selection of functions.
@@ -35,10 +41,15 @@ impl ConjoiningClauses {
         // Because we'll be growing the set of built-in functions, handling each differently, and
         // ultimately allowing user-specified functions, we match on the function name first.
         match where_fn.operator.0.as_str() {
+            "double" => self.apply_conversion(known, ValueType::Double, where_fn),
             "fulltext" => self.apply_fulltext(known, where_fn),
+            "get-else" => self.apply_get_else(known, where_fn),
+            "get-some" => self.apply_get_some(known, where_fn),
             "ground" => self.apply_ground(known, where_fn),
+            "long" => self.apply_conversion(known, ValueType::Long, where_fn),
             "tx-data" => self.apply_tx_data(known, where_fn),
             "tx-ids" => self.apply_tx_ids(known, where_fn),
+            "tx-instant" => self.apply_tx_instant(known, where_fn),
             _ => bail!(AlgebrizerError::UnknownFunction(where_fn.operator.clone())),
         }
     }
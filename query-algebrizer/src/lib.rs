@@ -16,34 +16,62 @@ extern crate mentat_core;
 extern crate core_traits;
 extern crate query_algebrizer_traits;
 
-use std::collections::BTreeSet;
+use std::cell::RefCell;
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
 use std::ops::Sub;
 use std::rc::Rc;
 
 mod types;
 mod validate;
 mod clauses;
+mod memo;
+
+use validate::{
+    validate_in_sources,
+};
+
+use memo::{
+    AttributeFacts,
+    AttributeMemo,
+};
 
 use core_traits::{
     Entid,
+    KnownEntid,
     TypedValue,
     ValueType,
+    ValueTypeSet,
 };
 
 use mentat_core::{
+    AttributeIndexes,
     CachedAttributes,
+    HasSchema,
     Schema,
+    Statistics,
     parse_query,
 };
 
 use mentat_core::counter::RcCounter;
 
 use edn::query::{
+    Aggregate,
+    Binding,
     Element,
     FindSpec,
+    FnArg,
+    Keyword,
     Limit,
+    NonIntegerConstant,
+    Offset,
     Order,
+    OrderExpression,
     ParsedQuery,
+    PlainSymbol,
+    QueryFunction,
     SrcVar,
     Variable,
     WhereClause,
@@ -51,6 +79,7 @@ use edn::query::{
 
 use query_algebrizer_traits::errors::{
     AlgebrizerError,
+    BindingError,
     Result,
 };
 
@@ -72,6 +101,19 @@ pub use types::{
 pub struct Known<'s, 'c> {
     pub schema: &'s Schema,
     pub cache: Option<&'c CachedAttributes>,
+
+    /// Without these, patterns are joined in the order they're written.
+    pub statistics: Option<&'c Statistics>,
+
+    /// Patterns that use an attribute with an index of its own read from that index.
+    pub indexes: Option<&'c AttributeIndexes>,
+
+    /// Whether some string might have been offloaded to `large_values`.  Until one has, strings
+    /// are all stored inline, and are compared and ordered as they're stored.
+    pub large_values: bool,
+
+    memo: Option<&'c AttributeMemo>,
+    large_value_aliases: Option<&'c RefCell<BTreeSet<TableAlias>>>,
 }
 
 impl<'s, 'c> Known<'s, 'c> {
@@ -79,6 +121,11 @@ impl<'s, 'c> Known<'s, 'c> {
         Known {
             schema: s,
             cache: None,
+            statistics: None,
+            indexes: None,
+            large_values: false,
+            memo: None,
+            large_value_aliases: None,
         }
     }
 
@@ -86,6 +133,76 @@ impl<'s, 'c> Known<'s, 'c> {
         Known {
             schema: s,
             cache: c,
+            statistics: None,
+            indexes: None,
+            large_values: false,
+            memo: None,
+            large_value_aliases: None,
+        }
+    }
+
+    /// Use `statistics` to decide which patterns to join first.
+    pub fn with_statistics(self, statistics: &'c Statistics) -> Known<'s, 'c> {
+        Known {
+            statistics: Some(statistics),
+            ..self
+        }
+    }
+
+    /// Have patterns read from the attribute indexes in `indexes`.
+    pub fn with_indexes(self, indexes: &'c AttributeIndexes) -> Known<'s, 'c> {
+        Known {
+            indexes: Some(indexes),
+            ..self
+        }
+    }
+
+    /// Match, order, and test strings by their text, whether they're stored inline or offloaded to
+    /// `large_values`.  This costs a subquery for each, so is only worth doing once some string
+    /// has been offloaded.
+    pub fn with_large_values(self, large_values: bool) -> Known<'s, 'c> {
+        Known {
+            large_values,
+            ..self
+        }
+    }
+
+    /// Remember attribute lookups in `memo`, which must be empty or have been filled from this
+    /// schema.  Every `ConjoiningClauses` given the result -- nested ones included -- shares it.
+    fn with_memo(self, memo: &'c AttributeMemo) -> Known<'s, 'c> {
+        Known {
+            memo: Some(memo),
+            ..self
+        }
+    }
+
+    /// Collect in `aliases` the tables whose values might have been offloaded.  Like the memo,
+    /// every `ConjoiningClauses` given the result shares it.
+    fn with_large_value_aliases(self, aliases: &'c RefCell<BTreeSet<TableAlias>>) -> Known<'s, 'c> {
+        Known {
+            large_value_aliases: Some(aliases),
+            ..self
+        }
+    }
+
+    /// Note that the `v` column of `alias` might hold strings offloaded to `large_values`.
+    pub(crate) fn note_large_values(&self, alias: &TableAlias) {
+        if let Some(aliases) = self.large_value_aliases {
+            aliases.borrow_mut().insert(alias.clone());
+        }
+    }
+
+    pub(crate) fn entid_for_ident(&self, ident: &Keyword) -> Option<KnownEntid> {
+        match self.memo {
+            Some(memo) => memo.entid_for_ident(self.schema, ident),
+            None => self.schema.get_entid(ident),
+        }
+    }
+
+    pub(crate) fn attribute_facts(&self, entid: Entid) -> Option<AttributeFacts> {
+        match self.memo {
+            Some(memo) => memo.attribute_facts(self.schema, entid),
+            None => self.schema.attribute_for_entid(entid).map(AttributeFacts::of),
         }
     }
 }
@@ -146,6 +263,22 @@ pub struct AlgebraicQuery {
     pub named_projection: BTreeSet<Variable>,
     pub order: Option<Vec<OrderBy>>,
     pub limit: Limit,
+    pub offset: Offset,
+
+    /// False if the query asked for duplicate rows to be kept, rather than removed with
+    /// `SELECT DISTINCT`.
+    pub distinct: bool,
+
+    /// The `:in` variables that weren't given values, and the types each can take.  Their values
+    /// are bound to SQL parameters -- see `mentat_query_sql::format_select_var` -- when the query
+    /// is run.
+    pub parameters: BTreeMap<Variable, ValueTypeSet>,
+
+    /// The tables whose `v` column might hold strings offloaded to `large_values`, which are
+    /// stored by id, and so must be matched and ordered by the text they stand for.  Empty unless
+    /// the query was algebrized with `Known::large_values`.
+    pub large_value_aliases: BTreeSet<TableAlias>,
+
     pub cc: clauses::ConjoiningClauses,
 }
 
@@ -155,6 +288,12 @@ impl AlgebraicQuery {
         self.cc.is_known_empty()
     }
 
+    /// Return true if the find spec includes an aggregate, and so results will be grouped.
+    #[inline]
+    pub fn has_aggregates(&self) -> bool {
+        self.has_aggregates
+    }
+
     /// Return true if every variable in the find spec is fully bound to a single value.
     pub fn is_fully_bound(&self) -> bool {
         self.find_spec
@@ -186,6 +325,36 @@ impl AlgebraicQuery {
     pub fn unbound_variables(&self) -> BTreeSet<Variable> {
         self.cc.input_variables.sub(&self.cc.value_bound_variable_set())
     }
+
+    /// Return the unbound `:in` variables that can't be bound later as parameters, because we
+    /// don't know enough about their types.
+    pub fn unparameterized_variables(&self) -> BTreeSet<Variable> {
+        self.unbound_variables()
+            .into_iter()
+            .filter(|var| !self.parameters.contains_key(var))
+            .collect()
+    }
+
+    /// Return the types that each variable might have, as far as we can tell without running the
+    /// query: every variable bound to a column or a value, and every `:in` variable.  A variable
+    /// whose type we know nothing about might have any type.
+    pub fn variable_types(&self) -> BTreeMap<Variable, ValueTypeSet> {
+        let cc = &self.cc;
+        cc.column_bindings
+          .keys()
+          .chain(cc.value_bound_variables())
+          .chain(cc.input_variables.iter())
+          .chain(cc.known_types.keys())
+          .map(|var| {
+              let types = cc.known_type_set(var);
+              let types = match self.parameters.get(var) {
+                  Some(parameter_types) => types.intersection(parameter_types),
+                  None => types,
+              };
+              (var.clone(), types)
+          })
+          .collect()
+    }
 }
 
 pub fn algebrize_with_counter(known: Known, parsed: FindQuery, counter: usize) -> Result<AlgebraicQuery> {
@@ -196,11 +365,65 @@ pub fn algebrize(known: Known, parsed: FindQuery) -> Result<AlgebraicQuery> {
     algebrize_with_inputs(known, parsed, 0, QueryInputs::default())
 }
 
+/// The variables whose values a query's results depend on: those it projects, aggregates,
+/// pulls, or orders by, and those in `:with`.
+fn needed_variables(parsed: &FindQuery) -> BTreeSet<Variable> {
+    fn accumulate_arg(arg: &FnArg, acc: &mut BTreeSet<Variable>) {
+        match arg {
+            &FnArg::Variable(ref var) => {
+                acc.insert(var.clone());
+            },
+            &FnArg::Vector(ref args) => {
+                for arg in args.iter() {
+                    accumulate_arg(arg, acc);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    let mut needed = parsed.with.clone();
+    for element in parsed.find_spec.columns() {
+        match element {
+            &Element::Variable(ref var) |
+            &Element::Corresponding(ref var) => {
+                needed.insert(var.clone());
+            },
+            &Element::Pull(ref pull) => {
+                needed.insert(pull.var.clone());
+            },
+            &Element::Aggregate(ref aggregate) => {
+                for arg in aggregate.args.iter() {
+                    accumulate_arg(arg, &mut needed);
+                }
+            },
+        }
+    }
+    if let Some(ref order) = parsed.order {
+        for &Order(_, ref expression) in order.iter() {
+            match expression {
+                &OrderExpression::Variable(ref var) => {
+                    needed.insert(var.clone());
+                },
+                &OrderExpression::Call { ref args, .. } => {
+                    for arg in args.iter() {
+                        accumulate_arg(arg, &mut needed);
+                    }
+                },
+            }
+        }
+    }
+    needed
+}
+
 /// Take an ordering list. Any variables that aren't fixed by the query are used to produce
 /// a vector of `OrderBy` instances, including type comparisons if necessary. This function also
 /// returns a set of variables that should be added to the `with` clause to make the ordering
 /// clauses possible.
-fn validate_and_simplify_order(cc: &ConjoiningClauses, order: Option<Vec<Order>>)
+///
+/// A function call in the ordering list is either an aggregate that appears in the find spec,
+/// or arithmetic on numeric variables and numbers.
+fn validate_and_simplify_order(cc: &ConjoiningClauses, find_spec: &FindSpec, order: Option<Vec<Order>>)
     -> Result<(Option<Vec<OrderBy>>, BTreeSet<Variable>)> {
     match order {
         None => Ok((None, BTreeSet::default())),
@@ -208,7 +431,46 @@ fn validate_and_simplify_order(cc: &ConjoiningClauses, order: Option<Vec<Order>>
             let mut order_bys: Vec<OrderBy> = Vec::with_capacity(order.len() * 2);   // Space for tags.
             let mut vars: BTreeSet<Variable> = BTreeSet::default();
 
-            for Order(direction, var) in order.into_iter() {
+            for Order(direction, expression) in order.into_iter() {
+                let var = match expression {
+                    OrderExpression::Variable(var) => var,
+                    OrderExpression::Call { func, args } => {
+                        let in_find_spec = find_spec.columns().any(|e| match e {
+                            &Element::Aggregate(ref a) => a.func == func && a.args == args,
+                            _ => false,
+                        });
+                        if in_find_spec {
+                            order_bys.push(OrderBy(direction, OrderColumn::Aggregate(Aggregate { func, args })));
+                            continue;
+                        }
+
+                        let op = match ArithmeticOp::for_function(&func) {
+                            Some(op) => op,
+                            None => bail!(AlgebrizerError::InvalidOrderExpression(OrderExpression::Call { func, args }.to_string())),
+                        };
+                        if args.len() < 2 {
+                            bail!(AlgebrizerError::InvalidNumberOfArguments(func.0.clone(), 2, args.len()));
+                        }
+
+                        let mut operands = Vec::with_capacity(args.len());
+                        for (position, arg) in args.into_iter().enumerate() {
+                            operands.push(arithmetic_operand(cc, &func, position, arg)?);
+                        }
+
+                        // Arithmetic on fixed values orders nothing.
+                        let operand_vars: Vec<Variable> = operands.iter().filter_map(|o| match o {
+                            &ArithmeticOperand::Variable(ref var) => Some(var.clone()),
+                            &ArithmeticOperand::Value(_) => None,
+                        }).collect();
+                        if operand_vars.is_empty() {
+                            continue;
+                        }
+                        vars.extend(operand_vars);
+                        order_bys.push(OrderBy(direction, OrderColumn::Arithmetic(op, operands)));
+                        continue;
+                    },
+                };
+
                 // Eliminate any ordering clauses that are bound to fixed values.
                 if cc.bound_value(&var).is_some() {
                     continue;
@@ -221,9 +483,9 @@ fn validate_and_simplify_order(cc: &ConjoiningClauses, order: Option<Vec<Order>>
 
                 // Otherwise, determine if we also need to order by type…
                 if cc.known_type(&var).is_none() {
-                    order_bys.push(OrderBy(direction.clone(), VariableColumn::VariableTypeTag(var.clone())));
+                    order_bys.push(OrderBy(direction.clone(), OrderColumn::Variable(VariableColumn::VariableTypeTag(var.clone()))));
                 }
-                order_bys.push(OrderBy(direction, VariableColumn::Variable(var.clone())));
+                order_bys.push(OrderBy(direction, OrderColumn::Variable(VariableColumn::Variable(var.clone()))));
                 vars.insert(var.clone());
             }
 
@@ -232,8 +494,34 @@ fn validate_and_simplify_order(cc: &ConjoiningClauses, order: Option<Vec<Order>>
     }
 }
 
+/// Resolve an argument of ordering arithmetic: a number, or a variable that the query binds to
+/// numbers.  Fixed values are substituted, because they aren't projected.
+fn arithmetic_operand(cc: &ConjoiningClauses, function: &QueryFunction, position: usize, arg: FnArg) -> Result<ArithmeticOperand> {
+    match arg {
+        FnArg::Variable(var) => {
+            if let Some(value) = cc.bound_value(&var) {
+                if value.value_type().is_numeric() {
+                    return Ok(ArithmeticOperand::Value(value));
+                }
+                bail!(AlgebrizerError::InvalidArgumentType(function.0.clone(), ValueTypeSet::of_numeric_types(), position));
+            }
+            if !cc.column_bindings.contains_key(&var) {
+                bail!(AlgebrizerError::UnboundVariable(var.name()))
+            }
+            // We don't order by type here, so every value must be a number.
+            if !cc.known_type_set(&var).is_only_numeric() {
+                bail!(AlgebrizerError::InvalidArgumentType(function.0.clone(), ValueTypeSet::of_numeric_types(), position));
+            }
+            Ok(ArithmeticOperand::Variable(var))
+        },
+        FnArg::EntidOrInteger(i) => Ok(ArithmeticOperand::Value(TypedValue::Long(i))),
+        FnArg::Constant(NonIntegerConstant::Float(f)) => Ok(ArithmeticOperand::Value(TypedValue::Double(f))),
+        _ => bail!(AlgebrizerError::InvalidArgument(function.0.clone(), "numeric", position)),
+    }
+}
+
 
-fn simplify_limit(mut query: AlgebraicQuery) -> Result<AlgebraicQuery> {
+fn simplify_limit_and_offset(mut query: AlgebraicQuery) -> Result<AlgebraicQuery> {
     // Unpack any limit variables in place.
     let refined_limit =
         match query.limit {
@@ -268,6 +556,32 @@ fn simplify_limit(mut query: AlgebraicQuery) -> Result<AlgebraicQuery> {
     if let Some(lim) = refined_limit {
         query.limit = lim;
     }
+
+    // Same for offsets, except that zero is fine.
+    let refined_offset =
+        match query.offset {
+            Offset::Variable(ref v) => {
+                match query.cc.bound_value(v) {
+                    Some(TypedValue::Long(n)) => {
+                        if n < 0 {
+                            bail!(AlgebrizerError::InvalidOffset(n.to_string(), ValueType::Long))
+                        } else {
+                            Some(Offset::Fixed(n as u64))
+                        }
+                    },
+                    Some(val) => {
+                        bail!(AlgebrizerError::InvalidOffset(format!("{:?}", val), val.value_type()))
+                    },
+                    None => None,
+                }
+            },
+            Offset::None => None,
+            Offset::Fixed(_) => None,
+        };
+
+    if let Some(off) = refined_offset {
+        query.offset = off;
+    }
     Ok(query)
 }
 
@@ -275,42 +589,71 @@ pub fn algebrize_with_inputs(known: Known,
                              parsed: FindQuery,
                              counter: usize,
                              inputs: QueryInputs) -> Result<AlgebraicQuery> {
+    // Parts of the query are moved into the CC, so we find the variables we need first.
+    let needed = needed_variables(&parsed);
+
+    let memo = AttributeMemo::default();
+    let large_value_aliases = RefCell::new(BTreeSet::new());
+    let known = known.with_memo(&memo).with_large_value_aliases(&large_value_aliases);
     let alias_counter = RcCounter::with_initial(counter);
+    let mut inputs = inputs;
+    let relations = inputs.take_relations();
     let mut cc = ConjoiningClauses::with_inputs_and_alias_counter(parsed.in_vars, inputs, alias_counter);
 
     // This is so the rest of the query knows that `?x` is a ref if `(pull ?x …)` appears in `:find`.
     cc.derive_types_from_find_spec(&parsed.find_spec);
 
+    // Collection and relation inputs become computed tables that the rest of the query joins.
+    cc.apply_input_bindings(known.schema, parsed.in_bindings, relations)?;
+
     // Do we have a variable limit? If so, tell the CC that the var must be numeric.
     if let &Limit::Variable(ref var) = &parsed.limit {
         cc.constrain_var_to_long(var.clone());
     }
 
+    // Likewise a variable offset.
+    if let &Offset::Variable(ref var) = &parsed.offset {
+        cc.constrain_var_to_long(var.clone());
+    }
+
+    cc.use_rules(parsed.rules)?;
+
     // TODO: integrate default source into pattern processing.
     // TODO: flesh out the rest of find-into-context.
-    cc.apply_clauses(known, parsed.where_clauses)?;
+    // Testing for patterns with `EXISTS` relies on `DISTINCT` to discard the rows that joining
+    // them would have duplicated, so a query that keeps duplicates joins them all.
+    if parsed.distinct {
+        cc.apply_where_clauses(known, &needed, parsed.where_clauses)?;
+    } else {
+        cc.apply_clauses(known, parsed.where_clauses)?;
+    }
 
     cc.expand_column_bindings();
+    let parameters = cc.bind_parameters();
     cc.prune_extracted_types();
     cc.process_required_types()?;
 
-    let (order, extra_vars) = validate_and_simplify_order(&cc, parsed.order)?;
+    let (order, extra_vars) = validate_and_simplify_order(&cc, &parsed.find_spec, parsed.order)?;
 
     // This might leave us with an unused `:in` variable.
     let limit = if parsed.find_spec.is_unit_limited() { Limit::Fixed(1) } else { parsed.limit };
     let q = AlgebraicQuery {
         default_source: parsed.default_source,
+        has_aggregates: parsed.find_spec.has_aggregates(),
         find_spec: Rc::new(parsed.find_spec),
-        has_aggregates: false,           // TODO: we don't parse them yet.
         with: parsed.with,
         named_projection: extra_vars,
         order: order,
         limit: limit,
+        offset: parsed.offset,
+        distinct: parsed.distinct,
+        parameters: parameters,
+        large_value_aliases: large_value_aliases.into_inner(),
         cc: cc,
     };
 
     // Substitute in any fixed values and fail if they're out of range.
-    simplify_limit(q)
+    simplify_limit_and_offset(q)
 }
 
 pub use clauses::{
@@ -318,6 +661,8 @@ pub use clauses::{
 };
 
 pub use types::{
+    ArithmeticOp,
+    ArithmeticOperand,
     Column,
     ColumnAlternation,
     ColumnConstraint,
@@ -328,7 +673,9 @@ pub use types::{
     DatomsColumn,
     DatomsTable,
     FulltextColumn,
+    LeftJoin,
     OrderBy,
+    OrderColumn,
     QualifiedAlias,
     QueryValue,
     SourceAlias,
@@ -344,24 +691,46 @@ impl FindQuery {
             default_source: SrcVar::DefaultSrc,
             with: BTreeSet::default(),
             in_vars: BTreeSet::default(),
+            in_bindings: vec![],
             in_sources: BTreeSet::default(),
             limit: Limit::None,
+            offset: Offset::None,
+            distinct: true,
             where_clauses: where_clauses,
             order: None,
+            rules: vec![],
         }
     }
 
     pub fn from_parsed_query(parsed: ParsedQuery) -> Result<FindQuery> {
-        let in_vars = {
+        // Scalar and tuple bindings bind each of their variables to a single value, so their
+        // variables are simply inputs. Collection and relation bindings are kept as they are.
+        let (in_vars, in_bindings) = {
             let mut set: BTreeSet<Variable> = BTreeSet::default();
+            let mut bound: BTreeSet<Variable> = BTreeSet::default();
+            let mut bindings: Vec<Binding> = vec![];
 
-            for var in parsed.in_vars.into_iter() {
-                if !set.insert(var.clone()) {
-                    bail!(AlgebrizerError::DuplicateVariableError(var.name(), ":in"));
+            for binding in parsed.in_bindings.into_iter() {
+                if binding.is_empty() {
+                    bail!(AlgebrizerError::InvalidBinding(PlainSymbol::plain(":in"), BindingError::NoBoundVariable));
+                }
+                for var in binding.variables().into_iter().filter_map(|v| v) {
+                    if !bound.insert(var.clone()) {
+                        bail!(AlgebrizerError::DuplicateVariableError(var.name(), ":in"));
+                    }
+                }
+                match binding {
+                    Binding::BindScalar(var) => {
+                        set.insert(var);
+                    },
+                    Binding::BindTuple(places) => {
+                        set.extend(places.into_iter().filter_map(|place| place.into_var()));
+                    },
+                    binding => bindings.push(binding),
                 }
             }
 
-            set
+            (set, bindings)
         };
 
         let with = {
@@ -383,15 +752,28 @@ impl FindQuery {
             }
         }
 
+        // Same for `:offset ?x`.
+        if let Offset::Variable(ref v) = parsed.offset {
+            if !in_vars.contains(v) {
+                bail!(AlgebrizerError::UnknownOffsetVar(v.name()));
+            }
+        }
+
+        validate_in_sources(&parsed.in_sources)?;
+
         Ok(FindQuery {
             find_spec: parsed.find_spec,
             default_source: parsed.default_source,
             with,
             in_vars,
+            in_bindings,
             in_sources: parsed.in_sources,
             limit: parsed.limit,
+            offset: parsed.offset,
+            distinct: parsed.distinct,
             where_clauses: parsed.where_clauses,
             order: parsed.order,
+            rules: parsed.rules,
         })
     }
 }
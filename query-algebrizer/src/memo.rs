@@ -0,0 +1,128 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::cell::RefCell;
+
+use std::collections::HashMap;
+
+use core_traits::{
+    Attribute,
+    Entid,
+    KnownEntid,
+    ValueType,
+};
+
+use mentat_core::{
+    HasSchema,
+    Schema,
+};
+
+use edn::query::{
+    Keyword,
+};
+
+/// What we need to know about an attribute to decide which table a pattern that uses it reads,
+/// and what type its values have.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct AttributeFacts {
+    pub(crate) value_type: ValueType,
+    pub(crate) fulltext: bool,
+}
+
+impl AttributeFacts {
+    pub(crate) fn of(attribute: &Attribute) -> AttributeFacts {
+        AttributeFacts {
+            value_type: attribute.value_type,
+            fulltext: attribute.fulltext,
+        }
+    }
+}
+
+/// The idents and attributes looked up while algebrizing a query, remembered so that the clauses
+/// nested inside it -- the arms of each `or`, the body of each `not`, each rule -- don't look
+/// them up again.
+///
+/// A memo is only good for the schema it was filled from, so each query gets a new one.  See
+/// `algebrize_with_inputs`.
+#[derive(Default)]
+pub(crate) struct AttributeMemo {
+    entids: RefCell<HashMap<Keyword, Option<KnownEntid>>>,
+    attributes: RefCell<HashMap<Entid, Option<AttributeFacts>>>,
+}
+
+impl AttributeMemo {
+    pub(crate) fn entid_for_ident(&self, schema: &Schema, ident: &Keyword) -> Option<KnownEntid> {
+        let remembered = self.entids.borrow().get(ident).cloned();
+        if let Some(entid) = remembered {
+            return entid;
+        }
+        let entid = schema.get_entid(ident);
+        self.entids.borrow_mut().insert(ident.clone(), entid);
+        entid
+    }
+
+    pub(crate) fn attribute_facts(&self, schema: &Schema, entid: Entid) -> Option<AttributeFacts> {
+        let remembered = self.attributes.borrow().get(&entid).cloned();
+        if let Some(facts) = remembered {
+            return facts;
+        }
+        let facts = schema.attribute_for_entid(entid).map(AttributeFacts::of);
+        self.attributes.borrow_mut().insert(entid, facts);
+        facts
+    }
+
+    /// How many idents and attributes the memo remembers.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entids.borrow().len() + self.attributes.borrow().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Known;
+
+    #[test]
+    fn test_memo_remembers_lookups() {
+        let bar = Keyword::namespaced("foo", "bar");
+        let baz = Keyword::namespaced("foo", "baz");
+        let mut schema = Schema::default();
+        schema.entid_map.insert(99, bar.clone());
+        schema.ident_map.insert(bar.clone(), 99);
+        schema.attribute_map.insert(99, Attribute {
+            value_type: ValueType::String,
+            fulltext: true,
+            ..Default::default()
+        });
+
+        let memo = AttributeMemo::default();
+        let known = Known::for_schema(&schema).with_memo(&memo);
+        let facts = AttributeFacts {
+            value_type: ValueType::String,
+            fulltext: true,
+        };
+
+        // Failed lookups are remembered, too.
+        for _ in 0..2 {
+            assert_eq!(known.entid_for_ident(&bar), Some(KnownEntid(99)));
+            assert_eq!(known.entid_for_ident(&baz), None);
+            assert_eq!(known.attribute_facts(99), Some(facts));
+            assert_eq!(known.attribute_facts(100), None);
+        }
+        assert_eq!(memo.len(), 4);
+
+        // Without a memo, we look in the schema each time.
+        let known = Known::for_schema(&schema);
+        assert_eq!(known.entid_for_ident(&bar), Some(KnownEntid(99)));
+        assert_eq!(known.attribute_facts(99), Some(facts));
+    }
+}
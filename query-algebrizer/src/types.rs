@@ -26,11 +26,16 @@ use mentat_core::{
 };
 
 use edn::query::{
+    Aggregate,
+    Binding,
     Direction,
     FindSpec,
     Keyword,
     Limit,
+    Offset,
     Order,
+    QueryFunction,
+    Rule,
     SrcVar,
     Variable,
     WhereClause,
@@ -56,11 +61,28 @@ pub enum ComputedTable {
         projection: BTreeSet<Variable>,
         type_extraction: BTreeSet<Variable>,
         arms: Vec<::clauses::ConjoiningClauses>,
+
+        /// True if no projected row can come from more than one arm, so the arms can be combined
+        /// with `UNION ALL` rather than `UNION`. Rows duplicated within an arm are then kept, but
+        /// the enclosing query is either `DISTINCT` or doesn't care about duplicates.
+        disjoint: bool,
     },
     NamedValues {
         names: Vec<Variable>,
         values: Vec<TypedValue>,
     },
+
+    /// A recursive rule, computed with `WITH RECURSIVE`. Each arm projects every one of `columns`
+    /// along with its type tag; the last arm is the only one that refers to the table itself, via
+    /// a `RuleReference`.
+    RecursiveRule {
+        name: String,
+        columns: Vec<Variable>,
+        arms: Vec<::clauses::ConjoiningClauses>,
+    },
+
+    /// A reference, from within its recursive arm, to the table computed by a `RecursiveRule`.
+    RuleReference(String),
 }
 
 impl DatomsTable {
@@ -88,6 +110,9 @@ pub enum DatomsColumn {
     Value,
     Tx,
     ValueTypeTag,
+    /// Whether the datom is in the AVET index.  We never project this column, but constraining it
+    /// lets SQLite use the partial `idx_datoms_avet` index.
+    IndexAVET,
 }
 
 /// One of the named columns of our fulltext values table.
@@ -95,6 +120,11 @@ pub enum DatomsColumn {
 pub enum FulltextColumn {
     Rowid,
     Text,
+    /// The diacritic-free form of `text`, populated for `:db/normalize` attributes.
+    Folded,
+    /// Not a real column: the relevance of the row to the search being matched against this
+    /// table, as computed from SQLite's `matchinfo`.
+    Score,
 }
 
 /// One of the named columns of our transactions table.
@@ -120,6 +150,14 @@ pub enum Column {
     Fulltext(FulltextColumn),
     Variable(VariableColumn),
     Transactions(TransactionsColumn),
+
+    /// Not a real column: another column of the same table, converted to a long or a double.
+    Cast(Box<Column>, ValueType),
+
+    /// Not a real column: the first of these columns that isn't `NULL`, or else the value.  These
+    /// are columns of tables joined with `LEFT JOIN`, which are `NULL` when nothing matched.  With
+    /// neither columns nor value, it's always `NULL`.
+    Coalesce(Vec<QualifiedAlias>, Option<TypedValue>),
 }
 
 impl From<DatomsColumn> for Column {
@@ -149,6 +187,7 @@ impl DatomsColumn {
             Value => "v",
             Tx => "tx",
             ValueTypeTag => "value_type_tag",
+            IndexAVET => "index_avet",
         }
     }
 
@@ -178,6 +217,14 @@ impl ColumnName for VariableColumn {
     }
 }
 
+impl ColumnName for Aggregate {
+    // This should agree with `SimpleAggregate::column_name` in the projector.
+    fn column_name(&self) -> String {
+        let args: Vec<String> = self.args.iter().map(|arg| arg.to_string()).collect();
+        format!("({} {})", self.func, args.join(" "))
+    }
+}
+
 impl Debug for VariableColumn {
     fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
         match self {
@@ -201,6 +248,8 @@ impl Debug for Column {
             &Column::Fulltext(ref c) => c.fmt(f),
             &Column::Variable(ref v) => v.fmt(f),
             &Column::Transactions(ref t) => t.fmt(f),
+            &Column::Cast(ref c, value_type) => write!(f, "CAST({:?} AS {})", c, value_type),
+            &Column::Coalesce(ref columns, ref value) => write!(f, "COALESCE({:?}, {:?})", columns, value),
         }
     }
 }
@@ -211,6 +260,8 @@ impl FulltextColumn {
         match *self {
             Rowid => "rowid",
             Text => "text",
+            Folded => "folded",
+            Score => "score",
         }
     }
 }
@@ -274,6 +325,14 @@ impl Debug for SourceAlias {
     }
 }
 
+/// A table joined to those in the `FROM` list with `LEFT JOIN table ON ...`, so that rows for which
+/// it has no match are kept, with `NULL` for each of its columns.
+#[derive(PartialEq, Eq, Debug)]
+pub struct LeftJoin {
+    pub table: SourceAlias,
+    pub on: ColumnIntersection,
+}
+
 /// A particular column of a particular aliased table. E.g., "datoms123", Attribute.
 #[derive(PartialEq, Eq, Clone)]
 pub struct QualifiedAlias(pub TableAlias, pub Column);
@@ -295,6 +354,8 @@ impl QualifiedAlias {
             Column::Fulltext(_) => None,
             Column::Variable(_) => None,
             Column::Transactions(ref c) => c.associated_type_tag_column().map(Column::Transactions),
+            Column::Cast(_, _) => None,
+            Column::Coalesce(_, _) => None,
         }.map(|d| QualifiedAlias(self.0.clone(), d))
     }
 }
@@ -310,6 +371,10 @@ pub enum QueryValue {
     // cannot be a boolean, so `datoms00.value_type_tag` must be in the set `#{0, 4, 5}`.
     // Note that `5 = 5.0` in SQLite, and we preserve that here.
     PrimitiveLong(i64),
+
+    // An `:in` variable whose value isn't known until the query is run.  It's bound as a named
+    // SQL parameter.
+    Parameter(Variable),
 }
 
 impl Debug for QueryValue {
@@ -328,23 +393,71 @@ impl Debug for QueryValue {
             &PrimitiveLong(value) => {
                 write!(f, "primitive({:?})", value)
             },
+            &Parameter(ref var) => {
+                write!(f, "parameter({:?})", var)
+            },
 
         }
     }
 }
 
-/// Represents an entry in the ORDER BY list: a variable or a variable's type tag.
-/// (We require order vars to be projected, so we can simply use a variable here.)
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct OrderBy(pub Direction, pub VariableColumn);
+/// The arithmetic that we can order by.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl ArithmeticOp {
+    pub fn for_function(function: &QueryFunction) -> Option<ArithmeticOp> {
+        match function.0.name() {
+            "+" => Some(ArithmeticOp::Add),
+            "-" => Some(ArithmeticOp::Subtract),
+            "*" => Some(ArithmeticOp::Multiply),
+            "/" => Some(ArithmeticOp::Divide),
+            _ => None,
+        }
+    }
 
-impl From<Order> for OrderBy {
-    fn from(item: Order) -> OrderBy {
-        let Order(direction, variable) = item;
-        OrderBy(direction, VariableColumn::Variable(variable))
+    pub fn to_sql(&self) -> &'static str {
+        match self {
+            &ArithmeticOp::Add => "+",
+            &ArithmeticOp::Subtract => "-",
+            &ArithmeticOp::Multiply => "*",
+            &ArithmeticOp::Divide => "/",
+        }
     }
 }
 
+/// An operand of ordering arithmetic: a numeric variable, or a number.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ArithmeticOperand {
+    Variable(Variable),
+    Value(TypedValue),
+}
+
+/// What an entry in the ORDER BY list orders by.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OrderColumn {
+    /// A variable or a variable's type tag.
+    /// (We require order vars to be projected, so we can simply use a variable here.)
+    Variable(VariableColumn),
+
+    /// An aggregate from the find spec.  The projector projects aggregates from the outermost
+    /// query, named as `ColumnName` names them, so this can only order that query.
+    Aggregate(Aggregate),
+
+    /// Arithmetic on projected numeric variables and on numbers, like `(* ?price ?quantity)`.
+    /// Long division truncates, as it does in SQL.
+    Arithmetic(ArithmeticOp, Vec<ArithmeticOperand>),
+}
+
+/// Represents an entry in the ORDER BY list.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderBy(pub Direction, pub OrderColumn);
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 /// Define the different inequality operators that we support.
 /// Note that we deliberately don't just use "<=" and friends as strings:
@@ -443,6 +556,25 @@ impl Debug for Inequality {
     }
 }
 
+/// The operators with which strings are matched against patterns.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StringMatch {
+    /// SQLite's `GLOB`, which is case-sensitive.  `*`, `?`, and `[` are special in patterns.
+    Glob,
+    /// `REGEXP`, which SQLite implements by calling the `regexp` function that Mentat registers
+    /// on each connection.
+    Regexp,
+}
+
+impl StringMatch {
+    pub fn to_sql_operator(self) -> &'static str {
+        match self {
+            StringMatch::Glob   => "GLOB",
+            StringMatch::Regexp => "REGEXP",
+        }
+    }
+}
+
 #[derive(PartialEq, Eq)]
 pub enum ColumnConstraint {
     Equals(QualifiedAlias, QueryValue),
@@ -457,7 +589,18 @@ pub enum ColumnConstraint {
         check_value: bool,
     },
     NotExists(ComputedTable),
+    Exists(ComputedTable),
     Matches(QualifiedAlias, QueryValue),
+    /// The datom in this `datoms` table is in the AVET index.
+    InAVETIndex(TableAlias),
+    /// The string `value` matches `pattern`.
+    StringMatches {
+        operator: StringMatch,
+        value: QueryValue,
+        pattern: QueryValue,
+    },
+    /// The column, typically of a table joined with `LEFT JOIN`, isn't `NULL`.
+    NotNull(QualifiedAlias),
 }
 
 impl ColumnConstraint {
@@ -600,6 +743,18 @@ impl Debug for ColumnConstraint {
             &NotExists(ref ct) => {
                 write!(f, "NOT EXISTS {:?}", ct)
             },
+            &Exists(ref ct) => {
+                write!(f, "EXISTS {:?}", ct)
+            },
+            &InAVETIndex(ref table) => {
+                write!(f, "{}.index_avet IS NOT 0", table)
+            },
+            &StringMatches { operator, ref value, ref pattern } => {
+                write!(f, "{:?} {} {:?}", value, operator.to_sql_operator(), pattern)
+            },
+            &NotNull(ref qa) => {
+                write!(f, "{:?} IS NOT NULL", qa)
+            },
         }
     }
 }
@@ -608,7 +763,10 @@ impl Debug for ColumnConstraint {
 pub enum EmptyBecause {
     CachedAttributeHasNoValues { entity: Entid, attr: Entid },
     CachedAttributeHasNoEntity { value: TypedValue, attr: Entid },
+    CachedDatomNotPresent { entity: Entid, attr: Entid, value: TypedValue },
     ConflictingBindings { var: Variable, existing: TypedValue, desired: TypedValue },
+    PrefixMismatch { var: Variable, value: String, prefix: String },
+    SubstringMismatch { var: Variable, value: String, substring: String },
 
     // A variable is known to be of two conflicting sets of types.
     TypeMismatch { var: Variable, existing: ValueTypeSet, desired: ValueTypeSet },
@@ -616,6 +774,7 @@ pub enum EmptyBecause {
     // The same, but for non-variables.
     KnownTypeMismatch { left: ValueTypeSet, right: ValueTypeSet },
     NoValidTypes(Variable),
+    NoInputValues(Variable),
     NonAttributeArgument,
     NonInstantArgument,
     NonNumericArgument,
@@ -640,10 +799,21 @@ impl Debug for EmptyBecause {
             &CachedAttributeHasNoValues { ref entity, ref attr } => {
                 write!(f, "({}, {}, ?v, _) not present in store", entity, attr)
             },
+            &CachedDatomNotPresent { ref entity, ref attr, ref value } => {
+                write!(f, "({}, {}, {:?}, _) not present in store", entity, attr, value)
+            },
             &ConflictingBindings { ref var, ref existing, ref desired } => {
                 write!(f, "Var {:?} can't be {:?} because it's already bound to {:?}",
                        var, desired, existing)
             },
+            &PrefixMismatch { ref var, ref value, ref prefix } => {
+                write!(f, "Var {:?} is bound to {:?}, which doesn't start with {:?}",
+                       var, value, prefix)
+            },
+            &SubstringMismatch { ref var, ref value, ref substring } => {
+                write!(f, "Var {:?} is bound to {:?}, which doesn't include {:?}",
+                       var, value, substring)
+            },
             &TypeMismatch { ref var, ref existing, ref desired } => {
                 write!(f, "Type mismatch: {:?} can't be {:?}, because it's already {:?}",
                        var, desired, existing)
@@ -655,6 +825,9 @@ impl Debug for EmptyBecause {
             &NoValidTypes(ref var) => {
                 write!(f, "Type mismatch: {:?} has no valid types", var)
             },
+            &NoInputValues(ref var) => {
+                write!(f, "No values provided for {:?}", var)
+            },
             &NonAttributeArgument => {
                 write!(f, "Non-attribute argument in attribute place")
             },
@@ -707,11 +880,21 @@ pub struct FindQuery {
     pub find_spec: FindSpec,
     pub default_source: SrcVar,
     pub with: BTreeSet<Variable>,
+
+    /// Variables bound to a single value by `:in`, either alone or in a tuple binding.
     pub in_vars: BTreeSet<Variable>,
+
+    /// Collection and relation bindings in `:in`, whose values become a computed table.
+    pub in_bindings: Vec<Binding>,
     pub in_sources: BTreeSet<SrcVar>,
     pub limit: Limit,
+    pub offset: Offset,
+
+    /// False if duplicate result rows should be kept.  See `ParsedQuery::distinct`.
+    pub distinct: bool,
     pub where_clauses: Vec<WhereClause>,
     pub order: Option<Vec<Order>>,
+    pub rules: Vec<Rule>,
 }
 
 // Intermediate data structures for resolving patterns.
@@ -754,4 +937,5 @@ pub struct EvolvedPattern {
     pub attribute: EvolvedNonValuePlace,
     pub value: EvolvedValuePlace,
     pub tx: EvolvedNonValuePlace,
+    pub added: EvolvedValuePlace,
 }
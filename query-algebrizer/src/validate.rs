@@ -14,6 +14,10 @@ use edn::query::{
     ContainsVariables,
     OrJoin,
     NotJoin,
+    Optional,
+    Pattern,
+    PatternValuePlace,
+    SrcVar,
     Variable,
     UnifyVars,
 };
@@ -23,6 +27,44 @@ use query_algebrizer_traits::errors::{
     Result,
 };
 
+/// A clause can name its source, as in `($src or …)`, but we can only query the default source.
+fn validate_source(source: &Option<SrcVar>) -> Result<()> {
+    match source {
+        &None | &Some(SrcVar::DefaultSrc) => Ok(()),
+        &Some(SrcVar::NamedSrc(ref name)) => bail!(AlgebrizerError::UnsupportedSource(format!("${}", name))),
+    }
+}
+
+/// The name of the source that holds every datom ever asserted or retracted: `$history`.
+pub const HISTORY_SOURCE: &'static str = "history";
+
+/// A pattern can query the default source, or the transaction log through `$history`.  Only the
+/// log records retractions, so only patterns against it can say whether a datom was added.
+pub(crate) fn validate_pattern_source(pattern: &Pattern) -> Result<()> {
+    match pattern.source {
+        None | Some(SrcVar::DefaultSrc) => {
+            if pattern.added != PatternValuePlace::Placeholder {
+                bail!(AlgebrizerError::AddedWithoutHistory);
+            }
+            Ok(())
+        },
+        Some(SrcVar::NamedSrc(ref name)) if name == HISTORY_SOURCE => Ok(()),
+        Some(SrcVar::NamedSrc(ref name)) => bail!(AlgebrizerError::UnsupportedSource(format!("${}", name))),
+    }
+}
+
+/// A query can name `$` and `$history` in `:in`, and no other sources.
+pub(crate) fn validate_in_sources(sources: &BTreeSet<SrcVar>) -> Result<()> {
+    for source in sources {
+        if let &SrcVar::NamedSrc(ref name) = source {
+            if name != HISTORY_SOURCE {
+                bail!(AlgebrizerError::UnsupportedSource(format!("${}", name)));
+            }
+        }
+    }
+    Ok(())
+}
+
 /// In an `or` expression, every mentioned var is considered 'free'.
 /// In an `or-join` expression, every var in the var list is 'required'.
 ///
@@ -45,6 +87,8 @@ use query_algebrizer_traits::errors::{
 /// "As with rules, src-vars are not currently supported within the clauses of or, but are supported
 /// on the or clause as a whole at top level."
 pub(crate) fn validate_or_join(or_join: &OrJoin) -> Result<()> {
+    validate_source(&or_join.source)?;
+
     // Grab our mentioned variables and ensure that the rules are followed.
     match or_join.unify_vars {
         UnifyVars::Implicit => {
@@ -76,6 +120,8 @@ pub(crate) fn validate_or_join(or_join: &OrJoin) -> Result<()> {
 }
 
 pub(crate) fn validate_not_join(not_join: &NotJoin) -> Result<()> {
+    validate_source(&not_join.source)?;
+
     // Grab our mentioned variables and ensure that the rules are followed.
     match not_join.unify_vars {
         UnifyVars::Implicit => {
@@ -92,6 +138,10 @@ pub(crate) fn validate_not_join(not_join: &NotJoin) -> Result<()> {
     }
 }
 
+pub(crate) fn validate_optional(optional: &Optional) -> Result<()> {
+    validate_source(&optional.source)
+}
+
 #[cfg(test)]
 mod tests {
     extern crate mentat_core;
@@ -161,6 +211,7 @@ mod tests {
                         attribute: ident("artist", "type"),
                         value: value_ident("artist.type", "group"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     })));
                 assert_eq!(
                     right,
@@ -172,6 +223,7 @@ mod tests {
                                 attribute: ident("artist", "type"),
                                 value: value_ident("artist.type", "person"),
                                 tx: PatternNonValuePlace::Placeholder,
+                                added: PatternValuePlace::Placeholder,
                             }),
                             WhereClause::Pattern(Pattern {
                                 source: None,
@@ -179,6 +231,7 @@ mod tests {
                                 attribute: ident("artist", "gender"),
                                 value: value_ident("artist.gender", "female"),
                                 tx: PatternNonValuePlace::Placeholder,
+                                added: PatternValuePlace::Placeholder,
                             }),
                         ]));
             },
@@ -223,6 +276,7 @@ mod tests {
                         attribute: ident("artist", "type"),
                         value: value_ident("artist.type", "group"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     })));
                 assert_eq!(
                     right,
@@ -234,6 +288,7 @@ mod tests {
                                 attribute: ident("artist", "type"),
                                 value: PatternValuePlace::Variable(Variable::from_valid_name("?type")),
                                 tx: PatternNonValuePlace::Placeholder,
+                                added: PatternValuePlace::Placeholder,
                             }),
                             WhereClause::Pattern(Pattern {
                                 source: None,
@@ -241,6 +296,7 @@ mod tests {
                                 attribute: ident("artist", "role"),
                                 value: value_ident("artist.role", "parody"),
                                 tx: PatternNonValuePlace::Placeholder,
+                                added: PatternValuePlace::Placeholder,
                             }),
                         ]));
             },
@@ -296,6 +352,7 @@ mod tests {
                         attribute: artist_country.clone(),
                         value: value_ident("country", "CA"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     }));
                 assert_eq!(
                     clause2,
@@ -305,6 +362,7 @@ mod tests {
                         attribute: artist_country,
                         value: value_ident("country", "GB"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     }));
             },
             _ => panic!(),
@@ -335,6 +393,7 @@ mod tests {
                         attribute: ident("release", "artists"),
                         value: artist,
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     }));
                 assert_eq!(
                     clause2,
@@ -344,6 +403,7 @@ mod tests {
                         attribute: ident("release", "year"),
                         value: PatternValuePlace::EntidOrInteger(1970),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     }));
             },
             _ => panic!(),
@@ -372,4 +432,22 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    /// Test that `or` and `not` can name the default source, but no other.
+    #[test]
+    fn test_or_and_not_sources() {
+        let query = r#"[:find ?artist
+                        :where [?artist :artist/name]
+                               ($ or [?artist :artist/type :artist.type/group]
+                                     [?artist :artist/type :artist.type/person])
+                               ($ not [?artist :artist/country :country/GB])
+                               ($other not [?artist :artist/country :country/US])]"#;
+        let parsed = parse_find_string(query).expect("expected successful parse");
+        let results: Vec<bool> = parsed.where_clauses.into_iter().filter_map(|clause| match clause {
+            WhereClause::OrJoin(or_join) => Some(validate_or_join(&or_join).is_ok()),
+            WhereClause::NotJoin(not_join) => Some(validate_not_join(&not_join).is_ok()),
+            _ => None,
+        }).collect();
+        assert_eq!(results, vec![true, true, false]);
+    }
 }
@@ -18,6 +18,7 @@ mod utils;
 
 use core_traits::{
     Attribute,
+    TypedValue,
     ValueType,
 };
 
@@ -27,15 +28,25 @@ use mentat_core::{
 
 use edn::query::{
     Keyword,
+    PlainSymbol,
+    Variable,
+};
+
+use query_algebrizer_traits::errors::{
+    AlgebrizerError,
 };
 
 use utils::{
     add_attribute,
     alg,
     associate_ident,
+    bails_with_inputs,
 };
 
-use mentat_query_algebrizer::Known;
+use mentat_query_algebrizer::{
+    Known,
+    QueryInputs,
+};
 
 fn prepopulated_schema() -> Schema {
     let mut schema = Schema::default();
@@ -90,3 +101,17 @@ fn test_apply_fulltext() {
                     [?score :foo/bar _]]"#;
     assert!(alg(known, query).is_known_empty());
 }
+
+#[test]
+fn test_fulltext_value_cannot_be_bound() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    // Matched text is only ever read back; an input can't stand in for it.
+    let query = r#"[:find ?entity
+                    :in ?val
+                    :where [(fulltext $ :foo/description "hello") [[?entity ?val]]]]"#;
+    let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?val"), TypedValue::typed_string("hello"))]);
+    assert_eq!(bails_with_inputs(known, query, inputs),
+               AlgebrizerError::UnbindableInput(PlainSymbol::plain("?val"), "text".to_string()));
+}
@@ -0,0 +1,154 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+extern crate edn;
+extern crate mentat_core;
+extern crate core_traits;
+extern crate mentat_query_algebrizer;
+extern crate query_algebrizer_traits;
+
+mod utils;
+
+use core_traits::{
+    Attribute,
+    TypedValue,
+    ValueType,
+    ValueTypeSet,
+};
+
+use mentat_core::{
+    Schema,
+};
+
+use edn::query::{
+    Direction,
+    Keyword,
+    PlainSymbol,
+    Variable,
+};
+
+use query_algebrizer_traits::errors::{
+    AlgebrizerError,
+};
+
+use mentat_query_algebrizer::{
+    ArithmeticOp,
+    ArithmeticOperand,
+    Known,
+    OrderBy,
+    OrderColumn,
+    QueryInputs,
+    VariableColumn,
+    algebrize_with_inputs,
+    parse_find_string,
+};
+
+use utils::{
+    add_attribute,
+    associate_ident,
+    bails,
+};
+
+fn prepopulated_schema() -> Schema {
+    let mut schema = Schema::default();
+    associate_ident(&mut schema, Keyword::namespaced("foo", "name"), 65);
+    associate_ident(&mut schema, Keyword::namespaced("foo", "price"), 66);
+    associate_ident(&mut schema, Keyword::namespaced("foo", "quantity"), 67);
+    add_attribute(&mut schema, 65, Attribute {
+        value_type: ValueType::String,
+        ..Default::default()
+    });
+    add_attribute(&mut schema, 66, Attribute {
+        value_type: ValueType::Double,
+        ..Default::default()
+    });
+    add_attribute(&mut schema, 67, Attribute {
+        value_type: ValueType::Long,
+        ..Default::default()
+    });
+    schema
+}
+
+fn order(known: Known, query: &str, inputs: QueryInputs) -> Option<Vec<OrderBy>> {
+    let parsed = parse_find_string(query).expect("query input to have parsed");
+    algebrize_with_inputs(known, parsed, 0, inputs).expect("algebrizing to have succeeded").order
+}
+
+#[test]
+fn test_order_by_arithmetic() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    let query = r#"[:find ?e
+                    :where [?e :foo/price ?p] [?e :foo/quantity ?q]
+                    :order (desc (* ?p ?q)) ?e]"#;
+    let p = Variable::from_valid_name("?p");
+    let q = Variable::from_valid_name("?q");
+    let e = Variable::from_valid_name("?e");
+    assert_eq!(order(known, query, QueryInputs::default()),
+               Some(vec![OrderBy(Direction::Descending,
+                                 OrderColumn::Arithmetic(ArithmeticOp::Multiply,
+                                                         vec![ArithmeticOperand::Variable(p.clone()),
+                                                              ArithmeticOperand::Variable(q.clone())])),
+                         OrderBy(Direction::Ascending, OrderColumn::Variable(VariableColumn::Variable(e)))]));
+
+    // Fixed values are substituted, and arithmetic on only fixed values is dropped.
+    let query = r#"[:find ?e
+                    :in ?discount
+                    :where [?e :foo/price ?p]
+                    :order (- ?p ?discount) (+ ?discount 1)]"#;
+    let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?discount"), TypedValue::Long(5))]);
+    assert_eq!(order(known, query, inputs),
+               Some(vec![OrderBy(Direction::Ascending,
+                                 OrderColumn::Arithmetic(ArithmeticOp::Subtract,
+                                                         vec![ArithmeticOperand::Variable(p),
+                                                              ArithmeticOperand::Value(TypedValue::Long(5))]))]));
+
+    // Only numbers can be ordered by arithmetic.
+    let query = r#"[:find ?e :where [?e :foo/name ?n] [?e :foo/quantity ?q] :order (+ ?q ?n)]"#;
+    assert_eq!(bails(known, query),
+               AlgebrizerError::InvalidArgumentType(PlainSymbol::plain("+"), ValueTypeSet::of_numeric_types(), 1));
+
+    let query = r#"[:find ?e :where [?e :foo/quantity ?q] :order (+ ?q "1")]"#;
+    assert_eq!(bails(known, query),
+               AlgebrizerError::InvalidArgument(PlainSymbol::plain("+"), "numeric", 1));
+
+    let query = r#"[:find ?e :where [?e :foo/quantity ?q] :order (* ?q)]"#;
+    assert_eq!(bails(known, query),
+               AlgebrizerError::InvalidNumberOfArguments(PlainSymbol::plain("*"), 2, 1));
+}
+
+#[test]
+fn test_order_by_aggregate() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+
+    let query = r#"[:find ?n (count ?e)
+                    :where [?e :foo/name ?n]
+                    :order (desc (count ?e))]"#;
+    match order(known, query, QueryInputs::default()) {
+        Some(ref order) if order.len() == 1 => {
+            match &order[0] {
+                &OrderBy(Direction::Descending, OrderColumn::Aggregate(ref aggregate)) => {
+                    assert_eq!(aggregate.func.0, PlainSymbol::plain("count"));
+                },
+                o => panic!("unexpected order {:?}", o),
+            }
+        },
+        o => panic!("unexpected order {:?}", o),
+    }
+
+    // An aggregate can only be ordered by if it's projected.
+    let query = r#"[:find ?n (count ?e)
+                    :where [?e :foo/name ?n]
+                    :order (max ?e)]"#;
+    assert_eq!(bails(known, query),
+               AlgebrizerError::InvalidOrderExpression("(max ?e)".to_string()));
+}
@@ -24,13 +24,22 @@ use utils::{
 
 use core_traits::{
     ValueType,
+    ValueTypeSet,
 };
 
 use mentat_core::{
     Schema,
 };
 
-use mentat_query_algebrizer::Known;
+use edn::query::{
+    Variable,
+};
+
+use mentat_query_algebrizer::{
+    Known,
+    algebrize,
+    parse_find_string,
+};
 
 fn prepopulated_schema() -> Schema {
     SchemaBuilder::new()
@@ -42,6 +51,8 @@ fn prepopulated_schema() -> Schema {
         .define_simple_attr("test", "uuid", ValueType::Uuid, false)
         .define_simple_attr("test", "instant", ValueType::Instant, false)
         .define_simple_attr("test", "ref", ValueType::Ref, false)
+        .define_simple_attr("test", "bigint", ValueType::BigInteger, false)
+        .define_simple_attr("test", "bytes", ValueType::Bytes, false)
         .schema
 }
 
@@ -77,3 +88,20 @@ fn test_unbound() {
     let known = Known::for_schema(&schema);
     bails(known, "[:find ?e :where [(type ?e :db.type/string)]]");
 }
+
+#[test]
+fn test_variable_types() {
+    let schema = prepopulated_schema();
+    let known = Known::for_schema(&schema);
+    let q = "[:find ?e ?v ?x :in ?x :where [?e :test/long ?l] [?e _ ?v] [(> ?v 5)]]";
+    let parsed = parse_find_string(q).expect("parsed");
+    let types = algebrize(known, parsed).expect("algebrized").variable_types();
+
+    assert_eq!(types.get(&Variable::from_valid_name("?e")), Some(&ValueTypeSet::of_one(ValueType::Ref)));
+    assert_eq!(types.get(&Variable::from_valid_name("?l")), Some(&ValueTypeSet::of_one(ValueType::Long)));
+    assert_eq!(types.get(&Variable::from_valid_name("?v")), Some(&ValueTypeSet::of_numeric_types()));
+
+    // We know nothing about `?x`.
+    assert_eq!(types.get(&Variable::from_valid_name("?x")), Some(&ValueTypeSet::any()));
+    assert_eq!(types.len(), 4);
+}
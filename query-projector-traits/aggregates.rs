@@ -122,8 +122,12 @@ impl SimpleAggregationOp {
                         // String: lexicographic order.
                         String => Ok(the_type),
 
+                        // Bytes: lexicographic order, byte by byte.
+                        Bytes => Ok(the_type),
+
                         // These types are unordered.
-                        Keyword | Ref | Uuid => {
+                        // Big integers are stored as text, which doesn't sort numerically.
+                        Keyword | Ref | Uuid | BigInteger => {
                             bail!(ProjectorError::CannotApplyAggregateOperationToTypes(*self, possibilities))
                         },
                     }
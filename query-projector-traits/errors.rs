@@ -10,6 +10,7 @@
 
 use std; // To refer to std::result::Result.
 
+#[cfg(feature = "rusqlite")]
 use rusqlite;
 
 use core_traits::{
@@ -72,6 +73,7 @@ pub enum ProjectorError {
     PullError(#[cause] PullError),
 }
 
+#[cfg(feature = "rusqlite")]
 impl From<rusqlite::Error> for ProjectorError {
     fn from(error: rusqlite::Error) -> ProjectorError {
         ProjectorError::RusqliteError(error.to_string())
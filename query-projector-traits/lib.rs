@@ -11,6 +11,7 @@
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+#[cfg(feature = "rusqlite")]
 extern crate rusqlite;
 
 #[macro_use]
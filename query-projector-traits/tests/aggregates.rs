@@ -113,3 +113,28 @@ fn test_the_without_max_or_min() {
         _ => panic!(),
     }
 }
+
+#[test]
+fn test_order_by_aggregated_variable() {
+    let schema = prepopulated_schema();
+
+    let query = r#"[:find ?n (max ?a)
+                    :where
+                    [?e :foo/name ?n]
+                    [?e :foo/age ?a]
+                    :order ?a]"#;
+
+    // Grouping by `?a` to order by it would make `(max ?a)` meaningless.
+    let parsed = parse_find_string(query).expect("query input to have parsed");
+    let algebrized = algebrize(Known::for_schema(&schema), parsed).expect("query algebrizes");
+
+    use query_projector_traits::errors::{
+        ProjectorError,
+    };
+    match query_projection(&schema, &algebrized).err().expect("expected failure") {
+        ProjectorError::InvalidProjection(s) => {
+                assert_eq!(s.as_str(), "Can't order by ?a: it is aggregated. Order by a grouped variable or by `(the ?a)`.");
+            },
+        _ => panic!(),
+    }
+}
@@ -8,9 +8,14 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+// Without the `store` feature, projectors only describe their columns, and much of what they'd use
+// to read rows goes unused.
+#![cfg_attr(not(feature = "store"), allow(dead_code, unused_imports))]
+
 extern crate failure;
 
 extern crate indexmap;
+#[cfg(feature = "store")]
 extern crate rusqlite;
 
 extern crate edn;
@@ -18,14 +23,17 @@ extern crate mentat_core;
 extern crate db_traits;
 #[macro_use]
 extern crate core_traits;
+#[cfg(feature = "store")]
 extern crate mentat_db;                 // For value conversion.
 extern crate mentat_query_algebrizer;
+#[cfg(feature = "store")]
 extern crate mentat_query_pull;
 extern crate query_pull_traits;
 extern crate query_projector_traits;
 extern crate mentat_query_sql;
 
 use std::collections::{
+    BTreeMap,
     BTreeSet,
 };
 
@@ -33,6 +41,7 @@ use std::iter;
 
 use std::rc::Rc;
 
+#[cfg(feature = "store")]
 use rusqlite::{
     Row,
     Rows,
@@ -41,6 +50,7 @@ use rusqlite::{
 use core_traits::{
     Binding,
     TypedValue,
+    ValueTypeSet,
 };
 
 use mentat_core::{
@@ -52,8 +62,14 @@ use mentat_core::util::{
     Either,
 };
 
+#[cfg(feature = "store")]
 use mentat_db::{
     TypedSQLValue,
+    read_typed_value,
+};
+
+use edn::{
+    Keyword,
 };
 
 use edn::query::{
@@ -86,6 +102,7 @@ mod relresult;
 
 use project::{
     ProjectedElements,
+    column_metadata,
     project_elements,
 };
 
@@ -100,12 +117,16 @@ pub use projectors::{
 
 use projectors::{
     CollProjector,
-    CollTwoStagePullProjector,
     RelProjector,
-    RelTwoStagePullProjector,
     ScalarProjector,
-    ScalarTwoStagePullProjector,
     TupleProjector,
+};
+
+#[cfg(feature = "store")]
+use projectors::{
+    CollTwoStagePullProjector,
+    RelTwoStagePullProjector,
+    ScalarTwoStagePullProjector,
     TupleTwoStagePullProjector,
 };
 
@@ -122,9 +143,30 @@ use query_projector_traits::errors::{
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct QueryOutput {
     pub spec: Rc<FindSpec>,
+
+    /// One entry for each element of the find spec, in order.
+    pub columns: Rc<Vec<ColumnMetadata>>,
+
     pub results: QueryResults,
 }
 
+/// What the query says about one column of its results.  This lets generic consumers, like table
+/// viewers and exporters, pick a formatter or an editor for a column without parsing the query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnMetadata {
+    /// The find spec element, as written: `?name`, `(max ?age)`, `(pull ?e [*])`.
+    pub name: String,
+
+    /// The types that values in this column can have, as far as the algebrizer could tell.  Pull
+    /// expressions yield maps rather than values, so their set is empty.
+    pub value_types: ValueTypeSet,
+
+    /// The attribute whose values appear in this column, if there is exactly one: the column is a
+    /// variable, or the `min` or `max` of one, and every pattern that binds it in value position
+    /// names the same attribute.
+    pub attribute: Option<Keyword>,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum QueryResults {
     Scalar(Option<Binding>),
@@ -161,7 +203,7 @@ impl QueryOutput {
         self.results.is_empty()
     }
 
-    pub fn empty(spec: &Rc<FindSpec>) -> QueryOutput {
+    pub fn empty(spec: &Rc<FindSpec>, columns: &Rc<Vec<ColumnMetadata>>) -> QueryOutput {
         use self::FindSpec::*;
         let results =
             match &**spec {
@@ -172,6 +214,7 @@ impl QueryOutput {
             };
         QueryOutput {
             spec: spec.clone(),
+            columns: columns.clone(),
             results: results,
         }
     }
@@ -306,6 +349,18 @@ impl QueryResults {
         }
     }
 
+    /// Turn these results into rows, each with one binding for each element of the find spec.
+    /// This is the shape that `Projector::project_row` yields.
+    pub fn into_rows(self) -> Vec<Vec<Binding>> {
+        use QueryResults::*;
+        match self {
+            Scalar(o) => o.into_iter().map(|b| vec![b]).collect(),
+            Tuple(o)  => o.into_iter().collect(),
+            Coll(v)   => v.into_iter().map(|b| vec![b]).collect(),
+            Rel(r)    => r.into_iter().collect(),
+        }
+    }
+
     pub fn into_scalar(self) -> Result<Option<Binding>> {
         match self {
             QueryResults::Scalar(o) => Ok(o),
@@ -341,6 +396,38 @@ impl QueryResults {
             QueryResults::Rel(r) => Ok(r),
         }
     }
+
+    /// These results as EDN, shaped as the find spec shapes them: a single value or `nil`, a
+    /// vector of values or `nil`, a vector of values, or a vector of rows.
+    #[cfg(feature = "store")]
+    pub fn into_edn(self) -> edn::Value {
+        match self {
+            QueryResults::Scalar(o) => o.as_ref().map_or(edn::Value::Nil, binding_to_edn),
+            QueryResults::Tuple(o) => o.map_or(edn::Value::Nil, |vs| edn::Value::Vector(vs.iter().map(binding_to_edn).collect())),
+            QueryResults::Coll(v) => edn::Value::Vector(v.iter().map(binding_to_edn).collect()),
+            QueryResults::Rel(r) => {
+                edn::Value::Vector(r.into_iter()
+                                    .map(|row| edn::Value::Vector(row.iter().map(binding_to_edn).collect()))
+                                    .collect())
+            },
+        }
+    }
+}
+
+/// Render a binding as EDN.  Pulled maps are keyed by attribute keyword, and a value that an
+/// `optional` clause left absent is `nil`.
+#[cfg(feature = "store")]
+pub fn binding_to_edn(binding: &Binding) -> edn::Value {
+    match binding {
+        &Binding::Scalar(ref v) => v.to_edn_value_pair().0,
+        &Binding::Vec(ref vs) => edn::Value::Vector(vs.iter().map(binding_to_edn).collect()),
+        &Binding::Map(ref m) => {
+            edn::Value::Map(m.iter()
+                             .map(|(k, v)| (edn::Value::Keyword((**k).clone()), binding_to_edn(v)))
+                             .collect::<BTreeMap<_, _>>())
+        },
+        &Binding::Null => edn::Value::Nil,
+    }
 }
 
 type Index = i32;            // See rusqlite::RowIndex.
@@ -349,6 +436,7 @@ enum TypedIndex {
     Unknown(Index, Index),
 }
 
+#[cfg(feature = "store")]
 impl TypedIndex {
     /// Look up this index and type(index) pair in the provided row.
     /// This function will panic if:
@@ -364,20 +452,31 @@ impl TypedIndex {
     ///
     /// This function will return a runtime error if the type tag is unknown, or the value is
     /// otherwise not convertible by the DB layer.
-    fn lookup<'a, 'stmt>(&self, row: &Row<'a, 'stmt>) -> Result<Binding> {
+    ///
+    /// Offloaded large values are read from the store.
+    ///
+    /// A `NULL` value, which only an `optional` clause that didn't match can produce, is
+    /// `Binding::Null`, whatever its type tag.
+    fn lookup<'a, 'stmt>(&self, sqlite: &rusqlite::Connection, row: &Row<'a, 'stmt>) -> Result<Binding> {
         use TypedIndex::*;
 
         match self {
             &Known(value_index, value_type) => {
                 let v: rusqlite::types::Value = row.get(value_index);
-                TypedValue::from_sql_value_pair(v, value_type)
+                if v == rusqlite::types::Value::Null {
+                    return Ok(Binding::Null);
+                }
+                read_typed_value(sqlite, v, value_type)
                     .map(|v| v.into())
                     .map_err(|e| e.into())
             },
             &Unknown(value_index, type_index) => {
                 let v: rusqlite::types::Value = row.get(value_index);
+                if v == rusqlite::types::Value::Null {
+                    return Ok(Binding::Null);
+                }
                 let value_type_tag: i32 = row.get(type_index);
-                TypedValue::from_sql_value_pair(v, value_type_tag)
+                read_typed_value(sqlite, v, value_type_tag)
                     .map(|v| v.into())
                     .map_err(|e| e.into())
             },
@@ -416,8 +515,14 @@ pub struct CombinedProjection {
 }
 
 impl CombinedProjection {
-    fn flip_distinct_for_limit(mut self, limit: &Limit) -> Self {
-        if *limit == Limit::Fixed(1) {
+    fn flip_distinct(mut self, query: &AlgebraicQuery) -> Self {
+        if !query.distinct {
+            // The query asked for every row, duplicates and all.
+            self.distinct = false;
+        } else if query.offset.is_some() {
+            // Duplicate rows would count towards the offset, skipping too few distinct results.
+            self.distinct = true;
+        } else if query.limit == Limit::Fixed(1) {
             self.distinct = false;
         }
         self
@@ -437,6 +542,14 @@ impl IsPull for Element {
     }
 }
 
+/// Describe each column of the results of an algebrized query.
+pub fn query_columns(schema: &Schema, query: &AlgebraicQuery) -> Vec<ColumnMetadata> {
+    query.find_spec
+         .columns()
+         .map(|e| column_metadata(schema, &query.cc, e))
+         .collect()
+}
+
 /// Compute a suitable SQL projection for an algebrized query.
 /// This takes into account a number of things:
 /// - The variable list in the find spec.
@@ -475,51 +588,61 @@ pub fn query_projection(schema: &Schema, query: &AlgebraicQuery) -> Result<Eithe
         let results = QueryOutput::from_constants(&spec, query.cc.value_bindings(&variables));
         let f = Box::new(move || { results.clone() });
 
-        Ok(Either::Left(ConstantProjector::new(spec, f)))
+        let columns = Rc::new(query_columns(schema, query));
+        Ok(Either::Left(ConstantProjector::new(spec, columns, f)))
     } else if query.is_known_empty() {
         // Do a few gyrations to produce empty results of the right kind for the query.
         let empty = QueryOutput::empty_factory(&spec);
-        Ok(Either::Left(ConstantProjector::new(spec, empty)))
+        let columns = Rc::new(query_columns(schema, query));
+        Ok(Either::Left(ConstantProjector::new(spec, columns, empty)))
     } else {
+        // Without a store there's nothing to pull from, so a pull expression projects the entity
+        // it would have pulled.  The SQL is the same.
         match *query.find_spec {
             FindColl(ref element) => {
-                let elements = project_elements(1, iter::once(element), query)?;
-                if element.is_pull() {
-                    CollTwoStagePullProjector::combine(spec, elements)
-                } else {
-                    CollProjector::combine(spec, elements)
-                }.map(|p| p.flip_distinct_for_limit(&query.limit))
+                let elements = project_elements(schema, 1, iter::once(element), query)?;
+                #[cfg(feature = "store")]
+                {
+                    if element.is_pull() {
+                        return CollTwoStagePullProjector::combine(spec, elements).map(|p| Either::Right(p.flip_distinct(query)));
+                    }
+                }
+                CollProjector::combine(spec, elements).map(|p| p.flip_distinct(query))
             },
 
             FindScalar(ref element) => {
-                let elements = project_elements(1, iter::once(element), query)?;
-                if element.is_pull() {
-                    ScalarTwoStagePullProjector::combine(schema, spec, elements)
-                } else {
-                    ScalarProjector::combine(spec, elements)
+                let elements = project_elements(schema, 1, iter::once(element), query)?;
+                #[cfg(feature = "store")]
+                {
+                    if element.is_pull() {
+                        return ScalarTwoStagePullProjector::combine(schema, spec, elements).map(|p| Either::Right(p.flip_distinct(query)));
+                    }
                 }
+                ScalarProjector::combine(spec, elements).map(|p| p.flip_distinct(query))
             },
 
             FindRel(ref elements) => {
-                let is_pull = elements.iter().any(|e| e.is_pull());
                 let column_count = query.find_spec.expected_column_count();
-                let elements = project_elements(column_count, elements, query)?;
-                if is_pull {
-                    RelTwoStagePullProjector::combine(spec, column_count, elements)
-                } else {
-                    RelProjector::combine(spec, column_count, elements)
-                }.map(|p| p.flip_distinct_for_limit(&query.limit))
+                let projected = project_elements(schema, column_count, elements, query)?;
+                #[cfg(feature = "store")]
+                {
+                    if elements.iter().any(|e| e.is_pull()) {
+                        return RelTwoStagePullProjector::combine(spec, column_count, projected).map(|p| Either::Right(p.flip_distinct(query)));
+                    }
+                }
+                RelProjector::combine(spec, column_count, projected).map(|p| p.flip_distinct(query))
             },
 
             FindTuple(ref elements) => {
-                let is_pull = elements.iter().any(|e| e.is_pull());
                 let column_count = query.find_spec.expected_column_count();
-                let elements = project_elements(column_count, elements, query)?;
-                if is_pull {
-                    TupleTwoStagePullProjector::combine(spec, column_count, elements)
-                } else {
-                    TupleProjector::combine(spec, column_count, elements)
+                let projected = project_elements(schema, column_count, elements, query)?;
+                #[cfg(feature = "store")]
+                {
+                    if elements.iter().any(|e| e.is_pull()) {
+                        return TupleTwoStagePullProjector::combine(spec, column_count, projected).map(|p| Either::Right(p.flip_distinct(query)));
+                    }
                 }
+                TupleProjector::combine(spec, column_count, projected).map(|p| p.flip_distinct(query))
             },
         }.map(Either::Right)
     }
@@ -530,6 +653,7 @@ fn test_into_tuple() {
     let query_output = QueryOutput {
         spec: Rc::new(FindSpec::FindTuple(vec![Element::Variable(Variable::from_valid_name("?x")),
                                                Element::Variable(Variable::from_valid_name("?y"))])),
+        columns: Rc::new(vec![]),
         results: QueryResults::Tuple(Some(vec![Binding::Scalar(TypedValue::Long(0)),
                                                Binding::Scalar(TypedValue::Long(2))])),
     };
@@ -549,6 +673,7 @@ fn test_into_tuple() {
     let query_output = QueryOutput {
         spec: Rc::new(FindSpec::FindTuple(vec![Element::Variable(Variable::from_valid_name("?x")),
                                                Element::Variable(Variable::from_valid_name("?y"))])),
+        columns: Rc::new(vec![]),
         results: QueryResults::Tuple(None),
     };
 
@@ -12,17 +12,22 @@ use std::collections::{
     BTreeSet,
 };
 
+use std::rc::Rc;
+
 use indexmap::{
     IndexSet,
 };
 
 use core_traits::{
+    ValueType,
     ValueTypeSet,
 };
 
 use mentat_core::{
+    HasSchema,
     SQLValueType,
     SQLValueTypeSet,
+    Schema,
 };
 
 use mentat_core::util::{
@@ -37,8 +42,12 @@ use edn::query::{
 
 use mentat_query_algebrizer::{
     AlgebraicQuery,
+    Column,
     ColumnName,
     ConjoiningClauses,
+    DatomsColumn,
+    OrderBy,
+    OrderColumn,
     QualifiedAlias,
     VariableColumn,
 };
@@ -54,6 +63,7 @@ use mentat_query_sql::{
 
 use query_projector_traits::aggregates::{
     SimpleAggregation,
+    SimpleAggregationOp,
     projected_column_for_simple_aggregate,
 };
 
@@ -73,6 +83,7 @@ use pull::{
 };
 
 use super::{
+    ColumnMetadata,
     CombinedProjection,
     TypedIndex,
 };
@@ -84,6 +95,8 @@ use super::{
 ///   in order to apply DISTINCT to values prior to aggregation.
 /// - A collection of templates for the projector to use to extract values.
 /// - A list of columns to use for grouping. Grouping is a property of the projection!
+///
+/// It also describes each output column for the projector to attach to its results.
 pub(crate) struct ProjectedElements {
     pub sql_projection: Projection,
     pub pre_aggregate_projection: Option<Projection>,
@@ -94,6 +107,7 @@ pub(crate) struct ProjectedElements {
     // it would be more efficient to combine them.
     pub pulls: Vec<PullTemplate>,
     pub group_by: Vec<GroupBy>,
+    pub columns: Rc<Vec<ColumnMetadata>>,
 }
 
 impl ProjectedElements {
@@ -151,6 +165,32 @@ fn candidate_column(cc: &ConjoiningClauses, var: &Variable) -> Result<(ColumnOrE
         })
 }
 
+/// Offloaded strings are stored by id, and integers sort before any text, so a variable that the
+/// query is ordered by and that might be an offloaded string is projected with offloaded values
+/// replaced by the strings they stand for.  Other values are read from the store as they're
+/// projected.
+fn column_for_ordering(query: &AlgebraicQuery, var: &Variable, column: ColumnOrExpression) -> ColumnOrExpression {
+    let ordered = query.order.as_ref().map_or(false, |order| {
+        order.iter().any(|&OrderBy(_, ref c)| match c {
+            &OrderColumn::Variable(VariableColumn::Variable(ref v)) => v == var,
+            _ => false,
+        })
+    });
+    match column {
+        ColumnOrExpression::Column(qa) => {
+            if ordered &&
+               qa.1 == Column::Fixed(DatomsColumn::Value) &&
+               query.large_value_aliases.contains(&qa.0) &&
+               query.cc.known_type_set(var).contains(ValueType::String) {
+                ColumnOrExpression::ResolvedValue(qa)
+            } else {
+                ColumnOrExpression::Column(qa)
+            }
+        },
+        column => column,
+    }
+}
+
 /// Return the projected column -- that is, a value or SQL column and an associated name -- for a
 /// given variable. Also return the type.
 /// Callers are expected to determine whether to project a type tag as an additional SQL column.
@@ -179,7 +219,42 @@ pub fn projected_column_for_var(var: &Variable, cc: &ConjoiningClauses) -> Resul
 /// ```
 ///
 /// should fail to parse. See #358.
+/// Describe the output column for `element`.
+pub(crate) fn column_metadata(schema: &Schema, cc: &ConjoiningClauses, element: &Element) -> ColumnMetadata {
+    let (value_types, source) = match element {
+        &Element::Variable(ref var) |
+        &Element::Corresponding(ref var) => (cc.known_type_set(var), Some(var.clone())),
+
+        // Pull expressions produce maps, not typed values.
+        &Element::Pull(_) => (ValueTypeSet::none(), None),
+
+        &Element::Aggregate(ref a) => match a.to_simple() {
+            Some(simple) => {
+                let value_types = simple.op
+                                        .is_applicable_to_types(cc.known_type_set(&simple.var))
+                                        .map(ValueTypeSet::of_one)
+                                        .unwrap_or(ValueTypeSet::none());
+
+                // Only `min` and `max` yield values of the aggregated attribute.
+                match simple.op {
+                    SimpleAggregationOp::Max | SimpleAggregationOp::Min => (value_types, Some(simple.var)),
+                    SimpleAggregationOp::Avg | SimpleAggregationOp::Count | SimpleAggregationOp::Sum => (value_types, None),
+                }
+            },
+            None => (ValueTypeSet::none(), None),
+        },
+    };
+
+    ColumnMetadata {
+        name: element.to_string(),
+        value_types: value_types,
+        attribute: source.and_then(|var| cc.attribute_for_var(&var))
+                         .and_then(|a| schema.get_ident(a).cloned()),
+    }
+}
+
 pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
+    schema: &Schema,
     count: usize,
     elements: I,
     query: &AlgebraicQuery) -> Result<ProjectedElements> {
@@ -196,6 +271,7 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
     let mut min_max_count: usize = 0;
     let mut templates = vec![];
     let mut pulls: Vec<PullTemplate> = vec![];
+    let mut columns = Vec::with_capacity(count);
 
     let mut aggregates = false;
 
@@ -206,10 +282,15 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
     let mut outer_variables = IndexSet::new();
     let mut corresponded_variables = IndexSet::new();
 
+    // Any variable that appears inside an aggregate expression.
+    let mut aggregated_variables = BTreeSet::new();
+
     // Any variable that we are projecting from the inner query.
     let mut inner_variables = BTreeSet::new();
 
     for e in elements {
+        columns.push(column_metadata(schema, &query.cc, e));
+
         // Check for and reject duplicates.
         match e {
             &Element::Variable(ref var) => {
@@ -263,9 +344,9 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
             &Element::Corresponding(ref var) => {
                 inner_variables.insert(var.clone());
 
-                let (projected_column, type_set) = projected_column_for_var(&var, &query.cc)?;
-                outer_projection.push(Either::Left(projected_column.1.clone()));
-                inner_projection.push(projected_column);
+                let (ProjectedColumn(column, name), type_set) = projected_column_for_var(&var, &query.cc)?;
+                outer_projection.push(Either::Left(name.clone()));
+                inner_projection.push(ProjectedColumn(column_for_ordering(query, var, column), name));
 
                 if let Some(tag) = type_set.unique_type_tag() {
                     templates.push(TypedIndex::Known(i, tag));
@@ -312,6 +393,7 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
             &Element::Aggregate(ref a) => {
                 if let Some(simple) = a.to_simple() {
                     aggregates = true;
+                    aggregated_variables.insert(simple.var.clone());
 
                     use query_projector_traits::aggregates::SimpleAggregationOp::*;
                     match simple.op {
@@ -380,6 +462,17 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
             continue;
         }
 
+        // `(the ?x)` is already projected, and grouping by it would give each of its values a row
+        // of its own.
+        if corresponded_variables.contains(var) {
+            continue;
+        }
+
+        // Likewise, grouping by an aggregated variable would aggregate each of its values alone.
+        if aggregates && aggregated_variables.contains(var) {
+            bail!(ProjectorError::InvalidProjection(format!("Can't order by {}: it is aggregated. Order by a grouped variable or by `(the {})`.", var, var)));
+        }
+
         // If it's a fixed value, we need do nothing further.
         if query.cc.is_value_bound(&var) {
             continue;
@@ -388,7 +481,7 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
         let already_inner = inner_variables.contains(&var);
         let (column, name) = candidate_column(&query.cc, &var)?;
         if !already_inner {
-            inner_projection.push(ProjectedColumn(column, name.clone()));
+            inner_projection.push(ProjectedColumn(column_for_ordering(query, &var, column), name.clone()));
             inner_variables.insert(var.clone());
         }
 
@@ -417,6 +510,7 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
                       templates,
                       pulls,
                       group_by: vec![],
+                      columns: Rc::new(columns),
                   });
     }
 
@@ -521,5 +615,6 @@ pub(crate) fn project_elements<'a, I: IntoIterator<Item = &'a Element>>(
         templates,
         pulls,
         group_by,
+        columns: Rc::new(columns),
     })
 }
@@ -11,16 +11,24 @@
 use std::rc::Rc;
 
 use ::{
+    Binding,
+    ColumnMetadata,
     Element,
     FindSpec,
     QueryOutput,
     QueryResults,
-    Rows,
     Schema,
+};
+
+#[cfg(feature = "store")]
+use ::{
+    Row,
+    Rows,
     rusqlite,
 };
 
 use query_projector_traits::errors::{
+    ProjectorError,
     Result,
 };
 
@@ -32,13 +40,15 @@ use super::{
 /// Takes a boxed function that should return an empty result set of the desired type.
 pub struct ConstantProjector {
     spec: Rc<FindSpec>,
+    columns: Rc<Vec<ColumnMetadata>>,
     results_factory: Box<Fn() -> QueryResults>,
 }
 
 impl ConstantProjector {
-    pub fn new(spec: Rc<FindSpec>, results_factory: Box<Fn() -> QueryResults>) -> ConstantProjector {
+    pub fn new(spec: Rc<FindSpec>, columns: Rc<Vec<ColumnMetadata>>, results_factory: Box<Fn() -> QueryResults>) -> ConstantProjector {
         ConstantProjector {
             spec: spec,
+            columns: columns,
             results_factory: results_factory,
         }
     }
@@ -48,6 +58,7 @@ impl ConstantProjector {
         let spec = self.spec.clone();
         Ok(QueryOutput {
             spec: spec,
+            columns: self.columns.clone(),
             results: results,
         })
     }
@@ -56,10 +67,17 @@ impl ConstantProjector {
 // TODO: a ConstantProjector with non-constant pull expressions.
 
 impl Projector for ConstantProjector {
+    #[cfg(feature = "store")]
     fn project<'stmt, 's>(&self, _schema: &Schema, _sqlite: &'s rusqlite::Connection, _rows: Rows<'stmt>) -> Result<QueryOutput> {
         self.project_without_rows()
     }
 
+    #[cfg(feature = "store")]
+    fn project_row<'a, 'stmt>(&self, _schema: &Schema, _sqlite: &rusqlite::Connection, _row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+        // Constant projections don't run SQL, so there are never rows to project.
+        bail!(ProjectorError::InvalidProjection("constant projections have no rows".to_string()))
+    }
+
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
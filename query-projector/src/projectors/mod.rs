@@ -10,23 +10,40 @@
 
 use super::{
     Element,
+};
+
+#[cfg(feature = "store")]
+use super::{
+    Binding,
     Schema,
     QueryOutput,
+    Row,
     Rows,
     rusqlite,
 };
 
+#[cfg(feature = "store")]
 use query_projector_traits::errors::{
     Result,
 };
 
+/// Without the `store` feature, a projector only describes its columns: there are no rows to
+/// project.
 pub trait Projector {
+    #[cfg(feature = "store")]
     fn project<'stmt, 's>(&self, schema: &Schema, sqlite: &'s rusqlite::Connection, rows: Rows<'stmt>) -> Result<QueryOutput>;
+
+    /// Project a single row, yielding one binding for each element of the find spec.  This lets
+    /// consumers stream results rather than materializing them; pull expressions are run for each
+    /// row in turn.
+    #[cfg(feature = "store")]
+    fn project_row<'a, 'stmt>(&self, schema: &Schema, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>>;
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's>;
 }
 
 mod constant;
 mod simple;
+#[cfg(feature = "store")]
 mod pull_two_stage;
 
 pub use self::constant::ConstantProjector;
@@ -38,6 +55,7 @@ pub(crate) use self::simple::{
     TupleProjector,
 };
 
+#[cfg(feature = "store")]
 pub(crate) use self::pull_two_stage::{
     CollTwoStagePullProjector,
     RelTwoStagePullProjector,
@@ -24,6 +24,7 @@ use core_traits::{
 
 use ::{
     Binding,
+    ColumnMetadata,
     CombinedProjection,
     Element,
     FindSpec,
@@ -54,6 +55,7 @@ use super::{
 
 pub(crate) struct ScalarTwoStagePullProjector {
     spec: Rc<FindSpec>,
+    columns: Rc<Vec<ColumnMetadata>>,
     puller: Puller,
 }
 
@@ -61,16 +63,28 @@ pub(crate) struct ScalarTwoStagePullProjector {
 // The only output is the pull expression, and so we can directly supply the projected entity
 // to the pull SQL.
 impl ScalarTwoStagePullProjector {
-    fn with_template(schema: &Schema, spec: Rc<FindSpec>, pull: PullOperation) -> Result<ScalarTwoStagePullProjector> {
+    fn with_template(schema: &Schema, spec: Rc<FindSpec>, columns: Rc<Vec<ColumnMetadata>>, pull: PullOperation) -> Result<ScalarTwoStagePullProjector> {
         Ok(ScalarTwoStagePullProjector {
             spec: spec,
+            columns: columns,
             puller: Puller::prepare(schema, pull.0.clone())?,
         })
     }
 
+    /// The entity is always column 0 and a ref, but an `optional` clause might have left it absent.
+    fn pull_entity(&self, schema: &Schema, sqlite: &rusqlite::Connection, entity: Option<Entid>) -> Result<Binding> {
+        match entity {
+            None => Ok(Binding::Null),
+            Some(entity) => {
+                let bindings = self.puller.pull(schema, sqlite, once(entity))?;
+                Ok(Binding::Map(bindings.get(&entity).cloned().unwrap_or_else(Default::default)))
+            },
+        }
+    }
+
     pub(crate) fn combine(schema: &Schema, spec: Rc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let pull = elements.pulls.pop().expect("Expected a single pull");
-        let projector = Box::new(ScalarTwoStagePullProjector::with_template(schema, spec, pull.op)?);
+        let projector = Box::new(ScalarTwoStagePullProjector::with_template(schema, spec, elements.columns.clone(), pull.op)?);
         let distinct = false;
         elements.combine(projector, distinct)
     }
@@ -82,20 +96,22 @@ impl Projector for ScalarTwoStagePullProjector {
         let results =
             if let Some(r) = rows.next() {
                 let row = r?;
-                let entity: Entid = row.get(0);          // This will always be 0 and a ref.
-                let bindings = self.puller.pull(schema, sqlite, once(entity))?;
-                let m = Binding::Map(bindings.get(&entity).cloned().unwrap_or_else(Default::default));
-                QueryResults::Scalar(Some(m))
+                QueryResults::Scalar(Some(self.pull_entity(schema, sqlite, row.get(0))?))
             } else {
                 QueryResults::Scalar(None)
             };
 
         Ok(QueryOutput {
             spec: self.spec.clone(),
+            columns: self.columns.clone(),
             results: results,
         })
     }
 
+    fn project_row<'a, 'stmt>(&self, schema: &Schema, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+        Ok(vec![self.pull_entity(schema, sqlite, row.get(0))?])
+    }
+
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
@@ -104,15 +120,17 @@ impl Projector for ScalarTwoStagePullProjector {
 /// A tuple projector produces a single vector. It's the single-result version of rel.
 pub(crate) struct TupleTwoStagePullProjector {
     spec: Rc<FindSpec>,
+    columns: Rc<Vec<ColumnMetadata>>,
     len: usize,
     templates: Vec<TypedIndex>,
     pulls: Vec<PullTemplate>,
 }
 
 impl TupleTwoStagePullProjector {
-    fn with_templates(spec: Rc<FindSpec>, len: usize, templates: Vec<TypedIndex>, pulls: Vec<PullTemplate>) -> TupleTwoStagePullProjector {
+    fn with_templates(spec: Rc<FindSpec>, columns: Rc<Vec<ColumnMetadata>>, len: usize, templates: Vec<TypedIndex>, pulls: Vec<PullTemplate>) -> TupleTwoStagePullProjector {
         TupleTwoStagePullProjector {
             spec: spec,
+            columns: columns,
             len: len,
             templates: templates,
             pulls: pulls,
@@ -120,19 +138,19 @@ impl TupleTwoStagePullProjector {
     }
 
     // This is exactly the same as for rel.
-    fn collect_bindings<'a, 'stmt>(&self, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+    fn collect_bindings<'a, 'stmt>(&self, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
         // There will be at least as many SQL columns as Datalog columns.
         // gte 'cos we might be querying extra columns for ordering.
         // The templates will take care of ignoring columns.
         assert!(row.column_count() >= self.len as i32);
         self.templates
             .iter()
-            .map(|ti| ti.lookup(&row))
+            .map(|ti| ti.lookup(sqlite, &row))
             .collect::<Result<Vec<Binding>>>()
     }
 
     pub(crate) fn combine(spec: Rc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
-        let projector = Box::new(TupleTwoStagePullProjector::with_templates(spec, column_count, elements.take_templates(), elements.take_pulls()));
+        let projector = Box::new(TupleTwoStagePullProjector::with_templates(spec, elements.columns.clone(), column_count, elements.take_templates(), elements.take_pulls()));
         let distinct = false;
         elements.combine(projector, distinct)
     }
@@ -156,7 +174,7 @@ impl Projector for TupleTwoStagePullProjector {
                     p.collect_entity(&row);
                 }
 
-                let mut bindings = self.collect_bindings(row)?;
+                let mut bindings = self.collect_bindings(sqlite, row)?;
 
                 // Run the pull expressions for the collected IDs.
                 for mut p in pull_consumers.iter_mut() {
@@ -174,10 +192,32 @@ impl Projector for TupleTwoStagePullProjector {
             };
         Ok(QueryOutput {
             spec: self.spec.clone(),
+            columns: self.columns.clone(),
             results: results,
         })
     }
 
+    fn project_row<'a, 'stmt>(&self, schema: &Schema, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+        let pull_consumers: Result<Vec<PullConsumer>> = self.pulls
+                                                            .iter()
+                                                            .map(|op| PullConsumer::for_template(schema, op))
+                                                            .collect();
+        let mut pull_consumers = pull_consumers?;
+        for mut p in pull_consumers.iter_mut() {
+            p.collect_entity(&row);
+        }
+
+        let mut bindings = self.collect_bindings(sqlite, row)?;
+
+        for mut p in pull_consumers.iter_mut() {
+            p.pull(sqlite)?;
+        }
+        for p in pull_consumers.into_iter() {
+            p.expand(&mut bindings);
+        }
+        Ok(bindings)
+    }
+
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
@@ -189,22 +229,24 @@ impl Projector for TupleTwoStagePullProjector {
 /// the `Row`: one for the value and optionally one for the type tag.
 pub(crate) struct RelTwoStagePullProjector {
     spec: Rc<FindSpec>,
+    columns: Rc<Vec<ColumnMetadata>>,
     len: usize,
     templates: Vec<TypedIndex>,
     pulls: Vec<PullTemplate>,
 }
 
 impl RelTwoStagePullProjector {
-    fn with_templates(spec: Rc<FindSpec>, len: usize, templates: Vec<TypedIndex>, pulls: Vec<PullTemplate>) -> RelTwoStagePullProjector {
+    fn with_templates(spec: Rc<FindSpec>, columns: Rc<Vec<ColumnMetadata>>, len: usize, templates: Vec<TypedIndex>, pulls: Vec<PullTemplate>) -> RelTwoStagePullProjector {
         RelTwoStagePullProjector {
             spec: spec,
+            columns: columns,
             len: len,
             templates: templates,
             pulls: pulls,
         }
     }
 
-    fn collect_bindings_into<'a, 'stmt, 'out>(&self, row: Row<'a, 'stmt>, out: &mut Vec<Binding>) -> Result<()> {
+    fn collect_bindings_into<'a, 'stmt, 'out>(&self, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>, out: &mut Vec<Binding>) -> Result<()> {
         // There will be at least as many SQL columns as Datalog columns.
         // gte 'cos we might be querying extra columns for ordering.
         // The templates will take care of ignoring columns.
@@ -212,7 +254,7 @@ impl RelTwoStagePullProjector {
         let mut count = 0;
         for binding in self.templates
                            .iter()
-                           .map(|ti| ti.lookup(&row)) {
+                           .map(|ti| ti.lookup(sqlite, &row)) {
             out.push(binding?);
             count += 1;
         }
@@ -221,7 +263,7 @@ impl RelTwoStagePullProjector {
     }
 
     pub(crate) fn combine(spec: Rc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
-        let projector = Box::new(RelTwoStagePullProjector::with_templates(spec, column_count, elements.take_templates(), elements.take_pulls()));
+        let projector = Box::new(RelTwoStagePullProjector::with_templates(spec, elements.columns.clone(), column_count, elements.take_templates(), elements.take_pulls()));
 
         // If every column yields only one value, or if this is an aggregate query
         // (because by definition every column in an aggregate query is either
@@ -254,7 +296,7 @@ impl Projector for RelTwoStagePullProjector {
             for mut p in pull_consumers.iter_mut() {
                 p.collect_entity(&row);
             }
-            self.collect_bindings_into(row, &mut values)?;
+            self.collect_bindings_into(sqlite, row, &mut values)?;
         }
 
         // Run the pull expressions for the collected IDs.
@@ -271,10 +313,33 @@ impl Projector for RelTwoStagePullProjector {
 
         Ok(QueryOutput {
             spec: self.spec.clone(),
+            columns: self.columns.clone(),
             results: QueryResults::Rel(RelResult { width, values }),
         })
     }
 
+    fn project_row<'a, 'stmt>(&self, schema: &Schema, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+        let pull_consumers: Result<Vec<PullConsumer>> = self.pulls
+                                                            .iter()
+                                                            .map(|op| PullConsumer::for_template(schema, op))
+                                                            .collect();
+        let mut pull_consumers = pull_consumers?;
+        for mut p in pull_consumers.iter_mut() {
+            p.collect_entity(&row);
+        }
+
+        let mut bindings = Vec::with_capacity(self.len);
+        self.collect_bindings_into(sqlite, row, &mut bindings)?;
+
+        for mut p in pull_consumers.iter_mut() {
+            p.pull(sqlite)?;
+        }
+        for p in pull_consumers.into_iter() {
+            p.expand(&mut bindings);
+        }
+        Ok(bindings)
+    }
+
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
@@ -284,20 +349,22 @@ impl Projector for RelTwoStagePullProjector {
 /// Each value is sourced from the same column.
 pub(crate) struct CollTwoStagePullProjector {
     spec: Rc<FindSpec>,
+    columns: Rc<Vec<ColumnMetadata>>,
     pull: PullOperation,
 }
 
 impl CollTwoStagePullProjector {
-    fn with_pull(spec: Rc<FindSpec>, pull: PullOperation) -> CollTwoStagePullProjector {
+    fn with_pull(spec: Rc<FindSpec>, columns: Rc<Vec<ColumnMetadata>>, pull: PullOperation) -> CollTwoStagePullProjector {
         CollTwoStagePullProjector {
             spec: spec,
+            columns: columns,
             pull: pull,
         }
     }
 
     pub(crate) fn combine(spec: Rc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let pull = elements.pulls.pop().expect("Expected a single pull");
-        let projector = Box::new(CollTwoStagePullProjector::with_pull(spec, pull.op));
+        let projector = Box::new(CollTwoStagePullProjector::with_pull(spec, elements.columns.clone(), pull.op));
 
         // If every column yields only one value, or we're grouping by the value,
         // don't bother with DISTINCT. This shouldn't really apply to coll-pull.
@@ -324,10 +391,18 @@ impl Projector for CollTwoStagePullProjector {
 
         Ok(QueryOutput {
             spec: self.spec.clone(),
+            columns: self.columns.clone(),
             results: QueryResults::Coll(out),
         })
     }
 
+    fn project_row<'a, 'stmt>(&self, schema: &Schema, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+        let mut pull_consumer = PullConsumer::for_operation(schema, &self.pull)?;
+        pull_consumer.collect_entity(&row);
+        pull_consumer.pull(sqlite)?;
+        Ok(pull_consumer.into_coll_results())
+    }
+
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
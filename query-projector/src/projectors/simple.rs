@@ -12,6 +12,7 @@ use std::rc::Rc;
 
 use ::{
     Binding,
+    ColumnMetadata,
     CombinedProjection,
     Element,
     FindSpec,
@@ -19,10 +20,14 @@ use ::{
     QueryOutput,
     QueryResults,
     RelResult,
-    Row,
-    Rows,
     Schema,
     TypedIndex,
+};
+
+#[cfg(feature = "store")]
+use ::{
+    Row,
+    Rows,
     rusqlite,
 };
 
@@ -36,41 +41,50 @@ use super::{
 
 pub(crate) struct ScalarProjector {
     spec: Rc<FindSpec>,
+    columns: Rc<Vec<ColumnMetadata>>,
     template: TypedIndex,
 }
 
 impl ScalarProjector {
-    fn with_template(spec: Rc<FindSpec>, template: TypedIndex) -> ScalarProjector {
+    fn with_template(spec: Rc<FindSpec>, columns: Rc<Vec<ColumnMetadata>>, template: TypedIndex) -> ScalarProjector {
         ScalarProjector {
             spec: spec,
+            columns: columns,
             template: template,
         }
     }
 
     pub(crate) fn combine(spec: Rc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let template = elements.templates.pop().expect("Expected a single template");
-        let projector = Box::new(ScalarProjector::with_template(spec, template));
+        let projector = Box::new(ScalarProjector::with_template(spec, elements.columns.clone(), template));
         let distinct = false;
         elements.combine(projector, distinct)
     }
 }
 
 impl Projector for ScalarProjector {
-    fn project<'stmt, 's>(&self, _schema: &Schema, _sqlite: &'s rusqlite::Connection, mut rows: Rows<'stmt>) -> Result<QueryOutput> {
+    #[cfg(feature = "store")]
+    fn project<'stmt, 's>(&self, _schema: &Schema, sqlite: &'s rusqlite::Connection, mut rows: Rows<'stmt>) -> Result<QueryOutput> {
         let results =
             if let Some(r) = rows.next() {
                 let row = r?;
-                let binding = self.template.lookup(&row)?;
+                let binding = self.template.lookup(sqlite, &row)?;
                 QueryResults::Scalar(Some(binding))
             } else {
                 QueryResults::Scalar(None)
             };
         Ok(QueryOutput {
             spec: self.spec.clone(),
+            columns: self.columns.clone(),
             results: results,
         })
     }
 
+    #[cfg(feature = "store")]
+    fn project_row<'a, 'stmt>(&self, _schema: &Schema, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+        Ok(vec![self.template.lookup(sqlite, &row)?])
+    }
+
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
@@ -79,54 +93,64 @@ impl Projector for ScalarProjector {
 /// A tuple projector produces a single vector. It's the single-result version of rel.
 pub(crate) struct TupleProjector {
     spec: Rc<FindSpec>,
+    columns: Rc<Vec<ColumnMetadata>>,
     len: usize,
     templates: Vec<TypedIndex>,
 }
 
 impl TupleProjector {
-    fn with_templates(spec: Rc<FindSpec>, len: usize, templates: Vec<TypedIndex>) -> TupleProjector {
+    fn with_templates(spec: Rc<FindSpec>, columns: Rc<Vec<ColumnMetadata>>, len: usize, templates: Vec<TypedIndex>) -> TupleProjector {
         TupleProjector {
             spec: spec,
+            columns: columns,
             len: len,
             templates: templates,
         }
     }
 
     // This is just like we do for `rel`, but into a vec of its own.
-    fn collect_bindings<'a, 'stmt>(&self, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+    #[cfg(feature = "store")]
+    fn collect_bindings<'a, 'stmt>(&self, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
         // There will be at least as many SQL columns as Datalog columns.
         // gte 'cos we might be querying extra columns for ordering.
         // The templates will take care of ignoring columns.
         assert!(row.column_count() >= self.len as i32);
         self.templates
             .iter()
-            .map(|ti| ti.lookup(&row))
+            .map(|ti| ti.lookup(sqlite, &row))
             .collect::<Result<Vec<Binding>>>()
     }
 
     pub(crate) fn combine(spec: Rc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
-        let projector = Box::new(TupleProjector::with_templates(spec, column_count, elements.take_templates()));
+        let projector = Box::new(TupleProjector::with_templates(spec, elements.columns.clone(), column_count, elements.take_templates()));
         let distinct = false;
         elements.combine(projector, distinct)
     }
 }
 
 impl Projector for TupleProjector {
-    fn project<'stmt, 's>(&self, _schema: &Schema, _sqlite: &'s rusqlite::Connection, mut rows: Rows<'stmt>) -> Result<QueryOutput> {
+    #[cfg(feature = "store")]
+    fn project<'stmt, 's>(&self, _schema: &Schema, sqlite: &'s rusqlite::Connection, mut rows: Rows<'stmt>) -> Result<QueryOutput> {
         let results =
             if let Some(r) = rows.next() {
                 let row = r?;
-                let bindings = self.collect_bindings(row)?;
+                let bindings = self.collect_bindings(sqlite, row)?;
                 QueryResults::Tuple(Some(bindings))
             } else {
                 QueryResults::Tuple(None)
             };
         Ok(QueryOutput {
             spec: self.spec.clone(),
+            columns: self.columns.clone(),
             results: results,
         })
     }
 
+    #[cfg(feature = "store")]
+    fn project_row<'a, 'stmt>(&self, _schema: &Schema, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+        self.collect_bindings(sqlite, row)
+    }
+
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
@@ -138,20 +162,23 @@ impl Projector for TupleProjector {
 /// the `Row`: one for the value and optionally one for the type tag.
 pub(crate) struct RelProjector {
     spec: Rc<FindSpec>,
+    columns: Rc<Vec<ColumnMetadata>>,
     len: usize,
     templates: Vec<TypedIndex>,
 }
 
 impl RelProjector {
-    fn with_templates(spec: Rc<FindSpec>, len: usize, templates: Vec<TypedIndex>) -> RelProjector {
+    fn with_templates(spec: Rc<FindSpec>, columns: Rc<Vec<ColumnMetadata>>, len: usize, templates: Vec<TypedIndex>) -> RelProjector {
         RelProjector {
             spec: spec,
+            columns: columns,
             len: len,
             templates: templates,
         }
     }
 
-    fn collect_bindings_into<'a, 'stmt, 'out>(&self, row: Row<'a, 'stmt>, out: &mut Vec<Binding>) -> Result<()> {
+    #[cfg(feature = "store")]
+    fn collect_bindings_into<'a, 'stmt, 'out>(&self, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>, out: &mut Vec<Binding>) -> Result<()> {
         // There will be at least as many SQL columns as Datalog columns.
         // gte 'cos we might be querying extra columns for ordering.
         // The templates will take care of ignoring columns.
@@ -159,7 +186,7 @@ impl RelProjector {
         let mut count = 0;
         for binding in self.templates
                            .iter()
-                           .map(|ti| ti.lookup(&row)) {
+                           .map(|ti| ti.lookup(sqlite, &row)) {
             out.push(binding?);
             count += 1;
         }
@@ -168,7 +195,7 @@ impl RelProjector {
     }
 
     pub(crate) fn combine(spec: Rc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
-        let projector = Box::new(RelProjector::with_templates(spec, column_count, elements.take_templates()));
+        let projector = Box::new(RelProjector::with_templates(spec, elements.columns.clone(), column_count, elements.take_templates()));
 
         // If every column yields only one value, or if this is an aggregate query
         // (because by definition every column in an aggregate query is either
@@ -181,7 +208,8 @@ impl RelProjector {
 }
 
 impl Projector for RelProjector {
-    fn project<'stmt, 's>(&self, _schema: &Schema, _sqlite: &'s rusqlite::Connection, mut rows: Rows<'stmt>) -> Result<QueryOutput> {
+    #[cfg(feature = "store")]
+    fn project<'stmt, 's>(&self, _schema: &Schema, sqlite: &'s rusqlite::Connection, mut rows: Rows<'stmt>) -> Result<QueryOutput> {
         // Allocate space for five rows to start.
         // This is better than starting off by doubling the buffer a couple of times, and will
         // rapidly grow to support larger query results.
@@ -190,15 +218,23 @@ impl Projector for RelProjector {
 
         while let Some(r) = rows.next() {
             let row = r?;
-            self.collect_bindings_into(row, &mut values)?;
+            self.collect_bindings_into(sqlite, row, &mut values)?;
         }
 
         Ok(QueryOutput {
             spec: self.spec.clone(),
+            columns: self.columns.clone(),
             results: QueryResults::Rel(RelResult { width, values }),
         })
     }
 
+    #[cfg(feature = "store")]
+    fn project_row<'a, 'stmt>(&self, _schema: &Schema, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+        let mut values = Vec::with_capacity(self.len);
+        self.collect_bindings_into(sqlite, row, &mut values)?;
+        Ok(values)
+    }
+
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
@@ -208,20 +244,22 @@ impl Projector for RelProjector {
 /// Each value is sourced from the same column.
 pub(crate) struct CollProjector {
     spec: Rc<FindSpec>,
+    columns: Rc<Vec<ColumnMetadata>>,
     template: TypedIndex,
 }
 
 impl CollProjector {
-    fn with_template(spec: Rc<FindSpec>, template: TypedIndex) -> CollProjector {
+    fn with_template(spec: Rc<FindSpec>, columns: Rc<Vec<ColumnMetadata>>, template: TypedIndex) -> CollProjector {
         CollProjector {
             spec: spec,
+            columns: columns,
             template: template,
         }
     }
 
     pub(crate) fn combine(spec: Rc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let template = elements.templates.pop().expect("Expected a single template");
-        let projector = Box::new(CollProjector::with_template(spec, template));
+        let projector = Box::new(CollProjector::with_template(spec, elements.columns.clone(), template));
 
         // If every column yields only one value, or if this is an aggregate query
         // (because by definition every column in an aggregate query is either
@@ -234,19 +272,26 @@ impl CollProjector {
 }
 
 impl Projector for CollProjector {
-    fn project<'stmt, 's>(&self, _schema: &Schema, _sqlite: &'s rusqlite::Connection, mut rows: Rows<'stmt>) -> Result<QueryOutput> {
+    #[cfg(feature = "store")]
+    fn project<'stmt, 's>(&self, _schema: &Schema, sqlite: &'s rusqlite::Connection, mut rows: Rows<'stmt>) -> Result<QueryOutput> {
         let mut out: Vec<_> = vec![];
         while let Some(r) = rows.next() {
             let row = r?;
-            let binding = self.template.lookup(&row)?;
+            let binding = self.template.lookup(sqlite, &row)?;
             out.push(binding);
         }
         Ok(QueryOutput {
             spec: self.spec.clone(),
+            columns: self.columns.clone(),
             results: QueryResults::Coll(out),
         })
     }
 
+    #[cfg(feature = "store")]
+    fn project_row<'a, 'stmt>(&self, _schema: &Schema, sqlite: &rusqlite::Connection, row: Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+        Ok(vec![self.template.lookup(sqlite, &row)?])
+    }
+
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
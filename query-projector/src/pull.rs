@@ -8,11 +8,13 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+#[cfg(feature = "store")]
 use std::collections::{
     BTreeMap,
     BTreeSet,
 };
 
+#[cfg(feature = "store")]
 use core_traits::{
     Binding,
     Entid,
@@ -20,6 +22,7 @@ use core_traits::{
     TypedValue,
 };
 
+#[cfg(feature = "store")]
 use mentat_core::{
     Schema,
     ValueRc,
@@ -29,14 +32,20 @@ use edn::query::{
     PullAttributeSpec,
 };
 
+#[cfg(feature = "store")]
 use mentat_query_pull::{
     Puller,
 };
 
+#[cfg(feature = "store")]
 use query_projector_traits::errors::Result;
 
 use super::{
     Index,
+};
+
+#[cfg(feature = "store")]
+use super::{
     rusqlite,
 };
 
@@ -49,6 +58,7 @@ pub(crate) struct PullIndices {
     pub(crate) output_index: usize,
 }
 
+#[cfg(feature = "store")]
 impl PullIndices {
     fn zero() -> PullIndices {
         PullIndices {
@@ -64,6 +74,8 @@ pub(crate) struct PullTemplate {
     pub(crate) op: PullOperation,
 }
 
+/// Runs pull expressions against a store, once a query's rows have named the entities to pull.
+#[cfg(feature = "store")]
 pub(crate) struct PullConsumer<'schema> {
     indices: PullIndices,
     schema: &'schema Schema,
@@ -72,6 +84,7 @@ pub(crate) struct PullConsumer<'schema> {
     results: BTreeMap<Entid, ValueRc<StructuredMap>>,
 }
 
+#[cfg(feature = "store")]
 impl<'schema> PullConsumer<'schema> {
     pub(crate) fn for_puller(puller: Puller, schema: &'schema Schema, indices: PullIndices) -> PullConsumer<'schema> {
         PullConsumer {
@@ -93,9 +106,13 @@ impl<'schema> PullConsumer<'schema> {
         Ok(PullConsumer::for_puller(puller, schema, PullIndices::zero()))
     }
 
-    pub(crate) fn collect_entity<'a, 'stmt>(&mut self, row: &rusqlite::Row<'a, 'stmt>) -> Entid {
-        let entity = row.get(self.indices.sql_index);
-        self.entities.insert(entity);
+    /// The entity is absent if an `optional` clause didn't bind it, in which case there's
+    /// nothing to pull.
+    pub(crate) fn collect_entity<'a, 'stmt>(&mut self, row: &rusqlite::Row<'a, 'stmt>) -> Option<Entid> {
+        let entity: Option<Entid> = row.get(self.indices.sql_index);
+        if let Some(e) = entity {
+            self.entities.insert(e);
+        }
         entity
     }
 
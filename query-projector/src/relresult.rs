@@ -10,7 +10,6 @@
 
 use core_traits::{
     Binding,
-    TypedValue,
 };
 
 /// The result you get from a 'rel' query, like:
@@ -73,6 +72,8 @@ impl<T> RelResult<T> {
 
 #[test]
 fn test_rel_result() {
+    use core_traits::TypedValue;
+
     let empty = StructuredRelResult::empty(3);
     let unit = StructuredRelResult {
         width: 1,
@@ -104,11 +105,14 @@ fn test_rel_result() {
     assert_eq!(rr.next(), Some(vec![TypedValue::Long(5).into(), TypedValue::Boolean(true).into()].as_slice()));
     assert_eq!(rr.next(), Some(vec![TypedValue::Long(-2).into(), TypedValue::Boolean(false).into()].as_slice()));
     assert_eq!(rr.next(), None);
+
+    let with_absent: StructuredRelResult = vec![vec![Some(TypedValue::Long(5)), None]].into();
+    assert_eq!(with_absent.row(0), Some(vec![TypedValue::Long(5).into(), Binding::Null].as_slice()));
 }
 
-// Primarily for testing.
-impl From<Vec<Vec<TypedValue>>> for RelResult<Binding> {
-    fn from(src: Vec<Vec<TypedValue>>) -> Self {
+// Primarily for testing.  Rows can be written with `Option<TypedValue>`s where a value is absent.
+impl<T> From<Vec<Vec<T>>> for RelResult<Binding> where T: Into<Binding> {
+    fn from(src: Vec<Vec<T>>) -> Self {
         if src.is_empty() {
             RelResult::empty(0)
         } else {
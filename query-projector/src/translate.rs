@@ -28,10 +28,13 @@ use mentat_core::util::{
 
 use edn::query::{
     Limit,
+    Offset,
+    Variable,
 };
 
 use mentat_query_algebrizer::{
     AlgebraicQuery,
+    Column,
     ColumnAlternation,
     ColumnConstraint,
     ColumnConstraintOrAlternation,
@@ -71,12 +74,19 @@ use mentat_query_sql::{
     Values,
 };
 
-use std::collections::HashMap;
+use std::collections::{
+    BTreeSet,
+    HashMap,
+};
 
 use super::Result;
 
+/// The tables whose `v` column might hold offloaded strings.  See
+/// `AlgebraicQuery::large_value_aliases`.
+type LargeValueAliases = BTreeSet<TableAlias>;
+
 trait ToConstraint {
-    fn to_constraint(self) -> Constraint;
+    fn to_constraint(self, large_values: &LargeValueAliases) -> Constraint;
 }
 
 trait ToColumn {
@@ -90,27 +100,27 @@ impl ToColumn for QualifiedAlias {
 }
 
 impl ToConstraint for ColumnIntersection {
-    fn to_constraint(self) -> Constraint {
+    fn to_constraint(self, large_values: &LargeValueAliases) -> Constraint {
         Constraint::And {
-            constraints: self.into_iter().map(|x| x.to_constraint()).collect()
+            constraints: self.into_iter().map(|x| x.to_constraint(large_values)).collect()
         }
     }
 }
 
 impl ToConstraint for ColumnAlternation {
-    fn to_constraint(self) -> Constraint {
+    fn to_constraint(self, large_values: &LargeValueAliases) -> Constraint {
         Constraint::Or {
-            constraints: self.into_iter().map(|x| x.to_constraint()).collect()
+            constraints: self.into_iter().map(|x| x.to_constraint(large_values)).collect()
         }
     }
 }
 
 impl ToConstraint for ColumnConstraintOrAlternation {
-    fn to_constraint(self) -> Constraint {
+    fn to_constraint(self, large_values: &LargeValueAliases) -> Constraint {
         use self::ColumnConstraintOrAlternation::*;
         match self {
-            Alternation(alt) => alt.to_constraint(),
-            Constraint(c) => c.to_constraint(),
+            Alternation(alt) => alt.to_constraint(large_values),
+            Constraint(c) => c.to_constraint(large_values),
         }
     }
 }
@@ -160,16 +170,50 @@ fn possible_affinities(value_types: ValueTypeSet) -> HashMap<ValueTypeTag, Vec<S
     result
 }
 
+/// True if `qa` is the `v` column of a table that might hold offloaded strings by id.
+fn may_be_offloaded(qa: &QualifiedAlias, large_values: &LargeValueAliases) -> bool {
+    qa.1 == Column::Fixed(DatomsColumn::Value) && large_values.contains(&qa.0)
+}
+
+/// `qa = value`, where `value` might be a string that has been offloaded to `large_values`.
+///
+/// A string may be stored inline or by id, depending on its attribute and on when it was
+/// asserted, so we match either.  An `IN` list, unlike an `OR`, still lets SQLite use an index.
+fn value_equal(qa: QualifiedAlias, value: ColumnOrExpression, text: ColumnOrExpression, large_values: &LargeValueAliases) -> Constraint {
+    if !may_be_offloaded(&qa, large_values) {
+        return Constraint::equal(qa.to_column(), value);
+    }
+    Constraint::In {
+        left: qa.to_column(),
+        list: vec![value, ColumnOrExpression::LargeValueId(Box::new(text))],
+    }
+}
+
+/// The value of `value` for string functions, with offloaded strings replaced by their text.
+fn resolved_value(value: QueryValue, large_values: &LargeValueAliases) -> ColumnOrExpression {
+    match value {
+        QueryValue::Column(ref qa) if may_be_offloaded(qa, large_values) => ColumnOrExpression::ResolvedValue(qa.clone()),
+        value => value.into(),
+    }
+}
+
 impl ToConstraint for ColumnConstraint {
-    fn to_constraint(self) -> Constraint {
+    fn to_constraint(self, large_values: &LargeValueAliases) -> Constraint {
         use self::ColumnConstraint::*;
         match self {
             Equals(qa, QueryValue::Entid(entid)) =>
                 Constraint::equal(qa.to_column(), ColumnOrExpression::Entid(entid)),
 
+            Equals(qa, QueryValue::TypedValue(tv @ TypedValue::String(_))) =>
+                value_equal(qa, ColumnOrExpression::Value(tv.clone()), ColumnOrExpression::Value(tv), large_values),
+
             Equals(qa, QueryValue::TypedValue(tv)) =>
                 Constraint::equal(qa.to_column(), ColumnOrExpression::Value(tv)),
 
+            // An input might be a string.
+            Equals(qa, QueryValue::Parameter(var)) =>
+                value_equal(qa, ColumnOrExpression::Parameter(var.clone()), ColumnOrExpression::Parameter(var), large_values),
+
             Equals(left, QueryValue::Column(right)) =>
                 Constraint::equal(left.to_column(), right.to_column()),
 
@@ -213,6 +257,12 @@ impl ToConstraint for ColumnConstraint {
                 }
             },
 
+            NotNull(qa) => {
+                Constraint::IsNotNull {
+                    value: qa.to_column(),
+                }
+            },
+
             Matches(left, right) => {
                 Constraint::Infix {
                     op: Op("MATCH"),
@@ -241,11 +291,34 @@ impl ToConstraint for ColumnConstraint {
             },
 
             NotExists(computed_table) => {
-                let subquery = table_for_computed(computed_table, TableAlias::new());
+                let subquery = table_for_computed(computed_table, TableAlias::new(), large_values);
                 Constraint::NotExists {
                     subquery: subquery,
                 }
             },
+
+            Exists(computed_table) => {
+                let subquery = table_for_computed(computed_table, TableAlias::new(), large_values);
+                Constraint::Exists {
+                    subquery: subquery,
+                }
+            },
+
+            InAVETIndex(table) => {
+                Constraint::Infix {
+                    op: Op("IS NOT"),
+                    left: QualifiedAlias::new(table, DatomsColumn::IndexAVET).to_column(),
+                    right: ColumnOrExpression::Integer(0),
+                }
+            },
+
+            StringMatches { operator, value, pattern } => {
+                Constraint::Infix {
+                    op: Op(operator.to_sql_operator()),
+                    left: resolved_value(value, large_values),
+                    right: pattern.into(),
+                }
+            },
         }
     }
 }
@@ -275,63 +348,87 @@ impl<T> ConsumableVec<T> {
     }
 }
 
-fn table_for_computed(computed: ComputedTable, alias: TableAlias) -> TableOrSubquery {
+/// Project `projection` from a single arm of a union, along with the type tags of those variables
+/// in `type_extraction`. Each arm of a union must project the same shape with the same names.
+fn union_arm_query(projection: &[Variable], type_extraction: &BTreeSet<Variable>, cc: ConjoiningClauses, large_values: &LargeValueAliases) -> SelectQuery {
+    // We're going to end up with the variables being projected and also some
+    // type tag columns.
+    let mut columns: Vec<ProjectedColumn> = Vec::with_capacity(projection.len() + type_extraction.len());
+
+    // For each variable, find out which column it maps to within this arm, and
+    // project it as the variable name.
+    // E.g., SELECT datoms03.v AS `?x`.
+    for var in projection.iter() {
+        // TODO: chain results out.
+        let (projected_column, type_set) = projected_column_for_var(var, &cc).expect("every var to be bound");
+        columns.push(projected_column);
+
+        // Similarly, project type tags if they're not known conclusively in the
+        // outer query.
+        // Assumption: we'll never need to project a tag without projecting the value of a variable.
+        if type_extraction.contains(var) {
+            let expression =
+                if let Some(tag) = type_set.unique_type_tag() {
+                    // If we know the type for sure, just project the constant.
+                    // SELECT datoms03.v AS `?x`, 10 AS `?x_value_type_tag`
+                    ColumnOrExpression::Integer(tag)
+                } else {
+                    // Otherwise, we'll have an established type binding! This'll be
+                    // either a datoms table or, recursively, a subquery. Project
+                    // this:
+                    // SELECT datoms03.v AS `?x`,
+                    //        datoms03.value_type_tag AS `?x_value_type_tag`
+                    let extract = cc.extracted_types
+                                    .get(var)
+                                    .expect("Expected variable to have a known type or an extracted type");
+                    ColumnOrExpression::Column(extract.clone())
+                };
+            let type_column = VariableColumn::VariableTypeTag(var.clone());
+            let proj = ProjectedColumn(expression, type_column.column_name());
+            columns.push(proj);
+        }
+    }
+
+    // Each arm simply turns into a subquery.
+    // The SQL translation will stuff "UNION" between each arm.
+    let projection = Projection::Columns(columns);
+    cc_to_select_query(projection, cc, false, vec![], None, Limit::None, Offset::None, large_values)
+}
+
+fn table_for_computed(computed: ComputedTable, alias: TableAlias, large_values: &LargeValueAliases) -> TableOrSubquery {
     match computed {
         ComputedTable::Union {
-            projection, type_extraction, arms,
+            projection, type_extraction, arms, disjoint,
         } => {
             // The projection list for each CC must have the same shape and the same names.
             // The values we project might be fixed or they might be columns.
-            TableOrSubquery::Union(
-                arms.into_iter()
-                    .map(|cc| {
-                        // We're going to end up with the variables being projected and also some
-                        // type tag columns.
-                        let mut columns: Vec<ProjectedColumn> = Vec::with_capacity(projection.len() + type_extraction.len());
-
-                        // For each variable, find out which column it maps to within this arm, and
-                        // project it as the variable name.
-                        // E.g., SELECT datoms03.v AS `?x`.
-                        for var in projection.iter() {
-                            // TODO: chain results out.
-                            let (projected_column, type_set) = projected_column_for_var(var, &cc).expect("every var to be bound");
-                            columns.push(projected_column);
-
-                            // Similarly, project type tags if they're not known conclusively in the
-                            // outer query.
-                            // Assumption: we'll never need to project a tag without projecting the value of a variable.
-                            if type_extraction.contains(var) {
-                                let expression =
-                                    if let Some(tag) = type_set.unique_type_tag() {
-                                        // If we know the type for sure, just project the constant.
-                                        // SELECT datoms03.v AS `?x`, 10 AS `?x_value_type_tag`
-                                        ColumnOrExpression::Integer(tag)
-                                    } else {
-                                        // Otherwise, we'll have an established type binding! This'll be
-                                        // either a datoms table or, recursively, a subquery. Project
-                                        // this:
-                                        // SELECT datoms03.v AS `?x`,
-                                        //        datoms03.value_type_tag AS `?x_value_type_tag`
-                                        let extract = cc.extracted_types
-                                                        .get(var)
-                                                        .expect("Expected variable to have a known type or an extracted type");
-                                        ColumnOrExpression::Column(extract.clone())
-                                    };
-                                let type_column = VariableColumn::VariableTypeTag(var.clone());
-                                let proj = ProjectedColumn(expression, type_column.column_name());
-                                columns.push(proj);
-                            }
-                        }
-
-                        // Each arm simply turns into a subquery.
-                        // The SQL translation will stuff "UNION" between each arm.
-                        let projection = Projection::Columns(columns);
-                        cc_to_select_query(projection, cc, false, vec![], None, Limit::None)
-                  }).collect(),
-                alias)
+            let projection: Vec<Variable> = projection.into_iter().collect();
+            let arms = arms.into_iter()
+                           .map(|cc| union_arm_query(&projection[..], &type_extraction, cc, large_values))
+                           .collect();
+            if disjoint {
+                // No row can come from two arms, so there's nothing for `UNION` to remove.
+                TableOrSubquery::UnionAll(arms, alias)
+            } else {
+                TableOrSubquery::Union(arms, alias)
+            }
+        },
+        ComputedTable::RecursiveRule {
+            name, columns, arms,
+        } => {
+            // Every arm, including the recursive one, must agree on the types of the columns, so
+            // we always project type tags: the outer query only knows the union of them.
+            let type_extraction: BTreeSet<Variable> = columns.iter().cloned().collect();
+            let arms = arms.into_iter()
+                           .map(|cc| union_arm_query(&columns[..], &type_extraction, cc, large_values))
+                           .collect();
+            TableOrSubquery::RecursiveUnion(name, arms, alias)
+        },
+        ComputedTable::RuleReference(name) => {
+            TableOrSubquery::Reference(name, alias)
         },
         ComputedTable::Subquery(subquery) => {
-            TableOrSubquery::Subquery(Box::new(cc_to_exists(subquery)))
+            TableOrSubquery::Subquery(Box::new(cc_to_exists(subquery, large_values)))
         },
         ComputedTable::NamedValues {
             names, values,
@@ -344,6 +441,7 @@ fn table_for_computed(computed: ComputedTable, alias: TableAlias) -> TableOrSubq
 
 fn empty_query() -> SelectQuery {
     SelectQuery {
+        with: vec![],
         distinct: false,
         projection: Projection::One,
         from: FromClause::Nothing,
@@ -351,6 +449,7 @@ fn empty_query() -> SelectQuery {
         constraints: vec![],
         order: vec![],
         limit: Limit::None,
+        offset: Offset::None,
     }
 }
 
@@ -362,73 +461,100 @@ fn cc_to_select_query(projection: Projection,
                       distinct: bool,
                       group_by: Vec<GroupBy>,
                       order: Option<Vec<OrderBy>>,
-                      limit: Limit) -> SelectQuery {
-    let from = if cc.from.is_empty() {
+                      limit: Limit,
+                      offset: Offset,
+                      large_values: &LargeValueAliases) -> SelectQuery {
+    let from = if cc.from.is_empty() && cc.left_joins.is_empty() {
         FromClause::Nothing
     } else {
         // Move these out of the CC.
         let from = cc.from;
+        let left_joins = cc.left_joins;
+        let mut index_hints = cc.index_hints;
         let mut computed: ConsumableVec<_> = cc.computed_tables.into();
 
         // Why do we put computed tables directly into the `FROM` clause? The alternative is to use
         // a CTE (`WITH`). They're typically equivalent, but some SQL systems (notably Postgres)
         // treat CTEs as optimization barriers, so a `WITH` can be significantly slower. Given that
         // this is easy enough to change later, we'll opt for using direct inclusion in `FROM`.
+        // The exception is a union that appears more than once: see `share_repeated_unions`.
         let tables =
             from.into_iter().map(|source_alias| {
                 match source_alias {
                     SourceAlias(DatomsTable::Computed(i), alias) => {
                         let comp = computed.take_dangerously(i);
-                        table_for_computed(comp, alias)
+                        table_for_computed(comp, alias, large_values)
                     },
                     _ => {
-                        TableOrSubquery::Table(source_alias)
+                        match index_hints.remove(&source_alias.1) {
+                            Some(index) => TableOrSubquery::IndexedTable(source_alias, index),
+                            None => TableOrSubquery::Table(source_alias),
+                        }
                     }
                 }
             });
 
-        FromClause::TableList(TableList(tables.collect()))
+        // A `LEFT JOIN` needs something to its left.
+        let mut tables: Vec<TableOrSubquery> = tables.collect();
+        if tables.is_empty() {
+            tables.push(TableOrSubquery::Subquery(Box::new(empty_query())));
+        }
+        tables.extend(left_joins.into_iter().map(|left_join| {
+            let on = left_join.on.into_iter().map(|c| c.to_constraint(large_values)).collect();
+            let table = match left_join.table {
+                // The clauses of an `optional`.
+                SourceAlias(DatomsTable::Computed(i), alias) => table_for_computed(computed.take_dangerously(i), alias, large_values),
+                table => TableOrSubquery::Table(table),
+            };
+            TableOrSubquery::LeftJoin(Box::new(table), on)
+        }));
+
+        FromClause::TableList(TableList(tables))
     };
 
     let order = order.map_or(vec![], |vec| { vec.into_iter().map(|o| o.into()).collect() });
     let limit = if cc.empty_because.is_some() { Limit::Fixed(0) } else { limit };
     SelectQuery {
+        with: vec![],
         distinct: distinct,
         projection: projection,
         from: from,
         group_by: group_by,
         constraints: cc.wheres
                        .into_iter()
-                       .map(|c| c.to_constraint())
+                       .map(|c| c.to_constraint(large_values))
                        .collect(),
         order: order,
         limit: limit,
+        offset: offset,
     }
 }
 
 /// Return a query that projects `1` if the `cc` matches the store, and returns no results
 /// if it doesn't.
-pub fn cc_to_exists(cc: ConjoiningClauses) -> SelectQuery {
+pub fn cc_to_exists(cc: ConjoiningClauses, large_values: &LargeValueAliases) -> SelectQuery {
     if cc.is_known_empty() {
         // In this case we can produce a very simple query that returns no results.
         empty_query()
     } else {
-        cc_to_select_query(Projection::One, cc, false, vec![], None, Limit::None)
+        cc_to_select_query(Projection::One, cc, false, vec![], None, Limit::None, Offset::None, large_values)
     }
 }
 
 /// Take a query and wrap it as a subquery of a new query with the provided projection list.
-/// All limits, ordering, and grouping move to the outer query. The inner query is marked as
-/// distinct.
-fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
+/// All limits, offsets, ordering, and grouping move to the outer query. The inner query is marked
+/// as distinct unless the query asked for duplicates to be kept.
+fn re_project(mut inner: SelectQuery, projection: Projection, distinct: bool) -> SelectQuery {
     let outer_distinct = inner.distinct;
-    inner.distinct = true;
+    inner.distinct = distinct;
     let group_by = inner.group_by;
     inner.group_by = vec![];
     let order_by = inner.order;
     inner.order = vec![];
     let limit = inner.limit;
     inner.limit = Limit::None;
+    let offset = inner.offset;
+    inner.offset = Offset::None;
 
     use self::Projection::*;
 
@@ -451,6 +577,7 @@ fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
 
     if nullable.is_empty() {
         return SelectQuery {
+            with: vec![],
             distinct: outer_distinct,
             projection: projection,
             from: FromClause::TableList(TableList(vec![TableOrSubquery::Subquery(Box::new(inner))])),
@@ -458,28 +585,32 @@ fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
             group_by: group_by,
             order: order_by,
             limit: limit,
+            offset: offset,
         };
     }
 
     // Our pattern is `SELECT * FROM (SELECT ...) WHERE (nullable aggregate) IS NOT NULL`.  If
     // there's an `ORDER BY` in the subselect, SQL does not guarantee that the outer select will
-    // respect that order.  But `ORDER BY` is relevant to the subselect when we have a `LIMIT`.
-    // Thus we lift the `ORDER BY` if there’s no `LIMIT` in the subselect, and repeat the `ORDER BY`
-    // if there is.
+    // respect that order.  But `ORDER BY` is relevant to the subselect when we have a `LIMIT` or
+    // an `OFFSET`.  Thus we lift the `ORDER BY` if there’s neither in the subselect, and repeat the
+    // `ORDER BY` if there is.
     let subselect = SelectQuery {
+        with: vec![],
         distinct: outer_distinct,
         projection: projection,
         from: FromClause::TableList(TableList(vec![TableOrSubquery::Subquery(Box::new(inner))])),
         constraints: vec![],
         group_by: group_by,
         order: match &limit {
-            &Limit::None => vec![],
-            &Limit::Fixed(_) | &Limit::Variable(_) => order_by.clone(),
+            &Limit::None if !offset.is_some() => vec![],
+            _ => order_by.clone(),
         },
         limit,
+        offset,
     };
 
     SelectQuery {
+        with: vec![],
         distinct: false,
         projection: Projection::Star,
         from: FromClause::TableList(TableList(vec![TableOrSubquery::Subquery(Box::new(subselect))])),
@@ -487,6 +618,7 @@ fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
         group_by: vec![],
         order: order_by,
         limit: Limit::None, // Any limiting comes from the internal query.
+        offset: Offset::None,
     }
 }
 
@@ -508,17 +640,22 @@ pub fn query_to_select(schema: &Schema, query: AlgebraicQuery) -> Result<Project
                 query: match pre_aggregate_projection {
                     // If we know we need a nested query for aggregation, build that first.
                     Some(pre_aggregate) => {
-                        let inner = cc_to_select_query(pre_aggregate,
-                                                       query.cc,
-                                                       distinct,
-                                                       group_by_cols,
-                                                       query.order,
-                                                       query.limit);
-                        let outer = re_project(inner, sql_projection);
+                        let mut inner = cc_to_select_query(pre_aggregate,
+                                                           query.cc,
+                                                           distinct,
+                                                           group_by_cols,
+                                                           query.order,
+                                                           query.limit,
+                                                           query.offset,
+                                                           &query.large_value_aliases);
+                        inner.share_repeated_unions();
+                        let outer = re_project(inner, sql_projection, query.distinct);
                         outer
                     },
                     None => {
-                        cc_to_select_query(sql_projection, query.cc, distinct, group_by_cols, query.order, query.limit)
+                        let mut select = cc_to_select_query(sql_projection, query.cc, distinct, group_by_cols, query.order, query.limit, query.offset, &query.large_value_aliases);
+                        select.share_repeated_unions();
+                        select
                     },
                 },
                 projector: datalog_projector,
@@ -17,8 +17,6 @@ extern crate mentat_sql;
 
 use std::collections::BTreeMap;
 
-use std::rc::Rc;
-
 use edn::query::{
     FindSpec,
     Keyword,
@@ -33,7 +31,10 @@ use core_traits::{
 };
 
 use mentat_core::{
+    AttributeIndexes,
+    IndexDirection,
     Schema,
+    Statistics,
 };
 
 use mentat_query_algebrizer::{
@@ -53,7 +54,10 @@ use mentat_query_projector::translate::{
     query_to_select,
 };
 
-use mentat_sql::SQLQuery;
+use mentat_sql::{
+    SQLArg,
+    SQLQuery,
+};
 
 /// Produce the appropriate `Variable` for the provided valid ?-prefixed name.
 /// This lives here because we can't re-export macros:
@@ -114,6 +118,13 @@ fn translate(schema: &Schema, query: &'static str) -> SQLQuery {
     translate_with_inputs(schema, query, QueryInputs::default())
 }
 
+fn translate_with_large_values(schema: &Schema, query: &'static str) -> SQLQuery {
+    let known = Known::for_schema(schema).with_large_values(true);
+    let parsed = parse_find_string(query).expect("parse to succeed");
+    let algebrized = algebrize(known, parsed).expect("algebrize to succeed");
+    query_to_sql(query_to_select(schema, algebrized).expect("translate to succeed"))
+}
+
 fn translate_with_inputs_to_constant(schema: &Schema, query: &'static str, inputs: QueryInputs) -> ConstantProjector {
     query_to_constant(inner_translate_with_inputs(schema, query, inputs))
 }
@@ -144,8 +155,8 @@ fn prepopulated_schema() -> Schema {
     prepopulated_typed_schema(ValueType::String)
 }
 
-fn make_arg(name: &'static str, value: &'static str) -> (String, Rc<mentat_sql::Value>) {
-    (name.to_string(), Rc::new(mentat_sql::Value::Text(value.to_string())))
+fn make_arg(name: &'static str, value: &'static str) -> (String, SQLArg) {
+    (name.to_string(), SQLArg::text(value))
 }
 
 #[test]
@@ -198,6 +209,81 @@ fn test_limit() {
     assert_eq!(args, vec![make_arg("$v0", "yyy")]);
 }
 
+#[test]
+fn test_offset() {
+    let schema = prepopulated_schema();
+
+    let query = r#"[:find ?x :where [?x :foo/bar "yyy"] :limit 5 :offset 10]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT 5 OFFSET 10");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+
+    // SQLite doesn't allow OFFSET without LIMIT.
+    let query = r#"[:find ?x :where [?x :foo/bar "yyy"] :offset 10]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT -1 OFFSET 10");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+
+    // A zero offset is no offset.
+    let query = r#"[:find ?x :where [?x :foo/bar "yyy"] :offset 0]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0");
+
+    // A limit of one doesn't make `DISTINCT` unnecessary if we skip rows first.
+    let query = r#"[:find ?x :where [?x :foo/bar "yyy"] :limit 1 :offset 1]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT 1 OFFSET 1");
+}
+
+#[test]
+fn test_not_distinct() {
+    let schema = prepopulated_schema();
+
+    let query = r#"[:find ?x :where [?x :foo/bar "yyy"] :distinct false]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+
+    // Not even to page through results.
+    let query = r#"[:find ?x :where [?x :foo/bar "yyy"] :limit 5 :offset 10 :distinct false]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(sql, "SELECT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT 5 OFFSET 10");
+
+    // Aggregates see every row.
+    let query = r#"[:find (count ?t) :where [?e :foo/bar ?t] :distinct false]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(sql, "SELECT count(`?t`) AS `(count ?t)` \
+                     FROM \
+                     (SELECT \
+                      `datoms00`.v AS `?t` \
+                      FROM `datoms` AS `datoms00` \
+                      WHERE `datoms00`.a = 99)");
+}
+
+#[test]
+fn test_unbound_variable_offset() {
+    let schema = prepopulated_schema();
+
+    let query = r#"[:find ?x :in ?skip :where [?x :foo/bar "yyy"] :limit 5 :offset ?skip]"#;
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, QueryInputs::default());
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 \
+                     LIMIT 5 OFFSET $iskip");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+}
+
+#[test]
+fn test_bound_variable_offset() {
+    let schema = prepopulated_schema();
+
+    let query = r#"[:find ?x :in ?skip :where [?x :foo/bar "yyy"] :offset ?skip]"#;
+    let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?skip"), TypedValue::Long(20))]);
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT -1 OFFSET 20");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+}
+
 #[test]
 fn test_unbound_variable_limit() {
     let schema = prepopulated_schema();
@@ -254,10 +340,9 @@ fn test_bound_variable_limit_affects_types() {
     let select = query_to_select(&schema, algebrized).expect("query to translate");
     let SQLQuery { sql, args } = query_to_sql(select);
 
-    // TODO: this query isn't actually correct -- we don't yet algebrize for variables that are
-    // specified in `:in` but not provided at algebrizing time. But it shows what we care about
-    // at the moment: we don't project a type column, because we know it's a Long.
-    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, `datoms00`.v AS `?limit` FROM `datoms` AS `datoms00` LIMIT $ilimit");
+    // `?limit` wasn't provided, so it's bound as a parameter when the query is run. We don't
+    // project a type column, because we know it's a Long.
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, `datoms00`.v AS `?limit` FROM `datoms` AS `datoms00` WHERE `datoms00`.v = $ilimit LIMIT $ilimit");
     assert_eq!(args, vec![]);
 }
 
@@ -426,6 +511,120 @@ fn test_numeric_gte_known_attribute() {
     assert_eq!(args, vec![]);
 }
 
+#[test]
+fn test_starts_with() {
+    let schema = prepopulated_schema();
+    let query = r#"[:find ?x :where [?x :foo/bar ?y] [(starts-with ?y "http")]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 \
+                       AND `datoms00`.v >= $v0 \
+                       AND `datoms00`.v < $v1");
+    assert_eq!(args, vec![make_arg("$v0", "http"), make_arg("$v1", "httq")]);
+}
+
+#[test]
+fn test_starts_with_indexed_attribute() {
+    let mut schema = Schema::default();
+    associate_ident(&mut schema, Keyword::namespaced("page", "url"), 99);
+    add_attribute(&mut schema, 99, Attribute {
+        value_type: ValueType::String,
+        index: true,
+        ..Default::default()
+    });
+
+    // We constrain the type tag and `index_avet` so that SQLite can use the partial AVET index.
+    let query = r#"[:find ?x :where [?x :page/url ?y] [(starts-with ?y "http")]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 \
+                       AND (`datoms00`.value_type_tag = 10) \
+                       AND `datoms00`.index_avet IS NOT 0 \
+                       AND `datoms00`.v >= $v0 \
+                       AND `datoms00`.v < $v1");
+    assert_eq!(args, vec![make_arg("$v0", "http"), make_arg("$v1", "httq")]);
+}
+
+#[test]
+fn test_starts_with_unknown_attribute() {
+    let schema = Schema::default();
+    let query = "[:find ?x :where [?x _ ?y] [(starts-with ?y \"a\u{10FFFF}\")]]";
+    let SQLQuery { sql, args } = translate(&schema, query);
+
+    // The last character can't be incremented, so the upper bound increments the one before it.
+    assert_eq!(sql, "SELECT DISTINCT `all_datoms00`.e AS `?x` FROM `all_datoms` AS `all_datoms00` \
+                     WHERE (`all_datoms00`.value_type_tag = 10) \
+                       AND `all_datoms00`.v >= $v0 \
+                       AND `all_datoms00`.v < $v1");
+    assert_eq!(args, vec![make_arg("$v0", "a\u{10FFFF}"), make_arg("$v1", "b")]);
+}
+
+#[test]
+fn test_starts_with_bound_value() {
+    let schema = prepopulated_schema();
+    let query = r#"[:find ?x :in ?y ?p :where [?x :foo/bar ?y] [(starts-with ?y ?p)]]"#;
+    let y = Variable::from_valid_name("?y");
+    let p = Variable::from_valid_name("?p");
+
+    let inputs = QueryInputs::with_value_sequence(vec![(y.clone(), "https://example.com".into()), (p.clone(), "http".into())]);
+    let SQLQuery { sql, .. } = translate_with_inputs(&schema, query, inputs);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0");
+
+    let inputs = QueryInputs::with_value_sequence(vec![(y, "ftp://example.com".into()), (p, "http".into())]);
+    assert_query_is_empty(inner_translate_with_inputs(&schema, query, inputs),
+                          FindSpec::FindRel(vec![var!(?x).into()]));
+}
+
+#[test]
+fn test_str_starts_with() {
+    let schema = prepopulated_schema();
+    let query = r#"[:find ?x :where [?x :foo/bar ?y] [(str-starts-with? ?y "http")]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 \
+                       AND `datoms00`.v >= $v0 \
+                       AND `datoms00`.v < $v1");
+    assert_eq!(args, vec![make_arg("$v0", "http"), make_arg("$v1", "httq")]);
+}
+
+#[test]
+fn test_str_includes() {
+    let schema = prepopulated_schema();
+
+    // GLOB's special characters are escaped.
+    let query = r#"[:find ?x :where [?x :foo/bar ?y] [(str-includes? ?y "a*b?[c]")]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 \
+                       AND `datoms00`.v GLOB $v0");
+    assert_eq!(args, vec![make_arg("$v0", "*a[*]b[?][[]c]*")]);
+
+    // Keywords are stored as text, so without an attribute we check the type tag.
+    let query = r#"[:find ?x :where [?x _ ?y] [(str-includes? ?y "b")]]"#;
+    let SQLQuery { sql, args } = translate(&Schema::default(), query);
+    assert_eq!(sql, "SELECT DISTINCT `all_datoms00`.e AS `?x` FROM `all_datoms` AS `all_datoms00` \
+                     WHERE (`all_datoms00`.value_type_tag = 10) \
+                       AND `all_datoms00`.v GLOB $v0");
+    assert_eq!(args, vec![make_arg("$v0", "*b*")]);
+
+    // Only strings include strings.
+    let schema = prepopulated_typed_schema(ValueType::Long);
+    let query = r#"[:find ?x :where [?x :foo/bar ?y] [(str-includes? ?y "b")]]"#;
+    assert_query_is_empty(inner_translate_with_inputs(&schema, query, QueryInputs::default()), FindSpec::FindRel(vec![var!(?x).into()]));
+}
+
+#[test]
+fn test_regex_match() {
+    let schema = prepopulated_schema();
+    let query = r#"[:find ?x :where [?x :foo/bar ?y] [(regex-match ?y "^https?:")]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 \
+                       AND `datoms00`.v REGEXP $v0");
+    assert_eq!(args, vec![make_arg("$v0", "^https?:")]);
+}
+
 #[test]
 fn test_numeric_not_equals_known_attribute() {
     let schema = prepopulated_typed_schema(ValueType::Long);
@@ -602,6 +801,101 @@ fn test_complex_or_join_type_projection() {
     assert_eq!(args, vec![]);
 }
 
+#[test]
+fn test_disjoint_or_join_union_all() {
+    let mut schema = Schema::default();
+    associate_ident(&mut schema, Keyword::namespaced("page", "title"), 98);
+    add_attribute(&mut schema, 98, Attribute {
+        value_type: ValueType::String,
+        ..Default::default()
+    });
+    associate_ident(&mut schema, Keyword::namespaced("page", "url"), 97);
+    add_attribute(&mut schema, 97, Attribute {
+        value_type: ValueType::String,
+        ..Default::default()
+    });
+
+    // Each arm pins ?kind to a different constant, so no row can come from both arms.
+    let query = r#"[:find ?page ?kind
+                    :where
+                    (or-join [?page ?kind]
+                      (and [?page :page/title _] [(ground :page/title) ?kind])
+                      (and [?page :page/url _] [(ground :page/url) ?kind]))]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `c00`.`?page` AS `?page`, `c00`.`?kind` AS `?kind` \
+                     FROM (SELECT $v0 AS `?kind`, `datoms00`.e AS `?page` \
+                           FROM `datoms` AS `datoms00` \
+                           WHERE `datoms00`.a = 98 \
+                           UNION ALL \
+                           SELECT $v1 AS `?kind`, `datoms01`.e AS `?page` \
+                           FROM `datoms` AS `datoms01` \
+                           WHERE `datoms01`.a = 97) AS `c00`");
+
+    // Without ?kind the arms can match the same page, so we still need to remove duplicates.
+    let query = r#"[:find ?page
+                    :where
+                    (or-join [?page]
+                      (and [?page :page/title _] [(ground :page/title) ?kind])
+                      (and [?page :page/url _] [(ground :page/url) ?kind]))]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `c00`.`?page` AS `?page` \
+                     FROM (SELECT `datoms00`.e AS `?page` \
+                           FROM `datoms` AS `datoms00` \
+                           WHERE `datoms00`.a = 98 \
+                           UNION \
+                           SELECT `datoms01`.e AS `?page` \
+                           FROM `datoms` AS `datoms01` \
+                           WHERE `datoms01`.a = 97) AS `c00`");
+}
+
+#[test]
+fn test_repeated_union_is_shared() {
+    let mut schema = Schema::default();
+    associate_ident(&mut schema, Keyword::namespaced("page", "title"), 98);
+    add_attribute(&mut schema, 98, Attribute {
+        value_type: ValueType::String,
+        ..Default::default()
+    });
+    associate_ident(&mut schema, Keyword::namespaced("page", "url"), 97);
+    add_attribute(&mut schema, 97, Attribute {
+        value_type: ValueType::String,
+        ..Default::default()
+    });
+
+    // The two or-joins differ only in their variables, so the union is defined once.
+    let query = r#"[:find ?page ?other
+                    :where
+                    (or-join [?page]
+                      (and [?page :page/title _] [(ground :page/title) ?kind])
+                      (and [?page :page/url _] [(ground :page/url) ?kind]))
+                    (or-join [?other]
+                      (and [?other :page/title _] [(ground :page/title) ?kind])
+                      (and [?other :page/url _] [(ground :page/url) ?kind]))]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(sql, "WITH `shared00` AS (SELECT `datoms00`.e AS `?page` \
+                                          FROM `datoms` AS `datoms00` \
+                                          WHERE `datoms00`.a = 98 \
+                                          UNION \
+                                          SELECT `datoms01`.e AS `?page` \
+                                          FROM `datoms` AS `datoms01` \
+                                          WHERE `datoms01`.a = 97) \
+                     SELECT DISTINCT `c00`.`?page` AS `?page`, `c01`.`?other` AS `?other` \
+                     FROM `shared00` AS `c00`, \
+                          (SELECT `?page` AS `?other` FROM `shared00`) AS `c01`");
+
+    // Unions that match different attributes aren't shared.
+    let query = r#"[:find ?page ?other
+                    :where
+                    (or-join [?page]
+                      (and [?page :page/title _] [(ground :page/title) ?kind])
+                      (and [?page :page/url _] [(ground :page/url) ?kind]))
+                    (or-join [?other]
+                      (and [?other :page/title _] [(ground :page/title) ?kind])
+                      (and [?other :page/title _] [(ground :page/url) ?kind]))]"#;
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert!(!sql.contains("WITH"));
+}
+
 #[test]
 fn test_not() {
     let mut schema = Schema::default();
@@ -657,6 +951,45 @@ fn test_not_join() {
     assert_eq!(args, vec![make_arg("$v0", "4/4/2017")]);
 }
 
+#[test]
+fn test_existential_patterns() {
+    let mut schema = Schema::default();
+    associate_ident(&mut schema, Keyword::namespaced("foo", "name"), 65);
+    associate_ident(&mut schema, Keyword::namespaced("foo", "knows"), 66);
+    associate_ident(&mut schema, Keyword::namespaced("foo", "age"), 67);
+    add_attribute(&mut schema, 65, Attribute {
+        value_type: ValueType::String,
+        ..Default::default()
+    });
+    add_attribute(&mut schema, 66, Attribute {
+        value_type: ValueType::Ref,
+        multival: true,
+        ..Default::default()
+    });
+    add_attribute(&mut schema, 67, Attribute {
+        value_type: ValueType::Long,
+        ..Default::default()
+    });
+
+    // ?y is only there to check that ?x knows someone who is thirty.
+    let query = r#"[:find ?x
+                    :where [?x :foo/name ?name]
+                           [?x :foo/knows ?y]
+                           [?y :foo/age 30]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 65 AND EXISTS (SELECT 1 FROM `datoms` AS `datoms01`, `datoms` AS `datoms02` WHERE `datoms01`.a = 66 AND `datoms02`.a = 67 AND `datoms02`.v = 30 AND `datoms00`.e = `datoms01`.e AND `datoms01`.v = `datoms02`.e)");
+    assert_eq!(args, vec![]);
+
+    // Projecting ?y means we need every row of the join.
+    let query = r#"[:find ?x ?y
+                    :where [?x :foo/name ?name]
+                           [?x :foo/knows ?y]
+                           [?y :foo/age 30]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, `datoms01`.v AS `?y` FROM `datoms` AS `datoms00`, `datoms` AS `datoms01`, `datoms` AS `datoms02` WHERE `datoms00`.a = 65 AND `datoms01`.a = 66 AND `datoms02`.a = 67 AND `datoms02`.v = 30 AND `datoms00`.e = `datoms01`.e AND `datoms01`.v = `datoms02`.e");
+    assert_eq!(args, vec![]);
+}
+
 #[test]
 fn test_with_without_aggregate() {
     let schema = prepopulated_schema();
@@ -697,6 +1030,80 @@ fn test_order_by() {
     assert_eq!(args, vec![]);
 }
 
+#[test]
+fn test_large_values() {
+    let schema = prepopulated_schema();
+
+    // Once strings might have been offloaded, we match them by their text…
+    let query = r#"[:find ?x :where [?x :foo/bar "yyy"]]"#;
+    let SQLQuery { sql, args } = translate_with_large_values(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 \
+                       AND `datoms00`.v IN ($v0, (SELECT id FROM large_values WHERE text = $v0 AND typeof($v0) = 'text'))");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+
+    // … run predicates over their text…
+    let query = r#"[:find ?x :where [?x :foo/bar ?y] [(str-includes? ?y "b")]]"#;
+    let SQLQuery { sql, .. } = translate_with_large_values(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 \
+                       AND CASE WHEN `datoms00`.value_type_tag = 10 AND typeof(`datoms00`.v) = 'integer' THEN (SELECT text FROM large_values WHERE id = `datoms00`.v) ELSE `datoms00`.v END GLOB $v0");
+
+    // … and order by it.
+    let query = r#"[:find ?x :where [?x :foo/bar ?y] :order (desc ?y)]"#;
+    let SQLQuery { sql, .. } = translate_with_large_values(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, \
+                                     CASE WHEN `datoms00`.value_type_tag = 10 AND typeof(`datoms00`.v) = 'integer' \
+                                     THEN (SELECT text FROM large_values WHERE id = `datoms00`.v) \
+                                     ELSE `datoms00`.v END AS `?y` \
+                     FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 \
+                     ORDER BY `?y` DESC");
+
+    // Fulltext values are never offloaded.
+    let query = r#"[:find ?x :where [?x :foo/fts ?y] :order ?y]"#;
+    let SQLQuery { sql, .. } = translate_with_large_values(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `fulltext_datoms00`.e AS `?x`, `fulltext_datoms00`.v AS `?y` \
+                     FROM `fulltext_datoms` AS `fulltext_datoms00` \
+                     WHERE `fulltext_datoms00`.a = 100 \
+                     ORDER BY `?y` ASC");
+
+    // Nor is anything that isn't a string.
+    let schema = prepopulated_typed_schema(ValueType::Long);
+    let query = r#"[:find ?x :where [?x :foo/bar 5]]"#;
+    let SQLQuery { sql, .. } = translate_with_large_values(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 AND `datoms00`.v = 5");
+}
+
+#[test]
+fn test_order_by_expression() {
+    let schema = prepopulated_typed_schema(ValueType::Long);
+
+    // Arithmetic refers to the projected variables by name.
+    let query = r#"[:find ?x :where [?x :foo/bar ?y] :order (desc (* ?y 2))]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, `datoms00`.v AS `?y` \
+                     FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 \
+                     ORDER BY (`?y` * 2) DESC");
+    assert_eq!(args, vec![]);
+
+    // An aggregate orders the outer query, by its projected name.
+    let query = r#"[:find ?x (count ?y) :where [?x :foo/bar ?y] :order (desc (count ?y)) ?x]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT `?x` AS `?x`, count(`?y`) AS `(count ?y)` \
+                     FROM \
+                     (SELECT DISTINCT \
+                      `datoms00`.e AS `?x`, \
+                      `datoms00`.v AS `?y` \
+                      FROM `datoms` AS `datoms00` \
+                      WHERE `datoms00`.a = 99) \
+                     GROUP BY `?x` \
+                     ORDER BY `(count ?y)` DESC, `?x` ASC");
+    assert_eq!(args, vec![]);
+}
+
 #[test]
 fn test_complex_nested_or_join_type_projection() {
     let mut schema = Schema::default();
@@ -715,15 +1122,10 @@ fn test_complex_nested_or_join_type_projection() {
                         [_ :page/title ?y]))]"#;
 
     let SQLQuery { sql, args } = translate(&schema, input);
-    assert_eq!(sql, "SELECT `c00`.`?y` AS `?y` \
-                     FROM (SELECT `datoms00`.v AS `?y` \
-                           FROM `datoms` AS `datoms00` \
-                           WHERE `datoms00`.a = 98 \
-                           UNION \
-                           SELECT `datoms01`.v AS `?y` \
-                           FROM `datoms` AS `datoms01` \
-                           WHERE `datoms01`.a = 98) \
-                           AS `c00` \
+    // The two arms are identical, so only one survives, and there's no union at all.
+    assert_eq!(sql, "SELECT `datoms00`.v AS `?y` \
+                     FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 98 \
                      LIMIT 1");
     assert_eq!(args, vec![]);
 }
@@ -835,7 +1237,7 @@ fn test_compound_with_ground() {
     // arm of the `or` rather than numbered globally.  But SQLite scopes the names correctly, so it
     // works.  In the future, we might number the computed tables globally to make this more clear.
     assert_eq!(sql, "SELECT DISTINCT `c00`.`?x` AS `?x` FROM (\
-                         SELECT $v0 AS `?x` UNION \
+                         SELECT $v0 AS `?x` UNION ALL \
                          SELECT $v1 AS `?x`) AS `c00`");
     assert_eq!(args, vec![make_arg("$v0", "yyy"),
                           make_arg("$v1", "zzz"),]);
@@ -924,7 +1326,7 @@ fn test_fulltext() {
     assert_eq!(sql, "SELECT DISTINCT `datoms01`.e AS `?entity`, \
                                      `fulltext_values00`.text AS `?value`, \
                                      `datoms01`.tx AS `?tx`, \
-                                     0e0 AS `?score` \
+                                     mentat_fulltext_score(matchinfo(`fulltext_values00`.fulltext_values, 'pcnalx')) AS `?score` \
                      FROM `fulltext_values` AS `fulltext_values00`, \
                           `datoms` AS `datoms01` \
                      WHERE `datoms01`.a = 100 \
@@ -970,8 +1372,8 @@ fn test_fulltext() {
                        AND `datoms01`.v = `fulltext_values00`.rowid \
                        AND `fulltext_values00`.text MATCH $v0 \
                        AND `datoms02`.a = 99 \
-                       AND `datoms02`.v = 0e0 \
-                       AND `datoms01`.e = `datoms02`.e");
+                       AND `datoms01`.e = `datoms02`.e \
+                       AND mentat_fulltext_score(matchinfo(`fulltext_values00`.fulltext_values, 'pcnalx')) = `datoms02`.v");
     assert_eq!(args, vec![make_arg("$v0", "needle"),]);
 
     let query = r#"[:find ?entity ?value ?tx :where [?entity :foo/bar ?score] [(fulltext $ :foo/fts "needle") [[?entity ?value ?tx ?score]]]]"#;
@@ -986,11 +1388,45 @@ fn test_fulltext() {
                        AND `datoms02`.a = 100 \
                        AND `datoms02`.v = `fulltext_values01`.rowid \
                        AND `fulltext_values01`.text MATCH $v0 \
-                       AND `datoms00`.v = 0e0 \
-                       AND `datoms00`.e = `datoms02`.e");
+                       AND `datoms00`.e = `datoms02`.e \
+                       AND `datoms00`.v = mentat_fulltext_score(matchinfo(`fulltext_values01`.fulltext_values, 'pcnalx'))");
     assert_eq!(args, vec![make_arg("$v0", "needle"),]);
 }
 
+#[test]
+fn test_collection_and_relation_inputs() {
+    let schema = prepopulated_schema();
+
+    let query = r#"[:find ?x :in [?v ...] :where [?x :foo/bar ?v]]"#;
+    let inputs = QueryInputs::default()
+        .with_collection(Variable::from_valid_name("?v"),
+                         vec![TypedValue::typed_string("yyy"), TypedValue::typed_string("zzz")]);
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM (SELECT 0 AS `?v` WHERE 0 UNION ALL VALUES ($v0), ($v1)) AS `c00`, \
+                     `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 AND `c00`.`?v` = `datoms00`.v");
+    assert_eq!(args, vec![make_arg("$v0", "yyy"), make_arg("$v1", "zzz")]);
+
+    let query = r#"[:find ?x ?n :in ?a [[?v ?n]] :where [?x :foo/bar ?v]]"#;
+    let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?a"), TypedValue::Long(1))])
+        .with_relation(vec![Variable::from_valid_name("?v"), Variable::from_valid_name("?n")],
+                       vec![vec![TypedValue::typed_string("yyy"), TypedValue::Long(1)],
+                            vec![TypedValue::typed_string("zzz"), TypedValue::Long(2)]]);
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, `c00`.`?n` AS `?n` \
+                     FROM (SELECT 0 AS `?v`, 0 AS `?n` WHERE 0 UNION ALL VALUES ($v0, 1), ($v1, 2)) AS `c00`, \
+                     `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 AND `c00`.`?v` = `datoms00`.v");
+    assert_eq!(args, vec![make_arg("$v0", "yyy"), make_arg("$v1", "zzz")]);
+
+    // An empty collection matches nothing.
+    let query = r#"[:find ?x :in [?v ...] :where [?x :foo/bar ?v]]"#;
+    let inputs = QueryInputs::default().with_collection(Variable::from_valid_name("?v"), vec![]);
+    assert_query_is_empty(inner_translate_with_inputs(&schema, query, inputs),
+                          FindSpec::FindRel(vec![var!(?x).into()]));
+}
+
 #[test]
 fn test_fulltext_inputs() {
     let schema = prepopulated_typed_schema(ValueType::String);
@@ -1003,7 +1439,7 @@ fn test_fulltext_inputs() {
     types.insert(Variable::from_valid_name("?entity"), ValueType::Ref);
     let inputs = QueryInputs::new(types, BTreeMap::default()).expect("valid inputs");
 
-    // Without binding the value. q_once will err if you try this!
+    // Without binding the value, it's a parameter. q_once will err if you try this!
     let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs);
     assert_eq!(sql, "SELECT DISTINCT `fulltext_values00`.text AS `?val` \
                      FROM \
@@ -1011,7 +1447,8 @@ fn test_fulltext_inputs() {
                      `datoms` AS `datoms01` \
                      WHERE `datoms01`.a = 100 \
                        AND `datoms01`.v = `fulltext_values00`.rowid \
-                       AND `fulltext_values00`.text MATCH $v0");
+                       AND `fulltext_values00`.text MATCH $v0 \
+                       AND `datoms01`.e = $ientity");
     assert_eq!(args, vec![make_arg("$v0", "hello"),]);
 
     // With the value bound.
@@ -1209,6 +1646,25 @@ fn test_project_the() {
                        WHERE `datoms00`.a = 99)) \
                      WHERE `(max ?t)` IS NOT NULL");
     assert_eq!(args, vec![]);
+
+    // Ordering by (the) doesn't group by it.
+    let query = r#"[:find (the ?e) (max ?t)
+                    :where
+                    [?e :foo/bar ?t]
+                    :order ?e]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT * \
+                     FROM \
+                     (SELECT `?e` AS `?e`, max(`?t`) AS `(max ?t)` \
+                      FROM \
+                      (SELECT DISTINCT \
+                       `datoms00`.e AS `?e`, \
+                       `datoms00`.v AS `?t` \
+                       FROM `datoms` AS `datoms00` \
+                       WHERE `datoms00`.a = 99)) \
+                     WHERE `(max ?t)` IS NOT NULL \
+                     ORDER BY `?e` ASC");
+    assert_eq!(args, vec![]);
 }
 
 #[test]
@@ -1265,6 +1721,18 @@ fn test_tx_ids() {
                      AND `transactions02`.tx < `datoms01`.e");
     assert_eq!(args, vec![]);
 
+    // Instant bounds are compared with each transaction's own `:db/txInstant`, and the upper bound
+    // can be omitted.
+    let query = r#"[:find ?tx :where [(tx-ids $ #inst "2016-01-01T11:00:00.000Z") [?tx ...]]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `transactions00`.tx AS `?tx` \
+                     FROM `transactions` AS `transactions00`, \
+                     `datoms` AS `datoms01` \
+                     WHERE `datoms01`.e = `transactions00`.tx \
+                     AND `datoms01`.a = 101 \
+                     AND 1451646000000000 <= `datoms01`.v");
+    assert_eq!(args, vec![]);
+
     // In practice the following query would be inefficient because of the filter on all_datoms.tx,
     // but that is what (tx-data) is for.
     let query = r#"[:find ?e ?a ?v ?tx :where [(tx-ids $ 1000 2000) [[?tx]]] [?e ?a ?v ?tx]]"#;
@@ -1329,3 +1797,127 @@ fn test_tx_data() {
                      AND `transactions01`.tx = `transactions00`.tx");
     assert_eq!(args, vec![]);
 }
+
+#[test]
+fn test_conversion() {
+    let schema = Schema::default();
+
+    // Only numbers and instants are converted.
+    let SQLQuery { sql, .. } = translate(&schema, "[:find ?n :where [?e _ ?v] [(long ?v) ?n]]");
+    assert!(sql.starts_with("SELECT DISTINCT CAST(`all_datoms00`.v AS INTEGER) AS `?n` \
+                             FROM `all_datoms` AS `all_datoms00` WHERE "), "{}", sql);
+    assert!(sql.contains("`all_datoms00`.value_type_tag = 4"), "{}", sql);
+    assert!(sql.contains("`all_datoms00`.value_type_tag = 5"), "{}", sql);
+}
+
+#[test]
+fn test_get_else() {
+    let schema = prepopulated_typed_schema(ValueType::Long);
+
+    let query = r#"[:find ?x ?v :where [?x :foo/fts _] [(get-else $ ?x :foo/bar 0) ?v]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, \
+                     COALESCE(`datoms01`.v, 0) AS `?v` \
+                     FROM `datoms` AS `datoms00` \
+                     LEFT JOIN `datoms` AS `datoms01` ON `datoms01`.e = `datoms00`.e AND `datoms01`.a = 99 \
+                     WHERE `datoms00`.a = 100");
+    assert_eq!(args, vec![]);
+
+    // A default of another type needs its type tag.
+    let query = r#"[:find ?x ?v :where [?x :foo/fts _] [(get-else $ ?x :foo/bar "none") ?v]]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, \
+                     COALESCE(`datoms01`.v, $v0) AS `?v`, \
+                     COALESCE(`datoms01`.value_type_tag, 10) AS `?v_value_type_tag` \
+                     FROM `datoms` AS `datoms00` \
+                     LEFT JOIN `datoms` AS `datoms01` ON `datoms01`.e = `datoms00`.e AND `datoms01`.a = 99 \
+                     WHERE `datoms00`.a = 100");
+    assert_eq!(args, vec![make_arg("$v0", "none")]);
+}
+
+#[test]
+fn test_optional() {
+    let schema = prepopulated_schema();
+
+    let query = r#"[:find ?x ?v :where [?x :foo/fts _] (optional [?x :foo/bar ?v])]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, `c00`.`?v` AS `?v` \
+                     FROM `datoms` AS `datoms00` \
+                     LEFT JOIN (SELECT `datoms01`.v AS `?v`, `datoms01`.e AS `?x` \
+                                FROM `datoms` AS `datoms01` \
+                                WHERE `datoms01`.a = 99) AS `c00` \
+                     ON `c00`.`?x` = `datoms00`.e \
+                     WHERE `datoms00`.a = 100");
+    assert_eq!(args, vec![]);
+
+    // Clauses that can't match leave the variable absent.
+    let query = r#"[:find ?x ?v :where [?x :foo/fts _] (optional [?x :foo/missing ?v])]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, NULL AS `?v`, NULL AS `?v_value_type_tag` \
+                     FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 100");
+    assert_eq!(args, vec![]);
+}
+
+#[test]
+fn test_join_order_follows_statistics() {
+    let mut schema = prepopulated_schema();
+    associate_ident(&mut schema, Keyword::namespaced("foo", "baz"), 101);
+    add_attribute(&mut schema, 101, Attribute {
+        value_type: ValueType::Long,
+        ..Default::default()
+    });
+
+    let query = r#"[:find ?x :where [?x :foo/bar ?y] [?x :foo/baz 5]]"#;
+
+    // Without statistics, patterns are joined in the order they're written.
+    let SQLQuery { sql, .. } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM `datoms` AS `datoms00`, `datoms` AS `datoms01` \
+                     WHERE `datoms00`.a = 99 AND `datoms01`.a = 101 AND `datoms01`.v = 5 \
+                     AND `datoms00`.e = `datoms01`.e");
+
+    // With them, the attribute with fewer datoms comes first.
+    let statistics = Statistics::new(vec![(99, 1000), (101, 10)].into_iter().collect());
+    let known = Known::for_schema(&schema).with_statistics(&statistics);
+    let parsed = parse_find_string(query).expect("parse to succeed");
+    let algebrized = algebrize(known, parsed).expect("algebrize to succeed");
+    let SQLQuery { sql, .. } = query_to_sql(query_to_select(&schema, algebrized).expect("translate to succeed"));
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM `datoms` AS `datoms00`, `datoms` AS `datoms01` \
+                     WHERE `datoms00`.a = 101 AND `datoms00`.v = 5 AND `datoms01`.a = 99 \
+                     AND `datoms00`.e = `datoms01`.e");
+}
+
+#[test]
+fn test_attribute_index_hints() {
+    let schema = prepopulated_schema();
+    let mut indexes = AttributeIndexes::default();
+    indexes.insert(99, IndexDirection::Forward);
+
+    let translate_with_indexes = |query: &str, indexes: &AttributeIndexes| {
+        let known = Known::for_schema(&schema).with_indexes(indexes);
+        let parsed = parse_find_string(query).expect("parse to succeed");
+        let algebrized = algebrize(known, parsed).expect("algebrize to succeed");
+        query_to_sql(query_to_select(&schema, algebrized).expect("translate to succeed")).sql
+    };
+
+    // Patterns that use an indexed attribute read from its index.
+    let sql = translate_with_indexes(r#"[:find ?x ?y :where [?x :foo/bar ?y]]"#, &indexes);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x`, `datoms00`.v AS `?y` \
+                     FROM `datoms` AS `datoms00` INDEXED BY `idx_datoms_attribute_99_aev` \
+                     WHERE `datoms00`.a = 99");
+
+    // With a known value, we prefer the index that starts with the value.
+    indexes.insert(99, IndexDirection::Reverse);
+    let sql = translate_with_indexes(r#"[:find ?x :where [?x :foo/bar "yyy"]]"#, &indexes);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM `datoms` AS `datoms00` INDEXED BY `idx_datoms_attribute_99_ave` \
+                     WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0");
+
+    // Patterns with an unknown attribute don't.
+    let sql = translate_with_indexes(r#"[:find ?x :where [?x _ "yyy"]]"#, &indexes);
+    assert_eq!(sql, "SELECT DISTINCT `all_datoms00`.e AS `?x` \
+                     FROM `all_datoms` AS `all_datoms00` \
+                     WHERE `all_datoms00`.v = $v0 AND (`all_datoms00`.value_type_tag = 10)");
+}
@@ -64,6 +64,7 @@ extern crate edn;
 extern crate mentat_core;
 extern crate core_traits;
 extern crate mentat_db;
+extern crate db_traits;
 extern crate query_pull_traits;
 
 use std::collections::{
@@ -83,6 +84,7 @@ use core_traits::{
 };
 
 use mentat_core::{
+    CachedAttributes,
     Cloned,
     HasSchema,
     Keyword,
@@ -92,6 +94,15 @@ use mentat_core::{
 
 use mentat_db::cache;
 
+use mentat_db::{
+    TypedSQLValue,
+    resolved_value_sql,
+};
+
+use db_traits::errors::{
+    DbError,
+};
+
 use edn::query::{
     NamedPullAttribute,
     PullAttributeSpec,
@@ -137,6 +148,95 @@ pub fn pull_attributes_for_entities<E, A>(schema: &Schema,
         .pull(schema, db, entities)
 }
 
+/// Everything asserted about a single entity.  Each attribute maps to its value or, for
+/// cardinality-many attributes, to a `Binding::Vec` of its values.  Refs are entids.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Entity {
+    pub entid: Entid,
+    pub attributes: StructuredMap,
+}
+
+impl Entity {
+    pub fn get(&self, attribute: &Keyword) -> Option<&Binding> {
+        self.attributes.get(attribute)
+    }
+}
+
+/// Fetch every attribute of `entity`, or `None` if nothing is asserted about it.
+///
+/// If every attribute in the schema is cached then the entity is built from `cache`; otherwise
+/// it's read with a single query.
+pub fn lookup_entity(schema: &Schema,
+                     db: &rusqlite::Connection,
+                     cache: Option<&CachedAttributes>,
+                     entity: Entid) -> Result<Option<Entity>> {
+    let mut attributes = StructuredMap::default();
+
+    let fully_cached = cache.map_or(false, |cache| schema.attribute_map.keys().all(|a| cache.is_attribute_cached_forward(*a)));
+    if fully_cached {
+        let cache = cache.unwrap();
+        for (a, attribute) in schema.attribute_map.iter() {
+            let binding = if attribute.multival {
+                cache.get_values_for_entid(schema, *a, entity)
+                     .filter(|vs| !vs.is_empty())
+                     .map(|vs| Binding::Vec(ValueRc::new(vs.iter().cloned().map(|v| v.into()).collect())))
+            } else {
+                cache.get_value_for_entid(schema, *a, entity)
+                     .map(|v| v.clone().into())
+            };
+            if let Some(binding) = binding {
+                attributes.insert(attribute_name(schema, *a)?, binding);
+            }
+        }
+    } else {
+        let sql = format!("SELECT a, {}, value_type_tag FROM all_datoms WHERE e = ? ORDER BY a, value_type_tag, v",
+                          resolved_value_sql("all_datoms"));
+        let mut stmt = db.prepare_cached(&sql).map_err(DbError::from)?;
+        let mut rows = stmt.query(&[&entity]).map_err(DbError::from)?;
+
+        // Rows arrive grouped by attribute.
+        let mut values: Vec<(Entid, Vec<Binding>)> = vec![];
+        while let Some(row) = rows.next() {
+            let row = row.map_err(DbError::from)?;
+            let a: Entid = row.get_checked(0).map_err(DbError::from)?;
+            let v = TypedValue::from_sql_value_pair(row.get_checked(1).map_err(DbError::from)?,
+                                                    row.get_checked(2).map_err(DbError::from)?)?;
+            if values.last().map_or(false, |&(last, _)| last == a) {
+                values.last_mut().unwrap().1.push(v.into());
+            } else {
+                values.push((a, vec![v.into()]));
+            }
+        }
+        for (a, vs) in values {
+            attributes.insert(attribute_name(schema, a)?, attribute_binding(schema, a, vs));
+        }
+    }
+
+    if attributes.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(Entity {
+            entid: entity,
+            attributes,
+        }))
+    }
+}
+
+fn attribute_name(schema: &Schema, a: Entid) -> Result<ValueRc<Keyword>> {
+    schema.get_ident(a)
+          .map(|ident| ValueRc::new(ident.clone()))
+          .ok_or_else(|| PullError::UnnamedAttribute(a))
+}
+
+fn attribute_binding(schema: &Schema, a: Entid, mut vs: Vec<Binding>) -> Binding {
+    let multival = schema.attribute_for_entid(a).map_or(false, |attribute| attribute.multival);
+    if multival || vs.len() != 1 {
+        Binding::Vec(ValueRc::new(vs))
+    } else {
+        vs.pop().unwrap()
+    }
+}
+
 /// A `Puller` constructs on demand a map from a provided set of entity IDs to a set of structured maps.
 pub struct Puller {
     // The domain of this map is the set of attributes to fetch.
@@ -223,6 +323,87 @@ impl Puller {
                    db: &rusqlite::Connection,
                    entities: E) -> Result<PullResults>
         where E: IntoIterator<Item=Entid> {
+        let entities: Vec<Entid> = entities.into_iter().collect();
+        let seen = entities.iter().cloned().collect();
+        let mut maps = self.pull_without_components(schema, db, entities)?;
+        self.inline_components(schema, db, &mut maps, seen)?;
+        Ok(maps)
+    }
+
+    /// Component entities are part of the entities that refer to them, so, like Datomic, we
+    /// replace each reference through a component attribute with a map of everything about the
+    /// component -- recursively, but not into any entity in `seen`, which would never end.
+    fn inline_components(&self,
+                         schema: &Schema,
+                         db: &rusqlite::Connection,
+                         maps: &mut PullResults,
+                         seen: BTreeSet<Entid>) -> Result<()> {
+        let names: Vec<&ValueRc<Keyword>> = self.attributes
+                                                .iter()
+                                                .filter(|&(a, _)| schema.component_attributes.binary_search(a).is_ok())
+                                                .map(|(_, name)| name)
+                                                .collect();
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        let mut components: BTreeSet<Entid> = BTreeSet::new();
+        for map in maps.values() {
+            for name in names.iter() {
+                match map.get(*name) {
+                    Some(&Binding::Scalar(TypedValue::Ref(c))) => {
+                        components.insert(c);
+                    },
+                    Some(&Binding::Vec(ref vs)) => {
+                        components.extend(vs.iter().filter_map(|v| match v {
+                            &Binding::Scalar(TypedValue::Ref(c)) => Some(c),
+                            _ => None,
+                        }));
+                    },
+                    _ => {},
+                }
+            }
+        }
+        let components: Vec<Entid> = components.difference(&seen).cloned().collect();
+        if components.is_empty() {
+            return Ok(());
+        }
+
+        let wildcard = Puller::prepare(schema, vec![PullAttributeSpec::Wildcard])?;
+        let seen = seen.into_iter().chain(components.iter().cloned()).collect();
+        let mut pulled = wildcard.pull_without_components(schema, db, components)?;
+        wildcard.inline_components(schema, db, &mut pulled, seen)?;
+
+        let inline = |binding: &mut Binding| {
+            let component = match binding {
+                &mut Binding::Scalar(TypedValue::Ref(c)) => pulled.get(&c).cloned(),
+                _ => None,
+            };
+            if let Some(component) = component {
+                *binding = Binding::Map(component);
+            }
+        };
+        for map in maps.values_mut() {
+            let map = ValueRc::make_mut(map);
+            for name in names.iter() {
+                match map.0.get_mut(*name) {
+                    Some(&mut Binding::Vec(ref mut vs)) => {
+                        for v in ValueRc::make_mut(vs).iter_mut() {
+                            inline(v);
+                        }
+                    },
+                    Some(binding) => inline(binding),
+                    None => {},
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn pull_without_components(&self,
+                               schema: &Schema,
+                               db: &rusqlite::Connection,
+                               entities: Vec<Entid>) -> Result<PullResults> {
         // We implement pull by:
         // - Generating `AttributeCaches` for the provided attributes and entities.
         //   TODO: it would be nice to invert the cache as we build it, rather than have to invert it here.
@@ -233,7 +414,6 @@ impl Puller {
 
         // Build a cache for these attributes and entities.
         // TODO: use the store's existing cache!
-        let entities: Vec<Entid> = entities.into_iter().collect();
         let caches = cache::AttributeCaches::make_cache_for_entities_and_attributes(
             schema,
             db,
@@ -16,6 +16,7 @@ extern crate mentat_query_algebrizer;
 extern crate mentat_sql;
 
 use std::boxed::Box;
+use std::collections::HashMap;
 
 use core_traits::{
     Entid,
@@ -30,12 +31,17 @@ use mentat_core::{
 use edn::query::{
     Direction,
     Limit,
+    Offset,
     Variable,
 };
 
 use mentat_query_algebrizer::{
+    ArithmeticOperand,
     Column,
+    ColumnName,
+    FulltextColumn,
     OrderBy,
+    OrderColumn,
     QualifiedAlias,
     QueryValue,
     SourceAlias,
@@ -51,6 +57,7 @@ use sql_traits::errors::{
 use mentat_sql::{
     QueryBuilder,
     QueryFragment,
+    SQLArg,
     SQLiteQueryBuilder,
     SQLQuery,
 };
@@ -71,10 +78,17 @@ pub enum ColumnOrExpression {
     Integer(i32),       // We use these for type codes etc.
     Long(i64),
     Value(TypedValue),
+    Parameter(Variable),    // An `:in` variable bound when the query is run.
     // Some aggregates (`min`, `max`, `avg`) can be over 0 rows, and therefore can be `NULL`; that
     // needs special treatment.
     NullableAggregate(Box<Expression>, ValueType),      // Track the return type.
     Expression(Box<Expression>, ValueType),             // Track the return type.
+    // The id of the offloaded string the expression names, or `NULL` if it hasn't been offloaded
+    // or isn't a string.
+    LargeValueId(Box<ColumnOrExpression>),
+    // A datoms `v` column with offloaded strings replaced by their text, for ordering and string
+    // functions.  Its table must have a `value_type_tag` column.
+    ResolvedValue(QualifiedAlias),
 }
 
 pub enum Expression {
@@ -89,6 +103,7 @@ impl From<QueryValue> for ColumnOrExpression {
             QueryValue::Entid(e) => ColumnOrExpression::Entid(e),
             QueryValue::PrimitiveLong(v) => ColumnOrExpression::Long(v),
             QueryValue::TypedValue(v) => ColumnOrExpression::Value(v),
+            QueryValue::Parameter(var) => ColumnOrExpression::Parameter(var),
         }
     }
 }
@@ -151,6 +166,9 @@ pub enum Constraint {
     NotExists {
         subquery: TableOrSubquery,
     },
+    Exists {
+        subquery: TableOrSubquery,
+    },
     TypeCheck {
         value: ColumnOrExpression,
         affinity: SQLTypeAffinity
@@ -207,9 +225,61 @@ pub struct Join {
 #[allow(dead_code)]
 pub enum TableOrSubquery {
     Table(SourceAlias),
+
+    /// Like "table AS alias INDEXED BY index": a table read using the named index.
+    IndexedTable(SourceAlias, String),
     Union(Vec<SelectQuery>, TableAlias),
+
+    /// Like `Union`, but with `UNION ALL`, which doesn't remove duplicate rows.
+    UnionAll(Vec<SelectQuery>, TableAlias),
+
+    /// Like "(WITH RECURSIVE name AS (base UNION recursive) SELECT * FROM name) AS alias".
+    /// The last query is the only one that may refer to `name`.
+    RecursiveUnion(String, Vec<SelectQuery>, TableAlias),
+
+    /// A reference to the table named by an enclosing `RecursiveUnion` or `WITH` clause.
+    Reference(String, TableAlias),
+
+    /// Like "(SELECT `a` AS `b`, ... FROM name) AS alias": a reference to the table named by an
+    /// enclosing `WITH` clause, with its columns renamed.
+    RenamedReference(String, Vec<(Name, Name)>, TableAlias),
     Subquery(Box<SelectQuery>),
     Values(Values, TableAlias),
+
+    /// Like "table ON constraint AND ...": a table that's `LEFT JOIN`ed to the tables before it in
+    /// a `TableList`.
+    LeftJoin(Box<TableOrSubquery>, Vec<Constraint>),
+}
+
+/// A table defined in a `WITH` clause, like "WITH name(column, ...) AS (arm UNION arm)".
+/// A single arm is just a subquery.
+pub struct CommonTableExpression {
+    pub name: Name,
+
+    /// The names of the table's columns.  If empty, the columns take the names that the first arm
+    /// projects.
+    pub columns: Vec<Name>,
+
+    pub arms: Vec<SelectQuery>,
+
+    /// Whether the arms are joined with `UNION ALL` rather than `UNION`.
+    pub all: bool,
+
+    /// Whether the arms may refer to `name`.  Any recursive table makes the whole clause
+    /// `WITH RECURSIVE`.
+    pub recursive: bool,
+}
+
+impl CommonTableExpression {
+    pub fn new(name: Name, arms: Vec<SelectQuery>) -> CommonTableExpression {
+        CommonTableExpression {
+            name,
+            columns: vec![],
+            arms,
+            all: false,
+            recursive: false,
+        }
+    }
 }
 
 pub enum Values {
@@ -230,6 +300,7 @@ pub enum FromClause {
 }
 
 pub struct SelectQuery {
+    pub with: Vec<CommonTableExpression>,
     pub distinct: bool,
     pub projection: Projection,
     pub from: FromClause,
@@ -237,6 +308,7 @@ pub struct SelectQuery {
     pub group_by: Vec<GroupBy>,
     pub order: Vec<OrderBy>,
     pub limit: Limit,
+    pub offset: Offset,
 }
 
 fn push_variable_column(qb: &mut QueryBuilder, vc: &VariableColumn) -> BuildQueryResult {
@@ -250,6 +322,28 @@ fn push_variable_column(qb: &mut QueryBuilder, vc: &VariableColumn) -> BuildQuer
     }
 }
 
+fn push_order_column(qb: &mut QueryBuilder, column: &OrderColumn) -> BuildQueryResult {
+    match column {
+        &OrderColumn::Variable(ref vc) => push_variable_column(qb, vc),
+        &OrderColumn::Aggregate(ref aggregate) => {
+            qb.push_identifier(aggregate.column_name().as_str())
+        },
+        &OrderColumn::Arithmetic(op, ref operands) => {
+            qb.push_sql("(");
+            interpose!(operand, operands,
+                       { match operand {
+                             &ArithmeticOperand::Variable(ref var) => qb.push_identifier(var.as_str())?,
+                             &ArithmeticOperand::Value(ref value) => qb.push_typed_value(value)?,
+                         } },
+                       { qb.push_sql(" ");
+                         qb.push_sql(op.to_sql());
+                         qb.push_sql(" "); });
+            qb.push_sql(")");
+            Ok(())
+        },
+    }
+}
+
 fn push_column(qb: &mut QueryBuilder, col: &Column) -> BuildQueryResult {
     match col {
         &Column::Fixed(ref d) => {
@@ -265,6 +359,12 @@ fn push_column(qb: &mut QueryBuilder, col: &Column) -> BuildQueryResult {
             qb.push_sql(d.as_str());
             Ok(())
         },
+        &Column::Cast(_, _) => {
+            unreachable!("casts are qualified by their table")
+        },
+        &Column::Coalesce(_, _) => {
+            unreachable!("coalesced columns are qualified by their tables")
+        },
     }
 }
 
@@ -296,10 +396,35 @@ impl QueryFragment for ColumnOrExpression {
             &Value(ref v) => {
                 out.push_typed_value(v)
             },
+            &Parameter(ref var) => {
+                push_variable_param(var, out)
+            },
             &NullableAggregate(ref e, _) |
             &Expression(ref e, _) => {
                 e.push_sql(out)
             },
+            &LargeValueId(ref text) => {
+                // An input bound to a number would otherwise be compared as text.
+                out.push_sql("(SELECT id FROM large_values WHERE text = ");
+                text.push_sql(out)?;
+                out.push_sql(" AND typeof(");
+                text.push_sql(out)?;
+                out.push_sql(") = 'text')");
+                Ok(())
+            },
+            &ResolvedValue(ref qa) => {
+                let tag = qa.for_associated_type_tag().expect("a value column with a type tag");
+                out.push_sql("CASE WHEN ");
+                qualified_alias_push_sql(out, &tag)?;
+                out.push_sql(" = 10 AND typeof(");
+                qualified_alias_push_sql(out, qa)?;
+                out.push_sql(") = 'integer' THEN (SELECT text FROM large_values WHERE id = ");
+                qualified_alias_push_sql(out, qa)?;
+                out.push_sql(") ELSE ");
+                qualified_alias_push_sql(out, qa)?;
+                out.push_sql(" END");
+                Ok(())
+            },
         }
     }
 }
@@ -415,6 +540,10 @@ impl QueryFragment for Constraint {
                 out.push_sql("NOT EXISTS ");
                 subquery.push_sql(out)
             },
+            &Exists { ref subquery } => {
+                out.push_sql("EXISTS ");
+                subquery.push_sql(out)
+            },
             &TypeCheck { ref value, ref affinity } => {
                 out.push_sql("typeof(");
                 value.push_sql(out)?;
@@ -441,6 +570,49 @@ impl QueryFragment for JoinOp {
 
 // We don't own QualifiedAlias or QueryFragment, so we can't implement the trait.
 fn qualified_alias_push_sql(out: &mut QueryBuilder, qa: &QualifiedAlias) -> BuildQueryResult {
+    if qa.1 == Column::Fulltext(FulltextColumn::Score) {
+        // Scores aren't stored: they're computed for each match.  This function is registered on
+        // every connection by `mentat_db`.  `matchinfo` takes the FTS table's hidden column,
+        // which is named for the table, not its alias.
+        out.push_sql("mentat_fulltext_score(matchinfo(");
+        out.push_identifier(qa.0.as_str())?;
+        out.push_sql(".fulltext_values, 'pcnalx'))");
+        return Ok(());
+    }
+    if let Column::Cast(ref column, value_type) = qa.1 {
+        out.push_sql("CAST(");
+        qualified_alias_push_sql(out, &QualifiedAlias(qa.0.clone(), (**column).clone()))?;
+        match value_type {
+            ValueType::Double => out.push_sql(" AS REAL)"),
+            _ => out.push_sql(" AS INTEGER)"),
+        }
+        return Ok(());
+    }
+    if let Column::Coalesce(ref columns, ref default) = qa.1 {
+        // `COALESCE` needs at least two arguments.
+        if columns.is_empty() {
+            return match default {
+                &Some(ref value) => out.push_typed_value(value),
+                &None => {
+                    out.push_sql("NULL");
+                    Ok(())
+                },
+            };
+        }
+        if columns.len() == 1 && default.is_none() {
+            return qualified_alias_push_sql(out, &columns[0]);
+        }
+        out.push_sql("COALESCE(");
+        interpose!(column, columns,
+                   { qualified_alias_push_sql(out, column)? },
+                   { out.push_sql(", ") });
+        if let &Some(ref value) = default {
+            out.push_sql(", ");
+            out.push_typed_value(value)?;
+        }
+        out.push_sql(")");
+        return Ok(());
+    }
     out.push_identifier(qa.0.as_str())?;
     out.push_sql(".");
     push_column(out, &qa.1)
@@ -460,9 +632,15 @@ impl QueryFragment for TableList {
             return Ok(());
         }
 
-        interpose!(t, self.0,
-                   { t.push_sql(out)? },
-                   { out.push_sql(", ") });
+        for (i, t) in self.0.iter().enumerate() {
+            if i > 0 {
+                match t {
+                    &TableOrSubquery::LeftJoin(_, _) => out.push_sql(" LEFT JOIN "),
+                    _ => out.push_sql(", "),
+                }
+            }
+            t.push_sql(out)?;
+        }
         Ok(())
     }
 }
@@ -480,11 +658,53 @@ impl QueryFragment for TableOrSubquery {
         use self::TableOrSubquery::*;
         match self {
             &Table(ref sa) => source_alias_push_sql(out, sa),
+            &IndexedTable(ref sa, ref index) => {
+                source_alias_push_sql(out, sa)?;
+                out.push_sql(" INDEXED BY ");
+                out.push_identifier(index.as_str())
+            },
             &Union(ref subqueries, ref table_alias) => {
                 out.push_sql("(");
+                interpose!(subquery, subqueries,
+                           { push_compound_arm(subquery, out)? },
+                           { out.push_sql(" UNION ") });
+                out.push_sql(") AS ");
+                out.push_identifier(table_alias.as_str())
+            },
+            &UnionAll(ref subqueries, ref table_alias) => {
+                out.push_sql("(");
+                interpose!(subquery, subqueries,
+                           { push_compound_arm(subquery, out)? },
+                           { out.push_sql(" UNION ALL ") });
+                out.push_sql(") AS ");
+                out.push_identifier(table_alias.as_str())
+            },
+            &RecursiveUnion(ref name, ref subqueries, ref table_alias) => {
+                out.push_sql("(WITH RECURSIVE ");
+                out.push_identifier(name.as_str())?;
+                out.push_sql(" AS (");
                 interpose!(subquery, subqueries,
                            { subquery.push_sql(out)? },
                            { out.push_sql(" UNION ") });
+                out.push_sql(") SELECT * FROM ");
+                out.push_identifier(name.as_str())?;
+                out.push_sql(") AS ");
+                out.push_identifier(table_alias.as_str())
+            },
+            &Reference(ref name, ref table_alias) => {
+                out.push_identifier(name.as_str())?;
+                out.push_sql(" AS ");
+                out.push_identifier(table_alias.as_str())
+            },
+            &RenamedReference(ref name, ref columns, ref table_alias) => {
+                out.push_sql("(SELECT ");
+                interpose!(&(ref from, ref to), columns,
+                           { out.push_identifier(from.as_str())?;
+                             out.push_sql(" AS ");
+                             out.push_identifier(to.as_str())? },
+                           { out.push_sql(", ") });
+                out.push_sql(" FROM ");
+                out.push_identifier(name.as_str())?;
                 out.push_sql(") AS ");
                 out.push_identifier(table_alias.as_str())
             },
@@ -501,6 +721,19 @@ impl QueryFragment for TableOrSubquery {
                 out.push_sql(") AS ");
                 out.push_identifier(table_alias.as_str())
             },
+            &LeftJoin(ref table, ref constraints) => {
+                table.push_sql(out)?;
+                if constraints.is_empty() {
+                    // Every row matches.
+                    out.push_sql(" ON 1");
+                    return Ok(());
+                }
+                out.push_sql(" ON ");
+                interpose!(constraint, constraints,
+                           { constraint.push_sql(out)? },
+                           { out.push_sql(" AND ") });
+                Ok(())
+            },
         }
     }
 }
@@ -570,7 +803,7 @@ impl QueryFragment for FromClause {
 /// `var` is something like `?foo99-people`.
 /// Trim the `?` and escape the rest. Prepend `i` to distinguish from
 /// the inline value space `v`.
-fn format_select_var(var: &str) -> String {
+pub fn format_select_var(var: &str) -> String {
     use std::iter::once;
     let without_question = var.split_at(1).1;
     let replaced_iter = without_question.chars().map(|c|
@@ -579,15 +812,58 @@ fn format_select_var(var: &str) -> String {
     once('i').chain(replaced_iter).collect()
 }
 
-impl SelectQuery {
-    fn push_variable_param(&self, var: &Variable, out: &mut QueryBuilder) -> BuildQueryResult {
-        let bind_param = format_select_var(var.as_str());
-        out.push_bind_param(bind_param.as_str())
+fn push_variable_param(var: &Variable, out: &mut QueryBuilder) -> BuildQueryResult {
+    let bind_param = format_select_var(var.as_str());
+    out.push_bind_param(bind_param.as_str())
+}
+
+/// Push one arm of a compound `SELECT`.  SQLite only accepts a `WITH` clause at the start of the
+/// whole compound, so an arm with its own is wrapped in a subquery.
+fn push_compound_arm(arm: &SelectQuery, out: &mut QueryBuilder) -> BuildQueryResult {
+    if arm.with.is_empty() {
+        arm.push_sql(out)
+    } else {
+        out.push_sql("SELECT * FROM (");
+        arm.push_sql(out)?;
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+impl QueryFragment for CommonTableExpression {
+    fn push_sql(&self, out: &mut QueryBuilder) -> BuildQueryResult {
+        out.push_identifier(self.name.as_str())?;
+        if !self.columns.is_empty() {
+            out.push_sql("(");
+            interpose!(column, self.columns,
+                       { out.push_identifier(column.as_str())? },
+                       { out.push_sql(", ") });
+            out.push_sql(")");
+        }
+        out.push_sql(" AS (");
+        let op = if self.all { " UNION ALL " } else { " UNION " };
+        interpose!(arm, self.arms,
+                   { push_compound_arm(arm, out)? },
+                   { out.push_sql(op) });
+        out.push_sql(")");
+        Ok(())
     }
 }
 
 impl QueryFragment for SelectQuery {
     fn push_sql(&self, out: &mut QueryBuilder) -> BuildQueryResult {
+        if !self.with.is_empty() {
+            if self.with.iter().any(|cte| cte.recursive) {
+                out.push_sql("WITH RECURSIVE ");
+            } else {
+                out.push_sql("WITH ");
+            }
+            interpose!(cte, self.with,
+                       { cte.push_sql(out)? },
+                       { out.push_sql(", ") });
+            out.push_sql(" ");
+        }
+
         if self.distinct {
             out.push_sql("SELECT DISTINCT ");
         } else {
@@ -615,8 +891,8 @@ impl QueryFragment for SelectQuery {
 
         if !self.order.is_empty() {
             out.push_sql(" ORDER BY ");
-            interpose!(&OrderBy(ref dir, ref var), self.order,
-                       { push_variable_column(out, var)?;
+            interpose!(&OrderBy(ref dir, ref column), self.order,
+                       { push_order_column(out, column)?;
                          match dir {
                              &Direction::Ascending => { out.push_sql(" ASC"); },
                              &Direction::Descending => { out.push_sql(" DESC"); },
@@ -635,10 +911,28 @@ impl QueryFragment for SelectQuery {
             &Limit::Variable(ref var) => {
                 // Guess this wasn't bound yet. Produce an argument.
                 out.push_sql(" LIMIT ");
-                self.push_variable_param(var, out)?;
+                push_variable_param(var, out)?;
             },
         }
 
+        if self.offset.is_some() {
+            // SQLite only accepts OFFSET after a LIMIT; a negative limit means no limit.
+            if self.limit == Limit::None {
+                out.push_sql(" LIMIT -1");
+            }
+            match &self.offset {
+                &Offset::None => (),
+                &Offset::Fixed(offset) => {
+                    out.push_sql(" OFFSET ");
+                    out.push_sql(offset.to_string().as_str());
+                },
+                &Offset::Variable(ref var) => {
+                    out.push_sql(" OFFSET ");
+                    push_variable_param(var, out)?;
+                },
+            }
+        }
+
         Ok(())
     }
 }
@@ -648,12 +942,219 @@ impl SelectQuery {
         let mut builder = SQLiteQueryBuilder::new();
         self.push_sql(&mut builder).map(|_| builder.finish())
     }
+
+    /// Define each union that appears more than once in this query's `FROM` clause just once, in
+    /// a `WITH` clause, and refer to that definition instead.  Using the same rule twice, for
+    /// example, yields unions that differ only in the names of their variables and aliases.
+    ///
+    /// Only this query's own `FROM` clause is considered, not those of the queries nested in it.
+    pub fn share_repeated_unions(&mut self) {
+        let tables = match ::std::mem::replace(&mut self.from, FromClause::Nothing) {
+            FromClause::TableList(TableList(tables)) => tables,
+            from => {
+                self.from = from;
+                return;
+            },
+        };
+
+        let shapes: Vec<Option<UnionShape>> = tables.iter().map(UnionShape::of).collect();
+        let repeated = |shape: &Option<UnionShape>| {
+            shape.is_some() && shapes.iter().filter(|other| *other == shape).count() > 1
+        };
+
+        // The index of the first union of each shape, with its definition's name and columns.
+        let mut defined: Vec<(usize, Name, Vec<Name>)> = vec![];
+        let mut from = Vec::with_capacity(tables.len());
+        for (i, table) in tables.into_iter().enumerate() {
+            if !repeated(&shapes[i]) {
+                from.push(table);
+                continue;
+            }
+
+            let (arms, all, alias) = match table {
+                TableOrSubquery::Union(arms, alias) => (arms, false, alias),
+                TableOrSubquery::UnionAll(arms, alias) => (arms, true, alias),
+                _ => unreachable!("only unions have shapes"),
+            };
+            let columns = union_columns(&arms);
+
+            let reference = match defined.iter().find(|&&(j, _, _)| shapes[j] == shapes[i]) {
+                Some(&(_, ref name, ref defined_columns)) => {
+                    if *defined_columns == columns {
+                        TableOrSubquery::Reference(name.clone(), alias)
+                    } else {
+                        let renames = defined_columns.iter().cloned().zip(columns.into_iter()).collect();
+                        TableOrSubquery::RenamedReference(name.clone(), renames, alias)
+                    }
+                },
+                None => {
+                    let name = format!("shared{:02}", self.with.len());
+                    self.with.push(CommonTableExpression {
+                        all,
+                        ..CommonTableExpression::new(name.clone(), arms)
+                    });
+                    defined.push((i, name.clone(), columns));
+                    TableOrSubquery::Reference(name, alias)
+                },
+            };
+            from.push(reference);
+        }
+        self.from = FromClause::TableList(TableList(from));
+    }
+}
+
+/// The names of the columns that each arm of a union projects.
+fn union_columns(arms: &[SelectQuery]) -> Vec<Name> {
+    match arms.first().map(|arm| &arm.projection) {
+        Some(&Projection::Columns(ref columns)) => {
+            columns.iter().map(|&ProjectedColumn(_, ref name)| name.clone()).collect()
+        },
+        _ => vec![],
+    }
+}
+
+/// The SQL for a union, rewritten so that it doesn't depend on the names it declares with `AS`
+/// -- its alias, the aliases of its tables, and the names of its columns -- or on the names of
+/// its arguments.  Unions with the same shape produce the same rows, up to the names of their
+/// columns, which are in the same order.
+#[derive(Debug, PartialEq)]
+struct UnionShape {
+    sql: String,
+    args: Vec<SQLArg>,
+}
+
+impl UnionShape {
+    fn of(table: &TableOrSubquery) -> Option<UnionShape> {
+        let arms = match table {
+            &TableOrSubquery::Union(ref arms, _) => arms,
+            &TableOrSubquery::UnionAll(ref arms, _) => arms,
+            _ => return None,
+        };
+        if union_columns(arms).is_empty() {
+            return None;
+        }
+        let mut builder = SQLiteQueryBuilder::new();
+        table.push_sql(&mut builder).ok()?;
+        Some(UnionShape::canonicalize(builder.finish()))
+    }
+
+    fn canonicalize(query: SQLQuery) -> UnionShape {
+        enum Token<'a> {
+            Text(&'a str),
+            Identifier(&'a str),
+            Argument(&'a str),
+        }
+
+        // Split the SQL into backquoted identifiers, `$`-prefixed arguments, and everything else.
+        let sql = query.sql.as_str();
+        let mut tokens = vec![];
+        let mut start = 0;
+        let mut chars = sql.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '`' => {
+                    let mut end = sql.len();
+                    while let Some((j, c)) = chars.next() {
+                        if c == '`' {
+                            if let Some(&(_, '`')) = chars.peek() {
+                                // An escaped backquote.
+                                chars.next();
+                                continue;
+                            }
+                            end = j;
+                            break;
+                        }
+                    }
+                    tokens.push(Token::Text(&sql[start..i]));
+                    tokens.push(Token::Identifier(&sql[i + 1..end]));
+                    start = end + 1;
+                },
+                '$' => {
+                    let mut end = sql.len();
+                    while let Some(&(j, c)) = chars.peek() {
+                        if !(c.is_alphanumeric() || c == '_') {
+                            end = j;
+                            break;
+                        }
+                        chars.next();
+                    }
+                    tokens.push(Token::Text(&sql[start..i]));
+                    tokens.push(Token::Argument(&sql[i + 1..end]));
+                    start = end;
+                },
+                _ => {},
+            }
+        }
+        tokens.push(Token::Text(&sql[start.min(sql.len())..]));
+
+        let mut declared: HashMap<&str, usize> = HashMap::new();
+        let mut previous: &str = "";
+        for token in tokens.iter() {
+            match token {
+                &Token::Text(text) => previous = text,
+                &Token::Identifier(name) => {
+                    if previous.ends_with(" AS ") {
+                        let next = declared.len();
+                        declared.entry(name).or_insert(next);
+                    }
+                    previous = "";
+                },
+                &Token::Argument(_) => previous = "",
+            }
+        }
+
+        let values: HashMap<&str, &SQLArg> = query.args
+                                                  .iter()
+                                                  .map(|&(ref name, ref value)| (name.as_str(), value))
+                                                  .collect();
+        let mut args: Vec<SQLArg> = vec![];
+        let mut arg_names: HashMap<&str, usize> = HashMap::new();
+        let mut canonical = String::with_capacity(sql.len());
+        for token in tokens {
+            match token {
+                Token::Text(text) => canonical.push_str(text),
+                Token::Identifier(name) => {
+                    match declared.get(name) {
+                        // Declared names can't collide with anything else: everything else is
+                        // quoted.
+                        Some(index) => canonical.push_str(format!("#{}", index).as_str()),
+                        None => {
+                            canonical.push('`');
+                            canonical.push_str(name);
+                            canonical.push('`');
+                        },
+                    }
+                },
+                Token::Argument(name) => {
+                    match values.get(name) {
+                        Some(value) => {
+                            let next = args.len();
+                            let index = *arg_names.entry(name).or_insert(next);
+                            if index == next {
+                                args.push((*value).clone());
+                            }
+                            canonical.push_str(format!("$#{}", index).as_str());
+                        },
+                        // A parameter bound when the query runs.
+                        None => {
+                            canonical.push('$');
+                            canonical.push_str(name);
+                        },
+                    }
+                },
+            }
+        }
+
+        UnionShape {
+            sql: canonical,
+            args,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::rc::Rc;
 
     use mentat_query_algebrizer::{
         Column,
@@ -766,7 +1267,7 @@ mod tests {
         };
         let q = build_query(&c);
         assert_eq!("`fulltext01`.text MATCHES $v0", q.sql);
-        assert_eq!(vec![("$v0".to_string(), Rc::new(mentat_sql::Value::Text("needle".to_string())))], q.args);
+        assert_eq!(vec![("$v0".to_string(), SQLArg::text("needle"))], q.args);
 
         let c = Constraint::Infix {
             op: Op("="),
@@ -788,6 +1289,7 @@ mod tests {
         ];
 
         let mut query = SelectQuery {
+            with: vec![],
             distinct: true,
             projection: Projection::Columns(
                             vec![
@@ -816,6 +1318,7 @@ mod tests {
             group_by: vec![],
             order: vec![],
             limit: Limit::None,
+            offset: Offset::None,
         };
 
         let SQLQuery { sql, args } = query.to_sql_query().unwrap();
@@ -832,6 +1335,57 @@ mod tests {
 
     }
 
+    fn select_from(table: &str, constraints: Vec<Constraint>) -> SelectQuery {
+        SelectQuery {
+            with: vec![],
+            distinct: false,
+            projection: Projection::Star,
+            from: FromClause::TableList(TableList(vec![TableOrSubquery::Reference(table.to_string(), "t".to_string())])),
+            constraints,
+            group_by: vec![],
+            order: vec![],
+            limit: Limit::None,
+            offset: Offset::None,
+        }
+    }
+
+    #[test]
+    fn test_with() {
+        let below = |n| vec![Constraint::Infix {
+            op: Op("<"),
+            left: ColumnOrExpression::ExistingColumn("n".to_string()),
+            right: ColumnOrExpression::Integer(n),
+        }];
+        let mut base = select_from("datoms", below(3));
+        base.projection = Projection::One;
+
+        let mut query = select_from("numbers", vec![]);
+        query.with.push(CommonTableExpression {
+            columns: vec!["n".to_string()],
+            all: true,
+            recursive: true,
+            ..CommonTableExpression::new("numbers".to_string(), vec![base, select_from("numbers", below(10))])
+        });
+        query.with.push(CommonTableExpression::new("others".to_string(), vec![select_from("datoms", vec![])]));
+        assert_eq!("WITH RECURSIVE `numbers`(`n`) AS (\
+                    SELECT 1 FROM `datoms` AS `t` WHERE `n` < 3 \
+                    UNION ALL \
+                    SELECT * FROM `numbers` AS `t` WHERE `n` < 10), \
+                    `others` AS (SELECT * FROM `datoms` AS `t`) \
+                    SELECT * FROM `numbers` AS `t`",
+                   build(&query));
+
+        // An arm with its own `WITH` clause is wrapped, because SQLite only accepts one at the start
+        // of a compound `SELECT`.
+        let mut arm = select_from("others", vec![]);
+        arm.with.push(CommonTableExpression::new("others".to_string(), vec![select_from("datoms", vec![])]));
+        let union = TableOrSubquery::Union(vec![select_from("datoms", vec![]), arm], "u".to_string());
+        assert_eq!("(SELECT * FROM `datoms` AS `t` \
+                    UNION \
+                    SELECT * FROM (WITH `others` AS (SELECT * FROM `datoms` AS `t`) SELECT * FROM `others` AS `t`)) AS `u`",
+                   build(&union));
+    }
+
     #[test]
     fn test_format_select_var() {
         assert_eq!(format_select_var("?foo99-people"), "ifoo99_people");
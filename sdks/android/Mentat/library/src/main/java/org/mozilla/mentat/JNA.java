@@ -156,6 +156,7 @@ public interface JNA extends Library {
     double typed_value_into_double(TypedValue value);
     long typed_value_into_timestamp(TypedValue value);
     int typed_value_value_type(TypedValue value);
+    int typed_value_is_null(TypedValue value);
 
     TypedValueList row_at_index(RelResult rows, int index);
     RelResultIter typed_value_result_set_into_iter(RelResult rows);
@@ -165,6 +166,7 @@ public interface JNA extends Library {
     TypedValue typed_value_list_iter_next(TypedValueListIter iter);
 
     TypedValue value_at_index(TypedValueList rows, int index);
+    int value_at_index_is_null(TypedValueList rows, int index);
     long value_at_index_into_long(TypedValueList rows, int index);
     long value_at_index_into_entid(TypedValueList rows, int index);
     Pointer value_at_index_into_kw(TypedValueList rows, int index);
@@ -223,6 +223,7 @@ int64_t typed_value_into_timestamp(struct TypedValue*_Nonnull  value);
 char* _Nonnull typed_value_into_string(struct TypedValue*_Nonnull  value);
 uuid_t* _Nonnull typed_value_into_uuid(struct TypedValue*_Nonnull  value);
 enum ValueType typed_value_value_type(struct TypedValue*_Nonnull value);
+int32_t typed_value_is_null(struct TypedValue*_Nonnull value);
 
 struct QueryResultRow* _Nullable row_at_index(struct QueryResultRows* _Nonnull rows, const int32_t index);
 struct QueryRowsIterator* _Nonnull typed_value_result_set_into_iter(struct QueryResultRows* _Nonnull rows);
@@ -231,6 +232,7 @@ struct QueryRowIterator* _Nonnull typed_value_list_into_iter(struct QueryResultR
 struct TypedValue* _Nullable typed_value_list_iter_next(struct QueryRowIterator* _Nonnull iter);
 
 struct TypedValue* _Nonnull value_at_index(struct QueryResultRow* _Nonnull row, const int32_t index);
+int32_t value_at_index_is_null(struct QueryResultRow* _Nonnull row, const int32_t index);
 int64_t value_at_index_into_long(struct QueryResultRow* _Nonnull row, const int32_t index);
 int64_t value_at_index_into_entid(struct QueryResultRow* _Nonnull row, const int32_t index);
 char* _Nonnull value_at_index_into_kw(struct QueryResultRow* _Nonnull row, const int32_t index);
@@ -14,7 +14,13 @@ pub enum SQLError {
     InvalidParameterName(String),
 
     #[fail(display = "parameter name could be generated: '{}'", _0)]
-    BindParamCouldBeGenerated(String)
+    BindParamCouldBeGenerated(String),
+
+    #[fail(display = "generated SQL failed audit: {}: {}", reason, sql)]
+    AuditFailed {
+        sql: String,
+        reason: String,
+    },
 }
 
 pub type BuildQueryResult = Result<(), SQLError>;
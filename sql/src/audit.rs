@@ -0,0 +1,145 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Checks that generated SQL can't have been subverted by the values in a query.
+//!
+//! Numbers are written into SQL directly: they're formatted from Rust integers and floats, so
+//! they can't contain anything but a number.  Everything else -- strings, keywords, UUIDs -- must
+//! be a bind parameter.  The only string literals we ever write ourselves are a handful of fixed
+//! names, like the type names we compare `typeof` against.
+//!
+//! `SQLiteQueryBuilder` audits everything it builds in debug builds, and in release builds with
+//! the `sql_audit` feature, and panics if the audit fails: an audit failure is always a bug in
+//! the code that generated the SQL.
+
+use std::collections::BTreeSet;
+
+use sql_traits::errors::{
+    SQLError,
+};
+
+use SQLQuery;
+
+/// The string literals that Mentat writes into SQL itself.
+const KNOWN_LITERALS: &[&str] = &[
+    "null", "integer", "real", "text", "blob",      // Results of `typeof`.
+    "pcnalx",                                       // The format of `matchinfo`.
+];
+
+fn failed(query: &SQLQuery, reason: String) -> SQLError {
+    SQLError::AuditFailed {
+        sql: query.sql.clone(),
+        reason,
+    }
+}
+
+/// Check that `query` contains no string, blob, or quoted literals other than the ones we write
+/// ourselves; no comments or statement separators; and that each of its arguments is used.
+pub fn audit_sql(query: &SQLQuery) -> Result<(), SQLError> {
+    let sql = query.sql.as_str();
+    let mut parameters: BTreeSet<&str> = BTreeSet::new();
+    let mut chars = sql.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '`' => {
+                // An identifier.  Backticks inside it are doubled.
+                loop {
+                    match chars.next() {
+                        Some((_, '`')) => {
+                            if chars.peek().map(|&(_, c)| c) == Some('`') {
+                                chars.next();
+                            } else {
+                                break;
+                            }
+                        },
+                        Some(_) => {},
+                        None => return Err(failed(query, format!("unterminated identifier at {}", i))),
+                    }
+                }
+            },
+            '\'' => {
+                // An escaped quote ends this literal and starts another, which will fail below.
+                let end = loop {
+                    match chars.next() {
+                        Some((j, '\'')) => break j,
+                        Some(_) => {},
+                        None => return Err(failed(query, format!("unterminated literal at {}", i))),
+                    }
+                };
+                let literal = &sql[i + 1..end];
+                if !KNOWN_LITERALS.contains(&literal) {
+                    return Err(failed(query, format!("literal '{}' should be a bind parameter", literal)));
+                }
+            },
+            '"' => {
+                return Err(failed(query, format!("double-quoted literal at {}", i)));
+            },
+            ';' => {
+                return Err(failed(query, format!("statement separator at {}", i)));
+            },
+            '-' | '/' => {
+                let next = chars.peek().map(|&(_, c)| c);
+                if (c == '-' && next == Some('-')) || (c == '/' && next == Some('*')) {
+                    return Err(failed(query, format!("comment at {}", i)));
+                }
+            },
+            '$' => {
+                let mut end = i + 1;
+                while let Some(&(j, c)) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = j + c.len_utf8();
+                    chars.next();
+                }
+                parameters.insert(&sql[i..end]);
+            },
+            _ => {},
+        }
+    }
+
+    // An argument that isn't used means that its value went somewhere else.
+    for &(ref name, _) in query.args.iter() {
+        if !parameters.contains(name.as_str()) {
+            return Err(failed(query, format!("argument {} is not used", name)));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use SQLArg;
+
+    fn query(sql: &str, args: Vec<&str>) -> SQLQuery {
+        SQLQuery {
+            sql: sql.to_string(),
+            args: args.into_iter().map(|a| (a.to_string(), SQLArg::Integer(0))).collect(),
+        }
+    }
+
+    #[test]
+    fn test_audit_sql() {
+        assert!(audit_sql(&query("SELECT `foo``bar` FROM `datoms` WHERE v = $v0 AND a = 65 AND typeof(v) = 'text'", vec!["$v0"])).is_ok());
+        assert!(audit_sql(&query("SELECT x FROM t WHERE v = $v1 OR v = $v10", vec!["$v1", "$v10"])).is_ok());
+        assert!(audit_sql(&query("SELECT x FROM t WHERE v = $v10", vec!["$v1"])).is_err());
+        assert!(audit_sql(&query("SELECT x FROM t WHERE v = 'hello'", vec![])).is_err());
+        assert!(audit_sql(&query("SELECT x FROM t WHERE v = 'it''s'", vec![])).is_err());
+        assert!(audit_sql(&query("SELECT x FROM t WHERE v = X'00'", vec![])).is_err());
+        assert!(audit_sql(&query("SELECT x FROM t WHERE v = \"hello\"", vec![])).is_err());
+        assert!(audit_sql(&query("SELECT x FROM t WHERE v = 1 --5", vec![])).is_err());
+        assert!(audit_sql(&query("SELECT x FROM t; DROP TABLE t", vec![])).is_err());
+        assert!(audit_sql(&query("SELECT `x FROM t", vec![])).is_err());
+    }
+}
@@ -11,14 +11,13 @@
 extern crate failure;
 
 extern crate ordered_float;
+#[cfg(feature = "rusqlite")]
 extern crate rusqlite;
 
 extern crate core_traits;
 extern crate sql_traits;
 extern crate mentat_core;
 
-use std::rc::Rc;
-
 use std::collections::HashMap;
 
 use ordered_float::OrderedFloat;
@@ -33,21 +32,82 @@ use sql_traits::errors::{
 };
 
 use mentat_core::{
+    bigint_to_sql_text,
+    Keyword,
     ToMicros,
     ValueRc,
 };
 
+#[cfg(feature = "rusqlite")]
+use rusqlite::types::{
+    ToSql,
+    ToSqlOutput,
+    ValueRef,
+};
+
+#[cfg(feature = "rusqlite")]
 pub use rusqlite::types::Value;
 
+mod audit;
+
+pub use audit::{
+    audit_sql,
+};
+
 /// We want to accumulate values that will later be substituted into a SQL statement execution.
 /// This struct encapsulates the generated string and the _initial_ argument list.
 /// Additional user-supplied argument bindings, with their placeholders accumulated via
 /// `push_bind_param`, will be appended to this argument list.
 pub struct SQLQuery {
     pub sql: String,
+    pub args: Vec<(String, SQLArg)>,
+}
+
+/// A value to bind to an argument of a `SQLQuery`.
+///
+/// Strings and blobs are shared, not copied: a `Text` argument is the very string of the
+/// `TypedValue` it came from, and running a query -- however many times -- binds each argument
+/// by reference, without turning it into an owned `Value` first.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SQLArg {
+    Integer(i64),
+    Real(f64),
+    Text(ValueRc<String>),
+    Blob(ValueRc<Vec<u8>>),
+}
+
+impl SQLArg {
+    pub fn text(s: &str) -> SQLArg {
+        SQLArg::Text(ValueRc::new(s.to_string()))
+    }
+
+    /// The argument that matches `value` as it's stored in the `datoms` table.
+    pub fn from_typed_value(value: &TypedValue) -> SQLArg {
+        match value {
+            &TypedValue::Ref(x) => SQLArg::Integer(x),
+            &TypedValue::Boolean(x) => SQLArg::Integer(if x { 1 } else { 0 }),
+            &TypedValue::Instant(x) => SQLArg::Integer(x.to_micros()),
+            &TypedValue::Long(x) => SQLArg::Integer(x),
+            &TypedValue::Double(x) => SQLArg::Real(x.into_inner()),
+            &TypedValue::String(ref x) => SQLArg::Text(x.clone()),
+            &TypedValue::Uuid(ref u) => SQLArg::Blob(ValueRc::new(u.as_bytes().to_vec())),
+            &TypedValue::Keyword(ref x) => SQLArg::Text(ValueRc::new(x.to_string())),
+            &TypedValue::BigInteger(ref x) => SQLArg::Text(ValueRc::new(bigint_to_sql_text(x))),
+            &TypedValue::Bytes(ref x) => SQLArg::Blob(x.clone()),
+        }
+    }
+}
 
-    /// These will eventually perhaps be rusqlite `ToSql` instances.
-    pub args: Vec<(String, Rc<rusqlite::types::Value>)>,
+#[cfg(feature = "rusqlite")]
+impl ToSql for SQLArg {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput> {
+        Ok(ToSqlOutput::Borrowed(match self {
+            &SQLArg::Integer(x) => ValueRef::Integer(x),
+            &SQLArg::Real(x) => ValueRef::Real(x),
+            &SQLArg::Text(ref x) => ValueRef::Text(x.as_str()),
+            &SQLArg::Blob(ref x) => ValueRef::Blob(x.as_slice()),
+        }))
+    }
 }
 
 /// Gratefully based on Diesel's QueryBuilder trait:
@@ -89,13 +149,16 @@ pub struct SQLiteQueryBuilder {
     arg_prefix: String,
     arg_counter: i64,
 
-    // We can't just use an InternSet on the rusqlite::types::Value instances, because that
-    // includes f64, so it's not Hash or Eq.
-    // Instead we track byte and String arguments separately, mapping them to their argument name,
-    // in order to dedupe. We'll add these to the regular argument vector later.
-    byte_args: HashMap<Vec<u8>, String>,             // From value to argument name.
-    string_args: HashMap<ValueRc<String>, String>,   // From value to argument name.
-    args: Vec<(String, Rc<rusqlite::types::Value>)>, // (arg, value).
+    // We can't just use an InternSet on the `SQLArg` instances, because that includes f64, so
+    // it's not Hash or Eq.
+    // Instead we track byte, String, and keyword arguments separately, mapping them to their
+    // argument name, in order to dedupe. We'll add these to the regular argument vector later.
+    // One builder writes a whole query, subqueries and union arms included, so an ident that
+    // appears in every arm of a big `or` is still only one argument.
+    byte_args: HashMap<Vec<u8>, String>,                // From value to argument name.
+    string_args: HashMap<ValueRc<String>, String>,      // From value to argument name.
+    keyword_args: HashMap<ValueRc<Keyword>, String>,    // From value to argument name.
+    args: Vec<(String, SQLArg)>,                        // (arg, value).
 }
 
 impl SQLiteQueryBuilder {
@@ -111,6 +174,7 @@ impl SQLiteQueryBuilder {
 
             byte_args: HashMap::default(),
             string_args: HashMap::default(),
+            keyword_args: HashMap::default(),
             args: vec![],
         }
     }
@@ -121,11 +185,40 @@ impl SQLiteQueryBuilder {
         arg
     }
 
-    fn push_static_arg(&mut self, val: Rc<rusqlite::types::Value>) {
-        // TODO: intern these, too.
-        let arg = self.next_argument_name();
-        self.push_named_arg(arg.as_str());
-        self.args.push((arg, val));
+    fn push_static_arg(&mut self, val: SQLArg) {
+        match val {
+            SQLArg::Text(s) => {
+                self.push_interned_string_arg(&s);
+            },
+            SQLArg::Blob(b) => {
+                self.push_interned_byte_arg(&b);
+            },
+            val => {
+                let arg = self.next_argument_name();
+                self.push_named_arg(arg.as_str());
+                self.args.push((arg, val));
+            },
+        }
+    }
+
+    fn push_interned_byte_arg(&mut self, bytes: &[u8]) {
+        if let Some(arg) = self.byte_args.get(bytes).cloned() {         // Why, borrow checker, why?!
+            self.push_named_arg(arg.as_str());
+        } else {
+            let arg = self.next_argument_name();
+            self.push_named_arg(arg.as_str());
+            self.byte_args.insert(bytes.to_vec(), arg);
+        }
+    }
+
+    fn push_interned_string_arg(&mut self, s: &ValueRc<String>) {
+        if let Some(arg) = self.string_args.get(s).cloned() {
+            self.push_named_arg(arg.as_str());
+        } else {
+            let arg = self.next_argument_name();
+            self.push_named_arg(arg.as_str());
+            self.string_args.insert(s.clone(), arg);
+        }
     }
 
     fn push_named_arg(&mut self, arg: &str) {
@@ -160,34 +253,32 @@ impl QueryBuilder for SQLiteQueryBuilder {
                 self.push_sql(format!("{:e}", v).as_str());
             },
             &Instant(dt) => {
-                self.push_sql(format!("{}", dt.to_micros()).as_str());      // TODO: argument instead?
+                // Like the other numbers, this can't contain anything but a number.
+                self.push_sql(format!("{}", dt.to_micros()).as_str());
             },
             &Uuid(ref u) => {
-                let bytes = u.as_bytes();
-                if let Some(arg) = self.byte_args.get(bytes.as_ref()).cloned() {        // Why, borrow checker, why?!
-                    self.push_named_arg(arg.as_str());
-                } else {
-                    let arg = self.next_argument_name();
-                    self.push_named_arg(arg.as_str());
-                    self.byte_args.insert(bytes.clone().to_vec(), arg);
-                }
+                self.push_interned_byte_arg(u.as_bytes());
             },
-            // These are both `Rc`. Unfortunately, we can't use that fact when
-            // turning these into rusqlite Values.
-            // However, we can check to see whether there's an existing var that matches…
+            // This is `Rc`, and we bind the very same string; but we can also check to see
+            // whether there's an existing var that matches…
             &String(ref s) => {
-                if let Some(arg) = self.string_args.get(s).cloned() {
+                self.push_interned_string_arg(s);
+            },
+            &Keyword(ref k) => {
+                if let Some(arg) = self.keyword_args.get(k).cloned() {
                     self.push_named_arg(arg.as_str());
                 } else {
                     let arg = self.next_argument_name();
                     self.push_named_arg(arg.as_str());
-                    self.string_args.insert(s.clone(), arg);
+                    self.keyword_args.insert(k.clone(), arg);
                 }
             },
-            &Keyword(ref s) => {
-                // TODO: intern.
-                let v = Rc::new(rusqlite::types::Value::Text(s.as_ref().to_string()));
-                self.push_static_arg(v);
+            // Big integers are stored as text that sorts like the integers do.
+            &BigInteger(ref i) => {
+                self.push_interned_string_arg(&ValueRc::new(bigint_to_sql_text(i)));
+            },
+            &Bytes(ref b) => {
+                self.push_interned_byte_arg(b.as_slice());
             },
         }
         Ok(())
@@ -218,24 +309,36 @@ impl QueryBuilder for SQLiteQueryBuilder {
 
     fn finish(self) -> SQLQuery {
         // We collected string and byte arguments into separate maps so that we could
-        // dedupe them. Now we need to turn them into rusqlite Values.
+        // dedupe them. Now we need to turn them into arguments.
         let mut args = self.args;
         let string_args = self.string_args.into_iter().map(|(val, arg)| {
-             (arg, Rc::new(rusqlite::types::Value::Text(val.as_ref().clone())))
+            (arg, SQLArg::Text(val))
         });
         let byte_args = self.byte_args.into_iter().map(|(val, arg)| {
-            (arg, Rc::new(rusqlite::types::Value::Blob(val)))
+            (arg, SQLArg::Blob(ValueRc::new(val)))
+        });
+        let keyword_args = self.keyword_args.into_iter().map(|(val, arg)| {
+            (arg, SQLArg::Text(ValueRc::new(val.as_ref().to_string())))
         });
 
         args.extend(string_args);
         args.extend(byte_args);
+        args.extend(keyword_args);
 
         // Get the args in the right order -- $v0, $v1…
         args.sort_by(|&(ref k1, _), &(ref k2, _)| k1.cmp(k2));
-        SQLQuery {
+        let query = SQLQuery {
             sql: self.sql,
             args: args,
+        };
+
+        if cfg!(any(debug_assertions, feature = "sql_audit")) {
+            if let Err(e) = audit_sql(&query) {
+                panic!("{}", e);
+            }
         }
+
+        query
     }
 }
 
@@ -243,8 +346,8 @@ impl QueryBuilder for SQLiteQueryBuilder {
 mod tests {
     use super::*;
 
-    fn string_arg(s: &str) -> Rc<rusqlite::types::Value> {
-        Rc::new(rusqlite::types::Value::Text(s.to_string()))
+    fn string_arg(s: &str) -> SQLArg {
+        SQLArg::text(s)
     }
 
     #[test]
@@ -269,4 +372,48 @@ mod tests {
                    vec![("$v0".to_string(), string_arg("frobnicate")),
                         ("$v1".to_string(), string_arg("swoogle"))]);
     }
+
+    #[test]
+    fn test_interned_args() {
+        let mut s = SQLiteQueryBuilder::new();
+        let keyword = TypedValue::typed_ns_keyword("foo", "bar");
+        s.push_sql("SELECT ");
+        s.push_typed_value(&keyword).unwrap();
+        s.push_sql(", ");
+        s.push_static_arg(string_arg("frobnicate"));
+        s.push_sql(", ");
+        s.push_typed_value(&keyword).unwrap();
+        s.push_sql(", ");
+        s.push_typed_value(&TypedValue::typed_string("frobnicate")).unwrap();
+        s.push_sql(", ");
+        s.push_typed_value(&TypedValue::typed_ns_keyword("foo", "baz")).unwrap();
+        let q = s.finish();
+
+        assert_eq!(q.sql.as_str(), "SELECT $v0, $v1, $v0, $v1, $v2");
+        assert_eq!(q.args,
+                   vec![("$v0".to_string(), string_arg(":foo/bar")),
+                        ("$v1".to_string(), string_arg("frobnicate")),
+                        ("$v2".to_string(), string_arg(":foo/baz"))]);
+    }
+
+    #[test]
+    fn test_args_are_bound_by_reference() {
+        let value = TypedValue::typed_string("frobnicate");
+        let mut s = SQLiteQueryBuilder::new();
+        s.push_sql("SELECT ");
+        s.push_typed_value(&value).unwrap();
+        let q = s.finish();
+
+        // The argument is the value's own string…
+        match (&value, &q.args[0].1) {
+            (&TypedValue::String(ref v), &SQLArg::Text(ref arg)) => assert!(ValueRc::ptr_eq(v, arg)),
+            _ => panic!("expected a text argument"),
+        }
+
+        // … and it's bound without being copied.
+        match q.args[0].1.to_sql().unwrap() {
+            ToSqlOutput::Borrowed(ValueRef::Text(text)) => assert_eq!(text, "frobnicate"),
+            _ => panic!("expected a borrowed argument"),
+        }
+    }
 }
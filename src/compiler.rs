@@ -0,0 +1,98 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Mentat's Datalog-to-SQL compiler on its own: parse a query, algebrize it against a schema, and
+//! translate it into SQL over Mentat's tables, without a store to run it against.
+//!
+//! This is everything the crate offers when it's built without the `store` feature, in which case
+//! nothing depends on `rusqlite`: pull expressions project the entity they would have pulled, and
+//! nothing here opens a connection.
+
+use mentat_core::{
+    Schema,
+};
+
+pub use mentat_query_algebrizer::{
+    AlgebraicQuery,
+    EmptyBecause,
+    Known,
+    QueryInputs,
+    algebrize_with_inputs,
+    parse_find_string,
+};
+
+pub use mentat_query_projector::translate::{
+    ProjectedSelect,
+    query_to_select,
+};
+
+pub use mentat_sql::{
+    SQLQuery,
+};
+
+use public_traits::errors::{
+    Result,
+};
+
+/// What a query compiles to.
+pub enum CompiledQuery {
+    /// The query can't match anything, and why we believe that.
+    KnownEmpty(EmptyBecause),
+
+    /// The query's results are known without running any SQL.
+    KnownConstant,
+
+    /// The SQL to run.  `:in` variables that weren't given values are left as named parameters,
+    /// like `$iname` for `?name`.
+    Sql(SQLQuery),
+}
+
+/// Compile `query` against `schema`.
+pub fn compile<T>(schema: &Schema, query: &str, inputs: T) -> Result<CompiledQuery>
+    where T: Into<Option<QueryInputs>> {
+    let parsed = parse_find_string(query)?;
+    let known = Known::for_schema(schema);
+    let algebrized = algebrize_with_inputs(known, parsed, 0, inputs.into().unwrap_or_default())?;
+    if algebrized.is_known_empty() {
+        return Ok(CompiledQuery::KnownEmpty(algebrized.cc.empty_because.unwrap()));
+    }
+    match query_to_select(schema, algebrized)? {
+        ProjectedSelect::Constant(_constant) => Ok(CompiledQuery::KnownConstant),
+        ProjectedSelect::Query { query, projector: _projector } => Ok(CompiledQuery::Sql(query.to_sql_query()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let schema = Schema::default();
+
+        match compile(&schema, r#"[:find ?e ?a :where [?e ?a "x"]]"#, None).expect("compiled") {
+            CompiledQuery::Sql(SQLQuery { sql, .. }) => {
+                assert!(sql.starts_with("SELECT DISTINCT `all_datoms00`.e AS `?e`, `all_datoms00`.a AS `?a` FROM `all_datoms`"), "{}", sql);
+            },
+            _ => panic!("expected SQL"),
+        }
+
+        // An attribute the schema doesn't know can't match.
+        match compile(&schema, "[:find ?e :where [?e :foo/bar _]]", None).expect("compiled") {
+            CompiledQuery::KnownEmpty(_) => {},
+            _ => panic!("expected an empty query"),
+        }
+
+        match compile(&schema, "[:find ?x . :where [(ground 1) ?x]]", None).expect("compiled") {
+            CompiledQuery::KnownConstant => {},
+            _ => panic!("expected a constant"),
+        }
+    }
+}
@@ -18,9 +18,14 @@ use std::collections::{
     BTreeMap,
 };
 
+use std::path::{
+    PathBuf,
+};
+
 use std::sync::{
     Arc,
     Mutex,
+    MutexGuard,
 };
 
 use rusqlite;
@@ -29,6 +34,9 @@ use rusqlite::{
 };
 
 use edn;
+use edn::entities::{
+    EntidOrIdent,
+};
 
 pub use core_traits::{
     Attribute,
@@ -40,9 +48,12 @@ pub use core_traits::{
 };
 
 use mentat_core::{
+    AttributeIndexes,
     HasSchema,
+    IndexDirection,
     Keyword,
     Schema,
+    Statistics,
     TxReport,
     ValueRc,
 };
@@ -54,13 +65,18 @@ use mentat_db::cache::{
 
 use mentat_db::db;
 use mentat_db::{
+    AttributeSet,
     InProgressObserverTransactWatcher,
     PartitionMap,
+    RedundantAssertions,
+    StoreGeneration,
     TxObservationService,
     TxObserver,
+    TxSnapshot,
 };
 
 use mentat_query_pull::{
+    lookup_entity,
     pull_attributes_for_entities,
     pull_attributes_for_entity,
 };
@@ -68,9 +84,16 @@ use mentat_query_pull::{
 use mentat_transaction::{
     CacheAction,
     CacheDirection,
+    Entity,
     Metadata,
     InProgress,
     InProgressRead,
+    InteractiveGuard,
+    TransactionFunctions,
+    UnknownAttributes,
+    WriteHolderGuard,
+    resolve_entity,
+    write_transaction_in_progress,
 };
 
 use public_traits::errors::{
@@ -79,19 +102,32 @@ use public_traits::errors::{
 };
 
 use mentat_transaction::query::{
+    Datom,
     Known,
     PreparedResult,
     QueryExplanation,
     QueryInputs,
     QueryOutput,
+    Provenance,
+    TxData,
+    lookup_datoms,
+    lookup_provenance_for_attribute,
+    lookup_tx,
+    lookup_tx_data,
     lookup_value_for_attribute,
     lookup_values_for_attribute,
+    q_cached,
     q_explain,
     q_once,
-    q_prepare,
+    q_prepare_cached,
     q_uncached,
 };
 
+use mentat_transaction::query_plan::{
+    QueryPlan,
+    q_plan,
+};
+
 /// A mutable, safe reference to the current Mentat store.
 pub struct Conn {
     /// `Mutex` since all reads and writes need to be exclusive.  Internally, owned data for the
@@ -109,27 +145,109 @@ pub struct Conn {
     /// gives us copy-on-write semantics.
     /// We store that cached `Arc` here in a `Mutex`, so that the main copy can be carefully
     /// replaced on commit.
+    ///
+    /// Other connections, perhaps in other processes, can write to the same store.  Each read and
+    /// transaction first checks the store's generation, and reloads the metadata if it has moved
+    /// on: see `current_metadata`.
     metadata: Mutex<Metadata>,
 
-    // TODO: maintain set of change listeners or handles to transaction report queues. #298.
-
-    // TODO: maintain cache of query plans that could be shared across threads and invalidated when
-    // the schema changes. #315.
+    // Prepared queries are translated once per schema: see `mentat_transaction::query_cache`.
     pub(crate) tx_observer_service: Mutex<TxObservationService>,
+
+    /// Transaction functions that transactions begun on this connection can call.
+    tx_functions: Mutex<TransactionFunctions>,
+
+    /// The file backing this store, if any.  Write transactions register themselves against this
+    /// path so that a competing writer can report who is in its way.
+    path: Option<PathBuf>,
 }
 
 impl Conn {
     // Intentionally not public.
-    fn new(partition_map: PartitionMap, schema: Schema) -> Conn {
+    fn new(partition_map: PartitionMap, schema: Schema, store_generation: StoreGeneration, statistics: Statistics, indexes: AttributeIndexes, path: Option<PathBuf>) -> Conn {
         Conn {
-            metadata: Mutex::new(Metadata::new(0, partition_map, Arc::new(schema), Default::default())),
+            metadata: Mutex::new(Metadata::new(0, partition_map, Arc::new(schema), Default::default(), store_generation, Arc::new(statistics), indexes)),
             tx_observer_service: Mutex::new(TxObservationService::new()),
+            tx_functions: Mutex::new(TransactionFunctions::default()),
+            path: path,
         }
     }
 
     pub fn connect(sqlite: &mut rusqlite::Connection) -> Result<Conn> {
         let db = db::ensure_current_version(sqlite)?;
-        Ok(Conn::new(db.partition_map, db.schema))
+        let store_generation = db::read_generation(sqlite)?;
+        let statistics = db::read_statistics(sqlite)?;
+        let indexes = db::read_attribute_indexes(sqlite)?;
+        let path = db::database_path(sqlite)?;
+        Ok(Conn::new(db.partition_map, db.schema, store_generation, statistics, indexes, path))
+    }
+
+    /// Recount the datoms for each attribute, which queries use to decide the order in which to
+    /// join their patterns.  The counts are taken when the store is opened, and transacting doesn't
+    /// update them: do this after adding or removing a lot of data.
+    pub fn update_statistics(&self, sqlite: &rusqlite::Connection) -> Result<()> {
+        let statistics = db::read_statistics(sqlite)?;
+        self.metadata.lock().unwrap().statistics = Arc::new(statistics);
+        Ok(())
+    }
+
+    /// The current metadata, first brought up to date if another connection has written to the
+    /// store since it was read.
+    ///
+    /// Fails with `MentatError::IncompatibleSchemaChange` if that connection removed attributes we
+    /// knew about or changed their value types.  The metadata is up to date regardless, so the
+    /// caller can try again.
+    fn current_metadata(&self, sqlite: &rusqlite::Connection) -> Result<MutexGuard<Metadata>> {
+        let mut metadata = self.metadata.lock().unwrap();
+        Conn::refresh_metadata(&mut metadata, sqlite)?;
+        Ok(metadata)
+    }
+
+    fn refresh_metadata(metadata: &mut Metadata, sqlite: &rusqlite::Connection) -> Result<()> {
+        let store_generation = db::read_generation(sqlite)?;
+        if store_generation != metadata.store_generation {
+            let incompatible = Conn::reload_metadata(metadata, sqlite, store_generation)?;
+            if !incompatible.is_empty() {
+                bail!(MentatError::IncompatibleSchemaChange(incompatible));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reread the partition map and, if it has changed, the schema, and repopulate the cached
+    /// attributes that still exist.  Returns the idents of attributes that changed incompatibly.
+    fn reload_metadata(metadata: &mut Metadata, sqlite: &rusqlite::Connection, store_generation: StoreGeneration) -> Result<Vec<String>> {
+        let db = db::read_db(sqlite)?;
+
+        let mut incompatible = vec![];
+        if store_generation.schema != metadata.store_generation.schema {
+            for (&a, attribute) in metadata.schema.attribute_map.iter() {
+                match db.schema.attribute_for_entid(a) {
+                    Some(reloaded) if reloaded.value_type == attribute.value_type => (),
+                    _ => incompatible.push(metadata.schema.get_ident(a).map_or_else(|| a.to_string(), |ident| ident.to_string())),
+                }
+            }
+            metadata.schema = Arc::new(db.schema);
+        }
+
+        // Any cached value might have been changed.
+        let mut cache = SQLiteAttributeCache::default();
+        {
+            let schema = &*metadata.schema;
+            let previous = &metadata.attribute_cache;
+            for &a in previous.forward_cached_attributes().iter().filter(|&&a| schema.attribute_for_entid(a).is_some()) {
+                cache.register_forward(schema, sqlite, a)?;
+            }
+            for &a in previous.reverse_cached_attributes().iter().filter(|&&a| schema.attribute_for_entid(a).is_some()) {
+                cache.register_reverse(schema, sqlite, a)?;
+            }
+        }
+
+        metadata.attribute_cache = cache;
+        metadata.partition_map = db.partition_map;
+        metadata.store_generation = store_generation;
+        metadata.generation += 1;
+        Ok(incompatible)
     }
 
     /// Yield a clone of the current `Schema` instance.
@@ -149,6 +267,53 @@ impl Conn {
         self.metadata.lock().unwrap().schema.clone()
     }
 
+    /// Every attribute in the current schema, sorted by ident.
+    pub fn attributes(&self) -> Vec<(Keyword, Attribute)> {
+        self.current_schema().attributes()
+    }
+
+    /// The attributes in the current schema whose idents are in `namespace`, sorted by ident.
+    pub fn attributes_in_namespace(&self, namespace: &str) -> Vec<(Keyword, Attribute)> {
+        self.current_schema().attributes_in_namespace(namespace)
+    }
+
+    /// The current definition of the attribute named by `ident`, if it is one.
+    pub fn attribute(&self, ident: &Keyword) -> Option<Attribute> {
+        self.current_schema().attribute_for_ident(ident).map(|(a, _)| a.clone())
+    }
+
+    /// Every ident in the current schema and the entid it names, sorted by ident.
+    pub fn idents(&self) -> Vec<(Keyword, Entid)> {
+        self.current_schema().idents()
+    }
+
+    /// The file backing this store, if any.
+    pub(crate) fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    /// Begin reading the store as it is now, on a connection of its own.  See `Store::snapshot`.
+    pub fn snapshot(&self) -> Result<TxSnapshot> {
+        let path = self.path.as_ref().ok_or(MentatError::SnapshotUnavailable)?;
+        self.begin_snapshot(db::new_connection(path)?)
+    }
+
+    /// Begin reading the store as it is now on `sqlite`, which must not be in a transaction, with
+    /// the current metadata.  See `SharedStore::read`.
+    pub(crate) fn begin_snapshot(&self, mut sqlite: rusqlite::Connection) -> Result<TxSnapshot> {
+        // Nothing can commit through this `Conn` while we hold the metadata, but another
+        // connection might commit between our reloading and the snapshot beginning.
+        let mut metadata = self.metadata.lock().unwrap();
+        loop {
+            Conn::refresh_metadata(&mut metadata, &sqlite)?;
+            let snapshot = TxSnapshot::begin(sqlite, metadata.schema.clone(), metadata.attribute_cache.clone())?;
+            if db::read_generation(snapshot.sqlite())? == metadata.store_generation {
+                return Ok(snapshot);
+            }
+            sqlite = snapshot.end()?;
+        }
+    }
+
     pub fn current_cache(&self) -> SQLiteAttributeCache {
         self.metadata.lock().unwrap().attribute_cache.clone()
     }
@@ -168,8 +333,8 @@ impl Conn {
         where T: Into<Option<QueryInputs>> {
 
         // Doesn't clone, unlike `current_schema`.
-        let metadata = self.metadata.lock().unwrap();
-        let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache));
+        let metadata = self.current_metadata(sqlite)?;
+        let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache)).with_statistics(&metadata.statistics).with_indexes(&metadata.indexes);
         q_once(sqlite,
                known,
                query,
@@ -184,25 +349,54 @@ impl Conn {
                          inputs: T) -> Result<QueryOutput>
         where T: Into<Option<QueryInputs>> {
 
-        let metadata = self.metadata.lock().unwrap();
+        let metadata = self.current_metadata(sqlite)?;
         q_uncached(sqlite,
                    &*metadata.schema,        // Doesn't clone, unlike `current_schema`.
                    query,
                    inputs)
     }
 
+    /// Query the Mentat store using only the attribute cache.  This never touches SQLite, and so
+    /// needs no connection; queries that the cache can't answer fail with
+    /// `MentatError::UncachedQuery`.  See `mentat_transaction::query::q_cached`.
+    pub fn q_cached<T>(&self,
+                       query: &str,
+                       inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+
+        let metadata = self.metadata.lock().unwrap();
+        let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache));
+        q_cached(known,
+                 query,
+                 inputs)
+    }
+
     pub fn q_prepare<'sqlite, 'query, T>(&self,
                         sqlite: &'sqlite rusqlite::Connection,
                         query: &'query str,
                         inputs: T) -> PreparedResult<'sqlite>
         where T: Into<Option<QueryInputs>> {
 
-        let metadata = self.metadata.lock().unwrap();
-        let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache));
-        q_prepare(sqlite,
-                  known,
-                  query,
-                  inputs)
+        let metadata = self.current_metadata(sqlite)?;
+        q_prepare_cached(sqlite,
+                         &metadata.schema,
+                         Some(&metadata.attribute_cache),
+                         query,
+                         inputs)
+    }
+
+    pub fn q_plan<T>(&self,
+                     sqlite: &rusqlite::Connection,
+                     query: &str,
+                     inputs: T) -> Result<QueryPlan>
+        where T: Into<Option<QueryInputs>>
+    {
+        let metadata = self.current_metadata(sqlite)?;
+        let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache)).with_statistics(&metadata.statistics).with_indexes(&metadata.indexes);
+        q_plan(sqlite,
+               known,
+               query,
+               inputs)
     }
 
     pub fn q_explain<T>(&self,
@@ -211,8 +405,8 @@ impl Conn {
                         inputs: T) -> Result<QueryExplanation>
         where T: Into<Option<QueryInputs>>
     {
-        let metadata = self.metadata.lock().unwrap();
-        let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache));
+        let metadata = self.current_metadata(sqlite)?;
+        let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache)).with_statistics(&metadata.statistics).with_indexes(&metadata.indexes);
         q_explain(sqlite,
                   known,
                   query,
@@ -225,7 +419,7 @@ impl Conn {
                                               attributes: A) -> Result<BTreeMap<Entid, ValueRc<StructuredMap>>>
         where E: IntoIterator<Item=Entid>,
               A: IntoIterator<Item=Entid> {
-        let metadata = self.metadata.lock().unwrap();
+        let metadata = self.current_metadata(sqlite)?;
         let schema = &*metadata.schema;
         pull_attributes_for_entities(schema, sqlite, entities, attributes)
             .map_err(|e| e.into())
@@ -236,17 +430,29 @@ impl Conn {
                                          entity: Entid,
                                          attributes: A) -> Result<StructuredMap>
         where A: IntoIterator<Item=Entid> {
-        let metadata = self.metadata.lock().unwrap();
+        let metadata = self.current_metadata(sqlite)?;
         let schema = &*metadata.schema;
         pull_attributes_for_entity(schema, sqlite, entity, attributes)
             .map_err(|e| e.into())
     }
 
+    pub fn lookup_entity<E>(&self,
+                            sqlite: &rusqlite::Connection,
+                            entity: E) -> Result<Option<Entity>>
+        where E: Into<EntidOrIdent> {
+        let metadata = self.current_metadata(sqlite)?;
+        let schema = &*metadata.schema;
+        match resolve_entity(schema, entity) {
+            Some(e) => lookup_entity(schema, sqlite, Some(&metadata.attribute_cache), e).map_err(|e| e.into()),
+            None => Ok(None),
+        }
+    }
+
     pub fn lookup_values_for_attribute(&self,
                                        sqlite: &rusqlite::Connection,
                                        entity: Entid,
                                        attribute: &edn::Keyword) -> Result<Vec<TypedValue>> {
-        let metadata = self.metadata.lock().unwrap();
+        let metadata = self.current_metadata(sqlite)?;
         let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache));
         lookup_values_for_attribute(sqlite, known, entity, attribute)
     }
@@ -255,18 +461,42 @@ impl Conn {
                                       sqlite: &rusqlite::Connection,
                                       entity: Entid,
                                       attribute: &edn::Keyword) -> Result<Option<TypedValue>> {
-        let metadata = self.metadata.lock().unwrap();
+        let metadata = self.current_metadata(sqlite)?;
         let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache));
         lookup_value_for_attribute(sqlite, known, entity, attribute)
     }
 
-    /// Take a SQLite transaction.
-    fn begin_transaction_with_behavior<'m, 'conn>(&'m mut self, sqlite: &'conn mut rusqlite::Connection, behavior: TransactionBehavior) -> Result<InProgress<'m, 'conn>> {
-        let tx = sqlite.transaction_with_behavior(behavior)?;
+    pub fn provenance_for_attribute(&self,
+                                    sqlite: &rusqlite::Connection,
+                                    entity: Entid,
+                                    attribute: &edn::Keyword) -> Result<Vec<Provenance>> {
+        let metadata = self.current_metadata(sqlite)?;
+        lookup_provenance_for_attribute(sqlite, &*metadata.schema, entity, attribute)
+    }
+
+    pub fn tx_data(&self, sqlite: &rusqlite::Connection, tx: Entid) -> Result<Vec<Datom>> {
+        let metadata = self.current_metadata(sqlite)?;
+        lookup_tx_data(sqlite, &*metadata.schema, tx)
+    }
+
+    pub fn tx(&self, sqlite: &rusqlite::Connection, tx: Entid) -> Result<Option<TxData>> {
+        let metadata = self.current_metadata(sqlite)?;
+        lookup_tx(sqlite, &*metadata.schema, tx)
+    }
+
+    pub fn datoms(&self, sqlite: &rusqlite::Connection) -> Result<Vec<Datom>> {
+        let metadata = self.current_metadata(sqlite)?;
+        lookup_datoms(sqlite, &*metadata.schema)
+    }
+
+    /// Take a SQLite transaction.  This takes `&self` so that a `SharedStore` can write through a
+    /// `Conn` that its readers share; the public entry points take `&mut self`.
+    pub(crate) fn begin_transaction_with_behavior<'m, 'conn>(&'m self, sqlite: &'conn mut rusqlite::Connection, behavior: TransactionBehavior) -> Result<InProgress<'m, 'conn>> {
+        let tx = sqlite.transaction_with_behavior(behavior).map_err(|e| self.describe_busy(e))?;
         let (current_generation, current_partition_map, current_schema, cache_cow) =
         {
             // The mutex is taken during this block.
-            let ref current: Metadata = *self.metadata.lock().unwrap();
+            let ref current: Metadata = *self.current_metadata(&tx)?;
             (current.generation,
              // Expensive, but the partition map is updated after every committed transaction.
              current.partition_map.clone(),
@@ -283,11 +513,39 @@ impl Conn {
             schema: (*current_schema).clone(),
             cache: InProgressSQLiteAttributeCache::from_cache(cache_cow),
             use_caching: true,
+            redundant_assertions: RedundantAssertions::default(),
+            large_value_threshold: None,
+            unknown_attributes: UnknownAttributes::default(),
+            tx_functions: self.tx_functions.lock().unwrap().clone(),
             tx_observer: &self.tx_observer_service,
             tx_observer_watcher: InProgressObserverTransactWatcher::new(),
+            wrote: false,
+            write_holder: match (behavior, &self.path) {
+                (TransactionBehavior::Deferred, _) |
+                (_, &None) => None,
+                (_, &Some(ref path)) => Some(WriteHolderGuard::new(path.clone())),
+            },
+            interactive: match (behavior, &self.path) {
+                (TransactionBehavior::Deferred, _) |
+                (_, &None) => None,
+                (_, &Some(ref path)) => Some(InteractiveGuard::new(path.clone())),
+            },
         })
     }
 
+    /// If `error` is SQLite refusing a transaction because the database is busy, and we know which
+    /// writer in this process is responsible, say so.
+    fn describe_busy(&self, error: rusqlite::Error) -> MentatError {
+        if let rusqlite::Error::SqliteFailure(ref e, _) = error {
+            if e.code == rusqlite::ErrorCode::DatabaseBusy {
+                if let Some(described) = self.path.as_ref().and_then(|p| write_transaction_in_progress(p)) {
+                    return described;
+                }
+            }
+        }
+        error.into()
+    }
+
     // Helper to avoid passing connections around.
     // Make both args mutable so that we can't have parallel access.
     pub fn begin_read<'m, 'conn>(&'m mut self, sqlite: &'conn mut rusqlite::Connection) -> Result<InProgressRead<'m, 'conn>> {
@@ -333,9 +591,12 @@ impl Conn {
     /// The attribute should be a namespaced string: e.g., `:foo/bar`.
     /// `cache_action` determines if the attribute should be added or removed from the cache.
     /// CacheAction::Add is idempotent - each attribute is only added once.
-    /// CacheAction::Remove throws an error if the attribute does not currently exist in the cache.
-    pub fn cache(&mut self,
-                 sqlite: &mut rusqlite::Connection,
+    /// CacheAction::Deregister is a no-op if the attribute is not currently cached.
+    ///
+    /// The cache is shared by every user of this `Conn`, so this takes `&self`: registration
+    /// happens under the metadata lock, and is seen by all subsequent reads and transactions.
+    pub fn cache(&self,
+                 sqlite: &rusqlite::Connection,
                  schema: &Schema,
                  attribute: &Keyword,
                  cache_direction: CacheDirection,
@@ -366,6 +627,77 @@ impl Conn {
         }
     }
 
+    /// Create an index of the datoms of `attribute`, ordered by `direction`, unless there is one
+    /// already.  Queries run through this `Conn` that use `attribute` then read from that index.
+    /// This is worth doing for a few frequently-queried attributes of a large store: each index
+    /// takes space, and time to keep up to date when transacting.
+    pub fn ensure_attribute_index(&self,
+                                  sqlite: &rusqlite::Connection,
+                                  attribute: &Keyword,
+                                  direction: IndexDirection) -> Result<()> {
+        let mut metadata = self.metadata.lock().unwrap();
+        let attribute_entid: Entid;
+
+        // Immutable borrow of metadata.
+        {
+            attribute_entid = metadata.schema
+                                      .attribute_for_ident(&attribute)
+                                      .ok_or_else(|| MentatError::UnknownAttribute(attribute.to_string()))?.1.into();
+        }
+
+        db::ensure_attribute_index(sqlite, attribute_entid, direction)?;
+        metadata.indexes.insert(attribute_entid, direction);
+        Ok(())
+    }
+
+    /// Drop the index that `ensure_attribute_index` created, if there is one.
+    pub fn drop_attribute_index(&self,
+                                sqlite: &rusqlite::Connection,
+                                attribute: &Keyword,
+                                direction: IndexDirection) -> Result<()> {
+        let mut metadata = self.metadata.lock().unwrap();
+        let attribute_entid: Entid;
+
+        // Immutable borrow of metadata.
+        {
+            attribute_entid = metadata.schema
+                                      .attribute_for_ident(&attribute)
+                                      .ok_or_else(|| MentatError::UnknownAttribute(attribute.to_string()))?.1.into();
+        }
+
+        // Stop reading from the index before it goes.
+        metadata.indexes.remove(attribute_entid, direction);
+        db::drop_attribute_index(sqlite, attribute_entid, direction)?;
+        Ok(())
+    }
+
+    /// Return each attribute with an index of its own, and the direction of that index.
+    pub fn attribute_indexes(&self) -> Vec<(Keyword, IndexDirection)> {
+        let metadata = self.metadata.lock().unwrap();
+        metadata.indexes
+                .iter()
+                .filter_map(|&(a, direction)| metadata.schema.get_ident(a).map(|ident| (ident.clone(), direction)))
+                .collect()
+    }
+
+    /// Return each cached attribute, and the direction in which it is cached.
+    pub fn cached_attributes(&self) -> BTreeMap<Keyword, CacheDirection> {
+        let metadata = self.metadata.lock().unwrap();
+        let cache = &metadata.attribute_cache;
+        let forward = cache.forward_cached_attributes();
+        let reverse = cache.reverse_cached_attributes();
+        forward.union(reverse)
+               .filter_map(|a| {
+                   let direction = match (forward.contains(a), reverse.contains(a)) {
+                       (true, true) => CacheDirection::Both,
+                       (true, false) => CacheDirection::Forward,
+                       _ => CacheDirection::Reverse,
+                   };
+                   metadata.schema.get_ident(*a).map(|ident| (ident.clone(), direction))
+               })
+               .collect()
+    }
+
     pub fn register_observer(&mut self, key: String, observer: Arc<TxObserver>) {
         self.tx_observer_service.lock().unwrap().register(key, observer);
     }
@@ -373,6 +705,32 @@ impl Conn {
     pub fn unregister_observer(&mut self, key: &String) {
         self.tx_observer_service.lock().unwrap().deregister(key);
     }
+
+    /// Register `function` as the transaction function `name`, so that transaction data can call
+    /// it like `[:my/fn arg1 arg2]`.  Transactions already in progress don't see it.  Registering
+    /// another function with the same name replaces this one.
+    pub fn register_tx_function<F>(&mut self, name: Keyword, function: F)
+    where F: Fn(&InProgress, &[edn::Value]) -> Result<Vec<edn::entities::Entity<TypedValue>>> + 'static + Send + Sync {
+        self.tx_functions.lock().unwrap().insert(name, Arc::new(function));
+    }
+
+    pub fn unregister_tx_function(&mut self, name: &Keyword) {
+        self.tx_functions.lock().unwrap().remove(name);
+    }
+
+    /// Call `callback` after each committed transaction that changes any of `attributes`, with
+    /// the transaction's report and the attributes it changed.  Callbacks run on a notification
+    /// thread, in commit order, so they mustn't expect to be called before `commit` returns.
+    ///
+    /// Registering another observer with the same `key` replaces this one.
+    pub fn register_tx_observer<F>(&mut self, key: String, attributes: AttributeSet, callback: F)
+    where F: Fn(&str, &TxReport, &AttributeSet) + 'static + Send + Sync {
+        self.register_observer(key, Arc::new(TxObserver::for_each_transaction(attributes, callback)));
+    }
+
+    pub fn unregister_tx_observer(&mut self, key: &String) {
+        self.unregister_observer(key);
+    }
 }
 
 #[cfg(test)]
@@ -404,6 +762,8 @@ mod tests {
         QueryResults,
     };
 
+    use ::temp_store::TempStoreFile;
+
     use mentat_db::USER0;
 
     use mentat_transaction::{
@@ -511,6 +871,219 @@ mod tests {
         assert_eq!(tempid_offset + 3, tempid_offset_after);
     }
 
+    #[test]
+    fn test_redundant_assertions() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        conn.transact(&mut sqlite, r#"[
+            [:db/add "t" :db/ident :foo/tag]
+            [:db/add "t" :db/valueType :db.type/string]
+            [:db/add "t" :db/cardinality :db.cardinality/many]
+        ]"#).expect("transacted schema");
+        let e = conn.transact(&mut sqlite, r#"[[:db/add "e" :foo/tag "x"]]"#)
+                    .expect("transacted")
+                    .tempids["e"];
+
+        let count_tx_datoms = |sqlite: &rusqlite::Connection, tx: Entid| -> i64 {
+            sqlite.query_row("SELECT count(*) FROM transactions WHERE tx = ? AND e IS NOT ?",
+                             &[&tx, &tx], |row| row.get(0))
+                  .expect("counted")
+        };
+
+        // By default, re-asserting an existing datom isn't recorded.
+        let t = format!("[[:db/add {} :foo/tag \"x\"]]", e);
+        let report = conn.transact(&mut sqlite, t.as_str()).expect("transacted");
+        assert_eq!(count_tx_datoms(&sqlite, report.tx_id), 0);
+
+        // But it can be.
+        let report = {
+            let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            in_progress.redundant_assertions(RedundantAssertions::Record);
+            let report = in_progress.transact(t.as_str()).expect("transacted");
+            in_progress.commit().expect("committed");
+            report
+        };
+        assert_eq!(count_tx_datoms(&sqlite, report.tx_id), 1);
+
+        // Either way, the store is unchanged.
+        let values = conn.q_once(&sqlite, "[:find [?v ...] :where [_ :foo/tag ?v]]", None)
+                         .into_coll_result()
+                         .expect("queried");
+        assert_eq!(values, vec!["x".into()]);
+    }
+
+    #[test]
+    fn test_large_value_threshold() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        conn.transact(&mut sqlite, r#"[
+            {:db/ident :foo/key :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+            {:db/ident :foo/body :db/valueType :db.type/string :db/cardinality :db.cardinality/many}
+            {:db/ident :foo/other :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+
+        // Sorts after "short", and is stored inline before it's ever offloaded.
+        let long = "the value, which is much longer than the threshold";
+        let t = format!(r#"[{{:db/id "o" :foo/other "{}"}}]"#, long);
+        let other = conn.transact(&mut sqlite, t.as_str()).expect("transacted").tempids["o"];
+
+        let t = format!(r#"[{{:db/id "e" :foo/key "{}" :foo/body ["{}" "short"]}}]"#, long, long);
+        let e = {
+            let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            in_progress.large_value_threshold(Some(10));
+            let report = in_progress.transact(t.as_str()).expect("transacted");
+            in_progress.commit().expect("committed");
+            report.tempids["e"]
+        };
+
+        // The long value is stored once, out of line.
+        let offloaded: i64 = sqlite.query_row("SELECT count(*) FROM large_values", &[], |row| row.get(0)).expect("counted");
+        assert_eq!(offloaded, 1);
+        let inline: i64 = sqlite.query_row("SELECT count(*) FROM datoms WHERE e = ? AND typeof(v) = 'text'", &[&e], |row| row.get(0)).expect("counted");
+        assert_eq!(inline, 1);
+
+        // It reads back transparently, and matches by value, even without a threshold.
+        let q = format!(r#"[:find ?e . :where [?e :foo/key "{}"]]"#, long);
+        let found = conn.q_once(&sqlite, q.as_str(), None).into_scalar_result().expect("queried");
+        assert_eq!(found, Some(TypedValue::Ref(e).into()));
+
+        // The copy stored inline still matches, as does an input.
+        let q = format!(r#"[:find ?e . :where [?e :foo/other "{}"]]"#, long);
+        let found = conn.q_once(&sqlite, q.as_str(), None).into_scalar_result().expect("queried");
+        assert_eq!(found, Some(TypedValue::Ref(other).into()));
+
+        let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?v"), TypedValue::typed_string(long))]);
+        let found = conn.q_once(&sqlite, "[:find [?e ...] :in ?v :where [?e _ ?v] :order ?e]", inputs)
+                        .into_coll_result()
+                        .expect("queried");
+        assert_eq!(found, vec![TypedValue::Ref(other).into(), TypedValue::Ref(e).into()]);
+
+        // Offloaded values sort and match string predicates by their text.
+        let bodies = conn.q_once(&sqlite, "[:find [?v ...] :where [_ :foo/body ?v] :order ?v]", None)
+                         .into_coll_result()
+                         .expect("queried");
+        assert_eq!(bodies, vec![TypedValue::typed_string("short").into(), TypedValue::typed_string(long).into()]);
+
+        let bodies = conn.q_once(&sqlite, r#"[:find [?v ...] :where [_ :foo/body ?v] [(str-includes? ?v "which is")]]"#, None)
+                         .into_coll_result()
+                         .expect("queried");
+        assert_eq!(bodies, vec![TypedValue::typed_string(long).into()]);
+
+        let kw = Keyword::namespaced("foo", "key");
+        let key = conn.lookup_value_for_attribute(&sqlite, e, &kw).expect("looked up");
+        assert_eq!(key, Some(TypedValue::typed_string(long)));
+
+        // Including through the attribute cache.
+        let schema = conn.current_schema();
+        conn.cache(&mut sqlite, &schema, &kw, CacheDirection::Forward, CacheAction::Register).expect("cached");
+        let key = conn.lookup_value_for_attribute(&sqlite, e, &kw).expect("looked up");
+        assert_eq!(key, Some(TypedValue::typed_string(long)));
+
+        // Upserts and retractions see the offloaded value, too.
+        let t = format!(r#"[{{:db/id "u" :foo/key "{}" :foo/body "more"}} [:db/retract "u" :foo/body "{}"]]"#, long, long);
+        let report = conn.transact(&mut sqlite, t.as_str()).expect("transacted");
+        assert_eq!(report.tempids["u"], e);
+
+        let bodies = conn.q_once(&sqlite, "[:find [?v ...] :where [_ :foo/body ?v] :order ?v]", None)
+                         .into_coll_result()
+                         .expect("queried");
+        assert_eq!(bodies, vec![TypedValue::typed_string("more").into(), TypedValue::typed_string("short").into()]);
+    }
+
+    #[test]
+    fn test_q_iter() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        conn.transact(&mut sqlite, r#"[
+            {:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+        conn.transact(&mut sqlite, "[{:foo/n 1} {:foo/n 2} {:foo/n 3} {:foo/n 4}]").expect("transacted data");
+
+        let in_progress = conn.begin_read(&mut sqlite).expect("begun successfully");
+
+        // Rows are projected as they're read, so we can stop early.
+        let rows = in_progress.q_iter("[:find ?e ?n :where [?e :foo/n ?n] :order ?n]", None, |rows| {
+            rows.take(2).map(|row| row.map(|r| r[1].clone())).collect::<Result<Vec<_>>>()
+        }).expect("queried");
+        assert_eq!(rows, vec![Binding::from(1), Binding::from(2)]);
+
+        // Every kind of find spec yields rows.
+        let rows = in_progress.q_iter("[:find [?n ...] :where [_ :foo/n ?n] :order (desc ?n)]", None, |rows| {
+            rows.collect::<Result<Vec<_>>>()
+        }).expect("queried");
+        assert_eq!(rows, vec![vec![Binding::from(4)], vec![Binding::from(3)], vec![Binding::from(2)], vec![Binding::from(1)]]);
+
+        let rows = in_progress.q_iter("[:find (max ?n) . :where [_ :foo/n ?n]]", None, |rows| {
+            rows.collect::<Result<Vec<_>>>()
+        }).expect("queried");
+        assert_eq!(rows, vec![vec![Binding::from(4)]]);
+
+        let rows = in_progress.q_iter("[:find ?n . :where [_ :foo/n ?n] [(> ?n 10)]]", None, |rows| {
+            rows.collect::<Result<Vec<_>>>()
+        }).expect("queried");
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_attributes() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        let t = r#"[{:db/id "e" :foo/name "Alice" :foo/age 32 :foo/tags ["x" "y"] :foo/friend {:db/id "f" :foo/name "Bob"}}]"#;
+
+        // By default, unknown attributes are rejected.
+        match conn.transact(&mut sqlite, t).expect_err("expected transact to fail") {
+            MentatError::DbError(e) => {
+                assert_eq!(e.to_string(),
+                           "schema constraint violation: unknown attributes:\n  entity 0 uses :foo/age, which is not an attribute\n  entity 0 uses :foo/friend, which is not an attribute\n  entity 0 uses :foo/name, which is not an attribute\n  entity 0 uses :foo/tags, which is not an attribute\n");
+            },
+            e => panic!("expected an unknown attributes error, got {:?}", e),
+        }
+
+        // But they can be installed, with definitions inferred from their values.
+        let e = {
+            let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            in_progress.unknown_attributes(UnknownAttributes::Install);
+            let report = in_progress.transact(t).expect("transacted");
+            in_progress.commit().expect("committed");
+            report.tempids["e"]
+        };
+
+        let schema = conn.current_schema();
+        let attribute = |name: &str| schema.attribute_for_ident(&Keyword::namespaced("foo", name)).expect("installed").0.clone();
+        assert_eq!((attribute("name").value_type, attribute("name").multival), (ValueType::String, false));
+        assert_eq!((attribute("age").value_type, attribute("age").multival), (ValueType::Long, false));
+        assert_eq!((attribute("tags").value_type, attribute("tags").multival), (ValueType::String, true));
+        assert_eq!((attribute("friend").value_type, attribute("friend").multival), (ValueType::Ref, false));
+
+        // The definitions are marked as provisional.
+        let q = format!("[:find (count ?a) . :where [?a :db/doc {:?}]]", mentat_db::PROVISIONAL_ATTRIBUTE_DOC);
+        let provisional = conn.q_once(&sqlite, q.as_str(), None)
+                              .into_scalar_result()
+                              .expect("queried");
+        assert_eq!(provisional, Some(TypedValue::Long(4).into()));
+
+        let friend = conn.q_once(&sqlite, "[:find ?name . :in ?e :where [?e :foo/friend ?f] [?f :foo/name ?name]]",
+                                 QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?e"), TypedValue::Ref(e))]))
+                         .into_scalar_result()
+                         .expect("queried");
+        assert_eq!(friend, Some("Bob".into()));
+
+        // Values of disagreeing types can't be inferred.
+        let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+        in_progress.unknown_attributes(UnknownAttributes::Install);
+        match in_progress.transact(r#"[[:db/add "x" :bar/v 1] [:db/add "y" :bar/v "1"]]"#).expect_err("expected transact to fail") {
+            MentatError::DbError(e) => {
+                assert_eq!(e.to_string(),
+                           "schema constraint violation: unknown attributes:\n  entity 0 uses :bar/v, which is not an attribute\n  entity 1 uses :bar/v, which is not an attribute\n");
+            },
+            e => panic!("expected an unknown attributes error, got {:?}", e),
+        }
+    }
+
     #[test]
     fn test_simple_prepared_query() {
         let mut c = db::new_connection("").expect("Couldn't open conn.");
@@ -785,4 +1358,174 @@ mod tests {
         assert!(conn.current_cache().is_attribute_cached_forward(db_ident));
         assert!(conn.current_cache().is_attribute_cached_forward(db_type));
     }
+
+    #[test]
+    fn test_competing_write_transaction_is_described() {
+        let file = TempStoreFile::new();
+        let path = file.path();
+
+        let mut sqlite1 = db::new_connection(&path).expect("opened");
+        let mut conn1 = Conn::connect(&mut sqlite1).expect("connected");
+        let mut sqlite2 = db::new_connection(&path).expect("opened");
+        let mut conn2 = Conn::connect(&mut sqlite2).expect("connected");
+
+        {
+            let _ip = conn1.begin_transaction(&mut sqlite1).expect("began");
+
+            match conn2.begin_transaction(&mut sqlite2) {
+                Err(MentatError::WriteTransactionInProgress(thread, _age, _backtrace)) => {
+                    assert!(thread.contains(&format!("{:?}", ::std::thread::current().id())));
+                },
+                Err(e) => panic!("expected write transaction error, got {:?}", e),
+                Ok(_) => panic!("expected write transaction error"),
+            }
+
+            // Reads don't compete for the write lock.
+            conn2.begin_read(&mut sqlite2).expect("began read");
+        }
+
+        // Once the first writer is gone, the second can proceed.
+        conn2.begin_transaction(&mut sqlite2).expect("began").commit().expect("committed");
+    }
+
+    #[test]
+    fn test_external_writes_are_seen() {
+        let file = TempStoreFile::new();
+        let path = file.path();
+
+        let mut sqlite1 = db::new_connection(&path).expect("opened");
+        let mut conn1 = Conn::connect(&mut sqlite1).expect("connected");
+        let mut sqlite2 = db::new_connection(&path).expect("opened");
+        let mut conn2 = Conn::connect(&mut sqlite2).expect("connected");
+
+        let count = "[:find (count ?e) . :where [?e :foo/bar _]]";
+
+        // A schema change and some data, written through the first connection.
+        {
+            let mut in_progress = conn1.begin_transaction(&mut sqlite1).expect("began");
+            in_progress.transact(r#"[{:db/ident :foo/bar :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+                                     {:db/ident :foo/baz :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).expect("transacted schema");
+            in_progress.transact("[{:foo/bar 1} {:foo/bar 2}]").expect("transacted data");
+            in_progress.commit().expect("committed");
+        }
+
+        // The second connection notices, and loads the new schema.
+        assert_eq!(conn2.q_once(&sqlite2, count, None).expect("queried").into_scalar().expect("scalar"),
+                   Some(Binding::Scalar(TypedValue::Long(2))));
+        assert!(conn2.current_schema().attribute_for_ident(&kw!(:foo/bar)).is_some());
+        let bar = conn2.current_schema().get_entid(&kw!(:foo/bar)).expect("entid").0;
+
+        // Its cache is repopulated after further external writes.
+        conn2.cache(&sqlite2, &conn2.current_schema(), &kw!(:foo/bar), CacheDirection::Forward, CacheAction::Register).expect("cached");
+        let e = {
+            let mut in_progress = conn1.begin_transaction(&mut sqlite1).expect("began");
+            let report = in_progress.transact(r#"[{:db/id "e" :foo/bar 3}]"#).expect("transacted data");
+            in_progress.commit().expect("committed");
+            report.tempids["e"]
+        };
+        assert_eq!(conn2.q_once(&sqlite2, count, None).expect("queried").into_scalar().expect("scalar"),
+                   Some(Binding::Scalar(TypedValue::Long(3))));
+        assert!(conn2.current_cache().is_attribute_cached_forward(bar));
+        assert_eq!(conn2.current_cache().get_value_for_entid(&*conn2.current_schema(), bar, e),
+                   Some(&TypedValue::Long(3)));
+
+        // Entids allocated by the first connection aren't reused by the second.
+        {
+            let mut in_progress = conn2.begin_transaction(&mut sqlite2).expect("began");
+            in_progress.transact("[{:foo/bar 4}]").expect("transacted data");
+            in_progress.commit().expect("committed");
+        }
+        assert_eq!(conn1.q_once(&sqlite1, count, None).expect("queried").into_scalar().expect("scalar"),
+                   Some(Binding::Scalar(TypedValue::Long(4))));
+
+        // Removing an attribute is reported once; after that, the new schema is in use.
+        {
+            let mut in_progress = conn1.begin_transaction(&mut sqlite1).expect("began");
+            in_progress.transact("[[:db/retract :foo/baz :db/valueType :db.type/string]
+                                   [:db/retract :foo/baz :db/cardinality :db.cardinality/one]
+                                   [:db/retract :foo/baz :db/ident :foo/baz]]").expect("transacted schema");
+            in_progress.commit().expect("committed");
+        }
+        match conn2.q_once(&sqlite2, count, None) {
+            Err(MentatError::IncompatibleSchemaChange(attributes)) => assert_eq!(attributes, vec![":foo/baz".to_string()]),
+            Err(e) => panic!("expected incompatible schema change, got {:?}", e),
+            Ok(_) => panic!("expected incompatible schema change"),
+        }
+        assert!(conn2.current_schema().attribute_for_ident(&kw!(:foo/baz)).is_none());
+        conn2.q_once(&sqlite2, count, None).expect("queried");
+    }
+
+    #[test]
+    fn test_prepared_query_schema_change() {
+        let file = TempStoreFile::new();
+        let path = file.path();
+
+        let mut sqlite1 = db::new_connection(&path).expect("opened");
+        let mut conn1 = Conn::connect(&mut sqlite1).expect("connected");
+        let mut sqlite2 = db::new_connection(&path).expect("opened");
+        let conn2 = Conn::connect(&mut sqlite2).expect("connected");
+
+        {
+            let mut in_progress = conn1.begin_transaction(&mut sqlite1).expect("began");
+            in_progress.transact(r#"[{:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+                                     {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}]"#).expect("transacted schema");
+            in_progress.transact(r#"[{:foo/name "Alice" :foo/age 30} {:foo/name "Bob" :foo/age 40}]"#).expect("transacted data");
+            in_progress.commit().expect("committed");
+        }
+
+        let age = |name: &str| QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?name"), TypedValue::typed_string(name))]);
+        let mut prepared = conn2.q_prepare(&sqlite2, "[:find ?age . :in ?name :where [?e :foo/name ?name] [?e :foo/age ?age]]", None)
+                                .expect("prepared");
+
+        // The statement is rebound for each run.
+        assert_eq!(prepared.run(age("Alice")).expect("ran").into_scalar().expect("scalar"),
+                   Some(Binding::Scalar(TypedValue::Long(30))));
+        assert_eq!(prepared.run(age("Bob")).expect("ran").into_scalar().expect("scalar"),
+                   Some(Binding::Scalar(TypedValue::Long(40))));
+
+        // New data doesn't invalidate the query...
+        {
+            let mut in_progress = conn1.begin_transaction(&mut sqlite1).expect("began");
+            in_progress.transact(r#"[{:foo/name "Carol" :foo/age 50}]"#).expect("transacted data");
+            in_progress.commit().expect("committed");
+        }
+        assert_eq!(prepared.run(age("Carol")).expect("ran").into_scalar().expect("scalar"),
+                   Some(Binding::Scalar(TypedValue::Long(50))));
+
+        // ... but a new schema does.
+        {
+            let mut in_progress = conn1.begin_transaction(&mut sqlite1).expect("began");
+            in_progress.transact(r#"[{:db/ident :foo/email :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).expect("transacted schema");
+            in_progress.commit().expect("committed");
+        }
+        match prepared.run(age("Alice")) {
+            Err(MentatError::PreparedQuerySchemaMismatch) => {},
+            x => panic!("expected a schema mismatch, got {:?}", x.map(|o| o.results)),
+        }
+        drop(prepared);
+
+        let mut prepared = conn2.q_prepare(&sqlite2, "[:find ?age . :in ?name :where [?e :foo/name ?name] [?e :foo/age ?age]]", None)
+                                .expect("prepared");
+        assert_eq!(prepared.run(age("Alice")).expect("ran").into_scalar().expect("scalar"),
+                   Some(Binding::Scalar(TypedValue::Long(30))));
+
+        // Nor would it match the first string to be offloaded.
+        let long = "Dorothy, whose name is much longer than the threshold";
+        {
+            let mut in_progress = conn1.begin_transaction(&mut sqlite1).expect("began");
+            in_progress.large_value_threshold(Some(10));
+            in_progress.transact(format!(r#"[{{:foo/name "{}" :foo/age 60}}]"#, long)).expect("transacted data");
+            in_progress.commit().expect("committed");
+        }
+        match prepared.run(age(long)) {
+            Err(MentatError::PreparedQuerySchemaMismatch) => {},
+            x => panic!("expected a schema mismatch, got {:?}", x.map(|o| o.results)),
+        }
+        drop(prepared);
+
+        let mut prepared = conn2.q_prepare(&sqlite2, "[:find ?age . :in ?name :where [?e :foo/name ?name] [?e :foo/age ?age]]", None)
+                                .expect("prepared");
+        assert_eq!(prepared.run(age(long)).expect("ran").into_scalar().expect("scalar"),
+                   Some(Binding::Scalar(TypedValue::Long(60))));
+    }
 }
@@ -0,0 +1,411 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Conflict-free replicated data types, built out of ordinary datoms.
+//!
+//! Two devices that change the same cardinality-one datom while apart will conflict when they
+//! sync.  The types here are laid out so that they never do: every device only ever asserts
+//! datoms that no other device asserts, and the value is computed from all of them when read.
+//!
+//! - A *grow-only counter* keeps one slot per device.  `increment_counter` only touches the
+//!   calling device's slot, and the counter's value is the sum of every slot.
+//!
+//! - An *observed-remove set* of strings records each addition as a new element with a unique
+//!   tag.  `remove_from_set` marks the elements it can see as removed; an addition made
+//!   concurrently on another device has a tag this device hasn't seen, so it survives.  A value is
+//!   a member if any of its elements hasn't been removed.
+//!
+//! Counters and sets are named by strings, and their names, slots and element tags are unique
+//! identities, so entities created independently on two devices upsert into one.  Device IDs are
+//! up to the application; they must be stable and distinct for each device.
+//!
+//! ```
+//! extern crate mentat;
+//!
+//! use mentat::Store;
+//! use mentat::crdt::{
+//!     ReadCrdts,
+//!     TransactCrdts,
+//! };
+//!
+//! fn main() {
+//!     let mut store = Store::open("").expect("opened");
+//!     let mut in_progress = store.begin_transaction().expect("began");
+//!     in_progress.increment_counter("visits", "laptop", 2).expect("incremented");
+//!     in_progress.add_to_set("tags", "laptop", "rust").expect("added");
+//!     assert_eq!(in_progress.counter_value("visits").expect("read"), 2);
+//!     assert!(in_progress.set_members("tags").expect("read").contains("rust"));
+//!     in_progress.commit().expect("committed");
+//! }
+//! ```
+
+use std::collections::{
+    BTreeSet,
+};
+
+use uuid::Uuid;
+
+use core_traits::attribute::{
+    Unique,
+};
+
+use edn;
+
+use ::{
+    Binding,
+    HasSchema,
+    IntoResult,
+    Keyword,
+    QueryInputs,
+    TypedValue,
+    ValueType,
+};
+
+use ::errors::{
+    Result,
+};
+
+use mentat_transaction::{
+    EntityAttributes,
+    InProgress,
+    Queryable,
+    ToEntity,
+};
+
+use vocabulary::{
+    AttributeBuilder,
+    Definition,
+    VersionedStore,
+};
+
+lazy_static! {
+    static ref COUNTER_NAME: Keyword = kw!(:crdt.counter/name);
+    static ref COUNTER_SLOT: Keyword = kw!(:crdt.counter/slot);
+    static ref COUNTER_OF: Keyword = kw!(:crdt.counter/of);
+    static ref COUNTER_DEVICE: Keyword = kw!(:crdt.counter/device);
+    static ref COUNTER_COUNT: Keyword = kw!(:crdt.counter/count);
+
+    static ref SET_NAME: Keyword = kw!(:crdt.set/name);
+    static ref SET_TAG: Keyword = kw!(:crdt.set/tag);
+    static ref SET_OF: Keyword = kw!(:crdt.set/of);
+    static ref SET_VALUE: Keyword = kw!(:crdt.set/value);
+    static ref SET_DEVICE: Keyword = kw!(:crdt.set/device);
+    static ref SET_REMOVED: Keyword = kw!(:crdt.set/removed);
+}
+
+fn identity(value_type: ValueType) -> ::Attribute {
+    AttributeBuilder::helpful()
+        .value_type(value_type)
+        .multival(false)
+        .unique(Unique::Identity)
+        .build()
+}
+
+fn plain(value_type: ValueType) -> ::Attribute {
+    AttributeBuilder::helpful()
+        .value_type(value_type)
+        .multival(false)
+        .build()
+}
+
+/// The vocabulary for grow-only counters.  `increment_counter` installs it as needed.
+pub fn counter_vocabulary() -> Definition {
+    Definition::new(kw!(:org.mozilla.crdt/counter), 1, vec![
+        (COUNTER_NAME.clone(), identity(ValueType::String)),
+        (COUNTER_SLOT.clone(), identity(ValueType::String)),
+        (COUNTER_OF.clone(), plain(ValueType::Ref)),
+        (COUNTER_DEVICE.clone(), plain(ValueType::String)),
+        (COUNTER_COUNT.clone(), plain(ValueType::Long)),
+    ])
+}
+
+/// The vocabulary for observed-remove sets.  `add_to_set` installs it as needed.
+pub fn set_vocabulary() -> Definition {
+    Definition::new(kw!(:org.mozilla.crdt/set), 1, vec![
+        (SET_NAME.clone(), identity(ValueType::String)),
+        (SET_TAG.clone(), identity(ValueType::String)),
+        (SET_OF.clone(), plain(ValueType::Ref)),
+        (SET_VALUE.clone(), AttributeBuilder::helpful().value_type(ValueType::String).multival(false).index(true).build()),
+        (SET_DEVICE.clone(), plain(ValueType::String)),
+        (SET_REMOVED.clone(), plain(ValueType::Boolean)),
+    ])
+}
+
+/// An entity to transact, with an optional tempid.
+struct Fragment {
+    tempid: Option<String>,
+    attributes: EntityAttributes,
+}
+
+impl ToEntity for Fragment {
+    fn tempid(&self) -> Option<String> {
+        self.tempid.clone()
+    }
+
+    fn attributes(&self) -> EntityAttributes {
+        self.attributes.clone()
+    }
+}
+
+/// Name a counter's slot for a device.  Written as an EDN vector so that no choice of names can
+/// make two slots collide.
+fn slot_key(counter: &str, device: &str) -> String {
+    edn::Value::Vector(vec![edn::Value::Text(counter.to_string()),
+                            edn::Value::Text(device.to_string())]).to_string()
+}
+
+fn name_input(name: &str) -> QueryInputs {
+    QueryInputs::with_value_sequence(vec![(var!(?name), TypedValue::typed_string(name))])
+}
+
+fn into_long(binding: Option<Binding>) -> i64 {
+    match binding {
+        Some(Binding::Scalar(TypedValue::Long(count))) => count,
+        _ => 0,
+    }
+}
+
+pub trait ReadCrdts {
+    /// The value of the named grow-only counter: the sum of every device's slot.  Counters that
+    /// have never been incremented are zero.
+    fn counter_value(&self, counter: &str) -> Result<i64>;
+
+    /// How much `device` has contributed to the named counter.
+    fn counter_value_for_device(&self, counter: &str, device: &str) -> Result<i64>;
+
+    /// The members of the named observed-remove set.
+    fn set_members(&self, set: &str) -> Result<BTreeSet<String>>;
+}
+
+impl<T> ReadCrdts for T where T: HasSchema + Queryable {
+    fn counter_value(&self, counter: &str) -> Result<i64> {
+        if self.get_entid(&COUNTER_COUNT).is_none() {
+            return Ok(0);
+        }
+        let slots = self.q_once(r#"[:find ?slot ?count
+                                    :in ?name
+                                    :where [?counter :crdt.counter/name ?name]
+                                           [?slot :crdt.counter/of ?counter]
+                                           [?slot :crdt.counter/count ?count]]"#,
+                                name_input(counter))
+                        .into_rel_result()?;
+        Ok(slots.into_iter()
+                .map(|row| into_long(row.into_iter().nth(1)))
+                .sum())
+    }
+
+    fn counter_value_for_device(&self, counter: &str, device: &str) -> Result<i64> {
+        if self.get_entid(&COUNTER_COUNT).is_none() {
+            return Ok(0);
+        }
+        let inputs = QueryInputs::with_value_sequence(vec![(var!(?slot), TypedValue::typed_string(&slot_key(counter, device)))]);
+        let count = self.q_once(r#"[:find ?count .
+                                    :in ?slot
+                                    :where [?s :crdt.counter/slot ?slot]
+                                           [?s :crdt.counter/count ?count]]"#,
+                                inputs)
+                        .into_scalar_result()?;
+        Ok(into_long(count))
+    }
+
+    fn set_members(&self, set: &str) -> Result<BTreeSet<String>> {
+        if self.get_entid(&SET_REMOVED).is_none() {
+            return Ok(BTreeSet::new());
+        }
+        let values = self.q_once(r#"[:find [?value ...]
+                                     :in ?name
+                                     :where [?set :crdt.set/name ?name]
+                                            [?element :crdt.set/of ?set]
+                                            [?element :crdt.set/value ?value]
+                                            (not [?element :crdt.set/removed true])]"#,
+                                 name_input(set))
+                         .into_coll_result()?;
+        Ok(values.into_iter()
+                 .filter_map(|value| match value {
+                     Binding::Scalar(TypedValue::String(s)) => Some((*s).clone()),
+                     _ => None,
+                 })
+                 .collect())
+    }
+}
+
+pub trait TransactCrdts {
+    /// Add `by` to `device`'s slot of the named grow-only counter, creating the counter if
+    /// necessary, and return the counter's new value.
+    fn increment_counter(&mut self, counter: &str, device: &str, by: u32) -> Result<i64>;
+
+    /// Add `value` to the named observed-remove set, creating the set if necessary.  Adding a
+    /// value that's already a member is harmless.
+    fn add_to_set(&mut self, set: &str, device: &str, value: &str) -> Result<()>;
+
+    /// Remove every element for `value` in the named set that this store knows about, and return
+    /// how many there were.  Additions of `value` that this store hasn't yet seen are unaffected.
+    fn remove_from_set(&mut self, set: &str, value: &str) -> Result<usize>;
+}
+
+impl<'a, 'c> TransactCrdts for InProgress<'a, 'c> {
+    fn increment_counter(&mut self, counter: &str, device: &str, by: u32) -> Result<i64> {
+        self.ensure_vocabulary(&counter_vocabulary())?;
+
+        // Only this device writes this slot, so the read and the write can't race with another
+        // device, and the slot never decreases.
+        let count = self.counter_value_for_device(counter, device)? + by as i64;
+
+        let mut counter_entity = EntityAttributes::new();
+        counter_entity.add(COUNTER_NAME.clone(), counter.to_string());
+
+        let mut slot = EntityAttributes::new();
+        slot.add(COUNTER_SLOT.clone(), slot_key(counter, device));
+        slot.add_ref(COUNTER_OF.clone(), "counter");
+        slot.add(COUNTER_DEVICE.clone(), device.to_string());
+        slot.add(COUNTER_COUNT.clone(), count);
+
+        self.transact_entities(vec![
+            Fragment { tempid: Some("counter".to_string()), attributes: counter_entity }.to_entity(),
+            Fragment { tempid: None, attributes: slot }.to_entity(),
+        ])?;
+
+        self.counter_value(counter)
+    }
+
+    fn add_to_set(&mut self, set: &str, device: &str, value: &str) -> Result<()> {
+        self.ensure_vocabulary(&set_vocabulary())?;
+
+        let mut set_entity = EntityAttributes::new();
+        set_entity.add(SET_NAME.clone(), set.to_string());
+
+        let mut element = EntityAttributes::new();
+        element.add(SET_TAG.clone(), Uuid::new_v4().hyphenated().to_string());
+        element.add_ref(SET_OF.clone(), "set");
+        element.add(SET_VALUE.clone(), value.to_string());
+        element.add(SET_DEVICE.clone(), device.to_string());
+
+        self.transact_entities(vec![
+            Fragment { tempid: Some("set".to_string()), attributes: set_entity }.to_entity(),
+            Fragment { tempid: None, attributes: element }.to_entity(),
+        ])?;
+        Ok(())
+    }
+
+    fn remove_from_set(&mut self, set: &str, value: &str) -> Result<usize> {
+        if self.get_entid(&SET_REMOVED).is_none() {
+            return Ok(0);
+        }
+
+        let inputs = QueryInputs::with_value_sequence(vec![
+            (var!(?name), TypedValue::typed_string(set)),
+            (var!(?value), TypedValue::typed_string(value)),
+        ]);
+        let tags = self.q_once(r#"[:find [?tag ...]
+                                   :in ?name ?value
+                                   :where [?set :crdt.set/name ?name]
+                                          [?element :crdt.set/of ?set]
+                                          [?element :crdt.set/value ?value]
+                                          [?element :crdt.set/tag ?tag]
+                                          (not [?element :crdt.set/removed true])]"#,
+                               inputs)
+                       .into_coll_result()?;
+
+        // Tombstones only ever go from absent to true, so they merge with anything.
+        let removals: Vec<_> = tags.into_iter()
+                                   .filter_map(|tag| tag.into_scalar())
+                                   .map(|tag| {
+                                       let mut element = EntityAttributes::new();
+                                       element.add(SET_TAG.clone(), tag);
+                                       element.add(SET_REMOVED.clone(), true);
+                                       Fragment { tempid: None, attributes: element }.to_entity()
+                                   })
+                                   .collect();
+        let removed = removals.len();
+        if removed > 0 {
+            self.transact_entities(removals)?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ::{
+        Store,
+    };
+
+    #[test]
+    fn test_counter() {
+        let mut store = Store::open("").expect("opened");
+        assert_eq!(store.begin_read().expect("began").counter_value("visits").expect("read"), 0);
+
+        let mut ip = store.begin_transaction().expect("began");
+        assert_eq!(ip.increment_counter("visits", "laptop", 2).expect("incremented"), 2);
+        assert_eq!(ip.increment_counter("visits", "phone", 3).expect("incremented"), 5);
+        assert_eq!(ip.increment_counter("visits", "laptop", 1).expect("incremented"), 6);
+        assert_eq!(ip.increment_counter("other", "laptop", 1).expect("incremented"), 1);
+
+        assert_eq!(ip.counter_value_for_device("visits", "laptop").expect("read"), 3);
+        assert_eq!(ip.counter_value_for_device("visits", "phone").expect("read"), 3);
+        assert_eq!(ip.counter_value_for_device("visits", "tablet").expect("read"), 0);
+        ip.commit().expect("committed");
+
+        // Names that look like slot separators don't collide.
+        let mut ip = store.begin_transaction().expect("began");
+        ip.increment_counter("a b", "c", 1).expect("incremented");
+        ip.increment_counter("a", "b c", 10).expect("incremented");
+        assert_eq!(ip.counter_value("a b").expect("read"), 1);
+        assert_eq!(ip.counter_value("a").expect("read"), 10);
+    }
+
+    #[test]
+    fn test_observed_remove_set() {
+        let mut store = Store::open("").expect("opened");
+
+        let mut ip = store.begin_transaction().expect("began");
+        assert_eq!(ip.remove_from_set("tags", "rust").expect("removed"), 0);
+        ip.add_to_set("tags", "laptop", "rust").expect("added");
+        ip.add_to_set("tags", "phone", "rust").expect("added");
+        ip.add_to_set("tags", "phone", "sqlite").expect("added");
+        assert_eq!(ip.set_members("tags").expect("read"),
+                   vec!["rust".to_string(), "sqlite".to_string()].into_iter().collect());
+
+        // Removing a value removes every element for it that we've seen.
+        assert_eq!(ip.remove_from_set("tags", "rust").expect("removed"), 2);
+        assert_eq!(ip.remove_from_set("tags", "rust").expect("removed"), 0);
+        assert_eq!(ip.set_members("tags").expect("read"),
+                   vec!["sqlite".to_string()].into_iter().collect());
+
+        // Adding it again brings it back.
+        ip.add_to_set("tags", "laptop", "rust").expect("added");
+        assert!(ip.set_members("tags").expect("read").contains("rust"));
+        assert!(ip.set_members("other").expect("read").is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_add_survives_remove() {
+        let mut store = Store::open("").expect("opened");
+        let mut ip = store.begin_transaction().expect("began");
+        ip.add_to_set("tags", "laptop", "rust").expect("added");
+
+        // The phone removes the element it has seen.  Meanwhile the laptop, not having seen the
+        // removal, adds the value again; that element arrives as a fresh tag when they sync.
+        let seen = ip.q_once("[:find [?tag ...] :where [?e :crdt.set/tag ?tag]]", None)
+                     .into_coll_result()
+                     .expect("queried");
+        ip.add_to_set("tags", "laptop", "rust").expect("added");
+        for tag in seen {
+            let mut element = EntityAttributes::new();
+            element.add(SET_TAG.clone(), tag.into_scalar().expect("tag"));
+            element.add(SET_REMOVED.clone(), true);
+            ip.transact_entities(vec![Fragment { tempid: None, attributes: element }.to_entity()]).expect("removed");
+        }
+
+        assert!(ip.set_members("tags").expect("read").contains("rust"));
+    }
+}
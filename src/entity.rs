@@ -0,0 +1,210 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Typed entities: Rust structs that map onto a single entity each.
+//!
+//! `ToEntity` turns a value into transaction data.  `MentatEntity` goes the other way, reading a
+//! value back out of a pull result, and describes the attributes involved so that they can be
+//! installed as a vocabulary.  Both can be derived:
+//!
+//! ```ignore
+//! #[derive(MentatEntity)]
+//! #[mentat(namespace = "person")]
+//! struct Person {
+//!     #[mentat(tempid, unique = "identity")]
+//!     handle: String,
+//!     age: Option<i64>,
+//! }
+//!
+//! in_progress.ensure_vocabulary(&Person::vocabulary(kw!(:example/people), 1))?;
+//! in_progress.transact_entities(vec![person.to_entity()])?;
+//!
+//! let query = format!("[:find (pull ?e {}) . :where [?e :person/handle \"alice\"]]", Person::pull_pattern());
+//! let alice = Person::from_binding(&store.q_once(&query, None).into_scalar_result()?.unwrap())?;
+//! ```
+
+use ::{
+    Attribute,
+    BigInt,
+    Binding,
+    DateTime,
+    Keyword,
+    StructuredMap,
+    ToEntity,
+    TypedValue,
+    Utc,
+    Uuid,
+    ValueType,
+};
+
+use ::errors::{
+    MentatError,
+    Result,
+};
+
+use vocabulary::{
+    Definition,
+    Version,
+};
+
+pub use core_traits::attribute::{
+    Unique,
+};
+
+/// A Rust type that can be the value of an attribute.
+pub trait EntityValue: Sized {
+    /// The `:db/valueType` of attributes holding this type.
+    fn value_type() -> ValueType;
+
+    fn from_typed_value(value: TypedValue) -> Option<Self>;
+}
+
+impl EntityValue for String {
+    fn value_type() -> ValueType {
+        ValueType::String
+    }
+
+    fn from_typed_value(value: TypedValue) -> Option<Self> {
+        value.into_string().map(|s| (*s).clone())
+    }
+}
+
+impl EntityValue for i64 {
+    fn value_type() -> ValueType {
+        ValueType::Long
+    }
+
+    fn from_typed_value(value: TypedValue) -> Option<Self> {
+        value.into_long()
+    }
+}
+
+impl EntityValue for f64 {
+    fn value_type() -> ValueType {
+        ValueType::Double
+    }
+
+    fn from_typed_value(value: TypedValue) -> Option<Self> {
+        value.into_double()
+    }
+}
+
+impl EntityValue for bool {
+    fn value_type() -> ValueType {
+        ValueType::Boolean
+    }
+
+    fn from_typed_value(value: TypedValue) -> Option<Self> {
+        value.into_boolean()
+    }
+}
+
+impl EntityValue for Keyword {
+    fn value_type() -> ValueType {
+        ValueType::Keyword
+    }
+
+    fn from_typed_value(value: TypedValue) -> Option<Self> {
+        value.into_kw().map(|k| (*k).clone())
+    }
+}
+
+impl EntityValue for Uuid {
+    fn value_type() -> ValueType {
+        ValueType::Uuid
+    }
+
+    fn from_typed_value(value: TypedValue) -> Option<Self> {
+        value.into_uuid()
+    }
+}
+
+impl EntityValue for BigInt {
+    fn value_type() -> ValueType {
+        ValueType::BigInteger
+    }
+
+    fn from_typed_value(value: TypedValue) -> Option<Self> {
+        value.into_bigint()
+    }
+}
+
+impl EntityValue for DateTime<Utc> {
+    fn value_type() -> ValueType {
+        ValueType::Instant
+    }
+
+    fn from_typed_value(value: TypedValue) -> Option<Self> {
+        value.into_instant()
+    }
+}
+
+/// A type that maps onto a single entity, in both directions.
+///
+/// Derive this with `#[derive(MentatEntity)]` from `mentat_derive`, which also derives `ToEntity`.
+pub trait MentatEntity: ToEntity + Sized {
+    /// The attributes this type reads and writes, as they should be installed.
+    fn attribute_definitions() -> Vec<(Keyword, Attribute)>;
+
+    /// Build a value from a map of attribute values, such as a pull result or the result of
+    /// `Pullable::pull_attributes_for_entity`.
+    fn from_map(map: &StructuredMap) -> Result<Self>;
+
+    /// Build a value from a binding, which must be a map.  See `from_map`.
+    fn from_binding(binding: &Binding) -> Result<Self> {
+        match binding.as_map() {
+            Some(map) => Self::from_map(map),
+            None => bail!(MentatError::ExpectedEntityMap),
+        }
+    }
+
+    /// A pull pattern, like `[:person/handle :person/age]`, that fetches every attribute this type
+    /// reads.
+    fn pull_pattern() -> String {
+        let attributes: Vec<String> = Self::attribute_definitions().into_iter().map(|(a, _)| a.to_string()).collect();
+        format!("[{}]", attributes.join(" "))
+    }
+
+    /// A vocabulary definition that installs `attribute_definitions`.
+    fn vocabulary<N>(name: N, version: Version) -> Definition where N: Into<Keyword> {
+        Definition::new(name, version, Self::attribute_definitions())
+    }
+}
+
+fn read_scalar<T>(attribute: &Keyword, binding: &Binding) -> Result<T> where T: EntityValue {
+    binding.as_scalar()
+           .cloned()
+           .and_then(T::from_typed_value)
+           .ok_or_else(|| MentatError::UnexpectedEntityValue(attribute.to_string(), T::value_type()))
+}
+
+// The following are used by `#[derive(MentatEntity)]`.
+
+#[doc(hidden)]
+pub fn read_optional<T>(map: &StructuredMap, attribute: &Keyword) -> Result<Option<T>> where T: EntityValue {
+    match map.get(attribute) {
+        None => Ok(None),
+        Some(binding) => read_scalar(attribute, binding).map(Some),
+    }
+}
+
+#[doc(hidden)]
+pub fn read_one<T>(map: &StructuredMap, attribute: &Keyword) -> Result<T> where T: EntityValue {
+    read_optional(map, attribute)?.ok_or_else(|| MentatError::MissingEntityAttribute(attribute.to_string()))
+}
+
+#[doc(hidden)]
+pub fn read_many<T>(map: &StructuredMap, attribute: &Keyword) -> Result<Vec<T>> where T: EntityValue {
+    match map.get(attribute) {
+        None => Ok(vec![]),
+        Some(&Binding::Vec(ref bindings)) => bindings.iter().map(|b| read_scalar(attribute, b)).collect(),
+        Some(binding) => read_scalar(attribute, binding).map(|v| vec![v]),
+    }
+}
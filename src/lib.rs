@@ -10,36 +10,47 @@
 
 extern crate failure;
 
-#[macro_use]
+#[cfg_attr(feature = "store", macro_use)]
 extern crate lazy_static;
 
+#[cfg(feature = "store")]
 extern crate rusqlite;
 
 extern crate uuid;
 
 pub extern crate edn;
 extern crate mentat_core;
-#[macro_use]
+#[cfg_attr(feature = "store", macro_use)]
 extern crate core_traits;
+#[cfg(feature = "store")]
 extern crate mentat_db;
 extern crate db_traits;
 extern crate mentat_query_algebrizer;
 extern crate query_algebrizer_traits;
 extern crate mentat_query_projector;
 extern crate query_projector_traits;
+#[cfg(feature = "store")]
 extern crate mentat_query_pull;
 extern crate query_pull_traits;
 extern crate sql_traits;
 extern crate mentat_sql;
 extern crate public_traits;
+
+#[cfg(feature = "store")]
 extern crate mentat_transaction;
 
+#[cfg(feature = "store")]
+extern crate mentat_derive;
+
 #[cfg(feature = "syncable")]
 extern crate mentat_tolstoy;
 
 #[cfg(feature = "syncable")]
 extern crate tolstoy_traits;
 
+#[cfg(test)]
+extern crate tempfile;
+
 pub use core_traits::{
     Attribute,
     Binding,
@@ -54,6 +65,7 @@ pub use core_traits::{
 pub use mentat_core::{
     DateTime,
     HasSchema,
+    IndexDirection,
     Keyword,
     Schema,
     TxReport,
@@ -61,21 +73,40 @@ pub use mentat_core::{
     Uuid,
 };
 
+pub use mentat_core::metrics;
+
 pub use edn::query::{
     FindSpec,
 };
 
+#[cfg(feature = "store")]
 pub use mentat_db::{
     CORE_SCHEMA_VERSION,
     DB_SCHEMA_CORE,
     AttributeSet,
+    PROVISIONAL_ATTRIBUTE_DOC,
+    RedundantAssertions,
     TxObserver,
+    TxSnapshot,
     new_connection,
+    new_connection_with_flags,
+};
+
+#[cfg(feature = "store")]
+pub use mentat_db::maintenance::{
+    MaintenanceReport,
+};
+
+#[cfg(feature = "store")]
+pub use mentat_db::retention::{
+    Keep,
+    RetentionPolicy,
 };
 
 #[cfg(feature = "sqlcipher")]
 pub use mentat_db::{
     new_connection_with_key,
+    new_connection_with_key_and_flags,
     change_encryption_key,
 };
 
@@ -136,6 +167,7 @@ pub use public_traits::errors::{
 };
 
 pub use edn::{
+    BigInt,
     FromMicros,
     FromMillis,
     ParseError,
@@ -152,32 +184,81 @@ pub use mentat_query_projector::{
 pub use query_pull_traits::errors::PullError;
 pub use sql_traits::errors::SQLError;
 
+#[cfg(feature = "store")]
 pub use mentat_transaction::{
     Metadata,
+    QueryPriority,
 };
 
+#[cfg(feature = "store")]
 pub use mentat_transaction::query;
+#[cfg(feature = "store")]
+pub use mentat_transaction::query_plan;
+#[cfg(feature = "store")]
 pub use mentat_transaction::entity_builder;
 
+#[cfg(feature = "store")]
 pub use mentat_transaction::query::{
+    ColumnMetadata,
+    Datom,
+    DatomsBasis,
     IntoResult,
     PlainSymbol,
+    Provenance,
     QueryExecutionResult,
     QueryExplanation,
     QueryInputs,
     QueryOutput,
     QueryPlanStep,
     QueryResults,
+    QueryRows,
     RelResult,
+    TxData,
     Variable,
+    binding_to_edn,
     q_once,
 };
 
+#[cfg(feature = "store")]
+pub use mentat_transaction::query_plan::{
+    PlanNode,
+    PlanOperation,
+    QueryPlan,
+};
+
+#[cfg(not(feature = "store"))]
+pub use edn::query::{
+    PlainSymbol,
+    Variable,
+};
+
+#[cfg(not(feature = "store"))]
+pub use mentat_query_algebrizer::{
+    QueryInputs,
+};
+
+pub mod compiler;
+
+#[cfg(feature = "store")]
 pub mod conn;
+#[cfg(feature = "store")]
+pub mod crdt;
+#[cfg(feature = "store")]
+pub mod entity;
+#[cfg(feature = "store")]
 pub mod query_builder;
+#[cfg(feature = "store")]
+pub mod shared;
+#[cfg(feature = "store")]
 pub mod store;
+#[cfg(feature = "store")]
+pub mod tx_log;
+#[cfg(feature = "store")]
 pub mod vocabulary;
 
+#[cfg(all(test, feature = "store"))]
+mod temp_store;
+
 #[cfg(feature = "syncable")]
 mod sync;
 
@@ -188,27 +269,75 @@ pub use sync::{
 
 #[cfg(feature = "syncable")]
 pub use mentat_tolstoy::{
+    AttributeMerge,
+    CancellationToken,
+    Conflict,
+    ConflictResolver,
+    OursWin,
+    Resolution,
+    SyncFilter,
+    SyncOptions,
+    SyncPhase,
+    SyncProgress,
     SyncReport,
+    TheirsWin,
 };
 
+#[cfg(feature = "store")]
 pub use query_builder::{
     QueryBuilder,
 };
 
+#[cfg(feature = "store")]
+pub use tx_log::{
+    TxFollower,
+    TxLog,
+};
+
+#[cfg(feature = "store")]
 pub use conn::{
     Conn,
 };
 
+#[cfg(feature = "store")]
 pub use mentat_transaction::{
     CacheAction,
     CacheDirection,
+    DatabaseView,
+    Entity,
+    EntityAttributes,
+    ImportProgress,
+    ImportReport,
     InProgress,
     Pullable,
     Queryable,
+    ToEntity,
+    TransactionFunction,
+    UnknownAttributes,
+};
+
+#[cfg(feature = "store")]
+pub use shared::{
+    PooledRead,
+    SharedStore,
 };
 
+#[cfg(feature = "store")]
 pub use store::{
     Store,
+    StoreOptions,
+};
+
+#[cfg(feature = "store")]
+pub use entity::{
+    EntityValue,
+    MentatEntity,
+};
+
+#[cfg(feature = "store")]
+pub use mentat_derive::{
+    MentatEntity,
+    ToEntity,
 };
 
 #[cfg(test)]
@@ -0,0 +1,280 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Sharing a store between threads.
+//!
+//! A `Store` owns a single SQLite connection, and so is used by one thread at a time.  A
+//! `SharedStore` can be put in an `Arc` and used from many.  Each read takes a read-only
+//! connection from a pool, opening one if none is idle, and reads a snapshot of the store on it,
+//! so reads run alongside each other and alongside writes.  Writes take turns on a single
+//! writable connection.  This relies on SQLite's write-ahead log, so only stores backed by a file
+//! can be shared:
+//!
+//! ```ignore
+//! let store = Arc::new(SharedStore::open("/path/to/store.db")?);
+//! let reader = store.clone();
+//! thread::spawn(move || {
+//!     reader.q_once("[:find ?name :where [_ :person/name ?name]]", None)
+//! });
+//! store.transact(r#"[{:person/name "Alice"}]"#)?;
+//! ```
+
+use std::ops::{
+    Deref,
+};
+
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+use rusqlite;
+use rusqlite::{
+    TransactionBehavior,
+};
+
+use mentat_core::{
+    Keyword,
+    TxReport,
+};
+
+use mentat_db::{
+    TxObserver,
+    TxSnapshot,
+};
+
+use mentat_transaction::{
+    CacheAction,
+    CacheDirection,
+    InProgress,
+    Queryable,
+};
+
+use mentat_transaction::query::{
+    QueryInputs,
+    QueryOutput,
+};
+
+use conn::{
+    Conn,
+};
+
+use store::{
+    StoreOptions,
+};
+
+use public_traits::errors::{
+    Result,
+};
+
+/// How many read connections a `SharedStore` keeps open while they're not in use.  More can be
+/// open at once; those beyond this are closed when their reads end.
+const MAX_IDLE_READERS: usize = 8;
+
+/// A store that can be used from many threads at once.  See the `shared` module.
+pub struct SharedStore {
+    conn: Conn,
+    writer: Mutex<rusqlite::Connection>,
+    readers: Mutex<Vec<rusqlite::Connection>>,
+    options: StoreOptions,
+    path: String,
+}
+
+impl SharedStore {
+    pub(crate) fn new(conn: Conn, writer: rusqlite::Connection, options: StoreOptions, path: String) -> SharedStore {
+        SharedStore {
+            conn: conn,
+            writer: Mutex::new(writer),
+            readers: Mutex::new(vec![]),
+            options: options,
+            path: path,
+        }
+    }
+
+    /// Open a store at the supplied path, ensuring that it includes the bootstrap schema.
+    pub fn open(path: &str) -> Result<SharedStore> {
+        StoreOptions::new().open_shared(path)
+    }
+
+    pub fn conn(&self) -> &Conn {
+        &self.conn
+    }
+
+    /// Begin reading the store as it is now.  The read never sees what's transacted after it
+    /// began, and holds a connection until it's dropped, when the connection goes back to the pool.
+    pub fn read(&self) -> Result<PooledRead> {
+        let idle = self.readers.lock().unwrap().pop();
+        let sqlite = match idle {
+            Some(sqlite) => sqlite,
+            None => self.options.open_reader(&self.path)?,
+        };
+        Ok(PooledRead {
+            store: self,
+            snapshot: Some(self.conn.begin_snapshot(sqlite)?),
+        })
+    }
+
+    /// Run `f` in a write transaction, committing if it succeeds and rolling back if it fails.
+    /// This waits for any other write through this store to finish first.
+    pub fn write<T, F>(&self, f: F) -> Result<T> where F: FnOnce(&mut InProgress) -> Result<T> {
+        let mut sqlite = self.writer.lock().unwrap();
+        let mut in_progress = self.conn.begin_transaction_with_behavior(&mut *sqlite, TransactionBehavior::Immediate)?;
+        let result = f(&mut in_progress)?;
+        in_progress.commit()?;
+        Ok(result)
+    }
+
+    pub fn transact(&self, transaction: &str) -> Result<TxReport> {
+        self.write(|in_progress| in_progress.transact(transaction))
+    }
+
+    /// Run a query against the store as it is now.  See `read`.
+    pub fn q_once<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        self.read()?.q_once(query, inputs)
+    }
+
+    /// Cache the values of `attr` in the given direction, for every reader and writer.
+    pub fn cache(&self, attr: &Keyword, direction: CacheDirection) -> Result<()> {
+        let sqlite = self.writer.lock().unwrap();
+        let schema = &self.conn.current_schema();
+        self.conn.cache(&sqlite, schema, attr, direction, CacheAction::Register)
+    }
+
+    /// Stop caching the values of `attr`, in either direction.
+    pub fn uncache(&self, attr: &Keyword) -> Result<()> {
+        let sqlite = self.writer.lock().unwrap();
+        let schema = &self.conn.current_schema();
+        self.conn.cache(&sqlite, schema, attr, CacheDirection::Both, CacheAction::Deregister)
+    }
+
+    pub fn register_observer(&self, key: String, observer: Arc<TxObserver>) {
+        self.conn.tx_observer_service.lock().unwrap().register(key, observer);
+    }
+
+    pub fn unregister_observer(&self, key: &String) {
+        self.conn.tx_observer_service.lock().unwrap().deregister(key);
+    }
+}
+
+/// A read of a `SharedStore`, on a connection of its own.  This is a `TxSnapshot`, and is queried
+/// as one.
+pub struct PooledRead<'s> {
+    store: &'s SharedStore,
+
+    // Only taken when the read is dropped.
+    snapshot: Option<TxSnapshot>,
+}
+
+impl<'s> Deref for PooledRead<'s> {
+    type Target = TxSnapshot;
+
+    fn deref(&self) -> &TxSnapshot {
+        self.snapshot.as_ref().unwrap()
+    }
+}
+
+impl<'s> Drop for PooledRead<'s> {
+    fn drop(&mut self) {
+        // A connection whose read can't be ended is no use to the next reader.
+        if let Some(Ok(sqlite)) = self.snapshot.take().map(|snapshot| snapshot.end()) {
+            let mut readers = self.store.readers.lock().unwrap();
+            if readers.len() < MAX_IDLE_READERS {
+                readers.push(sqlite);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use core_traits::{
+        Binding,
+        TypedValue,
+    };
+
+    use ::{
+        IntoResult,
+    };
+
+    use ::temp_store::TempStoreFile;
+
+    use public_traits::errors::{
+        MentatError,
+    };
+
+    fn count(read: &PooledRead) -> Option<Binding> {
+        read.q_once("[:find (count ?e) . :where [?e :foo/bar _]]", None)
+            .expect("queried")
+            .into_scalar()
+            .expect("scalar")
+    }
+
+    #[test]
+    fn test_shared_store_is_send_and_sync() {
+        fn assert_send_and_sync<T: Send + Sync>() {}
+        assert_send_and_sync::<SharedStore>();
+    }
+
+    #[test]
+    fn test_in_memory_store_is_not_shared() {
+        match SharedStore::open("") {
+            Err(MentatError::SharedStoreUnavailable) => (),
+            Err(e) => panic!("expected shared store to be unavailable, got {:?}", e),
+            Ok(_) => panic!("expected shared store to be unavailable"),
+        }
+    }
+
+    #[test]
+    fn test_concurrent_reads_and_writes() {
+        let file = TempStoreFile::new();
+
+        let store = Arc::new(SharedStore::open(file.path_str()).expect("opened"));
+        store.transact("[{:db/ident :foo/bar :db/valueType :db.type/long :db/cardinality :db.cardinality/one}]").expect("transacted schema");
+
+        let threads: Vec<_> = (0..4).map(|i| {
+            let store = store.clone();
+            thread::spawn(move || {
+                store.transact(&format!("[{{:foo/bar {}}}]", i)).expect("transacted data");
+                let read = store.read().expect("began read");
+                assert!(count(&read).is_some());
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().expect("joined");
+        }
+
+        // A read doesn't see what's written after it began.
+        let before = store.read().expect("began read");
+        store.transact("[{:foo/bar 4}]").expect("transacted data");
+        let after = store.read().expect("began read");
+        assert_eq!(count(&before), Some(Binding::Scalar(TypedValue::Long(4))));
+        assert_eq!(count(&after), Some(Binding::Scalar(TypedValue::Long(5))));
+
+        // Their connections are kept for later reads.
+        drop(before);
+        drop(after);
+        let idle = store.readers.lock().unwrap().len();
+        assert!(idle >= 2);
+        store.read().expect("began read");
+        assert_eq!(store.readers.lock().unwrap().len(), idle);
+
+        // Writes that fail change nothing.
+        assert!(store.write(|in_progress| {
+            in_progress.transact("[{:foo/bar 5}]")?;
+            in_progress.transact("[{:foo/bar \"not a long\"}]")
+        }).is_err());
+        assert_eq!(count(&store.read().expect("began read")), Some(Binding::Scalar(TypedValue::Long(5))));
+    }
+}
@@ -14,36 +14,78 @@ use std::collections::{
     BTreeMap,
 };
 
+use std::path::{
+    Path,
+};
+
 use std::sync::{
     Arc,
 };
 
+use std::thread;
+
 use rusqlite;
 
 use edn;
 
 use core_traits::{
+    Attribute,
     Entid,
     StructuredMap,
     TypedValue,
 };
 
 use mentat_core::{
+    DateTime,
+    HasSchema,
+    IndexDirection,
     Keyword,
     TxReport,
+    Utc,
     ValueRc,
 };
+
+#[cfg(feature = "syncable")]
+use mentat_core::metrics;
+use edn::entities::{
+    EntidOrIdent,
+};
+
+use mentat_db::excision::{
+    vacuum,
+};
+
+use mentat_db::maintenance::{
+    self,
+    MaintenanceReport,
+};
+
+use mentat_db::retention::{
+    RetentionPolicy,
+};
+
 use mentat_db::{
+    AttributeSet,
+    TransactableValue,
     TxObserver,
+    TxSnapshot,
 };
 
 use mentat_transaction::{
+    BackgroundGuard,
     CacheAction,
     CacheDirection,
+    DatabaseView,
+    Entity,
+    ImportProgress,
+    ImportReport,
     InProgress,
     InProgressRead,
+    InteractiveGuard,
     Pullable,
+    QueryPriority,
     Queryable,
+    ToEntity,
 };
 
 use conn::{
@@ -51,18 +93,38 @@ use conn::{
 };
 
 use public_traits::errors::{
+    MentatError,
     Result,
 };
 
+use mentat_transaction::query_plan::{
+    QueryPlan,
+};
+
 use mentat_transaction::query::{
+    Datom,
+    DatomsBasis,
     PreparedResult,
+    Provenance,
     QueryExplanation,
     QueryInputs,
     QueryOutput,
+    tx_for_instant,
+};
+
+use shared::{
+    SharedStore,
+};
+
+use tx_log::{
+    TxFollower,
+    TxLog,
 };
 
 #[cfg(feature = "syncable")]
 use mentat_tolstoy::{
+    SyncFilter,
+    SyncOptions,
     SyncReport,
     SyncResult,
     SyncFollowup,
@@ -74,23 +136,151 @@ use sync::{
 };
 
 /// A convenience wrapper around a single SQLite connection and a Conn. This is suitable
-/// for applications that don't require complex connection management.
+/// for applications that don't require complex connection management.  To use a store from many
+/// threads at once, see `SharedStore`.
 pub struct Store {
     conn: Conn,
     sqlite: rusqlite::Connection,
+    options: StoreOptions,
+    priority: QueryPriority,
+    #[cfg(feature = "syncable")]
+    sync_filter: Option<SyncFilter>,
 }
 
-impl Store {
-    /// Open a store at the supplied path, ensuring that it includes the bootstrap schema.
-    pub fn open(path: &str) -> Result<Store> {
-        let mut connection = ::new_connection(path)?;
+/// How to open a `Store`, in the manner of `std::fs::OpenOptions`.  By default the store is
+/// writable, and is created if there's nothing at the path; `Store::open(path)` is
+/// `StoreOptions::new().open(path)`.
+#[derive(Clone, Debug)]
+pub struct StoreOptions {
+    read_only: bool,
+    create_if_missing: bool,
+    empty: bool,
+    encryption_key: Option<String>,
+}
+
+impl Default for StoreOptions {
+    fn default() -> StoreOptions {
+        StoreOptions {
+            read_only: false,
+            create_if_missing: true,
+            empty: false,
+            encryption_key: None,
+        }
+    }
+}
+
+impl StoreOptions {
+    pub fn new() -> StoreOptions {
+        StoreOptions::default()
+    }
+
+    /// Open the store without the ability to write to it.  The store must already exist, and
+    /// transacting against it fails.
+    pub fn read_only(&mut self, read_only: bool) -> &mut StoreOptions {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Whether to create the store if there's nothing at the path.
+    pub fn create_if_missing(&mut self, create_if_missing: bool) -> &mut StoreOptions {
+        self.create_if_missing = create_if_missing;
+        self
+    }
+
+    /// Require that there's nothing at the path, so that the store is newly created.
+    pub fn empty(&mut self, empty: bool) -> &mut StoreOptions {
+        self.empty = empty;
+        self
+    }
+
+    /// The key with which to encrypt and decrypt the store.  Opening fails unless linked against
+    /// sqlcipher (or something else that supports the Sqlite Encryption Extension).
+    #[cfg(feature = "sqlcipher")]
+    pub fn encryption_key<S>(&mut self, encryption_key: S) -> &mut StoreOptions where S: Into<String> {
+        self.encryption_key = Some(encryption_key.into());
+        self
+    }
+
+    /// Open a store at `path`, bootstrapping it if it's new.  The empty path opens an in-memory
+    /// store.
+    pub fn open(&self, path: &str) -> Result<Store> {
+        let exists = !path.is_empty() && Path::new(path).exists();
+        if self.empty && exists {
+            bail!(MentatError::PathAlreadyExists(path.to_string()));
+        }
+        if !path.is_empty() && !exists && (self.read_only || !self.create_if_missing) {
+            bail!(MentatError::PathDoesNotExist(path.to_string()));
+        }
+
+        let mut flags = rusqlite::OpenFlags::default();
+        if self.read_only {
+            flags.remove(rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE | rusqlite::OpenFlags::SQLITE_OPEN_CREATE);
+            flags.insert(rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY);
+        } else if !self.create_if_missing {
+            flags.remove(rusqlite::OpenFlags::SQLITE_OPEN_CREATE);
+        }
+
+        let mut connection = self.connect(path, flags)?;
         let conn = Conn::connect(&mut connection)?;
         Ok(Store {
             conn: conn,
             sqlite: connection,
+            options: self.clone(),
+            priority: QueryPriority::default(),
+            #[cfg(feature = "syncable")]
+            sync_filter: None,
         })
     }
 
+    /// Open a store at `path` that can be shared between threads.  In-memory stores can't be
+    /// shared.  See `SharedStore`.
+    pub fn open_shared(&self, path: &str) -> Result<SharedStore> {
+        if path.is_empty() {
+            bail!(MentatError::SharedStoreUnavailable);
+        }
+        let (sqlite, conn) = self.open(path)?.dismantle();
+        Ok(SharedStore::new(conn, sqlite, self.clone(), path.to_string()))
+    }
+
+    /// Open another, read-only, connection to the store at `path`, which must already be open.
+    pub(crate) fn open_reader(&self, path: &str) -> Result<rusqlite::Connection> {
+        let mut flags = rusqlite::OpenFlags::default();
+        flags.remove(rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE | rusqlite::OpenFlags::SQLITE_OPEN_CREATE);
+        flags.insert(rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY);
+        self.connect(path, flags)
+    }
+
+    fn connect(&self, path: &str, flags: rusqlite::OpenFlags) -> Result<rusqlite::Connection> {
+        let connection = match self.encryption_key {
+            #[cfg(feature = "sqlcipher")]
+            Some(ref key) => ::new_connection_with_key_and_flags(path, key, flags),
+            _ => ::new_connection_with_flags(path, flags),
+        };
+        connection.map_err(|e| self.connection_error(path, e))
+    }
+
+    /// SQLite reports that an encrypted database, opened without its key or with the wrong one,
+    /// isn't a database at all.  Say which key is at fault, since that's the likelier cause.
+    fn connection_error(&self, path: &str, error: rusqlite::Error) -> MentatError {
+        match error {
+            rusqlite::Error::SqliteFailure(ref e, _) if e.code == rusqlite::ErrorCode::NotADatabase => {
+                if self.encryption_key.is_some() {
+                    MentatError::IncorrectEncryptionKey(path.to_string())
+                } else {
+                    MentatError::EncryptionKeyRequired(path.to_string())
+                }
+            },
+            e => e.into(),
+        }
+    }
+}
+
+impl Store {
+    /// Open a store at the supplied path, ensuring that it includes the bootstrap schema.
+    pub fn open(path: &str) -> Result<Store> {
+        StoreOptions::new().open(path)
+    }
+
     pub fn transact(&mut self, transaction: &str) -> Result<TxReport> {
         let mut ip = self.begin_transaction()?;
         let report = ip.transact(transaction)?;
@@ -98,12 +288,132 @@ impl Store {
         Ok(report)
     }
 
+    /// Create the partition `partition`, like `:my/part`, starting at `start`.  Transactions can
+    /// then allocate entids in it with tempids like `(tempid :my/part "name")`.
+    ///
+    /// The new partition is split off the end of the partition that contains `start`, which can't
+    /// be `:db.part/tx`, and which mustn't have allocated `start` or any entid after it.
+    pub fn create_partition(&mut self, partition: &Keyword, start: Entid) -> Result<()> {
+        let mut ip = self.begin_transaction()?;
+        ip.create_partition(partition, start)?;
+        ip.commit()
+    }
+
+    /// Permanently remove `target` from the store, or, if `attributes` isn't empty, its values of
+    /// those attributes.  Its datoms and the transaction log's record of them are deleted, as are
+    /// fulltext and offloaded values that are no longer used.  With `before_tx`, only history
+    /// from before that transaction is removed, and what currently holds is kept.
+    ///
+    /// Only entities in partitions that allow excision, like `:db.part/user`, can be excised, and
+    /// never those with idents.  Excisions aren't observed by transaction observers, nor synced.
+    /// Call `vacuum` afterwards to shrink the database file.
+    pub fn excise(&mut self, target: Entid, attributes: &[Keyword], before_tx: Option<Entid>) -> Result<()> {
+        let mut ip = self.begin_transaction()?;
+        ip.excise(target, attributes, before_tx)?;
+        ip.commit()
+    }
+
+    /// Trim the values of `attribute` to those that `policy` keeps, and return how many were
+    /// trimmed.  Excising trimmed values is subject to the same restrictions as `excise`.
+    pub fn trim(&mut self, attribute: &Keyword, policy: RetentionPolicy) -> Result<usize> {
+        let mut ip = self.begin_transaction()?;
+        let trimmed = ip.trim(attribute, policy)?;
+        ip.commit()?;
+        Ok(trimmed)
+    }
+
+    /// Reclaim the space left by excisions and retractions, rewriting the database file.
+    pub fn vacuum(&mut self) -> Result<()> {
+        vacuum(&self.sqlite).map_err(|e| e.into())
+    }
+
+    /// Report the number of datoms for each attribute, the size of the fulltext values and their
+    /// index, and how much of the database file is free.  This reads the whole store.
+    pub fn maintenance(&self) -> Result<MaintenanceReport> {
+        maintenance::read_maintenance_report(&self.sqlite).map_err(|e| e.into())
+    }
+
+    /// Shrink the database file by up to `pages` of its free pages, or all of them.  This is much
+    /// quicker than `vacuum`, but leaves what remains as fragmented as it was.
+    pub fn incremental_vacuum(&mut self, pages: Option<u32>) -> Result<()> {
+        maintenance::incremental_vacuum(&self.sqlite, pages).map_err(|e| e.into())
+    }
+
+    /// Gather statistics for SQLite's query planner, and recount the datoms for each attribute
+    /// for our own; see `update_statistics`.  Worth doing after the store has grown or changed
+    /// a lot.
+    pub fn analyze(&mut self) -> Result<()> {
+        maintenance::analyze(&self.sqlite)?;
+        self.conn.update_statistics(&self.sqlite)
+    }
+
+    /// Merge the fulltext index into as few pieces as possible, making fulltext searches faster.
+    pub fn optimize_fulltext(&mut self) -> Result<()> {
+        maintenance::optimize_fulltext(&self.sqlite).map_err(|e| e.into())
+    }
+
+    /// Transact each of `entities` as a single entity, in one transaction.
+    pub fn transact_entities<I, E>(&mut self, entities: I) -> Result<TxReport>
+    where I: IntoIterator<Item=E>,
+          E: ToEntity {
+        let mut ip = self.begin_transaction()?;
+        let report = ip.transact_entities(entities.into_iter().map(|e| e.to_entity()))?;
+        ip.commit()?;
+        Ok(report)
+    }
+
+    /// Transact `entities` in batches of `batch_size`, in one SQLite transaction, calling
+    /// `progress` after each batch.  Nothing is committed unless every batch succeeds.  See
+    /// `mentat_transaction::bulk_import`.
+    pub fn import_bulk<I, V, F>(&mut self, entities: I, batch_size: usize, progress: F) -> Result<ImportReport>
+    where I: IntoIterator<Item=edn::entities::Entity<V>>,
+          V: TransactableValue,
+          F: FnMut(&ImportProgress) {
+        let mut ip = self.begin_transaction()?;
+        let report = ip.import_bulk(entities, batch_size, progress)?;
+        ip.commit()?;
+        Ok(report)
+    }
+
+    /// Sync only the attributes `filter` allows, or every attribute if it's `None`, unless the
+    /// `SyncOptions` for a particular sync say otherwise.  See `mentat_tolstoy::filter`.
+    #[cfg(feature = "syncable")]
+    pub fn set_sync_filter(&mut self, filter: Option<SyncFilter>) {
+        self.sync_filter = filter;
+    }
+
+    #[cfg(feature = "syncable")]
+    pub fn sync_filter(&self) -> Option<&SyncFilter> {
+        self.sync_filter.as_ref()
+    }
+
     #[cfg(feature = "syncable")]
     pub fn sync(&mut self, server_uri: &String, user_uuid: &String) -> Result<SyncResult> {
+        self.sync_with_options(server_uri, user_uuid, &mut SyncOptions::new())
+    }
+
+    /// Like `sync`, but filtering, resolving conflicts, reporting progress, and stopping when
+    /// cancelled as `options` asks.  Each atomic sync is committed as it completes; a cancelled one
+    /// is rolled back, and the sync fails with `TolstoyError::Cancelled`.
+    #[cfg(feature = "syncable")]
+    pub fn sync_with_options(&mut self, server_uri: &String, user_uuid: &String, options: &mut SyncOptions) -> Result<SyncResult> {
+        metrics::measure(metrics::SYNCS_EXECUTED, metrics::SYNC_DURATION, || {
+            self.sync_now(server_uri, user_uuid, options)
+        })
+    }
+
+    #[cfg(feature = "syncable")]
+    fn sync_now(&mut self, server_uri: &String, user_uuid: &String, options: &mut SyncOptions) -> Result<SyncResult> {
+        if options.sync_filter().is_none() {
+            if let Some(ref filter) = self.sync_filter {
+                options.filter(filter.clone());
+            }
+        }
+
         let mut reports = vec![];
         loop {
             let mut ip = self.begin_transaction()?;
-            let report = ip.sync(server_uri, user_uuid)?;
+            let report = ip.sync_with_options(server_uri, user_uuid, options)?;
             ip.commit()?;
 
             match report {
@@ -130,18 +440,21 @@ impl Store {
     /// Variant of `open` that allows a key (for encryption/decryption) to be
     /// supplied. Fails unless linked against sqlcipher (or something else that
     /// supports the Sqlite Encryption Extension).
+    ///
+    /// Opening an existing store with the wrong key fails with `IncorrectEncryptionKey`, and
+    /// opening an encrypted store with `open` fails with `EncryptionKeyRequired`.
+    pub fn open_encrypted(path: &str, encryption_key: &str) -> Result<Store> {
+        StoreOptions::new().encryption_key(encryption_key).open(path)
+    }
+
+    /// The same as `open_encrypted`.
     pub fn open_with_key(path: &str, encryption_key: &str) -> Result<Store> {
-        let mut connection = ::new_connection_with_key(path, encryption_key)?;
-        let conn = Conn::connect(&mut connection)?;
-        Ok(Store {
-            conn: conn,
-            sqlite: connection,
-        })
+        Store::open_encrypted(path, encryption_key)
     }
 
-    /// Change the key for a database that was opened using `open_with_key` (using `PRAGMA
+    /// Change the key for a database that was opened using `open_encrypted` (using `PRAGMA
     /// rekey`). Fails unless linked against sqlcipher (or something else that supports the Sqlite
-    /// Encryption Extension).
+    /// Encryption Extension).  The store must subsequently be opened with the new key.
     pub fn change_encryption_key(&mut self, new_encryption_key: &str) -> Result<()> {
         ::change_encryption_key(&self.sqlite, new_encryption_key)?;
         Ok(())
@@ -149,6 +462,44 @@ impl Store {
 }
 
 impl Store {
+    /// Let large sorts, groupings, and other intermediate query results spill to temporary files
+    /// once they outgrow `threshold` bytes.  Pass `None` to keep everything in memory, which is the
+    /// default.  Read results with `PreparedQuery::rows` to avoid collecting them in memory.  See
+    /// `mentat_db::db::set_spill_threshold`.
+    pub fn set_spill_threshold(&self, threshold: Option<u64>) -> Result<()> {
+        ::mentat_db::db::set_spill_threshold(&self.sqlite, threshold)?;
+        Ok(())
+    }
+
+    /// Run this store's queries at `priority`.  Background queries pause while an interactive
+    /// query or a write transaction is pending against the same database in this process.  See
+    /// `mentat_transaction::priority`.
+    pub fn set_query_priority(&mut self, priority: QueryPriority) {
+        self.priority = priority;
+    }
+
+    pub fn query_priority(&self) -> QueryPriority {
+        self.priority
+    }
+
+    /// Run `f`, which reads from this store's connection, at this store's query priority.
+    fn prioritized<T, F>(&self, f: F) -> T where F: FnOnce() -> T {
+        let path = match self.conn.path() {
+            Some(path) => path.clone(),
+            None => return f(),
+        };
+        match self.priority {
+            QueryPriority::Interactive => {
+                let _pending = InteractiveGuard::new(path);
+                f()
+            },
+            QueryPriority::Background => {
+                let _checkpoints = BackgroundGuard::new(&self.sqlite, path);
+                f()
+            },
+        }
+    }
+
     /// Intended for use from tests.
     pub fn sqlite_mut(&mut self) -> &mut rusqlite::Connection {
         &mut self.sqlite
@@ -173,19 +524,147 @@ impl Store {
         self.conn.begin_read(&mut self.sqlite)
     }
 
+    /// Take a read-only, point-in-time view of the store.  The snapshot has its own SQLite
+    /// connection and read transaction, so it can be queried -- from another thread, if need be --
+    /// for as long as it's kept, and it never sees what's transacted after it was taken, whether
+    /// through this store or another.  Dropping it ends the read transaction, which lets SQLite
+    /// checkpoint the WAL past it.
+    ///
+    /// Only unencrypted stores backed by a file can be snapshotted.  Taking a snapshot first
+    /// catches up with anything other connections have committed, so its schema matches what
+    /// it reads.
+    pub fn snapshot(&self) -> Result<TxSnapshot> {
+        self.conn.snapshot()
+    }
+
+    /// Query the store as it was once `tx` was committed.  Later assertions and retractions are
+    /// invisible to the view, as are later transactions in the log.  See `DatabaseView`.
+    pub fn as_of(&self, tx: Entid) -> DatabaseView {
+        DatabaseView::new(&self.sqlite, self.conn.current_schema(), DatomsBasis::AsOf(tx))
+    }
+
+    /// Query the store as it was at `instant`: as of the last transaction committed at or before
+    /// then.  Before the first transaction, the store was empty.
+    pub fn as_of_instant(&self, instant: &DateTime<Utc>) -> Result<DatabaseView> {
+        let tx = tx_for_instant(&self.sqlite, instant)?.unwrap_or(0);
+        Ok(self.as_of(tx))
+    }
+
+    /// Query only the datoms asserted after `tx` that still hold, such as those an indexer that
+    /// has seen everything up to `tx` has yet to see.  Queries otherwise work as usual, and can
+    /// join these datoms with constants, functions, and the like; patterns that should match
+    /// older datoms need the store itself.  Likewise, `$history` patterns see only later
+    /// transactions.  See `DatabaseView`.
+    pub fn since(&self, tx: Entid) -> DatabaseView {
+        DatabaseView::new(&self.sqlite, self.conn.current_schema(), DatomsBasis::Since(tx))
+    }
+
+    /// Like `since`, but after the last transaction committed at or before `instant`.
+    pub fn since_instant(&self, instant: &DateTime<Utc>) -> Result<DatabaseView> {
+        let tx = tx_for_instant(&self.sqlite, instant)?.unwrap_or(0);
+        Ok(self.since(tx))
+    }
+
     pub fn begin_transaction<'m>(&'m mut self) -> Result<InProgress<'m, 'm>> {
         self.conn.begin_transaction(&mut self.sqlite)
     }
 
-    pub fn cache(&mut self, attr: &Keyword, direction: CacheDirection) -> Result<()> {
+    /// Cache the values of `attr` in the given direction.  The cache is shared with every reader
+    /// and writer of this store.
+    pub fn cache(&self, attr: &Keyword, direction: CacheDirection) -> Result<()> {
         let schema = &self.conn.current_schema();
-        self.conn.cache(&mut self.sqlite,
+        self.conn.cache(&self.sqlite,
                         schema,
                         attr,
                         direction,
                         CacheAction::Register)
     }
 
+    /// Stop caching the values of `attr`, in either direction.
+    pub fn uncache(&self, attr: &Keyword) -> Result<()> {
+        let schema = &self.conn.current_schema();
+        self.conn.cache(&self.sqlite,
+                        schema,
+                        attr,
+                        CacheDirection::Both,
+                        CacheAction::Deregister)
+    }
+
+    /// Read the on-disk indices for `attributes` on a background thread, so that the first queries
+    /// after opening a store don't wait for cold pages.  `on_complete` is called on that thread
+    /// with the number of datoms read, or with the error that stopped warming.
+    ///
+    /// Warming uses its own connection, at background priority, so it pauses while this store
+    /// runs interactive queries or writes.  In-memory stores have nothing to warm.  To keep the
+    /// values themselves in memory, rather than the pages that hold them, use `cache`.
+    pub fn warm<F>(&self, attributes: &[Keyword], on_complete: F) -> Result<()>
+    where F: FnOnce(Result<usize>) + Send + 'static {
+        let schema = self.conn.current_schema();
+        let attributes = attributes.iter().map(|keyword| {
+            let entid = schema.get_entid(keyword)
+                              .ok_or_else(|| MentatError::UnknownAttribute(keyword.to_string()))?;
+            let attribute = schema.attribute_for_entid(entid)
+                                  .cloned()
+                                  .ok_or_else(|| MentatError::UnknownAttribute(keyword.to_string()))?;
+            Ok((entid.0, attribute))
+        }).collect::<Result<Vec<_>>>()?;
+        let path = self.conn.path().cloned();
+        let options = self.options.clone();
+
+        thread::spawn(move || {
+            let path = match path {
+                Some(path) => path,
+                None => return on_complete(Ok(0)),
+            };
+            let warm = || -> Result<usize> {
+                // Opened as the store was, so that an encrypted store is read with its key.
+                let sqlite = options.open_reader(&path.to_string_lossy())?;
+                let _checkpoints = BackgroundGuard::new(&sqlite, path.clone());
+                let mut datoms = 0;
+                for &(entid, ref attribute) in attributes.iter() {
+                    datoms += ::mentat_db::db::warm_attribute(&sqlite, entid, attribute)? as usize;
+                }
+                Ok(datoms)
+            };
+            on_complete(warm())
+        });
+        Ok(())
+    }
+
+    /// Recount the datoms for each attribute, so that queries join their patterns in a good
+    /// order.  See `Conn::update_statistics`.
+    pub fn update_statistics(&self) -> Result<()> {
+        self.conn.update_statistics(&self.sqlite)
+    }
+
+    /// Give `attribute` an index of its own, ordered by `direction`, for queries to read from.
+    /// See `Conn::ensure_attribute_index`.
+    pub fn ensure_attribute_index(&self, attribute: &Keyword, direction: IndexDirection) -> Result<()> {
+        self.conn.ensure_attribute_index(&self.sqlite, attribute, direction)
+    }
+
+    /// Drop an index that `ensure_attribute_index` created.
+    pub fn drop_attribute_index(&self, attribute: &Keyword, direction: IndexDirection) -> Result<()> {
+        self.conn.drop_attribute_index(&self.sqlite, attribute, direction)
+    }
+
+    /// Return each attribute with an index of its own, and the direction of that index.
+    pub fn attribute_indexes(&self) -> Vec<(Keyword, IndexDirection)> {
+        self.conn.attribute_indexes()
+    }
+
+    /// Return each cached attribute, and the direction in which it is cached.
+    pub fn cached_attributes(&self) -> BTreeMap<Keyword, CacheDirection> {
+        self.conn.cached_attributes()
+    }
+
+    /// Run a query using only the attribute cache, failing with `MentatError::UncachedQuery`
+    /// rather than reading from disk.  See `Conn::q_cached`.
+    pub fn q_cached<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        self.conn.q_cached(query, inputs)
+    }
+
     pub fn register_observer(&mut self, key: String, observer: Arc<TxObserver>) {
         self.conn.register_observer(key, observer);
     }
@@ -194,17 +673,101 @@ impl Store {
         self.conn.unregister_observer(key);
     }
 
+    /// See `Conn::register_tx_function`.
+    pub fn register_tx_function<F>(&mut self, name: Keyword, function: F)
+    where F: Fn(&InProgress, &[edn::Value]) -> Result<Vec<edn::entities::Entity<TypedValue>>> + 'static + Send + Sync {
+        self.conn.register_tx_function(name, function);
+    }
+
+    pub fn unregister_tx_function(&mut self, name: &Keyword) {
+        self.conn.unregister_tx_function(name);
+    }
+
+    /// See `Conn::register_tx_observer`.
+    pub fn register_tx_observer<F>(&mut self, key: String, attributes: AttributeSet, callback: F)
+    where F: Fn(&str, &TxReport, &AttributeSet) + 'static + Send + Sync {
+        self.conn.register_tx_observer(key, attributes, callback);
+    }
+
+    pub fn unregister_tx_observer(&mut self, key: &String) {
+        self.conn.unregister_tx_observer(key);
+    }
+
     pub fn last_tx_id(&self) -> Entid {
         self.conn.last_tx_id()
     }
+
+    /// Every attribute in the store, sorted by ident.
+    pub fn attributes(&self) -> Vec<(Keyword, Attribute)> {
+        self.conn.attributes()
+    }
+
+    /// The attributes whose idents are in `namespace` -- `foo` for `:foo/bar` -- sorted by ident.
+    pub fn attributes_in_namespace(&self, namespace: &str) -> Vec<(Keyword, Attribute)> {
+        self.conn.attributes_in_namespace(namespace)
+    }
+
+    /// The definition of the attribute named by `ident`, if it is one.
+    pub fn attribute(&self, ident: &Keyword) -> Option<Attribute> {
+        self.conn.attribute(ident)
+    }
+
+    /// Every ident in the store and the entid it names, sorted by ident.
+    pub fn idents(&self) -> Vec<(Keyword, Entid)> {
+        self.conn.idents()
+    }
+
+    /// Return the history of `attribute` for `entity`: each transaction that asserted or
+    /// retracted a value, when it did so, and which value, ordered by transaction.
+    pub fn provenance<E>(&self, entity: E, attribute: &Keyword) -> Result<Vec<Provenance>>
+        where E: Into<Entid> {
+        self.prioritized(|| self.conn.provenance_for_attribute(&self.sqlite, entity.into(), attribute))
+    }
+
+    /// Return the datoms that transaction `tx` asserted and retracted, as recorded in the
+    /// transaction log.  See `mentat::query::lookup_tx_data`.
+    pub fn tx_data(&self, tx: Entid) -> Result<Vec<Datom>> {
+        self.prioritized(|| self.conn.tx_data(&self.sqlite, tx))
+    }
+
+    /// Return the transactions in the log from `from_tx` up to but not including `to_tx`, or to the
+    /// end of the log if that's `None`, in the order they were committed.  See `mentat::tx_log`.
+    pub fn tx_range(&self, from_tx: Entid, to_tx: Option<Entid>) -> TxLog {
+        TxLog::new(&self.conn, &self.sqlite, from_tx, to_tx)
+    }
+
+    /// Report each transaction committed through this store from now on.  Those already committed
+    /// can be read with `tx_range`.  The follower is fed by an observer registered as `key`;
+    /// unregister it to stop following.  See `mentat::tx_log`.
+    pub fn follow(&mut self, key: String) -> Result<TxFollower> {
+        if self.conn.path().is_none() {
+            bail!(MentatError::SnapshotUnavailable);
+        }
+        let (observer, follower) = TxFollower::new();
+        self.register_observer(key, Arc::new(observer));
+        Ok(follower)
+    }
+
+    /// Return every datom the store holds now, other than those it was bootstrapped with and
+    /// transaction timestamps.  See `mentat::query::lookup_datoms`.
+    pub fn datoms(&self) -> Result<Vec<Datom>> {
+        self.prioritized(|| self.conn.datoms(&self.sqlite))
+    }
+
+    /// Describe how this store would run `query`, as a tree.  See `mentat::query_plan`.
+    pub fn q_plan<T>(&self, query: &str, inputs: T) -> Result<QueryPlan>
+        where T: Into<Option<QueryInputs>> {
+        self.prioritized(|| self.conn.q_plan(&self.sqlite, query, inputs))
+    }
 }
 
 impl Queryable for Store {
     fn q_once<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
         where T: Into<Option<QueryInputs>> {
-        self.conn.q_once(&self.sqlite, query, inputs)
+        self.prioritized(|| self.conn.q_once(&self.sqlite, query, inputs))
     }
 
+    /// Prepared queries run without regard to this store's query priority.
     fn q_prepare<T>(&self, query: &str, inputs: T) -> PreparedResult
         where T: Into<Option<QueryInputs>> {
         self.conn.q_prepare(&self.sqlite, query, inputs)
@@ -212,17 +775,17 @@ impl Queryable for Store {
 
     fn q_explain<T>(&self, query: &str, inputs: T) -> Result<QueryExplanation>
         where T: Into<Option<QueryInputs>> {
-        self.conn.q_explain(&self.sqlite, query, inputs)
+        self.prioritized(|| self.conn.q_explain(&self.sqlite, query, inputs))
     }
 
     fn lookup_values_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Vec<TypedValue>>
         where E: Into<Entid> {
-        self.conn.lookup_values_for_attribute(&self.sqlite, entity.into(), attribute)
+        self.prioritized(|| self.conn.lookup_values_for_attribute(&self.sqlite, entity.into(), attribute))
     }
 
     fn lookup_value_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Option<TypedValue>>
         where E: Into<Entid> {
-        self.conn.lookup_value_for_attribute(&self.sqlite, entity.into(), attribute)
+        self.prioritized(|| self.conn.lookup_value_for_attribute(&self.sqlite, entity.into(), attribute))
     }
 }
 
@@ -230,12 +793,17 @@ impl Pullable for Store {
     fn pull_attributes_for_entities<E, A>(&self, entities: E, attributes: A) -> Result<BTreeMap<Entid, ValueRc<StructuredMap>>>
     where E: IntoIterator<Item=Entid>,
           A: IntoIterator<Item=Entid> {
-        self.conn.pull_attributes_for_entities(&self.sqlite, entities, attributes)
+        self.prioritized(|| self.conn.pull_attributes_for_entities(&self.sqlite, entities, attributes))
     }
 
     fn pull_attributes_for_entity<A>(&self, entity: Entid, attributes: A) -> Result<StructuredMap>
     where A: IntoIterator<Item=Entid> {
-        self.conn.pull_attributes_for_entity(&self.sqlite, entity, attributes)
+        self.prioritized(|| self.conn.pull_attributes_for_entity(&self.sqlite, entity, attributes))
+    }
+
+    fn lookup_entity<E>(&self, entity: E) -> Result<Option<Entity>>
+    where E: Into<EntidOrIdent> {
+        self.prioritized(|| self.conn.lookup_entity(&self.sqlite, entity))
     }
 }
 
@@ -260,6 +828,7 @@ mod tests {
     };
     use std::time::{
         Duration,
+        Instant,
     };
 
     use mentat_db::cache::{
@@ -267,6 +836,7 @@ mod tests {
     };
 
     use core_traits::{
+        Binding,
         TypedValue,
         ValueType,
     };
@@ -280,14 +850,27 @@ mod tests {
         BuildTerms,
     };
 
+    use mentat_transaction::priority::{
+        MAX_PAUSE,
+        interactive_work_pending,
+    };
+
     use mentat_transaction::query::{
         PreparedQuery,
+        TxData,
     };
 
     use ::{
+        IntoResult,
         QueryInputs,
     };
 
+    use ::temp_store::TempStoreFile;
+
+    use public_traits::errors::{
+        MentatError,
+    };
+
     use ::vocabulary::{
         AttributeBuilder,
         Definition,
@@ -358,6 +941,113 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cached_query() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {  :db/ident       :foo/bar
+               :db/cardinality :db.cardinality/one
+               :db/index       true
+               :db/unique      :db.unique/identity
+               :db/valueType   :db.type/long },
+            {  :db/ident       :foo/baz
+               :db/cardinality :db.cardinality/one
+               :db/valueType   :db.type/string }]"#).expect("transact schema");
+        store.transact(r#"[{:foo/bar 15 :foo/baz "fifteen"} {:foo/bar 16}]"#).expect("transact data");
+
+        let query = r#"[:find ?baz . :in ?bar :where [?e :foo/bar ?bar] [?e :foo/baz ?baz]]"#;
+        let inputs = |bar| QueryInputs::with_value_sequence(vec![(var!(?bar), TypedValue::Long(bar))]);
+
+        // Nothing is cached, so this would need to read from disk.
+        match store.q_cached(query, inputs(15)) {
+            Err(MentatError::UncachedQuery) => {},
+            x => panic!("expected UncachedQuery, got {:?}", x),
+        }
+
+        store.cache(&kw!(:foo/bar), CacheDirection::Reverse).expect("cached");
+        match store.q_cached(query, inputs(15)) {
+            Err(MentatError::UncachedQuery) => {},
+            x => panic!("expected UncachedQuery, got {:?}", x),
+        }
+
+        // With both attributes cached, the query is answered without SQL, including when there's
+        // no answer.
+        store.cache(&kw!(:foo/baz), CacheDirection::Forward).expect("cached");
+        assert_eq!(store.q_cached(query, inputs(15)).into_scalar_result().expect("cached"),
+                   Some(TypedValue::typed_string("fifteen").into()));
+        assert_eq!(store.q_cached(query, inputs(16)).into_scalar_result().expect("cached"), None);
+        assert_eq!(store.q_cached(query, inputs(17)).into_scalar_result().expect("cached"), None);
+
+        // The answers agree with a query that goes to disk.
+        assert_eq!(store.q_cached(query, inputs(15)).expect("cached").results,
+                   store.q_once(query, inputs(15)).expect("queried").results);
+
+        // A scan can't be answered from the cache.
+        match store.q_cached(r#"[:find ?e :where [?e :foo/baz _]]"#, None) {
+            Err(MentatError::UncachedQuery) => {},
+            x => panic!("expected UncachedQuery, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_cached_query_write_through() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {  :db/ident       :foo/name
+               :db/cardinality :db.cardinality/one
+               :db/valueType   :db.type/string }
+            {  :db/ident       :foo/tag
+               :db/cardinality :db.cardinality/many
+               :db/valueType   :db.type/keyword }]"#).expect("transact schema");
+        let e = *store.transact(r#"[{:db/id "e" :foo/name "first" :foo/tag [:tag/a :tag/b]}]"#)
+                      .expect("transact data")
+                      .tempids.get("e").expect("e");
+
+        store.cache(&kw!(:foo/name), CacheDirection::Forward).expect("cached");
+        store.cache(&kw!(:foo/tag), CacheDirection::Forward).expect("cached");
+
+        let name = r#"[:find ?name . :in ?e :where [?e :foo/name ?name]]"#;
+        let tags = r#"[:find [?tag ...] :in ?e :where [?e :foo/tag ?tag] :order ?tag]"#;
+        let tagged = r#"[:find ?e . :in ?e ?tag :where [?e :foo/tag ?tag]]"#;
+        let inputs = || QueryInputs::with_value_sequence(vec![(var!(?e), TypedValue::Ref(e))]);
+        let tag_inputs = |tag: Keyword| QueryInputs::with_value_sequence(vec![(var!(?e), TypedValue::Ref(e)),
+                                                                              (var!(?tag), tag.into())]);
+
+        assert_eq!(store.q_cached(name, inputs()).into_scalar_result().expect("cached"),
+                   Some("first".into()));
+        assert_eq!(store.q_cached(tagged, tag_inputs(kw!(:tag/b))).into_scalar_result().expect("cached"),
+                   Some(TypedValue::Ref(e).into()));
+        assert_eq!(store.q_cached(tagged, tag_inputs(kw!(:tag/c))).into_scalar_result().expect("cached"),
+                   None);
+
+        // Several values for a cardinality-many attribute come from the cache, too, as a table
+        // of values rather than a scan of `datoms`.
+        let expected: Vec<Binding> = vec![kw!(:tag/a).into(), kw!(:tag/b).into()];
+        assert_eq!(store.q_once(tags, inputs()).into_coll_result().expect("queried"), expected);
+        match store.q_explain(tags, inputs()).expect("explained") {
+            QueryExplanation::ExecutionPlan { query, .. } => assert!(!query.sql.contains("datoms")),
+            _ => panic!("expected an execution plan"),
+        }
+
+        // Writes go through to the cache, without registering the attributes again.
+        store.transact(r#"[[:db/add ?e :foo/name "second"]
+                           [:db/retract ?e :foo/tag :tag/a]
+                           [:db/add ?e :foo/tag :tag/c]]"#.replace("?e", &e.to_string()).as_str())
+             .expect("transact changes");
+
+        assert_eq!(store.q_cached(name, inputs()).into_scalar_result().expect("cached"),
+                   Some("second".into()));
+        assert_eq!(store.q_cached(tagged, tag_inputs(kw!(:tag/a))).into_scalar_result().expect("cached"),
+                   None);
+        assert_eq!(store.q_cached(tagged, tag_inputs(kw!(:tag/c))).into_scalar_result().expect("cached"),
+                   Some(TypedValue::Ref(e).into()));
+
+        let expected: Vec<Binding> = vec![kw!(:tag/b).into(), kw!(:tag/c).into()];
+        assert_eq!(store.q_once(tags, inputs()).into_coll_result().expect("queried"), expected);
+        assert_eq!(store.conn.q_uncached(&store.sqlite, tags, inputs()).into_coll_result().expect("queried"),
+                   expected);
+    }
+
     #[test]
     fn test_cache_mutation() {
         let mut store = Store::open("").expect("opened");
@@ -498,6 +1188,55 @@ mod tests {
         assert!(conn.is_registered_as_observer(&key));
     }
 
+    #[test]
+    fn test_cache_uncache() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {  :db/ident       :foo/bar
+               :db/cardinality :db.cardinality/one
+               :db/valueType   :db.type/long },
+            {  :db/ident       :foo/baz
+               :db/cardinality :db.cardinality/one
+               :db/valueType   :db.type/boolean }]"#).expect("transact");
+        store.transact("[{:foo/bar 15 :foo/baz true}]").expect("transact");
+
+        let foo_bar = store.conn.current_schema().get_entid(&kw!(:foo/bar)).expect("foo/bar").0;
+
+        // Caching only needs a shared reference.
+        {
+            let shared = &store;
+            shared.cache(&kw!(:foo/bar), CacheDirection::Forward).expect("cache done");
+            shared.cache(&kw!(:foo/baz), CacheDirection::Both).expect("cache done");
+        }
+
+        let expected: BTreeMap<Keyword, CacheDirection> = vec![
+            (kw!(:foo/bar), CacheDirection::Forward),
+            (kw!(:foo/baz), CacheDirection::Both),
+        ].into_iter().collect();
+        assert_eq!(store.cached_attributes(), expected);
+
+        // Readers and writers see the store's cache.
+        {
+            let in_progress = store.begin_read().expect("begun");
+            assert!(in_progress.in_progress.cache.is_attribute_cached_forward(foo_bar));
+        }
+
+        store.uncache(&kw!(:foo/bar)).expect("uncache done");
+        assert!(!store.is_attribute_cached_forward(foo_bar));
+        {
+            let in_progress = store.begin_transaction().expect("begun");
+            assert!(!in_progress.cache.is_attribute_cached_forward(foo_bar));
+        }
+
+        let expected: BTreeMap<Keyword, CacheDirection> = vec![
+            (kw!(:foo/baz), CacheDirection::Both),
+        ].into_iter().collect();
+        assert_eq!(store.cached_attributes(), expected);
+
+        // Unknown attributes can't be cached.
+        assert!(store.cache(&kw!(:foo/unknown), CacheDirection::Forward).is_err());
+    }
+
     #[test]
     fn test_deregister_observer() {
         let mut conn = Store::open("").unwrap();
@@ -642,6 +1381,45 @@ mod tests {
         assert_eq!(o.changes, changesets);
     }
 
+    #[test]
+    fn test_tx_observer_receives_reports() {
+        let mut store = Store::open("").unwrap();
+        add_schema(&mut store);
+
+        let name_entid: Entid = store.conn().current_schema().get_entid(&kw!(:todo/name)).expect("entid to exist for name").into();
+        let label_entid: Entid = store.conn().current_schema().get_entid(&kw!(:label/name)).expect("entid to exist for label").into();
+        let mut registered_attrs = BTreeSet::new();
+        registered_attrs.insert(name_entid);
+
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let key = "Test Observing".to_string();
+        store.register_tx_observer(key.clone(), registered_attrs, move |obs_key, report, attributes| {
+            tx.lock().unwrap().send((obs_key.to_string(), report.clone(), attributes.clone())).unwrap();
+        });
+        assert!(store.is_registered_as_observer(&key));
+
+        let delay = Duration::from_millis(1000);
+
+        let report = store.transact(r#"[{:db/id "a" :todo/name "Buy milk" :label/name "groceries"}]"#).expect("transacted");
+        let (obs_key, observed, attributes) = rx.recv_timeout(delay).expect("notified");
+        assert_eq!(obs_key, key);
+        assert_eq!(observed, report);
+        assert!(observed.tempids.contains_key("a"));
+        assert!(attributes.contains(&name_entid));
+        assert!(attributes.contains(&label_entid));
+
+        // Changes to other attributes aren't reported.
+        store.transact(r#"[{:label/name "chores"}]"#).expect("transacted");
+        let report = store.transact(r#"[{:todo/name "Walk the dog"}]"#).expect("transacted");
+        assert_eq!(rx.recv_timeout(delay).expect("notified").1.tx_id, report.tx_id);
+
+        store.unregister_tx_observer(&key);
+        assert!(!store.is_registered_as_observer(&key));
+        store.transact(r#"[{:todo/name "Read a book"}]"#).expect("transacted");
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
     #[test]
     fn test_observer_not_notified_on_unregistered_change() {
         let mut conn = Store::open("").unwrap();
@@ -699,4 +1477,600 @@ mod tests {
         assert_eq!(o.txids, tx_ids);
         assert_eq!(o.changes, changesets);
     }
+
+    #[test]
+    fn test_provenance() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/tag :db/valueType :db.type/keyword :db/cardinality :db.cardinality/many}
+            {:db/ident :foo/bio :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true}
+        ]"#).expect("transacted schema");
+
+        let report1 = store.transact(r#"[{:db/id "a" :foo/name "Alice" :foo/tag :tag/x :foo/bio "Likes Rust"}]"#).expect("transacted");
+        let e = report1.tempids.get("a").cloned().expect("allocated");
+        let report2 = store.transact(&format!(r#"[[:db/add {e} :foo/name "Alicia"]
+                                                [:db/retract {e} :foo/tag :tag/x]
+                                                [:db/add {e} :foo/tag :tag/y]]"#, e = e)).expect("transacted");
+
+        assert_eq!(store.provenance(e, &kw!(:foo/name)).expect("provenance"),
+                   vec![Provenance { tx: report1.tx_id, tx_instant: report1.tx_instant, value: TypedValue::typed_string("Alice"), added: true },
+                        Provenance { tx: report2.tx_id, tx_instant: report2.tx_instant, value: TypedValue::typed_string("Alice"), added: false },
+                        Provenance { tx: report2.tx_id, tx_instant: report2.tx_instant, value: TypedValue::typed_string("Alicia"), added: true }]);
+
+        assert_eq!(store.provenance(e, &kw!(:foo/tag)).expect("provenance"),
+                   vec![Provenance { tx: report1.tx_id, tx_instant: report1.tx_instant, value: kw!(:tag/x).into(), added: true },
+                        Provenance { tx: report2.tx_id, tx_instant: report2.tx_instant, value: kw!(:tag/x).into(), added: false },
+                        Provenance { tx: report2.tx_id, tx_instant: report2.tx_instant, value: kw!(:tag/y).into(), added: true }]);
+
+        assert_eq!(store.provenance(e, &kw!(:foo/bio)).expect("provenance"),
+                   vec![Provenance { tx: report1.tx_id, tx_instant: report1.tx_instant, value: TypedValue::typed_string("Likes Rust"), added: true }]);
+
+        assert!(store.provenance(e, &kw!(:foo/unknown)).is_err());
+    }
+
+    #[test]
+    fn test_tx_data() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/bio :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true}
+        ]"#).expect("transacted schema");
+
+        let report = store.transact(r#"[{:db/id "a" :foo/name "Alice" :foo/bio "Likes Rust"}]"#).expect("transacted");
+        let e = report.tempids.get("a").cloned().expect("allocated");
+        let report = store.transact(&format!(r#"[[:db/add {} :foo/name "Alicia"]]"#, e)).expect("transacted");
+        let tx = report.tx_id;
+
+        let ident = |k: Keyword| EntidOrIdent::Ident(k);
+        assert_eq!(store.tx_data(tx).expect("tx data"),
+                   vec![Datom { e, a: ident(kw!(:foo/name)), v: TypedValue::typed_string("Alice"), tx, added: false },
+                        Datom { e, a: ident(kw!(:foo/name)), v: TypedValue::typed_string("Alicia"), tx, added: true },
+                        Datom { e: tx, a: ident(kw!(:db/txInstant)), v: TypedValue::Instant(report.tx_instant), tx, added: true }]);
+
+        // Fulltext values are resolved.
+        let data = store.tx_data(tx - 1).expect("tx data");
+        assert!(data.contains(&Datom { e, a: ident(kw!(:foo/bio)), v: TypedValue::typed_string("Likes Rust"), tx: tx - 1, added: true }));
+
+        assert_eq!(store.tx_data(tx + 1).expect("tx data"), vec![]);
+    }
+
+    #[test]
+    fn test_tx_range() {
+        let mut store = Store::open("").expect("opened");
+        let first = store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema").tx_id;
+        let txs: Vec<TxReport> = (0..250).map(|i| store.transact(&format!(r#"[{{:foo/name "{}"}}]"#, i)).expect("transacted"))
+                                         .collect();
+
+        // Reads span batches, and stop at the end of the log.
+        let log: Vec<TxData> = store.tx_range(first, None).collect::<Result<_>>().expect("read log");
+        assert_eq!(log.len(), 251);
+        assert_eq!(log.iter().map(|t| t.tx).collect::<Vec<_>>(),
+                   ::std::iter::once(first).chain(txs.iter().map(|r| r.tx_id)).collect::<Vec<_>>());
+        assert_eq!(log[1].instant, txs[0].tx_instant);
+        assert_eq!(log[1].datoms, store.tx_data(txs[0].tx_id).expect("tx data"));
+
+        // The end of the range is excluded.
+        let log: Vec<Entid> = store.tx_range(txs[10].tx_id, Some(txs[12].tx_id)).map(|t| t.expect("tx").tx).collect();
+        assert_eq!(log, vec![txs[10].tx_id, txs[11].tx_id]);
+        assert_eq!(store.tx_range(txs[249].tx_id + 1, None).count(), 0);
+    }
+
+    #[test]
+    fn test_follow() {
+        match Store::open("").expect("opened").follow("follower".to_string()) {
+            Err(MentatError::SnapshotUnavailable) => {},
+            _ => panic!("expected SnapshotUnavailable"),
+        }
+
+        let file = TempStoreFile::new();
+        let mut store = Store::open(file.path_str()).expect("opened");
+        store.transact(r#"[{:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).expect("transacted schema");
+
+        let follower = store.follow("follower".to_string()).expect("following");
+        assert!(follower.try_next().is_none());
+
+        let reports: Vec<TxReport> = ["Alice", "Bob"].iter().map(|name| store.transact(&format!(r#"[{{:foo/name "{}"}}]"#, name)).expect("transacted"))
+                                                     .collect();
+
+        // The follower keeps up from another thread.
+        let followed = ::std::thread::spawn(move || {
+            (0..2).map(|_| follower.next_timeout(Duration::from_secs(10)).expect("notified").expect("tx"))
+                  .collect::<Vec<TxData>>()
+        }).join().expect("joined");
+        assert_eq!(followed.iter().map(|t| (t.tx, t.instant)).collect::<Vec<_>>(),
+                   reports.iter().map(|r| (r.tx_id, r.tx_instant)).collect::<Vec<_>>());
+        assert!(followed[1].datoms.contains(&Datom { e: followed[1].datoms[0].e,
+                                                     a: EntidOrIdent::Ident(kw!(:foo/name)),
+                                                     v: TypedValue::typed_string("Bob"),
+                                                     tx: reports[1].tx_id,
+                                                     added: true }));
+
+        store.unregister_observer(&"follower".to_string());
+    }
+
+    #[test]
+    fn test_attribute_introspection() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+            {:db/ident :foo/tags :db/valueType :db.type/keyword :db/cardinality :db.cardinality/many}
+            {:db/ident :foo.bar/baz :db/valueType :db.type/ref :db/cardinality :db.cardinality/one :db/isComponent true}
+            {:db/ident :tag/x}
+        ]"#).expect("transacted schema");
+
+        let foo = store.attributes_in_namespace("foo");
+        assert_eq!(foo.iter().map(|&(ref ident, _)| ident.clone()).collect::<Vec<_>>(),
+                   vec![kw!(:foo/name), kw!(:foo/tags)]);
+
+        let name = store.attribute(&kw!(:foo/name)).expect(":foo/name");
+        assert_eq!(name.value_type, ValueType::String);
+        assert_eq!(name.unique, Some(Unique::Identity));
+        assert!(!name.multival);
+        assert_eq!(foo[0].1, name);
+
+        let baz = store.attribute(&kw!(:foo.bar/baz)).expect(":foo.bar/baz");
+        assert!(baz.component);
+
+        assert!(store.attribute(&kw!(:tag/x)).is_none());
+        assert!(store.attributes().iter().any(|&(ref ident, _)| *ident == kw!(:db/ident)));
+
+        let idents = store.idents();
+        let x = idents.iter().find(|&&(ref ident, _)| *ident == kw!(:tag/x)).map(|&(_, e)| e).expect(":tag/x");
+        assert_eq!(store.conn().current_schema().get_entid(&kw!(:tag/x)).map(|e| e.0), Some(x));
+    }
+
+    #[test]
+    fn test_excise() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+        store.cache(&kw!(:foo/name), CacheDirection::Forward).expect("cached");
+
+        let report = store.transact(r#"[{:db/id "a" :foo/name "Alice" :foo/age 30}]"#).expect("transacted");
+        let e = report.tempids.get("a").cloned().expect("allocated");
+        store.transact(&format!("[[:db/add {} :foo/age 31]]", e)).expect("transacted");
+
+        store.excise(e, &[kw!(:foo/name)], None).expect("excised");
+        assert_eq!(store.lookup_value_for_attribute(e, &kw!(:foo/name)).expect("looked up"), None);
+        assert_eq!(store.lookup_value_for_attribute(e, &kw!(:foo/age)).expect("looked up"), Some(TypedValue::Long(31)));
+        assert!(store.provenance(e, &kw!(:foo/name)).expect("provenance").is_empty());
+
+        store.excise(e, &[], None).expect("excised");
+        assert_eq!(store.lookup_entity(e).expect("looked up"), None);
+        assert!(store.provenance(e, &kw!(:foo/age)).expect("provenance").is_empty());
+        store.vacuum().expect("vacuumed");
+        assert_eq!(store.maintenance().expect("report").free_page_count, 0);
+
+        assert!(store.excise(e, &[kw!(:foo/unknown)], None).is_err());
+        let name = store.conn().current_schema().get_entid(&kw!(:foo/name)).expect("name").0;
+        assert!(store.excise(name, &[], None).is_err());
+    }
+
+    #[test]
+    fn test_create_partition() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+
+        let start = ::mentat_db::USER0 + 0x1000;
+        store.create_partition(&kw!(:foo/part), start).expect("created");
+
+        let report = store.transact(r#"[{:db/id (tempid :foo/part "a") :foo/name "Alice"}
+                                        {:db/id "b" :foo/name "Bob"}]"#).expect("transacted");
+        assert_eq!(report.tempids.get("a"), Some(&start));
+        assert!(report.tempids.get("b").cloned().expect("allocated") < start);
+
+        // Builders can name partitions, too.
+        let mut builder = store.begin_transaction().expect("began").builder();
+        let c = builder.partitioned_tempid(kw!(:foo/part), "c");
+        builder.add(c, kw!(:foo/name), TypedValue::typed_string("Carol")).expect("added");
+        let report = builder.commit().expect("committed");
+        assert_eq!(report.tempids.get("c"), Some(&(start + 1)));
+
+        assert!(store.create_partition(&kw!(:foo/part), start + 0x1000).is_err());
+        assert!(store.transact(r#"[{:db/id (tempid :db.part/tx "d") :foo/name "Dan"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_maintenance() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/note :db/valueType :db.type/string :db/cardinality :db.cardinality/many :db/index true :db/fulltext true}
+        ]"#).expect("transacted schema");
+        let note = store.conn().current_schema().get_entid(&kw!(:foo/note)).expect("note").0;
+
+        let notes: Vec<String> = (0..2000).map(|i| format!(r#"[:db/add "e" :foo/note "note number {} of many, long enough to fill some pages"]"#, i)).collect();
+        let report = store.transact(&format!("[{}]", notes.join(" "))).expect("transacted");
+        let e = report.tempids.get("e").cloned().expect("allocated");
+
+        let report = store.maintenance().expect("report");
+        assert_eq!(report.datoms.get(&note), Some(&2000));
+        assert_eq!(report.fulltext_values, 2000);
+        assert!(report.fulltext_text_bytes > 2000 * 50);
+        assert!(report.database_bytes() > report.free_bytes());
+
+        // Excising everything leaves free pages behind, which an incremental vacuum returns.
+        store.excise(e, &[], None).expect("excised");
+        let report = store.maintenance().expect("report");
+        assert_eq!(report.datoms.get(&note), None);
+        assert_eq!(report.fulltext_values, 0);
+        assert!(report.free_page_count > 0);
+        assert!(report.fragmentation() > 0.0);
+
+        store.incremental_vacuum(None).expect("vacuumed");
+        let vacuumed = store.maintenance().expect("report");
+        assert_eq!(vacuumed.free_page_count, 0);
+        assert!(vacuumed.page_count < report.page_count);
+
+        store.optimize_fulltext().expect("optimized");
+        store.analyze().expect("analyzed");
+    }
+
+    #[test]
+    fn test_tx_function() {
+        use edn::entities::{
+            AttributePlace,
+            Entity,
+            EntityPlace,
+            OpType,
+            ValuePlace,
+        };
+
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/count :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+        let report = store.transact(r#"[{:db/id "a" :foo/name "Alice"}]"#).expect("transacted");
+        let e = report.tempids.get("a").cloned().expect("allocated");
+
+        store.register_tx_function(kw!(:foo/increment), |in_progress, args| {
+            let e = args.get(0).and_then(|e| e.as_integer()).expect("entid");
+            let next = match in_progress.lookup_value_for_attribute(e, &kw!(:foo/count))? {
+                Some(TypedValue::Long(count)) => count + 1,
+                _ => 1,
+            };
+            Ok(vec![Entity::AddOrRetract {
+                op: OpType::Add,
+                e: EntityPlace::Entid(e.into()),
+                a: AttributePlace::Entid(kw!(:foo/count).into()),
+                v: ValuePlace::Atom(TypedValue::Long(next)),
+            }])
+        });
+        store.register_tx_function(kw!(:foo/twice), |_, args| {
+            let call = Entity::Call { f: kw!(:foo/increment), args: vec![TypedValue::Long(args[0].as_integer().expect("entid"))] };
+            Ok(vec![call.clone(), call])
+        });
+
+        store.transact(&format!("[[:foo/increment {}]]", e)).expect("transacted");
+        assert_eq!(store.lookup_value_for_attribute(e, &kw!(:foo/count)).expect("looked up"), Some(TypedValue::Long(1)));
+
+        // Calls mix with other data, and see the store as it was before the transaction.
+        store.transact(&format!(r#"[[:foo/increment {}] [:db/add {} :foo/name "Bob"]]"#, e, e)).expect("transacted");
+        assert_eq!(store.lookup_value_for_attribute(e, &kw!(:foo/count)).expect("looked up"), Some(TypedValue::Long(2)));
+        assert_eq!(store.lookup_value_for_attribute(e, &kw!(:foo/name)).expect("looked up"), Some(TypedValue::typed_string("Bob")));
+
+        // Both expansions of :foo/increment assert the same value, so they agree.
+        store.transact(&format!("[[:foo/twice {}]]", e)).expect("transacted");
+        assert_eq!(store.lookup_value_for_attribute(e, &kw!(:foo/count)).expect("looked up"), Some(TypedValue::Long(3)));
+
+        store.unregister_tx_function(&kw!(:foo/increment));
+        match store.transact(&format!("[[:foo/increment {}]]", e)).expect_err("expected unknown function") {
+            MentatError::DbError(e) => assert_eq!(e.kind(), ::db_traits::errors::DbErrorKind::UnknownTransactionFunction(":foo/increment".to_string())),
+            x => panic!("expected unknown transaction function, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_alter_cached_attribute_cardinality() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/tag :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+        store.cache(&kw!(:foo/tag), CacheDirection::Forward).expect("cached");
+        let tag = store.conn().current_schema().get_entid(&kw!(:foo/tag)).expect("tag").0;
+
+        let report = store.transact(r#"[{:db/id "a" :foo/tag 1}]"#).expect("transacted");
+        let e = report.tempids.get("a").cloned().expect("allocated");
+
+        // The cache follows the attribute from one value to many…
+        store.transact("[[:db/add :foo/tag :db/cardinality :db.cardinality/many]]").expect("altered");
+        store.transact(&format!("[[:db/add {} :foo/tag 2]]", e)).expect("transacted");
+        {
+            // Holding on to the cache would stop the next transaction from updating it in place.
+            let cache: SQLiteAttributeCache = store.conn.current_cache();
+            let tags: BTreeSet<TypedValue> = cache.get_values_for_entid(&store.conn.current_schema(), tag, e)
+                                                  .expect("cached values")
+                                                  .iter().cloned().collect();
+            assert_eq!(tags, vec![1, 2].into_iter().map(TypedValue::Long).collect());
+        }
+
+        // … and back again.
+        store.transact(&format!("[[:db/retract {} :foo/tag 1]]", e)).expect("transacted");
+        store.transact("[[:db/add :foo/tag :db/cardinality :db.cardinality/one]]").expect("altered");
+        let cache: SQLiteAttributeCache = store.conn.current_cache();
+        assert_eq!(cache.get_value_for_entid(&store.conn.current_schema(), tag, e), Some(&TypedValue::Long(2)));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        match Store::open("").expect("opened").snapshot() {
+            Err(MentatError::SnapshotUnavailable) => {},
+            _ => panic!("expected SnapshotUnavailable"),
+        }
+
+        let file = TempStoreFile::new();
+        let mut store = Store::open(file.path_str()).expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+        store.transact(r#"[{:foo/name "a"}]"#).expect("transacted");
+
+        let count = "[:find (count ?e) . :where [?e :foo/name _]]";
+        let snapshot = store.snapshot().expect("snapshot");
+        store.transact(r#"[{:foo/name "b"}]"#).expect("transacted");
+
+        // The snapshot doesn't see the later write, even from another thread.
+        let results = ::std::thread::spawn(move || {
+            snapshot.q_once(count, None).into_scalar_result().expect("results")
+        }).join().expect("joined");
+        assert_eq!(results, Some(TypedValue::Long(1).into()));
+
+        // A new snapshot does.
+        let results = store.snapshot().expect("snapshot").q_once(count, None).into_scalar_result().expect("results");
+        assert_eq!(results, Some(TypedValue::Long(2).into()));
+    }
+
+    #[test]
+    fn test_snapshot_sees_external_schema_changes() {
+        let file = TempStoreFile::new();
+        let store = Store::open(file.path_str()).expect("opened");
+        let mut other = Store::open(file.path_str()).expect("opened");
+        other.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+        other.transact(r#"[{:foo/name "a"}]"#).expect("transacted");
+
+        let snapshot = store.snapshot().expect("snapshot");
+        let results = snapshot.q_once("[:find (count ?e) . :where [?e :foo/name _]]", None)
+                              .into_scalar_result()
+                              .expect("results");
+        assert_eq!(results, Some(TypedValue::Long(1).into()));
+    }
+
+    #[test]
+    fn test_background_queries_yield() {
+        let file = TempStoreFile::new();
+        let path = file.path();
+
+        let mut writer = Store::open(file.path_str()).expect("opened");
+        let mut background = Store::open(file.path_str()).expect("opened");
+        background.set_query_priority(QueryPriority::Background);
+
+        let (sender, receiver) = mpsc::channel();
+        {
+            // An open write transaction is interactive work.
+            let _ip = writer.begin_transaction().expect("began");
+
+            // However the database is named.
+            let dir = path.parent().expect("temp dir");
+            let roundabout = dir.join("..")
+                                .join(dir.file_name().expect("temp dir name"))
+                                .join(path.file_name().expect("file name"));
+            assert!(interactive_work_pending(&roundabout));
+
+            ::std::thread::spawn(move || {
+                let results = background.q_once("[:find ?e ?a ?v :where [?e ?a ?v]]", None);
+                sender.send(results.is_ok()).expect("sent");
+            });
+
+            assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+
+            // But a background query only pauses for so long, even if the writer never finishes.
+            assert!(receiver.recv_timeout(MAX_PAUSE * 10).expect("query completed"));
+        }
+    }
+
+    #[test]
+    fn test_background_queries_dont_yield_to_own_thread() {
+        let file = TempStoreFile::new();
+
+        let mut writer = Store::open(file.path_str()).expect("opened");
+        let mut background = Store::open(file.path_str()).expect("opened");
+        background.set_query_priority(QueryPriority::Background);
+
+        {
+            // This thread's own write transaction can't finish while this thread waits for it.
+            let _ip = writer.begin_transaction().expect("began");
+            let started = Instant::now();
+            background.q_once("[:find ?e ?a ?v :where [?e ?a ?v]]", None).expect("query completed");
+            assert!(started.elapsed() < MAX_PAUSE);
+        }
+    }
+
+    #[test]
+    fn test_warm() {
+        let file = TempStoreFile::new();
+
+        let mut store = Store::open(file.path_str()).expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true}
+            {:db/ident :foo/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+        store.transact(r#"[{:foo/name "Alice" :foo/age 30} {:foo/name "Bob"}]"#).expect("transacted");
+
+        let (sender, receiver) = mpsc::channel();
+        store.warm(&[kw!(:foo/name), kw!(:foo/age)], move |result| {
+            sender.send(result.expect("warmed")).expect("sent");
+        }).expect("warming");
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)).expect("completed"), 3);
+
+        // Unknown attributes are rejected up front.
+        match store.warm(&[kw!(:foo/unknown)], |_| panic!("shouldn't warm")) {
+            Err(MentatError::UnknownAttribute(_)) => {},
+            x => panic!("expected UnknownAttribute, got {:?}", x),
+        }
+
+        // There's nothing to read for an in-memory store.
+        let store = Store::open("").expect("opened");
+        let (sender, receiver) = mpsc::channel();
+        store.warm(&[kw!(:db/ident)], move |result| {
+            sender.send(result.expect("warmed")).expect("sent");
+        }).expect("warming");
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)).expect("completed"), 0);
+    }
+
+    #[test]
+    fn test_spill_threshold() {
+        let file = TempStoreFile::new();
+        let mut store = Store::open(file.path_str()).expect("opened");
+        store.transact(r#"[{:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}]"#)
+             .expect("transacted schema");
+        let values: Vec<String> = (0..2000).map(|i| format!("{{:foo/n {}}}", i)).collect();
+        store.transact(&format!("[{}]", values.join(" "))).expect("transacted");
+
+        // The sort spills to disk, and its results are read back as they're needed.
+        store.set_spill_threshold(Some(16 * 1024)).expect("set threshold");
+        let mut prepared = store.q_prepare("[:find ?e ?n :where [?e :foo/n ?n] :order (desc (* ?n 3))]", None)
+                                .expect("prepared");
+        let mut count = 0;
+        let mut last = None;
+        for row in prepared.rows(None).expect("rows") {
+            let n = match row.expect("row")[1] {
+                Binding::Scalar(TypedValue::Long(n)) => n,
+                ref x => panic!("expected a long, got {:?}", x),
+            };
+            assert!(last.map_or(true, |last| n < last));
+            last = Some(n);
+            count += 1;
+        }
+        assert_eq!(count, 2000);
+        assert_eq!(last, Some(0));
+    }
+
+    #[test]
+    fn test_import_bulk() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[
+            {:db/ident :foo/name   :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+            {:db/ident :foo/friend :db/valueType :db.type/ref    :db/cardinality :db.cardinality/many}
+        ]"#).expect("transacted schema");
+
+        // Later batches refer to tempids from earlier ones, as entities, as values and in reverse.
+        let entities = edn::parse::entities(r#"[
+            {:db/id "a" :foo/name "Alice"}
+            {:db/id "b" :foo/name "Bob" :foo/friend "a"}
+            [:db/add "c" :foo/name "Carol"]
+            [:db/add "c" :foo/friend "b"]
+            {:db/id "a" :foo/_friend "c"}
+        ]"#).expect("parsed");
+
+        let mut seen = vec![];
+        let report = store.import_bulk(entities, 2, |progress| seen.push(progress.clone())).expect("imported");
+
+        assert_eq!(report.entities, 5);
+        assert_eq!(report.tx_ids.len(), 3);
+        assert_eq!(report.tempids.keys().cloned().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+        assert_eq!(seen.iter().map(|p| (p.entities, p.batches)).collect::<Vec<_>>(),
+                   vec![(2, 1), (4, 2), (5, 3)]);
+        assert_eq!(seen.last().and_then(|p| p.last_tx_id), report.tx_ids.last().cloned());
+
+        let friends = store.q_once(r#"[:find ?name ?friend
+                                       :where [?e :foo/name ?name]
+                                              [?e :foo/friend ?f]
+                                              [?f :foo/name ?friend]
+                                       :order ?name ?friend]"#, None)
+                           .into_rel_result()
+                           .expect("queried");
+        assert_eq!(friends.into_iter().collect::<Vec<_>>(), vec![
+            vec![TypedValue::typed_string("Bob").into(), TypedValue::typed_string("Alice").into()],
+            vec![TypedValue::typed_string("Carol").into(), TypedValue::typed_string("Alice").into()],
+            vec![TypedValue::typed_string("Carol").into(), TypedValue::typed_string("Bob").into()],
+        ]);
+
+        // A failing batch discards the whole import.
+        let entities = edn::parse::entities(r#"[
+            {:foo/name "Dave"}
+            {:foo/name 42}
+        ]"#).expect("parsed");
+        assert!(store.import_bulk(entities, 1, |_| {}).is_err());
+        let count = store.q_once("[:find (count ?e) . :where [?e :foo/name _]]", None)
+                         .into_scalar_result()
+                         .expect("queried");
+        assert_eq!(count, Some(TypedValue::Long(3).into()));
+    }
+
+    #[test]
+    fn test_observer_queries_snapshot() {
+        let file = TempStoreFile::new();
+
+        let mut store = Store::open(file.path_str()).expect("opened");
+        store.transact(r#"[{:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).expect("transacted schema");
+        let name: Entid = store.conn().current_schema().get_entid(&kw!(:foo/name)).expect("entid").into();
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let observer = TxObserver::with_snapshot(vec![name].into_iter().collect(), move |_key, _batch, snapshot| {
+            // Take long enough that the following transactions commit before we query.
+            ::std::thread::sleep(Duration::from_millis(50));
+            let count = snapshot.expect("snapshot")
+                                .q_once("[:find (count ?e) . :where [?e :foo/name _]]", None)
+                                .into_scalar_result()
+                                .expect("queried");
+            sender.lock().unwrap().send(count).expect("sent");
+        });
+        store.register_observer("snapshot".to_string(), Arc::new(observer));
+
+        for name in &["Alice", "Bob", "Carol"] {
+            store.transact(&format!(r#"[{{:foo/name "{}"}}]"#, name)).expect("transacted");
+        }
+
+        // Each notification sees exactly the state it describes, and nothing later.
+        let counts: Vec<Option<Binding>> = (0..3).map(|_| receiver.recv_timeout(Duration::from_secs(10)).expect("notified"))
+                                                 .collect();
+        assert_eq!(counts, vec![Some(TypedValue::Long(1).into()),
+                                Some(TypedValue::Long(2).into()),
+                                Some(TypedValue::Long(3).into())]);
+    }
+
+    #[test]
+    fn test_store_options() {
+        // Start from a name that nothing exists at.
+        let file = TempStoreFile::new();
+        let path = file.path_str();
+        ::std::fs::remove_file(path).expect("removed");
+
+        match StoreOptions::new().read_only(true).open(path) {
+            Err(MentatError::PathDoesNotExist(p)) => assert_eq!(p, path),
+            _ => panic!("expected an error"),
+        }
+        match StoreOptions::new().create_if_missing(false).open(path) {
+            Err(MentatError::PathDoesNotExist(_)) => {},
+            _ => panic!("expected an error"),
+        }
+
+        let mut store = StoreOptions::new().empty(true).open(path).expect("created");
+        store.transact(r#"[{:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).expect("transacted schema");
+        store.transact(r#"[{:foo/name "Alice"}]"#).expect("transacted");
+        drop(store);
+
+        match StoreOptions::new().empty(true).open(path) {
+            Err(MentatError::PathAlreadyExists(p)) => assert_eq!(p, path),
+            _ => panic!("expected an error"),
+        }
+
+        // A read-only store can be queried, but not written.
+        let mut store = StoreOptions::new().read_only(true).open(path).expect("opened");
+        let name = store.q_once("[:find ?name . :where [_ :foo/name ?name]]", None)
+                        .into_scalar_result()
+                        .expect("queried");
+        assert_eq!(name, Some(TypedValue::typed_string("Alice").into()));
+        store.transact(r#"[{:foo/name "Bob"}]"#).expect_err("read-only");
+    }
 }
@@ -19,6 +19,8 @@ use errors::{
 };
 
 use mentat_tolstoy::{
+    ConflictResolver,
+    SyncOptions,
     Syncer,
     RemoteClient,
     SyncReport,
@@ -26,6 +28,14 @@ use mentat_tolstoy::{
 
 pub trait Syncable {
     fn sync(&mut self, server_uri: &String, user_uuid: &String) -> Result<SyncReport>;
+
+    /// Like `sync`, but `resolver` decides conflicts between local and remote changes.
+    fn sync_with_resolver<C>(&mut self, server_uri: &String, user_uuid: &String, resolver: &mut C) -> Result<SyncReport>
+    where C: ConflictResolver;
+
+    /// Like `sync`, but resolving conflicts, reporting progress, and stopping when cancelled as
+    /// `options` asks.
+    fn sync_with_options(&mut self, server_uri: &String, user_uuid: &String, options: &mut SyncOptions) -> Result<SyncReport>;
 }
 
 impl<'a, 'c> Syncable for InProgress<'a, 'c> {
@@ -43,4 +53,23 @@ impl<'a, 'c> Syncable for InProgress<'a, 'c> {
         Syncer::sync(self, &mut remote_client)
             .map_err(|e| e.into())
     }
+
+    fn sync_with_resolver<C>(&mut self, server_uri: &String, user_uuid: &String, resolver: &mut C) -> Result<SyncReport>
+    where C: ConflictResolver {
+        let mut remote_client = RemoteClient::new(
+            server_uri.to_string(),
+            Uuid::parse_str(&user_uuid)?
+        );
+        Syncer::sync_with_resolver(self, &mut remote_client, resolver)
+            .map_err(|e| e.into())
+    }
+
+    fn sync_with_options(&mut self, server_uri: &String, user_uuid: &String, options: &mut SyncOptions) -> Result<SyncReport> {
+        let mut remote_client = RemoteClient::new(
+            server_uri.to_string(),
+            Uuid::parse_str(&user_uuid)?
+        );
+        Syncer::sync_with_options(self, &mut remote_client, options)
+            .map_err(|e| e.into())
+    }
 }
@@ -0,0 +1,56 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A uniquely named file for a test's store, for tests that need more than one connection to it.
+
+use std::fs;
+
+use std::path::{
+    PathBuf,
+};
+
+use tempfile::NamedTempFile;
+
+/// A temporary file for a store, which is removed when dropped along with the `-wal` and `-shm`
+/// files SQLite keeps beside it.  Drop it after the store's connections.
+pub struct TempStoreFile {
+    file: NamedTempFile,
+}
+
+impl TempStoreFile {
+    pub fn new() -> TempStoreFile {
+        TempStoreFile {
+            file: NamedTempFile::new().expect("temporary file"),
+        }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.file.path().to_path_buf()
+    }
+
+    pub fn path_str(&self) -> &str {
+        self.file.path().to_str().expect("path")
+    }
+
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = self.file.path().as_os_str().to_os_string();
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+}
+
+impl Drop for TempStoreFile {
+    fn drop(&mut self) {
+        // The file itself goes with `NamedTempFile`.
+        for suffix in &["-wal", "-shm", "-journal"] {
+            let _ = fs::remove_file(self.sibling(suffix));
+        }
+    }
+}
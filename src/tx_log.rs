@@ -0,0 +1,188 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Streaming the transaction log, for replicating a store elsewhere.
+//!
+//! `Store::tx_range` reads the transactions already in the log, and `Store::follow` reports
+//! each one committed after that, so that a search indexer, say, or a bridge to another sync
+//! system can keep up with a store without reading its SQLite tables:
+//!
+//! ```ignore
+//! let follower = store.follow("indexer".to_string())?;
+//! for tx in store.tx_range(TX0 + 1, None)? {
+//!     index(tx?);
+//! }
+//! // Elsewhere, perhaps on another thread:
+//! for tx in follower {
+//!     index(tx?);
+//! }
+//! ```
+
+use std::collections::VecDeque;
+
+use std::sync::{
+    Mutex,
+};
+
+use std::sync::mpsc::{
+    channel,
+    Receiver,
+};
+
+use std::time::{
+    Duration,
+};
+
+use rusqlite;
+
+use core_traits::{
+    Entid,
+};
+
+use mentat_db::{
+    AttributeSet,
+    TxObserver,
+};
+
+use mentat_db::entids::{
+    DB_TX_INSTANT,
+};
+
+use mentat_transaction::query::{
+    TxData,
+    lookup_tx,
+    lookup_tx_ids,
+};
+
+use conn::{
+    Conn,
+};
+
+use public_traits::errors::{
+    MentatError,
+    Result,
+};
+
+/// How many transaction ids `TxLog` reads at a time.
+const TX_LOG_BATCH_SIZE: usize = 100;
+
+/// The transactions in a range of the log, in the order they were committed.  Transactions are
+/// read as they're needed, so a long range doesn't all sit in memory at once.
+pub struct TxLog<'s> {
+    conn: &'s Conn,
+    sqlite: &'s rusqlite::Connection,
+    next_tx: Entid,
+    to_tx: Option<Entid>,
+    pending: VecDeque<Entid>,
+    exhausted: bool,
+}
+
+impl<'s> TxLog<'s> {
+    pub(crate) fn new(conn: &'s Conn, sqlite: &'s rusqlite::Connection, from_tx: Entid, to_tx: Option<Entid>) -> TxLog<'s> {
+        TxLog {
+            conn: conn,
+            sqlite: sqlite,
+            next_tx: from_tx,
+            to_tx: to_tx,
+            pending: VecDeque::new(),
+            exhausted: false,
+        }
+    }
+
+    fn fill(&mut self) -> Result<()> {
+        let txs = lookup_tx_ids(self.sqlite, self.next_tx, self.to_tx, TX_LOG_BATCH_SIZE)?;
+        self.exhausted = txs.len() < TX_LOG_BATCH_SIZE;
+        if let Some(last) = txs.last() {
+            self.next_tx = last + 1;
+        }
+        self.pending.extend(txs);
+        Ok(())
+    }
+}
+
+impl<'s> Iterator for TxLog<'s> {
+    type Item = Result<TxData>;
+
+    fn next(&mut self) -> Option<Result<TxData>> {
+        loop {
+            if self.pending.is_empty() && !self.exhausted {
+                if let Err(e) = self.fill() {
+                    self.exhausted = true;
+                    return Some(Err(e));
+                }
+            }
+            let tx = self.pending.pop_front()?;
+            match self.conn.tx(self.sqlite, tx) {
+                Ok(Some(data)) => return Some(Ok(data)),
+                // Excised since we read its id.
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// The transactions committed through a store after `Store::follow` was called, in the order they
+/// were committed.  Iterating blocks until the next one commits, and ends once the store is closed
+/// or the follower's observer is unregistered.
+///
+/// Each transaction is read from a snapshot taken as it committed, so a follower can be moved to
+/// another thread and keep up without touching the store.  Only unencrypted stores backed by a
+/// file can be snapshotted; for any other, each transaction is reported as
+/// `MentatError::SnapshotUnavailable`.
+pub struct TxFollower {
+    receiver: Receiver<Result<TxData>>,
+}
+
+impl TxFollower {
+    /// A follower, and the observer that feeds it.
+    pub(crate) fn new() -> (TxObserver, TxFollower) {
+        let (sender, receiver) = channel();
+        let sender = Mutex::new(sender);
+
+        // Every transaction has an instant.
+        let attributes: AttributeSet = vec![DB_TX_INSTANT].into_iter().collect();
+        let observer = TxObserver::with_snapshot(attributes, move |_key, batch, snapshot| {
+            let sender = sender.lock().unwrap();
+            for (tx, _) in batch {
+                let data = match snapshot {
+                    Some(snapshot) => match lookup_tx(snapshot.sqlite(), snapshot.schema(), *tx) {
+                        Ok(Some(data)) => Ok(data),
+                        Ok(None) => continue,
+                        Err(e) => Err(e),
+                    },
+                    None => Err(MentatError::SnapshotUnavailable),
+                };
+                // Nobody is listening if the follower was dropped, and that's fine.
+                let _ = sender.send(data);
+            }
+        });
+
+        (observer, TxFollower { receiver: receiver })
+    }
+
+    /// The next transaction, if one has committed, without waiting for one.
+    pub fn try_next(&self) -> Option<Result<TxData>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// The next transaction, waiting at most `timeout` for one to commit.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<Result<TxData>> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for TxFollower {
+    type Item = Result<TxData>;
+
+    fn next(&mut self) -> Option<Result<TxData>> {
+        self.receiver.recv().ok()
+    }
+}
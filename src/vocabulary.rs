@@ -78,6 +78,7 @@
 //!             ],
 //!             pre: Definition::no_op,
 //!             post: Definition::no_op,
+//!             migrations: vec![],
 //!         }).expect("ensured");
 //!
 //!         // Now we can do stuff.
@@ -132,6 +133,11 @@ use mentat_transaction::entity_builder::{
 /// AttributeBuilder is how you build vocabulary definitions to apply to a store.
 pub use mentat_db::AttributeBuilder;
 
+pub use mentat_db::retention::{
+    Keep,
+    RetentionPolicy,
+};
+
 pub type Version = u32;
 pub type Datom = (Entid, Entid, TypedValue);
 
@@ -153,6 +159,9 @@ pub type Datom = (Entid, Entid, TypedValue);
 /// upgraded. `pre` and `post` are run before and after the definition is transacted against the
 /// store. Each is called with the existing `Vocabulary` instance so that they can do version
 /// checks or employ more fine-grained logic.
+///
+/// Upgrades that need to transform data one version at a time can instead list `migrations`.  See
+/// `Migration`.
 #[derive(Clone)]
 pub struct Definition {
     pub name: Keyword,
@@ -160,6 +169,24 @@ pub struct Definition {
     pub attributes: Vec<(Keyword, Attribute)>,
     pub pre: fn(&mut InProgress, &Vocabulary) -> Result<()>,
     pub post: fn(&mut InProgress, &Vocabulary) -> Result<()>,
+    pub migrations: Vec<Migration>,
+}
+
+/// The data transformations needed to bring a vocabulary up to `version` from the version before.
+///
+/// When a store holds an older version of a vocabulary, `ensure_vocabulary` runs, in one
+/// transaction: the definition's `pre`; the `pre` of each migration newer than the stored version,
+/// in version order; the schema changes; the `post` of each of those migrations, in version order;
+/// and finally the definition's `post`.  Each function is called with the vocabulary as it was in
+/// the store before the upgrade.  If any of them fails, nothing is changed.
+///
+/// Mentat can't change an attribute's value type in place, so a `pre` or `post` function is the
+/// place to copy values from an old attribute into a new one, converting as it goes.
+#[derive(Clone)]
+pub struct Migration {
+    pub version: Version,
+    pub pre: fn(&mut InProgress, &Vocabulary) -> Result<()>,
+    pub post: fn(&mut InProgress, &Vocabulary) -> Result<()>,
 }
 
 /// ```
@@ -237,6 +264,7 @@ pub struct Definition {
 ///             println!("We migrated :example/links from version {}", from.version);
 ///             Ok(())
 ///         },
+///         migrations: vec![],
 ///     }).expect("ensured");
 ///
 ///     // Now we can do stuff.
@@ -258,9 +286,38 @@ impl Definition {
             attributes: attributes.into(),
             pre: Definition::no_op,
             post: Definition::no_op,
+            migrations: vec![],
         }
     }
 
+    /// Add a migration to run when upgrading to `version` from the version before.
+    pub fn with_migration(mut self,
+                          version: Version,
+                          pre: fn(&mut InProgress, &Vocabulary) -> Result<()>,
+                          post: fn(&mut InProgress, &Vocabulary) -> Result<()>) -> Definition {
+        self.migrations.push(Migration {
+            version: version,
+            pre: pre,
+            post: post,
+        });
+        self
+    }
+
+    /// The migrations needed to upgrade from `from`, in version order.
+    fn migrations_from(&self, from: &Vocabulary) -> Result<Vec<&Migration>> {
+        let mut migrations: Vec<&Migration> = vec![];
+        for migration in self.migrations.iter() {
+            if migration.version > self.version {
+                bail!(MentatError::InvalidMigrationVersion(self.name.to_string(), migration.version, self.version));
+            }
+            if migration.version > from.version {
+                migrations.push(migration);
+            }
+        }
+        migrations.sort_by_key(|migration| migration.version);
+        Ok(migrations)
+    }
+
     /// Called with an in-progress transaction and the previous vocabulary version
     /// if the definition's version is later than that of the vocabulary in the store.
     fn pre(&self, ip: &mut InProgress, from: &Vocabulary) -> Result<()> {
@@ -609,6 +666,14 @@ pub trait VersionedStore: HasVocabularies + HasSchema {
     /// functions invoked when vocabulary changes are necessary.
     fn ensure_vocabularies(&mut self, vocabularies: &mut VocabularySource) -> Result<BTreeMap<Keyword, VocabularyOutcome>>;
 
+    /// Trim the values of each attribute in `policies` according to its policy, and return how
+    /// many values were trimmed in all.
+    ///
+    /// Mentat doesn't store retention policies, and doesn't trim anything on its own: call this
+    /// with the policies for history-heavy attributes, like `:page/visit`, on whatever schedule
+    /// suits the application -- at startup, say, or when idle.
+    fn apply_retention(&mut self, policies: &[(Keyword, RetentionPolicy)]) -> Result<usize>;
+
     /// Make sure that our expectations of the core vocabulary — basic types and attributes — are met.
     fn verify_core_schema(&self) -> Result<()> {
         if let Some(core) = self.read_vocabulary_named(&DB_SCHEMA_CORE)? {
@@ -748,6 +813,14 @@ impl<'a, 'c> VersionedStore for InProgress<'a, 'c> {
         vocabularies.post(self)?;
         Ok(out)
     }
+
+    fn apply_retention(&mut self, policies: &[(Keyword, RetentionPolicy)]) -> Result<usize> {
+        let mut trimmed = 0;
+        for &(ref attribute, policy) in policies.iter() {
+            trimmed += self.trim(attribute, policy)?;
+        }
+        Ok(trimmed)
+    }
 }
 
 /// Implement `VocabularySource` to have full programmatic control over how a set of `Definition`s
@@ -828,13 +901,21 @@ impl<'a, 'c> VocabularyMechanics for InProgress<'a, 'c> {
         // We trust that the vocabulary will implement a 'pre' function that cleans up data for any
         // failable conversion (e.g., cardinality-many to cardinality-one).
 
+        let migrations = definition.migrations_from(&from_version)?;
+
         definition.pre(self, &from_version)?;
+        for migration in migrations.iter() {
+            (migration.pre)(self, &from_version)?;
+        }
 
         // TODO: don't do work for attributes that are unchanged. Here we rely on the transactor
         // to elide duplicate datoms.
         let (terms, _tempids) = definition.description_diff(self, &from_version)?;
         self.transact_entities(terms)?;
 
+        for migration in migrations.iter() {
+            (migration.post)(self, &from_version)?;
+        }
         definition.post(self, &from_version)?;
         Ok(VocabularyOutcome::Upgraded)
     }
@@ -45,6 +45,59 @@ use mentat::entity_builder::{
     BuildTerms,
 };
 
+use mentat::{
+    Binding,
+    EntityAttributes,
+    IntoResult,
+    Keyword,
+    MentatEntity,
+    Store,
+    ToEntity,
+    ValueType,
+};
+
+use mentat::vocabulary::{
+    VersionedStore,
+};
+
+#[derive(ToEntity)]
+#[mentat(namespace = "person")]
+struct Person {
+    #[mentat(tempid)]
+    handle: String,
+    full_name: String,
+    #[mentat(attribute = ":person/nick")]
+    nickname: Option<String>,
+    age: i64,
+    #[mentat(ref)]
+    friends: Vec<String>,
+    #[mentat(skip)]
+    #[allow(dead_code)]
+    visits: u32,
+}
+
+#[derive(Debug, MentatEntity, PartialEq)]
+struct Book {
+    #[mentat(tempid, unique = "identity")]
+    isbn: String,
+    #[mentat(fulltext)]
+    title: String,
+    pages: Option<i64>,
+    tags: Vec<Keyword>,
+    #[mentat(ref)]
+    sequel: Option<String>,
+}
+
+struct Tag(&'static str);
+
+impl ToEntity for Tag {
+    fn attributes(&self) -> EntityAttributes {
+        let mut attributes = EntityAttributes::new();
+        attributes.add(Keyword::namespaced("tag", "name"), self.0);
+        attributes
+    }
+}
+
 // In reality we expect the store to hand these out safely.
 fn fake_known_entid(e: Entid) -> KnownEntid {
     KnownEntid(e)
@@ -72,10 +125,11 @@ fn test_entity_builder_bogus_entids() {
     let mut conn = Conn::connect(&mut sqlite).unwrap();
     let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
 
-    // This should fail: unrecognized entid.
+    // This should fail: 999 is not an attribute.
     match in_progress.transact_entities(terms).expect_err("expected transact to fail") {
         MentatError::DbError(e) => {
-            assert_eq!(e.kind(), db_traits::errors::DbErrorKind::UnrecognizedEntid(999));
+            assert_eq!(e.to_string(),
+                       "schema constraint violation: unknown attributes:\n  entity 1 uses 999, which is not an attribute\n  entity 2 uses 999, which is not an attribute\n");
         },
         _ => panic!("Should have rejected the entid."),
     }
@@ -180,3 +234,98 @@ fn test_entity_builder() {
     assert_eq!(conn.lookup_value_for_attribute(&mut sqlite, *y, &foo_ref).expect("lookup succeeded"),
                 Some(TypedValue::Ref(*x)));
 }
+
+#[test]
+fn test_transact_entities() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :person/handle    :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+        {:db/ident :person/full-name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :person/nick      :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :person/age       :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+        {:db/ident :person/friends   :db/valueType :db.type/ref    :db/cardinality :db.cardinality/many}
+        {:db/ident :tag/name         :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+    ]"#).expect("transacted schema");
+
+    let people = vec![
+        Person { handle: "alice".into(), full_name: "Alice Liddell".into(), nickname: None, age: 7, friends: vec!["bob".into(), "carol".into()], visits: 1 },
+        Person { handle: "bob".into(), full_name: "Bob".into(), nickname: Some("Bobby".into()), age: 8, friends: vec![], visits: 2 },
+        Person { handle: "carol".into(), full_name: "Carol".into(), nickname: None, age: 9, friends: vec!["alice".into()], visits: 3 },
+    ];
+
+    let report = store.transact_entities(people.iter()).expect("transacted people");
+    let alice = *report.tempids.get("alice").expect("alice");
+    let bob = *report.tempids.get("bob").expect("bob");
+    let carol = *report.tempids.get("carol").expect("carol");
+
+    let friends = store.q_once(r#"[:find [?f ...] :in ?p :where [?p :person/friends ?f] :order ?f]"#,
+                               mentat::QueryInputs::with_value_sequence(vec![(mentat::Variable::from_valid_name("?p"), TypedValue::Ref(alice))]))
+                       .into_coll_result()
+                       .expect("friends");
+    let mut expected = vec![bob, carol];
+    expected.sort();
+    assert_eq!(friends, expected.into_iter().map(|e| Binding::Scalar(TypedValue::Ref(e))).collect::<Vec<_>>());
+
+    let nick = store.q_once(r#"[:find ?n . :where [?p :person/handle "bob"] [?p :person/nick ?n]]"#, None)
+                    .into_scalar_result()
+                    .expect("nick");
+    assert_eq!(nick, Some(TypedValue::typed_string("Bobby").into()));
+
+    // Unique identities upsert, with or without a tempid.
+    let older = Person { handle: "alice".into(), full_name: "Alice Liddell".into(), nickname: None, age: 8, friends: vec![], visits: 0 };
+    let report = store.transact_entities(vec![older]).expect("upserted");
+    assert_eq!(report.tempids.get("alice"), Some(&alice));
+
+    store.transact_entities(vec![Tag("red"), Tag("red"), Tag("blue")]).expect("transacted tags");
+    let tags = store.q_once(r#"[:find (count ?t) . :where [?t :tag/name _]]"#, None)
+                    .into_scalar_result()
+                    .expect("tags");
+    assert_eq!(tags, Some(TypedValue::Long(2).into()));
+
+    let age = store.q_once(r#"[:find ?a . :where [?p :person/handle "alice"] [?p :person/age ?a]]"#, None)
+                   .into_scalar_result()
+                   .expect("age");
+    assert_eq!(age, Some(TypedValue::Long(8).into()));
+}
+
+#[test]
+fn test_mentat_entity() {
+    let definitions = Book::attribute_definitions();
+    let names: Vec<String> = definitions.iter().map(|&(ref a, _)| a.to_string()).collect();
+    assert_eq!(names, vec![":book/isbn", ":book/title", ":book/pages", ":book/tags", ":book/sequel"]);
+    assert_eq!(definitions[0].1.unique, Some(mentat::entity::Unique::Identity));
+    assert!(definitions[0].1.index);
+    assert!(definitions[1].1.fulltext);
+    assert_eq!(definitions[2].1.value_type, ValueType::Long);
+    assert!(definitions[3].1.multival);
+    assert_eq!(definitions[3].1.value_type, ValueType::Keyword);
+    assert_eq!(definitions[4].1.value_type, ValueType::Ref);
+
+    let mut store = Store::open("").expect("opened");
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.ensure_vocabulary(&Book::vocabulary(kw!(:test/books), 1)).expect("installed vocabulary");
+        in_progress.commit().expect("committed");
+    }
+
+    let books = vec![
+        Book { isbn: "1".into(), title: "Alice".into(), pages: Some(100), tags: vec![kw!(:genre/fantasy)], sequel: Some("2".into()) },
+        Book { isbn: "2".into(), title: "Looking Glass".into(), pages: None, tags: vec![], sequel: None },
+    ];
+    store.transact_entities(books.iter()).expect("transacted books");
+
+    let query = format!("[:find (pull ?b {}) . :where [?b :book/isbn \"1\"]]", Book::pull_pattern());
+    let pulled = store.q_once(query.as_str(), None).into_scalar_result().expect("pulled").expect("found");
+    assert_eq!(Book::from_binding(&pulled).expect("read book"),
+               Book { isbn: "1".into(), title: "Alice".into(), pages: Some(100), tags: vec![kw!(:genre/fantasy)], sequel: None });
+
+    let query = format!("[:find (pull ?b {}) . :where [?b :book/isbn \"2\"]]", Book::pull_pattern());
+    let pulled = store.q_once(query.as_str(), None).into_scalar_result().expect("pulled").expect("found");
+    assert_eq!(Book::from_binding(&pulled).expect("read book"),
+               Book { isbn: "2".into(), title: "Looking Glass".into(), pages: None, tags: vec![], sequel: None });
+
+    match Book::from_binding(&Binding::Scalar(TypedValue::Long(1))) {
+        Err(MentatError::ExpectedEntityMap) => {},
+        x => panic!("expected ExpectedEntityMap, got {:?}", x),
+    }
+}
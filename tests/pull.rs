@@ -35,6 +35,7 @@ use mentat_core::{
 };
 
 use mentat::{
+    CacheDirection,
     Entid,
     HasSchema,
     IntoResult,
@@ -251,10 +252,163 @@ fn test_simple_pull() {
     assert_eq!(results, expected);
 }
 
+#[test]
+fn test_pull_in_each_find_spec() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    let report = store.transact(r#"[{:db/id "a" :foo/name "Alice" :foo/age 30}]"#).expect("transacted data");
+    let alice = *report.tempids.get("a").expect("alice");
+
+    let pulled: StructuredMap = vec![
+        (kw!(:foo/name), TypedValue::from("Alice")),
+        (kw!(:foo/age), TypedValue::Long(30)),
+    ].into();
+    let pulled: Binding = pulled.into();
+
+    // Pull expressions and plain variables can be mixed in a tuple…
+    let query = r#"[:find [?name (pull ?e [:foo/name :foo/age])]
+                    :where [?e :foo/name ?name]]"#;
+    let tuple = store.q_once(query, None)
+                     .into_tuple_result()
+                     .expect("result")
+                     .expect("a tuple");
+    assert_eq!(tuple, vec![Binding::Scalar(TypedValue::from("Alice")), pulled.clone()]);
+
+    // … and in a relation.
+    let query = r#"[:find ?e (pull ?e [:foo/name :foo/age]) ?age
+                    :where [?e :foo/age ?age]]"#;
+    let rel = store.q_once(query, None)
+                   .into_rel_result()
+                   .expect("result");
+    let expected = RelResult {
+                       width: 3,
+                       values: vec![TypedValue::Ref(alice).into(), pulled.clone(), TypedValue::Long(30).into()],
+                   };
+    assert_eq!(rel, expected);
+
+    // Pulling attributes that the entity doesn't have yields an empty map, not nothing.
+    let query = r#"[:find (pull ?e [:db/ident]) .
+                    :where [?e :foo/name "Alice"]]"#;
+    let scalar = store.q_once(query, None)
+                      .into_scalar_result()
+                      .expect("result")
+                      .expect("a map");
+    let empty: StructuredMap = Default::default();
+    assert_eq!(scalar, empty.into());
+}
+
+#[test]
+fn test_lookup_entity() {
+    let mut store = Store::open("").expect("opened");
+    let alice;
+    let bob;
+    let charlie;
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(r#"[
+            {:db/ident :foo/name
+             :db/valueType :db.type/string
+             :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/nick
+             :db/valueType :db.type/string
+             :db/cardinality :db.cardinality/many}
+            {:db/ident :foo/friend
+             :db/valueType :db.type/ref
+             :db/cardinality :db.cardinality/many}
+        ]"#).expect("transacted schema");
+        let report = in_progress.transact(r#"[
+            {:db/id "b" :foo/name "Bob"}
+            {:db/id "c" :foo/name "Charlie" :foo/nick "Chuck"}
+            {:db/id "a" :db/ident :people/alice :foo/name "Alice" :foo/nick ["Al" "Ali"] :foo/friend ["b" "c"]}
+        ]"#).expect("transacted data");
+        alice = *report.tempids.get("a").expect("a");
+        bob = *report.tempids.get("b").expect("b");
+        charlie = *report.tempids.get("c").expect("c");
+        in_progress.commit().expect("committed");
+    }
+
+    let entity = store.lookup_entity(alice).expect("looked up").expect("alice");
+    assert_eq!(entity.entid, alice);
+    assert_eq!(entity.get(&kw!(:foo/name)), Some(&"Alice".into()));
+    assert_eq!(entity.get(&kw!(:db/ident)), Some(&TypedValue::Keyword(kw!(:people/alice).into()).into()));
+
+    // Cardinality-many attributes are vectors, even with a single value.  Refs are entids.
+    let nicks = Binding::Vec(ValueRc::new(vec!["Al".into(), "Ali".into()]));
+    let friends = Binding::Vec(ValueRc::new(vec![TypedValue::Ref(bob).into(), TypedValue::Ref(charlie).into()]));
+    assert_eq!(entity.get(&kw!(:foo/nick)), Some(&nicks));
+    assert_eq!(entity.get(&kw!(:foo/friend)), Some(&friends));
+    assert_eq!(store.lookup_entity(charlie).expect("looked up").expect("charlie").get(&kw!(:foo/nick)),
+               Some(&Binding::Vec(ValueRc::new(vec!["Chuck".into()]))));
+
+    // Entities can be named by ident.
+    assert_eq!(store.lookup_entity(kw!(:people/alice)).expect("looked up"), Some(entity.clone()));
+
+    // Nothing is asserted about these.
+    assert_eq!(store.lookup_entity(kw!(:people/nobody)).expect("looked up"), None);
+    assert_eq!(store.lookup_entity(alice + 1000).expect("looked up"), None);
+
+    // With every attribute cached, the entity comes from the cache, and agrees.
+    let attributes: Vec<Keyword> = store.conn().current_schema()
+                                        .attribute_map
+                                        .keys()
+                                        .map(|a| store.conn().current_schema().get_ident(*a).expect("named").clone())
+                                        .collect();
+    for attribute in attributes.iter() {
+        store.cache(attribute, CacheDirection::Forward).expect("cached");
+    }
+    let cached = store.lookup_entity(alice).expect("looked up").expect("alice");
+    assert_eq!(cached.get(&kw!(:foo/nick)), Some(&nicks));
+    assert_eq!(cached.get(&kw!(:foo/friend)), Some(&friends));
+    assert_eq!(cached.get(&kw!(:foo/name)), entity.get(&kw!(:foo/name)));
+    assert_eq!(cached.attributes.len(), entity.attributes.len());
+}
+
+#[test]
+fn test_pull_components() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :order/id :db/valueType :db.type/long :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+        {:db/ident :order/item :db/valueType :db.type/ref :db/cardinality :db.cardinality/many :db/isComponent true}
+        {:db/ident :order/customer :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+        {:db/ident :item/sku :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :item/note :db/valueType :db.type/ref :db/cardinality :db.cardinality/one :db/isComponent true}
+        {:db/ident :note/text :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    let report = store.transact(r#"[
+        {:db/id "customer" :note/text "Alice"}
+        {:order/id 1
+         :order/customer "customer"
+         :order/item [{:item/sku "apple" :item/note {:note/text "ripe"}}]}
+    ]"#).expect("transacted data");
+    let customer = *report.tempids.get("customer").expect("customer");
+
+    // Components are inlined, recursively; other refs are entids.
+    let note: StructuredMap = vec![(kw!(:note/text), TypedValue::from("ripe"))].into();
+    let item: StructuredMap = vec![
+        (kw!(:item/sku), Binding::from(TypedValue::from("apple"))),
+        (kw!(:item/note), note.into()),
+    ].into();
+    let order: StructuredMap = vec![
+        (kw!(:order/id), Binding::from(TypedValue::Long(1))),
+        (kw!(:order/customer), TypedValue::Ref(customer).into()),
+        (kw!(:order/item), Binding::Vec(ValueRc::new(vec![item.into()]))),
+    ].into();
+
+    let query = r#"[:find (pull ?o [:order/id :order/customer :order/item]) .
+                    :where [?o :order/id 1]]"#;
+    let pulled = store.q_once(query, None)
+                      .into_scalar_result()
+                      .expect("result")
+                      .expect("a map");
+    assert_eq!(pulled, order.into());
+}
+
 // TEST:
 // - Constant query bodies in pull.
 // - Values that are present in the cache (=> constant pull, too).
-// - Pull for each kind of find spec.
 // - That the keys in each map are ValueRc::ptr_eq.
 // - Entity presence/absence when the pull expressions don't match anything.
 // - Aliases. (No parser support yet.)
@@ -9,6 +9,8 @@
 // specific language governing permissions and limitations under the License.
 
 extern crate chrono;
+#[cfg(feature = "sqlcipher")]
+extern crate tempfile;
 extern crate time;
 
 #[macro_use]
@@ -31,6 +33,7 @@ use chrono::FixedOffset;
 use core_traits::{
     Entid,
     KnownEntid,
+    StructuredMap,
     ValueType,
     ValueTypeSet,
 };
@@ -47,6 +50,10 @@ use query_projector_traits::aggregates::{
 };
 
 use mentat::{
+    BigInt,
+    ColumnMetadata,
+    DatomsBasis,
+    IndexDirection,
     IntoResult,
     Keyword,
     PlainSymbol,
@@ -62,7 +69,10 @@ use mentat::{
     new_connection,
 };
 
-use mentat::query::q_uncached;
+use mentat::query::{
+    PreparedQuery,
+    q_uncached,
+};
 
 use mentat::conn::Conn;
 
@@ -84,7 +94,7 @@ fn test_rel() {
     let end = time::PreciseTime::now();
 
     // This will need to change each time we add a default ident.
-    assert_eq!(40, results.len());
+    assert_eq!(44, results.len());
 
     // Every row is a pair of a Ref and a Keyword.
     if let QueryResults::Rel(rel) = results {
@@ -195,7 +205,7 @@ fn test_coll() {
         .results;
     let end = time::PreciseTime::now();
 
-    assert_eq!(40, results.len());
+    assert_eq!(44, results.len());
 
     if let QueryResults::Coll(ref coll) = results {
         assert!(coll.iter().all(|item| item.matches_type(ValueType::Ref)));
@@ -358,6 +368,192 @@ fn test_tx_as_input() {
     }
 }
 
+#[test]
+fn test_tx_instant() {
+    let mut c = new_connection("").expect("Couldn't open conn.");
+    let mut conn = Conn::connect(&mut c).expect("Couldn't open DB.");
+    conn.transact(&mut c, r#"[
+        [:db/add "s" :db/ident :foo/uuid]
+        [:db/add "s" :db/valueType :db.type/uuid]
+        [:db/add "s" :db/cardinality :db.cardinality/one]
+    ]"#).expect("successful transaction");
+    let first = conn.transact(&mut c, r#"[
+        [:db/add "u" :foo/uuid #uuid "cf62d552-6569-4d1b-b667-04703041dfc4"]
+    ]"#).expect("successful transaction");
+    let second = conn.transact(&mut c, r#"[
+        [:db/add "u" :foo/uuid #uuid "550e8400-e29b-41d4-a716-446655440000"]
+    ]"#).expect("successful transaction");
+
+    // Transactions can be projected, and ordered by when they happened.
+    let r = conn.q_once(&mut c,
+                        r#"[:find ?tx ?when
+                            :where [_ :foo/uuid _ ?tx]
+                                   [(tx-instant ?tx) ?when]
+                            :order (desc ?when) (desc ?tx)]"#, None)
+                .expect("results")
+                .into();
+    match r {
+        QueryResults::Rel(ref v) => {
+            assert_eq!(*v, vec![
+                vec![TypedValue::Ref(second.tx_id), TypedValue::Instant(second.tx_instant)],
+                vec![TypedValue::Ref(first.tx_id), TypedValue::Instant(first.tx_instant)],
+            ].into());
+        },
+        _ => panic!("Expected query to work."),
+    }
+
+    // A transaction can be given directly.
+    let r = conn.q_once(&mut c,
+                        &format!("[:find ?when . :where [(tx-instant {}) ?when]]", first.tx_id), None)
+                .expect("results")
+                .into();
+    match r {
+        QueryResults::Scalar(Some(Binding::Scalar(TypedValue::Instant(when)))) => {
+            assert_eq!(when, first.tx_instant);
+        },
+        _ => panic!("Expected query to work."),
+    }
+}
+
+#[test]
+fn test_bigint() {
+    let mut c = new_connection("").expect("Couldn't open conn.");
+    let mut conn = Conn::connect(&mut c).expect("Couldn't open DB.");
+    conn.transact(&mut c, r#"[
+        [:db/add "s" :db/ident :foo/big]
+        [:db/add "s" :db/valueType :db.type/bigint]
+        [:db/add "s" :db/cardinality :db.cardinality/one]
+        [:db/add "s" :db/index true]
+        [:db/add "n" :db/ident :foo/name]
+        [:db/add "n" :db/valueType :db.type/string]
+        [:db/add "n" :db/cardinality :db.cardinality/one]
+    ]"#).expect("successful transaction");
+
+    // Longs are big integers, too.
+    let report = conn.transact(&mut c, r#"[
+        {:db/id "a" :foo/name "a" :foo/big 123456789012345678901234567890N}
+        {:db/id "b" :foo/name "b" :foo/big 5}
+    ]"#).expect("successful transaction");
+    let a = *report.tempids.get("a").expect("a");
+
+    let big = BigInt::from_str("123456789012345678901234567890").expect("big integer");
+    let r = conn.q_once(&mut c,
+                        r#"[:find ?name ?v
+                            :where [?e :foo/big ?v] [?e :foo/name ?name]
+                            :order ?name]"#, None)
+                .expect("results")
+                .into();
+    match r {
+        QueryResults::Rel(ref v) => {
+            assert_eq!(*v, vec![
+                vec![TypedValue::typed_string("a"), TypedValue::BigInteger(big.clone())],
+                vec![TypedValue::typed_string("b"), TypedValue::BigInteger(BigInt::from(5))],
+            ].into());
+        },
+        _ => panic!("Expected query to work."),
+    }
+
+    // Big integers can be matched as constants, whether or not we know the attribute...
+    for query in &["[:find ?e . :where [?e :foo/big 123456789012345678901234567890N]]",
+                   "[:find ?e . :where [?e ?a 123456789012345678901234567890N]]"] {
+        let r = conn.q_once(&mut c, query, None).expect("results").into();
+        match r {
+            QueryResults::Scalar(Some(Binding::Scalar(TypedValue::Ref(e)))) => assert_eq!(e, a),
+            _ => panic!("Expected query to work."),
+        }
+    }
+
+    // ... or as inputs.
+    let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?v"), TypedValue::BigInteger(big))]);
+    let r = conn.q_once(&mut c, "[:find ?name . :in ?v :where [?e :foo/big ?v] [?e :foo/name ?name]]", inputs)
+                .expect("results")
+                .into();
+    match r {
+        QueryResults::Scalar(Some(Binding::Scalar(name))) => assert_eq!(name, TypedValue::typed_string("a")),
+        _ => panic!("Expected query to work."),
+    }
+
+    // Big integers sort numerically, not by their digits.
+    conn.transact(&mut c, r#"[
+        {:db/id "c" :foo/name "c" :foo/big -1000N}
+        {:db/id "d" :foo/name "d" :foo/big -99N}
+        {:db/id "e" :foo/name "e" :foo/big 0N}
+        {:db/id "f" :foo/name "f" :foo/big 40N}
+    ]"#).expect("successful transaction");
+    for (order, expected) in &[("?v", vec!["c", "d", "e", "b", "f", "a"]),
+                               ("(desc ?v)", vec!["a", "f", "b", "e", "d", "c"])] {
+        let query = format!("[:find [?name ...] :where [?e :foo/big ?v] [?e :foo/name ?name] :order {}]", order);
+        let r = conn.q_once(&mut c, query.as_str(), None).expect("results").into();
+        match r {
+            QueryResults::Coll(names) => {
+                let expected: Vec<Binding> = expected.iter().map(|n| TypedValue::typed_string(n).into()).collect();
+                assert_eq!(names, expected);
+            },
+            _ => panic!("Expected query to work."),
+        }
+    }
+}
+
+#[test]
+fn test_bytes() {
+    let mut c = new_connection("").expect("Couldn't open conn.");
+    let mut conn = Conn::connect(&mut c).expect("Couldn't open DB.");
+    conn.transact(&mut c, r#"[
+        [:db/add "s" :db/ident :foo/hash]
+        [:db/add "s" :db/valueType :db.type/bytes]
+        [:db/add "s" :db/cardinality :db.cardinality/one]
+        [:db/add "s" :db/unique :db.unique/identity]
+        [:db/add "s" :db/index true]
+        [:db/add "n" :db/ident :foo/name]
+        [:db/add "n" :db/valueType :db.type/string]
+        [:db/add "n" :db/cardinality :db.cardinality/one]
+    ]"#).expect("successful transaction");
+
+    let report = conn.transact(&mut c, r#"[
+        {:db/id "a" :foo/hash #bytes "3q2+7w==" :foo/name "a"}
+        {:db/id "b" :foo/hash #bytes "AAEC" :foo/name "b"}
+    ]"#).expect("successful transaction");
+    let a = *report.tempids.get("a").expect("a");
+
+    // Bytes are compared exactly, so they can identify an entity.
+    let report = conn.transact(&mut c, r#"[
+        {:db/id "c" :foo/hash #bytes "3q2+7w==" :foo/name "c"}
+    ]"#).expect("successful transaction");
+    assert_eq!(report.tempids.get("c"), Some(&a));
+
+    let r = conn.q_once(&mut c,
+                        r#"[:find ?name ?hash
+                            :where [?e :foo/hash ?hash] [?e :foo/name ?name]
+                            :order ?name]"#, None)
+                .expect("results")
+                .into();
+    match r {
+        QueryResults::Rel(ref v) => {
+            assert_eq!(*v, vec![
+                vec![TypedValue::typed_string("b"), TypedValue::from(vec![0u8, 1, 2])],
+                vec![TypedValue::typed_string("c"), TypedValue::from(vec![0xdeu8, 0xad, 0xbe, 0xef])],
+            ].into());
+        },
+        _ => panic!("Expected query to work."),
+    }
+
+    // Bytes can be matched as constants, whether or not we know the attribute.
+    for query in &[r#"[:find ?e . :where [?e :foo/hash #bytes "3q2+7w=="]]"#,
+                   r#"[:find ?e . :where [?e ?a #bytes "3q2+7w=="]]"#] {
+        let r = conn.q_once(&mut c, query, None).expect("results").into();
+        match r {
+            QueryResults::Scalar(Some(Binding::Scalar(TypedValue::Ref(e)))) => assert_eq!(e, a),
+            _ => panic!("Expected query to work."),
+        }
+    }
+
+    // A string is not bytes.
+    let r: QueryResults = conn.q_once(&mut c, r#"[:find ?e . :where [?e :foo/hash "3q2+7w=="]]"#, None)
+                .expect("results")
+                .into();
+    assert_eq!(r, QueryResults::Scalar(None));
+}
+
 #[test]
 fn test_fulltext() {
     let mut c = new_connection("").expect("Couldn't open conn.");
@@ -396,7 +592,7 @@ fn test_fulltext() {
                  None) => {
                      assert_eq!(x, v);
                      assert_eq!(text.as_str(), "hello darkness my old friend");
-                     assert_eq!(score, 0.0f64.into());
+                     assert!(score.into_inner() > 0.0);
                  },
                  _ => panic!("Unexpected results."),
             }
@@ -462,6 +658,70 @@ fn test_fulltext() {
         },
         _ => panic!("Expected query to work."),
     }
+
+    // Scores rank matches: the more often a term appears, the more relevant the text.
+    conn.transact(&mut c, r#"[
+        [:db/add "w" :foo/fts "a darkness, a darkness, and darkness again"]
+        [:db/add "w" :foo/fts "a bright and sunny morning"]
+    ]"#).unwrap();
+    let r = conn.q_once(&mut c,
+                        r#"[:find ?val ?score
+                            :where [(fulltext $ :foo/fts "darkness") [[_ ?val _ ?score]]]
+                            :order (desc ?score)]"#, None)
+                .expect("results")
+                .into();
+    match r {
+        QueryResults::Rel(rels) => {
+            let values: Vec<Vec<Binding>> = rels.into_iter().collect();
+            assert_eq!(values.len(), 2);
+            assert_eq!(values[0][0], "a darkness, a darkness, and darkness again".into());
+            assert_eq!(values[1][0], "hello darkness my old friend".into());
+        },
+        _ => panic!("Expected query to work."),
+    }
+}
+
+#[test]
+fn test_normalize() {
+    let mut c = new_connection("").expect("Couldn't open conn.");
+    let mut conn = Conn::connect(&mut c).expect("Couldn't open DB.");
+
+    conn.transact(&mut c, r#"[
+        [:db/add "s" :db/ident :foo/dessert]
+        [:db/add "s" :db/valueType :db.type/string]
+        [:db/add "s" :db/fulltext true]
+        [:db/add "s" :db/index true]
+        [:db/add "s" :db/normalize true]
+        [:db/add "s" :db/cardinality :db.cardinality/one]
+    ]"#).unwrap();
+
+    // Transact the precomposed form.
+    let v = conn.transact(&mut c, "[[:db/add \"v\" :foo/dessert \"cr\u{e8}me br\u{fb}l\u{e9}e\"]]")
+                .unwrap()
+                .tempids
+                .get("v").cloned().expect("v was mapped");
+
+    // A decomposed query constant finds it.
+    let r: QueryResults = conn.q_once(&mut c,
+                        "[:find ?x . :where [?x :foo/dessert \"cre\u{300}me bru\u{302}le\u{301}e\"]]", None)
+                .expect("results")
+                .into();
+    assert_eq!(r, QueryResults::Scalar(Some(Binding::Scalar(TypedValue::Ref(v)))));
+
+    // Fulltext search ignores diacritics, but returns the stored text.
+    let r: QueryResults = conn.q_once(&mut c,
+                        r#"[:find [?x ?val]
+                            :where [(fulltext $ :foo/dessert "creme") [[?x ?val]]]]"#, None)
+                .expect("results")
+                .into();
+    assert_eq!(r, QueryResults::Tuple(Some(vec![Binding::Scalar(TypedValue::Ref(v)),
+                                                "cr\u{e8}me br\u{fb}l\u{e9}e".into()])));
+
+    let r: QueryResults = conn.q_once(&mut c,
+                        "[:find ?x . :where [(fulltext $ :foo/dessert \"br\u{fb}l\u{e9}e\") [[?x]]]]", None)
+                .expect("results")
+                .into();
+    assert_eq!(r, QueryResults::Scalar(Some(Binding::Scalar(TypedValue::Ref(v)))));
 }
 
 #[test]
@@ -544,6 +804,95 @@ fn test_lookup() {
     assert!(two_longs.contains(&fetched_many));
 }
 
+#[test]
+fn test_ident_in_entity_position() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :app/setting :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :app/flag :db/valueType :db.type/boolean :db/cardinality :db.cardinality/many}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:db/ident :my/singleton :app/setting "on" :app/flag [true false]}
+        {:db/ident :my/other :app/setting "off"}
+    ]"#).expect("transacted data");
+
+    let singleton = store.conn().current_schema().get_entid(&kw!(:my/singleton)).expect("ident").0;
+
+    // A keyword in entity position is resolved to its entid when the query is algebrized…
+    let r = store.q_once(r#"[:find ?v . :where [:my/singleton :app/setting ?v]]"#, None)
+                 .into_scalar_result()
+                 .expect("results");
+    assert_eq!(r, Some(TypedValue::typed_string("on").into()));
+
+    let r = store.q_once(r#"[:find [?v ...] :where [:my/singleton :app/flag ?v]]"#, None)
+                 .into_coll_result()
+                 .expect("results");
+    assert_eq!(r.len(), 2);
+
+    // … so no join against :db/ident is needed.
+    let explanation = store.q_explain(r#"[:find ?v . :where [:my/singleton :app/setting ?v]]"#, None).expect("explained");
+    match explanation {
+        mentat::QueryExplanation::ExecutionPlan { query, .. } => {
+            assert!(query.sql.contains(&format!("`datoms00`.e = {}", singleton)), "{}", query.sql);
+            assert!(!query.sql.contains("datoms01"), "{}", query.sql);
+        },
+        _ => panic!("expected an execution plan"),
+    }
+
+    // An unknown ident can't match anything.
+    let r = store.q_once(r#"[:find ?v . :where [:my/missing :app/setting ?v]]"#, None)
+                 .into_scalar_result()
+                 .expect("results");
+    assert_eq!(r, None);
+}
+
+#[test]
+fn test_lookup_ref_in_entity_position() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/email :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        [:db/add "x" :foo/email "x@y.com"]
+        [:db/add [:foo/email "x@y.com"] :foo/name "X"]
+    ]"#).expect_err("lookup refs resolve against the store before the transaction");
+    store.transact(r#"[{:foo/email "x@y.com"} {:foo/email "z@y.com" :foo/name "Z"}]"#).expect("transacted data");
+    store.transact(r#"[[:db/add [:foo/email "x@y.com"] :foo/name "X"]]"#).expect("transacted with lookup ref");
+
+    let r = store.q_once(r#"[:find ?n . :where [[:foo/email "x@y.com"] :foo/name ?n]]"#, None)
+                 .into_scalar_result()
+                 .expect("results");
+    assert_eq!(r, Some(TypedValue::typed_string("X").into()));
+
+    // Lookup refs work inside `or` and `not`, too.
+    let r = store.q_once(r#"[:find [?n ...]
+                             :where [?e :foo/name ?n]
+                                    (not [[:foo/email "x@y.com"] :foo/name ?n])]"#, None)
+                 .into_coll_result()
+                 .expect("results");
+    assert_eq!(r, vec![TypedValue::typed_string("Z").into()]);
+
+    let r = store.q_once(r#"[:find [?n ...]
+                             :where (or [[:foo/email "x@y.com"] :foo/name ?n]
+                                        [[:foo/email "z@y.com"] :foo/name ?n])]"#, None)
+                 .into_coll_result()
+                 .expect("results");
+    assert_eq!(r.len(), 2);
+
+    // A lookup ref that doesn't resolve can't match anything.
+    let r = store.q_once(r#"[:find ?n . :where [[:foo/email "w@y.com"] :foo/name ?n]]"#, None)
+                 .into_scalar_result()
+                 .expect("results");
+    assert_eq!(r, None);
+
+    // Lookup ref attributes must be :db/unique.
+    match store.q_once(r#"[:find ?n . :where [[:foo/name "X"] :foo/name ?n]]"#, None).expect_err("expected non-unique lookup ref") {
+        MentatError::AlgebrizerError(query_algebrizer_traits::errors::AlgebrizerError::NonUniqueLookupRef(attribute)) => assert_eq!(attribute, ":foo/name"),
+        x => panic!("expected NonUniqueLookupRef, got {:?}", x),
+    }
+}
+
 #[test]
 fn test_aggregates_type_handling() {
     let mut store = Store::open("").expect("opened");
@@ -638,8 +987,8 @@ fn test_aggregates_type_handling() {
                  .expect("results")
                  .unwrap();
 
-    // Yes, the current version is in the store as a Long!
-    let total = 30i64 + 20i64 + 10i64 + ::mentat_db::db::CURRENT_VERSION as i64;
+    // Yes, the core schema version is in the store as a Long!
+    let total = 30i64 + 20i64 + 10i64 + ::mentat_db::CORE_SCHEMA_VERSION as i64;
     assert_eq!(Binding::Scalar(TypedValue::Long(total)), r);
 
     let r = store.q_once(r#"[:find (avg ?v) .
@@ -667,6 +1016,8 @@ fn test_type_reqs() {
         {:db/ident :test/uuid    :db/valueType :db.type/uuid    :db/cardinality :db.cardinality/one}
         {:db/ident :test/instant :db/valueType :db.type/instant :db/cardinality :db.cardinality/one}
         {:db/ident :test/ref     :db/valueType :db.type/ref     :db/cardinality :db.cardinality/one}
+        {:db/ident :test/bigint  :db/valueType :db.type/bigint  :db/cardinality :db.cardinality/one}
+        {:db/ident :test/bytes   :db/valueType :db.type/bytes   :db/cardinality :db.cardinality/one}
     ]"#).unwrap();
 
     conn.transact(&mut c, r#"[
@@ -677,7 +1028,9 @@ fn test_type_reqs() {
          :test/keyword :foo/bar
          :test/uuid    #uuid "12341234-1234-1234-1234-123412341234"
          :test/instant #inst "2018-01-01T11:00:00.000Z"
-         :test/ref     1}
+         :test/ref     1
+         :test/bigint  123456789012345678901234567890N
+         :test/bytes   #bytes "3q2+7w=="}
     ]"#).unwrap();
 
     let eid_query = r#"[:find ?eid :where [?eid :test/string "foo"]]"#;
@@ -996,6 +1349,42 @@ fn test_basic_aggregates() {
         },
         _ => panic!("Expected rel."),
     }
+
+    // Which age is most common?
+    let r = store.q_once(r#"[:find ?age (count ?person)
+                             :order (desc (count ?person)) ?age
+                             :where [?person :foo/age ?age]]"#, None)
+                 .expect("results")
+                 .into();
+
+    match r {
+        QueryResults::Rel(vals) => {
+            assert_eq!(vals, vec![
+                vec![TypedValue::Long(28), TypedValue::Long(2)],
+                vec![TypedValue::Long(14), TypedValue::Long(1)],
+                vec![TypedValue::Long(22), TypedValue::Long(1)],
+                vec![TypedValue::Long(42), TypedValue::Long(1)],
+            ].into());
+        },
+        _ => panic!("Expected rel."),
+    }
+
+    // Who's oldest, via arithmetic?
+    let r = store.q_once(r#"[:find [?name ?age]
+                             :order (- 50 ?age)
+                             :where
+                             [?x :foo/age ?age]
+                             [?x :foo/name ?name]]"#, None)
+                 .expect("results")
+                 .into();
+    match r {
+        QueryResults::Tuple(Some(vals)) => {
+            assert_eq!(vals,
+                       vec!["Carlos".into(),
+                            Binding::Scalar(TypedValue::Long(42))]);
+        },
+        r => panic!("Unexpected results {:?}", r),
+    }
 }
 
 #[test]
@@ -1335,6 +1724,18 @@ fn test_aggregation_implicit_grouping() {
                     .into_tuple_result()
                     .expect("tuple results").unwrap());
 
+    // Ordering by the corresponding variable doesn't change which row it comes from.
+    assert_eq!(vec!["Alice".into(), Binding::Scalar(TypedValue::Long(99))],
+               store.q_once(r#"[:find [(the ?name) (max ?score)]
+                                :where
+                                [?game :foo/score ?score]
+                                [?person :foo/play ?game]
+                                [?person :foo/is-vegetarian true]
+                                [?person :foo/name ?name]
+                                :order ?name]"#, None)
+                    .into_tuple_result()
+                    .expect("tuple results").unwrap());
+
     // We can't run an ambiguous correspondence.
     let res = store.q_once(r#"[:find [(the ?name) (min ?score) (max ?score)]
                                :where
@@ -1404,6 +1805,270 @@ fn test_aggregation_implicit_grouping() {
     }
 }
 
+#[test]
+fn test_limit_and_offset() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[{:foo/n 1} {:foo/n 2} {:foo/n 3} {:foo/n 4} {:foo/n 5}]"#).expect("transacted data");
+
+    let page = |store: &mut Store, query: &str, inputs: Option<QueryInputs>| -> Vec<Binding> {
+        store.q_once(query, inputs).into_coll_result().expect("results")
+    };
+    let longs = |ns: &[i64]| -> Vec<Binding> { ns.iter().map(|n| TypedValue::Long(*n).into()).collect() };
+
+    assert_eq!(page(&mut store, "[:find [?n ...] :where [_ :foo/n ?n] :order ?n :limit 2 :offset 1]", None),
+               longs(&[2, 3]));
+
+    // An offset without a limit returns everything after it.
+    assert_eq!(page(&mut store, "[:find [?n ...] :where [_ :foo/n ?n] :order (desc ?n) :offset 3]", None),
+               longs(&[2, 1]));
+
+    // Offsets past the end are fine, and zero is no offset at all.
+    assert_eq!(page(&mut store, "[:find [?n ...] :where [_ :foo/n ?n] :order ?n :offset 10]", None),
+               longs(&[]));
+    assert_eq!(page(&mut store, "[:find [?n ...] :where [_ :foo/n ?n] :order ?n :limit 1 :offset 0]", None),
+               longs(&[1]));
+
+    // The offset can be an input.
+    let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?skip"), TypedValue::Long(4))]);
+    assert_eq!(page(&mut store, "[:find [?n ...] :in ?skip :where [_ :foo/n ?n] :order ?n :offset ?skip]", Some(inputs)),
+               longs(&[5]));
+
+    let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?skip"), TypedValue::Long(-1))]);
+    match store.q_once("[:find [?n ...] :in ?skip :where [_ :foo/n ?n] :offset ?skip]", Some(inputs)).expect_err("expected error") {
+        MentatError::AlgebrizerError(query_algebrizer_traits::errors::AlgebrizerError::InvalidOffset(val, ValueType::Long)) => assert_eq!(val, "-1"),
+        e => panic!("Unexpected error {:?}", e),
+    }
+
+    match store.q_once("[:find [?n ...] :where [_ :foo/n ?n] :offset ?skip]", None).expect_err("expected error") {
+        MentatError::AlgebrizerError(query_algebrizer_traits::errors::AlgebrizerError::UnknownOffsetVar(var)) => assert_eq!(var.to_string(), "?skip"),
+        e => panic!("Unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn test_not_distinct() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/n :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/tag :db/valueType :db.type/keyword :db/cardinality :db.cardinality/many}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[{:foo/n 1 :foo/tag [:a :b]} {:foo/n 1 :foo/tag :a} {:foo/n 2}]"#).expect("transacted data");
+
+    let ns = |store: &mut Store, query: &str| -> Vec<Binding> {
+        store.q_once(query, None).into_coll_result().expect("results")
+    };
+    let longs = |ns: &[i64]| -> Vec<Binding> { ns.iter().map(|n| TypedValue::Long(*n).into()).collect() };
+
+    assert_eq!(ns(&mut store, "[:find [?n ...] :where [_ :foo/n ?n] :order ?n]"),
+               longs(&[1, 2]));
+    assert_eq!(ns(&mut store, "[:find [?n ...] :where [_ :foo/n ?n] :order ?n :distinct false]"),
+               longs(&[1, 1, 2]));
+
+    // Each row that matches is kept, even those that only differ in variables we don't project.
+    assert_eq!(ns(&mut store, "[:find [?n ...] :where [?e :foo/n ?n] [?e :foo/tag _] :order ?n :distinct false]"),
+               longs(&[1, 1, 1]));
+
+    // Aggregates, too.
+    assert_eq!(Binding::Scalar(TypedValue::Long(4)),
+               store.q_once("[:find (sum ?n) . :where [_ :foo/n ?n] :distinct false]", None)
+                    .into_scalar_result()
+                    .expect("scalar results").unwrap());
+}
+
+#[test]
+fn test_ground_binding_forms() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+        {:db/ident :foo/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:foo/name "Alice" :foo/age 30}
+        {:foo/name "Bob"   :foo/age 40}
+        {:foo/name "Carol" :foo/age 50}
+    ]"#).expect("transacted data");
+
+    // Scalar.
+    let age = store.q_once(r#"[:find ?age . :where [(ground "Bob") ?name] [?e :foo/name ?name] [?e :foo/age ?age]]"#, None)
+                   .into_scalar_result()
+                   .expect("result");
+    assert_eq!(age, Some(TypedValue::Long(40).into()));
+
+    // Tuple.
+    let name = store.q_once(r#"[:find ?name . :where [(ground [40 "Bob"]) [?age ?name]] [?e :foo/name ?name] [?e :foo/age ?age]]"#, None)
+                    .into_scalar_result()
+                    .expect("result");
+    assert_eq!(name, Some(TypedValue::typed_string("Bob").into()));
+
+    // Collection.
+    let ages = store.q_once(r#"[:find [?age ...] :where [(ground ["Alice" "Carol" "Dave"]) [?name ...]] [?e :foo/name ?name] [?e :foo/age ?age] :order ?age]"#, None)
+                    .into_coll_result()
+                    .expect("result");
+    assert_eq!(ages, vec![TypedValue::Long(30).into(), TypedValue::Long(50).into()]);
+
+    // Relation: only the rows that agree with the store match.
+    let names = store.q_once(r#"[:find [?name ...] :where [(ground [["Alice" 30] ["Bob" 41] ["Carol" 50]]) [[?name ?age]]] [?e :foo/name ?name] [?e :foo/age ?age] :order ?name]"#, None)
+                     .into_coll_result()
+                     .expect("result");
+    assert_eq!(names, vec![TypedValue::typed_string("Alice").into(), TypedValue::typed_string("Carol").into()]);
+}
+
+#[test]
+fn test_starts_with() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :page/url   :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true}
+        {:db/ident :page/title :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :page/tag   :db/valueType :db.type/keyword :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:page/url "https://example.com/a" :page/title "Example"}
+        {:page/url "https://example.org/" :page/title "example"}
+        {:page/url "http://example.com/" :page/title "Éxample"}
+        {:page/tag :example/tag}
+    ]"#).expect("transacted data");
+
+    let strings = |store: &mut Store, query: &str| -> Vec<String> {
+        let mut results: Vec<String> = store.q_once(query, None)
+                                            .into_coll_result()
+                                            .expect("results")
+                                            .into_iter()
+                                            .map(|b| b.into_string().expect("string").as_ref().clone())
+                                            .collect();
+        results.sort();
+        results
+    };
+
+    // Indexed and unindexed attributes agree.
+    assert_eq!(strings(&mut store, r#"[:find [?u ...] :where [_ :page/url ?u] [(starts-with ?u "https://example.")]]"#),
+               vec!["https://example.com/a", "https://example.org/"]);
+    assert_eq!(strings(&mut store, r#"[:find [?t ...] :where [_ :page/title ?t] [(starts-with ?t "Ex")]]"#),
+               vec!["Example"]);
+
+    // Matching is by code point: case and accents matter.
+    assert_eq!(strings(&mut store, r#"[:find [?t ...] :where [_ :page/title ?t] [(starts-with ?t "É")]]"#),
+               vec!["Éxample"]);
+    assert_eq!(strings(&mut store, r#"[:find [?t ...] :where [_ :page/title ?t] [(starts-with ?t "")]]"#),
+               vec!["Example", "example", "Éxample"]);
+
+    // Keywords aren't strings, even though they're stored as text.
+    assert_eq!(strings(&mut store, r#"[:find [?v ...] :where [_ _ ?v] [(starts-with ?v ":ex")]]"#),
+               Vec::<String>::new());
+    assert_eq!(strings(&mut store, r#"[:find [?v ...] :where [_ _ ?v] [(starts-with ?v "http:")]]"#),
+               vec!["http://example.com/"]);
+}
+
+#[test]
+fn test_string_predicates() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :page/url   :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true}
+        {:db/ident :page/title :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :page/visits :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:page/url "https://example.com/a?b=c" :page/title "Example *" :page/visits 2}
+        {:page/url "https://example.org/" :page/title "example"}
+        {:page/url "ftp://example.com/" :page/title "FTP"}
+    ]"#).expect("transacted data");
+
+    let strings = |store: &mut Store, query: &str| -> Vec<String> {
+        let mut results: Vec<String> = store.q_once(query, None)
+                                            .into_coll_result()
+                                            .expect("results")
+                                            .into_iter()
+                                            .map(|b| b.into_string().expect("string").as_ref().clone())
+                                            .collect();
+        results.sort();
+        results
+    };
+
+    assert_eq!(strings(&mut store, r#"[:find [?u ...] :where [_ :page/url ?u] [(str-starts-with? ?u "https:")]]"#),
+               vec!["https://example.com/a?b=c", "https://example.org/"]);
+
+    // Substrings match literally and case-sensitively.
+    assert_eq!(strings(&mut store, r#"[:find [?u ...] :where [_ :page/url ?u] [(str-includes? ?u "example.com")]]"#),
+               vec!["ftp://example.com/", "https://example.com/a?b=c"]);
+    assert_eq!(strings(&mut store, r#"[:find [?u ...] :where [_ :page/url ?u] [(str-includes? ?u "a?b")]]"#),
+               vec!["https://example.com/a?b=c"]);
+    assert_eq!(strings(&mut store, r#"[:find [?t ...] :where [_ :page/title ?t] [(str-includes? ?t "*")]]"#),
+               vec!["Example *"]);
+    assert_eq!(strings(&mut store, r#"[:find [?t ...] :where [_ :page/title ?t] [(str-includes? ?t "Ex")]]"#),
+               vec!["Example *"]);
+
+    // Regular expressions match anywhere unless anchored.
+    assert_eq!(strings(&mut store, r#"[:find [?u ...] :where [_ :page/url ?u] [(regex-match ?u "\\.(org|net)/$")]]"#),
+               vec!["https://example.org/"]);
+    assert_eq!(strings(&mut store, r#"[:find [?t ...] :where [_ :page/title ?t] [(regex-match ?t "(?i)^example")]]"#),
+               vec!["Example *", "example"]);
+
+    // Only strings are matched, even without an attribute.
+    assert_eq!(strings(&mut store, r#"[:find [?v ...] :where [_ _ ?v] [(regex-match ?v "^2$")]]"#),
+               Vec::<String>::new());
+
+    // Patterns that don't compile fail when the query runs.
+    assert!(store.q_once(r#"[:find ?u :where [_ :page/url ?u] [(regex-match ?u "(")]]"#, None).is_err());
+}
+
+#[test]
+fn test_rules() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :person/name   :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :person/parent :db/valueType :db.type/ref    :db/cardinality :db.cardinality/many}
+        {:db/ident :person/friend :db/valueType :db.type/ref    :db/cardinality :db.cardinality/many}
+    ]"#).expect("transacted schema");
+
+    store.transact(r#"[
+        {:db/id "a" :person/name "Alice" :person/parent "b" :person/friend "e"}
+        {:db/id "b" :person/name "Bob"   :person/parent "c"}
+        {:db/id "c" :person/name "Carol" :person/parent "d"}
+        {:db/id "d" :person/name "Dave"}
+        {:db/id "e" :person/name "Eve"}
+    ]"#).expect("transacted data");
+
+    let names = |store: &mut Store, query: &str| -> Vec<String> {
+        let mut names: Vec<String> =
+            store.q_once(query, None)
+                 .into_coll_result()
+                 .expect("result")
+                 .into_iter()
+                 .map(|b| b.into_string().expect("string").to_string())
+                 .collect();
+        names.sort();
+        names
+    };
+
+    // A rule with several definitions behaves like `or-join`.
+    let knows = r#"[:find [?name ...]
+                    :where [?alice :person/name "Alice"]
+                           (knows ?alice ?other)
+                           [?other :person/name ?name]
+                    :rules [[(knows ?x ?y) [?x :person/parent ?y]]
+                            [(knows ?x ?y) [?x :person/friend ?y]]]]"#;
+    assert_eq!(names(&mut store, knows), vec!["Bob", "Eve"]);
+
+    // A recursive rule computes its transitive closure.
+    let ancestors = r#"[:find [?name ...]
+                        :where [?alice :person/name "Alice"]
+                               (ancestor ?alice ?a)
+                               [?a :person/name ?name]
+                        :rules [[(ancestor ?x ?y) [?x :person/parent ?y]]
+                                [(ancestor ?x ?y) [?x :person/parent ?z] (ancestor ?z ?y)]]]"#;
+    assert_eq!(names(&mut store, ancestors), vec!["Bob", "Carol", "Dave"]);
+
+    // Rules must be defined before they're used.
+    match store.q_once(r#"[:find ?x :where (unknown ?x)]"#, None) {
+        Err(MentatError::AlgebrizerError(query_algebrizer_traits::errors::AlgebrizerError::UnknownRule(name))) => {
+            assert_eq!(name, PlainSymbol::plain("unknown"));
+        },
+        x => panic!("Got unexpected results {:?}", x),
+    }
+}
+
 #[test]
 fn test_tx_ids() {
     let mut store = Store::open("").expect("opened");
@@ -1428,14 +2093,13 @@ fn test_tx_ids() {
     ]"#).expect("tx3 to apply").tx_id;
 
     fn assert_tx_id_range(store: &Store, after: Entid, before: Entid, expected: Vec<TypedValue>) {
-        // TODO: after https://github.com/mozilla/mentat/issues/641, use q_prepare with inputs bound
-        // at execution time.
-        let r = store.q_once(r#"[:find [?tx ...]
-                                 :in ?after ?before
-                                 :where
-                                 [(tx-ids $ ?after ?before) [?tx ...]]
-                                ]"#,
-                             QueryInputs::with_value_sequence(vec![
+        let mut prepared = store.q_prepare(r#"[:find [?tx ...]
+                                               :in ?after ?before
+                                               :where
+                                               [(tx-ids $ ?after ?before) [?tx ...]]
+                                              ]"#, None)
+                                .expect("prepared");
+        let r = prepared.run(QueryInputs::with_value_sequence(vec![
                                  (Variable::from_valid_name("?after"),  TypedValue::Ref(after)),
                                  (Variable::from_valid_name("?before"), TypedValue::Ref(before)),
                              ]))
@@ -1454,6 +2118,49 @@ fn test_tx_ids() {
     assert_tx_id_range(&store, tx1, tx3, vec![TypedValue::Ref(tx1), TypedValue::Ref(tx2)]);
     assert_tx_id_range(&store, tx2, tx3, vec![TypedValue::Ref(tx2)]);
     assert_tx_id_range(&store, tx2, tx3 + 1, vec![TypedValue::Ref(tx2), TypedValue::Ref(tx3)]);
+
+    // Without an upper bound, the range runs to the latest transaction.
+    let r = store.q_once(&format!("[:find [?tx ...] :where [(tx-ids $ {}) [?tx ...]]]", tx2), None)
+                 .into_coll_result()
+                 .expect("results");
+    assert_eq!(r, vec![TypedValue::Ref(tx2).into(), TypedValue::Ref(tx3).into()]);
+}
+
+#[test]
+fn test_tx_ids_by_instant() {
+    let mut store = Store::open("").expect("opened");
+
+    store.transact(r#"[
+        [:db/add "a" :db/ident :foo/term]
+        [:db/add "a" :db/valueType :db.type/string]
+        [:db/add "a" :db/cardinality :db.cardinality/many]
+    ]"#).unwrap();
+
+    // These instants are later than those of the transactions that bootstrapped the store.
+    let tx1 = store.transact(r#"[
+        [:db/add (transaction-tx) :db/txInstant #inst "2100-01-01T00:00:00.000Z"]
+        [:db/add "v" :foo/term "1"]
+    ]"#).expect("tx1 to apply").tx_id;
+
+    let tx2 = store.transact(r#"[
+        [:db/add (transaction-tx) :db/txInstant #inst "2100-02-01T00:00:00.000Z"]
+        [:db/add "v" :foo/term "2"]
+    ]"#).expect("tx2 to apply").tx_id;
+
+    // Bounds that are instants are compared with each transaction's `:db/txInstant`: the lower
+    // bound is inclusive and the upper exclusive.
+    let r = store.q_once(r#"[:find [?tx ...]
+                             :where [(tx-ids $ #inst "2100-01-01T00:00:00.000Z" #inst "2100-02-01T00:00:00.000Z") [?tx ...]]]"#, None)
+                 .into_coll_result()
+                 .expect("results");
+    assert_eq!(r, vec![TypedValue::Ref(tx1).into()]);
+
+    // Instants and transactions can be mixed.
+    let r = store.q_once(&format!(r#"[:find [?tx ...]
+                                      :where [(tx-ids $ #inst "2100-01-15T00:00:00.000Z" {}) [?tx ...]]]"#, tx2 + 1), None)
+                 .into_coll_result()
+                 .expect("results");
+    assert_eq!(r, vec![TypedValue::Ref(tx2).into()]);
 }
 
 fn run_tx_data_test(mut store: Store) {
@@ -1519,10 +2226,763 @@ fn test_tx_data() {
     run_tx_data_test(Store::open("").expect("opened"));
 }
 
-#[cfg(feature = "sqlite")]
+#[cfg(feature = "sqlcipher")]
 #[test]
 fn test_encrypted() {
     // We expect this to blow up completely if something is wrong with the encryption,
     // so the specific test we use doesn't matter that much.
-    run_tx_data_test(Store::open_with_key("", "secret").expect("opened"));
+    run_tx_data_test(Store::open_encrypted("", "secret").expect("opened"));
+}
+
+#[cfg(feature = "sqlcipher")]
+#[test]
+fn test_encryption_key_rotation() {
+    // SQLite removes the store's -wal and -shm files when the last connection to it closes.
+    let file = tempfile::NamedTempFile::new().expect("temporary file");
+    let path = file.path().to_str().expect("path");
+
+    let mut store = Store::open_encrypted(path, "old").expect("created");
+    store.transact(r#"[{:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).expect("transacted schema");
+    store.transact(r#"[{:foo/name "Alice"}]"#).expect("transacted");
+    store.change_encryption_key("new").expect("rekeyed");
+    drop(store);
+
+    match Store::open(path) {
+        Err(MentatError::EncryptionKeyRequired(p)) => assert_eq!(p, path),
+        _ => panic!("expected an error"),
+    }
+    match Store::open_encrypted(path, "old") {
+        Err(MentatError::IncorrectEncryptionKey(p)) => assert_eq!(p, path),
+        _ => panic!("expected an error"),
+    }
+
+    let mut store = Store::open_encrypted(path, "new").expect("opened");
+    let name = store.q_once("[:find ?name . :where [_ :foo/name ?name]]", None)
+                    .into_scalar_result()
+                    .expect("queried");
+    assert_eq!(name, Some(TypedValue::typed_string("Alice").into()));
+}
+
+#[test]
+fn test_column_metadata() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/nick :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[{:foo/name "Alice" :foo/nick "Al" :foo/age 30}]"#).expect("transacted");
+
+    let column = |name: &str, value_types: ValueTypeSet, attribute: Option<Keyword>| ColumnMetadata {
+        name: name.to_string(),
+        value_types: value_types,
+        attribute: attribute,
+    };
+
+    let output = store.q_once(r#"[:find ?name (max ?age) (count ?e) ?label ?a
+                                  :with ?e
+                                  :where
+                                  [?e :foo/name ?name]
+                                  [?e :foo/age ?age]
+                                  (or [?e :foo/name ?label]
+                                      [?e :foo/nick ?label])
+                                  [?e ?a _]]"#, None)
+                      .expect("queried");
+    assert_eq!(*output.columns, vec![
+        column("?name", ValueTypeSet::of_one(ValueType::String), Some(kw!(:foo/name))),
+        column("(max ?age)", ValueTypeSet::of_one(ValueType::Long), Some(kw!(:foo/age))),
+        column("(count ?e)", ValueTypeSet::of_one(ValueType::Long), None),
+        // Bound to two different attributes.
+        column("?label", ValueTypeSet::of_one(ValueType::String), None),
+        column("?a", ValueTypeSet::of_one(ValueType::Ref), None),
+    ]);
+    assert_eq!(output.len(), 6);
+
+    // Queries that are known to be empty, or are answered without SQL, are described too.
+    let output = store.q_once(r#"[:find ?name :where [?e :foo/name ?name] [?e :foo/age "old"]]"#, None)
+                      .expect("queried");
+    assert!(output.is_empty());
+    assert_eq!(*output.columns, vec![column("?name", ValueTypeSet::of_one(ValueType::String), Some(kw!(:foo/name)))]);
+
+    let output = store.q_once(r#"[:find ?x . :where [(ground 5) ?x]]"#, None)
+                      .expect("queried");
+    assert_eq!(output.clone().into_scalar().expect("scalar"), Some(Binding::Scalar(TypedValue::Long(5))));
+    assert_eq!(*output.columns, vec![column("?x", ValueTypeSet::of_one(ValueType::Long), None)]);
+}
+
+/// `:in` variables without values become parameters that are bound each time the query runs.
+#[test]
+fn test_prepared_query_parameters() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    let report = store.transact(r#"[
+        {:db/id "a" :foo/name "Alice" :foo/age 35}
+        {:db/id "b" :foo/name "Bob"   :foo/age 25}
+        {:db/id "c" :foo/name "Carol" :foo/age 45}
+    ]"#).expect("transacted");
+    let alice = report.tempids.get("a").cloned().expect("alice");
+    let bob = report.tempids.get("b").cloned().expect("bob");
+
+    let name = |s: &str| QueryInputs::with_value_sequence(vec![(var!(?name), TypedValue::typed_string(s))]);
+
+    // A parameter in a pattern.
+    {
+        let mut prepared = store.q_prepare("[:find ?e . :in ?name :where [?e :foo/name ?name]]", None)
+                                .expect("prepared");
+        assert_eq!(prepared.run(name("Alice")).into_scalar_result().expect("ran"), Some(Binding::Scalar(TypedValue::Ref(alice))));
+        assert_eq!(prepared.run(name("Bob")).into_scalar_result().expect("ran"), Some(Binding::Scalar(TypedValue::Ref(bob))));
+        assert_eq!(prepared.run(name("Dave")).into_scalar_result().expect("ran"), None);
+
+        match prepared.run(None).expect_err("expected an error") {
+            MentatError::UnboundVariables(vars) => assert_eq!(vars, vec!["?name".to_string()].into_iter().collect()),
+            e => panic!("expected UnboundVariables, got {:?}", e),
+        }
+        let wrong = QueryInputs::with_value_sequence(vec![(var!(?name), TypedValue::Long(1))]);
+        match prepared.run(wrong).expect_err("expected an error") {
+            MentatError::ParameterTypeMismatch(var, types, ValueType::Long) => {
+                assert_eq!(var, "?name");
+                assert_eq!(types, ValueTypeSet::of_one(ValueType::String));
+            },
+            e => panic!("expected ParameterTypeMismatch, got {:?}", e),
+        }
+    }
+
+    // A parameter only in a predicate, and one for the limit.
+    {
+        let mut prepared = store.q_prepare(r#"[:find [?name ...]
+                                               :in ?min ?limit
+                                               :where [?e :foo/age ?age]
+                                                      [(>= ?age ?min)]
+                                                      [?e :foo/name ?name]
+                                               :order ?name
+                                               :limit ?limit]"#, None)
+                                .expect("prepared");
+        let inputs = |min: i64, limit: i64| QueryInputs::with_value_sequence(vec![
+            (var!(?min), TypedValue::Long(min)),
+            (var!(?limit), TypedValue::Long(limit)),
+        ]);
+        assert_eq!(prepared.run(inputs(30, 10)).into_coll_result().expect("ran"),
+                   vec![TypedValue::typed_string("Alice").into(), TypedValue::typed_string("Carol").into()]);
+        assert_eq!(prepared.run(inputs(20, 2)).into_coll_result().expect("ran"),
+                   vec![TypedValue::typed_string("Alice").into(), TypedValue::typed_string("Bob").into()]);
+    }
+
+    // We can't bind a value without knowing its type, unless we're told it.
+    let query = "[:find ?e . :in ?v :where [?e ?a ?v] [?e :foo/age _]]";
+    match store.q_prepare(query, None) {
+        Err(MentatError::UnboundVariables(vars)) => assert_eq!(vars, vec!["?v".to_string()].into_iter().collect()),
+        Err(e) => panic!("expected UnboundVariables, got {:?}", e),
+        Ok(_) => panic!("expected UnboundVariables"),
+    }
+    let mut prepared = store.q_prepare(query, QueryInputs::with_type_sequence(vec![(var!(?v), ValueType::String)]))
+                            .expect("prepared");
+    let v = QueryInputs::with_value_sequence(vec![(var!(?v), TypedValue::typed_string("Bob"))]);
+    assert_eq!(prepared.run(v).into_scalar_result().expect("ran"), Some(Binding::Scalar(TypedValue::Ref(bob))));
+}
+
+/// Collection, relation and tuple bindings in `:in`.
+#[test]
+fn test_in_binding_forms() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:foo/name "Alice" :foo/age 35}
+        {:foo/name "Bob"   :foo/age 25}
+        {:foo/name "Carol" :foo/age 45}
+    ]"#).expect("transacted");
+
+    let names = |names: Vec<&str>| names.into_iter().map(|s| TypedValue::typed_string(s)).collect::<Vec<_>>();
+
+    // A collection.
+    let inputs = QueryInputs::default().with_collection(var!(?name), names(vec!["Alice", "Carol", "Dave"]));
+    let results = store.q_once("[:find [?age ...] :in [?name ...] :where [?e :foo/name ?name] [?e :foo/age ?age] :order ?age]", inputs)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results, vec![TypedValue::Long(35).into(), TypedValue::Long(45).into()]);
+
+    // A relation, alongside a scalar.
+    let inputs = QueryInputs::with_value_sequence(vec![(var!(?min), TypedValue::Long(30))])
+        .with_relation(vec![var!(?name), var!(?nickname)],
+                       vec![names(vec!["Alice", "Al"]), names(vec!["Bob", "Bobby"]), names(vec!["Carol", "Caz"])]);
+    let results = store.q_once(r#"[:find [?nickname ...]
+                                   :in ?min [[?name ?nickname]]
+                                   :where [?e :foo/name ?name] [?e :foo/age ?age] [(>= ?age ?min)]
+                                   :order ?nickname]"#, inputs)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results, vec![TypedValue::typed_string("Al").into(), TypedValue::typed_string("Caz").into()]);
+
+    // A tuple binds each of its variables to a single value.
+    let inputs = QueryInputs::with_value_sequence(vec![(var!(?name), TypedValue::typed_string("Bob")),
+                                                      (var!(?age), TypedValue::Long(25))]);
+    let results = store.q_once("[:find ?e . :in [?name ?age] :where [?e :foo/name ?name] [?e :foo/age ?age]]", inputs)
+                       .into_scalar_result()
+                       .expect("results");
+    assert!(results.is_some());
+
+    // Values for a collection must be supplied.
+    match store.q_once("[:find ?e :in [?name ...] :where [?e :foo/name ?name]]", None).expect_err("expected an error") {
+        MentatError::AlgebrizerError(query_algebrizer_traits::errors::AlgebrizerError::UnboundInputBinding(name)) => assert_eq!(name, "?name"),
+        e => panic!("expected UnboundInputBinding, got {:?}", e),
+    }
+}
+
+#[test]
+fn test_repeated_or_join_is_shared() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/nick :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:foo/name "Alice" :foo/age 35}
+        {:foo/nick "Bobby" :foo/age 25}
+        {:foo/age 45}
+    ]"#).expect("transacted");
+
+    let query = r#"[:find ?a ?b
+                    :where
+                    (or-join [?a] (and [?a :foo/name ?x] [(ground 1) ?k]) (and [?a :foo/nick ?x] [(ground 2) ?k]))
+                    (or-join [?b] (and [?b :foo/name ?x] [(ground 1) ?k]) (and [?b :foo/nick ?x] [(ground 2) ?k]))]"#;
+    match store.q_explain(query, None).expect("explained") {
+        mentat::QueryExplanation::ExecutionPlan { query, .. } => {
+            assert!(query.sql.starts_with("WITH `shared00` AS ("), "{}", query.sql);
+        },
+        _ => panic!("expected an execution plan"),
+    }
+    let results = store.q_once(query, None).into_rel_result().expect("results");
+    assert_eq!(results.row_count(), 4);
+
+    // The shared definition also works beneath an aggregate.
+    let results = store.q_once(r#"[:find (count ?a) .
+                                   :where
+                                   (or-join [?a] (and [?a :foo/name ?x] [(ground 1) ?k]) (and [?a :foo/nick ?x] [(ground 2) ?k]))
+                                   (or-join [?b] (and [?b :foo/name ?x] [(ground 1) ?k]) (and [?b :foo/nick ?x] [(ground 2) ?k]))
+                                   [?a :foo/age ?age]
+                                   [?b :foo/age ?age]]"#, None)
+                       .into_scalar_result()
+                       .expect("results");
+    assert_eq!(results, Some(TypedValue::Long(2).into()));
+}
+
+#[test]
+fn test_query_plan() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:foo/name "Alice" :foo/age 35}
+        {:foo/name "Bob"   :foo/age 25}
+    ]"#).expect("transacted");
+    store.sqlite_mut().execute_batch("ANALYZE").expect("analyzed");
+
+    let plan = store.q_plan("[:find ?name :where [?e :foo/age ?age] [(> ?age 30)] [?e :foo/name ?name]]", None)
+                    .expect("planned");
+    let nodes = match plan {
+        mentat::QueryPlan::Execution { nodes, .. } => nodes,
+        _ => panic!("expected an execution plan"),
+    };
+
+    // The two patterns are joined, each looking up datoms by attribute, and the second by entity
+    // too.  SQLite's statistics give an estimate for each.
+    let join = &nodes[0];
+    assert_eq!(join.operation, mentat::PlanOperation::Join);
+    assert_eq!(join.children.len(), 2);
+    match &join.children[1].operation {
+        &mentat::PlanOperation::Search { ref table, ref alias, ref constraints, .. } => {
+            assert_eq!(table, "datoms");
+            assert_eq!(alias, &Some("datoms01".to_string()));
+            assert!(constraints.contains(&"e=?".to_string()), "{:?}", constraints);
+        },
+        other => panic!("expected a search, got {:?}", other),
+    }
+    assert!(join.children.iter().all(|child| child.estimated_rows.is_some()));
+    assert!(join.estimated_rows.is_some());
+
+    // A union is a subquery with a compound query inside it, one child for each arm.
+    let plan = store.q_plan(r#"[:find ?e :where (or-join [?e] (and [?e :foo/age ?age] [(> ?age 30)]) [?e :foo/name "Bob"])]"#, None)
+                    .expect("planned");
+    let nodes = match plan {
+        mentat::QueryPlan::Execution { nodes, .. } => nodes,
+        _ => panic!("expected an execution plan"),
+    };
+    assert_eq!(nodes[0].operation, mentat::PlanOperation::Subquery);
+    let compound = &nodes[0].children[0];
+    assert_eq!(compound.operation, mentat::PlanOperation::Compound);
+    assert_eq!(compound.children.len(), 2);
+    assert!(nodes.iter().any(|node| node.operation == mentat::PlanOperation::ScanSubquery { alias: Some("c00".to_string()) }));
+
+    // Queries that don't need SQL don't have a plan.
+    match store.q_plan("[:find ?e :where [?e :foo/age \"old\"]]", None).expect("planned") {
+        mentat::QueryPlan::KnownEmpty(_) => {},
+        _ => panic!("expected a known-empty plan"),
+    }
+}
+
+#[test]
+fn test_join_order_uses_statistics() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:foo/name "Alice" :foo/age 35}
+        {:foo/name "Bob"}
+        {:foo/name "Carol"}
+    ]"#).expect("transacted");
+
+    let age = store.conn().current_schema().get_entid(&kw!(:foo/age)).expect("ident").0;
+    let query = r#"[:find ?name . :where [?e :foo/name ?name] [?e :foo/age 35]]"#;
+    let first_alias_is_age = |store: &Store| {
+        match store.q_explain(query, None).expect("explained") {
+            mentat::QueryExplanation::ExecutionPlan { query, .. } => query.sql.contains(&format!("`datoms00`.a = {}", age)),
+            _ => panic!("expected an execution plan"),
+        }
+    };
+
+    // The store was empty when it was opened, so nothing is known about these attributes yet.
+    assert!(!first_alias_is_age(&store));
+
+    // There are fewer ages than names, so the age pattern is joined first.
+    store.update_statistics().expect("updated statistics");
+    assert!(first_alias_is_age(&store));
+    assert_eq!(store.q_once(query, None).into_scalar_result().expect("results"),
+               Some(TypedValue::typed_string("Alice").into()));
+}
+
+#[test]
+fn test_attribute_indexes() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:foo/name "Alice" :foo/age 35}
+        {:foo/name "Bob" :foo/age 40}
+    ]"#).expect("transacted");
+
+    let query = r#"[:find ?name . :where [?e :foo/name ?name] [?e :foo/age 35]]"#;
+    let indexes_used = |store: &Store| -> usize {
+        match store.q_explain(query, None).expect("explained") {
+            mentat::QueryExplanation::ExecutionPlan { query, .. } => query.sql.matches("INDEXED BY").count(),
+            _ => panic!("expected an execution plan"),
+        }
+    };
+    assert_eq!(indexes_used(&store), 0);
+
+    store.ensure_attribute_index(&kw!(:foo/age), IndexDirection::Reverse).expect("created index");
+    store.ensure_attribute_index(&kw!(:foo/age), IndexDirection::Reverse).expect("index already exists");
+    assert_eq!(store.attribute_indexes(), vec![(kw!(:foo/age), IndexDirection::Reverse)]);
+    assert_eq!(indexes_used(&store), 1);
+    assert_eq!(store.q_once(query, None).into_scalar_result().expect("results"),
+               Some(TypedValue::typed_string("Alice").into()));
+
+    // Datoms transacted after the index was created are found through it, too.
+    store.transact(r#"[{:foo/name "Carol" :foo/age 35}]"#).expect("transacted");
+    assert_eq!(store.q_once(r#"[:find [?name ...] :where [?e :foo/name ?name] [?e :foo/age 35] :order ?name]"#, None)
+                    .into_coll_result()
+                    .expect("results"),
+               vec![TypedValue::typed_string("Alice").into(), TypedValue::typed_string("Carol").into()]);
+
+    store.drop_attribute_index(&kw!(:foo/age), IndexDirection::Reverse).expect("dropped index");
+    assert!(store.attribute_indexes().is_empty());
+    assert_eq!(indexes_used(&store), 0);
+
+    match store.ensure_attribute_index(&kw!(:foo/height), IndexDirection::Forward) {
+        Err(MentatError::UnknownAttribute(_)) => {},
+        x => panic!("expected an unknown attribute error, got {:?}", x),
+    }
+}
+
+#[test]
+fn test_numeric_conversions() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name   :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/score  :db/valueType :db.type/double :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/count  :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/when   :db/valueType :db.type/instant :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:foo/name "a" :foo/score 2.75 :foo/count 3 :foo/when #inst "1970-01-01T00:00:01.000Z"}
+        {:foo/name "b" :foo/score -1.5 :foo/count 7}
+    ]"#).expect("transacted");
+
+    // Doubles truncate towards zero.
+    let results = store.q_once(r#"[:find [?whole ...]
+                                   :where [_ :foo/score ?score] [(long ?score) ?whole]
+                                   :order ?whole]"#, None)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results, vec![TypedValue::Long(-1).into(), TypedValue::Long(2).into()]);
+
+    // Converted values can be compared and projected.
+    let results = store.q_once(r#"[:find ?name ?ratio
+                                   :where [?e :foo/name ?name] [?e :foo/count ?count]
+                                          [(double ?count) ?ratio] [(> ?ratio 5.0)]]"#, None)
+                       .into_rel_result()
+                       .expect("results");
+    assert_eq!(results.row_count(), 1);
+    assert_eq!(results.row(0).expect("row").to_vec(),
+               vec![TypedValue::typed_string("b").into(), TypedValue::Double(7.0.into()).into()]);
+
+    // Instants are microseconds since the epoch.
+    let results = store.q_once(r#"[:find ?micros . :where [_ :foo/when ?when] [(long ?when) ?micros]]"#, None)
+                       .into_scalar_result()
+                       .expect("results");
+    assert_eq!(results, Some(TypedValue::Long(1_000_000).into()));
+
+    // Bound inputs are converted before the query runs.
+    let inputs = QueryInputs::with_value_sequence(vec![(var!(?limit), TypedValue::Double(2.5.into()))]);
+    let results = store.q_once(r#"[:find [?name ...]
+                                   :in ?limit
+                                   :where [(long ?limit) ?n] [?e :foo/count ?count] [(> ?count ?n)] [?e :foo/name ?name]
+                                   :order ?name]"#, inputs)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results, vec![TypedValue::typed_string("a").into(), TypedValue::typed_string("b").into()]);
+}
+
+#[test]
+fn test_optional_attributes() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name   :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/nick   :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age    :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:foo/name "alice" :foo/nick "al" :foo/age 30}
+        {:foo/name "bob"}
+    ]"#).expect("transacted");
+
+    // `get-else` falls back to the default.
+    let results = store.q_once(r#"[:find ?name ?age
+                                   :where [?e :foo/name ?name] [(get-else $ ?e :foo/age -1) ?age]
+                                   :order ?name]"#, None)
+                       .into_rel_result()
+                       .expect("results");
+    assert_eq!(results.row_count(), 2);
+    assert_eq!(results.row(0).expect("row").to_vec(),
+               vec![TypedValue::typed_string("alice").into(), TypedValue::Long(30).into()]);
+    assert_eq!(results.row(1).expect("row").to_vec(),
+               vec![TypedValue::typed_string("bob").into(), TypedValue::Long(-1).into()]);
+
+    // The default can have a different type.
+    let results = store.q_once(r#"[:find ?age .
+                                   :where [?e :foo/name "bob"] [(get-else $ ?e :foo/age "unknown") ?age]]"#, None)
+                       .into_scalar_result()
+                       .expect("results");
+    assert_eq!(results, Some(TypedValue::typed_string("unknown").into()));
+
+    // `get-some` takes the first attribute the entity has.
+    let results = store.q_once(r#"[:find ?name ?a ?v
+                                   :where [?e :foo/name ?name] [(get-some $ ?e :foo/nick :foo/name) [?a ?v]]
+                                   :order ?name]"#, None)
+                       .into_rel_result()
+                       .expect("results");
+    let nick = store.conn().current_schema().get_entid(&kw!(:foo/nick)).expect("entid");
+    let name = store.conn().current_schema().get_entid(&kw!(:foo/name)).expect("entid");
+    assert_eq!(results.row_count(), 2);
+    assert_eq!(results.row(0).expect("row").to_vec(),
+               vec![TypedValue::typed_string("alice").into(), TypedValue::Ref(nick.0).into(), TypedValue::typed_string("al").into()]);
+    assert_eq!(results.row(1).expect("row").to_vec(),
+               vec![TypedValue::typed_string("bob").into(), TypedValue::Ref(name.0).into(), TypedValue::typed_string("bob").into()]);
+
+    // Entities with none of the attributes don't match.
+    let results = store.q_once(r#"[:find [?name ...]
+                                   :where [?e :foo/name ?name] [(get-some $ ?e :foo/nick :foo/age) [_ ?v]]]"#, None)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results, vec![TypedValue::typed_string("alice").into()]);
+
+    // `missing?` matches entities without the attribute.
+    let results = store.q_once(r#"[:find [?name ...]
+                                   :where [?e :foo/name ?name] [(missing? $ ?e :foo/nick)]]"#, None)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results, vec![TypedValue::typed_string("bob").into()]);
+}
+
+#[test]
+fn test_optional_clauses() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name   :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/nick   :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/friend :db/valueType :db.type/ref    :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:db/id "a" :foo/name "alice" :foo/nick "al" :foo/friend "b"}
+        {:db/id "b" :foo/name "bob"}
+    ]"#).expect("transacted");
+
+    // Rows without a match are kept, with the variable absent.
+    let results = store.q_once(r#"[:find ?name ?nick
+                                   :where [?e :foo/name ?name] (optional [?e :foo/nick ?nick])
+                                   :order ?name]"#, None)
+                       .into_rel_result()
+                       .expect("results");
+    assert_eq!(results.row_count(), 2);
+    assert_eq!(results.row(0).expect("row").to_vec(),
+               vec![TypedValue::typed_string("alice").into(), TypedValue::typed_string("al").into()]);
+    assert_eq!(results.row(1).expect("row").to_vec(),
+               vec![TypedValue::typed_string("bob").into(), Binding::Null]);
+
+    // Every clause must match for any of the variables to be bound.
+    let results = store.q_once(r#"[:find ?name ?friend
+                                   :where [?e :foo/name ?name]
+                                          (optional [?e :foo/friend ?f] [?f :foo/name ?friend])
+                                   :order ?name]"#, None)
+                       .into_rel_result()
+                       .expect("results");
+    assert_eq!(results.row(0).expect("row").to_vec(),
+               vec![TypedValue::typed_string("alice").into(), TypedValue::typed_string("bob").into()]);
+    assert_eq!(results.row(1).expect("row").to_vec(),
+               vec![TypedValue::typed_string("bob").into(), Binding::Null]);
+
+    // Clauses that can never match leave every row's variable absent.
+    let results = store.q_once(r#"[:find [?age ...]
+                                   :where [?e :foo/name _] (optional [?e :foo/age ?age])]"#, None)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results, vec![Binding::Null]);
+}
+
+#[test]
+fn test_optional_absent_values() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name   :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/friend :db/valueType :db.type/ref    :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:db/id "a" :foo/name "alice" :foo/friend "b"}
+        {:db/id "b" :foo/name "bob"}
+    ]"#).expect("transacted");
+
+    let expected: RelResult<Binding> = vec![
+        vec![Some(TypedValue::typed_string("alice")), Some(TypedValue::typed_string("bob"))],
+        vec![Some(TypedValue::typed_string("bob")), None],
+    ].into();
+    let results = store.q_once(r#"[:find ?name ?friend
+                                   :where [?e :foo/name ?name]
+                                          (optional [?e :foo/friend ?f] [?f :foo/name ?friend])
+                                   :order ?name]"#, None)
+                       .expect("results");
+    assert_eq!(results.results.clone().into_rel().expect("rel"), expected);
+
+    // Absent values are rendered as `nil`.
+    assert_eq!(results.results.into_edn().to_pretty(120).expect("pretty"), r#"[["alice" "bob"] ["bob" nil]]"#);
+
+    // There's nothing to pull for an absent entity.
+    let results = store.q_once(r#"[:find ?name (pull ?f [:foo/name])
+                                   :where [?e :foo/name ?name] (optional [?e :foo/friend ?f])
+                                   :order ?name]"#, None)
+                       .into_rel_result()
+                       .expect("results");
+    let bob: StructuredMap = vec![(Keyword::namespaced("foo", "name"), TypedValue::typed_string("bob"))].into();
+    assert_eq!(results.row(0).expect("row").to_vec(),
+               vec![TypedValue::typed_string("alice").into(), bob.into()]);
+    assert_eq!(results.row(1).expect("row").to_vec(),
+               vec![TypedValue::typed_string("bob").into(), Binding::Null]);
+}
+
+#[test]
+fn test_history() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    let report = store.transact(r#"[{:db/id "a" :foo/name "alice"}]"#).expect("transacted");
+    let alice = report.tempids.get("a").cloned().expect("alice");
+    let first = report.tx_id;
+    let second = store.transact(&format!("[[:db/add {} :foo/name \"alicia\"]]", alice)).expect("renamed").tx_id;
+    let third = store.transact(&format!("[[:db/retract {} :foo/name \"alicia\"]]", alice)).expect("retracted").tx_id;
+
+    // The current datoms know nothing about Alice.
+    let results = store.q_once(r#"[:find ?name :where [_ :foo/name ?name]]"#, None)
+                       .into_rel_result()
+                       .expect("results");
+    assert_eq!(results.row_count(), 0);
+
+    // The log knows everything.
+    let results = store.q_once(r#"[:find ?name ?tx ?added
+                                   :in $ $history
+                                   :where [$history _ :foo/name ?name ?tx ?added]
+                                   :order ?tx (desc ?added)]"#, None)
+                       .into_rel_result()
+                       .expect("results");
+    let row = |name: &str, tx: Entid, added: bool| -> Vec<Binding> {
+        vec![TypedValue::typed_string(name).into(), TypedValue::Ref(tx).into(), TypedValue::Boolean(added).into()]
+    };
+    assert_eq!(results.into_iter().collect::<Vec<_>>(),
+               vec![row("alice", first, true),
+                    row("alicia", second, true),
+                    row("alice", second, false),
+                    row("alicia", third, false)]);
+
+    // Retractions only, joined against the current datoms.
+    let results = store.q_once(r#"[:find [?name ...]
+                                   :where [$history ?e :foo/name ?name _ false] [?e :foo/name _]]"#, None)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results, vec![]);
+    let results = store.q_once(r#"[:find [?name ...]
+                                   :where [$history _ :foo/name ?name _ false]
+                                   :order ?name]"#, None)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results, vec![TypedValue::typed_string("alice").into(), TypedValue::typed_string("alicia").into()]);
+}
+
+#[test]
+fn test_as_of() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/bio :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true}
+    ]"#).expect("transacted schema");
+    let report = store.transact(r#"[{:db/id "a" :foo/name "alice" :foo/bio "likes cats"}]"#).expect("transacted");
+    let alice = report.tempids.get("a").cloned().expect("alice");
+    let first = report.tx_id;
+    let first_instant = report.tx_instant;
+    store.transact(&format!("[[:db/add {} :foo/name \"alicia\"] [:db/add {} :foo/bio \"likes dogs\"]]", alice, alice)).expect("renamed");
+    store.transact(r#"[{:foo/name "bob"}]"#).expect("transacted");
+
+    let names = "[:find [?name ...] :where [_ :foo/name ?name] :order ?name]";
+    let then = store.as_of(first);
+    assert_eq!(then.q_once(names, None).into_coll_result().expect("results"),
+               vec![TypedValue::typed_string("alice").into()]);
+    assert_eq!(then.lookup_value_for_attribute(alice, &kw!(:foo/name)).expect("looked up"),
+               Some(TypedValue::typed_string("alice")));
+    assert_eq!(then.q_once(r#"[:find ?e . :where [(fulltext $ :foo/bio "cats") [[?e]]]]"#, None)
+                   .into_scalar_result()
+                   .expect("results"),
+               Some(TypedValue::Ref(alice).into()));
+    assert_eq!(then.q_prepare(names, None).expect("prepared").run(None).into_coll_result().expect("ran").len(), 1);
+
+    // The log ends with the basis, too.
+    assert_eq!(then.q_once("[:find (count ?tx) . :where [$history _ :foo/name _ ?tx]]", None)
+                   .into_scalar_result()
+                   .expect("results"),
+               Some(TypedValue::Long(1).into()));
+
+    // The present is unaffected.
+    assert_eq!(store.q_once(names, None).into_coll_result().expect("results"),
+               vec![TypedValue::typed_string("alicia").into(), TypedValue::typed_string("bob").into()]);
+
+    // Later transactions might share the first's instant.
+    match store.as_of_instant(&first_instant).expect("as of").basis() {
+        DatomsBasis::AsOf(tx) => assert!(tx >= first),
+        basis => panic!("unexpected basis {:?}", basis),
+    }
+}
+
+#[test]
+fn test_since() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    let report = store.transact(r#"[{:db/id "a" :foo/name "alice" :foo/age 30} {:db/id "b" :foo/name "bob"}]"#).expect("transacted");
+    let alice = report.tempids.get("a").cloned().expect("alice");
+    let bob = report.tempids.get("b").cloned().expect("bob");
+    let watermark = report.tx_id;
+    store.transact(&format!("[[:db/add {} :foo/age 31] [:db/add {} :foo/age 40]]", alice, bob)).expect("aged");
+    store.transact(&format!("[[:db/retract {} :foo/age 40]]", bob)).expect("retracted");
+    store.transact(r#"[{:foo/name "carol"}]"#).expect("transacted");
+
+    let since = store.since(watermark);
+    let results = since.q_once("[:find ?e ?age :where [?e :foo/age ?age] [(> ?age 30)]]", None)
+                       .into_rel_result()
+                       .expect("results");
+    assert_eq!(results.into_iter().collect::<Vec<_>>(),
+               vec![vec![TypedValue::Ref(alice).into(), TypedValue::Long(31).into()]]);
+
+    let results = since.q_once("[:find [?name ...] :where [_ :foo/name ?name]]", None)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results, vec![TypedValue::typed_string("carol").into()]);
+
+    // Bob's age was asserted and retracted since the watermark.
+    let results = since.q_once("[:find [?added ...] :where [$history ?e :foo/age 40 _ ?added]]", None)
+                       .into_coll_result()
+                       .expect("results");
+    assert_eq!(results.len(), 2);
+    assert_eq!(since.lookup_value_for_attribute(bob, &kw!(:foo/age)).expect("looked up"), None);
+}
+
+#[test]
+fn test_prepared_query_cache() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    let report = store.transact(r#"[{:db/id "a" :foo/name "Alice"} {:foo/name "Bob"}]"#).expect("transacted");
+    let alice = report.tempids.get("a").cloned().expect("alice");
+
+    // Preparing again reuses the translation, but still sees new data.
+    let query = r#"[:find ?n . :where [?e :foo/name "Alice"] [?e :foo/age ?n]]"#;
+    let by_name = "[:find [?name ...] :where [_ :foo/name ?name]]";
+    assert_eq!(store.q_prepare(by_name, None).expect("prepared").run(None).into_coll_result().expect("ran").len(), 2);
+    store.transact(r#"[{:foo/name "Carol"}]"#).expect("transacted");
+    assert_eq!(store.q_prepare(by_name, None).expect("prepared").run(None).into_coll_result().expect("ran").len(), 3);
+
+    // `:foo/age` doesn't exist yet, so this query can't match anything.
+    match store.q_prepare(query, None).expect("prepared") {
+        PreparedQuery::Empty { .. } => {},
+        _ => panic!("expected an empty query"),
+    }
+
+    // Once it does, the old translation mustn't be used.
+    store.transact(r#"[
+        {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(&format!("[[:db/add {} :foo/age 35]]", alice)).expect("transacted");
+    let mut prepared = store.q_prepare(query, None).expect("prepared");
+    assert_eq!(prepared.run(None).into_scalar_result().expect("ran"), Some(TypedValue::Long(35).into()));
+}
+
+#[test]
+fn test_existential_patterns() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name  :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/knows :db/valueType :db.type/ref    :db/cardinality :db.cardinality/many}
+        {:db/ident :foo/age   :db/valueType :db.type/long   :db/cardinality :db.cardinality/one}
+    ]"#).expect("transacted schema");
+    store.transact(r#"[
+        {:db/id "a" :foo/name "Alice" :foo/knows ["b" "c" "d"]}
+        {:db/id "b" :foo/name "Bob" :foo/age 30 :foo/knows "c"}
+        {:db/id "c" :foo/age 30}
+        {:db/id "d" :foo/age 40}
+    ]"#).expect("transacted");
+
+    let query = r#"[:find [?name ...]
+                    :where [?x :foo/name ?name]
+                           [?x :foo/knows ?y]
+                           [?y :foo/age 30]
+                    :order ?name]"#;
+    match store.q_explain(query, None).expect("explained") {
+        mentat::QueryExplanation::ExecutionPlan { query, .. } => {
+            assert!(query.sql.contains("EXISTS"), "{}", query.sql);
+        },
+        _ => panic!("expected an execution plan"),
+    }
+    let names = store.q_once(query, None).into_coll_result().expect("results");
+    assert_eq!(names, vec![TypedValue::typed_string("Alice").into(),
+                           TypedValue::typed_string("Bob").into()]);
 }
@@ -41,9 +41,21 @@ mod tolstoy_tests {
 
     use mentat::new_connection;
 
+    use mentat::{
+        HasSchema,
+        Keyword,
+    };
+
     use mentat_db::TX0;
 
     use mentat_tolstoy::{
+        AttributeMerge,
+        CancellationToken,
+        OursWin,
+        SyncFilter,
+        SyncOptions,
+        SyncPhase,
+        SyncProgress,
         Tx,
         TxPart,
         GlobalTransactionLog,
@@ -62,6 +74,7 @@ mod tolstoy_tests {
         TxReceiver,
     };
     use core_traits::{
+        Binding,
         Entid,
         TypedValue,
         ValueType,
@@ -349,6 +362,9 @@ mod tolstoy_tests {
             [:db.schema/core :db.schema/attribute 37 ?tx true]
             [:db.schema/core :db.schema/attribute 38 ?tx true]
             [:db.schema/core :db.schema/attribute 39 ?tx true]
+            [:db.schema/core :db.schema/attribute 41 ?tx true]
+            [:db.schema/core :db.schema/attribute 42 ?tx true]
+            [:db.schema/core :db.schema/attribute 44 ?tx true]
             [:db/ident :db/ident :db/ident ?tx true]
             [:db.part/db :db/ident :db.part/db ?tx true]
             [:db/txInstant :db/ident :db/txInstant ?tx true]
@@ -389,6 +405,10 @@ mod tolstoy_tests {
             [:db.schema/version :db/ident :db.schema/version ?tx true]
             [:db.schema/attribute :db/ident :db.schema/attribute ?tx true]
             [:db.schema/core :db/ident :db.schema/core ?tx true]
+            [:db/normalize :db/ident :db/normalize ?tx true]
+            [:db/caseInsensitive :db/ident :db/caseInsensitive ?tx true]
+            [:db.type/bigint :db/ident :db.type/bigint ?tx true]
+            [:db/uniqueWith :db/ident :db/uniqueWith ?tx true]
             [?tx :db/txInstant ?ms ?tx true]
             [:db/ident :db/valueType 24 ?tx true]
             [:db/txInstant :db/valueType 31 ?tx true]
@@ -406,6 +426,9 @@ mod tolstoy_tests {
             [:db/doc :db/valueType 27 ?tx true]
             [:db.schema/version :db/valueType 25 ?tx true]
             [:db.schema/attribute :db/valueType 23 ?tx true]
+            [:db/normalize :db/valueType 30 ?tx true]
+            [:db/caseInsensitive :db/valueType 30 ?tx true]
+            [:db/uniqueWith :db/valueType 23 ?tx true]
             [:db/ident :db/cardinality 33 ?tx true]
             [:db/txInstant :db/cardinality 33 ?tx true]
             [:db.install/partition :db/cardinality 34 ?tx true]
@@ -422,6 +445,9 @@ mod tolstoy_tests {
             [:db/doc :db/cardinality 33 ?tx true]
             [:db.schema/version :db/cardinality 33 ?tx true]
             [:db.schema/attribute :db/cardinality 34 ?tx true]
+            [:db/normalize :db/cardinality 33 ?tx true]
+            [:db/caseInsensitive :db/cardinality 33 ?tx true]
+            [:db/uniqueWith :db/cardinality 33 ?tx true]
             [:db/ident :db/unique 36 ?tx true]
             [:db.schema/attribute :db/unique 35 ?tx true]
             [:db/ident :db/index true ?tx true]
@@ -1367,4 +1393,268 @@ mod tolstoy_tests {
             conn_2, sqlite_2, remote_client
         );
     }
+
+    #[test]
+    fn test_conflict_resolution() {
+        let mut sqlite_1 = new_connection("").unwrap();
+        let mut sqlite_2 = new_connection("").unwrap();
+
+        let mut conn_1 = Conn::connect(&mut sqlite_1).unwrap();
+        let mut conn_2 = Conn::connect(&mut sqlite_2).unwrap();
+
+        let mut remote_client = TestRemoteClient::new();
+
+        conn_1.transact(&mut sqlite_1, "[
+            {:db/ident :person/name
+              :db/valueType :db.type/string
+              :db/cardinality :db.cardinality/one}
+            {:db/ident :person/nick
+              :db/valueType :db.type/string
+              :db/cardinality :db.cardinality/many}]").expect("transacted");
+
+        let ivan = *conn_1.transact(&mut sqlite_1, r#"[
+            {:db/id "i" :person/name "Ivan" :person/nick "Vanya"}]"#).expect("transacted").tempids.get("i").unwrap();
+
+        // Both start off knowing Ivan.
+        assert_sync!(SyncReport::RemoteFastForward, conn_1, sqlite_1, remote_client);
+        assert_sync!(SyncReport::Merge(SyncFollowup::None), conn_2, sqlite_2, remote_client);
+
+        // Both change his name and give him another nickname.
+        conn_1.transact(&mut sqlite_1, format!(r#"[
+            [:db/add {} :person/name "Ivan Ivanovich"]
+            [:db/add {} :person/nick "Vanechka"]]"#, ivan, ivan)).expect("transacted");
+        conn_2.transact(&mut sqlite_2, format!(r#"[
+            [:db/add {} :person/name "John"]
+            [:db/add {} :person/nick "Johnny"]]"#, ivan, ivan)).expect("transacted");
+
+        assert_sync!(SyncReport::RemoteFastForward, conn_1, sqlite_1, remote_client);
+
+        // Second's name wins, and both nicknames are kept.
+        {
+            let mut ip = conn_2.begin_transaction(&mut sqlite_2).expect("begun successfully");
+            let mut resolver = AttributeMerge::new(OursWin);
+            match Syncer::sync_with_resolver(&mut ip, &mut remote_client, &mut resolver).expect("sync report") {
+                SyncReport::Merge(SyncFollowup::FullSync) => (),
+                wr => panic!("Wrong sync report: {:?}", wr),
+            }
+            ip.commit().expect("committed");
+        }
+
+        let values = |query: &str| -> Vec<TypedValue> {
+            let mut values: Vec<TypedValue> = conn_2.q_once(&sqlite_2, query, None)
+                                                    .expect("queried")
+                                                    .into_coll()
+                                                    .expect("coll")
+                                                    .into_iter()
+                                                    .filter_map(|b| b.into_scalar())
+                                                    .collect();
+            values.sort();
+            values
+        };
+        assert_eq!(values("[:find [?name ...] :where [_ :person/name ?name]]"),
+                   vec![TypedValue::typed_string("John")]);
+        assert_eq!(values("[:find [?nick ...] :where [_ :person/nick ?nick]]"),
+                   vec![TypedValue::typed_string("Johnny"),
+                        TypedValue::typed_string("Vanechka"),
+                        TypedValue::typed_string("Vanya")]);
+    }
+
+    #[test]
+    fn test_sync_progress_and_cancellation() {
+        let mut sqlite_1 = new_connection("").unwrap();
+        let mut sqlite_2 = new_connection("").unwrap();
+
+        let mut conn_1 = Conn::connect(&mut sqlite_1).unwrap();
+        let mut conn_2 = Conn::connect(&mut sqlite_2).unwrap();
+
+        let mut remote_client = TestRemoteClient::new();
+
+        conn_1.transact(&mut sqlite_1, "[
+            {:db/ident :person/name
+              :db/valueType :db.type/string
+              :db/cardinality :db.cardinality/one}]").expect("transacted");
+        conn_1.transact(&mut sqlite_1, r#"[{:person/name "Ivan"}]"#).expect("transacted");
+        conn_1.transact(&mut sqlite_1, r#"[{:person/name "Petr"}]"#).expect("transacted");
+
+        // Pushing reports each uploaded transaction.
+        let mut reported: Vec<SyncProgress> = vec![];
+        {
+            let mut progress = |p: &SyncProgress| reported.push(p.clone());
+            let mut options = SyncOptions::new();
+            options.progress(&mut progress);
+
+            let mut ip = conn_1.begin_transaction(&mut sqlite_1).expect("begun successfully");
+            match Syncer::sync_with_options(&mut ip, &mut remote_client, &mut options).expect("sync report") {
+                SyncReport::RemoteFastForward => (),
+                wr => panic!("Wrong sync report: {:?}", wr),
+            }
+            ip.commit().expect("committed");
+        }
+        assert!(reported.iter().all(|p| p.phase == SyncPhase::Push));
+        assert_eq!(reported.iter().map(|p| p.txs).collect::<Vec<_>>(),
+                   (1..remote_client.rowid_tx.len() + 1).collect::<Vec<_>>());
+
+        // A cancelled sync changes nothing locally...
+        {
+            let token = CancellationToken::new();
+            let mut options = SyncOptions::new();
+            options.cancellation(token.clone());
+            token.cancel();
+
+            let mut ip = conn_2.begin_transaction(&mut sqlite_2).expect("begun successfully");
+            match Syncer::sync_with_options(&mut ip, &mut remote_client, &mut options).expect_err("expected sync to fail, but did not") {
+                MentatError::TolstoyError(TolstoyError::Cancelled) => (),
+                we => panic!("Failed with wrong error: {:?}", we),
+            }
+        }
+
+        // ... so the next one goes as it would have.  It pulls everything, then applies everything
+        // but the bootstrap transaction.
+        let mut reported: Vec<SyncProgress> = vec![];
+        {
+            let mut progress = |p: &SyncProgress| reported.push(p.clone());
+            let mut options = SyncOptions::new();
+            options.progress(&mut progress);
+
+            let mut ip = conn_2.begin_transaction(&mut sqlite_2).expect("begun successfully");
+            match Syncer::sync_with_options(&mut ip, &mut remote_client, &mut options).expect("sync report") {
+                SyncReport::Merge(SyncFollowup::None) => (),
+                wr => panic!("Wrong sync report: {:?}", wr),
+            }
+            ip.commit().expect("committed");
+        }
+        let txs = remote_client.rowid_tx.len();
+        assert_eq!(reported.first().map(|p| (p.phase, p.txs)), Some((SyncPhase::Pull, txs)));
+        assert_eq!(reported.last().map(|p| (p.phase, p.txs)), Some((SyncPhase::Apply, txs - 1)));
+
+        let names = conn_2.q_once(&sqlite_2, "[:find [?name ...] :where [_ :person/name ?name]]", None)
+                          .expect("queried")
+                          .into_coll()
+                          .expect("coll");
+        assert_eq!(names.len(), 2);
+    }
+
+    #[test]
+    fn test_filtered_sync() {
+        let mut sqlite_1 = new_connection("").unwrap();
+        let mut sqlite_2 = new_connection("").unwrap();
+
+        let mut conn_1 = Conn::connect(&mut sqlite_1).unwrap();
+        let mut conn_2 = Conn::connect(&mut sqlite_2).unwrap();
+
+        let mut remote_client = TestRemoteClient::new();
+
+        macro_rules! assert_filtered_sync {
+            ( $report: pat, $conn: expr, $sqlite: expr, $remote: expr ) => {{
+                let mut options = SyncOptions::new();
+                options.filter(SyncFilter::namespaces(vec!["bookmarks"]));
+
+                let mut ip = $conn.begin_transaction(&mut $sqlite).expect("begun successfully");
+                match Syncer::sync_with_options(&mut ip, &mut $remote, &mut options).expect("sync report") {
+                    $report => (),
+                    wr => panic!("Wrong sync report: {:?}", wr),
+                }
+                ip.commit().expect("committed");
+            }};
+        }
+
+        conn_1.transact(&mut sqlite_1, "[
+            {:db/ident :bookmarks/url
+              :db/valueType :db.type/string
+              :db/cardinality :db.cardinality/one}
+            {:db/ident :local/visits
+              :db/valueType :db.type/long
+              :db/cardinality :db.cardinality/one}]").expect("transacted");
+        conn_1.transact(&mut sqlite_1, r#"[
+            {:bookmarks/url "https://mozilla.org" :local/visits 3}]"#).expect("transacted");
+
+        // Only the bookmark leaves the first client.
+        assert_filtered_sync!(SyncReport::RemoteFastForward, conn_1, sqlite_1, remote_client);
+        assert_sync!(SyncReport::Merge(SyncFollowup::None), conn_2, sqlite_2, remote_client);
+
+        assert_eq!(conn_2.q_once(&sqlite_2, "[:find [?url ...] :where [_ :bookmarks/url ?url]]", None)
+                         .expect("queried")
+                         .into_coll()
+                         .expect("coll"),
+                   vec![TypedValue::typed_string("https://mozilla.org").into()]);
+        assert!(conn_2.current_schema().get_entid(&Keyword::namespaced("local", "visits")).is_none());
+
+        // The second client, which syncs everything, keeps notes of its own...
+        conn_2.transact(&mut sqlite_2, "[
+            {:db/ident :local/note
+              :db/valueType :db.type/string
+              :db/cardinality :db.cardinality/one}]").expect("transacted");
+        conn_2.transact(&mut sqlite_2, r#"[
+            {:bookmarks/url "https://example.com" :local/note "for later"}]"#).expect("transacted");
+        assert_sync!(SyncReport::RemoteFastForward, conn_2, sqlite_2, remote_client);
+
+        // ... which the first client ignores, while keeping its own private data intact.
+        assert_filtered_sync!(SyncReport::LocalFastForward, conn_1, sqlite_1, remote_client);
+
+        let mut urls: Vec<TypedValue> = conn_1.q_once(&sqlite_1, "[:find [?url ...] :where [_ :bookmarks/url ?url]]", None)
+                                              .expect("queried")
+                                              .into_coll()
+                                              .expect("coll")
+                                              .into_iter()
+                                              .filter_map(|b| b.into_scalar())
+                                              .collect();
+        urls.sort();
+        assert_eq!(urls, vec![TypedValue::typed_string("https://example.com"),
+                              TypedValue::typed_string("https://mozilla.org")]);
+        assert!(conn_1.current_schema().get_entid(&Keyword::namespaced("local", "note")).is_none());
+        assert_eq!(conn_1.q_once(&sqlite_1, "[:find ?v . :where [_ :local/visits ?v]]", None)
+                         .expect("queried")
+                         .into_scalar()
+                         .expect("scalar"),
+                   Some(TypedValue::Long(3).into()));
+    }
+
+    #[test]
+    fn test_offloaded_values_sync() {
+        let mut sqlite_1 = new_connection("").unwrap();
+        let mut sqlite_2 = new_connection("").unwrap();
+
+        let mut conn_1 = Conn::connect(&mut sqlite_1).unwrap();
+        let mut conn_2 = Conn::connect(&mut sqlite_2).unwrap();
+
+        let mut remote_client = TestRemoteClient::new();
+
+        let schema = "[
+            {:db/ident :page/description
+              :db/valueType :db.type/string
+              :db/cardinality :db.cardinality/one}]";
+        conn_1.transact(&mut sqlite_1, schema).expect("transacted");
+        conn_2.transact(&mut sqlite_2, schema).expect("transacted");
+
+        // Each client offloads a description of its own to `large_values`.
+        let first = "a description much longer than the threshold, from the first client";
+        let second = "a description much longer than the threshold, from the second client";
+        {
+            let mut ip = conn_1.begin_transaction(&mut sqlite_1).expect("begun successfully");
+            ip.large_value_threshold(Some(10));
+            ip.transact(format!(r#"[{{:page/description "{}"}}]"#, first)).expect("transacted");
+            ip.commit().expect("committed");
+        }
+        {
+            let mut ip = conn_2.begin_transaction(&mut sqlite_2).expect("begun successfully");
+            ip.large_value_threshold(Some(10));
+            ip.transact(format!(r#"[{{:page/description "{}"}}]"#, second)).expect("transacted");
+            ip.commit().expect("committed");
+        }
+
+        // Uploading reads offloaded values from the log, and merging rewinds the second client's
+        // transaction, which retracts its offloaded value.
+        assert_sync!(SyncReport::RemoteFastForward, conn_1, sqlite_1, remote_client);
+        assert_sync!(SyncReport::Merge(SyncFollowup::FullSync), conn_2, sqlite_2, remote_client);
+        assert_sync!(SyncReport::RemoteFastForward, conn_2, sqlite_2, remote_client);
+        assert_sync!(SyncReport::LocalFastForward, conn_1, sqlite_1, remote_client);
+
+        let descriptions = "[:find [?d ...] :where [_ :page/description ?d] :order ?d]";
+        let expected: Vec<Binding> = vec![TypedValue::typed_string(first).into(),
+                                          TypedValue::typed_string(second).into()];
+        assert_eq!(conn_1.q_once(&sqlite_1, descriptions, None).expect("queried").into_coll().expect("coll"),
+                   expected);
+        assert_eq!(conn_2.q_once(&sqlite_2, descriptions, None).expect("queried").into_coll().expect("coll"),
+                   expected);
+    }
 }
@@ -22,6 +22,7 @@ use mentat::vocabulary;
 
 use mentat::vocabulary::{
     Definition,
+    HasVocabularies,
     SimpleVocabularySource,
     Version,
     VersionedStore,
@@ -95,6 +96,7 @@ lazy_static! {
             ],
             pre: Definition::no_op,
             post: Definition::no_op,
+            migrations: vec![],
         }
     };
 }
@@ -442,6 +444,7 @@ fn test_upgrade_with_functions() {
         ],
         pre: Definition::no_op,
         post: Definition::no_op,
+        migrations: vec![],
     };
 
     let movies_v1 = vocabulary::Definition {
@@ -463,6 +466,7 @@ fn test_upgrade_with_functions() {
         ],
         pre: Definition::no_op,
         post: Definition::no_op,
+        migrations: vec![],
     };
 
     let people_v1 = vocabulary::Definition {
@@ -489,6 +493,7 @@ fn test_upgrade_with_functions() {
         ],
         pre: Definition::no_op,
         post: Definition::no_op,
+        migrations: vec![],
     };
 
     // Apply v1 of each.
@@ -568,6 +573,7 @@ fn test_upgrade_with_functions() {
         ],
         pre: Definition::no_op,
         post: Definition::no_op,
+        migrations: vec![],
     };
 
     // Mutable borrow of store.
@@ -646,6 +652,7 @@ fn test_upgrade_with_functions() {
         attributes: people_v1.attributes.clone(),
         pre: Definition::no_op,
         post: people_v1_to_v2,
+        migrations: vec![],
     };
 
     // Mutable borrow of store.
@@ -775,6 +782,7 @@ fn test_upgrade_with_functions() {
             }
         },
         post: Definition::no_op,
+        migrations: vec![],
     };
 
     // This migration is better: once we rewrite the names, we merge the entities.
@@ -797,6 +805,7 @@ fn test_upgrade_with_functions() {
             }
         },
         post: Definition::no_op,
+        migrations: vec![],
     };
 
     // Mutable borrow of store.
@@ -873,6 +882,7 @@ fn test_upgrade_with_functions() {
         ],
         pre: Definition::no_op,
         post: Definition::no_op,
+        migrations: vec![],
     };
     let food_v3 = vocabulary::Definition {
         name: kw!(:org.mozilla/food),
@@ -902,6 +912,7 @@ fn test_upgrade_with_functions() {
             Ok(())
         },
         post: Definition::no_op,
+        migrations: vec![],
     };
     let people_v3 = vocabulary::Definition {
         name: kw!(:org.mozilla/people),
@@ -917,6 +928,7 @@ fn test_upgrade_with_functions() {
             }
             Ok(())
         },
+        migrations: vec![],
     };
 
     // For this more complex option, let's implement the VocabularySource trait rather than
@@ -1055,3 +1067,189 @@ fn test_upgrade_with_functions() {
              TypedValue::typed_string("weird blue worms").into()];
     assert_eq!(expected, r);
 }
+
+#[test]
+fn test_upgrade_with_migrations() {
+    fn string_attribute() -> mentat::Attribute {
+        vocabulary::AttributeBuilder::helpful()
+            .value_type(ValueType::String)
+            .multival(false)
+            .build()
+    }
+
+    let contacts_v1 = Definition::new(kw!(:org.mozilla/contacts), 1, vec![
+        (kw!(:contact/name), string_attribute()),
+    ]);
+
+    let mut store = Store::open("").expect("open");
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        assert_eq!(VocabularyOutcome::Installed, in_progress.ensure_vocabulary(&contacts_v1).expect("installed"));
+        in_progress.transact(r#"[
+            {:contact/name "Ada Lovelace"}
+            {:contact/name "Grace Hopper"}
+        ]"#).expect("transacted");
+        in_progress.commit().expect("committed");
+    }
+
+    // Version 2 splits names into first and last names.
+    fn split_names(ip: &mut InProgress, _from: &Vocabulary) -> mentat::errors::Result<()> {
+        let first = ip.get_entid(&kw!(:contact/first)).expect(":contact/first");
+        let last = ip.get_entid(&kw!(:contact/last)).expect(":contact/last");
+        let mut builder = TermBuilder::new();
+        for row in ip.q_once("[:find ?c ?name :where [?c :contact/name ?name]]", None)
+                     .into_rel_result()?
+                     .into_iter() {
+            let mut row = row.into_iter();
+            match (row.next(), row.next()) {
+                (Some(Binding::Scalar(TypedValue::Ref(c))), Some(Binding::Scalar(TypedValue::String(name)))) => {
+                    let mut parts = name.splitn(2, ' ');
+                    builder.add(KnownEntid(c), first, TypedValue::typed_string(parts.next().unwrap_or("")))?;
+                    builder.add(KnownEntid(c), last, TypedValue::typed_string(parts.next().unwrap_or("")))?;
+                },
+                _ => {},
+            }
+        }
+        ip.transact_builder(builder).and(Ok(())).map_err(|e| e.into())
+    }
+
+    // Version 3 upper-cases last names.  This relies on version 2's migration having run first.
+    fn upper_case_last_names(ip: &mut InProgress, _from: &Vocabulary) -> mentat::errors::Result<()> {
+        let last = ip.get_entid(&kw!(:contact/last)).expect(":contact/last");
+        let mut builder = TermBuilder::new();
+        for row in ip.q_once("[:find ?c ?last :where [?c :contact/last ?last]]", None)
+                     .into_rel_result()?
+                     .into_iter() {
+            let mut row = row.into_iter();
+            match (row.next(), row.next()) {
+                (Some(Binding::Scalar(TypedValue::Ref(c))), Some(Binding::Scalar(TypedValue::String(name)))) => {
+                    builder.add(KnownEntid(c), last, TypedValue::typed_string(&name.to_uppercase()))?;
+                },
+                _ => {},
+            }
+        }
+        if builder.is_empty() {
+            return Ok(());
+        }
+        ip.transact_builder(builder).and(Ok(())).map_err(|e| e.into())
+    }
+
+    fn fail(_ip: &mut InProgress, _from: &Vocabulary) -> mentat::errors::Result<()> {
+        Err(MentatError::NotYetImplemented("failing migration".to_string()))
+    }
+
+    let contacts_v3_attributes = vec![
+        (kw!(:contact/name), string_attribute()),
+        (kw!(:contact/first), string_attribute()),
+        (kw!(:contact/last), string_attribute()),
+    ];
+
+    // A migration to a version beyond the definition's is a mistake.
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        let bad = Definition::new(kw!(:org.mozilla/contacts), 2, contacts_v3_attributes.clone())
+            .with_migration(3, Definition::no_op, Definition::no_op);
+        match in_progress.ensure_vocabulary(&bad).expect_err("expected failure") {
+            MentatError::InvalidMigrationVersion(name, 3, 2) => assert_eq!(name, ":org.mozilla/contacts"),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    // A failing migration leaves the store untouched when the transaction is abandoned.
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        let failing = Definition::new(kw!(:org.mozilla/contacts), 3, contacts_v3_attributes.clone())
+            .with_migration(2, Definition::no_op, split_names)
+            .with_migration(3, Definition::no_op, fail);
+        in_progress.ensure_vocabulary(&failing).expect_err("expected failure");
+    }
+    assert_eq!(1, store.begin_read().expect("began read")
+                       .read_vocabulary_named(&kw!(:org.mozilla/contacts)).expect("read").expect("present")
+                       .version);
+    assert!(store.begin_read().expect("began read").get_entid(&kw!(:contact/first)).is_none());
+
+    // Migrations run in version order, regardless of the order in which they're listed.
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        let contacts_v3 = Definition::new(kw!(:org.mozilla/contacts), 3, contacts_v3_attributes.clone())
+            .with_migration(3, Definition::no_op, upper_case_last_names)
+            .with_migration(2, Definition::no_op, split_names);
+        assert_eq!(VocabularyOutcome::Upgraded, in_progress.ensure_vocabulary(&contacts_v3).expect("upgraded"));
+        in_progress.commit().expect("committed");
+    }
+
+    let names = store.q_once(r#"[:find ?first ?last
+                                 :order ?first
+                                 :where [?c :contact/first ?first]
+                                        [?c :contact/last ?last]]"#, None)
+                     .into_rel_result()
+                     .expect("names");
+    let expected: RelResult<Binding> =
+        vec![vec![TypedValue::typed_string("Ada"), TypedValue::typed_string("LOVELACE")],
+             vec![TypedValue::typed_string("Grace"), TypedValue::typed_string("HOPPER")]].into();
+    assert_eq!(expected, names);
+}
+
+#[test]
+fn test_apply_retention() {
+    let visit = vocabulary::AttributeBuilder::helpful()
+        .value_type(ValueType::Long)
+        .multival(true)
+        .build();
+    let title = vocabulary::AttributeBuilder::helpful()
+        .value_type(ValueType::String)
+        .multival(true)
+        .build();
+
+    let mut store = Store::open("").expect("open");
+    let pages = Definition::new(kw!(:org.mozilla/pages), 1, vec![
+        (kw!(:page/visit), visit),
+        (kw!(:page/title), title),
+    ]);
+    let retention = vec![
+        (kw!(:page/visit), vocabulary::RetentionPolicy::keep_latest(2)),
+        (kw!(:page/title), vocabulary::RetentionPolicy::keep_days(30).excising()),
+    ];
+
+    let page = {
+        let mut in_progress = store.begin_transaction().expect("began");
+        assert_eq!(VocabularyOutcome::Installed, in_progress.ensure_vocabulary(&pages).expect("installed"));
+        let report = in_progress.transact(r#"[[:db/add (transaction-tx) :db/txInstant #inst "2018-01-01T00:00:00.000Z"]
+                                              [:db/add "p" :page/visit 1]
+                                              [:db/add "p" :page/title "Old"]]"#).expect("transacted");
+        let page = report.tempids["p"];
+        in_progress.transact(format!(r#"[[:db/add {} :page/visit 2] [:db/add {} :page/title "New"]]"#, page, page))
+                   .expect("transacted");
+        in_progress.transact(format!(r#"[[:db/add {} :page/visit 3]]"#, page)).expect("transacted");
+        in_progress.commit().expect("committed");
+        page
+    };
+
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        assert_eq!(2, in_progress.apply_retention(&retention).expect("trimmed"));
+        in_progress.commit().expect("committed");
+    }
+
+    let values = |store: &mut Store, q: &str| {
+        store.q_once(q, QueryInputs::with_value_sequence(vec![(var!(?p), TypedValue::Ref(page))]))
+             .into_coll_result()
+             .expect("values")
+    };
+    assert_eq!(values(&mut store, "[:find [?v ...] :in ?p :where [?p :page/visit ?v] :order ?v]"),
+               vec![Binding::from(TypedValue::Long(2)), Binding::from(TypedValue::Long(3))]);
+    assert_eq!(values(&mut store, "[:find [?t ...] :in ?p :where [?p :page/title ?t]]"),
+               vec![Binding::from(TypedValue::typed_string("New"))]);
+
+    // The retracted visit is still in the transaction log; the excised title isn't.
+    let logged = |store: &mut Store, v: &str| -> i64 {
+        store.sqlite_mut()
+             .query_row(&format!("SELECT count(*) FROM transactions WHERE e = {} AND v = {}", page, v), &[], |row| row.get(0))
+             .expect("counted")
+    };
+    assert_eq!(logged(&mut store, "1"), 2);
+    assert_eq!(logged(&mut store, "'Old'"), 0);
+
+    // Nothing more to trim.
+    assert_eq!(0, store.trim(&kw!(:page/visit), vocabulary::RetentionPolicy::keep_latest(2)).expect("trimmed"));
+}
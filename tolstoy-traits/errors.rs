@@ -43,6 +43,9 @@ pub enum TolstoyError {
     #[fail(display = "not yet implemented: {}", _0)]
     NotYetImplemented(String),
 
+    #[fail(display = "sync cancelled")]
+    Cancelled,
+
     #[fail(display = "{}", _0)]
     DbError(#[cause] DbError),
 
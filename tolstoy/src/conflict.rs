@@ -0,0 +1,266 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Resolving conflicts between local and remote changes during a merge.
+//!
+//! A conflict arises when both sides changed the values of the same attribute of the same entity
+//! since the last time they synced, and that entity already existed then.  The syncer asks a
+//! `ConflictResolver` what to do about each one, after the remote's transactions have been
+//! applied and before the local ones are rebased on top of them.  Local changes that conflict are
+//! then replaced by whatever the resolution calls for.
+
+use std::collections::BTreeMap;
+
+use core_traits::{
+    Attribute,
+    Entid,
+    TypedValue,
+};
+
+use mentat_core::{
+    Keyword,
+};
+
+use mentat_db::{
+    entids,
+};
+
+use public_traits::errors::{
+    Result,
+};
+
+use types::{
+    TxPart,
+};
+
+/// The net effect of a series of transactions on the values of one attribute of one entity.
+/// Asserting a value and then retracting it, or vice versa, is no change at all.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Changes {
+    pub added: Vec<TypedValue>,
+    pub retracted: Vec<TypedValue>,
+}
+
+impl Changes {
+    fn record(&mut self, v: TypedValue, added: bool) {
+        let (undone, done) = if added {
+            (&mut self.retracted, &mut self.added)
+        } else {
+            (&mut self.added, &mut self.retracted)
+        };
+        match undone.iter().position(|u| *u == v) {
+            Some(i) => {
+                undone.remove(i);
+            },
+            None => done.push(v),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.retracted.is_empty()
+    }
+}
+
+/// Both sides changed the values of attribute `a` of entity `e`.
+#[derive(Clone, Debug)]
+pub struct Conflict {
+    pub e: Entid,
+    pub a: Entid,
+    pub ident: Keyword,
+    pub attribute: Attribute,
+    /// What the local transactions did.
+    pub ours: Changes,
+    /// What the remote transactions did.  These have already been applied.
+    pub theirs: Changes,
+    /// The values after the remote transactions were applied.
+    pub current: Vec<TypedValue>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Resolution {
+    /// Keep the remote's values, dropping the local changes.
+    Theirs,
+    /// Replace the remote's changes with the local ones.
+    Ours,
+    /// Apply the local changes on top of the remote's.  For a cardinality-many attribute, this
+    /// keeps the values either side added, and drops those either side retracted.
+    Both,
+    /// Make these the values.
+    Values(Vec<TypedValue>),
+}
+
+impl Conflict {
+    /// The assertions (`true`) and retractions (`false`) that bring about `resolution`.
+    pub fn terms(&self, resolution: &Resolution) -> Vec<(TypedValue, bool)> {
+        let ours = || {
+            self.ours.retracted.iter().map(|v| (v.clone(), false))
+                .chain(self.ours.added.iter().map(|v| (v.clone(), true)))
+        };
+        match resolution {
+            &Resolution::Theirs => vec![],
+            &Resolution::Both => ours().collect(),
+            &Resolution::Ours => {
+                // Undo whatever they did that we didn't.
+                let undo_additions = self.theirs.added.iter()
+                    .filter(|v| !self.ours.added.contains(v))
+                    .map(|v| (v.clone(), false));
+                let undo_retractions = self.theirs.retracted.iter()
+                    .filter(|v| !self.ours.retracted.contains(v) && !self.ours.added.contains(v))
+                    .map(|v| (v.clone(), true));
+                ours().chain(undo_additions).chain(undo_retractions).collect()
+            },
+            &Resolution::Values(ref values) => {
+                self.current.iter()
+                    .filter(|v| !values.contains(v))
+                    .map(|v| (v.clone(), false))
+                    .chain(values.iter()
+                                 .filter(|v| !self.current.contains(v))
+                                 .map(|v| (v.clone(), true)))
+                    .collect()
+            },
+        }
+    }
+}
+
+/// Decides what to do when local and remote changes conflict.  Any
+/// `FnMut(&Conflict) -> Result<Resolution>` is one.
+pub trait ConflictResolver {
+    fn resolve(&mut self, conflict: &Conflict) -> Result<Resolution>;
+}
+
+impl<F> ConflictResolver for F where F: FnMut(&Conflict) -> Result<Resolution> {
+    fn resolve(&mut self, conflict: &Conflict) -> Result<Resolution> {
+        self(conflict)
+    }
+}
+
+/// The remote's changes always win.
+pub struct TheirsWin;
+
+impl ConflictResolver for TheirsWin {
+    fn resolve(&mut self, _conflict: &Conflict) -> Result<Resolution> {
+        Ok(Resolution::Theirs)
+    }
+}
+
+/// The local changes always win.
+pub struct OursWin;
+
+impl ConflictResolver for OursWin {
+    fn resolve(&mut self, _conflict: &Conflict) -> Result<Resolution> {
+        Ok(Resolution::Ours)
+    }
+}
+
+/// Keep both sides' changes to cardinality-many attributes, and defer to another resolver for
+/// cardinality-one attributes, which can't hold both.
+pub struct AttributeMerge<R> where R: ConflictResolver {
+    single_valued: R,
+}
+
+impl<R> AttributeMerge<R> where R: ConflictResolver {
+    pub fn new(single_valued: R) -> AttributeMerge<R> {
+        AttributeMerge {
+            single_valued: single_valued,
+        }
+    }
+}
+
+impl<R> ConflictResolver for AttributeMerge<R> where R: ConflictResolver {
+    fn resolve(&mut self, conflict: &Conflict) -> Result<Resolution> {
+        if conflict.attribute.multival {
+            Ok(Resolution::Both)
+        } else {
+            self.single_valued.resolve(conflict)
+        }
+    }
+}
+
+/// The net changes `parts` make to each attribute of each entity for which `existed` is true.
+/// Schema attributes and transaction instants are left out: the former are merged separately,
+/// and the latter are never in conflict.
+pub(crate) fn changes_by_attribute<'p, I, F>(parts: I, existed: F) -> BTreeMap<(Entid, Entid), Changes>
+where I: IntoIterator<Item=&'p TxPart>,
+      F: Fn(Entid) -> bool {
+    let mut changes: BTreeMap<(Entid, Entid), Changes> = BTreeMap::new();
+    for part in parts {
+        if part.a == entids::DB_TX_INSTANT || entids::is_a_schema_attribute(part.a) || !existed(part.e) {
+            continue;
+        }
+        changes.entry((part.e, part.a))
+               .or_insert_with(Changes::default)
+               .record(part.v.clone(), part.added);
+    }
+    changes.into_iter().filter(|&(_, ref c)| !c.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conflict(ours: Changes, theirs: Changes, current: Vec<TypedValue>) -> Conflict {
+        Conflict {
+            e: 65536,
+            a: 65537,
+            ident: Keyword::namespaced("person", "name"),
+            attribute: Attribute::default(),
+            ours: ours,
+            theirs: theirs,
+            current: current,
+        }
+    }
+
+    fn s(v: &str) -> TypedValue {
+        TypedValue::typed_string(v)
+    }
+
+    #[test]
+    fn test_net_changes() {
+        let mut changes = Changes::default();
+        changes.record(s("Ivan"), false);
+        changes.record(s("Vanya"), true);
+        changes.record(s("Vanechka"), true);
+        changes.record(s("Vanechka"), false);
+        assert_eq!(changes, Changes { added: vec![s("Vanya")], retracted: vec![s("Ivan")] });
+    }
+
+    #[test]
+    fn test_terms() {
+        // Both renamed Ivan.
+        let c = conflict(Changes { added: vec![s("Vanya")], retracted: vec![s("Ivan")] },
+                         Changes { added: vec![s("Ivan Ivanovich")], retracted: vec![s("Ivan")] },
+                         vec![s("Ivan Ivanovich")]);
+
+        assert!(c.terms(&Resolution::Theirs).is_empty());
+        assert_eq!(c.terms(&Resolution::Ours), vec![(s("Ivan"), false),
+                                                    (s("Vanya"), true),
+                                                    (s("Ivan Ivanovich"), false)]);
+        assert_eq!(c.terms(&Resolution::Both), vec![(s("Ivan"), false),
+                                                    (s("Vanya"), true)]);
+        assert_eq!(c.terms(&Resolution::Values(vec![s("John")])), vec![(s("Ivan Ivanovich"), false),
+                                                                       (s("John"), true)]);
+    }
+
+    #[test]
+    fn test_attribute_merge() {
+        let mut many = conflict(Changes::default(), Changes::default(), vec![]);
+        many.attribute.multival = true;
+        let one = conflict(Changes::default(), Changes::default(), vec![]);
+
+        let mut resolver = AttributeMerge::new(TheirsWin);
+        assert_eq!(resolver.resolve(&many).expect("resolved"), Resolution::Both);
+        assert_eq!(resolver.resolve(&one).expect("resolved"), Resolution::Theirs);
+
+        let mut resolver = AttributeMerge::new(|c: &Conflict| -> Result<Resolution> {
+            Ok(Resolution::Values(c.ours.added.clone()))
+        });
+        assert_eq!(resolver.resolve(&one).expect("resolved"), Resolution::Values(vec![]));
+    }
+}
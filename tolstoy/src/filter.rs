@@ -0,0 +1,169 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Syncing only some attributes.
+//!
+//! A `SyncFilter` names the attribute namespaces that are synced.  Datoms of other attributes are
+//! left out of the transactions a sync uploads, and dropped from those it downloads, so that, say,
+//! `:bookmarks/*` and `:history/*` are shared while `:local/*` stays on this device.
+//!
+//! The bootstrapped `:db/*` attributes are always synced, since the schema is built from them, but
+//! not when they describe an entity whose ident is outside the allowed namespaces: a private
+//! attribute's definition stays as private as its values.  The same goes for enumerated values
+//! like `:mood/happy`, whose namespace must be allowed for references to them to make sense
+//! elsewhere.  Every transaction is still synced, if only as its `:db/txInstant`, so that both
+//! sides agree on the transaction log.
+//!
+//! Changing the filter doesn't revisit what has already been synced.
+
+use std::collections::BTreeSet;
+
+use core_traits::{
+    Entid,
+    TypedValue,
+};
+
+use mentat_core::{
+    EntidMap,
+    Keyword,
+};
+
+use mentat_db::{
+    entids,
+};
+
+use types::{
+    TxPart,
+};
+
+fn is_core_namespace(namespace: &str) -> bool {
+    namespace == "db" || namespace.starts_with("db.")
+}
+
+/// Which attributes to sync, by namespace.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SyncFilter {
+    namespaces: BTreeSet<String>,
+}
+
+impl SyncFilter {
+    /// Sync attributes in `namespaces`, and no others.
+    pub fn namespaces<I, S>(namespaces: I) -> SyncFilter where I: IntoIterator<Item=S>, S: Into<String> {
+        SyncFilter {
+            namespaces: namespaces.into_iter().map(|ns| ns.into()).collect(),
+        }
+    }
+
+    /// Whether the attribute or entity named `ident` is synced.
+    pub fn allows(&self, ident: &Keyword) -> bool {
+        match ident.namespace() {
+            Some(namespace) => is_core_namespace(namespace) || self.namespaces.contains(namespace),
+            None => false,
+        }
+    }
+
+    fn allows_part(&self, part: &TxPart, idents: &EntidMap) -> bool {
+        if part.a == entids::DB_TX_INSTANT {
+            return true;
+        }
+        // An attribute we can't name is one we can't vouch for.
+        let a = match idents.get(&part.a) {
+            Some(a) => a,
+            None => return false,
+        };
+        if !self.allows(a) {
+            return false;
+        }
+        match idents.get(&part.e) {
+            Some(e) if a.namespace().map_or(false, is_core_namespace) => self.allows(e),
+            _ => true,
+        }
+    }
+
+    /// The parts of a transaction that are synced.  `idents` names attributes and entities, and
+    /// should include any idents the transaction itself asserts.  The partition map that comes
+    /// with the first part stays with whichever part is now first.
+    pub(crate) fn retain(&self, mut parts: Vec<TxPart>, idents: &EntidMap) -> Vec<TxPart> {
+        let partitions = parts.first_mut().and_then(|part| part.partitions.take());
+        let mut retained: Vec<TxPart> = parts.into_iter().filter(|part| self.allows_part(part, idents)).collect();
+        if let Some(first) = retained.first_mut() {
+            first.partitions = partitions;
+        }
+        retained
+    }
+}
+
+/// `idents`, and the idents `parts` assert.
+pub(crate) fn idents_with<'p, I>(idents: &EntidMap, parts: I) -> EntidMap where I: IntoIterator<Item=&'p TxPart> {
+    let mut idents = idents.clone();
+    for part in parts {
+        if part.a != entids::DB_IDENT || !part.added {
+            continue;
+        }
+        if let TypedValue::Keyword(ref ident) = part.v {
+            idents.insert(part.e, (**ident).clone());
+        }
+    }
+    idents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(e: Entid, a: Entid, v: TypedValue) -> TxPart {
+        TxPart {
+            partitions: None,
+            e: e,
+            a: a,
+            v: v,
+            tx: 268435457,
+            added: true,
+        }
+    }
+
+    #[test]
+    fn test_allows() {
+        let filter = SyncFilter::namespaces(vec!["bookmarks", "history"]);
+        assert!(filter.allows(&Keyword::namespaced("bookmarks", "url")));
+        assert!(filter.allows(&Keyword::namespaced("db", "ident")));
+        assert!(filter.allows(&Keyword::namespaced("db.type", "string")));
+        assert!(!filter.allows(&Keyword::namespaced("local", "secret")));
+        assert!(!filter.allows(&Keyword::namespaced("bookmarks.local", "note")));
+        assert!(!filter.allows(&Keyword::plain("bookmarks")));
+    }
+
+    #[test]
+    fn test_retain() {
+        let filter = SyncFilter::namespaces(vec!["bookmarks"]);
+        let (url, secret, page) = (65536, 65537, 65538);
+        let mut parts = vec![
+            part(page, secret, TypedValue::typed_string("hunter2")),
+            part(url, entids::DB_IDENT, TypedValue::typed_ns_keyword("bookmarks", "url")),
+            part(secret, entids::DB_IDENT, TypedValue::typed_ns_keyword("local", "secret")),
+            part(secret, entids::DB_CARDINALITY, TypedValue::Ref(entids::DB_CARDINALITY_ONE)),
+            part(page, url, TypedValue::typed_string("https://example.com")),
+            part(268435457, entids::DB_TX_INSTANT, TypedValue::Long(0)),
+        ];
+        parts[0].partitions = Some(Default::default());
+
+        let mut bootstrap = EntidMap::default();
+        bootstrap.insert(entids::DB_IDENT, Keyword::namespaced("db", "ident"));
+        bootstrap.insert(entids::DB_CARDINALITY, Keyword::namespaced("db", "cardinality"));
+        bootstrap.insert(entids::DB_TX_INSTANT, Keyword::namespaced("db", "txInstant"));
+        let idents = idents_with(&bootstrap, &parts);
+
+        // The private attribute's definition and value are dropped, and the partitions move along.
+        let retained = filter.retain(parts, &idents);
+        assert_eq!(retained.iter().map(|p| (p.e, p.a)).collect::<Vec<_>>(),
+                   vec![(url, entids::DB_IDENT), (page, url), (268435457, entids::DB_TX_INSTANT)]);
+        assert!(retained[0].partitions.is_some());
+    }
+}
@@ -42,6 +42,19 @@ extern crate tolstoy_traits;
 extern crate mentat_transaction;
 
 pub mod bootstrap;
+pub mod conflict;
+pub use conflict::{
+    AttributeMerge,
+    Conflict,
+    ConflictResolver,
+    OursWin,
+    Resolution,
+    TheirsWin,
+};
+pub mod filter;
+pub use filter::{
+    SyncFilter,
+};
 pub mod metadata;
 pub use metadata::{
     PartitionsTable,
@@ -49,6 +62,13 @@ pub use metadata::{
 };
 mod datoms;
 pub mod debug;
+pub mod options;
+pub use options::{
+    CancellationToken,
+    SyncOptions,
+    SyncPhase,
+    SyncProgress,
+};
 pub mod remote_client;
 pub use remote_client::{
     RemoteClient,
@@ -0,0 +1,184 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::fmt;
+
+use std::sync::Arc;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+
+use public_traits::errors::{
+    Result,
+};
+
+use tolstoy_traits::errors::{
+    TolstoyError,
+};
+
+use mentat_core::{
+    EntidMap,
+};
+
+use conflict::{
+    ConflictResolver,
+};
+
+use filter::{
+    SyncFilter,
+};
+
+use types::{
+    TxPart,
+};
+
+/// What a sync is doing.  A sync pulls remote transactions, applies them, resolves them against
+/// local transactions if both sides have changed, and pushes local transactions; which of these
+/// happen depends on what changed where.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SyncPhase {
+    /// Downloading remote transactions.
+    Pull,
+    /// Rebasing local transactions on top of remote ones.
+    Resolve,
+    /// Transacting remote transactions locally.
+    Apply,
+    /// Uploading local transactions.
+    Push,
+}
+
+impl fmt::Display for SyncPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncPhase::Pull => write!(f, "pull"),
+            SyncPhase::Resolve => write!(f, "resolve"),
+            SyncPhase::Apply => write!(f, "apply"),
+            SyncPhase::Push => write!(f, "push"),
+        }
+    }
+}
+
+/// How far a sync has got.  Passed to the progress callback as each phase makes progress.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SyncProgress {
+    pub phase: SyncPhase,
+
+    /// The number of transactions handled so far in this phase.
+    pub txs: usize,
+
+    /// The number of datoms in those transactions.
+    pub datoms: usize,
+}
+
+/// Asks a sync to stop.  Clones share their state, so one can be handed to a sync and another
+/// cancelled from elsewhere, such as another thread.
+///
+/// A cancelled sync fails with `TolstoyError::Cancelled` the next time it checks, which it does
+/// before each transaction it pulls, applies, rebases, or pushes.  Local changes are made in the
+/// sync's SQLite transaction, which is then rolled back; the remote head only moves once every
+/// local transaction has been pushed, so anything already pushed is ignored.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// How to sync, in the manner of `StoreOptions`.  By default, every attribute is synced, local
+/// changes are rebased on top of remote ones, nobody hears about progress, and the sync can't be
+/// cancelled.
+#[derive(Default)]
+pub struct SyncOptions<'a> {
+    filter: Option<SyncFilter>,
+    resolver: Option<&'a mut ConflictResolver>,
+    progress: Option<&'a mut FnMut(&SyncProgress)>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl<'a> SyncOptions<'a> {
+    pub fn new() -> SyncOptions<'a> {
+        SyncOptions::default()
+    }
+
+    /// Sync only the attributes `filter` allows.  See the `filter` module.
+    pub fn filter(&mut self, filter: SyncFilter) -> &mut SyncOptions<'a> {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn sync_filter(&self) -> Option<&SyncFilter> {
+        self.filter.as_ref()
+    }
+
+    /// Decide conflicts between local and remote changes with `resolver`.  See the `conflict`
+    /// module.
+    pub fn resolver(&mut self, resolver: &'a mut ConflictResolver) -> &mut SyncOptions<'a> {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Call `progress` as the sync makes progress.
+    pub fn progress(&mut self, progress: &'a mut FnMut(&SyncProgress)) -> &mut SyncOptions<'a> {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Stop the sync when `cancellation` is cancelled.
+    pub fn cancellation(&mut self, cancellation: CancellationToken) -> &mut SyncOptions<'a> {
+        self.cancellation = Some(cancellation);
+        self
+    }
+
+    /// The parts of a transaction to sync.  See `SyncFilter::retain`.
+    pub(crate) fn retain(&self, parts: Vec<TxPart>, idents: &EntidMap) -> Vec<TxPart> {
+        match self.filter {
+            Some(ref filter) => filter.retain(parts, idents),
+            None => parts,
+        }
+    }
+
+    pub(crate) fn conflict_resolver(&mut self) -> Option<&mut ConflictResolver> {
+        match self.resolver {
+            Some(ref mut resolver) => {
+                let resolver: &mut ConflictResolver = &mut **resolver;
+                Some(resolver)
+            },
+            None => None,
+        }
+    }
+
+    pub(crate) fn report(&mut self, phase: SyncPhase, txs: usize, datoms: usize) {
+        if let Some(ref mut progress) = self.progress {
+            (*progress)(&SyncProgress {
+                phase: phase,
+                txs: txs,
+                datoms: datoms,
+            });
+        }
+    }
+
+    pub(crate) fn check_cancelled(&self) -> Result<()> {
+        match self.cancellation {
+            Some(ref token) if token.is_cancelled() => bail!(TolstoyError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
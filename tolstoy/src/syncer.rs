@@ -9,8 +9,12 @@
 // specific language governing permissions and limitations under the License.
 
 use std::fmt;
+use std::mem;
 
-use std::collections::HashSet;
+use std::collections::{
+    BTreeMap,
+    HashSet,
+};
 
 use rusqlite;
 use uuid::Uuid;
@@ -29,6 +33,10 @@ use edn::entities::{
     EntityPlace,
     LookupRef,
 };
+use mentat_core::{
+    EntidMap,
+    HasSchema,
+};
 use mentat_db::{
     CORE_SCHEMA_VERSION,
     timelines,
@@ -55,6 +63,20 @@ use bootstrap::{
     BootstrapHelper,
 };
 
+use conflict::{
+    changes_by_attribute,
+    Changes,
+    Conflict,
+    ConflictResolver,
+};
+use filter::{
+    idents_with,
+};
+use options::{
+    SyncOptions,
+    SyncPhase,
+};
+
 use public_traits::errors::{
     Result,
 };
@@ -279,7 +301,8 @@ impl Syncer {
 
     /// Upload local txs: (from_tx, HEAD]. Remote head is necessary here because we need to specify
     /// "parent" for each transaction we'll upload; remote head will be first transaction's parent.
-    fn fast_forward_remote<R>(db_tx: &mut rusqlite::Transaction, from_tx: Option<Entid>, remote_client: &mut R, remote_head: &Uuid) -> Result<()>
+    /// `idents` names the attributes and entities involved, for the sake of `options`'s filter.
+    fn fast_forward_remote<R>(db_tx: &mut rusqlite::Transaction, idents: &EntidMap, from_tx: Option<Entid>, remote_client: &mut R, remote_head: &Uuid, options: &mut SyncOptions) -> Result<()>
         where R: GlobalTransactionLog {
 
         // TODO consider moving head manipulations into uploader?
@@ -292,7 +315,9 @@ impl Syncer {
             let uploader = TxUploader::new(
                 remote_client,
                 remote_head,
-                SyncMetadata::get_partitions(db_tx, PartitionsTable::Tolstoy)?
+                SyncMetadata::get_partitions(db_tx, PartitionsTable::Tolstoy)?,
+                idents,
+                options
             );
             // Walk the local transactions in the database and upload them.
             report = Processor::process(db_tx, from_tx, uploader)?;
@@ -368,10 +393,33 @@ impl Syncer {
         }
     }
 
-    fn fast_forward_local<'a, 'c>(in_progress: &mut InProgress<'a, 'c>, txs: Vec<Tx>) -> Result<SyncReport> {
+    /// Download the remote transactions after `tx`, dropping the datoms `options`'s filter doesn't
+    /// allow.  `idents` names the attributes and entities known locally.
+    fn pull<R>(remote_client: &R, idents: &EntidMap, tx: &Uuid, options: &mut SyncOptions) -> Result<Vec<Tx>>
+        where R: GlobalTransactionLog {
+        options.check_cancelled()?;
+        let mut txs = remote_client.transactions_after(tx)?;
+        options.report(SyncPhase::Pull, txs.len(), txs.iter().map(|tx| tx.parts.len()).sum());
+
+        if options.sync_filter().is_some() {
+            // Attributes defined remotely are named by the transactions that define them.
+            let idents = idents_with(idents, txs.iter().flat_map(|tx| tx.parts.iter()));
+            for tx in txs.iter_mut() {
+                let parts = mem::replace(&mut tx.parts, vec![]);
+                tx.parts = options.retain(parts, &idents);
+            }
+        }
+        Ok(txs)
+    }
+
+    fn fast_forward_local<'a, 'c>(in_progress: &mut InProgress<'a, 'c>, txs: Vec<Tx>, options: &mut SyncOptions) -> Result<SyncReport> {
         let mut last_tx = None;
 
+        let (mut applied_txs, mut applied_datoms) = (0, 0);
         for tx in txs {
+            options.check_cancelled()?;
+            let datoms = tx.parts.len();
+
             let mut builder = TermBuilder::new();
 
             // TODO both here and in the merge scenario we're doing the same thing with the partition maps
@@ -392,6 +440,10 @@ impl Syncer {
             in_progress.partition_map = partition_map;
             let report = in_progress.transact_builder(builder)?;
             last_tx = Some((report.tx_id, tx.tx.clone()));
+
+            applied_txs += 1;
+            applied_datoms += datoms;
+            options.report(SyncPhase::Apply, applied_txs, applied_datoms);
         }
 
         // We've just transacted a new tx, and generated a new tx entid.  Map it to the corresponding
@@ -403,7 +455,52 @@ impl Syncer {
         Ok(SyncReport::LocalFastForward)
     }
 
-    fn merge(ip: &mut InProgress, incoming_txs: Vec<Tx>, mut local_txs_to_merge: Vec<LocalTx>) -> Result<SyncReport> {
+    /// The values of attribute `a` of entity `e`.
+    fn current_values(ip: &InProgress, e: Entid, a: Entid) -> Result<Vec<TypedValue>> {
+        let values = ip.q_once(
+            "[:find [?v ...] :in ?e ?a :where [?e ?a ?v]]",
+            QueryInputs::with_value_sequence(
+                vec![
+                    (Variable::from_valid_name("?e"), TypedValue::Ref(e)),
+                    (Variable::from_valid_name("?a"), TypedValue::Ref(a)),
+                ]
+            )
+        )?.into_coll()?;
+        Ok(values.into_iter().filter_map(|v| v.into_scalar()).collect())
+    }
+
+    /// Ask `resolver` about each attribute of an entity that both `ours` and `theirs` changed,
+    /// returning the terms that resolve each conflict.
+    fn resolve_conflicts(ip: &InProgress, resolver: &mut ConflictResolver, ours: BTreeMap<(Entid, Entid), Changes>, theirs: &BTreeMap<(Entid, Entid), Changes>)
+        -> Result<BTreeMap<(Entid, Entid), Vec<(TypedValue, bool)>>> {
+        let mut resolved = BTreeMap::new();
+        for ((e, a), ours) in ours {
+            let theirs = match theirs.get(&(e, a)) {
+                // Both sides making the same change isn't a conflict.
+                Some(theirs) if *theirs != ours => theirs.clone(),
+                _ => continue,
+            };
+            let (ident, attribute) = match (ip.schema.get_ident(a), ip.schema.attribute_for_entid(a)) {
+                (Some(ident), Some(attribute)) => (ident.clone(), attribute.clone()),
+                _ => continue,
+            };
+            let conflict = Conflict {
+                e: e,
+                a: a,
+                ident: ident,
+                attribute: attribute,
+                ours: ours,
+                theirs: theirs,
+                current: Syncer::current_values(ip, e, a)?,
+            };
+            let resolution = resolver.resolve(&conflict)?;
+            d(&format!("resolved conflict {:?} as {:?}", conflict, resolution));
+            resolved.insert((e, a), conflict.terms(&resolution));
+        }
+        Ok(resolved)
+    }
+
+    fn merge(ip: &mut InProgress, incoming_txs: Vec<Tx>, mut local_txs_to_merge: Vec<LocalTx>, options: &mut SyncOptions) -> Result<SyncReport> {
         d(&format!("Rewinding local transactions."));
 
         // 1) Rewind local to shared root.
@@ -422,6 +519,16 @@ impl Syncer {
             Some(schema) => ip.schema = schema,
             None => ()
         };
+        ip.wrote = true;
+
+        // Only entities that existed at the shared root can have been changed by both sides:
+        // those allocated since are distinct even if their entids coincide.
+        let (ours, theirs) = {
+            let existed = |e: Entid| new_partition_map.values().any(|partition| partition.contains_entid(e));
+            (changes_by_attribute(local_txs_to_merge.iter().flat_map(|tx| tx.parts.iter()), &existed),
+             changes_by_attribute(incoming_txs.iter().flat_map(|tx| tx.parts.iter()), &existed))
+        };
+
         ip.partition_map = new_partition_map;
 
         // 2) Transact incoming.
@@ -437,13 +544,17 @@ impl Syncer {
                 None => return Ok(SyncReport::BadRemoteState("Missing partition map in incoming transaction".to_string()))
             };
 
+            let datoms = remote_tx.parts.len();
             Syncer::remote_parts_to_builder(&mut builder, remote_tx.parts)?;
 
-            builders.push((builder, partition_map, remote_tx.tx));
+            builders.push((builder, partition_map, remote_tx.tx, datoms));
         }
 
         let mut remote_report = None;
-        for (builder, mut partition_map, remote_tx) in builders {
+        let (mut applied_txs, mut applied_datoms) = (0, 0);
+        for (builder, mut partition_map, remote_tx, datoms) in builders {
+            options.check_cancelled()?;
+
             // Make space in the provided tx partition for the transaction we're about to create.
             // See function's notes for details.
             Syncer::rewind_tx_partition_by_one(&mut partition_map)?;
@@ -452,14 +563,34 @@ impl Syncer {
             // letting us just use KnownEntid in the builders.
             ip.partition_map = partition_map;
             remote_report = Some((ip.transact_builder(builder)?.tx_id, remote_tx));
+
+            applied_txs += 1;
+            applied_datoms += datoms;
+            options.report(SyncPhase::Apply, applied_txs, applied_datoms);
         }
 
+        // 3) Decide what to do about attributes both sides changed.  Without a resolver, local
+        // changes are rebased like any other.
+        let resolved = match options.conflict_resolver() {
+            Some(resolver) => Syncer::resolve_conflicts(ip, resolver, ours, &theirs)?,
+            None => BTreeMap::new(),
+        };
+
         d(&format!("Transacting local on top of incoming..."));
-        // 3) Rebase local transactions on top of remote.
+        // 4) Rebase local transactions on top of remote.
         let mut clean_rebase = true;
-        for local_tx in local_txs_to_merge {
+        let (mut rebased_txs, mut rebased_datoms) = (0, 0);
+        for mut local_tx in local_txs_to_merge {
+            options.check_cancelled()?;
+            rebased_txs += 1;
+            rebased_datoms += local_tx.parts.len();
+            options.report(SyncPhase::Resolve, rebased_txs, rebased_datoms);
+
             let mut builder = TermBuilder::new();
 
+            // Conflicting changes are replaced by their resolutions, below.
+            local_tx.parts.retain(|part| !resolved.contains_key(&(part.e, part.a)));
+
             // This is the beginnings of entity merging.
 
             // An entid might be already known to the Schema, or it
@@ -675,6 +806,32 @@ impl Syncer {
             }
         }
 
+        // 5) Transact the resolutions of conflicts, against the entities as they are.
+        if !resolved.is_empty() {
+            let mut builder = TermBuilder::new();
+            for ((e, a), terms) in resolved {
+                for (v, added) in terms {
+                    let entity: EntityPlace<TypedValue> = KnownEntid(e).into();
+                    if added {
+                        builder.add(entity, KnownEntid(a), v)?;
+                    } else {
+                        builder.retract(entity, KnownEntid(a), v)?;
+                    }
+                }
+            }
+
+            if !builder.is_empty() {
+                ip.savepoint("speculative_resolution")?;
+                let report = ip.transact_builder(builder)?;
+                if !SyncMetadata::is_tx_empty(&ip.transaction, report.tx_id)? {
+                    clean_rebase = false;
+                    ip.release_savepoint("speculative_resolution")?;
+                } else {
+                    ip.rollback_savepoint("speculative_resolution")?;
+                }
+            }
+        }
+
         // TODO
         // At this point, we've rebased local transactions on top of remote.
         // This would be a good point to create a "merge commit" and upload our loosing timeline.
@@ -693,13 +850,13 @@ impl Syncer {
         }
     }
 
-    fn first_sync_against_non_empty<R>(ip: &mut InProgress, remote_client: &R, local_metadata: &SyncMetadata) -> Result<SyncReport>
+    fn first_sync_against_non_empty<R>(ip: &mut InProgress, remote_client: &R, local_metadata: &SyncMetadata, options: &mut SyncOptions) -> Result<SyncReport>
         where R: GlobalTransactionLog {
 
         d(&format!("remote non-empty on first sync, adopting remote state."));
 
         // 1) Download remote transactions.
-        let incoming_txs = remote_client.transactions_after(&Uuid::nil())?;
+        let incoming_txs = Syncer::pull(remote_client, &ip.schema.entid_map, &Uuid::nil(), options)?;
         if incoming_txs.len() == 0 {
             return Ok(SyncReport::BadRemoteState("Remote specified non-root HEAD but gave no transactions".to_string()));
         }
@@ -750,7 +907,7 @@ impl Syncer {
             },
 
             SyncAction::LocalFastForward => {
-                Syncer::fast_forward_local(ip, incoming_txs[1 ..].to_vec())?;
+                Syncer::fast_forward_local(ip, incoming_txs[1 ..].to_vec(), options)?;
                 Ok(SyncReport::Merge(SyncFollowup::None))
             },
 
@@ -760,7 +917,8 @@ impl Syncer {
                 Syncer::merge(
                     ip,
                     incoming_txs[1 ..].to_vec(),
-                    local_txs
+                    local_txs,
+                    options
                 )
             }
         }
@@ -768,6 +926,24 @@ impl Syncer {
 
     pub fn sync<R>(ip: &mut InProgress, remote_client: &mut R) -> Result<SyncReport>
         where R: GlobalTransactionLog {
+        Syncer::sync_with_options(ip, remote_client, &mut SyncOptions::new())
+    }
+
+    /// Like `sync`, but when both sides changed the same attribute of the same entity since they
+    /// last synced, let `resolver` decide the outcome.  See the `conflict` module.
+    pub fn sync_with_resolver<R, C>(ip: &mut InProgress, remote_client: &mut R, resolver: &mut C) -> Result<SyncReport>
+        where R: GlobalTransactionLog, C: ConflictResolver {
+        let mut options = SyncOptions::new();
+        options.resolver(resolver);
+        Syncer::sync_with_options(ip, remote_client, &mut options)
+    }
+
+    /// Like `sync`, but filtering, resolving conflicts, reporting progress, and stopping when
+    /// cancelled as `options` asks.  A cancelled sync fails with `TolstoyError::Cancelled`, and `ip`
+    /// should then be rolled back.
+    pub fn sync_with_options<R>(ip: &mut InProgress, remote_client: &mut R, options: &mut SyncOptions) -> Result<SyncReport>
+        where R: GlobalTransactionLog {
+        options.check_cancelled()?;
 
         d(&format!("sync flowing"));
 
@@ -788,7 +964,7 @@ impl Syncer {
 
         // Currently, first sync against a non-empty remote is special.
         if locally_known_remote_head == Uuid::nil() && remote_head != Uuid::nil() {
-            return Syncer::first_sync_against_non_empty(ip, remote_client, &local_metadata);
+            return Syncer::first_sync_against_non_empty(ip, remote_client, &local_metadata, options);
         }
 
         match Syncer::what_do(remote_state, local_state) {
@@ -799,7 +975,7 @@ impl Syncer {
 
             SyncAction::PopulateRemote => {
                 d(&format!("empty remote!"));
-                Syncer::fast_forward_remote(&mut ip.transaction, None, remote_client, &remote_head)?;
+                Syncer::fast_forward_remote(&mut ip.transaction, &ip.schema.entid_map, None, remote_client, &remote_head, options)?;
                 Ok(SyncReport::RemoteFastForward)
             },
 
@@ -815,17 +991,15 @@ impl Syncer {
                 // but failed to advance our own local head. If that's the case, and we can recognize it,
                 // our sync becomes just bumping our local head. AFAICT below would currently fail.
                 Syncer::fast_forward_remote(
-                    &mut ip.transaction, Some(upload_from_tx), remote_client, &remote_head
+                    &mut ip.transaction, &ip.schema.entid_map, Some(upload_from_tx), remote_client, &remote_head, options
                 )?;
                 Ok(SyncReport::RemoteFastForward)
             },
 
             SyncAction::LocalFastForward => {
                 d(&format!("fast-forwarding local store."));
-                Syncer::fast_forward_local(
-                    ip,
-                    remote_client.transactions_after(&locally_known_remote_head)?
-                )?;
+                let incoming_txs = Syncer::pull(remote_client, &ip.schema.entid_map, &locally_known_remote_head, options)?;
+                Syncer::fast_forward_local(ip, incoming_txs, options)?;
                 Ok(SyncReport::LocalFastForward)
             },
 
@@ -840,13 +1014,15 @@ impl Syncer {
                     Some(combine_local_from_tx),
                     LocalTxSet::new()
                 )?;
+                let remote_txs = Syncer::pull(remote_client, &ip.schema.entid_map, &locally_known_remote_head, options)?;
                 // Merge!
                 Syncer::merge(
                     ip,
                     // Remote txs to merge...
-                    remote_client.transactions_after(&locally_known_remote_head)?,
+                    remote_txs,
                     // ... with the local txs.
-                    local_txs
+                    local_txs,
+                    options
                 )
             },
         }
@@ -13,6 +13,7 @@ use rusqlite;
 
 use mentat_db::{
     TypedSQLValue,
+    logged_value_sql,
 };
 
 use core_traits::{
@@ -134,7 +135,8 @@ impl Processor {
             Some(tx) => format!(" WHERE timeline = 0 AND tx > {} ", tx),
             None => format!("WHERE timeline = 0")
         };
-        let select_query = format!("SELECT e, a, v, value_type_tag, tx, added FROM timelined_transactions {} ORDER BY tx", tx_filter);
+        let select_query = format!("SELECT e, a, {}, value_type_tag, tx, added FROM timelined_transactions AS t {} ORDER BY tx",
+                                   logged_value_sql("t"), tx_filter);
         let mut stmt = sqlite.prepare(&select_query)?;
 
         let mut rows = stmt.query_and_then(&[], to_tx_part)?.peekable();
@@ -16,6 +16,10 @@ use core_traits::{
     Entid,
 };
 
+use mentat_core::{
+    EntidMap,
+};
+
 use mentat_db::{
     PartitionMap,
     V1_PARTS,
@@ -25,6 +29,11 @@ use public_traits::errors::{
     Result,
 };
 
+use options::{
+    SyncOptions,
+    SyncPhase,
+};
+
 use tx_processor::{
     TxReceiver,
 };
@@ -41,22 +50,28 @@ pub struct UploaderReport {
     pub head: Option<Uuid>,
 }
 
-pub(crate) struct TxUploader<'c> {
+pub(crate) struct TxUploader<'c, 'o: 'c> {
     tx_temp_uuids: HashMap<Entid, Uuid>,
     remote_client: &'c mut GlobalTransactionLog,
     remote_head: &'c Uuid,
     rolling_temp_head: Option<Uuid>,
     local_partitions: PartitionMap,
+    idents: &'c EntidMap,
+    options: &'c mut SyncOptions<'o>,
+    uploaded_datoms: usize,
 }
 
-impl<'c> TxUploader<'c> {
-    pub fn new(client: &'c mut GlobalTransactionLog, remote_head: &'c Uuid, local_partitions: PartitionMap) -> TxUploader<'c> {
+impl<'c, 'o: 'c> TxUploader<'c, 'o> {
+    pub fn new(client: &'c mut GlobalTransactionLog, remote_head: &'c Uuid, local_partitions: PartitionMap, idents: &'c EntidMap, options: &'c mut SyncOptions<'o>) -> TxUploader<'c, 'o> {
         TxUploader {
             tx_temp_uuids: HashMap::new(),
             remote_client: client,
             remote_head: remote_head,
             rolling_temp_head: None,
             local_partitions: local_partitions,
+            idents: idents,
+            options: options,
+            uploaded_datoms: 0,
         }
     }
 }
@@ -88,9 +103,12 @@ where T: Iterator<Item=Entid> {
     m
 }
 
-impl<'c> TxReceiver<UploaderReport> for TxUploader<'c> {
+impl<'c, 'o: 'c> TxReceiver<UploaderReport> for TxUploader<'c, 'o> {
     fn tx<T>(&mut self, tx_id: Entid, datoms: &mut T) -> Result<()>
     where T: Iterator<Item=TxPart> {
+        // Nothing uploaded so far is reachable from the remote head, so stopping here is safe.
+        self.options.check_cancelled()?;
+
         // Yes, we generate a new UUID for a given Tx, even if we might
         // already have one mapped locally. Pre-existing local mapping will
         // be replaced if this sync succeeds entirely.
@@ -104,11 +122,19 @@ impl<'c> TxReceiver<UploaderReport> for TxUploader<'c> {
 
         // TODO separate bits of network work should be combined into single 'future'
 
-        let mut datoms: Vec<TxPart> = datoms.collect();
+        let datoms: Vec<TxPart> = datoms.collect();
+
+        // The partitions make room for every entity, synced or not, so that other clients don't
+        // allocate entids that are already taken here.
+        let partitions = allocate_partition_map_for_entids(datoms.iter().map(|d| d.e), &self.local_partitions);
+
+        // Leave out whatever isn't synced.  The transaction's instant always is, so there's
+        // something left to upload.
+        let mut datoms = self.options.retain(datoms, self.idents);
 
         // TODO this should live within a transaction, once server support is in place.
         // For now, we're uploading the PartitionMap in transaction's first chunk.
-        datoms[0].partitions = Some(allocate_partition_map_for_entids(datoms.iter().map(|d| d.e), &self.local_partitions));
+        datoms[0].partitions = Some(partitions);
 
         // Upload all chunks.
         for datom in &datoms {
@@ -137,6 +163,9 @@ impl<'c> TxReceiver<UploaderReport> for TxUploader<'c> {
         d(&format!("updating rolling head: {:?}", tx_uuid));
         self.rolling_temp_head = Some(tx_uuid.clone());
 
+        self.uploaded_datoms += datoms.len();
+        self.options.report(SyncPhase::Push, self.tx_temp_uuids.len(), self.uploaded_datoms);
+
         Ok(())
     }
 
@@ -183,7 +212,7 @@ pub mod tests {
         let new_map = allocate_partition_map_for_entids(entids.into_iter(), &bootstrap_map);
         assert_eq!(65537, new_map.get(PARTITION_USER).unwrap().next_entid());
         // Other partitions are untouched.
-        assert_eq!(41, new_map.get(PARTITION_DB).unwrap().next_entid());
+        assert_eq!(45, new_map.get(PARTITION_DB).unwrap().next_entid());
         assert_eq!(268435456, new_map.get(PARTITION_TX).unwrap().next_entid());
 
         // Only tx partition.
@@ -192,12 +221,12 @@ pub mod tests {
         assert_eq!(268435667, new_map.get(PARTITION_TX).unwrap().next_entid());
         // Other partitions are untouched.
         assert_eq!(65536, new_map.get(PARTITION_USER).unwrap().next_entid());
-        assert_eq!(41, new_map.get(PARTITION_DB).unwrap().next_entid());
+        assert_eq!(45, new_map.get(PARTITION_DB).unwrap().next_entid());
 
         // Only DB partition.
-        let entids = vec![41];
+        let entids = vec![45];
         let new_map = allocate_partition_map_for_entids(entids.into_iter(), &bootstrap_map);
-        assert_eq!(42, new_map.get(PARTITION_DB).unwrap().next_entid());
+        assert_eq!(46, new_map.get(PARTITION_DB).unwrap().next_entid());
         // Other partitions are untouched.
         assert_eq!(65536, new_map.get(PARTITION_USER).unwrap().next_entid());
         assert_eq!(268435456, new_map.get(PARTITION_TX).unwrap().next_entid());
@@ -208,13 +237,13 @@ pub mod tests {
         assert_eq!(65538, new_map.get(PARTITION_USER).unwrap().next_entid());
         assert_eq!(268435457, new_map.get(PARTITION_TX).unwrap().next_entid());
         // DB partition is untouched.
-        assert_eq!(41, new_map.get(PARTITION_DB).unwrap().next_entid());
+        assert_eq!(45, new_map.get(PARTITION_DB).unwrap().next_entid());
 
         // DB, user and tx partitions.
-        let entids = vec![41, 65666, 268435457];
+        let entids = vec![45, 65666, 268435457];
         let new_map = allocate_partition_map_for_entids(entids.into_iter(), &bootstrap_map);
         assert_eq!(65667, new_map.get(PARTITION_USER).unwrap().next_entid());
         assert_eq!(268435458, new_map.get(PARTITION_TX).unwrap().next_entid());
-        assert_eq!(42, new_map.get(PARTITION_DB).unwrap().next_entid());
+        assert_eq!(46, new_map.get(PARTITION_DB).unwrap().next_entid());
     }
 }
@@ -34,6 +34,8 @@ use CliError;
 
 use edn;
 
+use std::fmt;
+
 use failure::{
     Compat,
     Error,
@@ -41,15 +43,24 @@ use failure::{
 
 use mentat::{
     CacheDirection,
+    DateTime,
+    Entid,
+    Utc,
 };
 
+pub static COMMAND_AS_OF: &'static str = &"as-of";
 pub static COMMAND_CACHE: &'static str = &"cache";
+pub static COMMAND_CACHED: &'static str = &"cached";
 pub static COMMAND_CLOSE: &'static str = &"close";
+pub static COMMAND_DUMP: &'static str = &"dump";
+pub static COMMAND_EDIT: &'static str = &"edit";
 pub static COMMAND_EXIT_LONG: &'static str = &"exit";
 pub static COMMAND_EXIT_SHORT: &'static str = &"e";
+pub static COMMAND_FORMAT: &'static str = &"format";
 pub static COMMAND_HELP: &'static str = &"help";
 pub static COMMAND_IMPORT_LONG: &'static str = &"import";
 pub static COMMAND_IMPORT_SHORT: &'static str = &"i";
+pub static COMMAND_NOW: &'static str = &"now";
 pub static COMMAND_OPEN: &'static str = &"open";
 pub static COMMAND_OPEN_ENCRYPTED: &'static str = &"open_encrypted";
 pub static COMMAND_QUERY_LONG: &'static str = &"query";
@@ -57,28 +68,145 @@ pub static COMMAND_QUERY_SHORT: &'static str = &"q";
 pub static COMMAND_QUERY_EXPLAIN_LONG: &'static str = &"explain_query";
 pub static COMMAND_QUERY_EXPLAIN_SHORT: &'static str = &"eq";
 pub static COMMAND_QUERY_PREPARED_LONG: &'static str = &"query_prepared";
+pub static COMMAND_READ: &'static str = &"read";
+pub static COMMAND_REKEY: &'static str = &"rekey";
 pub static COMMAND_SCHEMA: &'static str = &"schema";
+pub static COMMAND_SINCE: &'static str = &"since";
+pub static COMMAND_STATS: &'static str = &"stats";
 pub static COMMAND_SYNC: &'static str = &"sync";
 pub static COMMAND_TIMER_LONG: &'static str = &"timer";
 pub static COMMAND_TRANSACT_LONG: &'static str = &"transact";
 pub static COMMAND_TRANSACT_SHORT: &'static str = &"t";
+pub static COMMAND_TX: &'static str = &"tx";
+pub static COMMAND_UNCACHE: &'static str = &"uncache";
+
+pub static OPEN_FLAG_EMPTY: &'static str = &"--empty";
+pub static OPEN_FLAG_ENCRYPTED: &'static str = &"--encrypted";
+pub static OPEN_FLAG_EXISTING: &'static str = &"--existing";
+pub static OPEN_FLAG_READ_ONLY: &'static str = &"--read-only";
+
+pub static SCHEMA_FLAG_DIFF: &'static str = &"--diff";
+
+static COMMAND_NAMES: [&'static str; 31] = [
+    COMMAND_AS_OF, COMMAND_CACHE, COMMAND_CACHED, COMMAND_CLOSE, COMMAND_DUMP, COMMAND_EDIT,
+    COMMAND_EXIT_LONG, COMMAND_EXIT_SHORT, COMMAND_FORMAT, COMMAND_HELP, COMMAND_IMPORT_LONG,
+    COMMAND_IMPORT_SHORT, COMMAND_NOW, COMMAND_OPEN, COMMAND_OPEN_ENCRYPTED, COMMAND_QUERY_LONG,
+    COMMAND_QUERY_SHORT, COMMAND_QUERY_EXPLAIN_LONG, COMMAND_QUERY_EXPLAIN_SHORT,
+    COMMAND_QUERY_PREPARED_LONG, COMMAND_READ, COMMAND_REKEY, COMMAND_SCHEMA, COMMAND_SINCE, COMMAND_STATS, COMMAND_SYNC, COMMAND_TIMER_LONG, COMMAND_TRANSACT_LONG,
+    COMMAND_TRANSACT_SHORT, COMMAND_TX, COMMAND_UNCACHE,
+];
+
+/// A point in the store's history: a transaction, or the instant at which it was transacted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Basis {
+    Tx(Entid),
+    Instant(DateTime<Utc>),
+}
+
+impl fmt::Display for Basis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Basis::Tx(tx) => write!(f, "{}", tx),
+            &Basis::Instant(ref instant) => write!(f, "{}", edn::Value::Instant(instant.clone())),
+        }
+    }
+}
+
+/// How query results are printed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// An aligned table, for reading.
+    Table,
+    Edn,
+    Json,
+    /// Comma-separated values, with a header row naming the columns.
+    Csv,
+}
+
+impl Default for OutputFormat {
+    fn default() -> OutputFormat {
+        OutputFormat::Table
+    }
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "table" => Some(OutputFormat::Table),
+            "edn" => Some(OutputFormat::Edn),
+            "json" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            &OutputFormat::Table => "table",
+            &OutputFormat::Edn => "edn",
+            &OutputFormat::Json => "json",
+            &OutputFormat::Csv => "csv",
+        })
+    }
+}
+
+/// The flags that can precede the path given to `.open`, like `.open --read-only my.db`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OpenFlags {
+    /// Open the database without the ability to write to it.  It must already exist.
+    pub read_only: bool,
+    /// Create a new database, refusing to open one that already exists.
+    pub empty: bool,
+    /// Refuse to create a database that doesn't already exist.
+    pub existing: bool,
+    /// Prompt for the key with which the database is encrypted.
+    pub encrypted: bool,
+}
+
+impl fmt::Display for OpenFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = [(self.read_only, OPEN_FLAG_READ_ONLY),
+                     (self.empty, OPEN_FLAG_EMPTY),
+                     (self.existing, OPEN_FLAG_EXISTING),
+                     (self.encrypted, OPEN_FLAG_ENCRYPTED)];
+        for &(_, flag) in flags.iter().filter(|&&(set, _)| set) {
+            write!(f, "{} ", flag)?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Command {
+    AsOf(Basis),
     Cache(String, CacheDirection),
+    Cached,
     Close,
+    Dump(String),
+    Edit,
     Exit,
+    Format(OutputFormat),
     Help(Vec<String>),
     Import(String),
-    Open(String),
+    Now,
+    Open(String, OpenFlags),
     OpenEncrypted(String, String),
     Query(String),
     QueryExplain(String),
     QueryPrepared(String),
-    Schema,
+    Read(String),
+    Rekey,
+    Schema(Option<String>),
+    SchemaDiff(String),
+    Since(Basis),
+    Stats,
     Sync(Vec<String>),
     Timer(bool),
     Transact(String),
+    Tx(Entid),
+    Uncache(String),
 }
 
 impl Command {
@@ -95,61 +223,145 @@ impl Command {
             => {
                 edn::parse::value(&args).is_ok()
             },
+            &Command::AsOf(_) |
             &Command::Cache(_, _) |
+            &Command::Cached |
             &Command::Close |
+            &Command::Dump(_) |
+            &Command::Edit |
             &Command::Exit |
+            &Command::Format(_) |
             &Command::Help(_) |
             &Command::Import(_) |
-            &Command::Open(_) |
+            &Command::Now |
+            &Command::Open(_, _) |
             &Command::OpenEncrypted(_, _) |
+            &Command::Read(_) |
+            &Command::Rekey |
             &Command::Timer(_) |
-            &Command::Schema |
-            &Command::Sync(_)
+            &Command::Schema(_) |
+            &Command::SchemaDiff(_) |
+            &Command::Since(_) |
+            &Command::Stats |
+            &Command::Sync(_) |
+            &Command::Tx(_) |
+            &Command::Uncache(_)
             => true,
         }
     }
 
+    /// Pasting several commands at once can put more than one on a line, like
+    /// `.t [[:db/add "a" :foo/bar 1]] .q [:find ?x :where [?x :foo/bar _]]`.  If this command's
+    /// EDN argument is incomplete only because another command follows it, split that command off
+    /// and return it as the remaining input.
+    pub fn split_trailing_command(self) -> (Command, Option<String>) {
+        fn split(args: String) -> (String, Option<String>) {
+            if edn::parse::value(&args).is_ok() {
+                return (args, None);
+            }
+            let at = args.char_indices()
+                         .filter(|&(i, c)| c == '.' && args[..i].ends_with(char::is_whitespace))
+                         .map(|(i, _)| i)
+                         .find(|&i| starts_command(&args[i..]) && edn::parse::value(args[..i].trim_right()).is_ok());
+            match at {
+                Some(i) => (args[..i].trim_right().to_string(), Some(args[i..].to_string())),
+                None => (args, None),
+            }
+        }
+
+        match self {
+            Command::Query(args) => {
+                let (args, rest) = split(args);
+                (Command::Query(args), rest)
+            },
+            Command::QueryExplain(args) => {
+                let (args, rest) = split(args);
+                (Command::QueryExplain(args), rest)
+            },
+            Command::QueryPrepared(args) => {
+                let (args, rest) = split(args);
+                (Command::QueryPrepared(args), rest)
+            },
+            Command::Transact(args) => {
+                let (args, rest) = split(args);
+                (Command::Transact(args), rest)
+            },
+            cmd => (cmd, None),
+        }
+    }
+
     pub fn is_timed(&self) -> bool {
         match self {
+            &Command::Dump(_) |
             &Command::Import(_) |
             &Command::Query(_) |
             &Command::QueryPrepared(_) |
+            &Command::Stats |
             &Command::Transact(_)
             => true,
 
+            &Command::AsOf(_) |
             &Command::Cache(_, _) |
+            &Command::Cached |
             &Command::Close |
+            &Command::Edit |
             &Command::Exit |
+            &Command::Format(_) |
             &Command::Help(_) |
-            &Command::Open(_) |
+            &Command::Now |
+            &Command::Open(_, _) |
             &Command::OpenEncrypted(_, _) |
             &Command::QueryExplain(_) |
+            &Command::Read(_) |
+            &Command::Rekey |
             &Command::Timer(_) |
-            &Command::Schema |
-            &Command::Sync(_)
+            &Command::Schema(_) |
+            &Command::SchemaDiff(_) |
+            &Command::Since(_) |
+            &Command::Sync(_) |
+            &Command::Tx(_) |
+            &Command::Uncache(_)
             => false,
         }
     }
 
     pub fn output(&self) -> String {
         match self {
+            &Command::AsOf(ref basis) => {
+                format!(".{} {}", COMMAND_AS_OF, basis)
+            },
             &Command::Cache(ref attr, ref direction) => {
                 format!(".{} {} {:?}", COMMAND_CACHE, attr, direction)
             },
+            &Command::Cached => {
+                format!(".{}", COMMAND_CACHED)
+            },
             &Command::Close => {
                 format!(".{}", COMMAND_CLOSE)
             },
+            &Command::Dump(ref path) => {
+                format!(".{} {}", COMMAND_DUMP, path)
+            },
+            &Command::Edit => {
+                format!(".{}", COMMAND_EDIT)
+            },
             &Command::Exit => {
                 format!(".{}", COMMAND_EXIT_LONG)
             },
+            &Command::Format(format) => {
+                format!(".{} {}", COMMAND_FORMAT, format)
+            },
             &Command::Help(ref args) => {
                 format!(".{} {:?}", COMMAND_HELP, args)
             },
             &Command::Import(ref args) => {
                format!(".{} {}", COMMAND_IMPORT_LONG, args)
             },
-            &Command::Open(ref args) => {
-                format!(".{} {}", COMMAND_OPEN, args)
+            &Command::Now => {
+                format!(".{}", COMMAND_NOW)
+            },
+            &Command::Open(ref args, ref flags) => {
+                format!(".{} {}{}", COMMAND_OPEN, flags, args)
             },
             &Command::OpenEncrypted(ref db, ref key) => {
                 format!(".{} {} {}", COMMAND_OPEN_ENCRYPTED, db, key)
@@ -163,9 +375,27 @@ impl Command {
             &Command::QueryPrepared(ref args) => {
                 format!(".{} {}", COMMAND_QUERY_PREPARED_LONG, args)
             },
-            &Command::Schema => {
+            &Command::Read(ref path) => {
+                format!(".{} {}", COMMAND_READ, path)
+            },
+            &Command::Rekey => {
+                format!(".{}", COMMAND_REKEY)
+            },
+            &Command::Schema(None) => {
                 format!(".{}", COMMAND_SCHEMA)
             },
+            &Command::Schema(Some(ref namespace)) => {
+                format!(".{} {}", COMMAND_SCHEMA, namespace)
+            },
+            &Command::SchemaDiff(ref path) => {
+                format!(".{} {} {}", COMMAND_SCHEMA, SCHEMA_FLAG_DIFF, path)
+            },
+            &Command::Since(ref basis) => {
+                format!(".{} {}", COMMAND_SINCE, basis)
+            },
+            &Command::Stats => {
+                format!(".{}", COMMAND_STATS)
+            },
             &Command::Sync(ref args) => {
                 format!(".{} {:?}", COMMAND_SYNC, args)
             },
@@ -175,10 +405,27 @@ impl Command {
             &Command::Transact(ref args) => {
                 format!(".{} {}", COMMAND_TRANSACT_LONG, args)
             },
+            &Command::Tx(tx) => {
+                format!(".{} {}", COMMAND_TX, tx)
+            },
+            &Command::Uncache(ref attr) => {
+                format!(".{} {}", COMMAND_UNCACHE, attr)
+            },
         }
     }
 }
 
+/// Whether `s` starts with the name of a command, like `.q`, whether or not its arguments are
+/// valid.
+pub fn starts_command(s: &str) -> bool {
+    let s = s.trim_left();
+    if !s.starts_with('.') {
+        return false;
+    }
+    let name = s[1..].split(char::is_whitespace).next().unwrap_or("");
+    COMMAND_NAMES.contains(&name)
+}
+
 pub fn command(s: &str) -> Result<Command, Error> {
     let path = || many1::<String, _>(satisfy(|c: char| !c.is_whitespace()));
     let argument = || many1::<String, _>(satisfy(|c: char| !c.is_whitespace()));
@@ -206,7 +453,7 @@ pub fn command(s: &str) -> Result<Command, Error> {
         string(command)
             .with(spaces())
             .with(arguments())
-            .map(move |args| {
+            .map(move |args: Vec<String>| {
                 if args.len() < num_args {
                     bail!(CliError::CommandParse("Missing required argument".to_string()));
                 }
@@ -217,7 +464,22 @@ pub fn command(s: &str) -> Result<Command, Error> {
             })
     };
 
+    // A transaction ID, or an instant like `#inst "2018-01-01T00:00:00Z"`.
+    let basis_parser = || spaces()
+                          .with(many1::<String, _>(try(any())))
+                          .map(|arg| {
+                              match edn::parse::value(arg.trim()).map(|v| v.without_spans()) {
+                                  Ok(edn::Value::Integer(tx)) => Ok(Basis::Tx(tx)),
+                                  Ok(edn::Value::Instant(instant)) => Ok(Basis::Instant(instant)),
+                                  _ => bail!(CliError::CommandParse(format!("Expected a transaction ID or #inst, got {:?}", arg.trim()))),
+                              }
+                          });
+
     // Commands.
+    let as_of_parser = string(COMMAND_AS_OF)
+                    .with(basis_parser())
+                    .map(|basis| basis.map(Command::AsOf));
+
     let cache_parser = string(COMMAND_CACHE)
                     .with(spaces())
                     .with(argument().skip(spaces()).and(direction_parser())
@@ -225,6 +487,14 @@ pub fn command(s: &str) -> Result<Command, Error> {
                         Ok(Command::Cache(arg, direction))
                     }));
 
+    let cached_parser = string(COMMAND_CACHED)
+                    .with(no_arg_parser())
+                    .map(|args| {
+                        if !args.is_empty() {
+                            bail!(CliError::CommandParse(format!("Unrecognized argument {:?}", args[0])) );
+                        }
+                        Ok(Command::Cached)
+                    });
 
     let close_parser = string(COMMAND_CLOSE)
                     .with(no_arg_parser())
@@ -235,7 +505,21 @@ pub fn command(s: &str) -> Result<Command, Error> {
                         Ok(Command::Close)
                     });
 
-    let exit_parser = try(string(COMMAND_EXIT_LONG)).or(try(string(COMMAND_EXIT_SHORT)))
+    let dump_parser = opener(COMMAND_DUMP, 1).map(|args_res|
+        args_res.map(|args| Command::Dump(args[0].clone())));
+
+    let edit_parser = string(COMMAND_EDIT)
+                    .with(no_arg_parser())
+                    .map(|args| {
+                        if !args.is_empty() {
+                            bail!(CliError::CommandParse(format!("Unrecognized argument {:?}", args[0])) );
+                        }
+                        Ok(Command::Edit)
+                    });
+
+    // `.e` mustn't match the start of another command, like `.edit`.
+    let exit_parser = try(string(COMMAND_EXIT_LONG))
+                    .or(try(string(COMMAND_EXIT_SHORT).skip(look_ahead(space().map(|_| ()).or(eof())))))
                     .with(no_arg_parser())
                     .map(|args| {
                         if !args.is_empty() {
@@ -251,6 +535,14 @@ pub fn command(s: &str) -> Result<Command, Error> {
                             Ok(Command::QueryExplain(x))
                         });
 
+    let format_parser = opener(COMMAND_FORMAT, 1).map(|args_res|
+        args_res.and_then(|args| {
+            match OutputFormat::from_name(&args[0]) {
+                Some(format) => Ok(Command::Format(format)),
+                None => bail!(CliError::CommandParse(format!("Expected one of table, edn, json, or csv, got {:?}", args[0]))),
+            }
+        }));
+
     let help_parser = string(COMMAND_HELP)
                     .with(spaces())
                     .with(arguments())
@@ -265,8 +557,43 @@ pub fn command(s: &str) -> Result<Command, Error> {
                         Ok(Command::Import(x))
                     });
 
-    let open_parser = opener(COMMAND_OPEN, 1).map(|args_res|
-        args_res.map(|args| Command::Open(args[0].clone())));
+    let now_parser = string(COMMAND_NOW)
+                    .with(no_arg_parser())
+                    .map(|args| {
+                        if !args.is_empty() {
+                            bail!(CliError::CommandParse(format!("Unrecognized argument {:?}", args[0])) );
+                        }
+                        Ok(Command::Now)
+                    });
+
+    let open_parser = string(COMMAND_OPEN)
+                    .with(spaces())
+                    .with(arguments())
+                    .map(|args| {
+                        let mut flags = OpenFlags::default();
+                        let mut paths = vec![];
+                        for arg in args {
+                            match arg.as_str() {
+                                flag if flag == OPEN_FLAG_READ_ONLY => flags.read_only = true,
+                                flag if flag == OPEN_FLAG_EMPTY => flags.empty = true,
+                                flag if flag == OPEN_FLAG_EXISTING => flags.existing = true,
+                                flag if flag == OPEN_FLAG_ENCRYPTED => flags.encrypted = true,
+                                flag if flag.starts_with("--") => {
+                                    bail!(CliError::CommandParse(format!("Unrecognized flag {:?}", flag)));
+                                },
+                                _ => paths.push(arg.clone()),
+                            }
+                        }
+                        if flags.empty && (flags.read_only || flags.existing) {
+                            bail!(CliError::CommandParse(format!("{} can't be combined with {} or {}",
+                                                                 OPEN_FLAG_EMPTY, OPEN_FLAG_READ_ONLY, OPEN_FLAG_EXISTING)));
+                        }
+                        match paths.len() {
+                            0 => bail!(CliError::CommandParse("Missing required argument".to_string())),
+                            1 => Ok(Command::Open(paths.pop().unwrap(), flags)),
+                            _ => bail!(CliError::CommandParse(format!("Unrecognized argument {:?}", paths[1]))),
+                        }
+                    });
 
     let open_encrypted_parser = opener(COMMAND_OPEN_ENCRYPTED, 2).map(|args_res|
         args_res.map(|args| Command::OpenEncrypted(args[0].clone(), args[1].clone())));
@@ -283,13 +610,55 @@ pub fn command(s: &str) -> Result<Command, Error> {
                             Ok(Command::QueryPrepared(x))
                         });
 
+    let read_parser = opener(COMMAND_READ, 1).map(|args_res|
+        args_res.map(|args| Command::Read(args[0].clone())));
+
+    let rekey_parser = string(COMMAND_REKEY)
+                    .with(no_arg_parser())
+                    .map(|args| {
+                        if !args.is_empty() {
+                            bail!(CliError::CommandParse(format!("Unrecognized argument {:?}", args[0])) );
+                        }
+                        Ok(Command::Rekey)
+                    });
+
+    // `.schema`, `.schema foo` for the `:foo/*` attributes, or `.schema --diff path`.
     let schema_parser = string(COMMAND_SCHEMA)
+                    .with(spaces())
+                    .with(arguments())
+                    .map(|args| {
+                        match args.first().map(|arg| arg.as_str()) {
+                            None => Ok(Command::Schema(None)),
+                            Some(flag) if flag == SCHEMA_FLAG_DIFF => {
+                                match args.len() {
+                                    1 => bail!(CliError::CommandParse("Missing required argument".to_string())),
+                                    2 => Ok(Command::SchemaDiff(args[1].clone())),
+                                    _ => bail!(CliError::CommandParse(format!("Unrecognized argument {:?}", args[2]))),
+                                }
+                            },
+                            Some(flag) if flag.starts_with("--") => {
+                                bail!(CliError::CommandParse(format!("Unrecognized flag {:?}", flag)));
+                            },
+                            Some(namespace) => {
+                                if args.len() > 1 {
+                                    bail!(CliError::CommandParse(format!("Unrecognized argument {:?}", args[1])));
+                                }
+                                Ok(Command::Schema(Some(namespace.trim_left_matches(':').to_string())))
+                            },
+                        }
+                    });
+
+    let since_parser = string(COMMAND_SINCE)
+                    .with(basis_parser())
+                    .map(|basis| basis.map(Command::Since));
+
+    let stats_parser = string(COMMAND_STATS)
                     .with(no_arg_parser())
                     .map(|args| {
                         if !args.is_empty() {
                             bail!(CliError::CommandParse(format!("Unrecognized argument {:?}", args[0])) );
                         }
-                        Ok(Command::Schema)
+                        Ok(Command::Stats)
                     });
 
     let sync_parser = string(COMMAND_SYNC)
@@ -318,23 +687,46 @@ pub fn command(s: &str) -> Result<Command, Error> {
                         Ok(Command::Transact(x))
                     });
 
+    let tx_parser = opener(COMMAND_TX, 1).map(|args_res|
+        args_res.and_then(|args| {
+            match args[0].parse::<Entid>() {
+                Ok(tx) => Ok(Command::Tx(tx)),
+                Err(_) => bail!(CliError::CommandParse(format!("Expected a transaction ID, got {:?}", args[0]))),
+            }
+        }));
+
+    let uncache_parser = opener(COMMAND_UNCACHE, 1).map(|args_res|
+        args_res.map(|args| Command::Uncache(args[0].clone())));
+
     spaces()
     .skip(token('.'))
-    .with(choice::<[&mut Parser<Input = _, Output = Result<Command, Error>>; 14], _>
+    .with(choice::<[&mut Parser<Input = _, Output = Result<Command, Error>>; 26], _>
           ([&mut try(help_parser),
+            &mut try(as_of_parser),
             &mut try(import_parser),
             &mut try(timer_parser),
+            &mut try(cached_parser),
             &mut try(cache_parser),
             &mut try(open_encrypted_parser),
             &mut try(open_parser),
             &mut try(close_parser),
+            &mut try(dump_parser),
+            &mut try(edit_parser),
             &mut try(explain_query_parser),
             &mut try(exit_parser),
+            &mut try(format_parser),
+            &mut try(now_parser),
             &mut try(query_prepared_parser),
             &mut try(query_parser),
+            &mut try(read_parser),
+            &mut try(rekey_parser),
             &mut try(schema_parser),
+            &mut try(since_parser),
+            &mut try(stats_parser),
             &mut try(sync_parser),
-            &mut try(transact_parser)]))
+            &mut try(tx_parser),
+            &mut try(transact_parser),
+            &mut try(uncache_parser)]))
         .parse(s)
         .unwrap_or((Err(CliError::CommandParse(format!("Invalid command {:?}", s)).into()), "")).0
 }
@@ -405,7 +797,8 @@ mod tests {
         let input = ".open database1";
         let cmd = command(&input).expect("Expected open command");
         match cmd {
-            Command::Open(arg) => {
+            Command::Open(arg, flags) => {
+                assert_eq!(flags, OpenFlags::default());
                 assert_eq!(arg, "database1".to_string());
             },
             _ => assert!(false)
@@ -417,7 +810,8 @@ mod tests {
         let input = ".open /path/to/my.db";
         let cmd = command(&input).expect("Expected open command");
         match cmd {
-            Command::Open(arg) => {
+            Command::Open(arg, flags) => {
+                assert_eq!(flags, OpenFlags::default());
                 assert_eq!(arg, "/path/to/my.db".to_string());
             },
             _ => assert!(false)
@@ -444,6 +838,16 @@ mod tests {
         assert_eq!(err.to_string(), "Missing required argument");
     }
 
+    #[test]
+    fn test_rekey_parser() {
+        let cmd = command(".rekey").expect("Expected rekey command");
+        assert_eq!(cmd, Command::Rekey);
+
+        let input = ".rekey hunter2";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), format!("Invalid command {:?}", input));
+    }
+
     #[test]
     fn test_sync_parser_path_arg() {
         let input = ".sync https://example.com/api/ 316ea470-ce35-4adf-9c61-e0de6e289c59";
@@ -462,7 +866,8 @@ mod tests {
         let input = ".open my.db";
         let cmd = command(&input).expect("Expected open command");
         match cmd {
-            Command::Open(arg) => {
+            Command::Open(arg, flags) => {
+                assert_eq!(flags, OpenFlags::default());
                 assert_eq!(arg, "my.db".to_string());
             },
             _ => assert!(false)
@@ -510,6 +915,54 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dump_parser() {
+        let input = ".dump /path/to/backup.mentat";
+        let cmd = command(&input).expect("Expected dump command");
+        assert_eq!(cmd, Command::Dump("/path/to/backup.mentat".to_string()));
+
+        let input = ".dump";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Missing required argument");
+    }
+
+    #[test]
+    fn test_read_parser() {
+        let input = ".read backup.mentat";
+        let cmd = command(&input).expect("Expected read command");
+        assert_eq!(cmd, Command::Read("backup.mentat".to_string()));
+    }
+
+    #[test]
+    fn test_edit_parser() {
+        let input = ".edit";
+        let cmd = command(&input).expect("Expected edit command");
+        assert_eq!(cmd, Command::Edit);
+
+        let input = ".edit arg1";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), format!("Invalid command {:?}", input));
+    }
+
+    #[test]
+    fn test_format_parser() {
+        let input = ".format json";
+        let cmd = command(&input).expect("Expected format command");
+        assert_eq!(cmd, Command::Format(OutputFormat::Json));
+
+        let input = ".format csv ";
+        let cmd = command(&input).expect("Expected format command");
+        assert_eq!(cmd, Command::Format(OutputFormat::Csv));
+
+        let input = ".format xml";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Expected one of table, edn, json, or csv, got \"xml\"");
+
+        let input = ".format";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Missing required argument");
+    }
+
     #[test]
     fn test_exit_parser_with_args() {
         let input = ".exit arg1";
@@ -548,10 +1001,43 @@ mod tests {
     }
 
     #[test]
-    fn test_schema_parser_with_args() {
-        let input = ".schema arg1";
+    fn test_schema_parser_namespace_arg() {
+        let input = ".schema foo";
+        let cmd = command(&input).expect("Expected schema command");
+        assert_eq!(cmd, Command::Schema(Some("foo".to_string())));
+
+        let input = ".schema :foo";
+        let cmd = command(&input).expect("Expected schema command");
+        assert_eq!(cmd, Command::Schema(Some("foo".to_string())));
+    }
+
+    #[test]
+    fn test_schema_parser_multiple_args() {
+        let input = ".schema foo bar";
         let err = command(&input).expect_err("Expected an error");
-        assert_eq!(err.to_string(), format!("Invalid command {:?}", input));
+        assert_eq!(err.to_string(), "Unrecognized argument \"bar\"");
+    }
+
+    #[test]
+    fn test_schema_parser_diff() {
+        let input = ".schema --diff /path/to/vocabulary.edn";
+        let cmd = command(&input).expect("Expected schema command");
+        assert_eq!(cmd, Command::SchemaDiff("/path/to/vocabulary.edn".to_string()));
+
+        let input = ".schema --diff";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Missing required argument");
+
+        let input = ".schema --diff a.edn b.edn";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Unrecognized argument \"b.edn\"");
+    }
+
+    #[test]
+    fn test_schema_parser_unknown_flag() {
+        let input = ".schema --bogus";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Unrecognized flag \"--bogus\"");
     }
 
     #[test]
@@ -559,7 +1045,7 @@ mod tests {
         let input = ".schema";
         let cmd = command(&input).expect("Expected schema command");
         match cmd {
-            Command::Schema => assert!(true),
+            Command::Schema(None) => assert!(true),
             _ => assert!(false)
         }
     }
@@ -569,7 +1055,7 @@ mod tests {
         let input = ".schema ";
         let cmd = command(&input).expect("Expected schema command");
         match cmd {
-            Command::Schema => assert!(true),
+            Command::Schema(None) => assert!(true),
             _ => assert!(false)
         }
     }
@@ -715,4 +1201,162 @@ mod tests {
         let err = command(&input).expect_err("Expected an error");
         assert_eq!(err.to_string(), format!("Invalid command {:?}", input));
     }
+
+    #[test]
+    fn test_uncache_parser() {
+        let input = ".uncache :foo/bar";
+        let cmd = command(&input).expect("Expected uncache command");
+        assert_eq!(cmd, Command::Uncache(":foo/bar".to_string()));
+
+        let input = ".uncache";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Missing required argument");
+    }
+
+    #[test]
+    fn test_cached_parser() {
+        let input = ".cached";
+        let cmd = command(&input).expect("Expected cached command");
+        assert_eq!(cmd, Command::Cached);
+
+        let input = ".cache :foo/bar forward";
+        let cmd = command(&input).expect("Expected cache command");
+        assert_eq!(cmd, Command::Cache(":foo/bar".to_string(), CacheDirection::Forward));
+    }
+
+    #[test]
+    fn test_stats_parser() {
+        let input = ".stats";
+        let cmd = command(&input).expect("Expected stats command");
+        assert_eq!(cmd, Command::Stats);
+
+        let input = ".stats :foo/bar";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), format!("Invalid command {:?}", input));
+    }
+
+    #[test]
+    fn test_as_of_parser() {
+        let input = ".as-of 268435460";
+        let cmd = command(&input).expect("Expected as-of command");
+        assert_eq!(cmd, Command::AsOf(Basis::Tx(268435460)));
+        assert_eq!(cmd.output(), ".as-of 268435460");
+
+        let input = ".as-of #inst \"2018-04-01T12:00:00.000Z\"";
+        let cmd = command(&input).expect("Expected as-of command");
+        let instant = "2018-04-01T12:00:00Z".parse::<DateTime<Utc>>().expect("instant");
+        assert_eq!(cmd, Command::AsOf(Basis::Instant(instant)));
+
+        let input = ".as-of";
+        command(&input).expect_err("Expected an error");
+
+        let input = ".as-of :foo/bar";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Expected a transaction ID or #inst, got \":foo/bar\"");
+    }
+
+    #[test]
+    fn test_tx_parser() {
+        let input = ".tx 268435460";
+        let cmd = command(&input).expect("Expected tx command");
+        assert_eq!(cmd, Command::Tx(268435460));
+        assert_eq!(cmd.output(), ".tx 268435460");
+
+        let input = ".tx";
+        command(&input).expect_err("Expected an error");
+
+        let input = ".tx :foo/bar";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Expected a transaction ID, got \":foo/bar\"");
+
+        // `.t` is still `.transact`.
+        let input = ".t [[:db/add 1 :foo/bar 2]]";
+        let cmd = command(&input).expect("Expected transact command");
+        assert_eq!(cmd, Command::Transact("[[:db/add 1 :foo/bar 2]]".to_string()));
+    }
+
+    #[test]
+    fn test_since_parser() {
+        let input = ".since 268435460 ";
+        let cmd = command(&input).expect("Expected since command");
+        assert_eq!(cmd, Command::Since(Basis::Tx(268435460)));
+        assert_eq!(cmd.output(), ".since 268435460");
+    }
+
+    #[test]
+    fn test_now_parser() {
+        let input = ".now";
+        let cmd = command(&input).expect("Expected now command");
+        assert_eq!(cmd, Command::Now);
+
+        let input = ".now 268435460";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), format!("Invalid command {:?}", input));
+    }
+
+    #[test]
+    fn test_starts_command() {
+        assert!(starts_command(".q [:find ?x]"));
+        assert!(starts_command("  .timer on"));
+        assert!(starts_command(".exit"));
+        assert!(!starts_command(".quux"));
+        assert!(!starts_command("[:find ?x]"));
+        assert!(!starts_command(". q"));
+    }
+
+    #[test]
+    fn test_split_trailing_command() {
+        let input = r#".t [[:db/add "a" :foo/bar 1]] .q [:find ?x :where [?x :foo/bar _]]"#;
+        let cmd = command(&input).expect("Expected transact command");
+        assert!(!cmd.is_complete());
+        let (cmd, rest) = cmd.split_trailing_command();
+        assert_eq!(cmd, Command::Transact(r#"[[:db/add "a" :foo/bar 1]]"#.to_string()));
+        assert_eq!(rest, Some(".q [:find ?x :where [?x :foo/bar _]]".to_string()));
+        assert!(cmd.is_complete());
+
+        // A complete command is left alone.
+        let input = ".q [:find ?x :where [?x :foo/bar _]]";
+        let (cmd, rest) = command(&input).expect("Expected query command").split_trailing_command();
+        assert_eq!(cmd, Command::Query("[:find ?x :where [?x :foo/bar _]]".to_string()));
+        assert_eq!(rest, None);
+
+        // So is one that's merely unfinished, even if it mentions something that looks like a command.
+        let input = r#".q [:find ?x :where [?x :foo/bar ".q"]"#;
+        let (cmd, rest) = command(&input).expect("Expected query command").split_trailing_command();
+        assert!(!cmd.is_complete());
+        assert_eq!(rest, None);
+
+        // Only EDN arguments are split.
+        let (cmd, rest) = Command::Timer(true).split_trailing_command();
+        assert_eq!(cmd, Command::Timer(true));
+        assert_eq!(rest, None);
+    }
+
+    #[test]
+    fn test_open_parser_flags() {
+        let input = ".open --read-only my.db";
+        let cmd = command(&input).expect("Expected open command");
+        assert_eq!(cmd, Command::Open("my.db".to_string(), OpenFlags { read_only: true, ..Default::default() }));
+        assert_eq!(cmd.output(), ".open --read-only my.db");
+
+        let input = ".open --existing --encrypted /path/to/my.db";
+        let cmd = command(&input).expect("Expected open command");
+        assert_eq!(cmd, Command::Open("/path/to/my.db".to_string(), OpenFlags { existing: true, encrypted: true, ..Default::default() }));
+        assert_eq!(cmd.output(), ".open --existing --encrypted /path/to/my.db");
+
+        let input = ".open my.db --empty";
+        let cmd = command(&input).expect("Expected open command");
+        assert_eq!(cmd, Command::Open("my.db".to_string(), OpenFlags { empty: true, ..Default::default() }));
+
+        let input = ".open --writable my.db";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Unrecognized flag \"--writable\"");
+
+        let input = ".open --empty --read-only my.db";
+        command(&input).expect_err("Expected an error");
+
+        let input = ".open --read-only";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Missing required argument");
+    }
 }
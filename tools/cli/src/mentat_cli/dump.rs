@@ -0,0 +1,164 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Exporting a store as transactions that rebuild it in a fresh store.
+//!
+//! A fresh store only knows the attributes it was bootstrapped with, so the export is two
+//! transactions: the first asserts every datom of a bootstrapped attribute, like `:db/ident` and
+//! `:db/valueType`, which installs the schema; the second asserts the rest.  Entities are named by
+//! tempids, which preserves the references between them, and by ident in the second transaction
+//! if they have one, since tempids don't span transactions.
+
+use failure::Error;
+
+use edn;
+
+use edn::entities::{
+    EntidOrIdent,
+};
+
+use mentat::{
+    Entid,
+    HasSchema,
+    Keyword,
+    Schema,
+    Store,
+    TypedValue,
+};
+
+use mentat_db::{
+    TypedSQLValue,
+    USER0,
+};
+
+use command_parser::{
+    COMMAND_TRANSACT_LONG,
+};
+
+fn tempid(e: Entid) -> edn::Value {
+    edn::Value::Text(e.to_string())
+}
+
+/// How a transaction names the entity `e`.
+fn entity(schema: &Schema, e: Entid, installing_schema: bool) -> edn::Value {
+    match schema.get_ident(e) {
+        // Bootstrapped entities are already known by their idents.
+        Some(ident) if e < USER0 || !installing_schema => edn::Value::Keyword(ident.clone()),
+        _ => tempid(e),
+    }
+}
+
+/// The transactions, as EDN, that recreate the datoms `store` holds in a fresh store.  There are
+/// at most two: see the module documentation.
+pub fn dump_transactions(store: &Store) -> Result<Vec<edn::Value>, Error> {
+    let schema = store.conn().current_schema();
+    let db_add = edn::Value::Keyword(Keyword::namespaced("db", "add"));
+
+    let mut installs = vec![];
+    let mut assertions = vec![];
+    for datom in store.datoms()? {
+        let (a, ident) = match datom.a {
+            EntidOrIdent::Ident(ident) => match schema.get_entid(&ident) {
+                Some(a) => (a.0, ident),
+                None => continue,
+            },
+            // Every attribute has an ident.
+            EntidOrIdent::Entid(_) => continue,
+        };
+        let installing_schema = a < USER0;
+        let v = match datom.v {
+            TypedValue::Ref(r) => entity(&schema, r, installing_schema),
+            v => v.to_edn_value_pair().0,
+        };
+        let term = edn::Value::Vector(vec![db_add.clone(),
+                                           entity(&schema, datom.e, installing_schema),
+                                           edn::Value::Keyword(ident),
+                                           v]);
+        if installing_schema {
+            installs.push(term);
+        } else {
+            assertions.push(term);
+        }
+    }
+
+    Ok(vec![installs, assertions].into_iter()
+                                 .filter(|terms| !terms.is_empty())
+                                 .map(edn::Value::Vector)
+                                 .collect())
+}
+
+/// A script of `.transact` commands, which `.read` runs, that recreates `store`.
+pub fn dump_script(store: &Store) -> Result<String, Error> {
+    let mut script = String::new();
+    for transaction in dump_transactions(store)? {
+        script.push_str(&format!(".{} {}\n", COMMAND_TRANSACT_LONG, transaction.to_pretty(120)?));
+    }
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mentat::{
+        Queryable,
+    };
+
+    #[test]
+    fn test_dump_round_trip() {
+        let mut store = Store::open("").expect("opened");
+        {
+            let mut in_progress = store.begin_transaction().expect("began");
+            in_progress.transact(r#"[
+                {:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+                {:db/ident :person/bio :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true :db/fulltext true}
+                {:db/ident :person/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/many}
+                {:db/ident :person/mood :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+                {:db/ident :mood/happy}
+            ]"#).expect("transacted schema");
+            in_progress.transact(r#"[
+                {:db/id "a" :person/name "Alice" :person/bio "Likes \"quotes\"" :person/friend "b" :person/mood :mood/happy}
+                {:db/id "b" :person/name "Bob" :person/friend "a"}
+            ]"#).expect("transacted data");
+            in_progress.commit().expect("committed");
+        }
+
+        let transactions = dump_transactions(&store).expect("dumped");
+        assert_eq!(transactions.len(), 2);
+
+        let mut fresh = Store::open("").expect("opened");
+        {
+            let mut in_progress = fresh.begin_transaction().expect("began");
+            for transaction in transactions {
+                in_progress.transact(transaction.to_string()).expect("transacted dump");
+            }
+            in_progress.commit().expect("committed");
+        }
+
+        let friends = r#"[:find ?name ?friend
+                          :where [?p :person/name ?name]
+                                 [?p :person/friend ?f]
+                                 [?f :person/name ?friend]
+                          :order ?name]"#;
+        assert_eq!(store.q_once(friends, None).expect("queried").results,
+                   fresh.q_once(friends, None).expect("queried").results);
+
+        let bio = r#"[:find ?bio . :where [?p :person/name "Alice"] [?p :person/bio ?bio]]"#;
+        assert_eq!(fresh.q_once(bio, None).expect("queried").into_scalar().expect("scalar"),
+                   Some(TypedValue::typed_string("Likes \"quotes\"").into()));
+
+        let mood = r#"[:find ?mood . :where [?p :person/name "Alice"] [?p :person/mood ?m] [?m :db/ident ?mood]]"#;
+        assert_eq!(fresh.q_once(mood, None).expect("queried").into_scalar().expect("scalar"),
+                   Some(Keyword::namespaced("mood", "happy").into()));
+
+        // The schema survives, too.
+        assert_eq!(store.attributes(), fresh.attributes());
+    }
+}
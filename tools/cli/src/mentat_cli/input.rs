@@ -8,12 +8,26 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::collections::VecDeque;
+
+use std::fs::File;
+
 use std::io::{
+    BufRead,
+    BufReader,
+    BufWriter,
+    Read,
     stdin,
     stdout,
     Write,
 };
 
+use std::path::Path;
+
+use std::process;
+
+use tempfile::NamedTempFile;
+
 use linefeed::{
     DefaultTerminal,
     Interface,
@@ -30,16 +44,22 @@ use self::InputResult::*;
 use command_parser::{
     Command,
     command,
+    starts_command,
 };
 
 use failure::Error;
 
+use CliError;
+
 /// Starting prompt
 const DEFAULT_PROMPT: &'static str = "mentat=> ";
 /// Prompt when further input is being read
 // TODO: Should this actually reflect the current open brace?
 const MORE_PROMPT: &'static str = "mentat.> ";
 
+/// Used by `.edit` when neither `$VISUAL` nor `$EDITOR` is set.
+const DEFAULT_EDITOR: &'static str = "vi";
+
 /// Possible results from reading input from `InputReader`
 #[derive(Clone, Debug)]
 pub enum InputResult {
@@ -58,6 +78,15 @@ pub struct InputReader {
     buffer: String,
     interface: Option<Interface<DefaultTerminal>>,
     in_process_cmd: Option<Command>,
+    /// Shown in the prompt when the session is pinned to a point in history, like `as-of 1234`.
+    basis: Option<String>,
+    /// Input that we've already read but not yet handled, like the rest of a paste that held
+    /// several commands.  We handle it before reading any more.
+    pending: VecDeque<String>,
+    /// The most recent input other than `.edit`, which `.edit` starts from.
+    last_entry: Option<String>,
+    /// Whether we're reading a script, rather than `stdin`.  A script ends when `pending` is empty.
+    script: bool,
 }
 
 enum UserAction {
@@ -76,11 +105,13 @@ enum UserAction {
 impl InputReader {
     /// Constructs a new `InputReader` reading from `stdin`.
     pub fn new(interface: Option<Interface<DefaultTerminal>>) -> InputReader {
+        let mut last_entry = None;
         if let Some(ref interface) = interface {
             // It's fine to fail to load history.
             let p = ::history_file_path();
-            let loaded = interface.load_history(&p);
+            let loaded = load_history(interface, &p);
             debug!("history read from {}: {}", p.display(), loaded.is_ok());
+            last_entry = loaded.ok().and_then(|entry| entry);
 
             let mut r = interface.lock_reader();
             // Handle SIGINT (Ctrl-C)
@@ -92,36 +123,101 @@ impl InputReader {
             buffer: String::new(),
             interface,
             in_process_cmd: None,
+            basis: None,
+            pending: VecDeque::new(),
+            last_entry,
+            script: false,
         }
     }
 
+    /// Constructs a new `InputReader` reading the lines of `script`.
+    pub fn with_script(script: &str) -> InputReader {
+        let mut reader = InputReader::new(None);
+        reader.pending.extend(script.lines().map(|line| line.to_string()));
+        reader.script = true;
+        reader
+    }
+
+    /// Returns whether the `InputReader` is reading a script.
+    pub fn is_script(&self) -> bool {
+        self.script
+    }
+
     /// Returns whether the `InputReader` is reading from a TTY.
     pub fn is_tty(&self) -> bool {
         self.interface.is_some()
     }
 
+    /// Sets the basis shown in the prompt, or clears it.
+    pub fn set_basis(&mut self, basis: Option<String>) {
+        self.basis = basis;
+    }
+
     /// Reads a single command, item, or statement from `stdin`.
     /// Returns `More` if further input is required for a complete result.
     /// In this case, the input received so far is buffered internally.
+    ///
+    /// A line can hold more than one command, and a command can follow an incomplete one;
+    /// each is returned by its own call, in order, so that an error in one doesn't lose those
+    /// after it.
     pub fn read_input(&mut self) -> Result<InputResult, Error> {
+        let line = match self.pending.pop_front() {
+            Some(line) => line,
+            None if self.script => {
+                // A script that ends partway through a command is an error.
+                if self.in_process_cmd.take().is_some() {
+                    let entry = self.buffer.clone();
+                    self.buffer.clear();
+                    bail!(CliError::CommandParse(format!("Incomplete command {:?}", entry)));
+                }
+                return Ok(Eof);
+            },
+            None => match self.prompt_for_line() {
+                Some(line) => line,
+                None => return Ok(Eof),
+            },
+        };
+
+        // A new command abandons an incomplete one: report it, and then handle the new command
+        // next time around.
+        if self.in_process_cmd.is_some() && starts_command(&line) {
+            let entry = self.buffer.clone();
+            self.buffer.clear();
+            self.add_history(entry.clone());
+            self.in_process_cmd = None;
+            self.pending.push_front(line);
+            bail!(CliError::CommandParse(format!("Incomplete command {:?}", entry)));
+        }
+
+        self.handle_line(line)
+    }
+
+    /// Prompts for and reads a line, or returns `None` at the end of input.
+    fn prompt_for_line(&mut self) -> Option<String> {
         let prompt = if self.in_process_cmd.is_some() { MORE_PROMPT } else { DEFAULT_PROMPT };
+        let prompt = match self.basis {
+            Some(ref basis) => prompt.replacen("mentat", &format!("mentat({})", basis), 1),
+            None => prompt.to_string(),
+        };
         let prompt = format!("{blue}{prompt}{reset}",
                              blue = color::Fg(::BLUE),
                              prompt = prompt,
                              reset = color::Fg(color::Reset));
-        let line = match self.read_line(prompt.as_str()) {
-            UserAction::TextInput(s) => s,
+        match self.read_line(prompt.as_str()) {
+            UserAction::TextInput(s) => Some(s),
             UserAction::Interrupt if self.in_process_cmd.is_some() => {
                 self.in_process_cmd = None;
                 self.buffer.clear();
                 // Move to the next line, so that our next prompt isn't on top
                 // of the previous.
                 println!();
-                String::new()
+                Some(String::new())
             },
-            _ => return Ok(Eof),
-        };
+            _ => None,
+        }
+    }
 
+    fn handle_line(&mut self, line: String) -> Result<InputResult, Error> {
         if !self.buffer.is_empty() {
             self.buffer.push('\n');
         }
@@ -152,6 +248,21 @@ impl InputReader {
             },
         };
 
+        // Anything after a complete command is another command, which we'll handle next.
+        let cmd = cmd.map(|cmd| {
+            let (cmd, rest) = cmd.split_trailing_command();
+            if let Some(rest) = rest {
+                if self.buffer.ends_with(&rest) {
+                    let len = self.buffer.len() - rest.len();
+                    self.buffer.truncate(len);
+                    let len = self.buffer.trim_right().len();
+                    self.buffer.truncate(len);
+                }
+                self.pending.push_front(rest);
+            }
+            cmd
+        });
+
         match cmd {
             Ok(cmd) => {
                 match cmd {
@@ -222,9 +333,17 @@ impl InputReader {
         }
     }
 
-    fn add_history(&self, line: String) {
+    /// Records a complete entry, which can span several lines, as a single history item so that
+    /// it's recalled as a whole.
+    fn add_history(&mut self, entry: String) {
+        if entry.trim().is_empty() {
+            return;
+        }
+        if command(&entry).ok() != Some(Command::Edit) {
+            self.last_entry = Some(entry.clone());
+        }
         if let Some(ref interface) = self.interface {
-            interface.add_history(line);
+            interface.add_history(entry);
         }
         self.save_history();
     }
@@ -233,8 +352,189 @@ impl InputReader {
         if let Some(ref interface) = self.interface {
             let p = ::history_file_path();
             // It's okay to fail to save history.
-            let saved = interface.save_history(&p);
+            let saved = save_history(interface, &p);
             debug!("history saved to {}: {}", p.display(), saved.is_ok());
         }
     }
+
+    /// Opens `$VISUAL` or `$EDITOR` on the most recent input, and queues whatever is saved to be
+    /// read as the next input.
+    pub fn edit(&mut self) -> Result<(), Error> {
+        let mut file = NamedTempFile::new()?;
+        if let Some(ref entry) = self.last_entry {
+            file.write_all(entry.as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+
+        let editor = ::std::env::var("VISUAL")
+                         .or_else(|_| ::std::env::var("EDITOR"))
+                         .unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+        let mut words = editor.split_whitespace();
+        let program = words.next().unwrap_or(DEFAULT_EDITOR);
+        let status = process::Command::new(program)
+                                      .args(words)
+                                      .arg(file.path())
+                                      .status()
+                                      .map_err(|e| CliError::CommandParse(format!("Couldn't run editor {:?}: {}", editor, e)))?;
+        if !status.success() {
+            bail!(CliError::CommandParse(format!("Editor {:?} exited with {}", editor, status)));
+        }
+
+        let mut content = String::new();
+        File::open(file.path())?.read_to_string(&mut content)?;
+        let content = content.trim();
+        if !content.is_empty() {
+            self.pending.push_back(content.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// History entries can span several lines, so each is stored on one line of the history file with
+/// its newlines, and the backslashes that escape them, escaped.
+fn escape_history_entry(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape_history_entry(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('\\')) => { chars.next(); entry.push('\\'); },
+            ('\\', Some('n')) => { chars.next(); entry.push('\n'); },
+            (c, _) => entry.push(c),
+        }
+    }
+    entry
+}
+
+/// Loads history from `path`, returning its most recent entry other than `.edit`.
+fn load_history(interface: &Interface<DefaultTerminal>, path: &Path) -> Result<Option<String>, Error> {
+    let mut last = None;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let entry = unescape_history_entry(&line?);
+        if command(&entry).ok() != Some(Command::Edit) {
+            last = Some(entry.clone());
+        }
+        interface.add_history(entry);
+    }
+    Ok(last)
+}
+
+fn save_history(interface: &Interface<DefaultTerminal>, path: &Path) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for entry in interface.lock_writer_append()?.history() {
+        writeln!(writer, "{}", escape_history_entry(entry))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader_with_input(lines: &[&str]) -> InputReader {
+        let mut reader = InputReader::new(None);
+        reader.pending.extend(lines.iter().map(|line| line.to_string()));
+        reader
+    }
+
+    fn expect_command(reader: &mut InputReader) -> Command {
+        match reader.read_input() {
+            Ok(MetaCommand(cmd)) => cmd,
+            other => panic!("Expected a command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_history_entry_escaping() {
+        let entries = [".q [:find ?x\n    :where [?x :foo/bar \"a\\\"b\\\\n\"]]",
+                       ".t [[:db/add \"a\" :foo/bar 1]]",
+                       "\\\\n\n\\"];
+        for entry in entries.iter() {
+            let escaped = escape_history_entry(entry);
+            assert!(!escaped.contains('\n'));
+            assert_eq!(unescape_history_entry(&escaped), entry.to_string());
+        }
+
+        // History written before entries were escaped reads back unchanged.
+        assert_eq!(unescape_history_entry(r#".q [:find ?x :where [?x :foo/bar "a\"b"]]"#),
+                   r#".q [:find ?x :where [?x :foo/bar "a\"b"]]"#);
+    }
+
+    #[test]
+    fn test_multi_line_entry_is_one_command() {
+        let mut reader = reader_with_input(&[".q [:find ?x\n :where [?x :foo/bar _]]"]);
+        assert_eq!(expect_command(&mut reader), Command::Query("[:find ?x\n :where [?x :foo/bar _]]".to_string()));
+        assert_eq!(reader.last_entry, Some(".q [:find ?x\n :where [?x :foo/bar _]]".to_string()));
+
+        // `.edit` starts from the last thing entered, not from itself.
+        reader.pending.push_back(".edit".to_string());
+        assert_eq!(expect_command(&mut reader), Command::Edit);
+        assert_eq!(reader.last_entry, Some(".q [:find ?x\n :where [?x :foo/bar _]]".to_string()));
+    }
+
+    #[test]
+    fn test_script() {
+        let mut reader = InputReader::with_script(".t [[:db/add \"a\" :foo/bar 1]\n]\n\n.q [:find ?x\n");
+        match reader.read_input() {
+            Ok(More) => {},
+            other => panic!("Expected more input, got {:?}", other),
+        }
+        assert_eq!(expect_command(&mut reader), Command::Transact("[[:db/add \"a\" :foo/bar 1]\n]".to_string()));
+        match reader.read_input() {
+            Ok(Empty) => {},
+            other => panic!("Expected an empty line, got {:?}", other),
+        }
+        match reader.read_input() {
+            Ok(More) => {},
+            other => panic!("Expected more input, got {:?}", other),
+        }
+
+        // The script ends in the middle of a query.
+        let err = reader.read_input().expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Incomplete command \".q [:find ?x\"");
+        match reader.read_input() {
+            Ok(Eof) => {},
+            other => panic!("Expected the end of the script, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_several_commands_on_one_line() {
+        let mut reader = reader_with_input(&[r#".t [[:db/add "a" :foo/bar 1]] .q [:find ?x :where [?x :foo/bar _]] .timer on"#]);
+        assert_eq!(expect_command(&mut reader), Command::Transact(r#"[[:db/add "a" :foo/bar 1]]"#.to_string()));
+        assert_eq!(expect_command(&mut reader), Command::Query("[:find ?x :where [?x :foo/bar _]]".to_string()));
+        assert_eq!(expect_command(&mut reader), Command::Timer(true));
+        assert!(reader.pending.is_empty());
+    }
+
+    #[test]
+    fn test_command_after_incomplete_command() {
+        let mut reader = reader_with_input(&[".q [:find ?x",
+                                             ".t [[:db/add \"a\" :foo/bar 1]",
+                                             "]",
+                                             ".bogus",
+                                             ".now"]);
+        match reader.read_input() {
+            Ok(More) => {},
+            other => panic!("Expected more input, got {:?}", other),
+        }
+
+        // The incomplete query is reported, and the transaction that follows it survives.
+        let err = reader.read_input().expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Incomplete command \".q [:find ?x\"");
+        match reader.read_input() {
+            Ok(More) => {},
+            other => panic!("Expected more input, got {:?}", other),
+        }
+        assert_eq!(expect_command(&mut reader), Command::Transact("[[:db/add \"a\" :foo/bar 1]\n]".to_string()));
+
+        // So does everything after an invalid command.
+        reader.read_input().expect_err("Expected an error");
+        assert_eq!(expect_command(&mut reader), Command::Now);
+    }
 }
@@ -10,6 +10,8 @@
 
 #![crate_name = "mentat_cli"]
 
+use std::io::Read;
+
 use std::path::{
     PathBuf,
 };
@@ -26,6 +28,7 @@ extern crate getopts;
 extern crate linefeed;
 extern crate rusqlite;
 extern crate tabwriter;
+extern crate tempfile;
 extern crate termion;
 extern crate time;
 
@@ -43,7 +46,8 @@ use termion::{
 
 static HISTORY_FILE_PATH: &str = ".mentat_history";
 
-/// The Mentat CLI stores input history in a readline-compatible file like "~/.mentat_history".
+/// The Mentat CLI stores input history in a file like "~/.mentat_history", one entry per line, with
+/// the newlines in multi-line entries escaped.
 /// This accords with main other tools which prefix with "." and suffix with "_history": lein,
 /// node_repl, python, and sqlite, at least.
 pub(crate) fn history_file_path() -> PathBuf {
@@ -56,13 +60,18 @@ static BLUE: color::Rgb = color::Rgb(0x99, 0xaa, 0xFF);
 static GREEN: color::Rgb = color::Rgb(0x77, 0xFF, 0x99);
 
 pub mod command_parser;
+pub mod dump;
 pub mod input;
+pub mod output;
 pub mod repl;
 
 #[derive(Debug, Fail)]
 pub enum CliError {
     #[fail(display = "{}", _0)]
     CommandParse(String),
+
+    #[fail(display = "invalid vocabulary: {}", _0)]
+    InvalidVocabulary(String),
 }
 
 pub fn run() -> i32 {
@@ -79,6 +88,10 @@ pub fn run() -> i32 {
     opts.optmulti("q", "query", "Execute a query on startup. Queries are executed after any transacts.", "QUERY");
     opts.optmulti("t", "transact", "Execute a transact on startup. Transacts are executed before queries.", "TRANSACT");
     opts.optmulti("i", "import", "Execute an import on startup. Imports are executed before queries.", "PATH");
+    opts.optopt("", "format", "How to print query results: table (the default), edn, json, or csv", "FORMAT");
+    opts.optopt("f", "file", "Run the commands in a file, after any startup commands, and exit", "PATH");
+    opts.optflag("", "quiet", "Don't echo the commands being run, or report opening and closing databases");
+    opts.optflag("", "stop-on-error", "Stop at the first command that fails. The exit status is non-zero if any command fails");
     opts.optflag("v", "version", "Print version and exit");
     opts.optflag("", "no-tty", "Don't try to use a TTY for readline-like input processing");
 
@@ -106,16 +119,27 @@ pub fn run() -> i32 {
         false => None,
     };
 
+    let format = match matches.opt_str("format") {
+        None => None,
+        Some(name) => match command_parser::OutputFormat::from_name(&name) {
+            Some(format) => Some(command_parser::Command::Format(format)),
+            None => {
+                println!("{}: unrecognized format {:?}; expected table, edn, json, or csv", args[0], name);
+                return 1;
+            },
+        },
+    };
+
     let mut last_arg: Option<&str> = None;
 
-    let cmds:Vec<command_parser::Command> = args.iter().filter_map(|arg| {
+    let cmds:Vec<command_parser::Command> = format.into_iter().chain(args.iter().filter_map(|arg| {
         match last_arg {
             Some("-d") => {
                 last_arg = None;
                 if let &Some(ref k) = &key {
                     Some(command_parser::Command::OpenEncrypted(arg.clone(), k.clone()))
                 } else {
-                    Some(command_parser::Command::Open(arg.clone()))
+                    Some(command_parser::Command::Open(arg.clone(), Default::default()))
                 }
             },
             Some("-q") => {
@@ -136,19 +160,35 @@ pub fn run() -> i32 {
                 None
             },
         }
-    }).collect();
+    })).collect();
+
+    let repl = match matches.opt_str("file") {
+        Some(path) => {
+            let mut script = String::new();
+            if let Err(e) = std::fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut script)) {
+                eprintln!("Error reading file {}: {}", path, e);
+                return 1;
+            }
+            repl::Repl::with_script(&script)
+        },
+        None => repl::Repl::new(!matches.opt_present("no-tty")),
+    };
 
-    let mut repl = match repl::Repl::new(!matches.opt_present("no-tty")) {
+    let mut repl = match repl {
         Ok(repl) => repl,
         Err(e) => {
             println!("{}", e);
             return 1
         }
     };
+    repl.set_quiet(matches.opt_present("quiet"));
+    repl.set_stop_on_error(matches.opt_present("stop-on-error"));
 
-    repl.run(Some(cmds));
-
-    0
+    if repl.run(Some(cmds)) {
+        0
+    } else {
+        1
+    }
 }
 
 /// Returns a version string.
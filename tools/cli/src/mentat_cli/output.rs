@@ -0,0 +1,188 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Rendering query results as EDN, JSON, or CSV, for consumption by other tools.
+//!
+//! Results are first converted to EDN, which preserves every value's type; JSON and CSV are then
+//! rendered from that.  Instants and UUIDs, which neither JSON nor CSV can represent, are rendered
+//! as RFC 3339 and hyphenated strings respectively, and keywords as strings like `":foo/bar"`.
+
+use std::io::Write;
+
+use failure::Error;
+
+use edn;
+
+use core_traits::{
+    Binding,
+};
+
+use mentat::{
+    QueryOutput,
+    QueryResults,
+    binding_to_edn,
+};
+
+fn write_json_string<W: Write>(out: &mut W, s: &str) -> Result<(), Error> {
+    write!(out, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")?;
+    Ok(())
+}
+
+fn write_json_array<'a, W, I>(out: &mut W, values: I) -> Result<(), Error>
+where W: Write, I: IntoIterator<Item=&'a edn::Value> {
+    write!(out, "[")?;
+    for (i, v) in values.into_iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write_json(out, v)?;
+    }
+    write!(out, "]")?;
+    Ok(())
+}
+
+pub fn write_json<W: Write>(out: &mut W, value: &edn::Value) -> Result<(), Error> {
+    match value {
+        &edn::Value::Nil => write!(out, "null")?,
+        &edn::Value::Boolean(b) => write!(out, "{}", b)?,
+        &edn::Value::Integer(i) => write!(out, "{}", i)?,
+        &edn::Value::BigInteger(ref i) => write!(out, "{}", i)?,
+        // JSON has no representation for NaN or the infinities.
+        &edn::Value::Float(f) if !f.into_inner().is_finite() => write!(out, "null")?,
+        &edn::Value::Float(f) => write!(out, "{:?}", f.into_inner())?,
+        &edn::Value::Text(ref s) => write_json_string(out, s)?,
+        &edn::Value::Instant(ref i) => write_json_string(out, &i.to_rfc3339())?,
+        &edn::Value::Uuid(ref u) => write_json_string(out, &u.hyphenated().to_string())?,
+        &edn::Value::Vector(ref vs) => write_json_array(out, vs)?,
+        &edn::Value::List(ref vs) => write_json_array(out, vs)?,
+        &edn::Value::Set(ref vs) => write_json_array(out, vs)?,
+        &edn::Value::Map(ref m) => {
+            write!(out, "{{")?;
+            for (i, (k, v)) in m.iter().enumerate() {
+                if i > 0 {
+                    write!(out, ",")?;
+                }
+                match k {
+                    &edn::Value::Text(ref s) => write_json_string(out, s)?,
+                    k => write_json_string(out, &k.to_string())?,
+                }
+                write!(out, ":")?;
+                write_json(out, v)?;
+            }
+            write!(out, "}}")?;
+        },
+        v => write_json_string(out, &v.to_string())?,
+    }
+    Ok(())
+}
+
+/// A value as it appears in a CSV cell: strings are written as they are, and anything structured,
+/// like a pull result, as EDN.
+fn csv_cell(value: &edn::Value) -> String {
+    match value {
+        &edn::Value::Nil => "".to_string(),
+        &edn::Value::Text(ref s) => s.clone(),
+        &edn::Value::Instant(ref i) => i.to_rfc3339(),
+        &edn::Value::Uuid(ref u) => u.hyphenated().to_string(),
+        v => v.to_string(),
+    }
+}
+
+/// Quote a field if it contains a delimiter, a quote, or a line break, as RFC 4180 describes.
+fn csv_escape(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn write_csv_row<W: Write>(out: &mut W, cells: Vec<String>) -> Result<(), Error> {
+    let cells: Vec<String> = cells.iter().map(|c| csv_escape(c)).collect();
+    write!(out, "{}\r\n", cells.join(","))?;
+    Ok(())
+}
+
+/// A header row naming the find spec's elements, then a row for each result.
+pub fn write_csv<W: Write>(out: &mut W, output: QueryOutput) -> Result<(), Error> {
+    write_csv_row(out, output.spec.columns().map(|e| e.to_string()).collect())?;
+    let cells = |bindings: &[Binding]| -> Vec<String> { bindings.iter().map(|b| csv_cell(&binding_to_edn(b))).collect() };
+    match output.results {
+        QueryResults::Scalar(v) => {
+            if let Some(v) = v {
+                write_csv_row(out, cells(&[v]))?;
+            }
+        },
+        QueryResults::Tuple(vs) => {
+            if let Some(vs) = vs {
+                write_csv_row(out, cells(&vs[..]))?;
+            }
+        },
+        QueryResults::Coll(vs) => {
+            for v in vs {
+                write_csv_row(out, cells(&[v]))?;
+            }
+        },
+        QueryResults::Rel(rows) => {
+            for row in rows {
+                write_csv_row(out, cells(&row[..]))?;
+            }
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(value: &edn::Value) -> String {
+        let mut out = vec![];
+        write_json(&mut out, value).expect("to write JSON");
+        String::from_utf8(out).expect("UTF-8")
+    }
+
+    #[test]
+    fn test_json() {
+        let value = edn::parse::value(r#"[1 2.5 "a \"quoted\"\nline" :foo/bar nil true
+                                          #inst "2018-01-01T00:00:00Z"
+                                          #uuid "550e8400-e29b-41d4-a716-446655440000"
+                                          {:foo/baz [1]}]"#).expect("to parse").without_spans();
+        assert_eq!(json(&value),
+                   r#"[1,2.5,"a \"quoted\"\nline",":foo/bar",null,true,"2018-01-01T00:00:00+00:00","550e8400-e29b-41d4-a716-446655440000",{":foo/baz":[1]}]"#);
+    }
+
+    #[test]
+    fn test_csv_escape() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_csv_cell() {
+        assert_eq!(csv_cell(&edn::Value::Text("x".to_string())), "x");
+        assert_eq!(csv_cell(&edn::Value::Nil), "");
+        assert_eq!(csv_cell(&edn::Value::Integer(3)), "3");
+    }
+}
@@ -8,6 +8,10 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::cell::Cell;
+
+use std::fmt;
+
 use std::io::Write;
 
 use failure::{
@@ -25,48 +29,86 @@ use termion::{
     style,
 };
 
+use termion::input::{
+    TermRead,
+};
+
 use time::{
     Duration,
     PreciseTime,
 };
 
 use core_traits::{
+    Attribute,
     StructuredMap,
+    ValueType,
+};
+
+use core_traits::attribute::{
+    Unique,
+};
+
+use core_traits::values;
+
+use edn::entities::{
+    EntidOrIdent,
 };
 
 use mentat::{
     Binding,
     CacheDirection,
+    DatabaseView,
+    Datom,
+    Entid,
+    HasSchema,
     Keyword,
     QueryExplanation,
     QueryOutput,
     QueryResults,
     Queryable,
+    Schema,
     Store,
+    StoreOptions,
     TxReport,
     TypedValue,
 };
 
+use CliError;
+
 use command_parser::{
+    Basis,
     Command,
+    OpenFlags,
+    OutputFormat,
 };
 
 use command_parser::{
+    COMMAND_AS_OF,
     COMMAND_CACHE,
+    COMMAND_CACHED,
+    COMMAND_DUMP,
+    COMMAND_EDIT,
     COMMAND_EXIT_LONG,
     COMMAND_EXIT_SHORT,
+    COMMAND_FORMAT,
     COMMAND_HELP,
     COMMAND_IMPORT_LONG,
+    COMMAND_NOW,
     COMMAND_OPEN,
     COMMAND_QUERY_LONG,
     COMMAND_QUERY_SHORT,
     COMMAND_QUERY_EXPLAIN_LONG,
     COMMAND_QUERY_EXPLAIN_SHORT,
     COMMAND_QUERY_PREPARED_LONG,
+    COMMAND_READ,
     COMMAND_SCHEMA,
+    COMMAND_SINCE,
+    COMMAND_STATS,
     COMMAND_TIMER_LONG,
     COMMAND_TRANSACT_LONG,
     COMMAND_TRANSACT_SHORT,
+    COMMAND_TX,
+    COMMAND_UNCACHE,
 };
 
 // These are still defined when this feature is disabled (so that we can
@@ -76,6 +118,7 @@ use command_parser::{
 #[cfg(feature = "sqlcipher")]
 use command_parser::{
     COMMAND_OPEN_ENCRYPTED,
+    COMMAND_REKEY,
 };
 
 #[cfg(feature = "syncable")]
@@ -83,6 +126,16 @@ use command_parser::{
     COMMAND_SYNC,
 };
 
+#[cfg(feature = "syncable")]
+use mentat::{
+    SyncOptions,
+    SyncProgress,
+};
+
+use dump;
+
+use output;
+
 use input::InputReader;
 use input::InputResult::{
     Empty,
@@ -99,14 +152,19 @@ lazy_static! {
             (COMMAND_EXIT_LONG, "Close the current database and exit the REPL."),
             (COMMAND_EXIT_SHORT, "Shortcut for `.exit`. Close the current database and exit the REPL."),
 
-            (COMMAND_OPEN, "Open a database at path."),
+            (COMMAND_OPEN, "Open a database at path. Usage: `.open [--read-only] [--existing] [--empty] [--encrypted] path`; `--existing` refuses to create the database, `--empty` refuses to open one that already exists, and `--encrypted` prompts for the key."),
 
             #[cfg(feature = "sqlcipher")]
             (COMMAND_OPEN_ENCRYPTED, "Open an encrypted database at path using the provided key."),
 
-            (COMMAND_SCHEMA, "Output the schema for the current open database."),
+            #[cfg(feature = "sqlcipher")]
+            (COMMAND_REKEY, "Prompt for a new key for the current open encrypted database, which must be opened with that key from then on."),
+
+            (COMMAND_SCHEMA, "Output the schema for the current open database. Usage: `.schema` for every attribute, `.schema foo` for the `:foo/*` attributes, or `.schema --diff vocabulary.edn` to list the attributes in a file that are missing from or conflict with the database."),
 
             (COMMAND_IMPORT_LONG, "Transact the contents of a file against the current open database."),
+            (COMMAND_READ, "Run the commands in a file, such as one written by `.dump`. Usage: `.read backup.mentat`"),
+            (COMMAND_DUMP, "Write the schema and every datom of the current open database to a file, as transactions that `.read` replays into a fresh database. Usage: `.dump backup.mentat`"),
 
             (COMMAND_QUERY_LONG, "Execute a query against the current open database."),
             (COMMAND_QUERY_SHORT, "Shortcut for `.query`. Execute a query against the current open database."),
@@ -119,9 +177,23 @@ lazy_static! {
             (COMMAND_QUERY_EXPLAIN_LONG, "Show the SQL and query plan that would be executed for a given query."),
             (COMMAND_QUERY_EXPLAIN_SHORT, "Shortcut for `.explain_query`. Show the SQL and query plan that would be executed for a given query."),
 
+            (COMMAND_EDIT, "Compose a query or transaction in $VISUAL or $EDITOR, starting from the previous input. What's saved is run when the editor exits."),
+
+            (COMMAND_FORMAT, "Set how query results are printed: `table`, `edn`, `json`, or `csv`. Usage: `.format json`"),
+
             (COMMAND_TIMER_LONG, "Enable or disable timing of query and transact operations."),
 
+            (COMMAND_TX, "Show the datoms asserted and retracted by a transaction. Usage: `.tx 268435460`"),
+
+            (COMMAND_AS_OF, "Query the database as it was at a transaction or instant. Usage: `.as-of 268435460` or `.as-of #inst \"2018-01-01T00:00:00Z\"`"),
+            (COMMAND_SINCE, "Query only what was transacted after a transaction or instant. Usage: `.since 268435460`"),
+            (COMMAND_NOW, "Stop querying a point in history set by `.as-of` or `.since`."),
+
             (COMMAND_CACHE, "Cache an attribute. Usage: `.cache :foo/bar reverse`"),
+            (COMMAND_UNCACHE, "Stop caching an attribute. Usage: `.uncache :foo/bar`"),
+            (COMMAND_CACHED, "List the cached attributes, and the direction in which each is cached."),
+
+            (COMMAND_STATS, "Show the number of datoms for each attribute, the size of the fulltext values, and how much of the database file is free."),
 
             #[cfg(feature = "syncable")]
             (COMMAND_SYNC, "Synchronize the database against a Mentat Sync Server URL for a provided user UUID."),
@@ -144,6 +216,67 @@ fn parse_namespaced_keyword(input: &str) -> Option<Keyword> {
     }
 }
 
+/// Parse attribute definitions, like those output by `.schema` or transacted to install a
+/// vocabulary: a vector or list of maps, each with a `:db/ident` and a `:db/valueType`.
+/// `:db/uniqueWith` partners are looked up in `schema`.
+fn parse_vocabulary(input: &str, schema: &Schema) -> Result<Vec<(Keyword, Attribute)>, Error> {
+    let value = edn::parse::value(input).map_err(|e| CliError::InvalidVocabulary(e.to_string()))?.without_spans();
+    let maps = match value {
+        edn::Value::Vector(maps) => maps,
+        edn::Value::List(maps) => maps.into_iter().collect(),
+        _ => bail!(CliError::InvalidVocabulary("expected a vector of attribute maps".to_string())),
+    };
+
+    let mut attributes = Vec::with_capacity(maps.len());
+    for map in maps {
+        let map = match map {
+            edn::Value::Map(map) => map,
+            other => bail!(CliError::InvalidVocabulary(format!("expected an attribute map, got {}", other))),
+        };
+        let ident = match map.get(&*values::DB_IDENT) {
+            Some(&edn::Value::Keyword(ref ident)) => ident.clone(),
+            _ => bail!(CliError::InvalidVocabulary("attribute map has no :db/ident".to_string())),
+        };
+        let value_type = match map.get(&*values::DB_VALUE_TYPE) {
+            Some(&edn::Value::Keyword(ref t)) => ValueType::from_keyword(t),
+            _ => None,
+        };
+        let value_type = match value_type {
+            Some(t) => t,
+            None => bail!(CliError::InvalidVocabulary(format!("{} has no valid :db/valueType", ident))),
+        };
+        let flag = |attr: &edn::Value| map.get(attr) == Some(&edn::Value::Boolean(true));
+        let unique = match map.get(&*values::DB_UNIQUE) {
+            None => None,
+            Some(v) if *v == *values::DB_UNIQUE_IDENTITY => Some(Unique::Identity),
+            Some(v) if *v == *values::DB_UNIQUE_VALUE => Some(Unique::Value),
+            Some(v) => bail!(CliError::InvalidVocabulary(format!("{} has invalid :db/unique {}", ident, v))),
+        };
+        let unique_with = match map.get(&*values::DB_UNIQUE_WITH) {
+            None => None,
+            Some(&edn::Value::Keyword(ref partner)) => match schema.get_entid(partner) {
+                Some(entid) => Some(entid.0),
+                None => bail!(CliError::InvalidVocabulary(format!("{} has unknown :db/uniqueWith {}", ident, partner))),
+            },
+            Some(v) => bail!(CliError::InvalidVocabulary(format!("{} has invalid :db/uniqueWith {}", ident, v))),
+        };
+        let attribute = Attribute {
+            value_type,
+            multival: map.get(&*values::DB_CARDINALITY) == Some(&*values::DB_CARDINALITY_MANY),
+            unique,
+            index: flag(&*values::DB_INDEX),
+            fulltext: flag(&*values::DB_FULLTEXT),
+            component: flag(&*values::DB_IS_COMPONENT),
+            no_history: flag(&*values::DB_NO_HISTORY),
+            normalize: flag(&*values::DB_NORMALIZE),
+            case_insensitive: flag(&*values::DB_CASE_INSENSITIVE),
+            unique_with,
+        };
+        attributes.push((ident, attribute));
+    }
+    Ok(attributes)
+}
+
 fn format_time(duration: Duration) {
     let m_nanos = duration.num_nanoseconds();
     if let Some(nanos) = m_nanos {
@@ -185,12 +318,37 @@ fn format_time(duration: Duration) {
               reset = style::Reset);
 }
 
+/// The point in history that a session's queries are pinned to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum SessionBasis {
+    AsOf(Basis),
+    Since(Basis),
+}
+
+impl SessionBasis {
+    fn prompt(&self) -> String {
+        match self {
+            &SessionBasis::AsOf(ref basis) => format!("{} {}", COMMAND_AS_OF, basis),
+            &SessionBasis::Since(ref basis) => format!("{} {}", COMMAND_SINCE, basis),
+        }
+    }
+}
+
 /// Executes input and maintains state of persistent items.
 pub struct Repl {
     input_reader: InputReader,
     path: String,
     store: Store,
     timer_on: bool,
+    basis: Option<SessionBasis>,
+    format: OutputFormat,
+    /// Don't echo commands read from a script or startup options, or report opening and closing
+    /// databases.
+    quiet: bool,
+    /// Stop running commands once one has failed.
+    stop_on_error: bool,
+    /// Whether any command has failed.  A `Cell`, since most commands only read the REPL's state.
+    failed: Cell<bool>,
 }
 
 impl Repl {
@@ -210,32 +368,78 @@ impl Repl {
             None
         };
 
-        let input_reader = InputReader::new(interface);
+        Repl::with_input_reader(InputReader::new(interface))
+    }
 
+    /// Constructs a new `Repl` that runs the commands in `script`, rather than reading them from
+    /// `stdin`.
+    pub fn with_script(script: &str) -> Result<Repl, String> {
+        Repl::with_input_reader(InputReader::with_script(script))
+    }
+
+    fn with_input_reader(input_reader: InputReader) -> Result<Repl, String> {
         let store = Store::open("").map_err(|e| e.to_string())?;
         Ok(Repl {
             input_reader,
             path: "".to_string(),
             store,
             timer_on: false,
+            basis: None,
+            format: OutputFormat::default(),
+            quiet: false,
+            stop_on_error: false,
+            failed: Cell::new(false),
         })
     }
 
-    /// Runs the REPL interactively.
-    pub fn run(&mut self, startup_commands: Option<Vec<Command>>) {
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    pub fn set_stop_on_error(&mut self, stop_on_error: bool) {
+        self.stop_on_error = stop_on_error;
+    }
+
+    /// Reports a command's failure, which ends the run if we're stopping on errors.
+    fn report_error<T: fmt::Display>(&self, error: T) {
+        self.failed.set(true);
+        eprintln!("{}", error);
+    }
+
+    fn should_stop(&self) -> bool {
+        self.stop_on_error && self.failed.get()
+    }
+
+    /// Echo a command that the user didn't type.  Echo to stderr, so that only results reach
+    /// stdout when they're being piped.
+    fn echo(&self, command: &Command) {
+        if !self.quiet {
+            eprintln!("{}", command.output());
+        }
+    }
+
+    /// Runs the REPL, interactively or over a script, until the input ends, `.exit`, or -- if
+    /// we're stopping on errors -- a command fails.  Returns whether every command succeeded.
+    pub fn run(&mut self, startup_commands: Option<Vec<Command>>) -> bool {
         if let Some(cmds) = startup_commands {
             for command in cmds.iter() {
-                println!("{}", command.output());
-                self.handle_command(command.clone());
+                self.echo(command);
+                if !self.handle_command(command.clone()) || self.should_stop() {
+                    self.input_reader.save_history();
+                    return !self.failed.get();
+                }
             }
         }
 
-        loop {
+        while !self.should_stop() {
             let res = self.input_reader.read_input();
 
             match res {
                 Ok(MetaCommand(cmd)) => {
                     debug!("read command: {:?}", cmd);
+                    if self.input_reader.is_script() {
+                        self.echo(&cmd);
+                    }
                     if !self.handle_command(cmd) {
                         break;
                     }
@@ -248,24 +452,100 @@ impl Repl {
                     }
                     break;
                 },
-                Err(e) => eprintln!("{}", e.to_string()),
+                Err(e) => self.report_error(e),
             }
         }
 
         self.input_reader.save_history();
+        !self.failed.get()
     }
 
     fn cache(&mut self, attr: String, direction: CacheDirection) {
         if let Some(kw) = parse_namespaced_keyword(attr.as_str()) {
             match self.store.cache(&kw, direction) {
                 Result::Ok(_) => (),
-                Result::Err(e) => eprintln!("Couldn't cache attribute: {}", e),
+                Result::Err(e) => self.report_error(format!("Couldn't cache attribute: {}", e)),
             };
         } else {
-            eprintln!("Invalid attribute {}", attr);
+            self.report_error(format!("Invalid attribute {}", attr));
         }
     }
 
+    fn uncache(&mut self, attr: String) {
+        if let Some(kw) = parse_namespaced_keyword(attr.as_str()) {
+            match self.store.uncache(&kw) {
+                Result::Ok(_) => (),
+                Result::Err(e) => self.report_error(format!("Couldn't uncache attribute: {}", e)),
+            };
+        } else {
+            self.report_error(format!("Invalid attribute {}", attr));
+        }
+    }
+
+    fn print_cached_attributes(&self) {
+        for (attr, direction) in self.store.cached_attributes() {
+            println!("{} {:?}", attr, direction);
+        }
+    }
+
+    fn print_stats(&self) -> Result<(), Error> {
+        let report = self.store.maintenance()?;
+        let schema = self.store.conn().current_schema();
+
+        // Largest attributes first.
+        let mut counts: Vec<(String, u64)> = report.datoms.iter().map(|(&a, &count)| {
+            let a = schema.get_ident(a).map(|ident| ident.to_string()).unwrap_or_else(|| a.to_string());
+            (a, count)
+        }).collect();
+        counts.sort_by(|x, y| y.1.cmp(&x.1).then_with(|| x.0.cmp(&y.0)));
+
+        let stdout = ::std::io::stdout();
+        let mut output = TabWriter::new(stdout.lock());
+        writeln!(output, "| attribute\t| datoms\t|")?;
+        writeln!(output, "---\t---\t")?;
+        for (a, count) in counts {
+            writeln!(output, "| {}\t| {}\t|", a, count)?;
+        }
+        writeln!(output, "---\t---\t")?;
+        writeln!(output, "| total\t| {}\t|", report.total_datoms())?;
+        output.flush()?;
+
+        println!("Fulltext: {} values, {} bytes of text, {} bytes of index",
+                 report.fulltext_values, report.fulltext_text_bytes, report.fulltext_index_bytes);
+        println!("Database: {} bytes in {} pages, {} free ({:.1}% fragmented)",
+                 report.database_bytes(), report.page_count, report.free_page_count, report.fragmentation() * 100.0);
+        Ok(())
+    }
+
+    fn print_tx_data(&self, tx: Entid) {
+        match self.store.tx_data(tx) {
+            Ok(datoms) => {
+                if let Err(e) = self.print_datoms(datoms) {
+                    self.report_error(e);
+                }
+            },
+            Err(e) => self.report_error(e),
+        }
+    }
+
+    fn print_datoms(&self, datoms: Vec<Datom>) -> Result<(), Error> {
+        let stdout = ::std::io::stdout();
+        let mut output = TabWriter::new(stdout.lock());
+        writeln!(output, "| e\t| a\t| v\t| tx\t| added\t|")?;
+        writeln!(output, "---\t---\t---\t---\t---\t")?;
+        for datom in datoms {
+            let a = match datom.a {
+                EntidOrIdent::Entid(e) => e.to_string(),
+                EntidOrIdent::Ident(ref ident) => ident.to_string(),
+            };
+            writeln!(output, "| {}\t| {}\t| {}\t| {}\t| {}\t|",
+                     datom.e, a, self.value_as_string(&datom.v), datom.tx, datom.added)?;
+        }
+        writeln!(output, "---\t---\t---\t---\t---\t")?;
+        output.flush()?;
+        Ok(())
+    }
+
     /// Runs a single command input.
     fn handle_command(&mut self, cmd: Command) -> bool {
         let should_print_times = self.timer_on && cmd.is_timed();
@@ -274,44 +554,99 @@ impl Repl {
         let mut end: Option<PreciseTime> = None;
 
         match cmd {
+            Command::AsOf(basis) => {
+                self.set_basis(Some(SessionBasis::AsOf(basis)));
+            },
             Command::Cache(attr, direction) => {
                 self.cache(attr, direction);
             },
+            Command::Cached => {
+                self.print_cached_attributes();
+            },
             Command::Close => {
                 self.close();
             },
+            Command::Dump(path) => {
+                self.dump(path);
+            },
+            Command::Edit => {
+                if let Err(e) = self.input_reader.edit() {
+                    self.report_error(e);
+                }
+            },
             Command::Exit => {
-                eprintln!("Exiting…");
+                if !self.quiet {
+                    eprintln!("Exiting…");
+                }
                 return false;
             },
+            Command::Format(format) => {
+                self.format = format;
+            },
             Command::Help(args) => {
                 self.help_command(args);
             },
             Command::Import(path) => {
                 self.execute_import(path);
             },
-            Command::Open(db) => {
-                match self.open(db) {
-                    Ok(_) => println!("Database {:?} opened", self.db_name()),
-                    Err(e) => eprintln!("{}", e.to_string()),
+            Command::Now => {
+                self.set_basis(None);
+            },
+            Command::Open(db, flags) => {
+                let encryption_key = if flags.encrypted {
+                    match self.read_encryption_key() {
+                        Ok(Some(key)) => Some(key),
+                        Ok(None) => return true,
+                        Err(e) => {
+                            self.report_error(e);
+                            return true;
+                        },
+                    }
+                } else {
+                    None
+                };
+                let result = match self.open_common(db.clone(), encryption_key.as_ref().map(|k| k.as_str()), &flags) {
+                    // The database turned out to be encrypted: ask for its key, as `--encrypted` would.
+                    #[cfg(feature = "sqlcipher")]
+                    Err(::mentat::MentatError::EncryptionKeyRequired(_)) if !flags.encrypted && self.input_reader.is_tty() => {
+                        match self.read_encryption_key() {
+                            Ok(Some(key)) => self.open_common(db, Some(key.as_str()), &flags),
+                            Ok(None) => return true,
+                            Err(e) => {
+                                self.report_error(e);
+                                return true;
+                            },
+                        }
+                    },
+                    result => result,
+                };
+                match result {
+                    Ok(_) if self.quiet => (),
+                    Ok(_) => println!("Database {:?} opened{}", self.db_name(), if flags.read_only { " read-only" } else { "" }),
+                    Err(e) => self.report_error(e),
                 };
             },
             Command::OpenEncrypted(db, encryption_key) => {
                 match self.open_with_key(db, &encryption_key) {
+                    Ok(_) if self.quiet => (),
                     Ok(_) => println!("Database {:?} opened with key {:?}", self.db_name(), encryption_key),
-                    Err(e) => eprintln!("{}", e.to_string()),
+                    Err(e) => self.report_error(e),
                 }
             },
             Command::Query(query) => {
-                self.store
-                    .q_once(query.as_str(), None)
+                let output = match self.basis_view() {
+                    Ok(Some(view)) => view.q_once(query.as_str(), None),
+                    Ok(None) => self.store.q_once(query.as_str(), None),
+                    Err(e) => Err(e),
+                };
+                output
                     .map_err(|e| e.into())
                     .and_then(|o| {
                         end = Some(PreciseTime::now());
                         self.print_results(o)
                     })
                     .map_err(|err| {
-                        eprintln!("{:?}.", err);
+                        self.report_error(format!("{:?}.", err));
                     })
                     .ok();
             },
@@ -319,8 +654,18 @@ impl Repl {
                 self.explain_query(query);
             },
             Command::QueryPrepared(query) => {
-                self.store
-                    .q_prepare(query.as_str(), None)
+                let view = match self.basis_view() {
+                    Ok(view) => view,
+                    Err(err) => {
+                        self.report_error(format!("{:?}.", err));
+                        return true;
+                    },
+                };
+                let prepared = match view {
+                    Some(ref view) => view.q_prepare(query.as_str(), None),
+                    None => self.store.q_prepare(query.as_str(), None),
+                };
+                prepared
                     .and_then(|mut p| {
                         let prepare_end = PreciseTime::now();
                         if should_print_times {
@@ -336,29 +681,65 @@ impl Repl {
                     })
                     .map(|o| self.print_results(o))
                     .map_err(|err| {
-                        eprintln!("{:?}.", err);
+                        self.report_error(format!("{:?}.", err));
                     })
                     .ok();
             },
-            Command::Schema => {
+            Command::Read(path) => {
+                return self.read_script(path);
+            },
+            Command::Rekey => {
+                self.rekey();
+            },
+            Command::Schema(None) => {
                 let edn = self.store.conn().current_schema().to_edn_value();
                 match edn.to_pretty(120) {
                     Ok(s) => println!("{}", s),
-                    Err(e) => eprintln!("{}", e)
+                    Err(e) => self.report_error(e)
                 };
             },
+            Command::Schema(Some(namespace)) => {
+                let edn = edn::Value::Vector(self.store.attributes_in_namespace(&namespace)
+                                                 .into_iter()
+                                                 .map(|(ident, attribute)| attribute.to_edn_value(Some(ident)))
+                                                 .collect());
+                match edn.to_pretty(120) {
+                    Ok(s) => println!("{}", s),
+                    Err(e) => self.report_error(e)
+                };
+            },
+            Command::SchemaDiff(path) => {
+                self.diff_schema(path);
+            },
+
+            Command::Since(basis) => {
+                self.set_basis(Some(SessionBasis::Since(basis)));
+            },
+            Command::Stats => {
+                if let Err(e) = self.print_stats() {
+                    self.report_error(e);
+                }
+            },
 
             #[cfg(feature = "syncable")]
             Command::Sync(args) => {
-                match self.store.sync(&args[0], &args[1]) {
+                let quiet = self.quiet;
+                let mut progress = |p: &SyncProgress| {
+                    if !quiet {
+                        eprintln!("Sync {}: {} transactions, {} datoms", p.phase, p.txs, p.datoms);
+                    }
+                };
+                let mut options = SyncOptions::new();
+                options.progress(&mut progress);
+                match self.store.sync_with_options(&args[0], &args[1], &mut options) {
                     Ok(report) => println!("Sync report: {}", report),
-                    Err(e) => eprintln!("{:?}", e)
+                    Err(e) => self.report_error(format!("{:?}", e))
                 };
             },
 
             #[cfg(not(feature = "syncable"))]
             Command::Sync(_) => {
-                eprintln!(".sync requires the syncable Mentat feature");
+                self.report_error(".sync requires the syncable Mentat feature");
             },
 
             Command::Timer(on) => {
@@ -367,6 +748,12 @@ impl Repl {
             Command::Transact(transaction) => {
                 self.execute_transact(transaction);
             },
+            Command::Tx(tx) => {
+                self.print_tx_data(tx);
+            },
+            Command::Uncache(attr) => {
+                self.uncache(attr);
+            },
         }
 
         let end = end.unwrap_or_else(PreciseTime::now);
@@ -386,52 +773,195 @@ impl Repl {
         let mut content: String = "".to_string();
         match ::std::fs::File::open(path.clone()).and_then(|mut f| f.read_to_string(&mut content)) {
             Ok(_) => self.execute_transact(content),
-            Err(e) => eprintln!("Error reading file {}: {}", path, e)
+            Err(e) => self.report_error(format!("Error reading file {}: {}", path, e))
+        }
+    }
+
+    fn dump(&self, path: String) {
+        let written = dump::dump_script(&self.store)
+            .and_then(|script| ::std::fs::File::create(&path)
+                                              .and_then(|mut f| f.write_all(script.as_bytes()))
+                                              .map_err(|e| e.into()));
+        if let Err(e) = written {
+            self.report_error(format!("Error writing file {}: {}", path, e));
+        }
+    }
+
+    /// Run the commands in the file at `path`.  Returns `false` if one of them is `.exit`.
+    fn read_script(&mut self, path: String) -> bool {
+        use ::std::io::Read;
+        let mut script: String = "".to_string();
+        if let Err(e) = ::std::fs::File::open(path.clone()).and_then(|mut f| f.read_to_string(&mut script)) {
+            self.report_error(format!("Error reading file {}: {}", path, e));
+            return true;
+        }
+
+        let mut reader = InputReader::with_script(&script);
+        while !self.should_stop() {
+            match reader.read_input() {
+                Ok(MetaCommand(cmd)) => {
+                    if !self.handle_command(cmd) {
+                        return false;
+                    }
+                },
+                Ok(Empty) |
+                Ok(More) => (),
+                Ok(Eof) => break,
+                Err(e) => self.report_error(e),
+            }
+        }
+        true
+    }
+
+    /// Compare the attributes defined in the file at `path` against the store's schema, printing
+    /// those that the store lacks and those that it defines differently.
+    fn diff_schema(&self, path: String) {
+        use ::std::io::Read;
+        let mut content: String = "".to_string();
+        if let Err(e) = ::std::fs::File::open(path.clone()).and_then(|mut f| f.read_to_string(&mut content)) {
+            self.report_error(format!("Error reading file {}: {}", path, e));
+            return;
+        }
+        let expected = match parse_vocabulary(&content, &self.store.conn().current_schema()) {
+            Ok(attributes) => attributes,
+            Err(e) => {
+                self.report_error(format!("Error reading file {}: {}", path, e));
+                return;
+            },
+        };
+
+        let mut missing = vec![];
+        let mut conflicting = vec![];
+        for (ident, attribute) in expected {
+            match self.store.attribute(&ident) {
+                None => missing.push(ident),
+                Some(ref existing) if *existing != attribute => conflicting.push((ident, existing.clone(), attribute)),
+                Some(_) => (),
+            }
+        }
+
+        if missing.is_empty() && conflicting.is_empty() {
+            println!("Schema matches {}.", path);
+            return;
+        }
+        if !missing.is_empty() {
+            println!("Missing:");
+            for ident in missing {
+                println!("  {}", ident);
+            }
+        }
+        if !conflicting.is_empty() {
+            println!("Conflicting:");
+            for (ident, existing, attribute) in conflicting {
+                println!("  {}", ident);
+                println!("    store: {}", existing.to_edn_value(None));
+                println!("    file:  {}", attribute.to_edn_value(None));
+            }
         }
     }
 
     fn open_common(
         &mut self,
         path: String,
-        encryption_key: Option<&str>
+        encryption_key: Option<&str>,
+        flags: &OpenFlags
     ) -> ::mentat::errors::Result<()> {
-        if self.path.is_empty() || path != self.path {
-            let next = match encryption_key {
+        // Flags ask for something other than the store we have, so honor them even for the same path.
+        if self.path.is_empty() || path != self.path || *flags != OpenFlags::default() {
+            let mut options = StoreOptions::new();
+            options.read_only(flags.read_only)
+                   .create_if_missing(!flags.existing)
+                   .empty(flags.empty);
+            match encryption_key {
                 #[cfg(not(feature = "sqlcipher"))]
-                Some(_) => return Err(::mentat::MentatError::RusqliteError(".open_encrypted requires the sqlcipher Mentat feature".into(), "".into())),
+                Some(_) => return Err(::mentat::MentatError::RusqliteError("Encrypted databases require the sqlcipher Mentat feature".into(), "".into())),
                 #[cfg(feature = "sqlcipher")]
                 Some(k) => {
-                    Store::open_with_key(path.as_str(), k)?
+                    options.encryption_key(k);
                 },
-                _ => {
-                    Store::open(path.as_str())?
-                }
+                _ => {},
             };
+            let next = options.open(path.as_str())?;
             self.path = path;
             self.store = next;
+            self.set_basis(None);
         }
 
         Ok(())
     }
 
     fn open<T>(&mut self, path: T) -> ::mentat::errors::Result<()> where T: Into<String> {
-        self.open_common(path.into(), None)
+        self.open_common(path.into(), None, &OpenFlags::default())
     }
 
     fn open_with_key<T, U>(&mut self, path: T, encryption_key: U)
     -> ::mentat::errors::Result<()> where T: Into<String>, U: AsRef<str> {
-        self.open_common(path.into(), Some(encryption_key.as_ref()))
+        self.open_common(path.into(), Some(encryption_key.as_ref()), &OpenFlags::default())
+    }
+
+    /// Prompt for a key without echoing it.  Returns `None` if the prompt was abandoned.
+    fn read_encryption_key(&self) -> Result<Option<String>, Error> {
+        print!("Key: ");
+        ::std::io::stdout().flush()?;
+        let key = if self.input_reader.is_tty() {
+            let key = ::std::io::stdin().read_passwd(&mut ::std::io::stdout())?;
+            println!();
+            key
+        } else {
+            let mut key = String::new();
+            match ::std::io::stdin().read_line(&mut key)? {
+                0 => None,
+                _ => Some(key.trim_right_matches(|c| c == '\n' || c == '\r').to_string()),
+            }
+        };
+        Ok(key)
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    fn rekey(&mut self) {
+        let key = match self.read_encryption_key() {
+            Ok(Some(key)) => key,
+            Ok(None) => return,
+            Err(e) => return self.report_error(e),
+        };
+        match self.store.change_encryption_key(&key) {
+            Ok(_) if self.quiet => (),
+            Ok(_) => println!("Database {:?} rekeyed", self.db_name()),
+            Err(e) => self.report_error(e),
+        }
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    fn rekey(&mut self) {
+        self.report_error("Encrypted databases require the sqlcipher Mentat feature");
     }
 
     // Close the current store by opening a new in-memory store in its place.
     fn close(&mut self) {
         let old_db_name = self.db_name();
         match self.open("") {
+            Ok(_) if self.quiet => (),
             Ok(_) => println!("Database {:?} closed.", old_db_name),
-            Err(e) => eprintln!("{}", e),
+            Err(e) => self.report_error(e),
         };
     }
 
+    fn set_basis(&mut self, basis: Option<SessionBasis>) {
+        self.input_reader.set_basis(basis.as_ref().map(|b| b.prompt()));
+        self.basis = basis;
+    }
+
+    /// The store as seen from the session's basis, if the session is pinned to one.
+    fn basis_view(&self) -> ::mentat::errors::Result<Option<DatabaseView>> {
+        match self.basis {
+            None => Ok(None),
+            Some(SessionBasis::AsOf(Basis::Tx(tx))) => Ok(Some(self.store.as_of(tx))),
+            Some(SessionBasis::AsOf(Basis::Instant(ref instant))) => self.store.as_of_instant(instant).map(Some),
+            Some(SessionBasis::Since(Basis::Tx(tx))) => Ok(Some(self.store.since(tx))),
+            Some(SessionBasis::Since(Basis::Instant(ref instant))) => self.store.since_instant(instant).map(Some),
+        }
+    }
+
     fn toggle_timer(&mut self, on: bool) {
         self.timer_on = on;
     }
@@ -455,7 +985,7 @@ impl Repl {
                     write!(output, ".{}\t", cmd).unwrap();
                     writeln!(output, "{}", msg).unwrap();
                 } else {
-                    eprintln!("Unrecognised command {}", arg);
+                    self.report_error(format!("Unrecognised command {}", arg));
                     return;
                 }
             }
@@ -465,6 +995,30 @@ impl Repl {
     }
 
     fn print_results(&self, query_output: QueryOutput) -> Result<(), Error> {
+        let stdout = ::std::io::stdout();
+        match self.format {
+            OutputFormat::Table => self.print_table(query_output),
+            OutputFormat::Edn => {
+                let edn = query_output.results.into_edn();
+                println!("{}", edn.to_pretty(120)?);
+                Ok(())
+            },
+            OutputFormat::Json => {
+                let mut out = stdout.lock();
+                output::write_json(&mut out, &query_output.results.into_edn())?;
+                writeln!(out, "")?;
+                Ok(())
+            },
+            OutputFormat::Csv => {
+                let mut out = stdout.lock();
+                output::write_csv(&mut out, query_output)?;
+                out.flush()?;
+                Ok(())
+            },
+        }
+    }
+
+    fn print_table(&self, query_output: QueryOutput) -> Result<(), Error> {
         let stdout = ::std::io::stdout();
         let mut output = TabWriter::new(stdout.lock());
 
@@ -518,9 +1072,14 @@ impl Repl {
     }
 
     pub fn explain_query(&self, query: String) {
-        match self.store.q_explain(query.as_str(), None) {
+        let explanation = match self.basis_view() {
+            Ok(Some(view)) => view.q_explain(query.as_str(), None),
+            Ok(None) => self.store.q_explain(query.as_str(), None),
+            Err(e) => Err(e),
+        };
+        match explanation {
             Result::Err(err) =>
-                println!("{:?}.", err),
+                self.report_error(format!("{:?}.", err)),
             Result::Ok(QueryExplanation::KnownConstant) =>
                 println!("Query is known constant!"),
             Result::Ok(QueryExplanation::KnownEmpty(empty_because)) =>
@@ -530,7 +1089,7 @@ impl Repl {
                 if !query.args.is_empty() {
                     println!("  Bindings:");
                     for (arg_name, value) in query.args {
-                        println!("    {} = {:?}", arg_name, *value)
+                        println!("    {} = {:?}", arg_name, value)
                     }
                 }
 
@@ -559,7 +1118,7 @@ impl Repl {
     pub fn execute_transact(&mut self, transaction: String) {
         match self.transact(transaction) {
             Result::Ok(report) => println!("{:?}", report),
-            Result::Err(err) => eprintln!("Error: {:?}.", err),
+            Result::Err(err) => self.report_error(format!("Error: {:?}.", err)),
         }
     }
 
@@ -576,6 +1135,7 @@ impl Repl {
             &Scalar(ref v) => self.value_as_string(v),
             &Map(ref v) => self.map_as_string(v),
             &Vec(ref v) => self.vec_as_string(v),
+            &Null => "nil".to_string(),
         }
     }
 
@@ -617,6 +1177,8 @@ impl Repl {
             &Ref(r) => format!("{}", r),
             &String(ref s) => format!("{:?}", s.to_string()),
             &Uuid(ref u) => format!("{}", u),
+            &BigInteger(ref i) => format!("{}N", i),
+            &Bytes(ref b) => format!("{}", edn::Value::Bytes(b.as_ref().clone())),
         }
     }
 }
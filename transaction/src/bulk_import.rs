@@ -0,0 +1,263 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Importing large datasets.
+//!
+//! Transacting a million datoms as one EDN string means parsing and holding the whole thing in
+//! memory, and then running one enormous transaction.  `InProgress::import_bulk` instead takes an
+//! iterator of entities and transacts them in fixed-size batches, all inside the single SQLite
+//! transaction that the `InProgress` already holds.  Nothing is visible to other connections until
+//! the caller commits, and rolling back discards the whole import.
+//!
+//! Each batch goes through the ordinary transactor, so every batch is validated just as
+//! `transact` would validate it, and gets its own transaction ID.  Tempids are resolved across
+//! batches: an entity can refer to a tempid that was allocated in an earlier batch.
+//!
+//! The transactor reuses prepared statements for batches of the same shape, and fixed-size
+//! batches mostly have the same shape, so the import enlarges the connection's statement cache and
+//! SQLite's page cache while it runs.  Mentat's indices enforce uniqueness as datoms are written,
+//! so unlike a plain SQLite bulk load, index maintenance can't be deferred until the end; keeping
+//! index pages in memory between batches is what we can do instead.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use edn::{
+    Keyword,
+};
+
+use edn::entities::{
+    AttributePlace,
+    EntidOrIdent,
+    Entity,
+    EntityPlace,
+    MapNotation,
+    TempId,
+    ValuePlace,
+};
+
+use core_traits::{
+    Entid,
+    ValueType,
+};
+
+use mentat_core::{
+    HasSchema,
+    Schema,
+};
+
+use mentat_db::{
+    TransactableValue,
+};
+
+use public_traits::errors::{
+    Result,
+};
+
+use InProgress;
+
+/// How many entities `import_bulk` transacts at a time unless told otherwise.
+pub const DEFAULT_IMPORT_BATCH_SIZE: usize = 1000;
+
+/// How many prepared statements the connection keeps while importing.
+const IMPORT_STATEMENT_CACHE_CAPACITY: usize = 128;
+
+/// The page cache SQLite uses while importing, in KiB.
+const IMPORT_PAGE_CACHE_KIB: i64 = 64 * 1024;
+
+/// SQLite's default statement cache capacity, which we restore after importing.
+const DEFAULT_STATEMENT_CACHE_CAPACITY: usize = 16;
+
+/// How far an import has got.  Passed to the progress callback after each batch.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ImportProgress {
+    /// The number of entities transacted so far.
+    pub entities: usize,
+
+    /// The number of batches transacted so far.
+    pub batches: usize,
+
+    /// The transaction that wrote the most recent batch.
+    pub last_tx_id: Option<Entid>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ImportReport {
+    /// The number of entities imported.
+    pub entities: usize,
+
+    /// The transactions the import wrote, one for each batch, in order.
+    pub tx_ids: Vec<Entid>,
+
+    /// Every external tempid in the imported entities, and the entity it names.
+    pub tempids: BTreeMap<String, Entid>,
+}
+
+/// Rewrites references to tempids that earlier batches allocated, so that later batches refer to
+/// the same entities.
+struct TempIdResolver<'a> {
+    schema: &'a Schema,
+    tempids: &'a BTreeMap<String, Entid>,
+}
+
+impl<'a> TempIdResolver<'a> {
+    fn lookup(&self, tempid: &TempId) -> Option<EntidOrIdent> {
+        match tempid {
+            &TempId::External(ref name) |
+            &TempId::Partitioned(_, ref name) => self.tempids.get(name).map(|e| EntidOrIdent::Entid(*e)),
+            &TempId::Internal(_) => None,
+        }
+    }
+
+    /// Strings are only tempids where an entity is expected: as `:db/id`, as the value of a ref
+    /// attribute, or as the value of a reversed attribute.
+    fn names_entity(&self, a: &EntidOrIdent) -> bool {
+        let entid = match a {
+            &EntidOrIdent::Ident(ref keyword) => {
+                if keyword.is_backward() || *keyword == Keyword::namespaced("db", "id") {
+                    return true;
+                }
+                match self.schema.get_entid(keyword) {
+                    Some(entid) => entid.0,
+                    None => return false,
+                }
+            },
+            &EntidOrIdent::Entid(entid) => entid,
+        };
+        self.schema
+            .attribute_for_entid(entid)
+            .map_or(false, |attribute| attribute.value_type == ValueType::Ref)
+    }
+
+    fn resolve_entity<V: TransactableValue>(&self, entity: Entity<V>) -> Entity<V> {
+        match entity {
+            Entity::AddOrRetract { op, e, a, v } => {
+                let names_entity = match a {
+                    AttributePlace::Entid(ref a) => self.names_entity(a),
+                };
+                Entity::AddOrRetract {
+                    op: op,
+                    e: self.resolve_entity_place(e),
+                    a: a,
+                    v: self.resolve_value_place(v, names_entity),
+                }
+            },
+            Entity::MapNotation(map) => Entity::MapNotation(self.resolve_map(map)),
+            Entity::RetractEntity { e } => Entity::RetractEntity { e: self.resolve_entity_place(e) },
+            Entity::RetractAttribute { e, a } => Entity::RetractAttribute { e: self.resolve_entity_place(e), a },
+            Entity::Call { f, args } => Entity::Call { f, args },
+        }
+    }
+
+    fn resolve_map<V: TransactableValue>(&self, map: MapNotation<V>) -> MapNotation<V> {
+        map.into_iter()
+           .map(|(a, v)| {
+               let names_entity = self.names_entity(&a);
+               (a, self.resolve_value_place(v, names_entity))
+           })
+           .collect()
+    }
+
+    fn resolve_entity_place<V: TransactableValue>(&self, e: EntityPlace<V>) -> EntityPlace<V> {
+        match e {
+            EntityPlace::TempId(tempid) => match self.lookup(&tempid) {
+                Some(entid) => EntityPlace::Entid(entid),
+                None => EntityPlace::TempId(tempid),
+            },
+            e => e,
+        }
+    }
+
+    fn resolve_value_place<V: TransactableValue>(&self, v: ValuePlace<V>, names_entity: bool) -> ValuePlace<V> {
+        match v {
+            ValuePlace::TempId(tempid) => match self.lookup(&tempid) {
+                Some(entid) => ValuePlace::Entid(entid),
+                None => ValuePlace::TempId(tempid),
+            },
+            ValuePlace::Atom(atom) => {
+                let entid = if names_entity {
+                    atom.as_tempid().and_then(|tempid| self.lookup(&tempid))
+                } else {
+                    None
+                };
+                match entid {
+                    Some(entid) => ValuePlace::Entid(entid),
+                    None => ValuePlace::Atom(atom),
+                }
+            },
+            ValuePlace::Vector(vs) => {
+                ValuePlace::Vector(vs.into_iter().map(|v| self.resolve_value_place(v, names_entity)).collect())
+            },
+            ValuePlace::MapNotation(map) => ValuePlace::MapNotation(self.resolve_map(map)),
+            v => v,
+        }
+    }
+}
+
+impl<'a, 'c> InProgress<'a, 'c> {
+    /// Transact `entities` in batches of `batch_size`, calling `progress` after each batch.  See
+    /// the module documentation.
+    ///
+    /// If a batch fails, the error is returned and the batches before it remain in this
+    /// `InProgress`; roll back to discard them.
+    pub fn import_bulk<I, V, F>(&mut self, entities: I, batch_size: usize, mut progress: F) -> Result<ImportReport>
+    where I: IntoIterator<Item=Entity<V>>,
+          V: TransactableValue,
+          F: FnMut(&ImportProgress) {
+        assert!(batch_size > 0, "batch_size must be positive");
+
+        let previous_cache_size: i64 = self.transaction.query_row("PRAGMA cache_size", &[], |row| row.get(0))?;
+        self.transaction.execute_batch(&format!("PRAGMA cache_size = -{}", IMPORT_PAGE_CACHE_KIB))?;
+        self.transaction.set_prepared_statement_cache_capacity(IMPORT_STATEMENT_CACHE_CAPACITY);
+
+        let result = self.import_batches(entities.into_iter(), batch_size, &mut progress);
+
+        self.transaction.set_prepared_statement_cache_capacity(DEFAULT_STATEMENT_CACHE_CAPACITY);
+        self.transaction.execute_batch(&format!("PRAGMA cache_size = {}", previous_cache_size))?;
+        result
+    }
+
+    fn import_batches<I, V, F>(&mut self, mut entities: I, batch_size: usize, progress: &mut F) -> Result<ImportReport>
+    where I: Iterator<Item=Entity<V>>,
+          V: TransactableValue,
+          F: FnMut(&ImportProgress) {
+        let mut report = ImportReport::default();
+        let mut batch: Vec<Entity<V>> = Vec::with_capacity(batch_size);
+        loop {
+            batch.clear();
+            {
+                let resolver = TempIdResolver {
+                    schema: &self.schema,
+                    tempids: &report.tempids,
+                };
+                batch.extend(entities.by_ref()
+                                     .take(batch_size)
+                                     .map(|entity| resolver.resolve_entity(entity)));
+            }
+            if batch.is_empty() {
+                return Ok(report);
+            }
+
+            let count = batch.len();
+            let tx_report = self.transact_entities(batch.drain(..))?;
+
+            report.entities += count;
+            report.tx_ids.push(tx_report.tx_id);
+            report.tempids.extend(tx_report.tempids.into_iter());
+
+            progress(&ImportProgress {
+                entities: report.entities,
+                batches: report.tx_ids.len(),
+                last_tx_id: Some(tx_report.tx_id),
+            });
+        }
+    }
+}
@@ -51,17 +51,24 @@
 // The second is to expose a declarative, programmatic builder pattern for constructing entities.
 //
 // We probably need both, but this file provides the latter.
+//
+// For application types that map onto a single entity each, `ToEntity` goes one step further: a
+// type describes itself once -- by hand or with `#[derive(ToEntity)]` from `mentat_derive` -- and
+// collections of it can be transacted directly.
 
 use edn::{
     InternSet,
+    Keyword,
     PlainSymbol,
     ValueRc,
 };
 use edn::entities::{
     AttributePlace,
+    EntidOrIdent,
     Entity,
     EntityPlace,
     LookupRef,
+    MapNotation,
     OpType,
     TempId,
     TxFunction,
@@ -97,6 +104,8 @@ pub struct EntityBuilder<T: BuildTerms + Sized> {
 
 pub trait BuildTerms where Self: Sized {
     fn named_tempid<I>(&mut self, name: I) -> ValueRc<TempId> where I: Into<String>;
+    /// Like `(tempid :my/part "name")`: a tempid to allocate in `partition`.
+    fn partitioned_tempid<I>(&mut self, partition: Keyword, name: I) -> ValueRc<TempId> where I: Into<String>;
     fn describe_tempid(self, name: &str) -> EntityBuilder<Self>;
     fn describe<E>(self, entity: E) -> EntityBuilder<Self> where E: Into<EntityPlace<TypedValue>>;
     fn add<E, A, V>(&mut self, e: E, a: A, v: V) -> Result<()>
@@ -107,6 +116,13 @@ pub trait BuildTerms where Self: Sized {
     where E: Into<EntityPlace<TypedValue>>,
           A: Into<AttributePlace>,
           V: Into<ValuePlace<TypedValue>>;
+    /// Like `[:db.fn/retractEntity e]`.
+    fn retract_entity<E>(&mut self, e: E) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>>;
+    /// Like `[:db.fn/retractAttribute e a]`.
+    fn retract_attribute<E, A>(&mut self, e: E, a: A) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>>,
+          A: Into<AttributePlace>;
 }
 
 impl BuildTerms for TermBuilder {
@@ -114,6 +130,10 @@ impl BuildTerms for TermBuilder {
         self.tempids.intern(TempId::External(name.into()))
     }
 
+    fn partitioned_tempid<I>(&mut self, partition: Keyword, name: I) -> ValueRc<TempId> where I: Into<String> {
+        self.tempids.intern(TempId::Partitioned(partition, name.into()))
+    }
+
     fn describe_tempid(mut self, name: &str) -> EntityBuilder<Self> {
         let e = self.named_tempid(name);
         self.describe(e)
@@ -141,6 +161,19 @@ impl BuildTerms for TermBuilder {
         self.terms.push(Entity::AddOrRetract { op: OpType::Retract, e: e.into(), a: a.into(), v: v.into() });
         Ok(())
     }
+
+    fn retract_entity<E>(&mut self, e: E) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>> {
+        self.terms.push(Entity::RetractEntity { e: e.into() });
+        Ok(())
+    }
+
+    fn retract_attribute<E, A>(&mut self, e: E, a: A) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>>,
+          A: Into<AttributePlace> {
+        self.terms.push(Entity::RetractAttribute { e: e.into(), a: a.into() });
+        Ok(())
+    }
 }
 
 impl TermBuilder {
@@ -236,6 +269,10 @@ impl<'a, 'c> BuildTerms for InProgressBuilder<'a, 'c> {
         self.builder.named_tempid(name)
     }
 
+    fn partitioned_tempid<I>(&mut self, partition: Keyword, name: I) -> ValueRc<TempId> where I: Into<String> {
+        self.builder.partitioned_tempid(partition, name)
+    }
+
     fn describe_tempid(mut self, name: &str) -> EntityBuilder<InProgressBuilder<'a, 'c>> {
         let e = self.builder.named_tempid(name.to_string());
         self.describe(e)
@@ -261,6 +298,17 @@ impl<'a, 'c> BuildTerms for InProgressBuilder<'a, 'c> {
           V: Into<ValuePlace<TypedValue>> {
         self.builder.retract(e, a, v)
     }
+
+    fn retract_entity<E>(&mut self, e: E) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>> {
+        self.builder.retract_entity(e)
+    }
+
+    fn retract_attribute<E, A>(&mut self, e: E, a: A) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>>,
+          A: Into<AttributePlace> {
+        self.builder.retract_attribute(e, a)
+    }
 }
 
 impl<'a, 'c> EntityBuilder<InProgressBuilder<'a, 'c>> {
@@ -277,3 +325,78 @@ impl<'a, 'c> EntityBuilder<InProgressBuilder<'a, 'c>> {
         self.finish().0.commit()
     }
 }
+
+/// The attributes and values of a single entity, as collected by `ToEntity::attributes`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EntityAttributes {
+    map: MapNotation<TypedValue>,
+}
+
+impl EntityAttributes {
+    pub fn new() -> EntityAttributes {
+        EntityAttributes::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Add `value` for `attribute`.  Adding several values for the same attribute only makes sense
+    /// if it's cardinality many.
+    pub fn add<V>(&mut self, attribute: Keyword, value: V) where V: Into<TypedValue> {
+        self.push(attribute, ValuePlace::Atom(value.into()));
+    }
+
+    /// Refer to the entity named by `tempid` elsewhere in the same transaction.
+    pub fn add_ref<S>(&mut self, attribute: Keyword, tempid: S) where S: Into<String> {
+        self.push(attribute, ValuePlace::TempId(ValueRc::new(TempId::External(tempid.into()))));
+    }
+
+    fn push(&mut self, attribute: Keyword, value: ValuePlace<TypedValue>) {
+        let attribute = EntidOrIdent::Ident(attribute);
+        let value = match self.map.remove(&attribute) {
+            None => value,
+            Some(ValuePlace::Vector(mut values)) => {
+                values.push(value);
+                ValuePlace::Vector(values)
+            },
+            Some(existing) => ValuePlace::Vector(vec![existing, value]),
+        };
+        self.map.insert(attribute, value);
+    }
+}
+
+/// A value that can be transacted as a single entity, much like the EDN map
+/// `{:db/id "alice" :person/name "Alice" :person/friend "bob"}`.
+///
+/// Implement this for your own types, or derive it with `mentat_derive`, and transact them with
+/// `Store::transact_entities`.
+pub trait ToEntity {
+    /// The name of this entity's tempid.  Other entities in the same transaction can refer to this
+    /// one with `EntityAttributes::add_ref`, and the report maps the name to the entity's entid.
+    /// Entities without a tempid still upsert on any unique identity attributes.
+    fn tempid(&self) -> Option<String> {
+        None
+    }
+
+    fn attributes(&self) -> EntityAttributes;
+
+    fn to_entity(&self) -> Entity<TypedValue> {
+        let mut map = self.attributes().map;
+        if let Some(tempid) = self.tempid() {
+            map.insert(EntidOrIdent::Ident(Keyword::namespaced("db", "id")),
+                       ValuePlace::TempId(ValueRc::new(TempId::External(tempid))));
+        }
+        Entity::MapNotation(map)
+    }
+}
+
+impl<'a, T> ToEntity for &'a T where T: ToEntity {
+    fn tempid(&self) -> Option<String> {
+        (*self).tempid()
+    }
+
+    fn attributes(&self) -> EntityAttributes {
+        (*self).attributes()
+    }
+}
@@ -9,6 +9,9 @@
 // specific language governing permissions and limitations under the License.
 
 extern crate failure;
+#[macro_use]
+extern crate lazy_static;
+extern crate libsqlite3_sys as ffi;
 extern crate rusqlite;
 
 extern crate edn;
@@ -22,6 +25,7 @@ extern crate mentat_query_algebrizer;
 extern crate mentat_query_projector;
 extern crate mentat_query_pull;
 extern crate mentat_sql;
+extern crate mentat_query_sql;
 
 use std::sync::{
     Arc,
@@ -32,7 +36,10 @@ use std::io::Read;
 
 use std::borrow::Borrow;
 
-use std::collections::BTreeMap;
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
 
 use std::fs::{
     File,
@@ -47,6 +54,7 @@ use edn::{
     Keyword,
 };
 use edn::entities::{
+    EntidOrIdent,
     TempId,
     OpType,
 };
@@ -66,25 +74,58 @@ use public_traits::errors::{
 };
 
 use mentat_core::{
+    CachedAttributes,
     HasSchema,
     Schema,
     TxReport,
+    UpdateableCache,
     ValueRc,
 };
 
+use mentat_core::metrics;
+
 use mentat_query_pull::{
+    lookup_entity,
     pull_attributes_for_entities,
     pull_attributes_for_entity,
 };
 
+pub use mentat_query_pull::{
+    Entity,
+};
+
 use mentat_db::{
     transact,
     transact_terms,
     InProgressObserverTransactWatcher,
     PartitionMap,
+    RedundantAssertions,
+    provisional_attributes,
     TransactableValue,
     TransactWatcher,
     TxObservationService,
+    TxSnapshot,
+};
+
+use db_traits::errors::{
+    DbError,
+    DbErrorKind,
+};
+
+use mentat_db::db::{
+    bump_generation,
+    create_partition,
+    database_path,
+};
+
+use mentat_db::excision::{
+    excise,
+};
+
+use mentat_db::retention::{
+    RetentionPolicy,
+    excise_trimmed,
+    trimmed_datoms,
 };
 
 use mentat_db::internal_types::TermWithTempIds;
@@ -94,25 +135,59 @@ use mentat_db::cache::{
     InProgressSQLiteAttributeCache,
 };
 
+pub mod bulk_import;
 pub mod entity_builder;
 pub mod metadata;
+pub mod priority;
 pub mod query;
+pub mod query_cache;
+pub mod query_plan;
+pub mod view;
+pub mod write_holder;
+
+pub use bulk_import::{
+    DEFAULT_IMPORT_BATCH_SIZE,
+    ImportProgress,
+    ImportReport,
+};
+
+use entity_builder::{
+    BuildTerms,
+};
 
 pub use entity_builder::{
+    EntityAttributes,
     InProgressBuilder,
     TermBuilder,
+    ToEntity,
 };
 
 pub use metadata::{
     Metadata,
 };
 
+pub use priority::{
+    BackgroundGuard,
+    InteractiveGuard,
+    QueryPriority,
+};
+
+pub use view::{
+    DatabaseView,
+};
+
+pub use write_holder::{
+    WriteHolderGuard,
+    write_transaction_in_progress,
+};
+
 use query::{
     Known,
     PreparedResult,
     QueryExplanation,
     QueryInputs,
     QueryOutput,
+    QueryRows,
     lookup_value_for_attribute,
     lookup_values_for_attribute,
     q_explain,
@@ -135,6 +210,36 @@ pub enum CacheAction {
     Deregister,
 }
 
+/// What to do when a transaction uses attributes that aren't installed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnknownAttributes {
+    /// Fail the transaction, naming every unknown attribute.
+    Reject,
+
+    /// Install a provisional definition for each unknown attribute, inferred from the values it's
+    /// used with, before transacting.  This is intended for prototyping: the definitions are
+    /// marked with `:db/doc` so that they can be found and replaced with deliberate ones.
+    Install,
+}
+
+impl Default for UnknownAttributes {
+    fn default() -> UnknownAttributes {
+        UnknownAttributes::Reject
+    }
+}
+
+/// A transaction function: given the state of the store before the transaction, and the arguments
+/// it was called with, return the entities to transact in place of the call.  Transaction data
+/// calls a function registered as `:my/fn` like `[:my/fn arg1 arg2]`.
+pub type TransactionFunction = Fn(&InProgress, &[edn::Value]) -> Result<Vec<edn::entities::Entity<TypedValue>>> + Send + Sync;
+
+/// Registered transaction functions, by name.
+pub type TransactionFunctions = BTreeMap<Keyword, Arc<TransactionFunction>>;
+
+/// Transaction functions can return calls to other transaction functions, but only this deep; this
+/// stops a function that calls itself unconditionally.
+const MAX_TRANSACTION_FUNCTION_DEPTH: usize = 32;
+
 /// Represents an in-progress, not yet committed, set of changes to the store.
 /// Call `commit` to commit your changes, or `rollback` to discard them.
 /// A transaction is held open until you do so.
@@ -147,8 +252,21 @@ pub struct InProgress<'a, 'c> {
     pub schema: Schema,
     pub cache: InProgressSQLiteAttributeCache,
     pub use_caching: bool,
+    pub redundant_assertions: RedundantAssertions,
+    pub large_value_threshold: Option<usize>,
+    pub unknown_attributes: UnknownAttributes,
+    pub tx_functions: TransactionFunctions,
     pub tx_observer: &'a Mutex<TxObservationService>,
     pub tx_observer_watcher: InProgressObserverTransactWatcher,
+
+    /// Whether this has changed the store, and so must bump its generation on commit.
+    pub wrote: bool,
+
+    /// Present if this is a write transaction; records the writer until this is dropped.
+    pub write_holder: Option<WriteHolderGuard>,
+
+    /// Present if this is a write transaction; background queries pause until this is dropped.
+    pub interactive: Option<InteractiveGuard>,
 }
 
 /// Represents an in-progress set of reads to the store. Just like `InProgress`,
@@ -164,6 +282,16 @@ pub trait Queryable {
         where T: Into<Option<QueryInputs>>;
     fn q_prepare<T>(&self, query: &str, inputs: T) -> PreparedResult
         where T: Into<Option<QueryInputs>>;
+
+    /// Run a query, handing its results to `f` one row at a time rather than materializing them.
+    /// The rows can't outlive the underlying statement, so they're only available within `f`.
+    fn q_iter<T, F, R>(&self, query: &str, inputs: T, f: F) -> Result<R>
+        where T: Into<Option<QueryInputs>>,
+              F: FnOnce(QueryRows) -> Result<R> {
+        let mut prepared = self.q_prepare(query, inputs)?;
+        let rows = prepared.rows(None)?;
+        f(rows)
+    }
     fn lookup_values_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Vec<TypedValue>>
         where E: Into<Entid>;
     fn lookup_value_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Option<TypedValue>>
@@ -176,6 +304,19 @@ pub trait Pullable {
           A: IntoIterator<Item=Entid>;
     fn pull_attributes_for_entity<A>(&self, entity: Entid, attributes: A) -> Result<StructuredMap>
     where A: IntoIterator<Item=Entid>;
+
+    /// Fetch every attribute of an entity, named by entid or by ident.  Returns `None` if nothing
+    /// is asserted about the entity.
+    fn lookup_entity<E>(&self, entity: E) -> Result<Option<Entity>>
+    where E: Into<EntidOrIdent>;
+}
+
+/// The entid named by `entity`, if there is one.
+pub fn resolve_entity<E>(schema: &Schema, entity: E) -> Option<Entid> where E: Into<EntidOrIdent> {
+    match entity.into() {
+        EntidOrIdent::Entid(e) => Some(e),
+        EntidOrIdent::Ident(ref ident) => schema.get_entid(ident).map(|e| e.into()),
+    }
 }
 
 impl<'a, 'c> InProgress<'a, 'c> {
@@ -188,6 +329,26 @@ impl<'a, 'c> InProgress<'a, 'c> {
         self.use_caching = yesno;
     }
 
+    /// Choose whether transactions record assertions of datoms that are already present.  By
+    /// default they don't: such assertions change nothing.
+    pub fn redundant_assertions(&mut self, redundant_assertions: RedundantAssertions) {
+        self.redundant_assertions = redundant_assertions;
+    }
+
+    /// Choose the length, in bytes, above which string values are stored once in a side table
+    /// rather than in the datoms table and its indices.  By default no values are offloaded.
+    /// Offloaded values read back transparently, and compare equal to the strings they stand for;
+    /// they're compared as opaque ids, though, so range predicates over them don't work.
+    pub fn large_value_threshold(&mut self, large_value_threshold: Option<usize>) {
+        self.large_value_threshold = large_value_threshold;
+    }
+
+    /// Choose whether transactions that use attributes that aren't installed fail, which is the
+    /// default, or install provisional definitions for them.
+    pub fn unknown_attributes(&mut self, unknown_attributes: UnknownAttributes) {
+        self.unknown_attributes = unknown_attributes;
+    }
+
     /// If you only have a reference to an `InProgress`, you can't use the easy builder.
     /// This exists so you can make your own.
     pub fn transact_builder(&mut self, builder: TermBuilder) -> Result<TxReport> {
@@ -198,25 +359,77 @@ impl<'a, 'c> InProgress<'a, 'c> {
     }
 
     pub fn transact_terms<I>(&mut self, terms: I, tempid_set: InternSet<TempId>) -> Result<TxReport> where I: IntoIterator<Item=TermWithTempIds> {
-        let w = InProgressTransactWatcher::new(
-                &mut self.tx_observer_watcher,
-                self.cache.transact_watcher());
         let (report, next_partition_map, next_schema, _watcher) =
-            transact_terms(&self.transaction,
-                           self.partition_map.clone(),
-                           &self.schema,
-                           &self.schema,
-                           w,
-                           terms,
-                           tempid_set)?;
+            metrics::measure(metrics::TRANSACTS_EXECUTED, metrics::TRANSACT_DURATION, || {
+                let w = InProgressTransactWatcher::new(
+                        &mut self.tx_observer_watcher,
+                        self.cache.transact_watcher());
+                transact_terms(&self.transaction,
+                               self.partition_map.clone(),
+                               &self.schema,
+                               &self.schema,
+                               w,
+                               self.redundant_assertions,
+                               self.large_value_threshold,
+                               terms,
+                               tempid_set)
+            })?;
         self.partition_map = next_partition_map;
+        self.wrote = true;
         if let Some(schema) = next_schema {
-            self.schema = schema;
+            let old_schema = ::std::mem::replace(&mut self.schema, schema);
+            self.recache_altered_attributes(&old_schema)?;
         }
+        self.tx_observer_watcher.did_transact(&report);
         Ok(report)
     }
 
     pub fn transact_entities<I, V: TransactableValue>(&mut self, entities: I) -> Result<TxReport> where I: IntoIterator<Item=edn::entities::Entity<V>> {
+        let entities = self.expand_calls(entities)?;
+        match self.unknown_attributes {
+            UnknownAttributes::Reject => self.transact_entities_as_given(entities),
+            UnknownAttributes::Install => {
+                let definitions = provisional_attributes(&self.schema, &entities[..])?;
+                if !definitions.is_empty() {
+                    self.transact_entities_as_given(definitions)?;
+                }
+                self.transact_entities_as_given(entities)
+            },
+        }
+    }
+
+    /// Replace each call to a registered transaction function with the entities it returns,
+    /// expanding any calls those entities make in turn.  Every function sees the store as it was
+    /// before this transaction.
+    fn expand_calls<I, V: TransactableValue>(&self, entities: I) -> Result<Vec<edn::entities::Entity<V>>> where I: IntoIterator<Item=edn::entities::Entity<V>> {
+        let mut expanded = Vec::new();
+        for entity in entities {
+            self.expand_call(entity, 0, &mut expanded)?;
+        }
+        Ok(expanded)
+    }
+
+    fn expand_call<V: TransactableValue>(&self, entity: edn::entities::Entity<V>, depth: usize, expanded: &mut Vec<edn::entities::Entity<V>>) -> Result<()> {
+        match entity {
+            edn::entities::Entity::Call { f, args } => {
+                if depth >= MAX_TRANSACTION_FUNCTION_DEPTH {
+                    bail!(DbError::from(DbErrorKind::TransactionFunctionTooDeep(f.to_string())));
+                }
+                let function = match self.tx_functions.get(&f) {
+                    Some(function) => function.clone(),
+                    None => bail!(DbError::from(DbErrorKind::UnknownTransactionFunction(f.to_string()))),
+                };
+                let args: Vec<edn::Value> = args.into_iter().map(|v| v.into_edn_value()).collect();
+                for entity in (*function)(self, &args[..])? {
+                    self.expand_call(entity.map_values(&mut V::from_typed_value), depth + 1, expanded)?;
+                }
+            },
+            entity => expanded.push(entity),
+        }
+        Ok(())
+    }
+
+    fn transact_entities_as_given<I, V: TransactableValue>(&mut self, entities: I) -> Result<TxReport> where I: IntoIterator<Item=edn::entities::Entity<V>> {
         // We clone the partition map here, rather than trying to use a Cell or using a mutable
         // reference, for two reasons:
         // 1. `transact` allocates new IDs in partitions before and while doing work that might
@@ -225,23 +438,46 @@ impl<'a, 'c> InProgress<'a, 'c> {
         //    `Metadata` on return. If we used `Cell` or other mechanisms, we'd be using
         //    `Default::default` in those situations to extract the partition map, and so there
         //    would still be some cost.
-        let w = InProgressTransactWatcher::new(
-                &mut self.tx_observer_watcher,
-                self.cache.transact_watcher());
         let (report, next_partition_map, next_schema, _watcher) =
-            transact(&self.transaction,
-                     self.partition_map.clone(),
-                     &self.schema,
-                     &self.schema,
-                     w,
-                     entities)?;
+            metrics::measure(metrics::TRANSACTS_EXECUTED, metrics::TRANSACT_DURATION, || {
+                let w = InProgressTransactWatcher::new(
+                        &mut self.tx_observer_watcher,
+                        self.cache.transact_watcher());
+                transact(&self.transaction,
+                         self.partition_map.clone(),
+                         &self.schema,
+                         &self.schema,
+                         w,
+                         self.redundant_assertions,
+                         self.large_value_threshold,
+                         entities)
+            })?;
         self.partition_map = next_partition_map;
+        self.wrote = true;
         if let Some(schema) = next_schema {
-            self.schema = schema;
+            let old_schema = ::std::mem::replace(&mut self.schema, schema);
+            self.recache_altered_attributes(&old_schema)?;
         }
+        self.tx_observer_watcher.did_transact(&report);
         Ok(report)
     }
 
+    /// Rebuild the cache of every cached attribute whose cardinality or uniqueness differs from
+    /// `old_schema`: those change how its values are cached.
+    fn recache_altered_attributes(&mut self, old_schema: &Schema) -> Result<()> {
+        let altered: Vec<Entid> = self.schema.attribute_map.iter().filter_map(|(&a, attribute)| {
+            match old_schema.attribute_for_entid(a) {
+                Some(old) if old.multival != attribute.multival ||
+                             old.unique.is_some() != attribute.unique.is_some() => Some(a),
+                _ => None,
+            }
+        }).collect();
+        for a in altered {
+            self.cache.recache(&self.schema, &self.transaction, a)?;
+        }
+        Ok(())
+    }
+
     pub fn transact<B>(&mut self, transaction: B) -> Result<TxReport> where B: Borrow<str> {
         let entities = edn::parse::entities(transaction.borrow())?;
         self.transact_entities(entities)
@@ -263,6 +499,13 @@ impl<'a, 'c> InProgress<'a, 'c> {
         // The mutex is taken during this entire method.
         let mut metadata = self.mutex.lock().unwrap();
 
+        // Only observers that will query need a snapshot, and only a file can be opened twice.
+        let snapshot_path = if self.tx_observer.lock().unwrap().wants_snapshot() {
+            database_path(&self.transaction)?
+        } else {
+            None
+        };
+
         if self.generation != metadata.generation {
             // Somebody else wrote!
             // Retrying is tracked by https://github.com/mozilla/mentat/issues/357.
@@ -271,10 +514,18 @@ impl<'a, 'c> InProgress<'a, 'c> {
             bail!(MentatError::UnexpectedLostTransactRace);
         }
 
+        // Let other connections to the store know that their metadata is stale.
+        let store_generation = if self.wrote {
+            bump_generation(&self.transaction, self.schema != *(metadata.schema))?
+        } else {
+            metadata.store_generation
+        };
+
         // Commit the SQLite transaction while we hold the mutex.
         self.transaction.commit()?;
 
         metadata.generation += 1;
+        metadata.store_generation = store_generation;
         metadata.partition_map = self.partition_map;
 
         // Update the conn's cache if we made any changes.
@@ -288,12 +539,85 @@ impl<'a, 'c> InProgress<'a, 'c> {
             // TODO: consider making vocabulary lookup lazy -- we won't need it much of the time.
         }
 
+        // We still hold the mutex, so nothing else can have committed through this `Conn`: the
+        // snapshot sees exactly what we just wrote.  Failing to take it doesn't fail the commit.
+        let snapshot = snapshot_path.and_then(|path| {
+            TxSnapshot::open(&path, metadata.schema.clone(), metadata.attribute_cache.clone()).ok()
+        });
+
         let txes = self.tx_observer_watcher.txes;
-        self.tx_observer.lock().unwrap().in_progress_did_commit(txes);
+        let tx_reports = self.tx_observer_watcher.tx_reports;
+        self.tx_observer.lock().unwrap().in_progress_did_commit(txes, tx_reports, snapshot);
 
         Ok(())
     }
 
+    /// Create the partition `partition`, from which tempids like `(tempid :my/part "name")` are
+    /// allocated, starting at `start`.  See `mentat_db::db::create_partition`.
+    pub fn create_partition(&mut self, partition: &Keyword, start: Entid) -> Result<()> {
+        create_partition(&self.transaction, &mut self.partition_map, &partition.to_string(), start)?;
+        self.wrote = true;
+        Ok(())
+    }
+
+    /// Excise `target`, or, if `attributes` isn't empty, its datoms of those attributes, from the
+    /// store and its transaction log.  With `before_tx`, only history before that transaction is
+    /// excised.  See `mentat_db::excision::excise`.
+    pub fn excise(&mut self, target: Entid, attributes: &[Keyword], before_tx: Option<Entid>) -> Result<()> {
+        let attributes: BTreeSet<Entid> = attributes.iter().map(|attribute| {
+            self.schema
+                .get_entid(attribute)
+                .map(|a| a.0)
+                .ok_or_else(|| MentatError::UnknownAttribute(attribute.to_string()))
+        }).collect::<Result<_>>()?;
+        let excised = excise(&self.transaction, &self.schema, &self.partition_map, target, &attributes, before_tx)?;
+        self.wrote = true;
+        self.uncache_excised(excised)
+    }
+
+    /// Excised datoms are gone as surely as retracted ones.  The cache takes those of cached
+    /// attributes as `(a, e, v)`, grouped by attribute.
+    fn uncache_excised(&mut self, excised: Vec<(Entid, Entid, TypedValue)>) -> Result<()> {
+        let mut excised: Vec<_> = excised.into_iter()
+                                         .filter(|&(_, a, _)| self.cache.is_attribute_cached_forward(a) ||
+                                                              self.cache.is_attribute_cached_reverse(a))
+                                         .map(|(e, a, v)| (a, e, v))
+                                         .collect();
+        excised.sort_by_key(|&(a, e, _)| (a, e));
+        self.cache.update(&self.schema, excised.into_iter(), vec![].into_iter())?;
+        Ok(())
+    }
+
+    /// Trim the values of `attribute` to those that `policy` keeps, retracting or excising the
+    /// others, and return how many were trimmed.  Retractions are transacted like any others;
+    /// excisions, as with `excise`, aren't observed or synced.  See `mentat_db::retention`.
+    pub fn trim(&mut self, attribute: &Keyword, policy: RetentionPolicy) -> Result<usize> {
+        let a = self.schema
+                    .get_entid(attribute)
+                    .ok_or_else(|| MentatError::UnknownAttribute(attribute.to_string()))?;
+        let now = ::core_traits::now();
+        if policy.excise {
+            let excised = excise_trimmed(&self.transaction, &self.schema, &self.partition_map, a.0, policy.keep, now)?;
+            let trimmed = excised.len();
+            if trimmed > 0 {
+                self.wrote = true;
+                self.uncache_excised(excised)?;
+            }
+            Ok(trimmed)
+        } else {
+            let datoms = trimmed_datoms(&self.transaction, a.0, policy.keep, now)?;
+            let trimmed = datoms.len();
+            if trimmed > 0 {
+                let mut builder = TermBuilder::new();
+                for (e, _, v) in datoms {
+                    builder.retract(KnownEntid(e), a, v)?;
+                }
+                self.transact_builder(builder)?;
+            }
+            Ok(trimmed)
+        }
+    }
+
     pub fn cache(&mut self,
                  attribute: &Keyword,
                  cache_direction: CacheDirection,
@@ -382,6 +706,11 @@ impl<'a, 'c> Pullable for InProgressRead<'a, 'c> {
     where A: IntoIterator<Item=Entid> {
         self.in_progress.pull_attributes_for_entity(entity, attributes)
     }
+
+    fn lookup_entity<E>(&self, entity: E) -> Result<Option<Entity>>
+    where E: Into<EntidOrIdent> {
+        self.in_progress.lookup_entity(entity)
+    }
 }
 
 impl<'a, 'c> Queryable for InProgress<'a, 'c> {
@@ -448,6 +777,70 @@ impl<'a, 'c> Pullable for InProgress<'a, 'c> {
         pull_attributes_for_entity(&self.schema, &*(self.transaction), entity, attributes)
             .map_err(|e| e.into())
     }
+
+    fn lookup_entity<E>(&self, entity: E) -> Result<Option<Entity>>
+    where E: Into<EntidOrIdent> {
+        match resolve_entity(&self.schema, entity) {
+            Some(e) => lookup_entity(&self.schema, &*(self.transaction), Some(&self.cache), e).map_err(|e| e.into()),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Observers registered with `TxObserver::with_snapshot` can query what was just committed.
+impl Queryable for TxSnapshot {
+    fn q_once<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        let known = Known::new(self.schema(), Some(self.cache()));
+        q_once(self.sqlite(), known, query, inputs)
+    }
+
+    fn q_prepare<T>(&self, query: &str, inputs: T) -> PreparedResult
+        where T: Into<Option<QueryInputs>> {
+        let known = Known::new(self.schema(), Some(self.cache()));
+        q_prepare(self.sqlite(), known, query, inputs)
+    }
+
+    fn q_explain<T>(&self, query: &str, inputs: T) -> Result<QueryExplanation>
+        where T: Into<Option<QueryInputs>> {
+        let known = Known::new(self.schema(), Some(self.cache()));
+        q_explain(self.sqlite(), known, query, inputs)
+    }
+
+    fn lookup_values_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Vec<TypedValue>>
+        where E: Into<Entid> {
+        let known = Known::new(self.schema(), Some(self.cache()));
+        lookup_values_for_attribute(self.sqlite(), known, entity, attribute)
+    }
+
+    fn lookup_value_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Option<TypedValue>>
+        where E: Into<Entid> {
+        let known = Known::new(self.schema(), Some(self.cache()));
+        lookup_value_for_attribute(self.sqlite(), known, entity, attribute)
+    }
+}
+
+impl Pullable for TxSnapshot {
+    fn pull_attributes_for_entities<E, A>(&self, entities: E, attributes: A) -> Result<BTreeMap<Entid, ValueRc<StructuredMap>>>
+    where E: IntoIterator<Item=Entid>,
+          A: IntoIterator<Item=Entid> {
+        pull_attributes_for_entities(self.schema(), self.sqlite(), entities, attributes)
+            .map_err(|e| e.into())
+    }
+
+    fn pull_attributes_for_entity<A>(&self, entity: Entid, attributes: A) -> Result<StructuredMap>
+    where A: IntoIterator<Item=Entid> {
+        pull_attributes_for_entity(self.schema(), self.sqlite(), entity, attributes)
+            .map_err(|e| e.into())
+    }
+
+    fn lookup_entity<E>(&self, entity: E) -> Result<Option<Entity>>
+    where E: Into<EntidOrIdent> {
+        match resolve_entity(self.schema(), entity) {
+            Some(e) => lookup_entity(self.schema(), self.sqlite(), Some(self.cache()), e).map_err(|e| e.into()),
+            None => Ok(None),
+        }
+    }
 }
 
 impl<'a, 'c> HasSchema for InProgressRead<'a, 'c> {
@@ -10,11 +10,14 @@ use std::sync::{
 };
 
 use mentat_core::{
+    AttributeIndexes,
     Schema,
+    Statistics,
 };
 
 use mentat_db::{
     PartitionMap,
+    StoreGeneration,
 };
 
 use mentat_db::cache::{
@@ -26,16 +29,30 @@ pub struct Metadata {
     pub partition_map: PartitionMap,
     pub schema: Arc<Schema>,
     pub attribute_cache: SQLiteAttributeCache,
+
+    /// The store's generation when this metadata was read.  If the store has moved on, another
+    /// connection has written to it, and this metadata is stale.
+    pub store_generation: StoreGeneration,
+
+    /// How many datoms each attribute had when these were last counted, for planning queries.
+    /// Unlike the rest of the metadata, these aren't kept up to date.
+    pub statistics: Arc<Statistics>,
+
+    /// The attributes with indexes of their own, which queries read from.
+    pub indexes: AttributeIndexes,
 }
 
 impl Metadata {
     // Intentionally not public.
-    pub fn new(generation: u64, partition_map: PartitionMap, schema: Arc<Schema>, cache: SQLiteAttributeCache) -> Metadata {
+    pub fn new(generation: u64, partition_map: PartitionMap, schema: Arc<Schema>, cache: SQLiteAttributeCache, store_generation: StoreGeneration, statistics: Arc<Statistics>, indexes: AttributeIndexes) -> Metadata {
         Metadata {
+            store_generation: store_generation,
             generation: generation,
             partition_map: partition_map,
             schema: schema,
             attribute_cache: cache,
+            statistics: statistics,
+            indexes: indexes,
         }
     }
 }
@@ -0,0 +1,255 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Queries run at one of two priorities.  Interactive queries -- the default -- are the ones a
+//! user is waiting on.  Background queries are sweeps that can take as long as they like, but
+//! shouldn't make a keystroke-driven lookup wait for them.
+//!
+//! While an interactive query or a write transaction is pending against a database, background
+//! queries against the same database pause.  They check at regular intervals, using SQLite's
+//! progress handler, and resume when there's no interactive work left.  Mentat opens databases in
+//! WAL mode, so a paused reader doesn't hold up writers.
+//!
+//! A background query pauses for at most `MAX_PAUSE` in all, so that a long-lived write transaction
+//! slows it down rather than starving it, and never pauses for interactive work that its own thread
+//! is doing, which could never finish while it waits.
+//!
+//! Like `write_holder`, this only knows about work in the current process, and databases are
+//! identified by their canonical path; in-memory stores always run at full speed.
+
+use std::cell::{
+    Cell,
+    RefCell,
+};
+
+use std::collections::{
+    BTreeMap,
+};
+
+use std::fs;
+
+use std::marker::PhantomData;
+
+use std::os::raw::{
+    c_int,
+    c_void,
+};
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use std::ptr;
+
+use std::sync::{
+    Condvar,
+    Mutex,
+};
+
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use ffi;
+
+use rusqlite;
+
+/// How many SQLite virtual machine instructions a background query executes between checks for
+/// pending interactive work.
+const CHECKPOINT_INTERVAL: c_int = 1000;
+
+/// The longest a background query pauses, in total, before it runs at full speed regardless.
+pub const MAX_PAUSE: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref PENDING: Mutex<BTreeMap<PathBuf, usize>> = Mutex::new(BTreeMap::new());
+    static ref PENDING_CHANGED: Condvar = Condvar::new();
+}
+
+thread_local! {
+    /// The interactive work this thread is doing, which its own background queries mustn't wait
+    /// for.
+    static PENDING_HERE: RefCell<BTreeMap<PathBuf, usize>> = RefCell::new(BTreeMap::new());
+}
+
+/// The same database can be reached by many paths; `PENDING` is keyed by the canonical one.  A
+/// path we can't canonicalize is used as it is.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn pending_here(path: &Path) -> bool {
+    // The thread-local might already be gone if we're called while the thread exits.
+    PENDING_HERE.try_with(|here| here.borrow().get(path).map_or(false, |count| *count > 0))
+                .unwrap_or(false)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QueryPriority {
+    /// Run at full speed, pausing background queries until done.
+    Interactive,
+
+    /// Pause whenever interactive work is pending.
+    Background,
+}
+
+impl Default for QueryPriority {
+    fn default() -> QueryPriority {
+        QueryPriority::Interactive
+    }
+}
+
+/// Return true if an interactive query or write transaction is pending against the database at
+/// `path`.
+pub fn interactive_work_pending(path: &Path) -> bool {
+    PENDING.lock().unwrap()
+           .get(&canonical(path))
+           .map_or(false, |count| *count > 0)
+}
+
+/// Block until no interactive work is pending against the database at `path`, or until the
+/// query's remaining allowance of `paused` runs out, unless this thread is doing that work itself.
+fn yield_to_interactive(path: &Path, paused: &Cell<Duration>) {
+    if paused.get() >= MAX_PAUSE || pending_here(path) {
+        return;
+    }
+
+    // This runs inside a SQLite callback, where we mustn't panic.  If another thread poisoned the
+    // lock, just carry on.
+    let mut pending = match PENDING.lock() {
+        Ok(pending) => pending,
+        Err(_) => return,
+    };
+    let started = Instant::now();
+    let deadline = started + (MAX_PAUSE - paused.get());
+    while pending.get(path).map_or(false, |count| *count > 0) {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        pending = match PENDING_CHANGED.wait_timeout(pending, deadline - now) {
+            Ok((pending, _)) => pending,
+            Err(_) => break,
+        };
+    }
+    paused.set(paused.get() + started.elapsed());
+}
+
+/// Records interactive work against a database until dropped.  Interactive queries hold one for
+/// as long as they run, and a write `InProgress` owns one for as long as it's open.
+///
+/// A guard is dropped on the thread that made it, which is how that thread's own background
+/// queries know not to wait for it.
+#[derive(Debug)]
+pub struct InteractiveGuard {
+    path: PathBuf,
+
+    // Not `Send`.
+    _thread: PhantomData<*const ()>,
+}
+
+impl InteractiveGuard {
+    pub fn new(path: PathBuf) -> InteractiveGuard {
+        let path = canonical(&path);
+        *PENDING.lock().unwrap().entry(path.clone()).or_insert(0) += 1;
+        PENDING_HERE.with(|here| *here.borrow_mut().entry(path.clone()).or_insert(0) += 1);
+        InteractiveGuard {
+            path: path,
+            _thread: PhantomData,
+        }
+    }
+}
+
+impl Drop for InteractiveGuard {
+    fn drop(&mut self) {
+        let path = &self.path;
+        let _ = PENDING_HERE.try_with(|here| {
+            let mut here = here.borrow_mut();
+            let done = match here.get_mut(path) {
+                Some(count) => {
+                    *count -= 1;
+                    *count == 0
+                },
+                None => false,
+            };
+            if done {
+                here.remove(path);
+            }
+        });
+
+        // Don't panic while unwinding just because another thread poisoned the lock.
+        if let Ok(mut pending) = PENDING.lock() {
+            let done = match pending.get_mut(&self.path) {
+                Some(count) => {
+                    *count -= 1;
+                    *count == 0
+                },
+                None => false,
+            };
+            if done {
+                pending.remove(&self.path);
+                PENDING_CHANGED.notify_all();
+            }
+        }
+    }
+}
+
+/// What a background query's progress handler needs to know.
+struct Checkpoints {
+    path: PathBuf,
+
+    /// How long the query has paused so far.
+    paused: Cell<Duration>,
+}
+
+extern "C" fn checkpoint(checkpoints: *mut c_void) -> c_int {
+    let checkpoints = unsafe { &*(checkpoints as *const Checkpoints) };
+    yield_to_interactive(&checkpoints.path, &checkpoints.paused);
+
+    // Zero means 'continue'.
+    0
+}
+
+/// Makes every statement run on a connection pause for pending interactive work until dropped.
+pub struct BackgroundGuard<'c> {
+    sqlite: &'c rusqlite::Connection,
+
+    // Boxed so that the address we hand to SQLite is stable.
+    checkpoints: Box<Checkpoints>,
+}
+
+impl<'c> BackgroundGuard<'c> {
+    pub fn new(sqlite: &'c rusqlite::Connection, path: PathBuf) -> BackgroundGuard<'c> {
+        let checkpoints = Box::new(Checkpoints {
+            path: canonical(&path),
+            paused: Cell::new(Duration::from_secs(0)),
+        });
+        unsafe {
+            ffi::sqlite3_progress_handler(sqlite.handle(),
+                                          CHECKPOINT_INTERVAL,
+                                          Some(checkpoint),
+                                          &*checkpoints as *const Checkpoints as *mut c_void);
+        }
+        BackgroundGuard {
+            sqlite: sqlite,
+            checkpoints: checkpoints,
+        }
+    }
+}
+
+impl<'c> Drop for BackgroundGuard<'c> {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::sqlite3_progress_handler(self.sqlite.handle(), 0, None, ptr::null_mut());
+        }
+    }
+}
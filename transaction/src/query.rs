@@ -11,18 +11,51 @@
 use rusqlite;
 use rusqlite::types::ToSql;
 
+use std::collections::{
+    BTreeMap,
+};
+
 use std::rc::Rc;
 
+use std::sync::{
+    Arc,
+};
+
 use core_traits::{
+    AttributeBitFlags,
     Binding,
     Entid,
     KnownEntid,
     TypedValue,
+    ValueTypeSet,
 };
 
 use mentat_core::{
+    CachedAttributes,
+    DateTime,
+    FromMicros,
     HasSchema,
     Schema,
+    ToMicros,
+    Utc,
+};
+
+use mentat_core::metrics;
+
+use edn::entities::{
+    EntidOrIdent,
+};
+
+use mentat_db::{
+    TX0,
+    TypedSQLValue,
+    has_large_values,
+    read_generation,
+    resolved_value_sql,
+};
+
+use mentat_db::entids::{
+    DB_TX_INSTANT,
 };
 
 use mentat_query_algebrizer::{
@@ -55,6 +88,7 @@ use edn::query::{
 use mentat_query_projector::{
     ConstantProjector,
     Projector,
+    query_columns,
 };
 
 use mentat_query_projector::translate::{
@@ -62,18 +96,27 @@ use mentat_query_projector::translate::{
     query_to_select,
 };
 
+use mentat_query_sql::{
+    format_select_var,
+};
+
 use mentat_sql::{
+    SQLArg,
     SQLQuery,
 };
 
+use query_cache;
+
 pub use mentat_query_algebrizer::{
     Known,
 };
 
 pub use mentat_query_projector::{
+    ColumnMetadata,     // Describes each column of a `QueryOutput`.
     QueryOutput,        // Includes the columns/find spec.
     QueryResults,       // The results themselves.
     RelResult,
+    binding_to_edn,     // Renders a single result value as EDN.
 };
 
 use public_traits::errors::{
@@ -84,9 +127,19 @@ use public_traits::errors::{
 pub type QueryExecutionResult = Result<QueryOutput>;
 pub type PreparedResult<'sqlite> = Result<PreparedQuery<'sqlite>>;
 
+/// A query that has been translated, and, unless it's known to be empty or constant, compiled to
+/// a SQLite statement, which is kept, and rebound with each run's inputs.
+///
+/// A query is translated against the schema as it was when it was prepared.  If another
+/// connection to the store changes the schema, running the query fails with
+/// `PreparedQuerySchemaMismatch`, and it must be prepared again.  So does running a query
+/// prepared before any string was offloaded to `large_values` once one has been.
 pub enum PreparedQuery<'sqlite> {
     Empty {
         find_spec: Rc<FindSpec>,
+        columns: Rc<Vec<ColumnMetadata>>,
+        connection: &'sqlite rusqlite::Connection,
+        schema_generation: i64,
     },
     Constant {
         select: ConstantProjector,
@@ -95,27 +148,194 @@ pub enum PreparedQuery<'sqlite> {
         statement: rusqlite::Statement<'sqlite>,
         schema: Schema,
         connection: &'sqlite rusqlite::Connection,
-        args: Vec<(String, Rc<rusqlite::types::Value>)>,
-        projector: Box<Projector>,
+        schema_generation: i64,
+        large_values: bool,
+        args: Vec<(String, SQLArg)>,
+        parameters: BTreeMap<Variable, ValueTypeSet>,
+        projector: Rc<Projector>,
+    },
+}
+
+/// The parts of a prepared query that don't depend on a SQLite connection, which we can keep to
+/// prepare the same query again.  See `query_cache`.
+#[derive(Clone)]
+pub(crate) enum PreparedTranslation {
+    Empty {
+        find_spec: Rc<FindSpec>,
+        columns: Rc<Vec<ColumnMetadata>>,
+    },
+    Bound {
+        sql: String,
+        /// Whether `sql` matches strings offloaded to `large_values`.  See `Known::large_values`.
+        large_values: bool,
+        args: Vec<(String, SQLArg)>,
+        parameters: BTreeMap<Variable, ValueTypeSet>,
+        projector: Rc<Projector>,
     },
 }
 
+impl PreparedTranslation {
+    fn prepare<'sqlite>(self, sqlite: &'sqlite rusqlite::Connection, schema: &Schema) -> PreparedResult<'sqlite> {
+        let schema_generation = read_generation(sqlite)?.schema;
+        match self {
+            PreparedTranslation::Empty { find_spec, columns } => {
+                Ok(PreparedQuery::Empty {
+                    find_spec,
+                    columns,
+                    connection: sqlite,
+                    schema_generation,
+                })
+            },
+            PreparedTranslation::Bound { sql, large_values, args, parameters, projector } => {
+                let statement = sqlite.prepare(sql.as_str())?;
+                Ok(PreparedQuery::Bound {
+                    statement,
+                    schema: schema.clone(),
+                    connection: sqlite,
+                    schema_generation,
+                    large_values,
+                    args,
+                    parameters,
+                    projector,
+                })
+            },
+        }
+    }
+}
+
+/// A translated query: either one we can keep, or a constant, which we can't.
+enum Translation {
+    Constant(ConstantProjector),
+    Prepared(PreparedTranslation),
+}
+
+/// The values of `parameters`, taken from `inputs`, to bind alongside the statement's own
+/// arguments.  Parameters that the statement doesn't mention -- say, because they only appeared
+/// in a branch the algebrizer pruned -- are checked but not bound.
+fn bind_parameters(statement: &rusqlite::Statement,
+                   parameters: &BTreeMap<Variable, ValueTypeSet>,
+                   inputs: Option<QueryInputs>) -> Result<Vec<(String, SQLArg)>> {
+    let inputs = inputs.unwrap_or_default();
+    let missing: ::std::collections::BTreeSet<String> =
+        parameters.keys()
+                  .filter(|var| !inputs.values().contains_key(var))
+                  .map(|var| var.to_string())
+                  .collect();
+    if !missing.is_empty() {
+        bail!(MentatError::UnboundVariables(missing));
+    }
+
+    let mut bound = Vec::with_capacity(parameters.len());
+    for (var, types) in parameters.iter() {
+        let value = &inputs.values()[var];
+        if !types.contains(value.value_type()) {
+            bail!(MentatError::ParameterTypeMismatch(var.to_string(), types.clone(), value.value_type()));
+        }
+        let name = format!("${}", format_select_var(var.as_str()));
+        if statement.parameter_index(&name)?.is_none() {
+            continue;
+        }
+        bound.push((name, SQLArg::from_typed_value(value)));
+    }
+    Ok(bound)
+}
+
+/// Fail if the store's schema has changed since a query was translated against it.
+fn check_schema_generation(sqlite: &rusqlite::Connection, schema_generation: i64) -> Result<()> {
+    if read_generation(sqlite)?.schema != schema_generation {
+        bail!(MentatError::PreparedQuerySchemaMismatch);
+    }
+    Ok(())
+}
+
+/// Fail if a string has been offloaded since a query was translated on the understanding that
+/// none had been, since the query wouldn't match it.
+fn check_large_values(sqlite: &rusqlite::Connection, large_values: bool) -> Result<()> {
+    if !large_values && has_large_values(sqlite)? {
+        bail!(MentatError::PreparedQuerySchemaMismatch);
+    }
+    Ok(())
+}
+
 impl<'sqlite> PreparedQuery<'sqlite> {
-    pub fn run<T>(&mut self, _inputs: T) -> QueryExecutionResult where T: Into<Option<QueryInputs>> {
+    /// Run this query.  `inputs` must supply a value for each `:in` variable that wasn't given
+    /// one when the query was prepared.
+    pub fn run<T>(&mut self, inputs: T) -> QueryExecutionResult where T: Into<Option<QueryInputs>> {
         match self {
-            &mut PreparedQuery::Empty { ref find_spec } => {
-                Ok(QueryOutput::empty(find_spec))
+            &mut PreparedQuery::Empty { ref find_spec, ref columns, connection, schema_generation } => {
+                check_schema_generation(connection, schema_generation)?;
+                Ok(QueryOutput::empty(find_spec, columns))
             },
             &mut PreparedQuery::Constant { ref select } => {
                 select.project_without_rows().map_err(|e| e.into())
             },
-            &mut PreparedQuery::Bound { ref mut statement, ref schema, ref connection, ref args, ref projector } => {
-                let rows = run_statement(statement, args)?;
-                projector.project(schema, connection, rows)
-                         .map_err(|e| e.into())
+            &mut PreparedQuery::Bound { ref mut statement, ref schema, ref connection, schema_generation, large_values, ref args, ref parameters, ref projector } => {
+                check_schema_generation(connection, schema_generation)?;
+                check_large_values(connection, large_values)?;
+                let parameters = bind_parameters(statement, parameters, inputs.into())?;
+                metrics::measure(metrics::QUERIES_EXECUTED, metrics::QUERY_DURATION, || {
+                    let rows = run_statement(statement, args, &parameters)?;
+                    projector.project(schema, connection, rows)
+                             .map_err(|e| e.into())
+                })
             }
         }
     }
+
+    /// Run this query, returning its results one row at a time rather than all at once.  Rows
+    /// are read from the underlying SQLite cursor as the returned iterator is advanced.
+    pub fn rows<T>(&mut self, inputs: T) -> Result<QueryRows> where T: Into<Option<QueryInputs>> {
+        match self {
+            &mut PreparedQuery::Empty { connection, schema_generation, .. } => {
+                check_schema_generation(connection, schema_generation)?;
+                Ok(QueryRows::Materialized(vec![].into_iter()))
+            },
+            &mut PreparedQuery::Constant { ref select } => {
+                let output = select.project_without_rows()?;
+                Ok(QueryRows::Materialized(output.results.into_rows().into_iter()))
+            },
+            &mut PreparedQuery::Bound { ref mut statement, ref schema, ref connection, schema_generation, large_values, ref args, ref parameters, ref projector } => {
+                check_schema_generation(connection, schema_generation)?;
+                check_large_values(connection, large_values)?;
+                let parameters = bind_parameters(statement, parameters, inputs.into())?;
+                let rows = run_statement(statement, args, &parameters)?;
+                Ok(QueryRows::Streaming {
+                    rows,
+                    schema,
+                    connection: *connection,
+                    projector: &**projector,
+                })
+            },
+        }
+    }
+}
+
+/// The results of a query, one row at a time.  Each row has one binding for each element of the
+/// query's find spec: a scalar or coll query yields rows of length one.
+pub enum QueryRows<'stmt> {
+    Materialized(::std::vec::IntoIter<Vec<Binding>>),
+    Streaming {
+        rows: rusqlite::Rows<'stmt>,
+        schema: &'stmt Schema,
+        connection: &'stmt rusqlite::Connection,
+        projector: &'stmt Projector,
+    },
+}
+
+impl<'stmt> Iterator for QueryRows<'stmt> {
+    type Item = Result<Vec<Binding>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            &mut QueryRows::Materialized(ref mut rows) => rows.next().map(Ok),
+            &mut QueryRows::Streaming { ref mut rows, schema, connection, projector } => {
+                rows.next().map(|row| {
+                    let row = row?;
+                    projector.project_row(schema, connection, row).map_err(|e| e.into())
+                })
+            },
+        }
+    }
 }
 
 pub trait IntoResult {
@@ -191,7 +411,8 @@ fn fetch_values<'sqlite>
  known: Known,
  entity: Entid,
  attribute: Entid,
- only_one: bool) -> QueryExecutionResult {
+ only_one: bool,
+ basis: Option<DatomsBasis>) -> QueryExecutionResult {
     let v = Variable::from_valid_name("?v");
 
     // This should never fail.
@@ -209,7 +430,7 @@ fn fetch_values<'sqlite>
 
     let algebrized = algebrize_query(known, query, None)?;
 
-    run_algebrized_query(known, sqlite, algebrized)
+    run_algebrized_query(known, sqlite, algebrized, basis)
 }
 
 fn lookup_attribute(schema: &Schema, attribute: &Keyword) -> Result<KnownEntid> {
@@ -232,9 +453,11 @@ pub fn lookup_value<'sqlite, 'schema, 'cache, E, A>
     let attrid = attribute.into();
 
     if known.is_attribute_cached_forward(attrid) {
+        metrics::increment_counter(metrics::CACHE_HITS, 1);
         Ok(known.get_value_for_entid(known.schema, attrid, entid).cloned())
     } else {
-        fetch_values(sqlite, known, entid, attrid, true)
+        metrics::increment_counter(metrics::CACHE_MISSES, 1);
+        fetch_values(sqlite, known, entid, attrid, true, None)
             .into_scalar_result()
             // Safe to unwrap: we never retrieve structure.
             .map(|r| r.map(|v| v.into_scalar().unwrap()))
@@ -252,11 +475,13 @@ pub fn lookup_values<'sqlite, E, A>
     let attrid = attribute.into();
 
     if known.is_attribute_cached_forward(attrid) {
+        metrics::increment_counter(metrics::CACHE_HITS, 1);
         Ok(known.get_values_for_entid(known.schema, attrid, entid)
                 .cloned()
                 .unwrap_or_else(|| vec![]))
     } else {
-        fetch_values(sqlite, known, entid, attrid, false)
+        metrics::increment_counter(metrics::CACHE_MISSES, 1);
+        fetch_values(sqlite, known, entid, attrid, false, None)
             .into_coll_result()
             // Safe to unwrap: we never retrieve structure.
             .map(|v| v.into_iter().map(|x| x.into_scalar().unwrap()).collect())
@@ -287,17 +512,278 @@ pub fn lookup_values_for_attribute<'sqlite, 'attribute, E>
     lookup_values(sqlite, known, entity.into(), attribute)
 }
 
+/// A single assertion or retraction of a value for an entity and attribute, as recorded in the
+/// transaction log.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Provenance {
+    /// The transaction that made the change.
+    pub tx: Entid,
+    /// The `:db/txInstant` of that transaction.
+    pub tx_instant: DateTime<Utc>,
+    pub value: TypedValue,
+    /// `true` if the value was asserted, `false` if it was retracted.
+    pub added: bool,
+}
+
+/// Return every change to the provided entity and attribute in the transaction log, ordered by
+/// transaction.  Within a single transaction, retractions are ordered before assertions.
+/// If `attribute` doesn't name an attribute, an error is returned.
+pub fn lookup_provenance_for_attribute<'sqlite, 'attribute, E>
+(sqlite: &'sqlite rusqlite::Connection,
+ schema: &Schema,
+ entity: E,
+ attribute: &'attribute Keyword) -> Result<Vec<Provenance>>
+ where E: Into<Entid> {
+    let attrid = lookup_attribute(schema, attribute)?;
+    let fulltext = schema.attribute_for_entid(attrid.0).map(|a| a.fulltext).unwrap_or(false);
+
+    // Fulltext values are stored in the log as rowids into `fulltext_values`, and large values as
+    // ids into `large_values`.
+    let value = if fulltext {
+        "(SELECT text FROM fulltext_values WHERE rowid = t.v)".to_string()
+    } else {
+        resolved_value_sql("t")
+    };
+    let sql = format!(r#"SELECT t.tx, i.v, {}, t.value_type_tag, t.added
+                         FROM transactions AS t, datoms AS i
+                         WHERE t.e = ? AND t.a = ? AND i.e = t.tx AND i.a = ?
+                         ORDER BY t.tx ASC, t.added ASC"#, value);
+
+    let mut stmt = sqlite.prepare(&sql)?;
+    let entid = entity.into();
+    let rows = stmt.query_and_then(&[&entid, &attrid.0, &DB_TX_INSTANT], |row| -> Result<Provenance> {
+        let tx: Entid = row.get_checked(0)?;
+        let tx_instant = DateTime::<Utc>::from_micros(row.get_checked(1)?);
+        let value_type_tag: i32 = row.get_checked(3)?;
+        let value = TypedValue::from_sql_value_pair(row.get_checked(2)?, value_type_tag)?;
+        let added: bool = row.get_checked(4)?;
+        Ok(Provenance { tx, tx_instant, value, added })
+    })?;
+    rows.collect()
+}
+
+/// A state of the store, other than the present, for a query to see.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DatomsBasis {
+    /// The datoms that held once the given transaction was committed.
+    AsOf(Entid),
+
+    /// The datoms that hold now and were asserted after the given transaction: what an
+    /// incremental consumer that has seen everything up to that transaction hasn't seen yet.
+    Since(Entid),
+}
+
+/// The bound parameter that holds a basis's transaction.  Variables are bound as `$i…`, so this
+/// can't collide with one.
+const BASIS_TX_PARAMETER: &'static str = "$basis_tx";
+
+/// The attributes with the given flag, as a SQL list.
+fn attributes_with_flag(schema: &Schema, flag: AttributeBitFlags) -> String {
+    let flag = flag as u8;
+    let entids: Vec<String> = schema.attribute_map
+                                    .iter()
+                                    .filter(|&(_, attribute)| attribute.flags() & flag != 0)
+                                    .map(|(entid, _)| entid.to_string())
+                                    .collect();
+    entids.join(", ")
+}
+
+/// Rewrite `query` to read the datoms of `basis` rather than those of the present.
+///
+/// Translated queries read datoms from `datoms`, `fulltext_datoms`, `all_datoms`, and
+/// `transactions`.  We shadow each of those with a common table expression of the same name.
+///
+/// As of a transaction, datoms are derived from the transaction log: a datom holds if the last
+/// change to it at or before that transaction was an assertion.  The index flags of each datom
+/// follow the current schema.  None of this is indexed, so querying the past is much slower than
+/// querying the present.
+///
+/// Since a transaction, datoms are those of the present, constrained to later transactions.
+/// The tables themselves are named with their schema, which common table expressions can't
+/// shadow.
+fn restrict_to_basis(schema: &Schema, basis: Option<DatomsBasis>, query: SQLQuery) -> SQLQuery {
+    let (tx, transactions, datoms) = match basis {
+        None => return query,
+        Some(DatomsBasis::AsOf(tx)) => {
+            let transactions = format!(
+                r#"SELECT e, a, v, value_type_tag, tx, added FROM timelined_transactions
+                   WHERE timeline IS 0 AND tx <= {tx}"#,
+                tx = BASIS_TX_PARAMETER);
+            let datoms = format!(
+                r#"SELECT e, a, v, tx, value_type_tag,
+                          a IN ({avet}) AS index_avet, a IN ({vaet}) AS index_vaet,
+                          a IN ({fulltext}) AS index_fulltext,
+                          a IN ({unique}) AS unique_value, a IN ({folded}) AS unique_folded
+                     FROM (SELECT e, a, v, value_type_tag, max(tx) AS tx, added FROM `transactions`
+                           GROUP BY e, a, value_type_tag, v)
+                     WHERE added IS NOT 0"#,
+                avet = attributes_with_flag(schema, AttributeBitFlags::IndexAVET),
+                vaet = attributes_with_flag(schema, AttributeBitFlags::IndexVAET),
+                fulltext = attributes_with_flag(schema, AttributeBitFlags::IndexFulltext),
+                unique = attributes_with_flag(schema, AttributeBitFlags::UniqueValue),
+                folded = attributes_with_flag(schema, AttributeBitFlags::UniqueFolded));
+            (tx, transactions, datoms)
+        },
+        Some(DatomsBasis::Since(tx)) => {
+            let transactions = format!(
+                r#"SELECT e, a, v, value_type_tag, tx, added FROM main.transactions WHERE tx > {tx}"#,
+                tx = BASIS_TX_PARAMETER);
+            let datoms = format!(
+                r#"SELECT * FROM main.datoms WHERE tx > {tx}"#,
+                tx = BASIS_TX_PARAMETER);
+            (tx, transactions, datoms)
+        },
+    };
+
+    let tables = format!(
+        r#"`transactions` AS ({transactions}),
+           `datoms` AS ({datoms}),
+           `fulltext_datoms` AS (SELECT e, a, fulltext_values.text AS v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value, unique_folded
+                                   FROM `datoms`, fulltext_values
+                                   WHERE `datoms`.index_fulltext IS NOT 0 AND `datoms`.v = fulltext_values.rowid),
+           `all_datoms` AS (SELECT e, a, v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value, unique_folded
+                              FROM `datoms`
+                              WHERE index_fulltext IS 0
+                            UNION ALL
+                            SELECT e, a, v, tx, value_type_tag, index_avet, index_vaet, index_fulltext, unique_value, unique_folded
+                              FROM `fulltext_datoms`)"#,
+        transactions = transactions,
+        datoms = datoms);
+
+    // The query might have common table expressions of its own, which can refer to ours.
+    let SQLQuery { sql, mut args } = query;
+    let sql = if sql.starts_with("WITH RECURSIVE ") {
+        format!("WITH RECURSIVE {}, {}", tables, &sql["WITH RECURSIVE ".len()..])
+    } else if sql.starts_with("WITH ") {
+        format!("WITH {}, {}", tables, &sql["WITH ".len()..])
+    } else {
+        format!("WITH {} {}", tables, sql)
+    };
+    args.push((BASIS_TX_PARAMETER.to_string(), SQLArg::Integer(tx)));
+    SQLQuery { sql, args }
+}
+
+/// The last transaction committed at or before `instant`, if there is one.
+pub fn tx_for_instant(sqlite: &rusqlite::Connection, instant: &DateTime<Utc>) -> Result<Option<Entid>> {
+    let tx: Option<Entid> = sqlite.query_row("SELECT max(e) FROM datoms WHERE a = ? AND v <= ?",
+                                             &[&DB_TX_INSTANT, &instant.to_micros()],
+                                             |row| row.get_checked(0))??;
+    Ok(tx)
+}
+
+/// A single assertion or retraction made by a transaction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Datom {
+    pub e: Entid,
+    /// The attribute, by ident if the schema knows one.
+    pub a: EntidOrIdent,
+    pub v: TypedValue,
+    pub tx: Entid,
+    /// `true` if the datom was asserted, `false` if it was retracted.
+    pub added: bool,
+}
+
+/// Return the datoms asserted and retracted by the transaction `tx`, including its
+/// `:db/txInstant`, ordered by entity and attribute.  Within an entity and attribute, retractions
+/// are ordered before assertions.  An unknown transaction made no changes.
+pub fn lookup_tx_data(sqlite: &rusqlite::Connection, schema: &Schema, tx: Entid) -> Result<Vec<Datom>> {
+    // Fulltext values are stored in the log as rowids into `fulltext_values`, and large values as
+    // ids into `large_values`.
+    let sql = format!(r#"SELECT t.e, t.a,
+                                CASE WHEN t.a IN ({}) THEN (SELECT text FROM fulltext_values WHERE rowid = t.v) ELSE {} END,
+                                t.value_type_tag, t.added
+                         FROM transactions AS t
+                         WHERE t.tx = ?
+                         ORDER BY t.e ASC, t.a ASC, t.added ASC, t.value_type_tag ASC, t.v ASC"#,
+                      attributes_with_flag(schema, AttributeBitFlags::IndexFulltext),
+                      resolved_value_sql("t"));
+
+    let mut stmt = sqlite.prepare(&sql)?;
+    let rows = stmt.query_and_then(&[&tx], |row| -> Result<Datom> {
+        let e: Entid = row.get_checked(0)?;
+        let a: Entid = row.get_checked(1)?;
+        let value_type_tag: i32 = row.get_checked(3)?;
+        let v = TypedValue::from_sql_value_pair(row.get_checked(2)?, value_type_tag)?;
+        let added: bool = row.get_checked(4)?;
+        let a = schema.get_ident(a).map_or(EntidOrIdent::Entid(a), |ident| EntidOrIdent::Ident(ident.clone()));
+        Ok(Datom { e, a, v, tx, added })
+    })?;
+    rows.collect()
+}
+
+/// A transaction as recorded in the transaction log.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TxData {
+    pub tx: Entid,
+    pub instant: DateTime<Utc>,
+    /// Ordered as `lookup_tx_data` orders them, and including the `:db/txInstant`.
+    pub datoms: Vec<Datom>,
+}
+
+/// Return the transaction `tx` as the log records it, or `None` if the log doesn't know it.
+pub fn lookup_tx(sqlite: &rusqlite::Connection, schema: &Schema, tx: Entid) -> Result<Option<TxData>> {
+    let datoms = lookup_tx_data(sqlite, schema, tx)?;
+    let instant = datoms.iter().filter_map(|datom| {
+        let is_instant = match datom.a {
+            EntidOrIdent::Entid(a) => a == DB_TX_INSTANT,
+            EntidOrIdent::Ident(ref a) => schema.get_entid(a).map_or(false, |a| a.0 == DB_TX_INSTANT),
+        };
+        match datom.v {
+            TypedValue::Instant(instant) if is_instant && datom.e == tx && datom.added => Some(instant),
+            _ => None,
+        }
+    }).next();
+    Ok(instant.map(|instant| TxData { tx, instant, datoms }))
+}
+
+/// Return the ids of up to `limit` transactions in the log, in the order they were committed,
+/// starting with `from_tx` and stopping before `to_tx`, if given.
+pub fn lookup_tx_ids(sqlite: &rusqlite::Connection, from_tx: Entid, to_tx: Option<Entid>, limit: usize) -> Result<Vec<Entid>> {
+    let mut stmt = sqlite.prepare("SELECT DISTINCT tx FROM transactions WHERE tx >= ? AND tx < ? ORDER BY tx ASC LIMIT ?")?;
+    let to_tx = to_tx.unwrap_or(Entid::max_value());
+    let limit = limit as i64;
+    let rows = stmt.query_and_then(&[&from_tx, &to_tx, &limit], |row| -> Result<Entid> {
+        Ok(row.get_checked(0)?)
+    })?;
+    rows.collect()
+}
+
+/// Return every datom currently in the store other than those the bootstrap transaction asserted
+/// and the `:db/txInstant` of each transaction, ordered by entity, attribute, and value.
+pub fn lookup_datoms(sqlite: &rusqlite::Connection, schema: &Schema) -> Result<Vec<Datom>> {
+    let sql = format!(r#"SELECT d.e, d.a, {}, d.value_type_tag, d.tx
+                         FROM all_datoms AS d
+                         WHERE d.tx > ? AND d.a IS NOT ?
+                         ORDER BY d.e ASC, d.a ASC, d.value_type_tag ASC, d.v ASC"#,
+                      resolved_value_sql("d"));
+
+    let mut stmt = sqlite.prepare(&sql)?;
+    let rows = stmt.query_and_then(&[&TX0, &DB_TX_INSTANT], |row| -> Result<Datom> {
+        let e: Entid = row.get_checked(0)?;
+        let a: Entid = row.get_checked(1)?;
+        let v = TypedValue::from_sql_value_pair(row.get_checked(2)?, row.get_checked(3)?)?;
+        let tx: Entid = row.get_checked(4)?;
+        let a = schema.get_ident(a).map_or(EntidOrIdent::Entid(a), |ident| EntidOrIdent::Ident(ident.clone()));
+        Ok(Datom { e, a, v, tx, added: true })
+    })?;
+    rows.collect()
+}
+
+/// Run `statement`, binding both its own `args` and the values of its `parameters`.  Each is
+/// bound by reference.
 fn run_statement<'sqlite, 'stmt, 'bound>
 (statement: &'stmt mut rusqlite::Statement<'sqlite>,
- bindings: &'bound [(String, Rc<rusqlite::types::Value>)]) -> Result<rusqlite::Rows<'stmt>> {
+ args: &'bound [(String, SQLArg)],
+ parameters: &'bound [(String, SQLArg)]) -> Result<rusqlite::Rows<'stmt>> {
 
-    let rows = if bindings.is_empty() {
+    let rows = if args.is_empty() && parameters.is_empty() {
         statement.query(&[])?
     } else {
         let refs: Vec<(&str, &ToSql)> =
-            bindings.iter()
-                    .map(|&(ref k, ref v)| (k.as_str(), v.as_ref() as &ToSql))
-                    .collect();
+            args.iter()
+                .chain(parameters.iter())
+                .map(|&(ref k, ref v)| (k.as_str(), v as &ToSql))
+                .collect();
         statement.query_named(&refs)?
     };
     Ok(rows)
@@ -306,12 +792,12 @@ fn run_statement<'sqlite, 'stmt, 'bound>
 fn run_sql_query<'sqlite, 'sql, 'bound, T, F>
 (sqlite: &'sqlite rusqlite::Connection,
  sql: &'sql str,
- bindings: &'bound [(String, Rc<rusqlite::types::Value>)],
+ bindings: &'bound [(String, SQLArg)],
  mut mapper: F) -> Result<Vec<T>>
     where F: FnMut(&rusqlite::Row) -> T
 {
     let mut statement = sqlite.prepare(sql)?;
-    let mut rows = run_statement(&mut statement, &bindings)?;
+    let mut rows = run_statement(&mut statement, bindings, &[])?;
     let mut result = vec![];
     while let Some(row_or_error) = rows.next() {
         result.push(mapper(&row_or_error?));
@@ -328,15 +814,28 @@ fn algebrize_query_str<'query, T>
     algebrize_query(known, parsed, inputs)
 }
 
+/// Like `algebrize_query_str`, but leaves any `:in` variables without values to be bound as
+/// parameters when the query is run.
+fn algebrize_prepared_query_str<'query, T>
+(known: Known,
+ query: &'query str,
+ inputs: T) -> Result<AlgebraicQuery>
+    where T: Into<Option<QueryInputs>> {
+    let parsed = parse_find_string(query)?;
+    Ok(algebrize_with_inputs(known, parsed, 0, inputs.into().unwrap_or_default())?)
+}
+
 fn run_algebrized_query<'sqlite>
 (known: Known,
  sqlite: &'sqlite rusqlite::Connection,
- algebrized: AlgebraicQuery) -> QueryExecutionResult {
+ algebrized: AlgebraicQuery,
+ basis: Option<DatomsBasis>) -> QueryExecutionResult {
     assert!(algebrized.unbound_variables().is_empty(),
             "Unbound variables should be checked by now");
     if algebrized.is_known_empty() {
         // We don't need to do any SQL work at all.
-        return Ok(QueryOutput::empty(&algebrized.find_spec));
+        let columns = Rc::new(query_columns(known.schema, &algebrized));
+        return Ok(QueryOutput::empty(&algebrized.find_spec, &columns));
     }
 
     let select = query_to_select(known.schema, algebrized)?;
@@ -346,10 +845,10 @@ fn run_algebrized_query<'sqlite>
                     .map_err(|e| e.into())
         },
         ProjectedSelect::Query { query, projector } => {
-            let SQLQuery { sql, args } = query.to_sql_query()?;
+            let SQLQuery { sql, args } = restrict_to_basis(known.schema, basis, query.to_sql_query()?);
 
             let mut statement = sqlite.prepare(sql.as_str())?;
-            let rows = run_statement(&mut statement, &args)?;
+            let rows = run_statement(&mut statement, &args, &[])?;
 
             projector.project(known.schema, sqlite, rows).map_err(|e| e.into())
         },
@@ -370,8 +869,11 @@ pub fn q_once<'sqlite, 'query, T>
  inputs: T) -> QueryExecutionResult
         where T: Into<Option<QueryInputs>>
 {
-    let algebrized = algebrize_query_str(known, query, inputs)?;
-    run_algebrized_query(known, sqlite, algebrized)
+    metrics::measure(metrics::QUERIES_EXECUTED, metrics::QUERY_DURATION, || {
+        let known = known.with_large_values(has_large_values(sqlite)?);
+        let algebrized = algebrize_query_str(known, query, inputs)?;
+        run_algebrized_query(known, sqlite, algebrized, None)
+    })
 }
 
 /// Just like `q_once`, but doesn't use any cached values.
@@ -383,52 +885,140 @@ pub fn q_uncached<'sqlite, 'schema, 'query, T>
         where T: Into<Option<QueryInputs>>
 {
     let known = Known::for_schema(schema);
-    let algebrized = algebrize_query_str(known, query, inputs)?;
+    metrics::measure(metrics::QUERIES_EXECUTED, metrics::QUERY_DURATION, || {
+        let known = known.with_large_values(has_large_values(sqlite)?);
+        let algebrized = algebrize_query_str(known, query, inputs)?;
+        run_algebrized_query(known, sqlite, algebrized, None)
+    })
+}
+
+/// Just like `q_once`, but answers only from the attribute cache, without touching SQLite at all.
+/// This is for latency-critical paths that mustn't wait on disk.
+///
+/// Only queries whose patterns the algebrizer can resolve against cached attributes -- looking up
+/// a cached attribute of a known entity, or a known value of a cached unique attribute -- can be
+/// answered.  Anything else fails with `MentatError::UncachedQuery` rather than running SQL.
+pub fn q_cached<'query, T>
+(known: Known,
+ query: &'query str,
+ inputs: T) -> QueryExecutionResult
+        where T: Into<Option<QueryInputs>>
+{
+    metrics::measure(metrics::QUERIES_EXECUTED, metrics::QUERY_DURATION, || {
+        let algebrized = algebrize_query_str(known, query, inputs)?;
+        if algebrized.is_known_empty() {
+            metrics::increment_counter(metrics::CACHE_HITS, 1);
+            let columns = Rc::new(query_columns(known.schema, &algebrized));
+            return Ok(QueryOutput::empty(&algebrized.find_spec, &columns));
+        }
 
-    run_algebrized_query(known, sqlite, algebrized)
+        match query_to_select(known.schema, algebrized)? {
+            ProjectedSelect::Constant(constant) => {
+                metrics::increment_counter(metrics::CACHE_HITS, 1);
+                constant.project_without_rows()
+                        .map_err(|e| e.into())
+            },
+            ProjectedSelect::Query { .. } => {
+                metrics::increment_counter(metrics::CACHE_MISSES, 1);
+                bail!(MentatError::UncachedQuery)
+            },
+        }
+    })
 }
 
-pub fn q_prepare<'sqlite, 'schema, 'cache, 'query, T>
-(sqlite: &'sqlite rusqlite::Connection,
- known: Known<'schema, 'cache>,
+fn translate_prepared_query<'query, T>
+(known: Known,
  query: &'query str,
- inputs: T) -> PreparedResult<'sqlite>
+ inputs: T,
+ basis: Option<DatomsBasis>) -> Result<Translation>
         where T: Into<Option<QueryInputs>>
 {
-    let algebrized = algebrize_query_str(known, query, inputs)?;
+    let algebrized = algebrize_prepared_query_str(known, query, inputs)?;
 
-    let unbound = algebrized.unbound_variables();
+    // Unbound `:in` variables become parameters, bound when the query is run, but only if we
+    // know their types well enough.
+    let unbound = algebrized.unparameterized_variables();
     if !unbound.is_empty() {
-        // TODO: Allow binding variables at execution time, not just
-        // preparation time.
         bail!(MentatError::UnboundVariables(unbound.into_iter().map(|v| v.to_string()).collect()));
     }
 
     if algebrized.is_known_empty() {
         // We don't need to do any SQL work at all.
-        return Ok(PreparedQuery::Empty {
+        let columns = Rc::new(query_columns(known.schema, &algebrized));
+        return Ok(Translation::Prepared(PreparedTranslation::Empty {
             find_spec: algebrized.find_spec,
-        });
+            columns: columns,
+        }));
     }
 
+    let parameters = algebrized.parameters.clone();
     let select = query_to_select(known.schema, algebrized)?;
     match select {
         ProjectedSelect::Constant(constant) => {
-            Ok(PreparedQuery::Constant {
-                select: constant,
-            })
+            Ok(Translation::Constant(constant))
         },
         ProjectedSelect::Query { query, projector } => {
-            let SQLQuery { sql, args } = query.to_sql_query()?;
-            let statement = sqlite.prepare(sql.as_str())?;
-
-            Ok(PreparedQuery::Bound {
-                statement,
-                schema: known.schema.clone(),
-                connection: sqlite,
+            let SQLQuery { sql, args } = restrict_to_basis(known.schema, basis, query.to_sql_query()?);
+            Ok(Translation::Prepared(PreparedTranslation::Bound {
+                sql,
+                large_values: known.large_values,
                 args,
-                projector: projector
-            })
+                parameters,
+                projector: Rc::from(projector),
+            }))
+        },
+    }
+}
+
+pub fn q_prepare<'sqlite, 'schema, 'cache, 'query, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ known: Known<'schema, 'cache>,
+ query: &'query str,
+ inputs: T) -> PreparedResult<'sqlite>
+        where T: Into<Option<QueryInputs>>
+{
+    let known = known.with_large_values(has_large_values(sqlite)?);
+    match translate_prepared_query(known, query, inputs, None)? {
+        Translation::Constant(constant) => Ok(PreparedQuery::Constant { select: constant }),
+        Translation::Prepared(translation) => translation.prepare(sqlite, known.schema),
+    }
+}
+
+/// Just like `q_prepare`, but reuses the translation of a recent query with the same text and
+/// schema; see `query_cache`.
+///
+/// Only queries prepared without inputs are kept, since inputs are algebrized into the query.
+/// Nor, when the attribute cache is in use, is anything kept: a translation might have been
+/// answered, in part, from values that will change.
+pub fn q_prepare_cached<'sqlite, 'cache, 'query, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ schema: &Arc<Schema>,
+ cache: Option<&'cache CachedAttributes>,
+ query: &'query str,
+ inputs: T) -> PreparedResult<'sqlite>
+        where T: Into<Option<QueryInputs>>
+{
+    let known = Known::new(&**schema, cache);
+    let inputs = inputs.into();
+    if inputs.is_some() || cache.map_or(false, |c| c.has_cached_attributes()) {
+        return q_prepare(sqlite, known, query, inputs);
+    }
+
+    // A translation cached before any string was offloaded won't match offloaded strings, so
+    // translate again once one has been.
+    let known = known.with_large_values(has_large_values(sqlite)?);
+    if let Some(translation) = query_cache::cached_translation(schema, query) {
+        match translation {
+            PreparedTranslation::Bound { large_values: false, .. } if known.large_values => {},
+            translation => return translation.prepare(sqlite, &**schema),
+        }
+    }
+
+    match translate_prepared_query(known, query, None, None)? {
+        Translation::Constant(constant) => Ok(PreparedQuery::Constant { select: constant }),
+        Translation::Prepared(translation) => {
+            query_cache::cache_translation(schema, query, translation.clone());
+            translation.prepare(sqlite, &**schema)
         },
     }
 }
@@ -440,14 +1030,26 @@ pub fn q_explain<'sqlite, 'query, T>
  inputs: T) -> Result<QueryExplanation>
         where T: Into<Option<QueryInputs>>
 {
-    let algebrized = algebrize_query_str(known, query, inputs)?;
+    explain_query(sqlite, known, query, inputs, None)
+}
+
+fn explain_query<'sqlite, 'query, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ known: Known,
+ query: &'query str,
+ inputs: T,
+ basis: Option<DatomsBasis>) -> Result<QueryExplanation>
+        where T: Into<Option<QueryInputs>>
+{
+    let known = known.with_large_values(has_large_values(sqlite)?);
+    let algebrized = algebrize_prepared_query_str(known, query, inputs)?;
     if algebrized.is_known_empty() {
         return Ok(QueryExplanation::KnownEmpty(algebrized.cc.empty_because.unwrap()));
     }
     match query_to_select(known.schema, algebrized)? {
         ProjectedSelect::Constant(_constant) => Ok(QueryExplanation::KnownConstant),
         ProjectedSelect::Query { query, projector: _projector } => {
-            let query = query.to_sql_query()?;
+            let query = restrict_to_basis(known.schema, basis, query.to_sql_query()?);
 
             let plan_sql = format!("EXPLAIN QUERY PLAN {}", query.sql);
 
@@ -464,3 +1066,67 @@ pub fn q_explain<'sqlite, 'query, T>
         },
     }
 }
+
+/// Just like `q_uncached`, but sees the datoms of `basis`.
+pub(crate) fn q_once_with_basis<'sqlite, 'query, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ schema: &Schema,
+ basis: DatomsBasis,
+ query: &'query str,
+ inputs: T) -> QueryExecutionResult
+        where T: Into<Option<QueryInputs>>
+{
+    let known = Known::for_schema(schema);
+    metrics::measure(metrics::QUERIES_EXECUTED, metrics::QUERY_DURATION, || {
+        let known = known.with_large_values(has_large_values(sqlite)?);
+        let algebrized = algebrize_query_str(known, query, inputs)?;
+        run_algebrized_query(known, sqlite, algebrized, Some(basis))
+    })
+}
+
+/// Just like `q_prepare`, without the attribute cache, but sees the datoms of `basis`.
+pub(crate) fn q_prepare_with_basis<'sqlite, 'query, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ schema: &Schema,
+ basis: DatomsBasis,
+ query: &'query str,
+ inputs: T) -> PreparedResult<'sqlite>
+        where T: Into<Option<QueryInputs>>
+{
+    let known = Known::for_schema(schema).with_large_values(has_large_values(sqlite)?);
+    match translate_prepared_query(known, query, inputs, Some(basis))? {
+        Translation::Constant(constant) => Ok(PreparedQuery::Constant { select: constant }),
+        Translation::Prepared(translation) => translation.prepare(sqlite, schema),
+    }
+}
+
+/// Just like `q_explain`, without the attribute cache, but sees the datoms of `basis`.
+pub(crate) fn q_explain_with_basis<'sqlite, 'query, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ schema: &Schema,
+ basis: DatomsBasis,
+ query: &'query str,
+ inputs: T) -> Result<QueryExplanation>
+        where T: Into<Option<QueryInputs>>
+{
+    explain_query(sqlite, Known::for_schema(schema), query, inputs, Some(basis))
+}
+
+/// The values of `attribute` for `entity` in `basis`: at most one if `only_one` is set.
+pub(crate) fn lookup_values_with_basis<'sqlite, 'attribute, E>
+(sqlite: &'sqlite rusqlite::Connection,
+ schema: &Schema,
+ basis: DatomsBasis,
+ entity: E,
+ attribute: &'attribute Keyword,
+ only_one: bool) -> Result<Vec<TypedValue>>
+ where E: Into<Entid> {
+    let attribute = lookup_attribute(schema, attribute)?;
+    let output = fetch_values(sqlite, Known::for_schema(schema), entity.into(), attribute.0, only_one, Some(basis))?;
+    // Safe to unwrap: we never retrieve structure.
+    if only_one {
+        Ok(output.into_scalar()?.into_iter().map(|v| v.into_scalar().unwrap()).collect())
+    } else {
+        Ok(output.into_coll()?.into_iter().map(|v| v.into_scalar().unwrap()).collect())
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A cache of translated queries, so that preparing a query that was prepared recently skips
+//! parsing, algebrizing, and translating it.
+//!
+//! Translations are keyed by the query's text and the schema they were translated against.  A
+//! `Conn` replaces its `Arc<Schema>` whenever the schema changes, so a new schema never matches
+//! an old entry, and once nothing else holds the old schema its entries are dropped.
+//!
+//! Translations hold `Rc`s, so they can't be shared between threads: each thread has its own
+//! cache.
+
+use std::cell::{
+    RefCell,
+};
+
+use std::collections::{
+    VecDeque,
+};
+
+use std::sync::{
+    Arc,
+};
+
+use mentat_core::{
+    Schema,
+};
+
+use query::{
+    PreparedTranslation,
+};
+
+/// How many translations each thread keeps.
+pub const PREPARED_QUERY_CACHE_CAPACITY: usize = 64;
+
+struct Entry {
+    schema: Arc<Schema>,
+    query: String,
+    translation: PreparedTranslation,
+}
+
+/// A least-recently-used cache of translations.  The most recently used is at the front.
+pub(crate) struct PreparedQueryCache {
+    capacity: usize,
+    entries: VecDeque<Entry>,
+}
+
+impl PreparedQueryCache {
+    pub(crate) fn with_capacity(capacity: usize) -> PreparedQueryCache {
+        PreparedQueryCache {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn get(&mut self, schema: &Arc<Schema>, query: &str) -> Option<PreparedTranslation> {
+        let position = self.entries
+                           .iter()
+                           .position(|e| Arc::ptr_eq(&e.schema, schema) && e.query == query)?;
+        let entry = self.entries.remove(position)?;
+        let translation = entry.translation.clone();
+        self.entries.push_front(entry);
+        Some(translation)
+    }
+
+    pub(crate) fn insert(&mut self, schema: &Arc<Schema>, query: &str, translation: PreparedTranslation) {
+        // Entries for schemas that nobody else holds can never be used again.
+        self.entries.retain(|e| Arc::strong_count(&e.schema) > 1);
+
+        self.entries.push_front(Entry {
+            schema: schema.clone(),
+            query: query.to_string(),
+            translation,
+        });
+        self.entries.truncate(self.capacity);
+    }
+}
+
+thread_local! {
+    static PREPARED_QUERIES: RefCell<PreparedQueryCache> =
+        RefCell::new(PreparedQueryCache::with_capacity(PREPARED_QUERY_CACHE_CAPACITY));
+}
+
+pub(crate) fn cached_translation(schema: &Arc<Schema>, query: &str) -> Option<PreparedTranslation> {
+    PREPARED_QUERIES.with(|cache| cache.borrow_mut().get(schema, query))
+}
+
+pub(crate) fn cache_translation(schema: &Arc<Schema>, query: &str, translation: PreparedTranslation) {
+    PREPARED_QUERIES.with(|cache| cache.borrow_mut().insert(schema, query, translation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use edn::query::{
+        Element,
+        FindSpec,
+        Variable,
+    };
+
+    fn translation() -> PreparedTranslation {
+        let spec = FindSpec::FindScalar(Element::Variable(Variable::from_valid_name("?x")));
+        PreparedTranslation::Empty {
+            find_spec: Rc::new(spec),
+            columns: Rc::new(vec![]),
+        }
+    }
+
+    #[test]
+    fn test_least_recently_used() {
+        let schema = Arc::new(Schema::default());
+        let mut cache = PreparedQueryCache::with_capacity(2);
+        cache.insert(&schema, "a", translation());
+        cache.insert(&schema, "b", translation());
+        assert!(cache.get(&schema, "a").is_some());
+
+        // "b" is now the least recently used.
+        cache.insert(&schema, "c", translation());
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&schema, "b").is_none());
+        assert!(cache.get(&schema, "a").is_some());
+        assert!(cache.get(&schema, "c").is_some());
+    }
+
+    #[test]
+    fn test_schema_changes() {
+        let old = Arc::new(Schema::default());
+        let mut cache = PreparedQueryCache::with_capacity(4);
+        cache.insert(&old, "a", translation());
+
+        // An equal schema isn't the same schema.
+        let new = Arc::new(Schema::default());
+        assert!(cache.get(&new, "a").is_none());
+
+        // Once the old schema is gone, so are its entries.
+        drop(old);
+        cache.insert(&new, "b", translation());
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&new, "b").is_some());
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Query plans as data.
+//!
+//! `q_explain` hands back the rows of SQLite's `EXPLAIN QUERY PLAN`, which are meant to be read
+//! by people.  `q_plan` turns those rows into a tree of `PlanNode`s: each table scan or index
+//! search, with the index it uses and the columns it constrains; each nested-loop join; and each
+//! subquery and compound query.  Where SQLite has statistics for a table -- that is, after
+//! `ANALYZE` -- each table access is annotated with the number of rows SQLite expects it to
+//! produce.
+//!
+//! The tree is built from the parent of each step, which SQLite reports from version 3.24.0 on.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use rusqlite;
+
+use mentat_query_algebrizer::{
+    EmptyBecause,
+};
+
+use mentat_sql::{
+    SQLQuery,
+};
+
+use public_traits::errors::{
+    Result,
+};
+
+use query::{
+    Known,
+    QueryExplanation,
+    QueryInputs,
+    QueryPlanStep,
+    q_explain,
+};
+
+/// How Mentat would execute a query.
+pub enum QueryPlan {
+    /// A query known in advance to be empty, and why we believe that.
+    KnownEmpty(EmptyBecause),
+
+    /// A query known in advance to return a constant value.
+    KnownConstant,
+
+    /// A query that takes actual work to execute.
+    Execution {
+        /// The translated query and any bindings.
+        query: SQLQuery,
+
+        /// The top-level steps of the plan, in the order SQLite runs them.
+        nodes: Vec<PlanNode>,
+    },
+}
+
+/// One step of a `QueryPlan`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanNode {
+    pub operation: PlanOperation,
+
+    /// SQLite's own description of this step.
+    pub detail: String,
+
+    /// How many rows SQLite expects this step to produce, or, for a step that's the inner loop of
+    /// a join, to produce for each row of the outer loops.  `None` if SQLite has no statistics for
+    /// the tables involved.
+    pub estimated_rows: Option<u64>,
+
+    pub children: Vec<PlanNode>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PlanOperation {
+    /// Reading every row of a table, or of one of its indices.
+    Scan {
+        table: String,
+        alias: Option<String>,
+        index: Option<String>,
+    },
+
+    /// Looking up rows with an index.  `constraints` are the indexed columns that the lookup
+    /// constrains, like `a=?`.
+    Search {
+        table: String,
+        alias: Option<String>,
+        index: Option<String>,
+        constraints: Vec<String>,
+    },
+
+    /// Reading the rows of a subquery in the `FROM` clause.  The subquery itself is a sibling
+    /// `Subquery` step.
+    ScanSubquery {
+        alias: Option<String>,
+    },
+
+    /// Nested loops over each of the children, the first outermost.  Each child's estimate is
+    /// per row of the loops outside it, so the join's estimate is their product.
+    Join,
+
+    /// A subquery, which SQLite may run as a co-routine, materialize, or re-run for each row.
+    Subquery,
+
+    /// A query made of several `SELECT`s, like a `UNION`.  The arms are the children.
+    Compound,
+
+    /// A temporary B-tree, used to remove duplicates or to sort.
+    TempBTree,
+
+    /// Anything else.
+    Other,
+}
+
+/// What `sqlite_stat1` knows about each index: the number of rows, followed by the average number
+/// of rows that share a value for each successive prefix of the index's columns.
+struct Statistics {
+    indices: BTreeMap<(String, Option<String>), Vec<u64>>,
+}
+
+impl Statistics {
+    fn load(sqlite: &rusqlite::Connection) -> Result<Statistics> {
+        let mut indices = BTreeMap::new();
+        let analyzed: i64 = sqlite.query_row("SELECT count(*) FROM sqlite_master WHERE name = 'sqlite_stat1'",
+                                             &[], |row| row.get(0))?;
+        if analyzed > 0 {
+            let mut statement = sqlite.prepare("SELECT tbl, idx, stat FROM sqlite_stat1")?;
+            let rows = statement.query_map(&[], |row| {
+                let table: String = row.get(0);
+                let index: Option<String> = row.get(1);
+                let stat: String = row.get(2);
+                (table, index, stat)
+            })?;
+            for row in rows {
+                let (table, index, stat) = row?;
+                indices.insert((table, index), parse_counts(&stat));
+            }
+        }
+        Ok(Statistics { indices })
+    }
+
+    fn table_rows(&self, table: &str, index: Option<&String>) -> Option<u64> {
+        let exact = self.indices.get(&(table.to_string(), index.cloned()));
+        exact.or_else(|| self.indices
+                             .iter()
+                             .find(|&(&(ref t, _), _)| t == table)
+                             .map(|(_, counts)| counts))
+             .and_then(|counts| counts.first().cloned())
+    }
+
+    fn search_rows(&self, table: &str, index: Option<&String>, constraints: &[String]) -> Option<u64> {
+        // SQLite only counts the leading equality constraints when it uses the statistics.
+        let equalities = constraints.iter()
+                                    .take_while(|c| c.ends_with("=?") && !c.ends_with(">=?") && !c.ends_with("<=?"))
+                                    .count();
+        if equalities == 0 {
+            return None;
+        }
+        index.and_then(|index| self.indices.get(&(table.to_string(), Some(index.clone()))))
+             .and_then(|counts| counts.get(equalities).cloned())
+    }
+}
+
+/// Parse the numbers at the start of a `sqlite_stat1` row.  Anything after them, like
+/// `unordered`, is a flag.
+fn parse_counts(stat: &str) -> Vec<u64> {
+    let mut counts = vec![];
+    for word in stat.split_whitespace() {
+        match word.parse() {
+            Ok(count) => counts.push(count),
+            Err(_) => break,
+        }
+    }
+    counts
+}
+
+/// Split the description of a table access, like `TABLE datoms AS datoms00 USING INDEX
+/// idx_datoms_eavt (e=? AND a=?)`, into the table, its alias, the index, and the constraints.
+fn parse_access(description: &str) -> (String, Option<String>, Option<String>, Vec<String>) {
+    let (description, constraints) = match description.find(" (") {
+        Some(i) if description.ends_with(")") => {
+            let constraints = description[i + 2..description.len() - 1].split(" AND ")
+                                                                     .map(|c| c.to_string())
+                                                                     .collect();
+            (&description[..i], constraints)
+        },
+        _ => (description, vec![]),
+    };
+
+    let mut words = description.split_whitespace().peekable();
+    if words.peek() == Some(&"TABLE") {
+        words.next();
+    }
+    let table = words.next().unwrap_or("").to_string();
+    let mut alias = None;
+    let mut index = None;
+    while let Some(word) = words.next() {
+        match word {
+            "AS" => alias = words.next().map(|a| a.to_string()),
+            "INDEX" => index = words.next().map(|i| i.to_string()),
+            _ => {},
+        }
+    }
+    (table, alias, index, constraints)
+}
+
+fn node_for_step(step: &QueryPlanStep, statistics: &Statistics) -> PlanNode {
+    let detail = step.detail.clone();
+    let (operation, estimated_rows) =
+        if detail.starts_with("SCAN SUBQUERY") {
+            let (_, alias, _, _) = parse_access(&detail["SCAN SUBQUERY".len()..]);
+            (PlanOperation::ScanSubquery { alias }, None)
+        } else if detail.starts_with("SCAN ") {
+            let (table, alias, index, _) = parse_access(&detail["SCAN ".len()..]);
+            let rows = statistics.table_rows(&table, index.as_ref());
+            (PlanOperation::Scan { table, alias, index }, rows)
+        } else if detail.starts_with("SEARCH ") {
+            let (table, alias, index, constraints) = parse_access(&detail["SEARCH ".len()..]);
+            let rows = if detail.contains("USING INTEGER PRIMARY KEY") {
+                Some(1)
+            } else {
+                statistics.search_rows(&table, index.as_ref(), &constraints[..])
+            };
+            (PlanOperation::Search { table, alias, index, constraints }, rows)
+        } else if detail.starts_with("COMPOUND") {
+            (PlanOperation::Compound, None)
+        } else if detail.contains("TEMP B-TREE") && !detail.contains("UNION") {
+            (PlanOperation::TempBTree, None)
+        } else if detail.contains("SUBQUERY") ||
+                  detail.starts_with("MATERIALIZE") ||
+                  detail.starts_with("CO-ROUTINE") ||
+                  detail.starts_with("UNION") {
+            (PlanOperation::Subquery, None)
+        } else {
+            (PlanOperation::Other, None)
+        };
+
+    PlanNode {
+        operation,
+        detail,
+        estimated_rows,
+        children: vec![],
+    }
+}
+
+fn is_table_access(node: &PlanNode) -> bool {
+    match node.operation {
+        PlanOperation::Scan { .. } |
+        PlanOperation::Search { .. } |
+        PlanOperation::ScanSubquery { .. } => true,
+        _ => false,
+    }
+}
+
+/// Group each run of consecutive table accesses, which SQLite runs as nested loops, into a
+/// `Join`.
+fn group_joins(nodes: Vec<PlanNode>) -> Vec<PlanNode> {
+    let mut grouped: Vec<PlanNode> = Vec::with_capacity(nodes.len());
+    let mut run: Vec<PlanNode> = vec![];
+    let flush = |run: &mut Vec<PlanNode>, grouped: &mut Vec<PlanNode>| {
+        match run.len() {
+            0 => {},
+            1 => grouped.push(run.pop().unwrap()),
+            _ => {
+                let estimated_rows = run.iter().fold(Some(1u64), |acc, node| {
+                    acc.and_then(|acc| node.estimated_rows.map(|rows| acc.saturating_mul(rows)))
+                });
+                grouped.push(PlanNode {
+                    operation: PlanOperation::Join,
+                    detail: "JOIN".to_string(),
+                    estimated_rows,
+                    children: run.drain(..).collect(),
+                });
+            },
+        }
+    };
+
+    for node in nodes {
+        if is_table_access(&node) {
+            run.push(node);
+        } else {
+            flush(&mut run, &mut grouped);
+            grouped.push(node);
+        }
+    }
+    flush(&mut run, &mut grouped);
+    grouped
+}
+
+fn children_of(parent: i32, steps: &[QueryPlanStep], statistics: &Statistics) -> Vec<PlanNode> {
+    // `EXPLAIN QUERY PLAN` reports each step's ID and its parent's ID in its first two columns.
+    let nodes = steps.iter()
+                     .filter(|step| step.order == parent && step.select_id != parent)
+                     .map(|step| {
+                         let mut node = node_for_step(step, statistics);
+                         node.children = children_of(step.select_id, steps, statistics);
+                         node
+                     })
+                     .collect();
+    group_joins(nodes)
+}
+
+/// Build the `QueryPlan` for `query`.  See the module documentation.
+pub fn q_plan<'sqlite, 'query, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ known: Known,
+ query: &'query str,
+ inputs: T) -> Result<QueryPlan>
+        where T: Into<Option<QueryInputs>>
+{
+    match q_explain(sqlite, known, query, inputs)? {
+        QueryExplanation::KnownEmpty(empty_because) => Ok(QueryPlan::KnownEmpty(empty_because)),
+        QueryExplanation::KnownConstant => Ok(QueryPlan::KnownConstant),
+        QueryExplanation::ExecutionPlan { query, steps } => {
+            let statistics = Statistics::load(sqlite)?;
+            let nodes = children_of(0, &steps[..], &statistics);
+            Ok(QueryPlan::Execution { query, nodes })
+        },
+    }
+}
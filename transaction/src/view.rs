@@ -0,0 +1,99 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Views of a store as it was at, or has changed since, some point in its history.  See
+//! `query::DatomsBasis`.
+
+use std::sync::{
+    Arc,
+};
+
+use rusqlite;
+
+use edn;
+
+use core_traits::{
+    Entid,
+    TypedValue,
+};
+
+use mentat_core::{
+    Schema,
+};
+
+use public_traits::errors::{
+    Result,
+};
+
+use query::{
+    DatomsBasis,
+    PreparedResult,
+    QueryExplanation,
+    QueryInputs,
+    QueryOutput,
+    lookup_values_with_basis,
+    q_explain_with_basis,
+    q_once_with_basis,
+    q_prepare_with_basis,
+};
+
+use Queryable;
+
+/// A store's datoms as of, or since, some transaction, to query as if they were the store itself.
+///
+/// Queries are interpreted using the current schema, and never use the attribute cache, which
+/// only knows the present.
+pub struct DatabaseView<'sqlite> {
+    sqlite: &'sqlite rusqlite::Connection,
+    schema: Arc<Schema>,
+    basis: DatomsBasis,
+}
+
+impl<'sqlite> DatabaseView<'sqlite> {
+    pub fn new(sqlite: &'sqlite rusqlite::Connection, schema: Arc<Schema>, basis: DatomsBasis) -> DatabaseView<'sqlite> {
+        DatabaseView {
+            sqlite,
+            schema,
+            basis,
+        }
+    }
+
+    pub fn basis(&self) -> DatomsBasis {
+        self.basis
+    }
+}
+
+impl<'sqlite> Queryable for DatabaseView<'sqlite> {
+    fn q_once<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        q_once_with_basis(self.sqlite, &self.schema, self.basis, query, inputs)
+    }
+
+    fn q_prepare<T>(&self, query: &str, inputs: T) -> PreparedResult
+        where T: Into<Option<QueryInputs>> {
+        q_prepare_with_basis(self.sqlite, &self.schema, self.basis, query, inputs)
+    }
+
+    fn q_explain<T>(&self, query: &str, inputs: T) -> Result<QueryExplanation>
+        where T: Into<Option<QueryInputs>> {
+        q_explain_with_basis(self.sqlite, &self.schema, self.basis, query, inputs)
+    }
+
+    fn lookup_values_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Vec<TypedValue>>
+        where E: Into<Entid> {
+        lookup_values_with_basis(self.sqlite, &self.schema, self.basis, entity, attribute, false)
+    }
+
+    fn lookup_value_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Option<TypedValue>>
+        where E: Into<Entid> {
+        lookup_values_with_basis(self.sqlite, &self.schema, self.basis, entity, attribute, true)
+            .map(|values| values.into_iter().next())
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! SQLite allows a single writer at a time.  When a write transaction can't begin because another
+//! one is open against the same database file, it's useful to know who is holding it.  Each write
+//! `InProgress` registers itself here, keyed by database path, for as long as it's open.
+//!
+//! This only knows about writers in the current process.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use std::sync::{
+    Mutex,
+};
+
+use std::thread::{
+    self,
+    ThreadId,
+};
+
+use std::time::{
+    Instant,
+};
+
+use failure::{
+    Backtrace,
+};
+
+use public_traits::errors::{
+    MentatError,
+};
+
+lazy_static! {
+    static ref WRITE_HOLDERS: Mutex<BTreeMap<PathBuf, WriteHolder>> = Mutex::new(BTreeMap::new());
+}
+
+/// Describes the thread holding the write transaction for a database.
+#[derive(Debug)]
+struct WriteHolder {
+    thread_id: ThreadId,
+    thread_name: Option<String>,
+    started: Instant,
+
+    /// Where the write transaction was begun.  Only captured in debug builds, and then only if
+    /// `RUST_BACKTRACE` is set.
+    backtrace: Option<Backtrace>,
+}
+
+impl WriteHolder {
+    fn current() -> WriteHolder {
+        let thread = thread::current();
+        WriteHolder {
+            thread_id: thread.id(),
+            thread_name: thread.name().map(|s| s.to_string()),
+            started: Instant::now(),
+            backtrace: if cfg!(debug_assertions) { Some(Backtrace::new()) } else { None },
+        }
+    }
+
+    fn to_error(&self) -> MentatError {
+        let thread = match self.thread_name {
+            Some(ref name) => format!("{:?} ({})", self.thread_id, name),
+            None => format!("{:?}", self.thread_id),
+        };
+        let backtrace = self.backtrace.as_ref()
+                                      .map(|b| b.to_string())
+                                      .filter(|b| !b.is_empty());
+        MentatError::WriteTransactionInProgress(thread, self.started.elapsed(), backtrace)
+    }
+}
+
+/// If a write transaction is known to be open against the database at `path`, return an error
+/// describing its holder.
+pub fn write_transaction_in_progress(path: &Path) -> Option<MentatError> {
+    WRITE_HOLDERS.lock().unwrap()
+                 .get(path)
+                 .map(|holder| holder.to_error())
+}
+
+/// Records the current thread as the writer for a database until dropped.  A write `InProgress`
+/// owns one of these, so the record is cleared however the transaction ends.
+#[derive(Debug)]
+pub struct WriteHolderGuard {
+    path: PathBuf,
+}
+
+impl WriteHolderGuard {
+    pub fn new(path: PathBuf) -> WriteHolderGuard {
+        WRITE_HOLDERS.lock().unwrap().insert(path.clone(), WriteHolder::current());
+        WriteHolderGuard {
+            path: path,
+        }
+    }
+}
+
+impl Drop for WriteHolderGuard {
+    fn drop(&mut self) {
+        // Don't panic while unwinding just because another thread poisoned the lock.
+        if let Ok(mut holders) = WRITE_HOLDERS.lock() {
+            holders.remove(&self.path);
+        }
+    }
+}